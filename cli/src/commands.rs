@@ -0,0 +1,303 @@
+//! The subcommands themselves, kept separate from argument parsing so they
+//! can be tested.
+
+use gcode::{
+    detect,
+    dialect::Dialect,
+    lint,
+    metrics::ParseMetrics,
+    modal::ModalSummary,
+    pipeline::{Diagnostics, Pipeline},
+    profile::MachineProfile,
+    slicer::SlicerSettings,
+    writer::{self, WriterConfig},
+    GcodeFile, Nop, Parser,
+};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Write},
+};
+
+/// Summarise what a program contains.
+pub fn stats(
+    src: &str,
+    dialect: &Dialect,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let metrics = ParseMetrics::measure(src, dialect);
+    let detection = detect::dialect(src);
+    let commands: usize = Parser::<Nop>::new_with_dialect(src, Nop, *dialect)
+        .map(|line| line.gcodes().len())
+        .sum();
+
+    writeln!(out, "lines:       {}", metrics.lines().len())?;
+    writeln!(out, "commands:    {}", commands)?;
+    writeln!(
+        out,
+        "understood:  {:.1}% ({} problem lines)",
+        metrics.fraction_understood() * 100.0,
+        metrics.problem_lines().count()
+    )?;
+    writeln!(
+        out,
+        "dialect:     {} ({:.0}% sure)",
+        detection.dialect.name(),
+        detection.confidence * 100.0
+    )?;
+    if let Some(generator) = &detection.generator {
+        writeln!(out, "generator:   {}", generator)?;
+    }
+
+    let mixed = ModalSummary::of(src, dialect).mixed();
+    if !mixed.is_empty() {
+        writeln!(out, "mixed modes: {}", mixed.join(", "))?;
+    }
+
+    if let Some(settings) = SlicerSettings::parse(src) {
+        writeln!(out, "settings:    {}", settings.len())?;
+    }
+
+    Ok(())
+}
+
+/// Print every likely mistake, and any [`MachineProfile`] violations,
+/// returning how many problems were found.
+pub fn lint(
+    src: &str,
+    dialect: &Dialect,
+    profile: Option<&MachineProfile>,
+    out: &mut dyn Write,
+) -> io::Result<usize> {
+    let mut problems: Vec<(usize, String)> = lint::conditions(src)
+        .into_iter()
+        .chain(lint::pairing(src, dialect))
+        .chain(lint::tapping(src, dialect))
+        .chain(lint::arcs(src, dialect))
+        .map(|warning| (warning.line, warning.to_string()))
+        .collect();
+
+    if let Some(profile) = profile {
+        problems.extend(
+            profile
+                .validate(src)
+                .into_iter()
+                .map(|violation| (violation.line, violation.to_string())),
+        );
+    }
+
+    problems.sort();
+    for (_, problem) in &problems {
+        writeln!(out, "{}", problem)?;
+    }
+
+    Ok(problems.len())
+}
+
+/// Estimate how long a program will take on a particular machine.
+pub fn estimate(
+    src: &str,
+    profile: &MachineProfile,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let analysis = profile.analyzer().analyze(src);
+
+    writeln!(out, "machine: {}", profile.name)?;
+    writeln!(out, "time:    {}", duration(analysis.total_time()))?;
+
+    let layers = analysis.layer_changes().len();
+    if layers > 0 {
+        writeln!(out, "layers:  {}", layers)?;
+    }
+
+    if let Some(bounds) = analysis.bounding_box() {
+        let (min, max) = (bounds.min, bounds.max);
+        writeln!(
+            out,
+            "extents: X {} to {}, Y {} to {}, Z {} to {}",
+            min.x, max.x, min.y, max.y, min.z, max.z
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Format a number of seconds as hours, minutes and seconds.
+fn duration(seconds: f32) -> String {
+    let total = seconds.round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Run a program through a [`Pipeline`].
+pub fn transform(
+    src: &str,
+    dialect: Dialect,
+    pipeline: Pipeline,
+) -> Result<(String, Diagnostics), Box<dyn Error>> {
+    let file = GcodeFile::parse_with_dialect(src, dialect)?.transform(pipeline);
+
+    Ok((file.to_string(), file.diagnostics().clone()))
+}
+
+/// Reformat a program using its dialect's preferred style.
+pub fn fmt(src: &str, dialect: &Dialect) -> Result<String, fmt::Error> {
+    let mut formatted = String::new();
+    writer::reformat(
+        src,
+        dialect,
+        &WriterConfig::for_dialect(dialect),
+        &mut formatted,
+    )?;
+
+    Ok(formatted)
+}
+
+/// Print the lines which differ between two programs, ignoring differences
+/// in formatting. Returns `true` if anything changed.
+pub fn diff(
+    old: &str,
+    new: &str,
+    dialect: &Dialect,
+    out: &mut dyn Write,
+) -> Result<bool, Box<dyn Error>> {
+    let old = fmt(old, dialect)?;
+    let new = fmt(new, dialect)?;
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    let edits = line_diff(&old, &new);
+
+    for edit in &edits {
+        match *edit {
+            Edit::Removed(i) => writeln!(out, "-{}: {}", i + 1, old[i])?,
+            Edit::Added(j) => writeln!(out, "+{}: {}", j + 1, new[j])?,
+        }
+    }
+
+    Ok(!edits.is_empty())
+}
+
+/// A line which only appears on one side of a diff.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Edit {
+    Removed(usize),
+    Added(usize),
+}
+
+/// Beyond this many cells the longest-common-subsequence table gets too big,
+/// and the changed region is reported as entirely replaced instead.
+const MAX_TABLE_SIZE: usize = 25_000_000;
+
+/// Find the lines which were removed from `old` and added to `new`.
+fn line_diff(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    if a.len() * b.len() > MAX_TABLE_SIZE {
+        return (0..a.len())
+            .map(|i| Edit::Removed(prefix + i))
+            .chain((0..b.len()).map(|j| Edit::Added(prefix + j)))
+            .collect();
+    }
+
+    // lengths[i][j] is the length of the LCS of a[i..] and b[j..]
+    let mut lengths = vec![vec![0_usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if i < a.len()
+            && (j == b.len() || lengths[i + 1][j] >= lengths[i][j + 1])
+        {
+            edits.push(Edit::Removed(prefix + i));
+            i += 1;
+        } else {
+            edits.push(Edit::Added(prefix + j));
+            j += 1;
+        }
+    }
+
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting_differences_dont_count() {
+        let dialect = Dialect::generic();
+        let mut out = Vec::new();
+
+        let changed = diff(
+            "G1X10 Y5\nG1 X20\nM5",
+            "G1 X10 Y5\nG1 X25\nM5\nM30",
+            &dialect,
+            &mut out,
+        )
+        .unwrap();
+
+        assert!(changed);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "-2: G1 X20\n+2: G1 X25\n+4: M30\n"
+        );
+    }
+
+    #[test]
+    fn lint_includes_profile_violations() {
+        let profile = MachineProfile::from_toml(
+            "name = \"router\"\n[[axes]]\nletter = \"X\"\nmin = 0\nmax = 100",
+        )
+        .unwrap();
+        let mut out = Vec::new();
+
+        let problems = lint(
+            "M3 S1000\nG1 X150 F100\nM30",
+            &profile.dialect(),
+            Some(&profile),
+            &mut out,
+        )
+        .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(problems, 2, "{}", out);
+        assert!(out.starts_with("line 1: "), "{}", out);
+        assert!(out.contains("line 2: "), "{}", out);
+    }
+
+    #[test]
+    fn durations_are_human_readable() {
+        assert_eq!(duration(59.4), "59s");
+        assert_eq!(duration(61.0), "1m 1s");
+        assert_eq!(duration(7322.0), "2h 2m 2s");
+    }
+}
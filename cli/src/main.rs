@@ -0,0 +1,250 @@
+//! The `gcode` command-line tool.
+//!
+//! Every subcommand is a thin wrapper around something the `gcode` crate
+//! already does, so this doubles as an example of how to plug the library's
+//! pieces together. If a subcommand needs more than a handful of lines, the
+//! library is probably missing something.
+
+mod commands;
+
+use gcode::{
+    detect,
+    dialect::Dialect,
+    pipeline::{PassConfig, PassRegistry, Pipeline, PipelineConfig},
+    profile::{DialectName, MachineProfile, Profile},
+};
+use std::{
+    error::Error,
+    fs,
+    io::{self, Read, Write},
+    process,
+};
+
+const USAGE: &str = "\
+Inspect and post-process g-code programs.
+
+USAGE:
+    gcode <COMMAND> [OPTIONS] <FILE>
+    gcode diff [OPTIONS] <OLD> <NEW>
+
+COMMANDS:
+    stats       Summarise what a program contains
+    lint        Look for likely mistakes
+    transform   Run a program through a pipeline of passes
+    estimate    Estimate how long a program will take to run
+    fmt         Reformat a program consistently
+    diff        Show which lines differ between two programs
+
+OPTIONS:
+    -d, --dialect <NAME>     The dialect to parse with (default: detected)
+    -p, --profile <PROFILE>  A built-in profile's name, or a TOML/JSON file
+        --pipeline <FILE>    A TOML/JSON pipeline description (transform)
+        --pass <NAME>        Add a built-in pass to the pipeline (transform)
+    -o, --output <FILE>      Write the program here instead of stdout
+        --check              Fail if the file isn't formatted (fmt)
+    -h, --help               Print this message
+
+Use \"-\" as a file name to read from stdin.
+";
+
+/// The options shared by every subcommand.
+#[derive(Debug, Default)]
+struct Options {
+    dialect: Option<DialectName>,
+    profile: Option<String>,
+    pipeline: Option<String>,
+    passes: Vec<String>,
+    output: Option<String>,
+    check: bool,
+}
+
+fn main() {
+    match run() {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        },
+    }
+}
+
+fn run() -> Result<i32, Box<dyn Error>> {
+    let mut args = pico_args::Arguments::from_env();
+
+    if args.contains(["-h", "--help"]) {
+        print!("{}", USAGE);
+        return Ok(0);
+    }
+
+    let command = match args.subcommand()? {
+        Some(command) => command,
+        None => {
+            eprint!("{}", USAGE);
+            return Ok(2);
+        },
+    };
+
+    let options = Options {
+        dialect: args.opt_value_from_fn(["-d", "--dialect"], dialect_name)?,
+        profile: args.opt_value_from_str(["-p", "--profile"])?,
+        pipeline: args.opt_value_from_str("--pipeline")?,
+        passes: args.values_from_str("--pass")?,
+        output: args.opt_value_from_str(["-o", "--output"])?,
+        check: args.contains("--check"),
+    };
+    let files: Vec<String> = args
+        .finish()
+        .into_iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    match (command.as_str(), files.as_slice()) {
+        ("diff", [old, new]) => {
+            let old_src = read_input(old)?;
+            let new_src = read_input(new)?;
+            let dialect = options
+                .dialect
+                .map_or_else(|| detected(&old_src), DialectName::dialect);
+
+            let changed =
+                commands::diff(&old_src, &new_src, &dialect, &mut out)?;
+            Ok(if changed { 1 } else { 0 })
+        },
+        ("diff", _) => Err("diff needs exactly two files".into()),
+        (_, [file]) => run_on_file(&command, file, &options, &mut out),
+        (_, []) => Err(format!("{} needs a file", command).into()),
+        (_, _) => Err(format!("{} only takes one file", command).into()),
+    }
+}
+
+fn run_on_file(
+    command: &str,
+    file: &str,
+    options: &Options,
+    out: &mut dyn Write,
+) -> Result<i32, Box<dyn Error>> {
+    let src = read_input(file)?;
+    let profile = options.profile.as_deref().map(load_profile).transpose()?;
+    let dialect = match (options.dialect, &profile) {
+        (Some(name), _) => name.dialect(),
+        (None, Some(profile)) => profile.dialect(),
+        (None, None) => detected(&src),
+    };
+
+    match command {
+        "stats" => {
+            commands::stats(&src, &dialect, out)?;
+            Ok(0)
+        },
+        "lint" => {
+            let problems =
+                commands::lint(&src, &dialect, profile.as_ref(), out)?;
+            Ok(if problems > 0 { 1 } else { 0 })
+        },
+        "estimate" => {
+            let profile = profile.ok_or("estimate needs a --profile")?;
+            commands::estimate(&src, &profile, out)?;
+            Ok(0)
+        },
+        "transform" => {
+            let pipeline = load_pipeline(options)?;
+            let (program, diagnostics) =
+                commands::transform(&src, dialect, pipeline)?;
+
+            for diagnostic in diagnostics.iter() {
+                eprintln!("{}", diagnostic);
+            }
+            write_output(options, &program, out)?;
+
+            Ok(if diagnostics.has_errors() { 1 } else { 0 })
+        },
+        "fmt" => {
+            let formatted = commands::fmt(&src, &dialect)?;
+
+            if options.check {
+                if formatted == src {
+                    Ok(0)
+                } else {
+                    eprintln!("{} isn't formatted", file);
+                    Ok(1)
+                }
+            } else {
+                write_output(options, &formatted, out)?;
+                Ok(0)
+            }
+        },
+        other => Err(format!("unknown command \"{}\"", other).into()),
+    }
+}
+
+fn dialect_name(name: &str) -> Result<DialectName, String> {
+    DialectName::from_name(name)
+        .ok_or_else(|| format!("unknown dialect \"{}\"", name))
+}
+
+fn detected(src: &str) -> Dialect { detect::dialect(src).dialect.dialect() }
+
+/// Read a file, or stdin if the name is `-`.
+fn read_input(name: &str) -> Result<String, Box<dyn Error>> {
+    if name == "-" {
+        let mut src = String::new();
+        let _ = io::stdin().read_to_string(&mut src)?;
+        Ok(src)
+    } else {
+        fs::read_to_string(name)
+            .map_err(|e| format!("unable to read \"{}\": {}", name, e).into())
+    }
+}
+
+fn write_output(
+    options: &Options,
+    text: &str,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    match &options.output {
+        Some(path) => fs::write(path, text),
+        None => out.write_all(text.as_bytes()),
+    }
+}
+
+/// Look up a built-in profile, falling back to loading one from disk.
+fn load_profile(name: &str) -> Result<MachineProfile, Box<dyn Error>> {
+    if let Some(profile) = Profile::from_name(name) {
+        return Ok(profile.machine_profile());
+    }
+
+    let src = fs::read_to_string(name).map_err(|e| {
+        format!("\"{}\" isn't a built-in profile or a file ({})", name, e)
+    })?;
+
+    if name.ends_with(".json") {
+        Ok(MachineProfile::from_json(&src)?)
+    } else {
+        Ok(MachineProfile::from_toml(&src)?)
+    }
+}
+
+/// Build the pipeline described by `--pipeline`, followed by any `--pass`es.
+fn load_pipeline(options: &Options) -> Result<Pipeline, Box<dyn Error>> {
+    let mut config = match &options.pipeline {
+        Some(path) if path.ends_with(".json") => {
+            PipelineConfig::from_json(&fs::read_to_string(path)?)?
+        },
+        Some(path) => PipelineConfig::from_toml(&fs::read_to_string(path)?)?,
+        None => PipelineConfig::default(),
+    };
+    config
+        .passes
+        .extend(options.passes.iter().map(PassConfig::new));
+
+    if config.passes.is_empty() {
+        return Err(
+            "transform needs a --pipeline or at least one --pass".into()
+        );
+    }
+
+    Ok(PassRegistry::with_builtin_passes().build(&config)?)
+}
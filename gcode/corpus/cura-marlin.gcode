@@ -0,0 +1,66 @@
+;FLAVOR:Marlin
+;TIME:412
+;Filament used: 0.52872m
+;Layer height: 0.2
+;MINX:100.2
+;MINY:100.2
+;MINZ:0.2
+;MAXX:119.8
+;MAXY:119.8
+;MAXZ:0.4
+;Generated with Cura_SteamEngine 5.4.0
+M140 S60
+M105
+M190 S60
+M104 S200
+M105
+M109 S200
+M82 ;absolute extrusion mode
+G28 ;Home
+G1 Z15.0 F6000 ;Move the platform down 15mm
+G92 E0
+G1 F200 E3
+G92 E0
+G92 E0
+G1 F1500 E-6.5
+;LAYER_COUNT:2
+;LAYER:0
+M107
+G0 F6000 X100.2 Y100.2 Z0.2
+;TYPE:WALL-OUTER
+G1 F1500 E0
+G1 F1200 X119.8 Y100.2 E0.65189
+G1 X119.8 Y119.8 E1.30378
+G1 X100.2 Y119.8 E1.95567
+G1 X100.2 Y100.2 E2.60756
+;MESH:cube.stl
+G0 F6000 X101 Y101
+;TYPE:FILL
+G1 F1800 X119 Y119 E3.45421
+;TIME_ELAPSED:201.1
+;LAYER:1
+M106 S255
+G0 F6000 X100.2 Y100.2 Z0.4
+;TYPE:WALL-OUTER
+G1 F1200 X119.8 Y100.2 E3.97572
+G1 X119.8 Y119.8 E4.49723
+G1 X100.2 Y119.8 E5.01874
+G1 X100.2 Y100.2 E5.54025
+;TIME_ELAPSED:412.3
+G1 F1500 E-0.96
+M140 S0
+M107
+G91 ;Relative positioning
+G1 E-2 F2700 ;Retract a bit
+G1 E-2 Z0.2 F2400 ;Retract and raise Z
+G1 X5 Y5 F3000 ;Wipe out
+G1 Z10 ;Raise Z more
+G90 ;Absolute positioning
+G1 X0 Y235 ;Present print
+M106 S0 ;Turn-off fan
+M104 S0 ;Turn-off hotend
+M140 S0 ;Turn-off bed
+M84 X Y E ;Disable all steppers but Z
+M82 ;absolute extrusion mode
+M104 S0
+;End of Gcode
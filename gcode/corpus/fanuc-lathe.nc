@@ -0,0 +1,22 @@
+%
+O1000 (SHAFT)
+(generated by Mastercam 2023)
+N10 G21 G40 G99
+N20 G50 S2000
+N30 T0101
+N40 G96 S180 M03
+N50 G00 X42. Z2. M08
+N60 G01 Z0. F0.2
+N70 X-1.6
+N80 G00 X40. Z2.
+N90 G01 Z-30. F0.25
+N100 X42.
+N110 G00 X100. Z100.
+N120 T0303
+N130 G97 S600 M03
+N140 G00 X42. Z5.
+N150 G76 P020060 Q100 R0.05
+N160 G76 X37.546 Z-25. P1227 Q300 F2.
+N170 G00 X100. Z100. M09
+N180 M30
+%
@@ -0,0 +1,20 @@
+; generated by LightBurn 1.4.03
+; GRBL device profile, absolute coords
+; Bounds: X10 Y10 to X30 Y30
+G00 G17 G40 G21 G54
+G90
+M4
+; Cut @ 1000 mm/min, 80% power
+M8
+G0 X10Y10
+G1 Y30S800F1000
+G1 X30
+G1 Y10
+G1 X10
+G0 X15Y15
+G2 X25Y15 I5 J0
+G2 X15Y15 I-5 J0
+M9
+G1 S0
+M5
+M2
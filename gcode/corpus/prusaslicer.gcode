@@ -0,0 +1,62 @@
+; generated by PrusaSlicer 2.6.0+linux-x64-GTK3 on 2023-07-04 at 10:15:01 UTC
+;
+; external perimeters extrusion width = 0.45mm
+; perimeters extrusion width = 0.45mm
+;
+M73 P0 R6
+M201 X1000 Y1000 Z200 E5000 ; sets maximum accelerations, mm/sec^2
+M203 X200 Y200 Z12 E120 ; sets maximum feedrates, mm / sec
+M204 P1250 R1250 T1250 ; sets acceleration (P, T) and retract acceleration (R), mm/sec^2
+M205 X8.00 Y8.00 Z0.40 E4.50 ; sets the jerk limits, mm/sec
+M107
+;TYPE:Custom
+M862.1 P0.4 ; nozzle diameter check
+G90 ; use absolute coordinates
+M83 ; extruder relative mode
+M104 S215 ; set extruder temp
+M140 S60 ; set bed temp
+M190 S60 ; wait for bed temp
+M109 S215 ; wait for extruder temp
+G28 W ; home all without mesh bed level
+G80 ; mesh bed leveling
+G1 Y-3.0 F1000.0 ; go outside print area
+G92 E0.0
+G1 X60.0 E9.0 F1000.0 ; intro line
+G1 X100.0 E12.5 F1000.0 ; intro line
+G92 E0.0
+M221 S95
+G21 ; set units to millimeters
+;LAYER_CHANGE
+;Z:0.2
+;HEIGHT:0.2
+G1 E-.8 F2100
+G1 Z.2 F10800
+G1 X115.55 Y98.45
+G1 E.8 F2100
+;TYPE:External perimeter
+;WIDTH:0.5
+G1 F1200
+G1 X134.45 Y98.45 E.88021
+G1 X134.45 Y117.34 E.88021
+G1 X115.55 Y117.34 E.88021
+G1 X115.55 Y98.51 E.87742
+M73 P50 R3
+;LAYER_CHANGE
+;Z:0.4
+;HEIGHT:0.2
+G1 E-.8 F2100
+G1 Z.4 F10800
+G1 X115.95 Y98.85
+G1 E.8 F2100
+;TYPE:External perimeter
+G1 F1200
+G1 X134.05 Y98.85 E.63283
+G1 X134.05 Y116.94 E.63283
+G1 X115.95 Y116.94 E.63283
+G1 X115.95 Y98.91 E.63073
+M73 P100 R0
+G1 Z30.4 F720 ; Move print head up
+M104 S0 ; turn off temperature
+M140 S0 ; turn off heatbed
+M107 ; turn off fan
+M84 ; disable motors
@@ -0,0 +1,839 @@
+//! Estimating how a program will run.
+//!
+//! An [`Analyzer`] runs a program through the [`Interpreter`] and turns the
+//! result into a timeline of [`Segment`]s, making it possible to ask
+//! questions like "how long will this take?" or "where will the tool be 30
+//! seconds in?".
+//!
+//! ```rust
+//! use gcode::{
+//!     analysis::Analyzer,
+//!     dialect::Dialect,
+//!     interpret::Position,
+//! };
+//!
+//! let src = "G1 X10 F600\nG1 X10 Y10";
+//! let analysis = Analyzer::new(Dialect::generic()).analyze(src);
+//!
+//! // both moves are 10mm long at 10mm/s
+//! assert_eq!(analysis.total_time(), 2.0);
+//! assert_eq!(analysis.position_at(0.5), Position::new(5.0, 0.0, 0.0));
+//! assert_eq!(analysis.position_at_line(1), Position::new(10.0, 0.0, 0.0));
+//! ```
+//!
+//! The timeline can also be used to find when interesting things happen
+//! (e.g. layer changes), which lets tools like timelapse cameras schedule
+//! themselves from the file alone.
+//!
+//! ```rust
+//! # use gcode::{analysis::Analyzer, dialect::Dialect};
+//! let src = "G1 Z0.2 F600\nG1 X10\n; TAKE_PHOTO\nG1 Z0.4\nG1 X0";
+//! let analysis = Analyzer::new(Dialect::reprap()).analyze(src);
+//!
+//! let layers: Vec<_> =
+//!     analysis.layer_changes().iter().map(|event| event.time).collect();
+//! assert_eq!(layers, &[0.02, 1.04]);
+//!
+//! let photos = analysis.triggers("TAKE_PHOTO");
+//! assert_eq!(photos[0].time, 1.02);
+//! ```
+
+use crate::{
+    dialect::Dialect,
+    interpret::{
+        self, Interpreter, MachineState, Motion, MotionKind, Position,
+    },
+    metadata::Annotations,
+    Nop, Parser, Span,
+};
+use std::{string::String, vec::Vec};
+
+/// Assumptions used when estimating how long a program will take.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct EstimatorConfig {
+    /// How fast rapid moves travel, in millimeters per minute.
+    pub rapid_feed_rate: f32,
+    /// The feed rate used if a program moves before setting one, in
+    /// millimeters per minute.
+    pub default_feed_rate: f32,
+    /// How quickly the machine speeds up and slows down, in millimeters per
+    /// second squared, or `None` to assume it reaches full speed instantly.
+    ///
+    /// Every move is assumed to start and finish at rest.
+    pub acceleration: Option<f32>,
+    /// A fixed number of seconds added to every move (e.g. for
+    /// communication and planning overhead).
+    pub command_overhead: f32,
+}
+
+impl Default for EstimatorConfig {
+    fn default() -> EstimatorConfig {
+        EstimatorConfig {
+            rapid_feed_rate: 3000.0,
+            default_feed_rate: 1000.0,
+            acceleration: None,
+            command_overhead: 0.0,
+        }
+    }
+}
+
+/// Something which builds an [`Analysis`] from a program's source text.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Analyzer {
+    dialect: Dialect,
+    config: EstimatorConfig,
+    initial_state: MachineState,
+}
+
+impl Analyzer {
+    /// Create a new [`Analyzer`] for programs written in a particular
+    /// [`Dialect`].
+    pub fn new(dialect: Dialect) -> Self {
+        Analyzer {
+            dialect,
+            config: EstimatorConfig::default(),
+            initial_state: MachineState::default(),
+        }
+    }
+
+    /// Create an [`Analyzer`] for one of the built-in machine profiles.
+    ///
+    /// ```rust
+    /// use gcode::{analysis::Analyzer, profile::Profile};
+    ///
+    /// let analyzer = Analyzer::for_machine(Profile::PrusaMk4);
+    ///
+    /// assert_eq!(analyzer.dialect(), &gcode::dialect::Dialect::reprap());
+    /// ```
+    #[cfg(feature = "builtin-profiles")]
+    pub fn for_machine(profile: crate::profile::Profile) -> Self {
+        profile.machine_profile().analyzer()
+    }
+
+    /// Use a different [`EstimatorConfig`].
+    pub fn with_config(self, config: EstimatorConfig) -> Self {
+        Analyzer { config, ..self }
+    }
+
+    /// Start the program from a particular [`MachineState`].
+    pub fn with_initial_state(self, initial_state: MachineState) -> Self {
+        Analyzer {
+            initial_state,
+            ..self
+        }
+    }
+
+    /// The [`Dialect`] programs are expected to be written in.
+    pub fn dialect(&self) -> &Dialect { &self.dialect }
+
+    /// The [`MachineState`] programs start from.
+    pub fn initial_state(&self) -> &MachineState { &self.initial_state }
+
+    /// The [`EstimatorConfig`] being used.
+    pub fn config(&self) -> &EstimatorConfig { &self.config }
+
+    /// Analyze a program.
+    pub fn analyze(&self, src: &str) -> Analysis {
+        let mut interpreter =
+            Interpreter::with_state(self.dialect, self.initial_state);
+        let mut segments = Vec::new();
+        let mut comments = Vec::new();
+        let mut time = 0.0;
+
+        for line in Parser::<_>::new_with_dialect(src, Nop, self.dialect) {
+            let span = line.span();
+            enter_span!(
+                TRACE,
+                "line",
+                start = span.start,
+                end = span.end,
+                line = span.line,
+                time
+            );
+
+            for comment in line.comments() {
+                comments.push(TimedComment {
+                    text: String::from(comment.text()),
+                    time,
+                    span: comment.span,
+                });
+            }
+
+            let mut push = |kind, duration: f32| {
+                segments.push(Segment {
+                    kind,
+                    start_time: time,
+                    duration,
+                    span,
+                });
+                time += duration;
+            };
+
+            for gcode in interpret::execution_order(&line) {
+                if let Some(duration) = self.dialect.dwell_seconds(gcode) {
+                    let position = interpreter.state().position;
+                    push(SegmentKind::Dwell { position }, duration.max(0.0));
+                }
+
+                // the interpreter skips over the moves canned cycles make
+                let threading = interpreter
+                    .threading_cycle(gcode)
+                    .into_iter()
+                    .flat_map(|cycle| cycle.motions());
+                let tapping = interpreter
+                    .tapping_cycle(gcode)
+                    .into_iter()
+                    .flat_map(|cycle| cycle.motions());
+                let moves = threading.chain(tapping);
+
+                for motion in moves.chain(interpreter.process(gcode)) {
+                    push(
+                        SegmentKind::Motion(motion),
+                        self.duration_of(&motion),
+                    );
+                }
+            }
+        }
+
+        Analysis {
+            segments,
+            comments,
+            initial_position: self.initial_state.position,
+            final_state: *interpreter.state(),
+            metadata: Annotations::new(),
+        }
+    }
+
+    /// Estimate how many seconds a [`Motion`] will take.
+    fn duration_of(&self, motion: &Motion) -> f32 {
+        let feed_rate = self.config.feed_rate_for(motion);
+
+        move_duration(motion.length(), feed_rate, self.config.acceleration)
+            + self.config.command_overhead
+    }
+}
+
+impl EstimatorConfig {
+    /// The feed rate a [`Motion`] will use, in millimeters per minute.
+    pub(crate) fn feed_rate_for(&self, motion: &Motion) -> f32 {
+        if motion.is_rapid() {
+            self.rapid_feed_rate
+        } else {
+            motion.feed_rate.unwrap_or(self.default_feed_rate)
+        }
+    }
+}
+
+/// How many seconds it takes to move `length` millimeters at `feed_rate`
+/// millimeters per minute, starting and finishing at rest.
+pub(crate) fn move_duration(
+    length: f32,
+    feed_rate: f32,
+    acceleration: Option<f32>,
+) -> f32 {
+    if feed_rate <= 0.0 {
+        return 0.0;
+    }
+
+    let speed = feed_rate / 60.0;
+
+    match acceleration {
+        Some(acceleration) if acceleration > 0.0 => {
+            let ramp_length = speed * speed / acceleration;
+
+            if length >= ramp_length {
+                // accelerate, cruise, then decelerate
+                length / speed + speed / acceleration
+            } else {
+                // never reaches full speed
+                2.0 * libm::sqrtf(length / acceleration)
+            }
+        },
+        _ => length / speed,
+    }
+}
+
+/// The different kinds of [`Segment`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum SegmentKind {
+    /// The machine is moving.
+    Motion(Motion),
+    /// The machine is waiting (`G4`).
+    Dwell {
+        /// Where the machine is waiting.
+        position: Position,
+    },
+}
+
+/// A single step in a program's timeline.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Segment {
+    /// What the machine is doing.
+    pub kind: SegmentKind,
+    /// When the segment starts, in seconds since the start of the program.
+    pub start_time: f32,
+    /// How long the segment takes, in seconds.
+    pub duration: f32,
+    /// The [`Line`][crate::Line] this segment came from.
+    pub span: Span,
+}
+
+impl Segment {
+    /// When the segment finishes, in seconds since the start of the program.
+    pub fn end_time(&self) -> f32 { self.start_time + self.duration }
+
+    /// Where the machine is when the segment starts.
+    pub fn start_position(&self) -> Position {
+        match self.kind {
+            SegmentKind::Motion(ref motion) => motion.start,
+            SegmentKind::Dwell { position } => position,
+        }
+    }
+
+    /// Where the machine is when the segment finishes.
+    pub fn end_position(&self) -> Position {
+        match self.kind {
+            SegmentKind::Motion(ref motion) => motion.end,
+            SegmentKind::Dwell { position } => position,
+        }
+    }
+
+    /// Interpolate the machine's position at a particular time (in seconds
+    /// since the start of the program) during this segment.
+    pub fn position_at(&self, time: f32) -> Position {
+        match self.kind {
+            SegmentKind::Motion(ref motion) if self.duration > 0.0 => {
+                motion.point_at((time - self.start_time) / self.duration)
+            },
+            _ => self.end_position(),
+        }
+    }
+}
+
+/// A comment, and when it would be reached.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct TimedComment {
+    /// The comment's text (see [`Comment::text()`][crate::Comment::text]).
+    pub text: String,
+    /// When the comment is reached, in seconds since the start of the
+    /// program.
+    pub time: f32,
+    /// Where the comment is in the source text.
+    pub span: Span,
+}
+
+/// Something interesting which happens at a particular time.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Event {
+    /// What happened.
+    pub kind: EventKind,
+    /// When it happened, in seconds since the start of the program.
+    pub time: f32,
+    /// The part of the source text which caused the event.
+    pub span: Span,
+}
+
+/// The different kinds of [`Event`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum EventKind {
+    /// The start of a new layer.
+    LayerChange {
+        /// The (zero-based) layer number.
+        layer: usize,
+        /// The layer's height, in millimeters.
+        z: f32,
+    },
+    /// A comment containing a trigger (see [`Analysis::triggers()`]).
+    Trigger,
+}
+
+/// An axis-aligned box, in millimeters.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct BoundingBox {
+    /// The corner with the smallest coordinates.
+    pub min: Position,
+    /// The corner with the largest coordinates.
+    pub max: Position,
+}
+
+impl BoundingBox {
+    /// Create a new [`BoundingBox`].
+    pub const fn new(min: Position, max: Position) -> Self {
+        BoundingBox { min, max }
+    }
+
+    /// Grow the box so it contains a position.
+    pub fn include(&mut self, position: Position) {
+        self.min = Position::new(
+            self.min.x.min(position.x),
+            self.min.y.min(position.y),
+            self.min.z.min(position.z),
+        );
+        self.max = Position::new(
+            self.max.x.max(position.x),
+            self.max.y.max(position.y),
+            self.max.z.max(position.z),
+        );
+    }
+
+    /// Does the box contain a position?
+    pub fn contains(&self, position: Position) -> bool {
+        (self.min.x..=self.max.x).contains(&position.x)
+            && (self.min.y..=self.max.y).contains(&position.y)
+            && (self.min.z..=self.max.z).contains(&position.z)
+    }
+}
+
+/// The estimated timeline for a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    segments: Vec<Segment>,
+    comments: Vec<TimedComment>,
+    initial_position: Position,
+    final_state: MachineState,
+    metadata: Annotations<usize>,
+}
+
+impl Analysis {
+    /// Every [`Segment`] in the program, in the order they are executed.
+    pub fn segments(&self) -> &[Segment] { &self.segments }
+
+    /// The [`MachineState`] after the program has finished.
+    pub fn final_state(&self) -> &MachineState { &self.final_state }
+
+    /// Metadata attached to each [`Segment`], keyed by its index in
+    /// [`Analysis::segments()`].
+    pub fn metadata(&self) -> &Annotations<usize> { &self.metadata }
+
+    /// Attach metadata to segments so later passes can read it.
+    pub fn metadata_mut(&mut self) -> &mut Annotations<usize> {
+        &mut self.metadata
+    }
+
+    /// The estimated time taken to run the entire program, in seconds.
+    pub fn total_time(&self) -> f32 {
+        self.segments.last().map(Segment::end_time).unwrap_or(0.0)
+    }
+
+    /// Find the [`Segment`] being executed at a particular time, in seconds
+    /// since the start of the program.
+    pub fn segment_at(&self, time: f32) -> Option<&Segment> {
+        if time < 0.0 || time > self.total_time() {
+            return None;
+        }
+
+        // the first segment which hasn't finished yet
+        let index = self
+            .segments
+            .partition_point(|segment| segment.end_time() < time);
+
+        self.segments.get(index)
+    }
+
+    /// Estimate where the machine will be at a particular time, in seconds
+    /// since the start of the program.
+    ///
+    /// Times before the program starts or after it finishes are clamped.
+    pub fn position_at(&self, time: f32) -> Position {
+        if time <= 0.0 {
+            return self.initial_position;
+        }
+
+        match self.segment_at(time) {
+            Some(segment) => segment.position_at(time),
+            None => self.final_position(),
+        }
+    }
+
+    /// Where the machine will be just before a particular (zero-based) line
+    /// is executed.
+    pub fn position_at_line(&self, line: usize) -> Position {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.span.line < line);
+
+        match index.checked_sub(1) {
+            Some(previous) => self.segments[previous].end_position(),
+            None => self.initial_position,
+        }
+    }
+
+    /// When a particular (zero-based) line will start executing, in seconds
+    /// since the start of the program.
+    pub fn time_at_line(&self, line: usize) -> f32 {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.span.line < line);
+
+        match self.segments.get(index) {
+            Some(segment) => segment.start_time,
+            None => self.total_time(),
+        }
+    }
+
+    /// The smallest box containing every position the machine visits, or
+    /// [`None`] if it never moves.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        let mut bounds: Option<BoundingBox> = None;
+        let mut include = |position| match bounds.as_mut() {
+            Some(b) => b.include(position),
+            None => bounds = Some(BoundingBox::new(position, position)),
+        };
+
+        for segment in &self.segments {
+            let motion = match segment.kind {
+                SegmentKind::Motion(ref motion) => motion,
+                SegmentKind::Dwell { .. } => continue,
+            };
+
+            include(motion.start);
+            if let MotionKind::Arc(_) = motion.kind {
+                for i in 1..ARC_SAMPLES {
+                    include(motion.point_at(i as f32 / ARC_SAMPLES as f32));
+                }
+            }
+            include(motion.end);
+        }
+
+        bounds
+    }
+
+    /// Every comment in the program, and when it would be reached.
+    pub fn comments(&self) -> &[TimedComment] { &self.comments }
+
+    /// Find when each layer starts.
+    ///
+    /// The layer markers written by common slicers (`;LAYER:3`,
+    /// `;LAYER_CHANGE`, and `; layer 3, Z = 0.8`) are used when present.
+    /// Otherwise a new layer starts whenever the machine feeds across the
+    /// XY plane at a height above the previous layer.
+    pub fn layer_changes(&self) -> Vec<Event> {
+        let markers: Vec<_> = self
+            .comments
+            .iter()
+            .filter(|comment| is_layer_marker(&comment.text))
+            .collect();
+
+        if markers.is_empty() {
+            return self.layer_changes_from_heights();
+        }
+
+        markers
+            .into_iter()
+            .enumerate()
+            .map(|(layer, comment)| Event {
+                kind: EventKind::LayerChange {
+                    layer,
+                    z: self.layer_height_after(comment.time),
+                },
+                time: comment.time,
+                span: comment.span,
+            })
+            .collect()
+    }
+
+    /// Find every comment containing a particular piece of text (e.g.
+    /// `TIMELAPSE_TAKE_FRAME`).
+    pub fn triggers(&self, trigger: &str) -> Vec<Event> {
+        self.comments
+            .iter()
+            .filter(|comment| comment.text.contains(trigger))
+            .map(|comment| Event {
+                kind: EventKind::Trigger,
+                time: comment.time,
+                span: comment.span,
+            })
+            .collect()
+    }
+
+    fn layer_changes_from_heights(&self) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut current_layer: Option<f32> = None;
+
+        for segment in &self.segments {
+            let z = match layer_height(segment) {
+                Some(z) => z,
+                None => continue,
+            };
+
+            if current_layer.is_none_or(|previous| z > previous + EPSILON) {
+                events.push(Event {
+                    kind: EventKind::LayerChange {
+                        layer: events.len(),
+                        z,
+                    },
+                    time: segment.start_time,
+                    span: segment.span,
+                });
+                current_layer = Some(z);
+            }
+        }
+
+        events
+    }
+
+    /// The height of the first layer-like move after a particular time.
+    fn layer_height_after(&self, time: f32) -> f32 {
+        self.segments
+            .iter()
+            .filter(|segment| segment.start_time >= time)
+            .find_map(layer_height)
+            .unwrap_or_else(|| self.position_at(time).z)
+    }
+
+    fn final_position(&self) -> Position {
+        self.segments
+            .last()
+            .map(Segment::end_position)
+            .unwrap_or(self.initial_position)
+    }
+}
+
+const EPSILON: f32 = 1e-4;
+/// How many pieces an arc is split into when finding its extent.
+const ARC_SAMPLES: usize = 16;
+
+/// If this segment feeds across the XY plane, what height is it at?
+fn layer_height(segment: &Segment) -> Option<f32> {
+    let motion = match segment.kind {
+        SegmentKind::Motion(ref motion) if !motion.is_rapid() => motion,
+        _ => return None,
+    };
+    let Motion { start, end, .. } = *motion;
+
+    let is_flat = (end.z - start.z).abs() < EPSILON;
+    let moves_across =
+        (end.x - start.x).abs() > EPSILON || (end.y - start.y).abs() > EPSILON;
+
+    if is_flat && moves_across {
+        Some(end.z)
+    } else {
+        None
+    }
+}
+
+fn is_layer_marker(text: &str) -> bool {
+    let upper = text.to_ascii_uppercase();
+
+    if upper.starts_with("LAYER:") || upper == "LAYER_CHANGE" {
+        return true;
+    }
+
+    // Simplify3D writes "; layer 3, Z = 0.8"
+    match upper.strip_prefix("LAYER ") {
+        Some(rest) => rest.starts_with(|c: char| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(src: &str) -> Analysis {
+        Analyzer::new(Dialect::generic()).analyze(src)
+    }
+
+    #[test]
+    fn rapids_use_the_rapid_feed_rate() {
+        let got = analyze("G0 X100");
+
+        assert_eq!(got.total_time(), 2.0);
+        assert_eq!(got.position_at(1.0), Position::new(50.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn dwells_take_time_without_moving() {
+        let got = analyze("G1 X10 F600\nG4 P2\nG1 X20");
+
+        assert_eq!(got.segments().len(), 3);
+        assert_eq!(got.total_time(), 4.0);
+        assert_eq!(got.position_at(2.0), Position::new(10.0, 0.0, 0.0));
+        assert_eq!(got.position_at(3.5), Position::new(15.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn times_outside_the_program_are_clamped() {
+        let got = analyze("G1 X10 F600");
+
+        assert_eq!(got.position_at(-1.0), Position::ORIGIN);
+        assert_eq!(got.position_at(100.0), Position::new(10.0, 0.0, 0.0));
+        assert!(got.segment_at(100.0).is_none());
+    }
+
+    #[test]
+    fn threading_cycles_include_every_pass() {
+        let src = "M3 S600\nG0 X10 Z0\nG76 P1 Z-20 I-1 J0.25 K0.5\nG0 X20";
+
+        let got = Analyzer::new(Dialect::linuxcnc()).analyze(src);
+
+        let threading: Vec<_> = got
+            .segments()
+            .iter()
+            .filter(|segment| match segment.kind {
+                SegmentKind::Motion(motion) => {
+                    matches!(motion.kind, MotionKind::Threading(_))
+                },
+                SegmentKind::Dwell { .. } => false,
+            })
+            .collect();
+        // 2 passes, each cutting 20mm at 600mm/min
+        assert_eq!(threading.len(), 2);
+        assert!(threading.iter().all(|segment| segment.duration == 2.0));
+        assert_eq!(got.position_at_line(3), Position::new(10.0, 0.0, 0.0));
+        assert_eq!(got.segments().len(), 1 + 2 * 4 + 1);
+    }
+
+    #[test]
+    fn tapping_includes_the_way_back_out() {
+        let src = "M3 S500\nG0 Z10\nG95 G84 Z-5 R5 F1\nG80";
+
+        let got = Analyzer::new(Dialect::linuxcnc()).analyze(src);
+
+        let durations: Vec<_> = got
+            .segments()
+            .iter()
+            .map(|segment| segment.duration)
+            .collect();
+        // 10mm down and back up at 500mm/min, with rapids either side
+        assert_eq!(durations.len(), 5);
+        assert!((durations[2] - 1.2).abs() < 1e-4);
+        assert!((durations[3] - 1.2).abs() < 1e-4);
+        assert!((got.total_time() - 2.8).abs() < 1e-4);
+        assert_eq!(got.position_at_line(3), Position::new(0.0, 0.0, 10.0));
+    }
+
+    #[test]
+    fn interpolate_along_arcs() {
+        let got = analyze("G1 X10 F600\nG3 X-10 Y0 I-10 J0");
+        let halfway =
+            got.segments()[1].start_time + got.segments()[1].duration / 2.0;
+
+        let position = got.position_at(halfway);
+
+        assert!(position.distance_to(Position::new(0.0, 10.0, 0.0)) < 1e-3);
+    }
+
+    #[test]
+    fn position_before_a_line() {
+        let got = analyze("G1 X10 F600\n(a comment)\nG1 Y10\nG1 Z10");
+
+        assert_eq!(got.position_at_line(0), Position::ORIGIN);
+        assert_eq!(got.position_at_line(2), Position::new(10.0, 0.0, 0.0));
+        assert_eq!(got.position_at_line(3), Position::new(10.0, 10.0, 0.0));
+        assert_eq!(got.position_at_line(100), Position::new(10.0, 10.0, 10.0));
+    }
+
+    #[test]
+    fn time_before_a_line() {
+        let got = analyze("G1 X10 F600\n(a comment)\nG1 Y10");
+
+        assert_eq!(got.time_at_line(0), 0.0);
+        assert_eq!(got.time_at_line(1), 1.0);
+        assert_eq!(got.time_at_line(2), 1.0);
+        assert_eq!(got.time_at_line(3), 2.0);
+    }
+
+    #[test]
+    fn bounding_boxes_include_the_extent_of_arcs() {
+        let got = analyze("G1 X10 F600\nG3 X-10 Y0 I-10 J0\nG0 Z5");
+        let bounds = got.bounding_box().unwrap();
+
+        assert_eq!(bounds.min.x, -10.0);
+        assert_eq!(bounds.max.x, 10.0);
+        assert_eq!(bounds.min.y, 0.0);
+        assert!((bounds.max.y - 10.0).abs() < 1e-3);
+        assert_eq!(bounds.max.z, 5.0);
+        assert!(analyze("G4 P1").bounding_box().is_none());
+    }
+
+    #[test]
+    fn continuation_lines_are_attributed_to_their_own_line() {
+        let got = analyze("G1 X10 F600\nX20\nX30");
+
+        assert_eq!(got.segments()[1].span.line, 1);
+        assert_eq!(got.position_at_line(2), Position::new(20.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn comments_know_when_they_happen() {
+        let got = analyze("G1 X10 F600\n(halfway)\nG1 X20 ; done");
+
+        let comments: Vec<_> = got
+            .comments()
+            .iter()
+            .map(|comment| (comment.text.as_str(), comment.time))
+            .collect();
+        assert_eq!(comments, &[("halfway", 1.0), ("done", 1.0)]);
+    }
+
+    #[test]
+    fn layer_changes_from_slicer_comments() {
+        let src =
+            ";LAYER_COUNT:2\n;LAYER:0\nG1 Z0.3 F600\nG1 X6\n;LAYER:1\nG0 \
+                   Z0.6\nG1 X0";
+
+        let got = analyze(src).layer_changes();
+
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].kind, EventKind::LayerChange { layer: 0, z: 0.3 });
+        assert_eq!(got[0].time, 0.0);
+        assert_eq!(got[1].kind, EventKind::LayerChange { layer: 1, z: 0.6 });
+        assert_eq!(got[1].span.line, 4);
+    }
+
+    #[test]
+    fn layer_changes_from_heights_ignore_z_hops() {
+        let src = "G1 Z0.2 F600\nG1 X10\nG1 Z1\nG0 X0\nG1 Z0.2\nG1 X5\nG1 \
+                   Z0.4\nG1 X0";
+
+        let got = analyze(src).layer_changes();
+
+        let heights: Vec<_> = got
+            .iter()
+            .map(|event| match event.kind {
+                EventKind::LayerChange { z, .. } => z,
+                other => panic!("Unexpected event: {:?}", other),
+            })
+            .collect();
+        assert_eq!(heights, &[0.2, 0.4]);
+    }
+
+    #[test]
+    fn recognise_layer_markers() {
+        assert!(is_layer_marker("LAYER:12"));
+        assert!(is_layer_marker("LAYER_CHANGE"));
+        assert!(is_layer_marker("layer 3, Z = 0.8"));
+        assert!(!is_layer_marker("LAYER_COUNT:40"));
+        assert!(!is_layer_marker("layer end"));
+    }
+}
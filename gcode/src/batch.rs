@@ -0,0 +1,559 @@
+//! Checking a whole folder of programs at once.
+//!
+//! Print farms and CAM departments often want to run the same checks over
+//! every file in a job folder before it goes anywhere near a machine. A
+//! [`Batch`] reads each file, optionally runs it through a [`Pipeline`],
+//! validates it against a [`MachineProfile`] and estimates how long it will
+//! take, then gathers everything into a single [`BatchReport`].
+//!
+//! ```rust,no_run
+//! use gcode::{
+//!     batch::{Batch, BatchConfig},
+//!     pipeline::{Pipeline, SanitizePass},
+//!     profile::MachineProfile,
+//!     transform::SanitizeConfig,
+//! };
+//!
+//! let config = BatchConfig {
+//!     threads: 0,
+//!     ..Default::default()
+//! };
+//! let batch = Batch::new(config)
+//!     .with_profile(MachineProfile::new("farm printer"))
+//!     .with_pipeline(|| {
+//!         Pipeline::new().with_pass(SanitizePass::new(SanitizeConfig::default()))
+//!     });
+//!
+//! let report = batch.run_dir("jobs/").unwrap();
+//! println!("{}", report.summary());
+//!
+//! for file in report.files().iter().filter(|f| !f.is_ok()) {
+//!     println!("{}: {} problems", file.path.display(), file.problem_count());
+//! }
+//! ```
+
+use crate::{
+    analysis::Analyzer,
+    dialect::Dialect,
+    pipeline::{Context, Diagnostics, Pipeline, Severity},
+    profile::{MachineProfile, Violation},
+    program::Program,
+};
+use core::{
+    fmt::{self, Debug, Display, Formatter},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use std::{
+    boxed::Box,
+    fs, io,
+    path::{Path, PathBuf},
+    string::{String, ToString},
+    sync::Mutex,
+    thread,
+    vec::Vec,
+};
+
+/// Settings shared by every file in a [`Batch`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchConfig {
+    /// The dialect files are written in, when no [`MachineProfile`] is used.
+    pub dialect: Dialect,
+    /// Only files with one of these extensions (compared without case) are
+    /// picked up by [`Batch::run_dir()`].
+    pub extensions: Vec<String>,
+    /// Look inside sub-directories too.
+    pub recursive: bool,
+    /// How many files to process at the same time, where `0` uses every
+    /// available CPU.
+    pub threads: usize,
+    /// Keep each program's text after the pipeline has run, in
+    /// [`FileReport::output`].
+    pub keep_output: bool,
+}
+
+impl Default for BatchConfig {
+    fn default() -> BatchConfig {
+        BatchConfig {
+            dialect: Dialect::default(),
+            extensions: ["gcode", "gco", "g", "nc", "ngc", "tap"]
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect(),
+            recursive: false,
+            threads: 1,
+            keep_output: false,
+        }
+    }
+}
+
+type PipelineFactory = Box<dyn Fn() -> Pipeline + Send + Sync>;
+
+/// Runs the same checks over many files.
+pub struct Batch {
+    config: BatchConfig,
+    profile: Option<MachineProfile>,
+    analyzer: Option<Analyzer>,
+    pipeline: Option<PipelineFactory>,
+}
+
+impl Batch {
+    /// Create a [`Batch`] which only estimates how long each file takes.
+    pub fn new(config: BatchConfig) -> Self {
+        Batch {
+            config,
+            profile: None,
+            analyzer: None,
+            pipeline: None,
+        }
+    }
+
+    /// Validate every file against a machine, using its dialect and timing
+    /// assumptions.
+    pub fn with_profile(self, profile: MachineProfile) -> Self {
+        Batch {
+            profile: Some(profile),
+            ..self
+        }
+    }
+
+    /// Estimate timings with a particular [`Analyzer`] instead of the one
+    /// from the dialect or [`MachineProfile`].
+    pub fn with_analyzer(self, analyzer: Analyzer) -> Self {
+        Batch {
+            analyzer: Some(analyzer),
+            ..self
+        }
+    }
+
+    /// Run each file through a [`Pipeline`] before checking it.
+    ///
+    /// Passes keep their own state, so `factory` is called to create a
+    /// fresh pipeline for each worker thread (see
+    /// [`PassRegistry::build()`] for creating one from a config file).
+    ///
+    /// [`PassRegistry::build()`]: crate::pipeline::PassRegistry::build
+    pub fn with_pipeline<F>(self, factory: F) -> Self
+    where
+        F: Fn() -> Pipeline + Send + Sync + 'static,
+    {
+        Batch {
+            pipeline: Some(Box::new(factory)),
+            ..self
+        }
+    }
+
+    /// The settings being used.
+    pub fn config(&self) -> &BatchConfig { &self.config }
+
+    /// Process every matching file in a directory.
+    ///
+    /// Only failing to read the directory itself is an error. Problems with
+    /// individual files are recorded in their [`FileReport`].
+    pub fn run_dir<P: AsRef<Path>>(&self, dir: P) -> io::Result<BatchReport> {
+        let mut paths = Vec::new();
+        self.find_files(dir.as_ref(), &mut paths)?;
+        paths.sort();
+
+        Ok(self.run_files(&paths))
+    }
+
+    /// Process a list of files, reporting them in the same order.
+    pub fn run_files<P: AsRef<Path> + Sync>(&self, paths: &[P]) -> BatchReport {
+        let threads = match self.config.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .min(paths.len())
+        .max(1);
+
+        let files = if threads == 1 {
+            let mut pipeline = self.pipeline.as_ref().map(|f| f());
+            paths
+                .iter()
+                .map(|path| self.process(path.as_ref(), pipeline.as_mut()))
+                .collect()
+        } else {
+            self.run_in_parallel(paths, threads)
+        };
+
+        BatchReport { files }
+    }
+
+    fn run_in_parallel<P: AsRef<Path> + Sync>(
+        &self,
+        paths: &[P],
+        threads: usize,
+    ) -> Vec<FileReport> {
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<FileReport>>> =
+            Mutex::new(paths.iter().map(|_| None).collect());
+
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let _ = scope.spawn(|| {
+                    let mut pipeline = self.pipeline.as_ref().map(|f| f());
+
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let path = match paths.get(index) {
+                            Some(path) => path.as_ref(),
+                            None => break,
+                        };
+
+                        let report = self.process(path, pipeline.as_mut());
+                        let mut results =
+                            results.lock().unwrap_or_else(|e| e.into_inner());
+                        results[index] = Some(report);
+                    }
+                });
+            }
+        });
+
+        results
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    fn find_files(
+        &self,
+        dir: &Path,
+        paths: &mut Vec<PathBuf>,
+    ) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                if self.config.recursive {
+                    self.find_files(&path, paths)?;
+                }
+            } else if self.has_matching_extension(&path) {
+                paths.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn has_matching_extension(&self, path: &Path) -> bool {
+        let extension = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => ext,
+            None => return false,
+        };
+
+        self.config
+            .extensions
+            .iter()
+            .any(|ext| ext.eq_ignore_ascii_case(extension))
+    }
+
+    fn dialect(&self) -> Dialect {
+        match self.profile {
+            Some(ref profile) => profile.dialect(),
+            None => self.config.dialect,
+        }
+    }
+
+    fn analyzer(&self) -> Analyzer {
+        match (self.analyzer, &self.profile) {
+            (Some(analyzer), _) => analyzer,
+            (None, Some(profile)) => profile.analyzer(),
+            (None, None) => Analyzer::new(self.config.dialect),
+        }
+    }
+
+    fn process(
+        &self,
+        path: &Path,
+        pipeline: Option<&mut Pipeline>,
+    ) -> FileReport {
+        let mut report = FileReport::new(path);
+
+        let src = match fs::read_to_string(path) {
+            Ok(src) => src,
+            Err(e) => {
+                report.error = Some(e.to_string());
+                return report;
+            },
+        };
+
+        let src = match pipeline {
+            Some(pipeline) => {
+                let mut program = Program::parse(&src, self.dialect());
+                let mut context = Context::new();
+                let _ = context.set("path", path.display().to_string());
+
+                report.diagnostics = pipeline.run(&mut program, &mut context);
+                program.to_string()
+            },
+            None => src,
+        };
+
+        report.lines = src.lines().count();
+        if let Some(ref profile) = self.profile {
+            report.violations = profile.validate(&src);
+        }
+        report.estimated_time =
+            Some(self.analyzer().analyze(&src).total_time());
+
+        if self.config.keep_output {
+            report.output = Some(src);
+        }
+
+        report
+    }
+}
+
+impl Debug for Batch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Batch")
+            .field("config", &self.config)
+            .field("profile", &self.profile)
+            .field("analyzer", &self.analyzer)
+            .field("pipeline", &self.pipeline.is_some())
+            .finish()
+    }
+}
+
+/// The results for a single file in a [`Batch`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileReport {
+    /// The file.
+    pub path: PathBuf,
+    /// Why the file couldn't be processed, if it couldn't be read.
+    pub error: Option<String>,
+    /// The number of lines, after the pipeline has run.
+    pub lines: usize,
+    /// How long the program is expected to take, in seconds.
+    pub estimated_time: Option<f32>,
+    /// Anything the pipeline reported.
+    pub diagnostics: Diagnostics,
+    /// Anything which won't work on the [`MachineProfile`].
+    pub violations: Vec<Violation>,
+    /// The program's text after the pipeline ran, if
+    /// [`BatchConfig::keep_output`] was set.
+    pub output: Option<String>,
+}
+
+impl FileReport {
+    fn new(path: &Path) -> Self {
+        FileReport {
+            path: path.to_path_buf(),
+            error: None,
+            lines: 0,
+            estimated_time: None,
+            diagnostics: Diagnostics::new(),
+            violations: Vec::new(),
+            output: None,
+        }
+    }
+
+    /// Was the file read, without any violations or pipeline errors?
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+            && self.violations.is_empty()
+            && !self.diagnostics.has_errors()
+    }
+
+    /// The number of violations and pipeline warnings or errors.
+    pub fn problem_count(&self) -> usize {
+        let diagnostics = self
+            .diagnostics
+            .iter()
+            .filter(|d| d.severity >= Severity::Warning)
+            .count();
+
+        self.violations.len() + diagnostics
+    }
+}
+
+/// The results of running a [`Batch`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchReport {
+    files: Vec<FileReport>,
+}
+
+impl BatchReport {
+    /// The result for each file.
+    pub fn files(&self) -> &[FileReport] { &self.files }
+
+    /// The result for a particular file.
+    pub fn file<P: AsRef<Path>>(&self, path: P) -> Option<&FileReport> {
+        self.files.iter().find(|f| f.path == path.as_ref())
+    }
+
+    /// Totals across every file.
+    pub fn summary(&self) -> BatchSummary {
+        let mut summary = BatchSummary::default();
+
+        for file in &self.files {
+            summary.files += 1;
+            if file.error.is_some() {
+                summary.unreadable += 1;
+            } else if !file.is_ok() {
+                summary.failed += 1;
+            }
+
+            summary.lines += file.lines;
+            summary.violations += file.violations.len();
+            for diagnostic in &file.diagnostics {
+                match diagnostic.severity {
+                    Severity::Info => {},
+                    Severity::Warning => summary.warnings += 1,
+                    Severity::Error => summary.errors += 1,
+                }
+            }
+            summary.estimated_time += file.estimated_time.unwrap_or(0.0);
+        }
+
+        summary
+    }
+}
+
+/// Totals across every file in a [`BatchReport`].
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct BatchSummary {
+    /// The number of files processed.
+    pub files: usize,
+    /// Files which couldn't be read.
+    pub unreadable: usize,
+    /// Files which were read, but had violations or pipeline errors.
+    pub failed: usize,
+    /// The total number of lines.
+    pub lines: usize,
+    /// The total number of [`Violation`]s.
+    pub violations: usize,
+    /// The total number of pipeline warnings.
+    pub warnings: usize,
+    /// The total number of pipeline errors.
+    pub errors: usize,
+    /// How long every file would take to run, in seconds.
+    pub estimated_time: f32,
+}
+
+impl BatchSummary {
+    /// The number of files which passed every check.
+    pub fn passed(&self) -> usize { self.files - self.unreadable - self.failed }
+}
+
+impl Display for BatchSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} files passed", self.passed(), self.files)?;
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+        if self.unreadable > 0 {
+            write!(f, ", {} couldn't be read", self.unreadable)?;
+        }
+
+        write!(
+            f,
+            " ({} violations, {} errors, {} warnings, {:.0}s in total)",
+            self.violations, self.errors, self.warnings, self.estimated_time
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pipeline::RenumberPass,
+        profile::{Axis, ViolationKind},
+    };
+
+    /// A fresh directory to put test files in.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gcode-batch-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("nested")).unwrap();
+
+        fs::write(dir.join("a.gcode"), "G1 X10 F600\n").unwrap();
+        fs::write(dir.join("b.NC"), "N1 G1 X500 F600\nN2 G1 X0\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a program").unwrap();
+        fs::write(dir.join("nested").join("c.gcode"), "G1 Y5 F600\n").unwrap();
+
+        dir
+    }
+
+    fn names(report: &BatchReport) -> Vec<String> {
+        report
+            .files()
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn check_a_folder_against_a_machine() {
+        let dir = scratch_dir("profile");
+        let mut profile = MachineProfile::new("small");
+        profile.axes.push(Axis::new('X', 0.0, 200.0));
+
+        let report = Batch::new(BatchConfig::default())
+            .with_profile(profile)
+            .run_dir(&dir)
+            .unwrap();
+
+        assert_eq!(names(&report), vec!["a.gcode", "b.NC"]);
+        assert!(report.files()[0].is_ok());
+        let b = &report.files()[1];
+        assert!(matches!(
+            b.violations[0].kind,
+            ViolationKind::OutOfBounds { axis: 'X', .. }
+        ));
+
+        let summary = report.summary();
+        assert_eq!(summary.files, 2);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.lines, 3);
+        // 10mm, then 500mm there and back, at 10mm/s
+        assert!((summary.estimated_time - 101.0).abs() < 0.01);
+        assert!(summary
+            .to_string()
+            .starts_with("1 of 2 files passed, 1 failed"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn run_a_pipeline_in_parallel() {
+        let dir = scratch_dir("parallel");
+        let config = BatchConfig {
+            recursive: true,
+            threads: 3,
+            keep_output: true,
+            ..Default::default()
+        };
+
+        let report = Batch::new(config)
+            .with_pipeline(|| {
+                Pipeline::new().with_pass(RenumberPass::new(10, 10))
+            })
+            .run_dir(&dir)
+            .unwrap();
+
+        assert_eq!(names(&report), vec!["a.gcode", "b.NC", "c.gcode"]);
+        let b = report.file(dir.join("b.NC")).unwrap();
+        assert_eq!(b.output.as_deref(), Some("N10 G1 X500 F600\nN20 G1 X0\n"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_files_are_reported() {
+        let missing = std::env::temp_dir().join("gcode-batch-missing.gcode");
+
+        let report = Batch::new(BatchConfig::default()).run_files(&[missing]);
+
+        assert!(report.files()[0].error.is_some());
+        assert_eq!(report.summary().unreadable, 1);
+        assert_eq!(report.summary().passed(), 0);
+    }
+}
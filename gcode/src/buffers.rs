@@ -72,10 +72,24 @@ impl<T, A: Array<Item = T>> Buffer<T> for ArrayVec<A> {
     }
 
     fn as_slice(&self) -> &[T] {
-        &self
+        self
     }
 }
 
+/// A read-only [`Buffer`] for items which live somewhere else, like a
+/// `static` table of [`Word`]s in firmware (see [`BorrowedGCode`]).
+///
+/// There's never any room to add more items.
+///
+/// [`BorrowedGCode`]: crate::BorrowedGCode
+impl<T> Buffer<T> for &[T] {
+    fn try_push(&mut self, item: T) -> Result<(), CapacityError<T>> {
+        Err(CapacityError(item))
+    }
+
+    fn as_slice(&self) -> &[T] { self }
+}
+
 /// The smallest usable set of [`Buffers`].
 ///
 /// ```rust
@@ -119,7 +133,7 @@ with_std! {
             Ok(())
         }
 
-        fn as_slice(&self) -> &[T] { &self }
+        fn as_slice(&self) -> &[T] { self }
     }
 }
 
@@ -0,0 +1,401 @@
+//! Tuning the time estimator against a real machine.
+//!
+//! The [`Analyzer`] only knows what the [`EstimatorConfig`] tells it, so its
+//! estimates drift from reality on machines which accelerate slowly or
+//! spend time processing each command. Given programs which have actually
+//! been run, along with how long they (or each of their layers) took,
+//! [`calibrate()`] fits the `acceleration` and `command_overhead` which
+//! best explain the measured times.
+//!
+//! ```rust
+//! use gcode::{
+//!     analysis::{Analyzer, EstimatorConfig},
+//!     calibration::{self, Measurement, TimedRun},
+//!     dialect::Dialect,
+//! };
+//!
+//! let short = "G1 X10 F600\nG1 X0";
+//! let long = "G1 X100 F6000\nG1 X0\nG1 X100\nG1 X0";
+//! // times measured on a machine with an acceleration of 100mm/s² and a
+//! // 0.05s overhead on each move
+//! let runs = [
+//!     TimedRun::new(short, Measurement::Total(2.3)),
+//!     TimedRun::new(long, Measurement::Total(8.2)),
+//! ];
+//!
+//! let analyzer = Analyzer::new(Dialect::generic());
+//! let calibration = calibration::calibrate(&analyzer, &runs).unwrap();
+//!
+//! assert!(calibration.error_after < calibration.error_before);
+//! let acceleration = calibration.config.acceleration.unwrap();
+//! assert!((acceleration - 100.0).abs() < 5.0);
+//! assert!((calibration.config.command_overhead - 0.05).abs() < 0.01);
+//!
+//! let tuned = analyzer.with_config(calibration.config);
+//! assert!((tuned.analyze(short).total_time() - 2.3).abs() < 0.05);
+//! ```
+//!
+//! Total times include anything the estimator can't see, like waiting for
+//! heaters, so per-layer times give better results when they are
+//! available.
+
+use crate::analysis::{self, Analysis, Analyzer, EstimatorConfig, SegmentKind};
+use core::fmt::{self, Display, Formatter};
+use std::vec::Vec;
+
+/// How long a program took to run on the real machine.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Measurement {
+    /// The whole program took this many seconds.
+    Total(f32),
+    /// How many seconds each layer took, starting from the first layer
+    /// change (see [`Analysis::layer_changes()`]).
+    Layers(Vec<f32>),
+}
+
+/// A program and how long it took to run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedRun<'src> {
+    /// The program's source text.
+    pub src: &'src str,
+    /// The times reported by the machine.
+    pub measurement: Measurement,
+}
+
+impl<'src> TimedRun<'src> {
+    /// Create a new [`TimedRun`].
+    pub fn new(src: &'src str, measurement: Measurement) -> Self {
+        TimedRun { src, measurement }
+    }
+}
+
+/// The result of [`calibrate()`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Calibration {
+    /// The analyzer's original config, with the fitted `acceleration` and
+    /// `command_overhead`.
+    pub config: EstimatorConfig,
+    /// The root-mean-square relative error of the original estimates (e.g.
+    /// `0.1` means they were typically 10% out).
+    pub error_before: f32,
+    /// The root-mean-square relative error using the fitted config.
+    pub error_after: f32,
+}
+
+/// Reasons [`calibrate()`] couldn't fit a config.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CalibrationError {
+    /// There were no (non-zero) times to fit against.
+    NoMeasurements,
+    /// A run reported times for a different number of layers than its
+    /// program contains.
+    LayerCountMismatch {
+        /// The run's index.
+        run: usize,
+        /// The number of layers in the program.
+        expected: usize,
+        /// The number of layer times which were given.
+        found: usize,
+    },
+}
+
+impl Display for CalibrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationError::NoMeasurements => {
+                write!(f, "there are no measured times to calibrate against")
+            },
+            CalibrationError::LayerCountMismatch {
+                run,
+                expected,
+                found,
+            } => write!(
+                f,
+                "run {} has {} layers, but {} layer times were given",
+                run, expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CalibrationError {}
+
+/// The smallest and largest accelerations considered, in mm/s².
+const ACCELERATION_RANGE: (f32, f32) = (10.0, 100_000.0);
+/// The number of accelerations tried before refining the best one.
+const COARSE_STEPS: usize = 64;
+
+/// Find the `acceleration` and `command_overhead` which make the analyzer's
+/// estimates best match the times measured on a real machine, keeping the
+/// rest of its [`EstimatorConfig`].
+///
+/// The fit minimises the squared relative error of every measured time, so
+/// short layers count as much as long ones. An acceleration of `None` is
+/// chosen if instant acceleration fits best.
+pub fn calibrate(
+    analyzer: &Analyzer,
+    runs: &[TimedRun<'_>],
+) -> Result<Calibration, CalibrationError> {
+    let base = *analyzer.config();
+    let mut samples = Vec::new();
+
+    for (i, run) in runs.iter().enumerate() {
+        let analysis = analyzer.analyze(run.src);
+        add_samples(&mut samples, i, &analysis, &run.measurement, &base)?;
+    }
+
+    samples.retain(|sample| sample.actual > 0.0);
+    if samples.is_empty() {
+        return Err(CalibrationError::NoMeasurements);
+    }
+
+    let error_before =
+        rms_error(&samples, base.acceleration, base.command_overhead);
+
+    let mut best = fit_overhead(&samples, None);
+    let consider = |best: &mut Fit, acceleration: f32| {
+        let candidate = fit_overhead(&samples, Some(acceleration));
+        if candidate.2 < best.2 {
+            *best = candidate;
+        }
+    };
+
+    // a coarse sweep over a logarithmic scale, then successively finer ones
+    // around the best acceleration
+    let (low, high) = ACCELERATION_RANGE;
+    let mut step = libm::powf(high / low, 1.0 / COARSE_STEPS as f32);
+    for i in 0..=COARSE_STEPS {
+        consider(&mut best, low * libm::powf(step, i as f32));
+    }
+    for _ in 0..6 {
+        if let Some(centre) = best.0 {
+            for i in -4..=4_i32 {
+                let acceleration = centre * libm::powf(step, i as f32 / 4.0);
+                consider(&mut best, acceleration);
+            }
+        }
+        step = libm::powf(step, 0.25);
+    }
+
+    let (acceleration, command_overhead, error_after) = best;
+
+    Ok(Calibration {
+        config: EstimatorConfig {
+            acceleration,
+            command_overhead,
+            ..base
+        },
+        error_before,
+        error_after,
+    })
+}
+
+/// A stretch of a program with a measured duration.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Sample {
+    /// Each move's length and feed rate.
+    moves: Vec<(f32, f32)>,
+    /// Time spent dwelling, which doesn't depend on the config.
+    dwell: f32,
+    actual: f32,
+}
+
+impl Sample {
+    fn estimate(&self, acceleration: Option<f32>, overhead: f32) -> f32 {
+        self.base_estimate(acceleration) + overhead * self.moves.len() as f32
+    }
+
+    /// The estimate without any per-command overhead.
+    fn base_estimate(&self, acceleration: Option<f32>) -> f32 {
+        self.moves
+            .iter()
+            .map(|&(length, feed_rate)| {
+                analysis::move_duration(length, feed_rate, acceleration)
+            })
+            .sum::<f32>()
+            + self.dwell
+    }
+}
+
+fn add_samples(
+    samples: &mut Vec<Sample>,
+    run: usize,
+    analysis: &Analysis,
+    measurement: &Measurement,
+    config: &EstimatorConfig,
+) -> Result<(), CalibrationError> {
+    let (boundaries, times) = match measurement {
+        Measurement::Total(total) => (vec![0], vec![*total]),
+        Measurement::Layers(times) => {
+            let boundaries: Vec<usize> = analysis
+                .layer_changes()
+                .iter()
+                .map(|event| event.span.start)
+                .collect();
+
+            if boundaries.len() != times.len() {
+                return Err(CalibrationError::LayerCountMismatch {
+                    run,
+                    expected: boundaries.len(),
+                    found: times.len(),
+                });
+            }
+
+            (boundaries, times.clone())
+        },
+    };
+
+    let first = samples.len();
+    samples.extend(times.into_iter().map(|actual| Sample {
+        actual,
+        ..Sample::default()
+    }));
+
+    for segment in analysis.segments() {
+        let layer =
+            boundaries.partition_point(|&start| start <= segment.span.start);
+        // anything before the first layer wasn't measured
+        let sample = match layer.checked_sub(1) {
+            Some(layer) => &mut samples[first + layer],
+            None => continue,
+        };
+
+        match segment.kind {
+            SegmentKind::Motion(ref motion) => sample
+                .moves
+                .push((motion.length(), config.feed_rate_for(motion))),
+            SegmentKind::Dwell { .. } => sample.dwell += segment.duration,
+        }
+    }
+
+    Ok(())
+}
+
+/// An acceleration, overhead and the resulting error.
+type Fit = (Option<f32>, f32, f32);
+
+/// Find the best overhead for a particular acceleration.
+fn fit_overhead(samples: &[Sample], acceleration: Option<f32>) -> Fit {
+    // the estimate is linear in the overhead, so least squares has a
+    // closed-form solution
+    let mut numerator = 0.0;
+    let mut denominator = 0.0;
+
+    for sample in samples {
+        let commands = sample.moves.len() as f32 / sample.actual;
+        let remaining = (sample.actual - sample.base_estimate(acceleration))
+            / sample.actual;
+
+        numerator += commands * remaining;
+        denominator += commands * commands;
+    }
+
+    let overhead = if denominator > 0.0 {
+        (numerator / denominator).max(0.0)
+    } else {
+        0.0
+    };
+
+    (
+        acceleration,
+        overhead,
+        rms_error(samples, acceleration, overhead),
+    )
+}
+
+fn rms_error(
+    samples: &[Sample],
+    acceleration: Option<f32>,
+    overhead: f32,
+) -> f32 {
+    let total: f32 = samples
+        .iter()
+        .map(|sample| {
+            let error = (sample.estimate(acceleration, overhead)
+                - sample.actual)
+                / sample.actual;
+            error * error
+        })
+        .sum();
+
+    libm::sqrtf(total / samples.len() as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+
+    const SRC: &str = ";LAYER:0\nG1 X10 Y10 F1200\nG1 X0\nG4 P500\n\
+                       ;LAYER:1\nG1 Z5 F300\nG1 Y0 F3000\nG1 X50\nG1 X0\n";
+
+    fn measured(config: EstimatorConfig) -> Vec<f32> {
+        let analysis = Analyzer::new(Dialect::reprap())
+            .with_config(config)
+            .analyze(SRC);
+        let layers = analysis.layer_changes();
+
+        vec![
+            layers[1].time - layers[0].time,
+            analysis.total_time() - layers[1].time,
+        ]
+    }
+
+    #[test]
+    fn recover_the_parameters_used_to_generate_the_times() {
+        let truth = EstimatorConfig {
+            acceleration: Some(500.0),
+            command_overhead: 0.02,
+            ..EstimatorConfig::default()
+        };
+        let runs = [TimedRun::new(SRC, Measurement::Layers(measured(truth)))];
+
+        let got = calibrate(&Analyzer::new(Dialect::reprap()), &runs).unwrap();
+
+        assert!(got.error_after < 1e-3, "{:?}", got);
+        assert!(got.error_before > 0.01, "{:?}", got);
+        let acceleration = got.config.acceleration.unwrap();
+        assert!((acceleration - 500.0).abs() < 25.0, "{:?}", got);
+        assert!((got.config.command_overhead - 0.02).abs() < 0.005);
+    }
+
+    #[test]
+    fn instant_acceleration_is_kept_when_it_fits() {
+        let times = measured(EstimatorConfig::default());
+        let runs = [TimedRun::new(SRC, Measurement::Layers(times))];
+
+        let got = calibrate(&Analyzer::new(Dialect::reprap()), &runs).unwrap();
+
+        assert_eq!(got.config, EstimatorConfig::default());
+        assert!(got.error_after < 1e-6);
+    }
+
+    #[test]
+    fn measurements_must_match_the_program() {
+        let analyzer = Analyzer::new(Dialect::reprap());
+
+        assert_eq!(
+            calibrate(&analyzer, &[]),
+            Err(CalibrationError::NoMeasurements)
+        );
+        assert_eq!(
+            calibrate(
+                &analyzer,
+                &[TimedRun::new(SRC, Measurement::Layers(vec![1.0]))]
+            ),
+            Err(CalibrationError::LayerCountMismatch {
+                run: 0,
+                expected: 2,
+                found: 1
+            })
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use crate::{Comment, Mnemonic, Span, Word};
 
 #[allow(unused_imports)] // rustdoc links
-use crate::{buffers::Buffers, GCode};
+use crate::{
+    buffers::Buffers, dialect::Dialect, GCode, StreamingParser, WordValue,
+};
 
 /// Callbacks used during the parsing process to indicate possible errors.
 pub trait Callbacks {
@@ -56,9 +58,60 @@ pub trait Callbacks {
 
     /// A [`Word`]'s letter was encountered without an accompanying number.
     fn letter_without_a_number(&mut self, _value: &str, _span: Span) {}
+
+    /// A [`Word`]'s number was malformed (e.g. the `1.2.3` in `X1.2.3`), so
+    /// only the part before its second decimal point was kept (see
+    /// [`Dialect::lenient`]).
+    ///
+    /// The `recovered` [`Word`] is still used, and `text` is the number as it
+    /// was written.
+    fn partial_word(&mut self, _recovered: Word, _text: &str, _span: Span) {}
+
+    /// A [`StreamingParser`] received a line which was too long for its
+    /// buffer, so the (zero-based) line was skipped.
+    fn line_buffer_overflowed(&mut self, _line: usize) {}
+
+    /// A [`Word`]'s value was a parameter or bracketed expression (see
+    /// [`Dialect::expressions`]).
+    ///
+    /// Returning `Some` replaces the expression with a number, otherwise the
+    /// [`Word`] keeps a [`WordValue::Expression`]. Commands and line numbers
+    /// can't be used without a number, so they are reported as
+    /// [`Callbacks::unknown_content()`] instead.
+    fn evaluate_expression(
+        &mut self,
+        _expression: &str,
+        _span: Span,
+    ) -> Option<f32> {
+        None
+    }
+
+    /// A parameter assignment (e.g. `#1 = [#2 + 1]`) was encountered (see
+    /// [`Dialect::expressions`]).
+    fn parameter_assignment(&mut self, _assignment: &str, _span: Span) {}
+
+    /// A line's checksum (see [`Dialect::checksums`]) didn't match the one
+    /// calculated from the text before it.
+    fn checksum_mismatch(
+        &mut self,
+        _written: u32,
+        _calculated: u8,
+        _span: Span,
+    ) {
+    }
+
+    /// A line number wasn't one more than the previous one (see
+    /// [`Dialect::checksums`]), meaning a line was skipped or repeated.
+    fn line_number_out_of_sequence(
+        &mut self,
+        _expected: u32,
+        _found: u32,
+        _span: Span,
+    ) {
+    }
 }
 
-impl<'a, C: Callbacks + ?Sized> Callbacks for &'a mut C {
+impl<C: Callbacks + ?Sized> Callbacks for &mut C {
     fn unknown_content(&mut self, text: &str, span: Span) {
         (*self).unknown_content(text, span);
     }
@@ -119,6 +172,39 @@ impl<'a, C: Callbacks + ?Sized> Callbacks for &'a mut C {
     fn letter_without_a_number(&mut self, value: &str, span: Span) {
         (*self).letter_without_a_number(value, span);
     }
+
+    fn partial_word(&mut self, recovered: Word, text: &str, span: Span) {
+        (*self).partial_word(recovered, text, span);
+    }
+
+    fn line_buffer_overflowed(&mut self, line: usize) {
+        (*self).line_buffer_overflowed(line);
+    }
+
+    fn evaluate_expression(
+        &mut self,
+        expression: &str,
+        span: Span,
+    ) -> Option<f32> {
+        (*self).evaluate_expression(expression, span)
+    }
+
+    fn parameter_assignment(&mut self, assignment: &str, span: Span) {
+        (*self).parameter_assignment(assignment, span);
+    }
+
+    fn checksum_mismatch(&mut self, written: u32, calculated: u8, span: Span) {
+        (*self).checksum_mismatch(written, calculated, span);
+    }
+
+    fn line_number_out_of_sequence(
+        &mut self,
+        expected: u32,
+        found: u32,
+        span: Span,
+    ) {
+        (*self).line_number_out_of_sequence(expected, found, span);
+    }
 }
 
 /// A set of callbacks that ignore any errors that occur.
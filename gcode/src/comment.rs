@@ -12,3 +12,30 @@ pub struct Comment<'input> {
     /// Where the comment is located in the original string.
     pub span: Span,
 }
+
+impl<'input> Comment<'input> {
+    /// The comment's text, without its delimiters (`;` or parentheses) or
+    /// any surrounding whitespace.
+    ///
+    /// ```rust
+    /// # use gcode::{Comment, Span};
+    /// let semicolon = Comment { value: "; LAYER:3", span: Span::PLACEHOLDER };
+    /// assert_eq!(semicolon.text(), "LAYER:3");
+    ///
+    /// let parens = Comment { value: "( tool 1 )", span: Span::PLACEHOLDER };
+    /// assert_eq!(parens.text(), "tool 1");
+    /// ```
+    pub fn text(&self) -> &'input str {
+        let value = self.value;
+
+        let inner = if let Some(rest) = value.strip_prefix(';') {
+            rest
+        } else if let Some(rest) = value.strip_prefix('(') {
+            rest.strip_suffix(')').unwrap_or(rest)
+        } else {
+            value
+        };
+
+        inner.trim()
+    }
+}
@@ -0,0 +1,890 @@
+//! O-word control flow.
+//!
+//! RS-274/NGC programs (e.g. LinuxCNC's) can define subroutines, branch and
+//! loop using *O-words*. Each control statement has a label (`O100` or
+//! `O<name>`) and a keyword, and statements belonging to the same block
+//! share a label.
+//!
+//! ```text
+//! O<square> sub
+//!   G1 X#1 Y#1
+//! O<square> endsub
+//!
+//! O100 if [#<_size> GT 10]
+//!   O<square> call [#<_size>]
+//! O100 endif
+//! ```
+//!
+//! Fanuc-style programs jump to the line with a particular sequence number
+//! instead, using `GOTO 100` or `IF [#1 GT 10] GOTO 100`.
+//!
+//! [`ControlFlow`] works out how the statements in a program fit together,
+//! which is used by the [`Executor`][crate::executor::Executor] when running
+//! a program and by the [`lint`][crate::lint] module.
+//!
+//! ```rust
+//! use gcode::control::{ControlFlow, Keyword, Label};
+//!
+//! let src = "O1 if [#1 GT 0]\nG0 X1\nO1 else\nG0 X2\nO1 endif\n";
+//! let control = ControlFlow::new(src);
+//!
+//! assert!(control.errors().is_empty());
+//! assert_eq!(control.statement(2).unwrap().keyword, Keyword::Else);
+//! assert_eq!(control.next_branch(0), Some(2));
+//! assert_eq!(control.end_of(0), Some(4));
+//! ```
+//!
+//! Each `GOTO` with a constant target is resolved ahead of time, and must
+//! refer to a sequence number which appears exactly once.
+//!
+//! ```rust
+//! use gcode::control::{ControlErrorKind, ControlFlow};
+//!
+//! let src = "N10 #1 = [#1 + 1]\nIF [#1 LT 3] GOTO 10\nGOTO 20\nN30 M30";
+//! let control = ControlFlow::new(src);
+//!
+//! assert_eq!(control.jump_target(1), Some(0));
+//! assert_eq!(control.errors()[0].kind, ControlErrorKind::UndefinedLabel(20));
+//! ```
+
+use crate::{
+    executor::{parse_statement, Item},
+    expr::{normalize_name, Cursor, Expression, ExpressionError},
+    Span,
+};
+use core::{
+    fmt::{self, Display, Formatter},
+    ops::Range,
+};
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    string::String,
+    vec::Vec,
+};
+
+/// The label identifying a block of control statements.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Label {
+    /// A numbered label (e.g. `O100`).
+    Number(u32),
+    /// A named label (e.g. `O<square>`), normalized so it ignores case and
+    /// whitespace.
+    Named(String),
+}
+
+impl Display for Label {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Label::Number(number) => write!(f, "O{}", number),
+            Label::Named(name) => write!(f, "O<{}>", name),
+        }
+    }
+}
+
+/// The keyword in a control statement.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+#[allow(missing_docs)]
+pub enum Keyword {
+    Sub,
+    EndSub,
+    Call,
+    Return,
+    If,
+    ElseIf,
+    Else,
+    EndIf,
+    /// Either the start of a `while` loop or the end of a `do` loop.
+    While,
+    EndWhile,
+    Do,
+    Break,
+    Continue,
+    Repeat,
+    EndRepeat,
+}
+
+impl Keyword {
+    const NAMES: [(&'static str, Keyword); 15] = [
+        ("sub", Keyword::Sub),
+        ("endsub", Keyword::EndSub),
+        ("call", Keyword::Call),
+        ("return", Keyword::Return),
+        ("if", Keyword::If),
+        ("elseif", Keyword::ElseIf),
+        ("else", Keyword::Else),
+        ("endif", Keyword::EndIf),
+        ("while", Keyword::While),
+        ("endwhile", Keyword::EndWhile),
+        ("do", Keyword::Do),
+        ("break", Keyword::Break),
+        ("continue", Keyword::Continue),
+        ("repeat", Keyword::Repeat),
+        ("endrepeat", Keyword::EndRepeat),
+    ];
+
+    /// Look up a keyword by name (ignoring case).
+    pub fn from_name(name: &str) -> Option<Keyword> {
+        Keyword::NAMES
+            .iter()
+            .find(|(candidate, _)| candidate.eq_ignore_ascii_case(name))
+            .map(|&(_, keyword)| keyword)
+    }
+
+    /// The keyword, as it is written in a program.
+    pub fn name(self) -> &'static str {
+        Keyword::NAMES
+            .iter()
+            .find(|&&(_, keyword)| keyword == self)
+            .map(|&(name, _)| name)
+            .unwrap_or_default()
+    }
+
+    fn opens_loop(self) -> bool {
+        matches!(self, Keyword::While | Keyword::Do | Keyword::Repeat)
+    }
+}
+
+/// A single control statement (e.g. `O100 if [#1 GT 2]`).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Statement {
+    /// The block's label.
+    pub label: Label,
+    /// What the statement does.
+    pub keyword: Keyword,
+    /// Any values following the keyword (a condition, loop count, return
+    /// value, or a subroutine's arguments).
+    pub arguments: Vec<Expression>,
+}
+
+impl Statement {
+    /// The condition for an `if`, `elseif` or `while`, or the number of
+    /// times to `repeat`.
+    pub fn condition(&self) -> Option<&Expression> {
+        match self.keyword {
+            Keyword::If
+            | Keyword::ElseIf
+            | Keyword::While
+            | Keyword::Repeat => self.arguments.first(),
+            _ => None,
+        }
+    }
+}
+
+/// Try to parse a control statement, with the cursor positioned just after
+/// the `O`.
+///
+/// Returns `None` if this is a plain `O` word (i.e. a program number).
+pub(crate) fn parse(
+    cursor: &mut Cursor<'_>,
+) -> Result<Option<Statement>, ExpressionError> {
+    cursor.skip_whitespace();
+
+    let label = if cursor.peek() == Some('<') {
+        let _ = cursor.bump();
+        let rest = cursor.rest();
+        let end = rest.find('>').ok_or(ExpressionError::UnexpectedEnd)?;
+        cursor.seek(cursor.position() + end + 1);
+        Label::Named(normalize_name(&rest[..end]))
+    } else {
+        let rest = cursor.rest();
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        match rest[..digits].parse() {
+            Ok(number) => {
+                cursor.seek(cursor.position() + digits);
+                Label::Number(number)
+            },
+            Err(_) => return Ok(None),
+        }
+    };
+
+    cursor.skip_whitespace();
+    let rest = cursor.rest();
+    let length = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+
+    let keyword = match Keyword::from_name(&rest[..length]) {
+        Some(keyword) => keyword,
+        None if matches!(label, Label::Named(_)) => {
+            return Err(cursor.unexpected())
+        },
+        None => return Ok(None),
+    };
+    cursor.seek(cursor.position() + length);
+
+    let mut arguments = Vec::new();
+
+    match keyword {
+        Keyword::If | Keyword::ElseIf | Keyword::While | Keyword::Repeat => {
+            arguments.push(cursor.real_value()?)
+        },
+        Keyword::Call | Keyword::Return | Keyword::EndSub => {
+            cursor.skip_whitespace();
+            while cursor
+                .peek()
+                .is_some_and(|c| c != '(' && c != ';' && c != '*')
+            {
+                arguments.push(cursor.real_value()?);
+                cursor.skip_whitespace();
+            }
+        },
+        _ => {},
+    }
+
+    Ok(Some(Statement {
+        label,
+        keyword,
+        arguments,
+    }))
+}
+
+/// A Fanuc-style jump to the line with a particular sequence number (e.g.
+/// `GOTO 100` or `IF [#1 GT 2] GOTO 100`).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Goto {
+    /// Only jump if this is true.
+    pub condition: Option<Expression>,
+    /// The sequence number (`N` word) to jump to.
+    pub target: Expression,
+}
+
+/// Try to parse a `GOTO` or `IF ... GOTO`, with the cursor positioned just
+/// after the first `letter`.
+///
+/// Returns `None` (leaving the cursor where it was) if this is an ordinary
+/// word, otherwise the [`Goto`] and where its target was written.
+pub(crate) fn parse_goto(
+    cursor: &mut Cursor<'_>,
+    letter: char,
+) -> Result<Option<(Goto, Range<usize>)>, ExpressionError> {
+    let start = cursor.position();
+
+    let condition = if letter.eq_ignore_ascii_case(&'I')
+        && starts_with_keyword(cursor.rest(), "F")
+    {
+        cursor.seek(start + 1);
+        let condition = cursor.real_value()?;
+        if !cursor.eat("GOTO") {
+            cursor.skip_whitespace();
+            return Err(cursor.unexpected());
+        }
+        Some(condition)
+    } else if letter.eq_ignore_ascii_case(&'G')
+        && starts_with_keyword(cursor.rest(), "OTO")
+    {
+        cursor.seek(start + 3);
+        None
+    } else {
+        return Ok(None);
+    };
+
+    cursor.skip_whitespace();
+    let target_start = cursor.position();
+    let target = cursor.real_value()?;
+
+    Ok(Some((
+        Goto { condition, target },
+        target_start..cursor.position(),
+    )))
+}
+
+fn starts_with_keyword(text: &str, keyword: &str) -> bool {
+    match text.get(..keyword.len()) {
+        Some(start) if start.eq_ignore_ascii_case(keyword) => !text
+            [keyword.len()..]
+            .starts_with(|c: char| c.is_ascii_alphabetic()),
+        _ => false,
+    }
+}
+
+/// How the control statements in a program fit together.
+///
+/// Lines are identified by their (zero-based) line number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlFlow {
+    statements: BTreeMap<usize, Statement>,
+    /// The statement which closes each block.
+    ends: BTreeMap<usize, usize>,
+    /// The statement which opened each block, for closing statements.
+    starts: BTreeMap<usize, usize>,
+    /// The next `elseif`, `else` or `endif` in a conditional.
+    branches: BTreeMap<usize, usize>,
+    /// The loop (or subroutine) a `break`, `continue` or `return` belongs
+    /// to.
+    enclosing: BTreeMap<usize, usize>,
+    subroutines: BTreeMap<Label, usize>,
+    gotos: BTreeMap<usize, Goto>,
+    /// Every line with a particular sequence number.
+    labels: BTreeMap<u32, Vec<usize>>,
+    /// Where each `GOTO` with a constant target goes.
+    jumps: BTreeMap<usize, usize>,
+    errors: Vec<ControlError>,
+}
+
+impl ControlFlow {
+    /// Find every control statement in a program and match them up.
+    ///
+    /// Lines which can't be parsed are ignored.
+    pub fn new(src: &str) -> Self {
+        let mut statements = BTreeMap::new();
+        let mut gotos = Vec::new();
+        let mut labels: BTreeMap<u32, Vec<(usize, Span)>> = BTreeMap::new();
+        let mut offset = 0;
+
+        for (line, text) in src.split_inclusive('\n').enumerate() {
+            let start = offset;
+            offset += text.len();
+            let items =
+                match parse_statement(text.trim_end_matches(['\n', '\r'])) {
+                    Ok(items) => items,
+                    Err(_) => continue,
+                };
+            let span_of = |range: Range<usize>| {
+                Span::new(start + range.start, start + range.end, line)
+            };
+
+            for item in items {
+                match item {
+                    Item::Control(statement) => {
+                        let _ = statements.entry(line).or_insert(statement);
+                    },
+                    Item::Goto { goto, start, end } => {
+                        gotos.push((line, goto, span_of(start..end)))
+                    },
+                    Item::Word {
+                        letter,
+                        value: Expression::Number(number),
+                        start,
+                        end,
+                    } if letter.eq_ignore_ascii_case(&'N') => {
+                        if let Some(number) = sequence_number(number) {
+                            labels
+                                .entry(number)
+                                .or_default()
+                                .push((line, span_of(start..end)));
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        let mut control = ControlFlow::from_statements(statements);
+        control.resolve_gotos(gotos, labels);
+        control
+    }
+
+    /// Check every `GOTO` with a constant target against the program's
+    /// sequence numbers, filling in the jump table.
+    fn resolve_gotos(
+        &mut self,
+        gotos: Vec<(usize, Goto, Span)>,
+        labels: BTreeMap<u32, Vec<(usize, Span)>>,
+    ) {
+        let mut reported = Vec::new();
+
+        for (line, goto, span) in gotos {
+            let target = match goto.target {
+                Expression::Number(number) => sequence_number(number),
+                _ => None,
+            };
+
+            match target.map(|target| (target, labels.get(&target))) {
+                Some((_, Some(found))) if found.len() == 1 => {
+                    let _ = self.jumps.insert(line, found[0].0);
+                },
+                // each duplicate is only reported once
+                Some((target, Some(_))) if reported.contains(&target) => {},
+                Some((target, Some(found))) => {
+                    reported.push(target);
+                    for &(line, span) in &found[1..] {
+                        self.errors.push(ControlError {
+                            line,
+                            span: Some(span),
+                            kind: ControlErrorKind::DuplicateLabel(target),
+                        });
+                    }
+                },
+                Some((target, None)) => self.errors.push(ControlError {
+                    line,
+                    span: Some(span),
+                    kind: ControlErrorKind::UndefinedLabel(target),
+                }),
+                // computed targets are checked as the program runs
+                None => {},
+            }
+
+            let _ = self.gotos.insert(line, goto);
+        }
+
+        self.labels = labels
+            .into_iter()
+            .map(|(number, found)| {
+                (number, found.into_iter().map(|(line, _)| line).collect())
+            })
+            .collect();
+        self.errors.sort_by_key(|error| error.line);
+    }
+
+    fn from_statements(statements: BTreeMap<usize, Statement>) -> Self {
+        let mut control = ControlFlow {
+            statements: BTreeMap::new(),
+            ends: BTreeMap::new(),
+            starts: BTreeMap::new(),
+            branches: BTreeMap::new(),
+            enclosing: BTreeMap::new(),
+            subroutines: BTreeMap::new(),
+            gotos: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            jumps: BTreeMap::new(),
+            errors: Vec::new(),
+        };
+        // open blocks, innermost last, along with the most recent branch
+        // for conditionals
+        let mut open: Vec<(usize, usize)> = Vec::new();
+
+        for (&line, statement) in &statements {
+            match statement.keyword {
+                Keyword::Sub => {
+                    let label = statement.label.clone();
+                    match control.subroutines.entry(label) {
+                        Entry::Occupied(_) => {
+                            control.error(line, ControlErrorKind::Redefined)
+                        },
+                        Entry::Vacant(entry) => {
+                            let _ = entry.insert(line);
+                        },
+                    }
+                    open.push((line, line));
+                },
+                Keyword::If | Keyword::Do | Keyword::Repeat => {
+                    open.push((line, line))
+                },
+                Keyword::While => {
+                    // a while with the same label as an open do closes it
+                    match open.last() {
+                        Some(&(start, _))
+                            if statements[&start].keyword == Keyword::Do
+                                && statements[&start].label
+                                    == statement.label =>
+                        {
+                            let _ = open.pop();
+                            control.close(start, line);
+                        },
+                        _ => open.push((line, line)),
+                    }
+                },
+                Keyword::ElseIf | Keyword::Else => match open.last_mut() {
+                    Some((start, previous))
+                        if statements[start].keyword == Keyword::If
+                            && statements[start].label == statement.label
+                            && statements[previous].keyword
+                                != Keyword::Else =>
+                    {
+                        let _ = control.branches.insert(*previous, line);
+                        *previous = line;
+                    },
+                    _ => control.error(line, ControlErrorKind::Unmatched),
+                },
+                Keyword::EndSub
+                | Keyword::EndIf
+                | Keyword::EndWhile
+                | Keyword::EndRepeat => {
+                    let opener = match statement.keyword {
+                        Keyword::EndSub => Keyword::Sub,
+                        Keyword::EndIf => Keyword::If,
+                        Keyword::EndWhile => Keyword::While,
+                        _ => Keyword::Repeat,
+                    };
+
+                    match open.last() {
+                        Some(&(start, previous))
+                            if statements[&start].keyword == opener
+                                && statements[&start].label
+                                    == statement.label =>
+                        {
+                            let _ = open.pop();
+                            if opener == Keyword::If {
+                                let _ = control.branches.insert(previous, line);
+                            }
+                            control.close(start, line);
+                        },
+                        _ => control.error(line, ControlErrorKind::Unmatched),
+                    }
+                },
+                Keyword::Break | Keyword::Continue => {
+                    let target = open.iter().rev().find(|(start, _)| {
+                        statements[start].keyword.opens_loop()
+                            && statements[start].label == statement.label
+                    });
+                    match target {
+                        Some(&(start, _)) => {
+                            let _ = control.enclosing.insert(line, start);
+                        },
+                        None => {
+                            control.error(line, ControlErrorKind::Unmatched)
+                        },
+                    }
+                },
+                Keyword::Return => {
+                    let target = open.iter().find(|(start, _)| {
+                        statements[start].keyword == Keyword::Sub
+                            && statements[start].label == statement.label
+                    });
+                    match target {
+                        Some(&(start, _)) => {
+                            let _ = control.enclosing.insert(line, start);
+                        },
+                        None => {
+                            control.error(line, ControlErrorKind::Unmatched)
+                        },
+                    }
+                },
+                Keyword::Call => {},
+            }
+        }
+
+        for (start, _) in open {
+            control.error(start, ControlErrorKind::Unclosed);
+        }
+
+        for (&line, statement) in &statements {
+            if statement.keyword == Keyword::Call
+                && !control.subroutines.contains_key(&statement.label)
+            {
+                control.error(line, ControlErrorKind::UndefinedSubroutine);
+            }
+        }
+
+        control.errors.sort_by_key(|error| error.line);
+        control.statements = statements;
+        control
+    }
+
+    fn close(&mut self, start: usize, end: usize) {
+        let _ = self.ends.insert(start, end);
+        let _ = self.starts.insert(end, start);
+    }
+
+    fn error(&mut self, line: usize, kind: ControlErrorKind) {
+        self.errors.push(ControlError {
+            line,
+            span: None,
+            kind,
+        });
+    }
+
+    /// The control statement on a particular line.
+    pub fn statement(&self, line: usize) -> Option<&Statement> {
+        self.statements.get(&line)
+    }
+
+    /// Every control statement in the program, in order.
+    pub fn statements(&self) -> impl Iterator<Item = (usize, &Statement)> + '_ {
+        self.statements
+            .iter()
+            .map(|(&line, statement)| (line, statement))
+    }
+
+    /// The line a subroutine is defined on.
+    pub fn subroutine(&self, label: &Label) -> Option<usize> {
+        self.subroutines.get(label).copied()
+    }
+
+    /// The statement which closes the block opened on a particular line
+    /// (e.g. the `endif` for an `if`, or the closing `while` for a `do`).
+    pub fn end_of(&self, line: usize) -> Option<usize> {
+        self.ends.get(&line).copied()
+    }
+
+    /// The statement which opened the block closed on a particular line.
+    pub fn start_of(&self, line: usize) -> Option<usize> {
+        self.starts.get(&line).copied()
+    }
+
+    /// The statement control moves to when an `if` or `elseif` condition is
+    /// false (the next `elseif`, `else` or `endif`).
+    pub fn next_branch(&self, line: usize) -> Option<usize> {
+        self.branches.get(&line).copied()
+    }
+
+    /// The loop a `break` or `continue` applies to, or the subroutine a
+    /// `return` returns from.
+    pub fn enclosing(&self, line: usize) -> Option<usize> {
+        self.enclosing.get(&line).copied()
+    }
+
+    /// The `if` a branch belongs to.
+    pub fn conditional_of(&self, line: usize) -> Option<usize> {
+        let mut current = line;
+
+        loop {
+            match self.branches.iter().find(|&(_, &next)| next == current) {
+                Some((&previous, _)) => current = previous,
+                None if current == line => return None,
+                None => return Some(current),
+            }
+        }
+    }
+
+    /// Is a `while` statement the end of a `do` loop?
+    pub fn closes_do_loop(&self, line: usize) -> bool {
+        self.start_of(line)
+            .and_then(|start| self.statement(start))
+            .is_some_and(|statement| statement.keyword == Keyword::Do)
+    }
+
+    /// The `GOTO` on a particular line.
+    pub fn goto(&self, line: usize) -> Option<&Goto> { self.gotos.get(&line) }
+
+    /// Every `GOTO` in the program, in order.
+    pub fn gotos(&self) -> impl Iterator<Item = (usize, &Goto)> + '_ {
+        self.gotos.iter().map(|(&line, goto)| (line, goto))
+    }
+
+    /// The line a `GOTO` with a constant target jumps to.
+    pub fn jump_target(&self, line: usize) -> Option<usize> {
+        self.jumps.get(&line).copied()
+    }
+
+    /// The only line with a particular sequence number (e.g. `N100`).
+    pub fn label(&self, number: u32) -> Option<usize> {
+        self.find_label(number).ok()
+    }
+
+    pub(crate) fn find_label(
+        &self,
+        number: u32,
+    ) -> Result<usize, ControlErrorKind> {
+        match self.labels.get(&number).map(Vec::as_slice) {
+            Some(&[line]) => Ok(line),
+            Some(_) => Err(ControlErrorKind::DuplicateLabel(number)),
+            None => Err(ControlErrorKind::UndefinedLabel(number)),
+        }
+    }
+
+    /// Problems with how the statements fit together.
+    pub fn errors(&self) -> &[ControlError] { &self.errors }
+
+    /// The error (if any) on a particular line.
+    pub fn error_at(&self, line: usize) -> Option<&ControlError> {
+        self.errors.iter().find(|error| error.line == line)
+    }
+}
+
+/// Convert an `N` word's number to a sequence number, if it's a whole
+/// number.
+fn sequence_number(number: f32) -> Option<u32> {
+    if number >= 0.0 && libm::truncf(number) == number {
+        Some(number as u32)
+    } else {
+        None
+    }
+}
+
+/// A control statement which doesn't fit into the program's structure.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct ControlError {
+    /// The (zero-based) line the statement is on.
+    pub line: usize,
+    /// The part of the line which is at fault, when known (e.g. a `GOTO`'s
+    /// target).
+    pub span: Option<Span>,
+    /// What is wrong with it.
+    pub kind: ControlErrorKind,
+}
+
+impl Display for ControlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line + 1, self.kind)
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+/// The different kinds of [`ControlError`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum ControlErrorKind {
+    /// A statement which closes or continues a block that isn't open (or
+    /// has a different label).
+    Unmatched,
+    /// A block which is never closed.
+    Unclosed,
+    /// A subroutine with the same label was already defined.
+    Redefined,
+    /// Calling a subroutine which isn't defined.
+    UndefinedSubroutine,
+    /// A `GOTO` whose sequence number isn't on any line.
+    UndefinedLabel(u32),
+    /// A sequence number which a `GOTO` jumps to, but which is used on more
+    /// than one line.
+    DuplicateLabel(u32),
+}
+
+impl Display for ControlErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlErrorKind::Unmatched => {
+                write!(f, "this doesn't match an open block")
+            },
+            ControlErrorKind::Unclosed => {
+                write!(f, "this block is never closed")
+            },
+            ControlErrorKind::Redefined => {
+                write!(f, "the subroutine is already defined")
+            },
+            ControlErrorKind::UndefinedSubroutine => {
+                write!(f, "the subroutine isn't defined")
+            },
+            ControlErrorKind::UndefinedLabel(number) => {
+                write!(f, "there is no N{} to jump to", number)
+            },
+            ControlErrorKind::DuplicateLabel(number) => {
+                write!(f, "N{} is used on more than one line", number)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_statements() {
+        let src = "O<Bolt Circle> call [1] [#2 * 2]";
+        let items = parse_statement(src).unwrap();
+
+        assert_eq!(
+            items,
+            vec![Item::Control(Statement {
+                label: Label::Named("boltcircle".into()),
+                keyword: Keyword::Call,
+                arguments: vec![
+                    Expression::Number(1.0),
+                    Expression::parse("[#2 * 2]").unwrap()
+                ],
+            })]
+        );
+    }
+
+    #[test]
+    fn program_numbers_arent_statements() {
+        let items = parse_statement("O1000 (part 1)").unwrap();
+
+        assert!(matches!(items[0], Item::Word { letter: 'O', .. }));
+        assert!(parse_statement("O<name> G1").is_err());
+    }
+
+    #[test]
+    fn match_up_blocks() {
+        let src = "O1 do\nO2 while [1]\nO2 break\nO2 endwhile\nO1 while [0]";
+        let control = ControlFlow::new(src);
+
+        assert!(control.errors().is_empty());
+        assert_eq!(control.end_of(0), Some(4));
+        assert!(control.closes_do_loop(4));
+        assert!(!control.closes_do_loop(1));
+        assert_eq!(control.end_of(1), Some(3));
+        assert_eq!(control.enclosing(2), Some(1));
+    }
+
+    #[test]
+    fn branches_form_a_chain() {
+        let src = "O1 if [1]\nO1 elseif [2]\nO1 else\nO1 endif";
+        let control = ControlFlow::new(src);
+
+        assert_eq!(control.next_branch(0), Some(1));
+        assert_eq!(control.next_branch(1), Some(2));
+        assert_eq!(control.next_branch(2), Some(3));
+        assert_eq!(control.conditional_of(2), Some(0));
+        assert_eq!(control.conditional_of(0), None);
+    }
+
+    #[test]
+    fn structural_errors() {
+        let src = "O1 if [1]\nO2 endif\nO3 call\nO4 sub\nO4 sub\nO4 endsub";
+        let control = ControlFlow::new(src);
+
+        let kinds: Vec<_> = control
+            .errors()
+            .iter()
+            .map(|error| (error.line, error.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (0, ControlErrorKind::Unclosed),
+                (1, ControlErrorKind::Unmatched),
+                (2, ControlErrorKind::UndefinedSubroutine),
+                (3, ControlErrorKind::Unclosed),
+                (4, ControlErrorKind::Redefined),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_goto_targets() {
+        let src = "N1 GOTO 3\nN2 GOTO 4\nN3\nN1 IF [#1] GOTO 1\nGOTO #5\nN1";
+        let control = ControlFlow::new(src);
+
+        assert_eq!(control.jump_target(0), Some(2));
+        assert_eq!(control.label(3), Some(2));
+        assert_eq!(control.label(1), None);
+        assert!(control.goto(3).unwrap().condition.is_some());
+        assert_eq!(control.jump_target(4), None);
+        assert_eq!(control.gotos().count(), 4);
+
+        let errors: Vec<_> = control
+            .errors()
+            .iter()
+            .map(|error| (error.line, error.span, error.kind))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    1,
+                    Some(Span::new(18, 19, 1)),
+                    ControlErrorKind::UndefinedLabel(4)
+                ),
+                (
+                    3,
+                    Some(Span::new(23, 25, 3)),
+                    ControlErrorKind::DuplicateLabel(1)
+                ),
+                (
+                    5,
+                    Some(Span::new(49, 51, 5)),
+                    ControlErrorKind::DuplicateLabel(1)
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,543 @@
+//! A corpus of real-world programs for conformance testing.
+//!
+//! Every slicer, CAM post-processor and controller has its own habits (e.g.
+//! Grbl senders which leave out every space, or Fanuc lathes which wrap a
+//! program in `%` signs), and it's easy for a new pass to work perfectly on
+//! the files its author happened to test with and fall over on everything
+//! else. The [`Corpus::builtin()`] fixtures cover a handful of popular
+//! generators and dialects, so downstream crates can check their own code
+//! against all of them.
+//!
+//! ```rust
+//! use gcode::{
+//!     analysis::Analyzer,
+//!     corpus::{self, Corpus},
+//! };
+//!
+//! let corpus = Corpus::builtin();
+//!
+//! // the crate's own invariants
+//! corpus.check(corpus::parses_cleanly).assert_ok();
+//! corpus.check(corpus::round_trips).assert_ok();
+//!
+//! // and one of ours
+//! let report = corpus.check(|fixture| {
+//!     let analysis =
+//!         Analyzer::new(fixture.dialect.dialect()).analyze(&fixture.src);
+//!
+//!     if analysis.total_time().is_finite() {
+//!         Ok(())
+//!     } else {
+//!         Err(format!("took {}s", analysis.total_time()))
+//!     }
+//! });
+//! assert!(report.is_ok(), "{}", report);
+//! ```
+//!
+//! Machines with quirks of their own can be added as extra fixtures, either
+//! in code or by putting files in a directory and using
+//! [`Corpus::load_dir()`]. Each file can describe itself with a header
+//! comment (see [`Fixture::from_source()`]).
+
+use crate::{
+    detect::{self, Generator},
+    dialect::Dialect,
+    file,
+    pipeline::{Context, Diagnostics, Pipeline},
+    profile::DialectName,
+    program::Program,
+    writer::{self, WriterConfig},
+    GCode, Parser,
+};
+use core::fmt::{self, Display, Formatter};
+use std::{
+    borrow::Cow,
+    fs, io,
+    path::Path,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// A single program in a [`Corpus`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixture {
+    /// A short name identifying the fixture (e.g. `"cura-marlin"`).
+    pub name: String,
+    /// The dialect the program is written in.
+    pub dialect: DialectName,
+    /// The program which generated the file, if it says.
+    pub generator: Option<Generator>,
+    /// Anything unusual about the program which it's meant to exercise.
+    pub quirks: Vec<String>,
+    /// The program's text.
+    pub src: Cow<'static, str>,
+}
+
+impl Fixture {
+    /// Create a new [`Fixture`], detecting its [`Generator`] from the
+    /// program's header.
+    pub fn new<N, S>(name: N, dialect: DialectName, src: S) -> Self
+    where
+        N: Into<String>,
+        S: Into<Cow<'static, str>>,
+    {
+        let src = src.into();
+
+        Fixture {
+            name: name.into(),
+            dialect,
+            generator: detect::generator(&src),
+            quirks: Vec::new(),
+            src,
+        }
+    }
+
+    /// Create a [`Fixture`] from a program which describes itself.
+    ///
+    /// Comments at the top of the file of the form `; dialect: grbl` set
+    /// the [`DialectName`], and each `; quirk: ...` comment adds to the
+    /// [`Fixture::quirks`] (parenthesised comments work too). The dialect is
+    /// detected from the program when it isn't given.
+    ///
+    /// ```rust
+    /// use gcode::{corpus::Fixture, profile::DialectName};
+    ///
+    /// let src = "\
+    /// ; dialect: grbl
+    /// ; quirk: no spaces between words
+    /// G0X10Y10
+    /// ";
+    /// let fixture = Fixture::from_source("my-laser", src);
+    ///
+    /// assert_eq!(fixture.dialect, DialectName::Grbl);
+    /// assert_eq!(fixture.quirks, vec!["no spaces between words"]);
+    /// ```
+    pub fn from_source<N, S>(name: N, src: S) -> Self
+    where
+        N: Into<String>,
+        S: Into<Cow<'static, str>>,
+    {
+        let mut fixture = Fixture::new(name, DialectName::Generic, src);
+        let mut dialect = None;
+
+        for line in fixture.src.lines().map(str::trim) {
+            if line.is_empty() {
+                continue;
+            }
+
+            let comment = match header_comment(line) {
+                Some(comment) => comment,
+                None => break,
+            };
+
+            match comment.split_once(':') {
+                Some((key, value)) if key.trim() == "dialect" => {
+                    dialect = DialectName::from_name(value.trim());
+                },
+                Some((key, value)) if key.trim() == "quirk" => {
+                    fixture.quirks.push(value.trim().to_string());
+                },
+                _ => {},
+            }
+        }
+
+        fixture.dialect =
+            dialect.unwrap_or_else(|| detect::dialect(&fixture.src).dialect);
+        fixture
+    }
+
+    /// The builder equivalent of pushing onto [`Fixture::quirks`].
+    pub fn with_quirk<Q: Into<String>>(mut self, quirk: Q) -> Self {
+        self.quirks.push(quirk.into());
+        self
+    }
+}
+
+/// The text of a comment taking up a whole line.
+fn header_comment(line: &str) -> Option<&str> {
+    if let Some(rest) = line.strip_prefix(';') {
+        Some(rest)
+    } else {
+        line.strip_prefix('(')?.strip_suffix(')')
+    }
+}
+
+/// A collection of [`Fixture`]s to check invariants against.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Corpus {
+    fixtures: Vec<Fixture>,
+}
+
+impl Corpus {
+    /// Create an empty [`Corpus`].
+    pub fn new() -> Self { Corpus::default() }
+
+    /// The fixtures which ship with this crate.
+    pub fn builtin() -> Self {
+        Corpus::new()
+            .with_fixture(
+                Fixture::new(
+                    "cura-marlin",
+                    DialectName::RepRap,
+                    include_str!("../corpus/cura-marlin.gcode"),
+                )
+                .with_quirk("absolute extrusion (M82)")
+                .with_quirk("retractions and primes without any movement")
+                .with_quirk("flags without numbers (M84 X Y E)"),
+            )
+            .with_fixture(
+                Fixture::new(
+                    "prusaslicer",
+                    DialectName::RepRap,
+                    include_str!("../corpus/prusaslicer.gcode"),
+                )
+                .with_quirk("relative extrusion (M83)")
+                .with_quirk("numbers without a leading zero (E.8)")
+                .with_quirk("commands with minor numbers (M862.1)"),
+            )
+            .with_fixture(
+                Fixture::new(
+                    "grbl-laser",
+                    DialectName::Grbl,
+                    include_str!("../corpus/grbl-laser.nc"),
+                )
+                .with_quirk("no spaces between words")
+                .with_quirk("dynamic laser power (M4)"),
+            )
+            .with_fixture(
+                Fixture::new(
+                    "linuxcnc-mill",
+                    DialectName::LinuxCnc,
+                    include_str!("../corpus/linuxcnc-mill.ngc"),
+                )
+                .with_quirk("canned cycle continued on following lines")
+                .with_quirk("cutter compensation"),
+            )
+            .with_fixture(
+                Fixture::new(
+                    "fanuc-lathe",
+                    DialectName::FanucLathe,
+                    include_str!("../corpus/fanuc-lathe.nc"),
+                )
+                .with_quirk("program wrapped in % signs")
+                .with_quirk("line numbers on every line")
+                .with_quirk("two-line G76 threading cycle")
+                .with_quirk("feed per revolution (G99)"),
+            )
+    }
+
+    /// Load every file in a directory as a [`Fixture`] (see
+    /// [`Fixture::from_source()`]), named after the file.
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut corpus = Corpus::new();
+        for path in paths {
+            let name = path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            let src = fs::read_to_string(&path)?;
+            corpus.push(Fixture::from_source(name, src));
+        }
+
+        Ok(corpus)
+    }
+
+    /// Add a [`Fixture`].
+    pub fn push(&mut self, fixture: Fixture) { self.fixtures.push(fixture); }
+
+    /// The builder equivalent of [`Corpus::push()`].
+    pub fn with_fixture(mut self, fixture: Fixture) -> Self {
+        self.push(fixture);
+        self
+    }
+
+    /// Add every [`Fixture`] from another [`Corpus`].
+    pub fn extend(&mut self, other: Corpus) {
+        self.fixtures.extend(other.fixtures);
+    }
+
+    /// Every [`Fixture`], in the order they were added.
+    pub fn fixtures(&self) -> &[Fixture] { &self.fixtures }
+
+    /// Look up a [`Fixture`] by name.
+    pub fn fixture(&self, name: &str) -> Option<&Fixture> {
+        self.fixtures.iter().find(|fixture| fixture.name == name)
+    }
+
+    /// Every [`Fixture`] written in a particular dialect.
+    pub fn for_dialect(
+        &self,
+        dialect: DialectName,
+    ) -> impl Iterator<Item = &Fixture> + '_ {
+        self.fixtures
+            .iter()
+            .filter(move |fixture| fixture.dialect == dialect)
+    }
+
+    /// Check an invariant against every [`Fixture`], collecting the ones
+    /// it fails for.
+    pub fn check<F>(&self, mut invariant: F) -> CorpusReport
+    where
+        F: FnMut(&Fixture) -> Result<(), String>,
+    {
+        let failures = self
+            .fixtures
+            .iter()
+            .filter_map(|fixture| {
+                invariant(fixture).err().map(|message| Failure {
+                    fixture: fixture.name.clone(),
+                    message,
+                })
+            })
+            .collect();
+
+        CorpusReport {
+            checked: self.fixtures.len(),
+            failures,
+        }
+    }
+
+    /// Run a fresh [`Pipeline`] over every [`Fixture`], then check an
+    /// invariant against the result.
+    pub fn check_pipeline<P, F>(
+        &self,
+        mut pipeline: P,
+        mut invariant: F,
+    ) -> CorpusReport
+    where
+        P: FnMut() -> Pipeline,
+        F: FnMut(&Fixture, &Program, &Diagnostics) -> Result<(), String>,
+    {
+        self.check(|fixture| {
+            let mut program =
+                Program::parse(&fixture.src, fixture.dialect.dialect());
+            let diagnostics = pipeline().run(&mut program, &mut Context::new());
+
+            invariant(fixture, &program, &diagnostics)
+        })
+    }
+}
+
+/// A [`Fixture`] which didn't satisfy an invariant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    /// The [`Fixture::name`].
+    pub fixture: String,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.fixture, self.message)
+    }
+}
+
+/// The result of checking an invariant against a [`Corpus`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorpusReport {
+    checked: usize,
+    failures: Vec<Failure>,
+}
+
+impl CorpusReport {
+    /// The number of fixtures which were checked.
+    pub fn checked(&self) -> usize { self.checked }
+
+    /// Every fixture which failed.
+    pub fn failures(&self) -> &[Failure] { &self.failures }
+
+    /// Did every fixture pass?
+    pub fn is_ok(&self) -> bool { self.failures.is_empty() }
+
+    /// Panic with a list of failures unless every fixture passed.
+    #[track_caller]
+    pub fn assert_ok(&self) {
+        assert!(self.is_ok(), "{}", self);
+    }
+}
+
+impl Display for CorpusReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} fixtures failed",
+            self.failures.len(),
+            self.checked
+        )?;
+
+        for failure in &self.failures {
+            write!(f, "\n  {}", failure)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Make sure the parser understands everything in a [`Fixture`].
+///
+/// Lines containing nothing but a `%` are ignored, because they mark the
+/// start and end of a program sent to Fanuc-style controllers rather than
+/// being part of it.
+pub fn parses_cleanly(fixture: &Fixture) -> Result<(), String> {
+    let dialect = fixture.dialect.dialect();
+
+    match file::first_syntax_error(&fixture.src, dialect) {
+        Some(error) => Err(error.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Make sure writing a [`Fixture`] back out and parsing it again gives the
+/// same commands, give or take the precision numbers are written with.
+pub fn round_trips(fixture: &Fixture) -> Result<(), String> {
+    let dialect = fixture.dialect.dialect();
+    let config = WriterConfig::for_dialect(&dialect);
+
+    let mut written = String::new();
+    writer::reformat(&fixture.src, &dialect, &config, &mut written)
+        .map_err(|e| e.to_string())?;
+
+    let original = commands(&fixture.src, dialect);
+    let got = commands(&written, dialect);
+
+    if original.len() != got.len() {
+        return Err(format!(
+            "wrote {} commands, but read back {}",
+            original.len(),
+            got.len()
+        ));
+    }
+
+    for (original, got) in original.iter().zip(&got) {
+        if !same_command(original, got) {
+            return Err(format!(
+                "wrote \"{}\", read back \"{}\"",
+                original, got
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn commands(src: &str, dialect: Dialect) -> Vec<GCode> {
+    Parser::<_>::new_with_dialect(src, crate::Nop, dialect)
+        .flat_map(|line| line.gcodes().to_vec())
+        .collect()
+}
+
+fn same_command(a: &GCode, b: &GCode) -> bool {
+    let close = |x: f32, y: f32| (x - y).abs() <= 1e-5 * x.abs().max(1.0);
+
+    a.key() == b.key()
+        && a.arguments().len() == b.arguments().len()
+        && a.arguments().iter().zip(b.arguments()).all(|(x, y)| {
+            x.letter == y.letter
+                && match (x.number(), y.number()) {
+                    (Some(x), Some(y)) => close(x, y),
+                    (x, y) => x == y,
+                }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::RenumberPass;
+
+    #[test]
+    fn builtin_fixtures_satisfy_the_crates_invariants() {
+        let corpus = Corpus::builtin();
+
+        corpus.check(parses_cleanly).assert_ok();
+        corpus.check(round_trips).assert_ok();
+
+        let names: Vec<_> = corpus
+            .for_dialect(DialectName::RepRap)
+            .map(|fixture| fixture.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["cura-marlin", "prusaslicer"]);
+        let cura = corpus.fixture("cura-marlin").unwrap();
+        assert_eq!(cura.generator.as_ref().unwrap().name, "Cura");
+    }
+
+    #[test]
+    fn failures_say_which_fixture_broke() {
+        let corpus = Corpus::builtin().with_fixture(Fixture::from_source(
+            "broken",
+            "; quirk: a stray number\nG1 X5 10\n",
+        ));
+
+        let report = corpus.check(parses_cleanly);
+
+        assert_eq!(report.checked(), 6);
+        assert_eq!(
+            report.to_string(),
+            "1 of 6 fixtures failed\n  broken: line 2: number without a \
+             letter (10)"
+        );
+    }
+
+    #[test]
+    fn passes_run_over_every_fixture() {
+        let corpus = Corpus::builtin();
+
+        let report = corpus.check_pipeline(
+            || Pipeline::new().with_pass(RenumberPass::new(10, 10)),
+            |fixture, program, _| {
+                let dialect = fixture.dialect.dialect();
+                let before = commands(&fixture.src, dialect).len();
+                let after = commands(&program.to_string(), dialect).len();
+
+                if before == after {
+                    Ok(())
+                } else {
+                    Err(format!("{} commands became {}", before, after))
+                }
+            },
+        );
+
+        report.assert_ok();
+        assert_eq!(report.checked(), 5);
+    }
+
+    #[test]
+    fn contributed_fixtures_are_loaded_from_a_directory() {
+        let dir = std::env::temp_dir()
+            .join(format!("gcode-corpus-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("b-lathe.nc"),
+            "(dialect: fanuc_lathe)\n(quirk: G98 means feed per minute)\n\
+             G98 G1 X10 F100\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("a-printer.gcode"),
+            "; generated by PrusaSlicer 2.6.0\nG1 X1 E1\n",
+        )
+        .unwrap();
+
+        let got = Corpus::load_dir(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let fixtures = got.fixtures();
+        assert_eq!(fixtures[0].name, "a-printer");
+        assert_eq!(fixtures[0].dialect, DialectName::RepRap);
+        assert_eq!(fixtures[1].name, "b-lathe");
+        assert_eq!(fixtures[1].dialect, DialectName::FanucLathe);
+        assert_eq!(fixtures[1].quirks, vec!["G98 means feed per minute"]);
+    }
+}
@@ -0,0 +1,602 @@
+//! Checking how much of a parametric program has actually been exercised.
+//!
+//! Running a program through the [`Executor`] only follows the path its
+//! parameters lead it down, so a branch which handles an unusual probe
+//! result might never be looked at. An [`Explorer`] runs the program
+//! several times, keeping track of which lines ran and which way each
+//! condition went:
+//!
+//! - Each [`Scenario`] sets parameters up front and supplies the results of any
+//!   probing moves (`G38.x`), the way a real machine would
+//! - With [`ExplorationLimits::explore_branches`] turned on, conditions which
+//!   depend on parameters are forced each way in turn, so both sides of every
+//!   `if` get run (within the limits)
+//!
+//! ```rust
+//! use gcode::{coverage::Explorer, dialect::Dialect};
+//!
+//! let src = "\
+//! G38.2 Z-10 F100
+//! O1 if [#5070 EQ 0]
+//!   (the probe never touched anything)
+//!   M0
+//! O1 else
+//!   G0 Z[#5063 + 5]
+//! O1 endif
+//! M30";
+//!
+//! let report = Explorer::new(src, Dialect::linuxcnc()).explore();
+//!
+//! assert_eq!(report.runs().len(), 2);
+//! assert!(report.coverage().is_complete());
+//! assert_eq!(report.coverage().branch(1), Some((true, true)));
+//! ```
+
+use crate::{
+    control::{ControlFlow, Keyword},
+    dialect::Dialect,
+    executor::{Budget, ExecutedLine, ExecutionError, Executor},
+    expr::ParameterId,
+    interpret::Position,
+    Mnemonic,
+};
+use std::{collections::BTreeMap, string::String, vec::Vec};
+
+/// A set of inputs to run a program with.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct Scenario {
+    /// A name to identify the scenario in reports.
+    pub name: String,
+    /// Parameters to set before the program starts.
+    pub parameters: Vec<(ParameterId, f32)>,
+    /// The result of each probing move, in order. Once these run out, every
+    /// probe touches something at the end of its move.
+    pub probes: Vec<ProbeResult>,
+}
+
+impl Scenario {
+    /// Create an empty [`Scenario`].
+    pub fn new(name: impl Into<String>) -> Self {
+        Scenario {
+            name: name.into(),
+            ..Scenario::default()
+        }
+    }
+
+    /// Set a parameter before the program starts.
+    pub fn with_parameter(
+        mut self,
+        parameter: ParameterId,
+        value: f32,
+    ) -> Self {
+        self.parameters.push((parameter, value));
+        self
+    }
+
+    /// Add the result of the next probing move.
+    pub fn with_probe(mut self, probe: ProbeResult) -> Self {
+        self.probes.push(probe);
+        self
+    }
+}
+
+/// What happened during a simulated probing move.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct ProbeResult {
+    /// Did the probe touch something?
+    pub tripped: bool,
+    /// Where the probe stopped, or `None` for the end of the move.
+    pub position: Option<Position>,
+}
+
+impl ProbeResult {
+    /// The probe touched something at a particular position.
+    pub const fn tripped_at(position: Position) -> Self {
+        ProbeResult {
+            tripped: true,
+            position: Some(position),
+        }
+    }
+
+    /// The probe got to the end of its move without touching anything.
+    pub const fn missed() -> Self {
+        ProbeResult {
+            tripped: false,
+            position: None,
+        }
+    }
+}
+
+/// How far an [`Explorer`] will go.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ExplorationLimits {
+    /// Force conditions which depend on parameters each way in turn, instead
+    /// of only following the path the parameters lead down.
+    pub explore_branches: bool,
+    /// The most times to run the program for each [`Scenario`].
+    pub max_paths: usize,
+    /// Only the first this many decisions in each run are explored, with
+    /// later conditions evaluated normally.
+    pub max_decisions: usize,
+    /// The [`Budget`] for each run, so forcing a loop's condition can't make
+    /// it go on forever.
+    pub budget: Budget,
+}
+
+impl Default for ExplorationLimits {
+    fn default() -> Self {
+        ExplorationLimits {
+            explore_branches: true,
+            max_paths: 64,
+            max_decisions: 16,
+            budget: Budget {
+                max_steps: Some(100_000),
+                max_iterations: Some(1_000),
+                max_call_depth: Some(Budget::DEFAULT_MAX_CALL_DEPTH),
+                timeout: None,
+            },
+        }
+    }
+}
+
+/// Runs a program under different [`Scenario`]s, measuring its
+/// [`Coverage`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explorer<'src> {
+    src: &'src str,
+    dialect: Dialect,
+    scenarios: Vec<Scenario>,
+    limits: ExplorationLimits,
+}
+
+impl<'src> Explorer<'src> {
+    /// Create an [`Explorer`] for some source text.
+    pub fn new(src: &'src str, dialect: Dialect) -> Self {
+        Explorer {
+            src,
+            dialect,
+            scenarios: Vec::new(),
+            limits: ExplorationLimits::default(),
+        }
+    }
+
+    /// Also run the program under a particular [`Scenario`].
+    ///
+    /// If no scenarios are added, the program is run with nothing set up
+    /// front.
+    pub fn with_scenario(mut self, scenario: Scenario) -> Self {
+        self.scenarios.push(scenario);
+        self
+    }
+
+    /// Change how far exploration will go.
+    pub fn with_limits(mut self, limits: ExplorationLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Run the program under every [`Scenario`].
+    pub fn explore(&self) -> CoverageReport {
+        let default = [Scenario::default()];
+        let scenarios = if self.scenarios.is_empty() {
+            &default[..]
+        } else {
+            &self.scenarios[..]
+        };
+
+        let mut report = CoverageReport {
+            runs: Vec::new(),
+            coverage: Coverage::new(self.src),
+        };
+
+        for scenario in scenarios {
+            self.explore_scenario(scenario, &mut report);
+        }
+
+        report
+    }
+
+    /// Do a depth-first search over the decisions made in each run,
+    /// flipping the most recent one which hasn't been tried both ways.
+    fn explore_scenario(
+        &self,
+        scenario: &Scenario,
+        report: &mut CoverageReport,
+    ) {
+        // each decision along the current path, and whether its other
+        // outcome has been tried already
+        let mut path: Vec<(bool, bool)> = Vec::new();
+
+        for _ in 0..self.limits.max_paths.max(1) {
+            let forced = path.iter().map(|&(taken, _)| taken).collect();
+            let run = self.run(scenario, forced, &mut report.coverage);
+
+            let limit = run.decisions.len().min(self.limits.max_decisions);
+            path.truncate(limit);
+            for decision in &run.decisions[path.len()..limit] {
+                path.push((decision.taken, false));
+            }
+            report.runs.push(run);
+
+            if !self.limits.explore_branches {
+                break;
+            }
+
+            while path.last().is_some_and(|&(_, tried)| tried) {
+                let _ = path.pop();
+            }
+            match path.last_mut() {
+                Some(last) => *last = (!last.0, true),
+                None => break,
+            }
+        }
+    }
+
+    fn run(
+        &self,
+        scenario: &Scenario,
+        forced: Vec<bool>,
+        coverage: &mut Coverage,
+    ) -> Run {
+        let explored = forced.len();
+        let mut executor = Executor::new(self.src, self.dialect)
+            .with_budget(self.limits.budget)
+            .with_decisions(forced);
+
+        for (parameter, value) in &scenario.parameters {
+            executor.parameters_mut().set(parameter, *value);
+        }
+
+        let mut probes = scenario.probes.iter();
+        let mut lines = 0;
+        let mut error = None;
+        let mut decided = 0;
+
+        while let Some(result) = executor.step() {
+            match result {
+                Ok(executed) => {
+                    lines += 1;
+                    let line = executor.last_line();
+                    if let Some(line) = line {
+                        coverage.hit(line);
+                    }
+                    // an elseif is checked while executing the if it
+                    // belongs to
+                    let made =
+                        executor.decisions().map_or(&[][..], |d| &d.made);
+                    for &(other, _) in &made[decided..] {
+                        if Some(other) != line {
+                            coverage.hit(other);
+                        }
+                    }
+                    decided = made.len();
+
+                    if let Some(probe) = probe_result(&executed, &mut probes) {
+                        simulate_probe(&mut executor, probe);
+                    }
+                },
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                },
+            }
+        }
+
+        let decisions: Vec<Decision> = executor
+            .decisions()
+            .map(|decisions| {
+                decisions
+                    .made
+                    .iter()
+                    .map(|&(line, taken)| Decision { line, taken })
+                    .collect()
+            })
+            .unwrap_or_default();
+        for decision in &decisions {
+            coverage.decided(*decision);
+        }
+
+        Run {
+            scenario: scenario.name.clone(),
+            decisions,
+            forced: explored,
+            lines,
+            error,
+        }
+    }
+}
+
+/// If a line probed, work out what the probe found.
+fn probe_result<'a, I>(
+    executed: &ExecutedLine<'_>,
+    probes: &mut I,
+) -> Option<ProbeResult>
+where
+    I: Iterator<Item = &'a ProbeResult>,
+{
+    let probed = executed.line.gcodes().iter().any(|gcode| {
+        gcode.mnemonic() == Mnemonic::General && gcode.major_number() == 38
+    });
+    if !probed {
+        return None;
+    }
+
+    let end = executed.motion.map(|motion| motion.end)?;
+    let probe = probes.next().copied().unwrap_or(ProbeResult {
+        tripped: true,
+        position: None,
+    });
+
+    Some(ProbeResult {
+        position: Some(probe.position.unwrap_or(end)),
+        ..probe
+    })
+}
+
+/// Set the parameters LinuxCNC uses to report a probe's result (`#5061`
+/// to `#5063` for the position and `#5070` for whether it tripped).
+fn simulate_probe(executor: &mut Executor<'_>, probe: ProbeResult) {
+    let position = probe.position.unwrap_or_default();
+    let parameters = executor.parameters_mut();
+
+    for (number, value) in (5061..).zip([position.x, position.y, position.z]) {
+        parameters.set(&ParameterId::Numbered(number), value);
+    }
+    let tripped = if probe.tripped { 1.0 } else { 0.0 };
+    parameters.set(&ParameterId::Numbered(5070), tripped);
+}
+
+/// The way a data-dependent condition went.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Decision {
+    /// The (zero-based) line the condition is on.
+    pub line: usize,
+    /// Was the condition true?
+    pub taken: bool,
+}
+
+/// A single run through the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    /// The name of the [`Scenario`] used.
+    pub scenario: String,
+    /// Every data-dependent decision, in order.
+    pub decisions: Vec<Decision>,
+    /// How many of the decisions were forced.
+    pub forced: usize,
+    /// The number of lines executed.
+    pub lines: usize,
+    /// The error which stopped the run early, if any.
+    pub error: Option<ExecutionError>,
+}
+
+/// Which parts of a program have been run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage {
+    /// The number of times each line was executed, or `None` for lines
+    /// without anything to execute.
+    hits: Vec<Option<usize>>,
+    /// Whether each condition has been seen to be true and false.
+    branches: BTreeMap<usize, (bool, bool)>,
+}
+
+impl Coverage {
+    fn new(src: &str) -> Self {
+        let control = ControlFlow::new(src);
+        let hits = src
+            .lines()
+            .enumerate()
+            .map(|(number, line)| {
+                // else and endif don't do anything when they're reached
+                let marker = control.statement(number).is_some_and(|s| {
+                    matches!(s.keyword, Keyword::Else | Keyword::EndIf)
+                });
+
+                if has_code(line) && !marker {
+                    Some(0)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        Coverage {
+            hits,
+            branches: BTreeMap::new(),
+        }
+    }
+
+    fn hit(&mut self, line: usize) {
+        if let Some(Some(count)) = self.hits.get_mut(line) {
+            *count += 1;
+        }
+    }
+
+    fn decided(&mut self, decision: Decision) {
+        let (was_true, was_false) =
+            self.branches.entry(decision.line).or_insert((false, false));
+
+        if decision.taken {
+            *was_true = true;
+        } else {
+            *was_false = true;
+        }
+    }
+
+    /// How many times a (zero-based) line was executed, or `None` if there
+    /// is nothing on it to execute.
+    pub fn hits(&self, line: usize) -> Option<usize> {
+        self.hits.get(line).copied().flatten()
+    }
+
+    /// Lines with something to execute which never ran.
+    pub fn uncovered(&self) -> impl Iterator<Item = usize> + '_ {
+        self.hits
+            .iter()
+            .enumerate()
+            .filter(|(_, hits)| **hits == Some(0))
+            .map(|(line, _)| line)
+    }
+
+    /// Whether a condition on a (zero-based) line has been seen to be true
+    /// and false, respectively.
+    pub fn branch(&self, line: usize) -> Option<(bool, bool)> {
+        self.branches.get(&line).copied()
+    }
+
+    /// Conditions which have only ever gone one way, and the outcome which
+    /// was never seen.
+    pub fn partial_branches(&self) -> impl Iterator<Item = (usize, bool)> + '_ {
+        self.branches
+            .iter()
+            .filter(|(_, &(was_true, was_false))| was_true != was_false)
+            .map(|(&line, &(was_true, _))| (line, !was_true))
+    }
+
+    /// The fraction of lines with something to execute which ran.
+    pub fn ratio(&self) -> f32 {
+        let lines = self.hits.iter().flatten().count();
+        let covered = self.hits.iter().flatten().filter(|&&n| n > 0).count();
+
+        if lines == 0 {
+            1.0
+        } else {
+            covered as f32 / lines as f32
+        }
+    }
+
+    /// Has every line run, and every condition gone both ways?
+    pub fn is_complete(&self) -> bool {
+        self.uncovered().next().is_none()
+            && self.partial_branches().next().is_none()
+    }
+}
+
+/// Does a line contain anything other than whitespace, comments and tape
+/// markers?
+fn has_code(line: &str) -> bool {
+    let mut depth = 0;
+
+    for c in line.chars() {
+        match c {
+            ';' if depth == 0 => break,
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            '%' if depth == 0 => {},
+            c if depth == 0 && !c.is_whitespace() => return true,
+            _ => {},
+        }
+    }
+
+    false
+}
+
+/// The result of an [`Explorer`] run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageReport {
+    runs: Vec<Run>,
+    coverage: Coverage,
+}
+
+impl CoverageReport {
+    /// Every run through the program.
+    pub fn runs(&self) -> &[Run] { &self.runs }
+
+    /// Which parts of the program were run, across every run.
+    pub fn coverage(&self) -> &Coverage { &self.coverage }
+
+    /// Runs which stopped because of an error.
+    pub fn failures(&self) -> impl Iterator<Item = &Run> + '_ {
+        self.runs.iter().filter(|run| run.error.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROBE: &str = "\
+G38.2 Z-10 F100
+O1 if [#5070 EQ 0]
+M0
+O1 endif
+G0 Z[#5063 + 5]";
+
+    #[test]
+    fn scenarios_supply_probe_results() {
+        let limits = ExplorationLimits {
+            explore_branches: false,
+            ..ExplorationLimits::default()
+        };
+        let explorer = Explorer::new(PROBE, Dialect::linuxcnc())
+            .with_limits(limits)
+            .with_scenario(Scenario::new("touched"))
+            .with_scenario(
+                Scenario::new("missed").with_probe(ProbeResult::missed()),
+            );
+
+        let report = explorer.explore();
+
+        assert_eq!(report.runs().len(), 2);
+        assert_eq!(report.runs()[0].decisions[0].taken, false);
+        assert_eq!(report.runs()[1].decisions[0].taken, true);
+        assert_eq!(report.coverage().hits(2), Some(1));
+        assert_eq!(report.coverage().hits(4), Some(2));
+        assert!(report.coverage().is_complete());
+    }
+
+    #[test]
+    fn report_what_was_missed() {
+        let limits = ExplorationLimits {
+            explore_branches: false,
+            ..ExplorationLimits::default()
+        };
+        let explorer = Explorer::new(PROBE, Dialect::linuxcnc())
+            .with_limits(limits)
+            .with_scenario(Scenario::new("tripped"));
+
+        let report = explorer.explore();
+        let coverage = report.coverage();
+
+        assert_eq!(coverage.uncovered().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(
+            coverage.partial_branches().collect::<Vec<_>>(),
+            vec![(1, true)]
+        );
+        assert_eq!(coverage.ratio(), 0.75);
+    }
+
+    #[test]
+    fn forced_loops_are_kept_in_check() {
+        let src = "#<n> = 0\nO1 while [#<n> LT 3]\n#<n> = [#<n> + 1]\n\
+                   O1 endwhile\nM30";
+        let limits = ExplorationLimits {
+            max_paths: 8,
+            max_decisions: 2,
+            ..ExplorationLimits::default()
+        };
+
+        let report = Explorer::new(src, Dialect::linuxcnc())
+            .with_limits(limits)
+            .explore();
+
+        // the loop can be forced to stop straight away or after one trip,
+        // or left to run normally
+        assert_eq!(report.runs().len(), 3);
+        assert!(report.failures().next().is_none());
+        assert!(report.coverage().is_complete());
+    }
+}
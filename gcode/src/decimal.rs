@@ -0,0 +1,309 @@
+//! Numbers which are stored exactly as they were written.
+//!
+//! Normally every number is converted to an `f32` while parsing, which means
+//! `X0.1` may be written back out as `X0.10000000149`, or a long coordinate
+//! may lose its last few digits. That is fine for most purposes, but some
+//! certification workflows require a program to survive being parsed,
+//! transformed and written out again without any numeric drift.
+//!
+//! Setting [`Dialect::decimal_precision`] makes the parser store numbers as a
+//! [`Decimal`] (an integer mantissa and a number of digits after the decimal
+//! point) instead, and the [`Writer`] writes them out digit-for-digit.
+//!
+//! ```rust
+//! use gcode::{
+//!     dialect::Dialect,
+//!     writer::{self, WriterConfig},
+//! };
+//!
+//! let src = "G1 X123456.789012 Y0.10 F1500\n";
+//!
+//! let dialect = Dialect::generic();
+//! let mut lossy = String::new();
+//! writer::reformat(src, &dialect, &WriterConfig::default(), &mut lossy)
+//!     .unwrap();
+//! assert_eq!(lossy, "G1 X123456.7891 Y0.1 F1500\n");
+//!
+//! let mut dialect = Dialect::generic();
+//! dialect.decimal_precision = Some(9);
+//! let mut exact = String::new();
+//! writer::reformat(src, &dialect, &WriterConfig::default(), &mut exact)
+//!     .unwrap();
+//! assert_eq!(exact, src);
+//! ```
+//!
+//! [`Dialect::decimal_precision`]: crate::dialect::Dialect::decimal_precision
+//! [`Writer`]: crate::writer::Writer
+
+use crate::{ParseError, Span};
+use core::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+/// The largest number of digits a [`Decimal`] can have after the decimal
+/// point.
+pub const MAX_SCALE: u8 = 18;
+
+/// An exact decimal number, stored as `mantissa * 10^-scale`.
+///
+/// Two [`Decimal`]s are only equal if they were written with the same
+/// number of digits, so `1.5` and `1.50` are different.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Decimal {
+    mantissa: i64,
+    scale: u8,
+}
+
+impl Decimal {
+    /// Create a new [`Decimal`] equal to `mantissa * 10^-scale`.
+    ///
+    /// # Panics
+    ///
+    /// The `scale` must not be larger than [`MAX_SCALE`].
+    pub const fn new(mantissa: i64, scale: u8) -> Self {
+        assert!(scale <= MAX_SCALE, "the scale is too large");
+
+        Decimal { mantissa, scale }
+    }
+
+    /// The digits, without a decimal point.
+    pub const fn mantissa(self) -> i64 { self.mantissa }
+
+    /// How many of the digits are after the decimal point.
+    pub const fn scale(self) -> u8 { self.scale }
+
+    /// Parse a number, rounding away any digits after the decimal point past
+    /// `max_scale`.
+    ///
+    /// Returns `None` if the text isn't a number or has too many digits to
+    /// store.
+    ///
+    /// ```rust
+    /// use gcode::decimal::Decimal;
+    ///
+    /// assert_eq!(Decimal::parse("-1.250", 5), Some(Decimal::new(-1250, 3)));
+    /// assert_eq!(Decimal::parse("0.12345", 3), Some(Decimal::new(123, 3)));
+    /// assert_eq!(Decimal::parse("1.2.3", 3), None);
+    /// ```
+    pub fn parse(text: &str, max_scale: u8) -> Option<Decimal> {
+        let max_scale = max_scale.min(MAX_SCALE);
+        let (negative, digits) = match text.as_bytes().first()? {
+            b'-' => (true, &text[1..]),
+            b'+' => (false, &text[1..]),
+            _ => (false, text),
+        };
+        let (integral, fraction) = match digits.find('.') {
+            Some(point) => (&digits[..point], &digits[point + 1..]),
+            None => (digits, ""),
+        };
+
+        let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if (integral.is_empty() && fraction.is_empty())
+            || !all_digits(integral)
+            || !all_digits(fraction)
+        {
+            return None;
+        }
+
+        let kept = fraction.len().min(usize::from(max_scale));
+        let mut mantissa: i64 = 0;
+
+        for digit in integral.bytes().chain(fraction[..kept].bytes()) {
+            mantissa = mantissa
+                .checked_mul(10)?
+                .checked_add(i64::from(digit - b'0'))?;
+        }
+
+        // round half away from zero using the first digit we dropped
+        if fraction.as_bytes().get(kept).is_some_and(|&d| d >= b'5') {
+            mantissa = mantissa.checked_add(1)?;
+        }
+
+        if negative {
+            mantissa = -mantissa;
+        }
+
+        Some(Decimal {
+            mantissa,
+            scale: kept as u8,
+        })
+    }
+
+    /// Change the number of digits after the decimal point, rounding half
+    /// away from zero if digits need to be dropped.
+    ///
+    /// Returns `None` if the result would be too big to store.
+    ///
+    /// ```rust
+    /// use gcode::decimal::Decimal;
+    ///
+    /// let value = Decimal::new(-1255, 3);
+    ///
+    /// assert_eq!(value.rescale(5), Some(Decimal::new(-125500, 5)));
+    /// assert_eq!(value.rescale(2), Some(Decimal::new(-126, 2)));
+    /// ```
+    pub fn rescale(self, scale: u8) -> Option<Decimal> {
+        let scale = scale.min(MAX_SCALE);
+
+        let mantissa = if scale >= self.scale {
+            self.mantissa
+                .checked_mul(10_i64.pow(u32::from(scale - self.scale)))?
+        } else {
+            let divisor = 10_i64.pow(u32::from(self.scale - scale));
+            let quotient = self.mantissa / divisor;
+            let remainder = self.mantissa % divisor;
+
+            if remainder.abs() * 2 >= divisor {
+                quotient + self.mantissa.signum()
+            } else {
+                quotient
+            }
+        };
+
+        Some(Decimal { mantissa, scale })
+    }
+
+    /// The closest `f64` to this number.
+    pub fn to_f64(self) -> f64 {
+        self.mantissa as f64 / libm::pow(10.0, f64::from(self.scale))
+    }
+
+    /// The closest `f32` to this number.
+    pub fn to_f32(self) -> f32 { self.to_f64() as f32 }
+
+    /// Is this number negative?
+    pub const fn is_negative(self) -> bool { self.mantissa < 0 }
+
+    /// Is this number zero?
+    pub const fn is_zero(self) -> bool { self.mantissa == 0 }
+
+    /// Split the number into the digits before and after the decimal point,
+    /// ignoring the sign.
+    pub(crate) fn parts(self) -> (u64, u64) {
+        let magnitude = self.mantissa.unsigned_abs();
+        let divisor = 10_u64.pow(u32::from(self.scale));
+
+        (magnitude / divisor, magnitude % divisor)
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (integral, fraction) = self.parts();
+
+        if self.is_negative() {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", integral)?;
+
+        if self.scale > 0 {
+            write!(f, ".{:01$}", fraction, usize::from(self.scale))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ParseError;
+
+    /// Parse a number, keeping up to [`MAX_SCALE`] digits after the decimal
+    /// point.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ParseError::Empty);
+        }
+
+        Decimal::parse(s, MAX_SCALE)
+            .ok_or_else(|| ParseError::Unexpected(Span::new(0, s.len(), 0)))
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Decimal {
+    type Error = ParseError;
+
+    fn try_from(s: &'a str) -> Result<Self, Self::Error> { s.parse() }
+}
+
+impl From<i32> for Decimal {
+    fn from(other: i32) -> Decimal { Decimal::new(i64::from(other), 0) }
+}
+
+impl From<Decimal> for f64 {
+    fn from(other: Decimal) -> f64 { other.to_f64() }
+}
+
+/// The number of decimal places in an increment like `0.001`, if it is a
+/// power of ten.
+pub(crate) fn scale_of_increment(increment: f32) -> Option<u8> {
+    (0..=MAX_SCALE).find(|&scale| {
+        let power = libm::powf(10.0, -f32::from(scale));
+        libm::fabsf(increment - power) <= power * 1e-4
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn numbers_round_trip_through_text() {
+        let inputs = ["0", "-1.5", "1.50", "0.000001", "123456789.123456789"];
+
+        for &input in &inputs {
+            let got: Decimal = input.parse().unwrap();
+
+            assert_eq!(got.to_string(), input);
+        }
+    }
+
+    #[test]
+    fn parse_unusual_numbers() {
+        let inputs = [
+            ("+3", Some(Decimal::new(3, 0))),
+            (".5", Some(Decimal::new(5, 1))),
+            ("-7.", Some(Decimal::new(-7, 0))),
+            ("-", None),
+            (".", None),
+            ("1e5", None),
+            ("99999999999999999999", None),
+        ];
+
+        for &(input, should_be) in &inputs {
+            assert_eq!(
+                Decimal::parse(input, MAX_SCALE),
+                should_be,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn extra_digits_are_rounded() {
+        assert_eq!(Decimal::parse("-0.125", 2), Some(Decimal::new(-13, 2)));
+        assert_eq!(Decimal::parse("0.124", 2), Some(Decimal::new(12, 2)));
+        assert_eq!(Decimal::parse("9.99", 0), Some(Decimal::new(10, 0)));
+    }
+
+    #[test]
+    fn convert_to_floats() {
+        assert_eq!(Decimal::new(-1250, 3).to_f32(), -1.25);
+        assert_eq!(Decimal::new(1, 1).to_f64(), 0.1);
+    }
+
+    #[test]
+    fn increments_which_are_powers_of_ten() {
+        assert_eq!(scale_of_increment(1.0), Some(0));
+        assert_eq!(scale_of_increment(0.001), Some(3));
+        assert_eq!(scale_of_increment(0.0001), Some(4));
+        assert_eq!(scale_of_increment(0.005), None);
+    }
+}
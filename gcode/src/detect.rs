@@ -0,0 +1,610 @@
+//! Guessing which [`Dialect`] a program was written for.
+//!
+//! Files uploaded by users rarely say which controller they are meant for,
+//! but they usually leave plenty of clues. Slicers and CAM packages announce
+//! themselves in a header comment, Grbl senders use `$` commands, RepRap
+//! firmware checksums its lines, and so on. [`dialect()`] looks at the start
+//! of a file and weighs up the evidence.
+//!
+//! ```rust
+//! use gcode::{
+//!     detect::{self, Generator},
+//!     profile::DialectName,
+//! };
+//!
+//! let src = "\
+//! ; generated by PrusaSlicer 2.6.0+win64 on 2023-07-04 at 10:15:01 UTC
+//! M104 S215
+//! G1 X10 Y10 E0.5 F1200
+//! ";
+//!
+//! let detection = detect::dialect(src);
+//!
+//! assert_eq!(detection.dialect, DialectName::RepRap);
+//! assert!(detection.confidence > 0.5);
+//! assert_eq!(
+//!     detection.generator,
+//!     Some(Generator::new("PrusaSlicer", "2.6.0"))
+//! );
+//! ```
+//!
+//! [`Dialect`]: crate::dialect::Dialect
+
+use crate::{profile::DialectName, Mnemonic, Nop};
+use core::fmt::{self, Display, Formatter};
+use std::string::{String, ToString};
+
+/// How many lines [`dialect()`] will look at before making up its mind.
+pub const DEFAULT_MAX_LINES: usize = 500;
+
+/// The best guess at which dialect a program uses.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Detection {
+    /// The most likely dialect ([`DialectName::Generic`] if there was
+    /// nothing to go on).
+    pub dialect: DialectName,
+    /// How sure we are, from `0.0` (a wild guess) to `1.0` (certain).
+    pub confidence: f32,
+    /// The program which generated the file, if it left a header comment.
+    pub generator: Option<Generator>,
+}
+
+/// The slicer or CAM package which generated a file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Generator {
+    /// The generator's name, spelled the way its authors do (e.g.
+    /// `"PrusaSlicer"` or `"Fusion 360"`).
+    pub name: String,
+    /// The version string, if one was given.
+    pub version: Option<String>,
+}
+
+impl Generator {
+    /// Create a new [`Generator`].
+    pub fn new<'a, N, V>(name: N, version: V) -> Self
+    where
+        N: Into<String>,
+        V: Into<Option<&'a str>>,
+    {
+        Generator {
+            name: name.into(),
+            version: version.into().map(ToString::to_string),
+        }
+    }
+
+    /// Recognise the header comment left by a well-known generator.
+    ///
+    /// ```rust
+    /// use gcode::detect::Generator;
+    ///
+    /// let cura = Generator::from_comment(";Generated with Cura_SteamEngine 5.4.0");
+    /// assert_eq!(cura, Some(Generator::new("Cura", "5.4.0")));
+    ///
+    /// let mastercam = Generator::from_comment("(MASTERCAM - X9)");
+    /// assert_eq!(mastercam, Some(Generator::new("Mastercam", "X9")));
+    ///
+    /// assert_eq!(Generator::from_comment("(roughing pass)"), None);
+    /// ```
+    pub fn from_comment(comment: &str) -> Option<Generator> {
+        let lowercase = comment.to_ascii_lowercase();
+
+        KNOWN_GENERATORS.iter().find_map(|known| {
+            let end = find_word(&lowercase, known.needle)?;
+
+            Some(Generator {
+                name: known.name.to_string(),
+                version: version_after(&comment[end..])
+                    .map(ToString::to_string),
+            })
+        })
+    }
+
+    /// The dialect this generator's output normally uses.
+    ///
+    /// This is [`None`] for CAM packages, where it depends on which post
+    /// processor was used.
+    pub fn dialect(&self) -> Option<DialectName> {
+        KNOWN_GENERATORS
+            .iter()
+            .find(|known| known.name == self.name)
+            .and_then(|known| known.dialect)
+    }
+}
+
+impl Display for Generator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} {}", self.name, version),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Find the program which generated a file, by looking for a well-known
+/// header comment in its first [`DEFAULT_MAX_LINES`] lines.
+pub fn generator(src: &str) -> Option<Generator> {
+    for text in src.lines().take(DEFAULT_MAX_LINES) {
+        for line in crate::full_parse_with_callbacks(text, Nop) {
+            for comment in line.comments() {
+                if let Some(generator) = Generator::from_comment(comment.value)
+                {
+                    return Some(generator);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Guess which dialect a program was written for by inspecting its first
+/// [`DEFAULT_MAX_LINES`] lines.
+pub fn dialect(src: &str) -> Detection {
+    dialect_within(src, DEFAULT_MAX_LINES)
+}
+
+/// Guess which dialect a program was written for, only looking at the first
+/// `max_lines` lines.
+///
+/// The evidence considered is:
+///
+/// - Header comments left by well-known slicers, CAM packages and senders
+/// - Commands which are characteristic of a particular family of controllers
+///   (e.g. `M104` for RepRap, `G71` for a Fanuc lathe)
+/// - RepRap-style line checksums (`N10 G1 X5*91`)
+/// - Grbl `$` system commands
+/// - LinuxCNC `O`-word control flow and named parameters
+/// - Fanuc `%` tape markers and `O1234` program numbers
+pub fn dialect_within(src: &str, max_lines: usize) -> Detection {
+    let mut scores = Scores::default();
+
+    for text in src.lines().take(max_lines) {
+        scores.line(text.trim());
+    }
+
+    scores.detection()
+}
+
+/// How much a generator's header comment counts for.
+const GENERATOR: f32 = 5.0;
+/// How much an unambiguous syntactic feature counts for.
+const SYNTAX: f32 = 3.0;
+/// How much a characteristic command counts for.
+const COMMAND: f32 = 1.0;
+/// Evidence needed before we are reasonably confident (the confidence is
+/// halved when this much has been found).
+const CONFIDENCE_THRESHOLD: f32 = 5.0;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Scores {
+    generator: Option<Generator>,
+    reprap: f32,
+    grbl: f32,
+    linuxcnc: f32,
+    fanuc: f32,
+    /// Evidence for a lathe, which refines a Fanuc guess.
+    lathe: f32,
+}
+
+impl Scores {
+    fn line(&mut self, text: &str) {
+        if text.starts_with('$') {
+            self.grbl += SYNTAX;
+            return;
+        }
+        if text == "%" {
+            self.fanuc += COMMAND;
+            return;
+        }
+        if has_checksum(text) {
+            self.reprap += SYNTAX;
+        }
+        if text.contains("#<") || is_control_statement(text) {
+            self.linuxcnc += SYNTAX;
+        }
+        if is_program_number(text) {
+            self.fanuc += COMMAND;
+        }
+
+        for line in crate::full_parse_with_callbacks(text, Nop) {
+            for comment in line.comments() {
+                self.comment(comment.value);
+            }
+
+            for gcode in line.gcodes() {
+                let number = (gcode.major_number(), gcode.minor_number());
+
+                match (gcode.mnemonic(), number) {
+                    (Mnemonic::General, (0..=1, 0))
+                        if gcode.has_argument('E') =>
+                    {
+                        self.reprap += COMMAND
+                    },
+                    (Mnemonic::General, (29, 0)) => self.reprap += COMMAND,
+                    (Mnemonic::General, (64, 0)) => self.linuxcnc += COMMAND,
+                    (Mnemonic::General, (65, 0))
+                    | (Mnemonic::General, (54, 1)) => self.fanuc += COMMAND,
+                    (Mnemonic::General, (70..=72, 0))
+                    | (Mnemonic::General, (96, 0)) => self.lathe += COMMAND,
+                    (
+                        Mnemonic::Miscellaneous,
+                        (73, 0)
+                        | (82..=84, 0)
+                        | (104, 0)
+                        | (106..=107, 0)
+                        | (109, 0)
+                        | (117, 0)
+                        | (140, 0)
+                        | (190, 0),
+                    ) => self.reprap += COMMAND,
+                    (Mnemonic::Miscellaneous, (62..=68, 0)) => {
+                        self.linuxcnc += COMMAND
+                    },
+                    (Mnemonic::Miscellaneous, (98..=99, 0)) => {
+                        self.fanuc += COMMAND
+                    },
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    fn comment(&mut self, comment: &str) {
+        /// Controllers which are often mentioned by name in a header.
+        const CONTROLLERS: &[(&str, DialectName)] = &[
+            ("grbl", DialectName::Grbl),
+            ("bcnc", DialectName::Grbl),
+            ("easel", DialectName::Grbl),
+            ("linuxcnc", DialectName::LinuxCnc),
+            ("fanuc", DialectName::Fanuc),
+            ("haas", DialectName::Fanuc),
+        ];
+
+        if self.generator.is_none() {
+            if let Some(generator) = Generator::from_comment(comment) {
+                if let Some(dialect) = generator.dialect() {
+                    self.add(dialect, GENERATOR);
+                }
+                self.generator = Some(generator);
+                return;
+            }
+        }
+
+        let comment = comment.to_lowercase();
+
+        for &(name, dialect) in CONTROLLERS {
+            if find_word(&comment, name).is_some() {
+                self.add(dialect, GENERATOR);
+            }
+        }
+    }
+
+    fn add(&mut self, dialect: DialectName, weight: f32) {
+        match dialect {
+            DialectName::Generic => {},
+            DialectName::RepRap => self.reprap += weight,
+            DialectName::Grbl => self.grbl += weight,
+            DialectName::LinuxCnc => self.linuxcnc += weight,
+            DialectName::Fanuc => self.fanuc += weight,
+            DialectName::FanucLathe => self.lathe += weight,
+        }
+    }
+
+    fn detection(self) -> Detection {
+        let fanuc = self.fanuc + self.lathe;
+        let candidates = [
+            (DialectName::RepRap, self.reprap),
+            (DialectName::Grbl, self.grbl),
+            (DialectName::LinuxCnc, self.linuxcnc),
+            (DialectName::Fanuc, fanuc),
+        ];
+        let total: f32 = candidates.iter().map(|(_, score)| score).sum();
+
+        let (mut dialect, best) = candidates.iter().copied().fold(
+            (DialectName::Generic, 0.0),
+            |best, candidate| {
+                if candidate.1 > best.1 {
+                    candidate
+                } else {
+                    best
+                }
+            },
+        );
+
+        if total == 0.0 {
+            return Detection {
+                dialect,
+                confidence: 0.0,
+                generator: self.generator,
+            };
+        }
+
+        if dialect == DialectName::Fanuc && self.lathe > 0.0 {
+            dialect = DialectName::FanucLathe;
+        }
+
+        Detection {
+            dialect,
+            confidence: best / (total + CONFIDENCE_THRESHOLD),
+            generator: self.generator,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct KnownGenerator {
+    /// What to look for in a (lowercase) comment.
+    needle: &'static str,
+    name: &'static str,
+    dialect: Option<DialectName>,
+}
+
+impl KnownGenerator {
+    const fn new(
+        needle: &'static str,
+        name: &'static str,
+        dialect: Option<DialectName>,
+    ) -> Self {
+        KnownGenerator {
+            needle,
+            name,
+            dialect,
+        }
+    }
+}
+
+/// Generators we know how to recognise, checked in order.
+const KNOWN_GENERATORS: &[KnownGenerator] = &[
+    KnownGenerator::new(
+        "prusaslicer",
+        "PrusaSlicer",
+        Some(DialectName::RepRap),
+    ),
+    KnownGenerator::new(
+        "superslicer",
+        "SuperSlicer",
+        Some(DialectName::RepRap),
+    ),
+    KnownGenerator::new("orcaslicer", "OrcaSlicer", Some(DialectName::RepRap)),
+    KnownGenerator::new(
+        "bambustudio",
+        "BambuStudio",
+        Some(DialectName::RepRap),
+    ),
+    KnownGenerator::new("slic3r", "Slic3r", Some(DialectName::RepRap)),
+    KnownGenerator::new("cura", "Cura", Some(DialectName::RepRap)),
+    KnownGenerator::new("simplify3d", "Simplify3D", Some(DialectName::RepRap)),
+    KnownGenerator::new("ideamaker", "ideaMaker", Some(DialectName::RepRap)),
+    KnownGenerator::new("kisslicer", "KISSlicer", Some(DialectName::RepRap)),
+    KnownGenerator::new(
+        "carbide create",
+        "Carbide Create",
+        Some(DialectName::Grbl),
+    ),
+    KnownGenerator::new("estlcam", "Estlcam", Some(DialectName::Grbl)),
+    KnownGenerator::new("fusion 360", "Fusion 360", None),
+    KnownGenerator::new("mastercam", "Mastercam", None),
+];
+
+/// Find a word in some text, returning the index just after it.
+fn find_word(haystack: &str, word: &str) -> Option<usize> {
+    haystack.match_indices(word).find_map(|(start, _)| {
+        let boundary = haystack[..start]
+            .chars()
+            .next_back()
+            .is_none_or(|c| !c.is_alphanumeric());
+
+        if boundary {
+            Some(start + word.len())
+        } else {
+            None
+        }
+    })
+}
+
+/// Look for a version number in the first few words after a generator's
+/// name (e.g. `"_SteamEngine 5.4.0"` or `"(R) Version 4.1.2"`).
+fn version_after(text: &str) -> Option<&str> {
+    text.split_whitespace()
+        .take(3)
+        .map(|word| {
+            word.split('+')
+                .next()
+                .unwrap_or_default()
+                .trim_end_matches(|c: char| !c.is_alphanumeric())
+        })
+        .find(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(c) if c.is_ascii_digit() => true,
+                Some('X') | Some('x') => {
+                    chars.next().is_some_and(|c| c.is_ascii_digit())
+                },
+                _ => false,
+            }
+        })
+}
+
+/// Does this line end in a RepRap-style checksum (`N10 G1 X5*91`)?
+fn has_checksum(text: &str) -> bool {
+    let before_comment = text.split(';').next().unwrap_or_default().trim_end();
+
+    match before_comment.rsplit_once('*') {
+        Some((line, checksum)) => {
+            line.trim_start().starts_with(['N', 'n'])
+                && !checksum.is_empty()
+                && checksum.bytes().all(|b| b.is_ascii_digit())
+        },
+        None => false,
+    }
+}
+
+/// Is this a LinuxCNC-style `O`-word statement (`o100 sub`, `O<name> call`)?
+fn is_control_statement(text: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "sub",
+        "endsub",
+        "call",
+        "if",
+        "elseif",
+        "else",
+        "endif",
+        "while",
+        "endwhile",
+        "do",
+        "repeat",
+        "endrepeat",
+        "break",
+        "continue",
+        "return",
+    ];
+
+    let rest = match text.strip_prefix(['O', 'o']) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let rest = if rest.starts_with('<') {
+        match rest.find('>') {
+            Some(end) => &rest[end + 1..],
+            None => return false,
+        }
+    } else {
+        rest.trim_start_matches(|c: char| c.is_ascii_digit())
+    };
+
+    rest.split_whitespace().next().is_some_and(|word| {
+        KEYWORDS
+            .iter()
+            .any(|keyword| word.eq_ignore_ascii_case(keyword))
+    })
+}
+
+/// Is this a Fanuc-style program number on a line of its own (`O1234`)?
+fn is_program_number(text: &str) -> bool {
+    let text = text.split('(').next().unwrap_or_default().trim_end();
+
+    text.strip_prefix('O').is_some_and(|number| {
+        !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_to_go_on() {
+        let got = dialect("G90\nG1 X10 Y10 F100\n");
+
+        assert_eq!(
+            got,
+            Detection {
+                dialect: DialectName::Generic,
+                confidence: 0.0,
+                generator: None,
+            }
+        );
+    }
+
+    #[test]
+    fn grbl_system_commands() {
+        let got = dialect("$H\n$X\nG21 G90\nG0 X5\n");
+
+        assert_eq!(got.dialect, DialectName::Grbl);
+        assert!(got.confidence > 0.5);
+    }
+
+    #[test]
+    fn reprap_checksums() {
+        let got = dialect("N1 G28*18\nN2 G1 X5*97\n");
+
+        assert_eq!(got.dialect, DialectName::RepRap);
+    }
+
+    #[test]
+    fn linuxcnc_control_flow() {
+        let got = dialect("o<probe> sub\n#<depth> = 2\no<probe> endsub\n");
+
+        assert_eq!(got.dialect, DialectName::LinuxCnc);
+    }
+
+    #[test]
+    fn fanuc_programs_and_lathes() {
+        let mill = dialect("%\nO1234 (PART)\nG54.1 P1\nM98 P2000\n%\n");
+        let lathe = dialect("%\nO0001\nG96 S200\nG71 U1 R0.5\n%\n");
+
+        assert_eq!(mill.dialect, DialectName::Fanuc);
+        assert_eq!(lathe.dialect, DialectName::FanucLathe);
+    }
+
+    #[test]
+    fn only_the_first_few_lines_are_checked() {
+        let src = "G1 X1\nG1 X2\n$H\n";
+
+        assert_eq!(dialect_within(src, 2).dialect, DialectName::Generic);
+        assert_eq!(dialect_within(src, 3).dialect, DialectName::Grbl);
+    }
+
+    #[test]
+    fn mixed_evidence_is_less_certain() {
+        let clear = dialect("; Cura\nM104 S200\nM140 S60\n");
+        let mixed = dialect("; Cura\nM104 S200\n$H\n");
+
+        assert_eq!(mixed.dialect, DialectName::RepRap);
+        assert!(mixed.confidence < clear.confidence);
+    }
+
+    #[test]
+    fn known_generators() {
+        let inputs = vec![
+            (
+                "; generated by SuperSlicer 2.4.58.5 on 2023-01-01",
+                Generator::new("SuperSlicer", "2.4.58.5"),
+            ),
+            (
+                "; G-Code generated by Simplify3D(R) Version 4.1.2",
+                Generator::new("Simplify3D", "4.1.2"),
+            ),
+            (
+                "(Fusion 360 CAM 2.0.16985)",
+                Generator::new("Fusion 360", "2.0.16985"),
+            ),
+            ("(Mastercam 2023)", Generator::new("Mastercam", "2023")),
+            ("; Carbide Create", Generator::new("Carbide Create", None)),
+        ];
+
+        for (comment, should_be) in inputs {
+            assert_eq!(Generator::from_comment(comment), Some(should_be));
+        }
+    }
+
+    #[test]
+    fn generators_must_be_whole_words() {
+        assert_eq!(Generator::from_comment("(accuracy check)"), None);
+    }
+
+    #[test]
+    fn non_ascii_comments_dont_panic() {
+        let got = Generator::from_comment("; İİİİİİ cura 5.0").unwrap();
+
+        assert_eq!(got.to_string(), "Cura 5.0");
+    }
+
+    #[test]
+    fn find_the_generator_in_a_file() {
+        let src = "%\nO1000 (BRACKET)\n(MASTERCAM - X9)\nG54 G90\n";
+        let got = generator(src).unwrap();
+
+        assert_eq!(got.to_string(), "Mastercam X9");
+        assert_eq!(got.dialect(), None);
+        assert_eq!(dialect(src).generator, Some(got));
+    }
+}
@@ -0,0 +1,537 @@
+//! Controller-specific conventions.
+//!
+//! There is no single "g-code language", every family of controllers has its
+//! own opinions about how a program should be written. A [`Dialect`] bundles
+//! those opinions together so they can be handed to the rest of the crate in
+//! one go.
+
+use crate::{
+    buffers::Buffer,
+    interpret::ToolSelection,
+    writer::{LineEnding, NumberFormat, NumberStyle},
+    GCode, Mnemonic, Word,
+};
+use core::convert::TryFrom;
+
+/// The conventions used by a particular family of controllers.
+///
+/// Most users will want to start from one of the presets (e.g.
+/// [`Dialect::fanuc()`]) and tweak individual fields as necessary.
+///
+/// ```rust
+/// use gcode::{dialect::Dialect, writer::NumberStyle};
+///
+/// let mut dialect = Dialect::reprap();
+/// dialect.number_format.style = NumberStyle::FixedDecimals { decimals: 2 };
+///
+/// assert_ne!(dialect, Dialect::reprap());
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Dialect {
+    /// How numbers should be formatted when writing g-code.
+    pub number_format: NumberFormat,
+    /// When set, a dimension (see [`Dialect::is_dimension_letter()`]) whose
+    /// number was written without a decimal point is treated as a multiple
+    /// of this increment.
+    ///
+    /// For example, older Fanuc controls with an increment of `0.001` read
+    /// `X100` as `X0.100`, while `X100.` still means `X100.0`.
+    pub least_input_increment: Option<f32>,
+    /// Pad `G` and `M` command numbers with leading zeroes until they are
+    /// at least this many digits long when writing (e.g. `2` gives `G01` and
+    /// `M06`, while `1` gives `G1` and `M6`).
+    ///
+    /// Leading zeroes are always ignored when parsing, so `G04` and `G4` are
+    /// the same command.
+    pub min_command_digits: u8,
+    /// The units used by the `P` argument of a dwell (`G4`).
+    pub dwell_units: DwellUnits,
+    /// How the number attached to a `T` word is decoded.
+    pub tool_encoding: ToolEncoding,
+    /// Does a `T` word make the tool active straight away (e.g. a lathe's
+    /// turret or a 3D printer's extruders), or does it only prepare the tool
+    /// for the next tool change (`M6`)?
+    pub immediate_tool_change: bool,
+    /// The `G` command whose `S` argument limits the spindle's maximum speed
+    /// in RPM, if any (e.g. `G50 S2000` on Fanuc lathes, or `G92 S2000` on
+    /// controls using the alternate lathe code system).
+    pub spindle_clamp_gcode: Option<u32>,
+    /// When set, numbers are stored exactly as they were written (as a
+    /// [`WordValue::Decimal`]) instead of being converted to `f32`, keeping
+    /// at most this many digits after the decimal point.
+    ///
+    /// See the [`decimal`][crate::decimal] module for more.
+    ///
+    /// [`WordValue::Decimal`]: crate::WordValue::Decimal
+    pub decimal_precision: Option<u8>,
+    /// Extra characters which may be used in place of a word's letter,
+    /// such as the `:` Fanuc controls accept instead of `O` for program
+    /// numbers when reading ISO-format tapes.
+    ///
+    /// See [`Dialect::leader_letter()`] for more.
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub alternate_leaders: [Option<AlternateLeader>; MAX_ALTERNATE_LEADERS],
+    /// Read RS-274/NGC parameters (`#100`, `#<_feed>`) and bracketed
+    /// expressions (`[1 + 2 * SIN[45]]`) as word values, and parameter
+    /// assignments (`#100 = 25.4`) as statements.
+    ///
+    /// Expressions are handed to [`Callbacks::evaluate_expression()`] while
+    /// parsing, and any which aren't evaluated are kept as a
+    /// [`WordValue::Expression`].
+    ///
+    /// [`Callbacks::evaluate_expression()`]: crate::Callbacks::evaluate_expression
+    /// [`WordValue::Expression`]: crate::WordValue::Expression
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub expressions: bool,
+    /// Read the trailing checksum (`*` followed by a number) hosts add to
+    /// each line they send to a RepRap-style printer (e.g.
+    /// `N42 G1 X10 *27`), and check that line numbers go up by one.
+    ///
+    /// Problems are reported using [`Callbacks::checksum_mismatch()`] and
+    /// [`Callbacks::line_number_out_of_sequence()`]. This is off for every
+    /// built-in dialect because files on disk don't contain checksums.
+    ///
+    /// [`Callbacks::checksum_mismatch()`]: crate::Callbacks::checksum_mismatch
+    /// [`Callbacks::line_number_out_of_sequence()`]: crate::Callbacks::line_number_out_of_sequence
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub checksums: bool,
+    /// How spindle-synchronised threading moves and cycles are written, if
+    /// the controller supports them.
+    ///
+    /// Printers use the same numbers for other things (e.g. Marlin's `G33`
+    /// is delta calibration), as do mills (`G76` is a fine boring cycle on
+    /// Fanuc mills), so this is `None` for them and for
+    /// [`Dialect::generic()`].
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub threading: Option<ThreadingStyle>,
+    /// The `G` codes which switch between feeding a certain distance per
+    /// minute and per revolution of the spindle.
+    ///
+    /// Lathes using Fanuc's "A" system use `G98` and `G99`, which means they
+    /// can't also be used to pick where canned cycles retract to.
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub feed_mode_gcodes: FeedModeGcodes,
+    /// The characters written at the end of each line.
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub line_ending: LineEnding,
+    /// Salvage what was probably meant from a malformed number, the way
+    /// most printer firmware does.
+    ///
+    /// A number with more than one decimal point keeps everything before the
+    /// second one, so `X1.2.3` is read as `X1.2` (and `X1..5` as `X1`), and
+    /// the whole number is reported using [`Callbacks::partial_word()`].
+    /// Otherwise the parser stops at the second decimal point and reports
+    /// the rest as a number without a letter.
+    ///
+    /// [`Callbacks::partial_word()`]: crate::Callbacks::partial_word
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub lenient: bool,
+}
+
+/// The most [`AlternateLeader`]s a [`Dialect`] can have.
+pub const MAX_ALTERNATE_LEADERS: usize = 4;
+
+impl Dialect {
+    /// A reasonable set of defaults which most controllers will accept.
+    pub const fn generic() -> Self {
+        Dialect {
+            number_format: NumberFormat {
+                style: NumberStyle::Trim { max_decimals: 4 },
+                plus_sign: false,
+                leading_zero: true,
+                always_decimal_point: false,
+            },
+            least_input_increment: None,
+            min_command_digits: 1,
+            dwell_units: DwellUnits::Seconds,
+            tool_encoding: ToolEncoding::Index,
+            immediate_tool_change: false,
+            spindle_clamp_gcode: None,
+            decimal_precision: None,
+            alternate_leaders: [None; MAX_ALTERNATE_LEADERS],
+            expressions: false,
+            checksums: false,
+            threading: None,
+            feed_mode_gcodes: FeedModeGcodes::STANDARD,
+            line_ending: LineEnding::Lf,
+            lenient: false,
+        }
+    }
+
+    /// Firmware used by hobbyist 3D printers (Marlin, RepRapFirmware,
+    /// Klipper, etc.).
+    ///
+    /// These read numbers with `strtod()`, so they are [`Dialect::lenient`]
+    /// about anything after a second decimal point.
+    pub const fn reprap() -> Self {
+        Dialect {
+            number_format: NumberFormat {
+                style: NumberStyle::Trim { max_decimals: 5 },
+                ..Dialect::generic().number_format
+            },
+            dwell_units: DwellUnits::Milliseconds,
+            immediate_tool_change: true,
+            lenient: true,
+            ..Dialect::generic()
+        }
+    }
+
+    /// The `grbl` firmware commonly found on hobbyist CNC routers.
+    pub const fn grbl() -> Self {
+        Dialect {
+            number_format: NumberFormat {
+                style: NumberStyle::Trim { max_decimals: 3 },
+                ..Dialect::generic().number_format
+            },
+            ..Dialect::generic()
+        }
+    }
+
+    /// The LinuxCNC flavour of RS-274/NGC.
+    pub const fn linuxcnc() -> Self {
+        Dialect {
+            expressions: true,
+            threading: Some(ThreadingStyle::LinuxCnc),
+            ..Dialect::generic()
+        }
+    }
+
+    /// Fanuc-style industrial controls, which expect fixed-width fields with
+    /// an implied decimal point (i.e. `X0100` means `0.100`).
+    ///
+    /// Lines end with `"\r\n"`, because that's what most DNC software
+    /// drip-feeding these controls over RS-232 expects.
+    pub const fn fanuc() -> Self {
+        Dialect {
+            number_format: NumberFormat {
+                style: NumberStyle::ImpliedDecimal {
+                    decimals: 3,
+                    min_width: 4,
+                },
+                ..Dialect::generic().number_format
+            },
+            least_input_increment: Some(0.001),
+            min_command_digits: 2,
+            dwell_units: DwellUnits::Milliseconds,
+            alternate_leaders: [
+                Some(AlternateLeader::new(':', 'O')),
+                None,
+                None,
+                None,
+            ],
+            line_ending: LineEnding::CrLf,
+            ..Dialect::generic()
+        }
+    }
+
+    /// A Fanuc-style lathe, where `T0102` selects tool `1` on the turret
+    /// using offset register `2`.
+    pub const fn fanuc_lathe() -> Self {
+        Dialect {
+            tool_encoding: ToolEncoding::ToolAndOffset { offset_digits: 2 },
+            immediate_tool_change: true,
+            spindle_clamp_gcode: Some(50),
+            threading: Some(ThreadingStyle::Fanuc),
+            feed_mode_gcodes: FeedModeGcodes::FANUC_LATHE,
+            ..Dialect::fanuc()
+        }
+    }
+
+    /// Does this letter introduce a dimension (i.e. a distance or angle)?
+    ///
+    /// ```rust
+    /// # use gcode::dialect::Dialect;
+    /// let dialect = Dialect::generic();
+    ///
+    /// assert!(dialect.is_dimension_letter('x'));
+    /// assert!(dialect.is_dimension_letter('J'));
+    /// assert!(!dialect.is_dimension_letter('F'));
+    /// ```
+    pub fn is_dimension_letter(&self, letter: char) -> bool {
+        is_dimension_letter(letter)
+    }
+
+    /// Which letter does a character stand for when it starts a word?
+    ///
+    /// Letters always stand for themselves, while other characters only
+    /// count if they're one of the [`Dialect::alternate_leaders`].
+    ///
+    /// ```rust
+    /// # use gcode::dialect::Dialect;
+    /// let fanuc = Dialect::fanuc();
+    ///
+    /// assert_eq!(fanuc.leader_letter('G'), Some('G'));
+    /// assert_eq!(fanuc.leader_letter(':'), Some('O'));
+    /// assert_eq!(Dialect::generic().leader_letter(':'), None);
+    ///
+    /// let program: Vec<_> = gcode::Parser::<_>::new_with_dialect(
+    ///     ":1234\nG00 X1.",
+    ///     gcode::Nop,
+    ///     fanuc,
+    /// )
+    /// .flat_map(|line| line.gcodes().to_vec())
+    /// .collect();
+    /// assert_eq!(program[0].mnemonic(), gcode::Mnemonic::ProgramNumber);
+    /// assert_eq!(program[0].major_number(), 1234);
+    /// ```
+    pub fn leader_letter(&self, c: char) -> Option<char> {
+        if c.is_ascii_alphabetic() {
+            return Some(c);
+        }
+
+        self.alternate_leaders
+            .iter()
+            .flatten()
+            .find(|leader| leader.character == c)
+            .map(|leader| leader.letter)
+    }
+
+    /// If this is a dwell (`G4`), how many seconds should the machine pause
+    /// for?
+    ///
+    /// The different spellings are all understood, with `P` interpreted
+    /// according to [`Dialect::dwell_units`], while `S`, `X` and `U` are
+    /// always in seconds.
+    ///
+    /// ```rust
+    /// # use gcode::dialect::Dialect;
+    /// let marlin = Dialect::reprap();
+    /// let linuxcnc = Dialect::linuxcnc();
+    /// let dwell = |src| gcode::parse(src).next().unwrap();
+    ///
+    /// assert_eq!(marlin.dwell_seconds(&dwell("G04 P500")), Some(0.5));
+    /// assert_eq!(marlin.dwell_seconds(&dwell("G4 S2")), Some(2.0));
+    /// assert_eq!(linuxcnc.dwell_seconds(&dwell("G4 P0.5")), Some(0.5));
+    /// assert_eq!(linuxcnc.dwell_seconds(&dwell("G4 X0.5")), Some(0.5));
+    /// assert_eq!(linuxcnc.dwell_seconds(&dwell("G1 X0.5")), None);
+    /// ```
+    pub fn dwell_seconds<A: Buffer<Word>>(
+        &self,
+        gcode: &GCode<A>,
+    ) -> Option<f32> {
+        if gcode.mnemonic != Mnemonic::General
+            || gcode.major_number() != 4
+            || gcode.minor_number() != 0
+        {
+            return None;
+        }
+
+        if let Some(p) = gcode.value_for('P') {
+            return Some(match self.dwell_units {
+                DwellUnits::Seconds => p,
+                DwellUnits::Milliseconds => p / 1000.0,
+            });
+        }
+
+        gcode
+            .value_for('S')
+            .or_else(|| gcode.value_for('X'))
+            .or_else(|| gcode.value_for('U'))
+    }
+
+    /// Decode a `T` word into the [`ToolSelection`] it represents.
+    ///
+    /// RepRap's `T-1` deselects the current tool, and any other negative or
+    /// fractional tool number isn't a valid selection. An `H` argument (e.g.
+    /// `T3 H3`) is used as the offset register when
+    /// [`Dialect::tool_encoding`] doesn't already provide one.
+    ///
+    /// ```rust
+    /// # use gcode::{dialect::Dialect, interpret::ToolSelection};
+    /// let tool = |src| gcode::parse(src).next().unwrap();
+    ///
+    /// let lathe = Dialect::fanuc_lathe();
+    /// assert_eq!(
+    ///     lathe.tool_selection(&tool("T0102")),
+    ///     Some(ToolSelection { tool: Some(1), offset: Some(2) }),
+    /// );
+    ///
+    /// let mill = Dialect::fanuc();
+    /// assert_eq!(
+    ///     mill.tool_selection(&tool("T12 H12")),
+    ///     Some(ToolSelection { tool: Some(12), offset: Some(12) }),
+    /// );
+    ///
+    /// let printer = Dialect::reprap();
+    /// assert_eq!(
+    ///     printer.tool_selection(&tool("T-1")),
+    ///     Some(ToolSelection::DESELECT),
+    /// );
+    /// assert_eq!(printer.tool_selection(&tool("T-2")), None);
+    /// assert_eq!(printer.tool_selection(&tool("G28")), None);
+    /// ```
+    pub fn tool_selection<A: Buffer<Word>>(
+        &self,
+        gcode: &GCode<A>,
+    ) -> Option<ToolSelection> {
+        if gcode.mnemonic != Mnemonic::ToolChange {
+            return None;
+        }
+
+        let number = match gcode.key() {
+            key if key.minor != 0 => return None,
+            key if key.major == -1 => return Some(ToolSelection::DESELECT),
+            key => u32::try_from(key.major).ok()?,
+        };
+        let offset_register = gcode.value_for('H').map(|h| h as u32);
+
+        match self.tool_encoding {
+            ToolEncoding::Index => Some(ToolSelection {
+                tool: Some(number),
+                offset: offset_register,
+            }),
+            ToolEncoding::ToolAndOffset { offset_digits } => {
+                let divisor = 10_u32.pow(u32::from(offset_digits));
+
+                Some(ToolSelection {
+                    tool: Some(number / divisor),
+                    offset: Some(number % divisor),
+                })
+            },
+        }
+    }
+}
+
+/// A character which can be written instead of a letter at the start of a
+/// word (e.g. `:1234` instead of `O1234`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct AlternateLeader {
+    /// The character as it's written in the program.
+    pub character: char,
+    /// The letter it stands for.
+    pub letter: char,
+}
+
+impl AlternateLeader {
+    /// Create a new [`AlternateLeader`].
+    pub const fn new(character: char, letter: char) -> Self {
+        AlternateLeader { character, letter }
+    }
+}
+
+/// The units a duration is measured in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum DwellUnits {
+    /// Seconds (e.g. LinuxCNC's `G4 P0.5`).
+    Seconds,
+    /// Milliseconds (e.g. Marlin's `G4 P500`).
+    Milliseconds,
+}
+
+/// The different ways a `T` word's number can be interpreted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum ToolEncoding {
+    /// The number is just the tool's index (e.g. `T3`).
+    Index,
+    /// The last `offset_digits` digits select an offset register and the
+    /// rest select the tool (e.g. `T0102` for tool `1`, offset `2`).
+    ToolAndOffset {
+        /// How many digits are used for the offset register.
+        offset_digits: u8,
+    },
+}
+
+/// The different ways threading moves and cycles are written.
+///
+/// Both styles use `G76` for a threading cycle which cuts the thread in
+/// several passes, each a little deeper than the last.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum ThreadingStyle {
+    /// `G33` moves with the distance per revolution in `K`, and `G76`
+    /// cycles written on a single line (`G76 P Z I J K R Q H E L`), as used
+    /// by LinuxCNC.
+    LinuxCnc,
+    /// `G32` moves with the lead in `F`, and `G76` cycles written as two
+    /// blocks, the first setting the number of finishing passes
+    /// (`G76 P020060 Q50 R0.02`) and the second cutting the thread
+    /// (`G76 X Z P Q F`), as used by Fanuc lathes.
+    ///
+    /// The thread height (`P`) and first cut depth (`Q`) in the second
+    /// block are written in multiples of the
+    /// [`Dialect::least_input_increment`], when there is one.
+    Fanuc,
+}
+
+impl ThreadingStyle {
+    /// The `G` code for a single spindle-synchronised move.
+    pub const fn move_gcode(self) -> u32 {
+        match self {
+            ThreadingStyle::LinuxCnc => 33,
+            ThreadingStyle::Fanuc => 32,
+        }
+    }
+
+    /// The letter giving a threading move's distance per revolution.
+    pub const fn pitch_letter(self) -> char {
+        match self {
+            ThreadingStyle::LinuxCnc => 'K',
+            ThreadingStyle::Fanuc => 'F',
+        }
+    }
+}
+
+/// The `G` codes used to select a [`FeedMode`].
+///
+/// [`FeedMode`]: crate::interpret::FeedMode
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct FeedModeGcodes {
+    /// Feed a certain distance per minute.
+    pub per_minute: u32,
+    /// Feed a certain distance per revolution of the spindle.
+    pub per_revolution: u32,
+}
+
+impl FeedModeGcodes {
+    /// `G98` and `G99`, as used by lathes with Fanuc's "A" system.
+    pub const FANUC_LATHE: FeedModeGcodes = FeedModeGcodes {
+        per_minute: 98,
+        per_revolution: 99,
+    };
+    /// `G94` and `G95`, as used by most controllers.
+    pub const STANDARD: FeedModeGcodes = FeedModeGcodes {
+        per_minute: 94,
+        per_revolution: 95,
+    };
+
+    /// Is this one of the feed mode `G` codes?
+    pub const fn contains(self, major: u32) -> bool {
+        major == self.per_minute || major == self.per_revolution
+    }
+}
+
+impl Default for FeedModeGcodes {
+    fn default() -> FeedModeGcodes { FeedModeGcodes::STANDARD }
+}
+
+impl Default for Dialect {
+    fn default() -> Dialect { Dialect::generic() }
+}
+
+/// The letters which introduce a dimension, in every dialect.
+pub(crate) fn is_dimension_letter(letter: char) -> bool {
+    "XYZUVWABCIJKR".contains(letter.to_ascii_uppercase())
+}
@@ -0,0 +1,744 @@
+//! Typed events embedded in comments.
+//!
+//! Tools upstream of the machine often leave annotations in comments (e.g.
+//! `;CUSTOM_EVENT frame=12 label="left wall"`) so something downstream can
+//! react to them. Rather than making every application pick these comments
+//! apart itself, an [`EventSchema`] describes an annotation's prefix and the
+//! key/value pairs it contains. Registering schemas with a [`Parser`] using
+//! [`Parser::with_events()`] produces a stream of [`Item`]s where matching
+//! comments also show up as [`CustomEvent`]s with their values already
+//! converted to the right types.
+//!
+//! ```rust
+//! use gcode::{
+//!     events::{EventSchema, EventSchemas, Field, FieldType, Item},
+//!     Nop, Parser,
+//! };
+//!
+//! let schemas = EventSchemas::new().with(
+//!     EventSchema::new("camera", "CUSTOM_EVENT")
+//!         .with_field(Field::required("frame", FieldType::Integer))
+//!         .with_field(Field::optional("label", FieldType::Text)),
+//! );
+//! let src = "G1 X10\n;CUSTOM_EVENT frame=12 label=\"left wall\"\nG1 X20";
+//!
+//! let events: Vec<_> = Parser::<_>::new(src, Nop)
+//!     .with_events(&schemas)
+//!     .filter_map(|item| match item {
+//!         Item::Event(event) => Some(event),
+//!         _ => None,
+//!     })
+//!     .collect();
+//!
+//! assert_eq!(events.len(), 1);
+//! assert_eq!(events[0].name, "camera");
+//! assert_eq!(events[0].integer("frame"), Some(12));
+//! assert_eq!(events[0].text("label"), Some("left wall"));
+//! assert_eq!(events[0].span.line, 1);
+//! ```
+
+use crate::{buffers::Buffers, Callbacks, Comment, Line, Parser, Span};
+use core::fmt::{self, Display, Formatter};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// The type of value a [`Field`] holds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum FieldType {
+    /// A whole number (e.g. `frame=12`).
+    Integer,
+    /// Any number (e.g. `z=0.4`).
+    Float,
+    /// `true`/`false`, `yes`/`no` or `1`/`0`. A key without a value (e.g.
+    /// `final`) is `true`.
+    Bool,
+    /// Anything at all, optionally in double quotes so it can contain
+    /// separators.
+    Text,
+}
+
+impl Display for FieldType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldType::Integer => write!(f, "an integer"),
+            FieldType::Float => write!(f, "a number"),
+            FieldType::Bool => write!(f, "true or false"),
+            FieldType::Text => write!(f, "some text"),
+        }
+    }
+}
+
+/// A key which may appear in an [`EventSchema`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Field {
+    /// The key (e.g. the `frame` in `frame=12`).
+    pub key: String,
+    /// The type of value it holds.
+    pub kind: FieldType,
+    /// Is it an error for the key to be missing?
+    pub required: bool,
+}
+
+impl Field {
+    /// A key which must always be given.
+    pub fn required<K: Into<String>>(key: K, kind: FieldType) -> Self {
+        Field {
+            key: key.into(),
+            kind,
+            required: true,
+        }
+    }
+
+    /// A key which may be left out.
+    pub fn optional<K: Into<String>>(key: K, kind: FieldType) -> Self {
+        Field {
+            key: key.into(),
+            kind,
+            required: false,
+        }
+    }
+}
+
+/// A description of the comments which make up a particular kind of
+/// [`CustomEvent`].
+///
+/// A comment matches if its text (see [`Comment::text()`]) starts with the
+/// `prefix`, followed by any number of `key=value` pairs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct EventSchema {
+    /// The name given to each [`CustomEvent`].
+    pub name: String,
+    /// The text a comment must start with.
+    pub prefix: String,
+    /// The keys an event may contain.
+    pub fields: Vec<Field>,
+    /// What separates one pair from the next, in addition to whitespace
+    /// (e.g. `,` for `frame=12,label=wall`).
+    pub separator: Option<char>,
+    /// What separates a key from its value.
+    pub assignment: char,
+    /// Keep keys which aren't in `fields` as [`FieldValue::Text`] instead of
+    /// reporting an [`EventError::UnknownField`].
+    pub allow_unknown_fields: bool,
+}
+
+impl EventSchema {
+    /// Create a schema for comments starting with `prefix` and containing
+    /// whitespace-separated `key=value` pairs.
+    pub fn new<N, P>(name: N, prefix: P) -> Self
+    where
+        N: Into<String>,
+        P: Into<String>,
+    {
+        EventSchema {
+            name: name.into(),
+            prefix: prefix.into(),
+            fields: Vec::new(),
+            separator: None,
+            assignment: '=',
+            allow_unknown_fields: false,
+        }
+    }
+
+    /// Add a [`Field`] to the schema.
+    pub fn with_field(mut self, field: Field) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    /// Does a comment's text start with this schema's prefix?
+    ///
+    /// A prefix ending in a letter or digit must also be followed by the end
+    /// of the comment or something other than a letter or digit, so a
+    /// prefix of `EVENT` doesn't match `EVENTS`.
+    pub fn matches(&self, text: &str) -> bool {
+        let rest = match text.strip_prefix(self.prefix.as_str()) {
+            Some(rest) => rest,
+            None => return false,
+        };
+
+        let ends_in_word = self.prefix.chars().last().is_some_and(is_word);
+        let continues_word = rest.chars().next().is_some_and(is_word);
+
+        !(ends_in_word && continues_word)
+    }
+
+    /// Try to turn a [`Comment`] into a [`CustomEvent`], returning `None` if
+    /// it doesn't start with this schema's prefix.
+    ///
+    /// ```rust
+    /// use gcode::{
+    ///     events::{EventSchema, Field, FieldType},
+    ///     Comment, Span,
+    /// };
+    ///
+    /// let schema = EventSchema::new("pause", "PAUSE")
+    ///     .with_field(Field::required("seconds", FieldType::Float));
+    /// let comment = |value| Comment {
+    ///     value,
+    ///     span: Span::PLACEHOLDER,
+    /// };
+    ///
+    /// let event = schema.parse(&comment("; PAUSE seconds=2.5")).unwrap();
+    /// assert_eq!(event.unwrap().float("seconds"), Some(2.5));
+    ///
+    /// let err = schema.parse(&comment("; PAUSE")).unwrap().unwrap_err();
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "the \"pause\" event needs a \"seconds\" field"
+    /// );
+    ///
+    /// assert!(schema.parse(&comment("; something else")).is_none());
+    /// ```
+    pub fn parse(
+        &self,
+        comment: &Comment<'_>,
+    ) -> Option<Result<CustomEvent, EventError>> {
+        let text = comment.text();
+
+        if !self.matches(text) {
+            return None;
+        }
+
+        Some(self.parse_fields(&text[self.prefix.len()..], comment.span))
+    }
+
+    fn parse_fields(
+        &self,
+        mut rest: &str,
+        span: Span,
+    ) -> Result<CustomEvent, EventError> {
+        let mut values = BTreeMap::new();
+
+        loop {
+            rest = rest.trim_start_matches(|c| self.is_separator(c));
+            if rest.is_empty() {
+                break;
+            }
+
+            let key_length = rest
+                .find(|c| self.is_separator(c) || c == self.assignment)
+                .unwrap_or(rest.len());
+            let key = &rest[..key_length];
+            rest = &rest[key_length..];
+
+            let value = match rest.strip_prefix(self.assignment) {
+                Some(after) => {
+                    let (value, after) =
+                        self.split_value(after).ok_or_else(|| {
+                            EventError::Malformed {
+                                event: self.name.clone(),
+                                span,
+                            }
+                        })?;
+                    rest = after;
+                    Some(value)
+                },
+                None => None,
+            };
+
+            if key.is_empty() {
+                return Err(EventError::Malformed {
+                    event: self.name.clone(),
+                    span,
+                });
+            }
+
+            let value = self.convert(key, value, span)?;
+            let _ = values.insert(key.to_string(), value);
+        }
+
+        for field in &self.fields {
+            if field.required && !values.contains_key(&field.key) {
+                return Err(EventError::MissingField {
+                    event: self.name.clone(),
+                    field: field.key.clone(),
+                    span,
+                });
+            }
+        }
+
+        Ok(CustomEvent {
+            name: self.name.clone(),
+            fields: values,
+            span,
+        })
+    }
+
+    /// Split a value off the start of some text, handling quotes.
+    fn split_value<'a>(&self, text: &'a str) -> Option<(&'a str, &'a str)> {
+        match text.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                Some((&quoted[..end], &quoted[end + 1..]))
+            },
+            None => {
+                let end =
+                    text.find(|c| self.is_separator(c)).unwrap_or(text.len());
+                Some(text.split_at(end))
+            },
+        }
+    }
+
+    fn convert(
+        &self,
+        key: &str,
+        value: Option<&str>,
+        span: Span,
+    ) -> Result<FieldValue, EventError> {
+        let kind = match self.fields.iter().find(|field| field.key == key) {
+            Some(field) => field.kind,
+            None if self.allow_unknown_fields => match value {
+                Some(value) => return Ok(FieldValue::Text(value.to_string())),
+                None => return Ok(FieldValue::Bool(true)),
+            },
+            None => {
+                return Err(EventError::UnknownField {
+                    event: self.name.clone(),
+                    field: key.to_string(),
+                    span,
+                })
+            },
+        };
+
+        let converted = match (kind, value) {
+            (FieldType::Bool, None) => Some(FieldValue::Bool(true)),
+            (_, None) => None,
+            (FieldType::Integer, Some(value)) => {
+                value.parse().ok().map(FieldValue::Integer)
+            },
+            (FieldType::Float, Some(value)) => {
+                value.parse().ok().map(FieldValue::Float)
+            },
+            (FieldType::Bool, Some(value)) => {
+                parse_bool(value).map(FieldValue::Bool)
+            },
+            (FieldType::Text, Some(value)) => {
+                Some(FieldValue::Text(value.to_string()))
+            },
+        };
+
+        converted.ok_or_else(|| EventError::InvalidValue {
+            event: self.name.clone(),
+            field: key.to_string(),
+            expected: kind,
+            span,
+        })
+    }
+
+    fn is_separator(&self, c: char) -> bool {
+        c.is_whitespace() || Some(c) == self.separator
+    }
+}
+
+fn is_word(c: char) -> bool { c.is_alphanumeric() || c == '_' }
+
+fn parse_bool(value: &str) -> Option<bool> {
+    const TRUE: &[&str] = &["true", "yes", "on", "1"];
+    const FALSE: &[&str] = &["false", "no", "off", "0"];
+
+    if TRUE.iter().any(|t| value.eq_ignore_ascii_case(t)) {
+        Some(true)
+    } else if FALSE.iter().any(|f| value.eq_ignore_ascii_case(f)) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// The value of a field in a [`CustomEvent`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum FieldValue {
+    /// A whole number.
+    Integer(i64),
+    /// Any number.
+    Float(f64),
+    /// `true` or `false`.
+    Bool(bool),
+    /// Some text, without any surrounding quotes.
+    Text(String),
+}
+
+/// An annotation which was found in a comment and matched an
+/// [`EventSchema`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct CustomEvent {
+    /// The [`EventSchema::name`] of the schema it matched.
+    pub name: String,
+    /// The value of each field which was given.
+    pub fields: BTreeMap<String, FieldValue>,
+    /// Where the comment is in the source text.
+    pub span: Span,
+}
+
+impl CustomEvent {
+    /// Get a field's value.
+    pub fn get(&self, key: &str) -> Option<&FieldValue> { self.fields.get(key) }
+
+    /// Get a [`FieldType::Integer`] field.
+    pub fn integer(&self, key: &str) -> Option<i64> {
+        match self.get(key)? {
+            FieldValue::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Get a [`FieldType::Float`] field.
+    pub fn float(&self, key: &str) -> Option<f64> {
+        match self.get(key)? {
+            FieldValue::Float(n) => Some(*n),
+            FieldValue::Integer(n) => Some(*n as f64),
+            _ => None,
+        }
+    }
+
+    /// Get a [`FieldType::Bool`] field.
+    pub fn boolean(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            FieldValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Get a [`FieldType::Text`] field.
+    pub fn text(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            FieldValue::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Why a comment which starts with an [`EventSchema::prefix`] couldn't be
+/// turned into a [`CustomEvent`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventError {
+    /// The comment contains a key the schema doesn't know about.
+    UnknownField {
+        /// The schema's name.
+        event: String,
+        /// The unknown key.
+        field: String,
+        /// Where the comment is.
+        span: Span,
+    },
+    /// A required key was missing.
+    MissingField {
+        /// The schema's name.
+        event: String,
+        /// The missing key.
+        field: String,
+        /// Where the comment is.
+        span: Span,
+    },
+    /// A value couldn't be converted to its [`FieldType`].
+    InvalidValue {
+        /// The schema's name.
+        event: String,
+        /// The key whose value is wrong.
+        field: String,
+        /// The type the value should have.
+        expected: FieldType,
+        /// Where the comment is.
+        span: Span,
+    },
+    /// The pairs couldn't be split up (e.g. a quote was never closed).
+    Malformed {
+        /// The schema's name.
+        event: String,
+        /// Where the comment is.
+        span: Span,
+    },
+}
+
+impl EventError {
+    /// Where the offending comment is.
+    pub fn span(&self) -> Span {
+        match *self {
+            EventError::UnknownField { span, .. }
+            | EventError::MissingField { span, .. }
+            | EventError::InvalidValue { span, .. }
+            | EventError::Malformed { span, .. } => span,
+        }
+    }
+}
+
+impl Display for EventError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EventError::UnknownField { event, field, .. } => write!(
+                f,
+                "the \"{}\" event doesn't have a \"{}\" field",
+                event, field
+            ),
+            EventError::MissingField { event, field, .. } => {
+                write!(f, "the \"{}\" event needs a \"{}\" field", event, field)
+            },
+            EventError::InvalidValue {
+                event,
+                field,
+                expected,
+                ..
+            } => write!(
+                f,
+                "the \"{}\" field of the \"{}\" event should be {}",
+                field, event, expected
+            ),
+            EventError::Malformed { event, .. } => {
+                write!(f, "unable to read the \"{}\" event's fields", event)
+            },
+        }
+    }
+}
+
+impl std::error::Error for EventError {}
+
+/// A set of [`EventSchema`]s, checked in the order they were added.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct EventSchemas {
+    schemas: Vec<EventSchema>,
+}
+
+impl EventSchemas {
+    /// Create an empty set of schemas.
+    pub fn new() -> Self { EventSchemas::default() }
+
+    /// Add a schema.
+    pub fn with(mut self, schema: EventSchema) -> Self {
+        self.register(schema);
+        self
+    }
+
+    /// Add a schema, replacing any existing schema with the same name.
+    pub fn register(&mut self, schema: EventSchema) {
+        match self.schemas.iter_mut().find(|s| s.name == schema.name) {
+            Some(existing) => *existing = schema,
+            None => self.schemas.push(schema),
+        }
+    }
+
+    /// Every registered schema.
+    pub fn schemas(&self) -> &[EventSchema] { &self.schemas }
+
+    /// Try to turn a [`Comment`] into a [`CustomEvent`] using the first
+    /// schema whose prefix it matches.
+    pub fn parse(
+        &self,
+        comment: &Comment<'_>,
+    ) -> Option<Result<CustomEvent, EventError>> {
+        self.schemas.iter().find_map(|schema| schema.parse(comment))
+    }
+}
+
+/// Something produced by [`Parser::with_events()`].
+#[derive(Debug)]
+pub enum Item<'input, B: Buffers<'input>> {
+    /// A line, exactly as the [`Parser`] would normally produce it.
+    Line(Line<'input, B>),
+    /// An event found in one of the previous line's comments.
+    Event(CustomEvent),
+    /// A comment which started with an event's prefix but didn't follow its
+    /// schema.
+    InvalidEvent(EventError),
+}
+
+/// An iterator which parses events out of each [`Line`]'s comments, created
+/// by [`Parser::with_events()`].
+#[derive(Debug)]
+pub struct WithEvents<'schemas, 'input, C, B: Buffers<'input>> {
+    parser: Parser<'input, C, B>,
+    schemas: &'schemas EventSchemas,
+    pending: VecDeque<Item<'input, B>>,
+}
+
+impl<'schemas, 'input, C, B: Buffers<'input>>
+    WithEvents<'schemas, 'input, C, B>
+{
+    pub(crate) fn new(
+        parser: Parser<'input, C, B>,
+        schemas: &'schemas EventSchemas,
+    ) -> Self {
+        WithEvents {
+            parser,
+            schemas,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<'schemas, 'input, C, B> Iterator for WithEvents<'schemas, 'input, C, B>
+where
+    C: Callbacks,
+    B: Buffers<'input>,
+{
+    type Item = Item<'input, B>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(item) = self.pending.pop_front() {
+            return Some(item);
+        }
+
+        let line = self.parser.next()?;
+
+        for comment in line.comments() {
+            match self.schemas.parse(comment) {
+                Some(Ok(event)) => self.pending.push_back(Item::Event(event)),
+                Some(Err(e)) => self.pending.push_back(Item::InvalidEvent(e)),
+                None => {},
+            }
+        }
+
+        Some(Item::Line(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Nop;
+
+    fn comment(value: &str) -> Comment<'_> {
+        Comment {
+            value,
+            span: Span::PLACEHOLDER,
+        }
+    }
+
+    fn schema() -> EventSchema {
+        EventSchema::new("marker", "MARK")
+            .with_field(Field::required("id", FieldType::Integer))
+            .with_field(Field::optional("z", FieldType::Float))
+            .with_field(Field::optional("final", FieldType::Bool))
+            .with_field(Field::optional("note", FieldType::Text))
+    }
+
+    #[test]
+    fn parse_every_type_of_field() {
+        let got = schema()
+            .parse(&comment("(MARK id=-3 z=0.25 final note=\"a, b\")"))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(got.integer("id"), Some(-3));
+        assert_eq!(got.float("z"), Some(0.25));
+        assert_eq!(got.boolean("final"), Some(true));
+        assert_eq!(got.text("note"), Some("a, b"));
+        assert_eq!(got.get("missing"), None);
+    }
+
+    #[test]
+    fn prefixes_must_be_whole_words() {
+        let schema = schema();
+
+        assert!(schema.matches("MARK"));
+        assert!(schema.matches("MARK id=1"));
+        assert!(!schema.matches("MARKER id=1"));
+        assert!(!schema.matches("mark id=1"));
+        assert!(EventSchema::new("layer", "LAYER:").matches("LAYER:3"));
+    }
+
+    #[test]
+    fn custom_separators() {
+        let schema = EventSchema {
+            separator: Some(','),
+            assignment: ':',
+            allow_unknown_fields: true,
+            ..EventSchema::new("tool", "TOOL")
+                .with_field(Field::required("n", FieldType::Integer))
+        };
+
+        let got = schema
+            .parse(&comment(";TOOL n:2,name:drill, flood"))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(got.integer("n"), Some(2));
+        assert_eq!(got.text("name"), Some("drill"));
+        assert_eq!(got.boolean("flood"), Some(true));
+    }
+
+    #[test]
+    fn bad_events_are_explained() {
+        let schema = schema();
+        let inputs = [
+            ("MARK", "the \"marker\" event needs a \"id\" field"),
+            (
+                "MARK id=1 colour=red",
+                "the \"marker\" event doesn't have a \"colour\" field",
+            ),
+            (
+                "MARK id=1.5",
+                "the \"id\" field of the \"marker\" event should be an \
+                 integer",
+            ),
+            (
+                "MARK id=1 final=maybe",
+                "the \"final\" field of the \"marker\" event should be true \
+                 or false",
+            ),
+            (
+                "MARK id=1 note=\"unterminated",
+                "unable to read the \"marker\" event's fields",
+            ),
+            (
+                "MARK id",
+                "the \"id\" field of the \"marker\" event should be an integer",
+            ),
+        ];
+
+        for &(src, should_be) in &inputs {
+            let err = schema.parse(&comment(src)).unwrap().unwrap_err();
+
+            assert_eq!(err.to_string(), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn events_follow_their_line() {
+        let schemas = EventSchemas::new().with(schema());
+        let src = "G1 X1 ; MARK id=1\nG1 X2 (MARK oops)\nG1 X3";
+
+        let got: Vec<_> = Parser::<_>::new(src, Nop)
+            .with_events(&schemas)
+            .map(|item| match item {
+                Item::Line(line) => format!("line {}", line.span().line),
+                Item::Event(event) => {
+                    format!("event {:?}", event.integer("id"))
+                },
+                Item::InvalidEvent(e) => format!("error on {}", e.span().line),
+            })
+            .collect();
+
+        assert_eq!(
+            got,
+            vec!["line 0", "event Some(1)", "line 1", "error on 1", "line 2"]
+        );
+    }
+}
@@ -0,0 +1,1715 @@
+//! Running parametric programs.
+//!
+//! The normal parser only understands literal numbers. The [`Executor`]
+//! walks through a program line-by-line, keeping a table of [`Parameters`]
+//! up to date and evaluating any [`Expression`]s so each line can be handed
+//! to the [`Interpreter`] as plain g-code.
+//!
+//! O-word [control flow][crate::control] (subroutines, conditionals and
+//! loops) and Fanuc-style `GOTO`s are followed as the program runs.
+//!
+//! # Debugging
+//!
+//! As well as being an [`Iterator`], the [`Executor`] can be used like a
+//! debugger. Execution can be paused at [`Breakpoint`]s, and everything
+//! (parameters, machine state, the call stack) can be inspected between
+//! steps.
+//!
+//! ```rust
+//! use gcode::{
+//!     dialect::Dialect,
+//!     executor::{Breakpoint, Executor, Stop},
+//!     expr::ParameterId,
+//! };
+//!
+//! let src = "#1 = 0\nO1 repeat [3]\n#1 = [#1 + 1]\nO1 endrepeat\nG0 X#1";
+//! let mut executor = Executor::new(src, Dialect::linuxcnc());
+//! executor.add_breakpoint(Breakpoint::Line(2));
+//!
+//! // pauses before executing the line
+//! assert_eq!(
+//!     executor.run(),
+//!     Ok(Stop::Breakpoint { breakpoint: Breakpoint::Line(2), line: 2 })
+//! );
+//! assert_eq!(executor.parameters().get(&ParameterId::Numbered(1)), Some(0.0));
+//!
+//! let step = executor.step().unwrap().unwrap();
+//! assert_eq!(step.assignments, vec![(ParameterId::Numbered(1), 1.0)]);
+//!
+//! executor.clear_breakpoints();
+//! assert_eq!(executor.run_to_line(4), Ok(Stop::ReachedLine(4)));
+//! assert_eq!(executor.parameters().get(&ParameterId::Numbered(1)), Some(3.0));
+//! ```
+//!
+//! # Budgets
+//!
+//! A program whose loops never terminate would keep the executor busy
+//! forever, so it gives up once a [`Budget`] is used up. By default, any
+//! single loop may run up to [`Budget::DEFAULT_MAX_ITERATIONS`] times and
+//! subroutines may be nested [`Budget::DEFAULT_MAX_CALL_DEPTH`] deep, but the
+//! total number of steps and the time spent can be limited as well.
+//!
+//! ```rust
+//! use gcode::{
+//!     dialect::Dialect,
+//!     executor::{Budget, Executor, ExecutionErrorKind, Limit},
+//! };
+//!
+//! let src = "#1 = 1\nO1 while [#1 GT 0]\nG0 X#1\nO1 endwhile";
+//! let budget = Budget {
+//!     max_iterations: Some(100),
+//!     ..Budget::default()
+//! };
+//! let mut executor = Executor::new(src, Dialect::linuxcnc()).with_budget(budget);
+//!
+//! let err = executor.find_map(Result::err).unwrap();
+//!
+//! match err.kind {
+//!     ExecutionErrorKind::BudgetExceeded(exceeded) => {
+//!         assert_eq!(exceeded.limit, Limit::Iterations(100));
+//!         // the loop's opening statement
+//!         assert_eq!(exceeded.loop_span.unwrap().line, 1);
+//!     },
+//!     other => panic!("unexpected error: {:?}", other),
+//! }
+//! assert!(executor.is_finished());
+//! ```
+//!
+//! # Rewinding
+//!
+//! The executor periodically saves a checkpoint of its state, so it can also
+//! step backwards. Going back to an earlier step restores the closest
+//! checkpoint and replays the lines in between, which means the
+//! [checkpoint interval][Executor::with_checkpoint_interval] trades memory
+//! for how quickly you can scrub backwards through a program.
+//!
+//! ```rust
+//! # use gcode::{dialect::Dialect, executor::Executor};
+//! let src = "G0 X1\nG0 X2\nG0 X3";
+//! let mut executor =
+//!     Executor::new(src, Dialect::linuxcnc()).with_checkpoint_interval(2);
+//!
+//! executor.by_ref().for_each(drop);
+//! assert_eq!(executor.state().position.x, 3.0);
+//!
+//! assert!(executor.step_back());
+//! assert_eq!(executor.state().position.x, 2.0);
+//! assert_eq!(executor.next_line(), 2);
+//!
+//! executor.seek(1);
+//! assert_eq!(executor.state().position.x, 1.0);
+//! ```
+//!
+//! ```rust
+//! use gcode::{dialect::Dialect, executor::Executor, expr::ParameterId};
+//!
+//! let src = "#<_depth> = 2\n#1 = [#<_depth> * 3]\nG1 X#1 Z-#<_depth> F100";
+//! let mut executor = Executor::new(src, Dialect::linuxcnc());
+//!
+//! let lines: Vec<_> = executor.by_ref().collect::<Result<_, _>>().unwrap();
+//!
+//! let g1 = &lines[2].line.gcodes()[0];
+//! assert_eq!(g1.value_for('X'), Some(6.0));
+//! assert_eq!(g1.value_for('Z'), Some(-2.0));
+//! assert_eq!(executor.parameters().get(&ParameterId::Numbered(1)), Some(6.0));
+//! ```
+
+use crate::{
+    control::{self, ControlErrorKind, ControlFlow, Goto, Keyword, Statement},
+    dialect::Dialect,
+    expr::{Cursor, Expression, ExpressionError, ParameterId, Parameters},
+    interpret::{Interpreter, MachineState, Motion},
+    Comment, GCode, Line, Mnemonic, Span, Word,
+};
+use core::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Instant,
+    vec::Vec,
+};
+
+/// Something which executes a parametric program one line at a time.
+#[derive(Debug, Clone)]
+pub struct Executor<'src> {
+    src: &'src str,
+    /// The byte offset of each line's start.
+    line_starts: Vec<usize>,
+    next_line: usize,
+    dialect: Dialect,
+    interpreter: Interpreter,
+    parameters: Parameters,
+    /// The last command word (e.g. `G1`), reused when a line only contains
+    /// arguments.
+    last_command: Option<(Mnemonic, f32)>,
+    control: ControlFlow,
+    /// Subroutine calls which haven't returned yet.
+    frames: Vec<Frame>,
+    /// Active `repeat` loops and the number of iterations left.
+    repeats: Vec<(usize, u32)>,
+    last_line: Option<usize>,
+    breakpoints: BTreeSet<Breakpoint>,
+    /// The line breakpoint execution last stopped at, which is passed over
+    /// when execution resumes.
+    stopped_at: Option<usize>,
+    /// The number of lines executed so far.
+    steps: usize,
+    checkpoint_interval: usize,
+    /// Saved states, sorted by step.
+    checkpoints: Vec<Checkpoint>,
+    /// Save a checkpoint before the next step, regardless of the interval.
+    force_checkpoint: bool,
+    budget: Budget,
+    /// When the first step was executed (or the first since restoring a
+    /// checkpoint), only recorded when there is a timeout.
+    started: Option<Instant>,
+    /// How many times each active loop (identified by the line it starts
+    /// on) has gone back to the start.
+    iterations: BTreeMap<usize, usize>,
+    /// The loop which most recently went back to the start.
+    current_loop: Option<usize>,
+    /// Used when exploring every path through a program.
+    decisions: Option<Decisions>,
+}
+
+/// The outcomes of data-dependent conditions, used by the
+/// [`coverage`][crate::coverage] module to steer execution down paths the
+/// program's parameters wouldn't normally take.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Decisions {
+    /// Outcomes to use instead of evaluating the condition, in order.
+    pub(crate) forced: Vec<bool>,
+    /// The line each decision was made on and its outcome.
+    pub(crate) made: Vec<(usize, bool)>,
+}
+
+impl<'src> Executor<'src> {
+    /// The number of steps between checkpoints, unless configured
+    /// otherwise.
+    pub const DEFAULT_CHECKPOINT_INTERVAL: usize = 256;
+
+    /// Create an [`Executor`] for some source text.
+    pub fn new(src: &'src str, dialect: Dialect) -> Self {
+        let line_starts = core::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .filter(|&start| start < src.len())
+            .collect();
+
+        let mut executor = Executor {
+            src,
+            line_starts,
+            next_line: 0,
+            dialect,
+            interpreter: Interpreter::new(dialect),
+            parameters: Parameters::new(),
+            last_command: None,
+            control: ControlFlow::new(src),
+            frames: Vec::new(),
+            repeats: Vec::new(),
+            last_line: None,
+            breakpoints: BTreeSet::new(),
+            stopped_at: None,
+            steps: 0,
+            checkpoint_interval: Executor::DEFAULT_CHECKPOINT_INTERVAL,
+            checkpoints: Vec::new(),
+            force_checkpoint: false,
+            budget: Budget::default(),
+            started: None,
+            iterations: BTreeMap::new(),
+            current_loop: None,
+            decisions: None,
+        };
+        executor.skip_blank_lines();
+
+        executor
+    }
+
+    /// Execute the next line, returning `None` once the program has
+    /// finished.
+    pub fn step(
+        &mut self,
+    ) -> Option<Result<ExecutedLine<'src>, ExecutionError>> {
+        if self.is_finished() {
+            return None;
+        }
+        self.stopped_at = None;
+
+        if self.force_checkpoint
+            || self.steps.is_multiple_of(self.checkpoint_interval)
+        {
+            self.save_checkpoint();
+        }
+
+        let line = self.next_line;
+        if let Err(e) = self.check_budget(line) {
+            self.halt();
+            return Some(Err(e));
+        }
+
+        self.next_line += 1;
+        self.last_line = Some(line);
+        self.steps += 1;
+
+        let result = self.execute(line);
+        if matches!(
+            result,
+            Err(ExecutionError {
+                kind: ExecutionErrorKind::BudgetExceeded(_),
+                ..
+            })
+        ) {
+            self.halt();
+        }
+        self.skip_blank_lines();
+
+        Some(result)
+    }
+
+    /// Limit how much work the executor will do before giving up.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Override the outcome of each data-dependent condition in turn, and
+    /// record every decision made.
+    pub(crate) fn with_decisions(mut self, forced: Vec<bool>) -> Self {
+        self.decisions = Some(Decisions {
+            forced,
+            made: Vec::new(),
+        });
+        self
+    }
+
+    pub(crate) fn decisions(&self) -> Option<&Decisions> {
+        self.decisions.as_ref()
+    }
+
+    /// Is a condition true?
+    ///
+    /// When exploring, conditions which depend on parameters can be forced
+    /// one way or the other, and those which can't be evaluated count as
+    /// false.
+    fn decide(
+        &mut self,
+        line: usize,
+        condition: &Expression,
+    ) -> Result<bool, ExecutionError> {
+        let value = condition.evaluate(&self.parameters);
+
+        match self.decisions.as_mut() {
+            Some(decisions) if !condition.is_constant() => {
+                let taken = match decisions.forced.get(decisions.made.len()) {
+                    Some(&forced) => forced,
+                    None => value.is_ok_and(|value| value != 0.0),
+                };
+                decisions.made.push((line, taken));
+                Ok(taken)
+            },
+            _ => value
+                .map(|value| value != 0.0)
+                .map_err(|e| ExecutionError::new(line, e)),
+        }
+    }
+
+    /// The limits on how much work the executor will do.
+    pub fn budget(&self) -> &Budget { &self.budget }
+
+    fn check_budget(&mut self, line: usize) -> Result<(), ExecutionError> {
+        let limit = match self.budget {
+            Budget {
+                max_steps: Some(max_steps),
+                ..
+            } if self.steps >= max_steps => Limit::Steps(max_steps),
+            Budget {
+                timeout: Some(timeout),
+                ..
+            } => {
+                let started = *self.started.get_or_insert_with(Instant::now);
+                if started.elapsed() <= timeout {
+                    return Ok(());
+                }
+                Limit::Timeout(timeout)
+            },
+            _ => return Ok(()),
+        };
+
+        Err(self.budget_exceeded(line, limit, self.current_loop))
+    }
+
+    /// Count another trip around the loop starting on `start`.
+    fn iterate(
+        &mut self,
+        line: usize,
+        start: usize,
+    ) -> Result<(), ExecutionError> {
+        self.current_loop = Some(start);
+        let iterations = self.iterations.entry(start).or_insert(0);
+        *iterations += 1;
+
+        match self.budget.max_iterations {
+            Some(max) if *iterations > max => Err(self.budget_exceeded(
+                line,
+                Limit::Iterations(max),
+                Some(start),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Forget about a loop which has finished.
+    fn exit_loop(&mut self, start: usize) {
+        let _ = self.iterations.remove(&start);
+        if self.current_loop == Some(start) {
+            self.current_loop = None;
+        }
+    }
+
+    fn budget_exceeded(
+        &self,
+        line: usize,
+        limit: Limit,
+        loop_start: Option<usize>,
+    ) -> ExecutionError {
+        let loop_span = loop_start.map(|start| {
+            let offset = self.line_starts[start];
+            let text = self.line_text(start);
+            Span::new(offset, offset + text.len(), start)
+        });
+
+        ExecutionError::new(line, BudgetExceeded { limit, loop_span })
+    }
+
+    /// Stop executing, so the program counts as finished.
+    fn halt(&mut self) { self.next_line = self.line_starts.len(); }
+
+    /// Save a checkpoint every `interval` steps (a smaller interval uses
+    /// more memory, but makes rewinding faster).
+    pub fn with_checkpoint_interval(mut self, interval: usize) -> Self {
+        self.checkpoint_interval = interval.max(1);
+        self
+    }
+
+    /// The number of steps between checkpoints.
+    pub fn checkpoint_interval(&self) -> usize { self.checkpoint_interval }
+
+    /// The number of lines which have been executed.
+    pub fn steps(&self) -> usize { self.steps }
+
+    /// Undo the most recent step, returning `false` if nothing has been
+    /// executed yet.
+    pub fn step_back(&mut self) -> bool {
+        if self.steps == 0 {
+            return false;
+        }
+
+        self.seek(self.steps - 1);
+        true
+    }
+
+    /// Go back to the start of the program.
+    pub fn rewind(&mut self) { self.seek(0); }
+
+    /// Move to the point where a particular number of steps have been
+    /// executed, going backwards or forwards as necessary.
+    ///
+    /// If the program finishes before then, this stops at the end.
+    pub fn seek(&mut self, step: usize) {
+        if step < self.steps {
+            let index = match self
+                .checkpoints
+                .binary_search_by_key(&step, |checkpoint| checkpoint.steps)
+            {
+                Ok(index) => index,
+                Err(index) => index - 1,
+            };
+            let checkpoint = self.checkpoints[index].clone();
+            self.restore(checkpoint);
+        }
+
+        while self.steps < step && self.step().is_some() {}
+    }
+
+    fn save_checkpoint(&mut self) {
+        self.force_checkpoint = false;
+
+        if let Err(index) = self
+            .checkpoints
+            .binary_search_by_key(&self.steps, |checkpoint| checkpoint.steps)
+        {
+            let checkpoint = Checkpoint {
+                steps: self.steps,
+                next_line: self.next_line,
+                last_line: self.last_line,
+                interpreter: self.interpreter,
+                parameters: self.parameters.clone(),
+                last_command: self.last_command,
+                frames: self.frames.clone(),
+                repeats: self.repeats.clone(),
+                iterations: self.iterations.clone(),
+                current_loop: self.current_loop,
+            };
+            self.checkpoints.insert(index, checkpoint);
+        }
+    }
+
+    fn restore(&mut self, checkpoint: Checkpoint) {
+        self.steps = checkpoint.steps;
+        self.next_line = checkpoint.next_line;
+        self.last_line = checkpoint.last_line;
+        self.interpreter = checkpoint.interpreter;
+        self.parameters = checkpoint.parameters;
+        self.last_command = checkpoint.last_command;
+        self.frames = checkpoint.frames;
+        self.repeats = checkpoint.repeats;
+        self.iterations = checkpoint.iterations;
+        self.current_loop = checkpoint.current_loop;
+        self.stopped_at = None;
+        self.started = None;
+    }
+
+    /// Keep executing until a [`Breakpoint`] is hit or the program
+    /// finishes.
+    ///
+    /// The line breakpoint execution last stopped at is passed over, so
+    /// calling `run()` again after stopping at a breakpoint continues on.
+    pub fn run(&mut self) -> Result<Stop, ExecutionError> {
+        self.run_until(None)
+    }
+
+    /// Keep executing until just before a particular (zero-based) line is
+    /// executed, stopping early if a [`Breakpoint`] is hit.
+    ///
+    /// If the line is never reached this runs to the end of the program.
+    pub fn run_to_line(&mut self, line: usize) -> Result<Stop, ExecutionError> {
+        self.run_until(Some(line))
+    }
+
+    fn run_until(
+        &mut self,
+        target: Option<usize>,
+    ) -> Result<Stop, ExecutionError> {
+        loop {
+            if self.is_finished() {
+                return Ok(Stop::Finished);
+            }
+
+            let line = self.next_line;
+            if target == Some(line) {
+                return Ok(Stop::ReachedLine(line));
+            }
+
+            let breakpoint = Breakpoint::Line(line);
+            if self.stopped_at != Some(line)
+                && self.breakpoints.contains(&breakpoint)
+            {
+                self.stopped_at = Some(line);
+                return Ok(Stop::Breakpoint { breakpoint, line });
+            }
+
+            let executed = match self.step() {
+                Some(result) => result?,
+                None => return Ok(Stop::Finished),
+            };
+
+            for (parameter, _) in executed.assignments {
+                let breakpoint = Breakpoint::ParameterWrite(parameter);
+                if self.breakpoints.contains(&breakpoint) {
+                    return Ok(Stop::Breakpoint { breakpoint, line });
+                }
+            }
+        }
+    }
+
+    /// Pause execution at a [`Breakpoint`].
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        let _ = self.breakpoints.insert(breakpoint);
+    }
+
+    /// Remove a [`Breakpoint`], returning whether it was set.
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        self.breakpoints.remove(breakpoint)
+    }
+
+    /// Remove every [`Breakpoint`].
+    pub fn clear_breakpoints(&mut self) { self.breakpoints.clear(); }
+
+    /// The [`Breakpoint`]s which are currently set.
+    pub fn breakpoints(&self) -> impl Iterator<Item = &Breakpoint> + '_ {
+        self.breakpoints.iter()
+    }
+
+    /// Has every line been executed?
+    pub fn is_finished(&self) -> bool {
+        self.next_line >= self.line_starts.len()
+    }
+
+    /// The current parameter values.
+    pub fn parameters(&self) -> &Parameters { &self.parameters }
+
+    /// Get mutable access to the parameters (e.g. to set inputs before
+    /// running).
+    ///
+    /// Checkpoints saved after this point are thrown away, seeing as they
+    /// wouldn't include the changes.
+    pub fn parameters_mut(&mut self) -> &mut Parameters {
+        let steps = self.steps;
+        self.checkpoints
+            .retain(|checkpoint| checkpoint.steps < steps);
+        self.force_checkpoint = true;
+
+        &mut self.parameters
+    }
+
+    /// The [`MachineState`] after the lines executed so far.
+    pub fn state(&self) -> &MachineState { self.interpreter.state() }
+
+    /// The (zero-based) number of the line which will be executed next.
+    pub fn next_line(&self) -> usize { self.next_line }
+
+    /// How the program's control statements fit together.
+    pub fn control_flow(&self) -> &ControlFlow { &self.control }
+
+    /// How many subroutine calls are currently active.
+    pub fn call_depth(&self) -> usize { self.frames.len() }
+
+    /// The (zero-based) lines of every subroutine call which hasn't
+    /// returned yet, outermost first.
+    pub fn call_stack(&self) -> impl Iterator<Item = usize> + '_ {
+        self.frames.iter().map(|frame| frame.return_line - 1)
+    }
+
+    /// The (zero-based) number of the line which was executed most
+    /// recently.
+    pub fn last_line(&self) -> Option<usize> { self.last_line }
+
+    /// The text of a (zero-based) line in the program.
+    pub fn source_line(&self, line: usize) -> Option<&'src str> {
+        if line < self.line_starts.len() {
+            Some(self.line_text(line))
+        } else {
+            None
+        }
+    }
+
+    fn skip_blank_lines(&mut self) {
+        while !self.is_finished()
+            && self.line_text(self.next_line).trim().is_empty()
+        {
+            self.next_line += 1;
+        }
+    }
+
+    /// The text of a particular line, without its newline.
+    fn line_text(&self, line: usize) -> &'src str {
+        let start = self.line_starts[line];
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .copied()
+            .unwrap_or(self.src.len());
+
+        self.src[start..end].trim_end_matches(['\n', '\r'])
+    }
+
+    /// Execute a single line.
+    fn execute(
+        &mut self,
+        line: usize,
+    ) -> Result<ExecutedLine<'src>, ExecutionError> {
+        let text = self.line_text(line);
+        let offset = self.line_starts[line];
+        let statement =
+            parse_statement(text).map_err(|e| ExecutionError::new(line, e))?;
+
+        let mut executed = Line::default();
+        let mut assignments = Vec::new();
+        let mut current: Option<GCode> = None;
+        let mut control = None;
+        let mut jump = None;
+
+        let span_of = |start: usize, end: usize| {
+            Span::new(offset + start, offset + end, line)
+        };
+
+        for item in statement {
+            match item {
+                Item::Comment { start, end } => {
+                    let comment = Comment {
+                        value: &text[start..end],
+                        span: span_of(start, end),
+                    };
+                    let _ = executed.push_comment(comment);
+                },
+                Item::Control(statement) => control = Some(statement),
+                Item::Goto { goto, .. } => jump = Some(goto),
+                Item::Assignment { parameter, value } => {
+                    let evaluate = || -> Result<_, ExpressionError> {
+                        let id = parameter
+                            .parameter_id(&self.parameters)?
+                            .ok_or(ExpressionError::UnexpectedEnd)?;
+                        Ok((id, value.evaluate(&self.parameters)?))
+                    };
+                    let assignment =
+                        evaluate().map_err(|e| ExecutionError::new(line, e))?;
+                    assignments.push(assignment);
+                },
+                Item::Word {
+                    letter,
+                    value,
+                    start,
+                    end,
+                } => {
+                    let value = self
+                        .word_value(letter, &value, &text[start..end])
+                        .map_err(|e| ExecutionError::new(line, e))?;
+                    let word = Word::new(letter, value, span_of(start, end));
+
+                    if letter.eq_ignore_ascii_case(&'N') {
+                        executed.set_line_number(word);
+                    } else if let Some(mnemonic) = Mnemonic::for_letter(letter)
+                    {
+                        if let Some(gcode) = current.take() {
+                            let _ = executed.push_gcode(gcode);
+                        }
+                        self.last_command = Some((mnemonic, value));
+                        current = Some(GCode::new(mnemonic, value, word.span));
+                    } else {
+                        if current.is_none() {
+                            if let Some((mnemonic, number)) = self.last_command
+                            {
+                                current = Some(GCode::new(
+                                    mnemonic,
+                                    number,
+                                    Span::PLACEHOLDER,
+                                ));
+                            }
+                        }
+                        // like the parser, arguments without a command are
+                        // dropped
+                        if let Some(gcode) = current.as_mut() {
+                            let _ = gcode.push_argument(word);
+                        }
+                    }
+                },
+            }
+        }
+
+        if let Some(gcode) = current.take() {
+            let _ = executed.push_gcode(gcode);
+        }
+
+        // parameters are only updated once the whole line has been read
+        for (id, value) in &assignments {
+            self.parameters.set(id, *value);
+        }
+
+        if let Some(statement) = &control {
+            self.run_control(line, statement)?;
+        }
+        if let Some(goto) = &jump {
+            self.jump(line, goto)?;
+        }
+
+        let motion = self.interpreter.process_line(&executed);
+
+        Ok(ExecutedLine {
+            line: executed,
+            assignments,
+            motion,
+            control,
+        })
+    }
+
+    /// Follow a control statement, updating the line to execute next.
+    fn run_control(
+        &mut self,
+        line: usize,
+        statement: &Statement,
+    ) -> Result<(), ExecutionError> {
+        if let Some(error) = self.control.error_at(line) {
+            return Err(ExecutionError::new(line, error.kind));
+        }
+
+        let related = |found: Option<usize>| {
+            found.ok_or_else(|| {
+                ExecutionError::new(line, ControlErrorKind::Unmatched)
+            })
+        };
+        let condition = |parameters: &Parameters| match statement.condition() {
+            Some(condition) => condition
+                .evaluate(parameters)
+                .map_err(|e| ExecutionError::new(line, e)),
+            None => Ok(0.0),
+        };
+
+        match statement.keyword {
+            // subroutine definitions are skipped unless they are called
+            Keyword::Sub => {
+                self.next_line = related(self.control.end_of(line))? + 1
+            },
+            Keyword::Call => self.call(line, statement)?,
+            Keyword::Return | Keyword::EndSub => {
+                self.return_from(line, statement)?
+            },
+            Keyword::If => self.branch(line)?,
+            // the previous branch was taken, so skip to the endif
+            Keyword::ElseIf | Keyword::Else => {
+                let start = related(self.control.conditional_of(line))?;
+                self.next_line = related(self.control.end_of(start))? + 1;
+            },
+            Keyword::EndIf | Keyword::Do => {},
+            Keyword::While if self.control.closes_do_loop(line) => {
+                let start = related(self.control.start_of(line))?;
+
+                if self.decide(line, &statement.arguments[0])? {
+                    self.iterate(line, start)?;
+                    self.next_line = start + 1;
+                } else {
+                    self.exit_loop(start);
+                }
+            },
+            Keyword::While => {
+                if !self.decide(line, &statement.arguments[0])? {
+                    self.exit_loop(line);
+                    self.next_line = related(self.control.end_of(line))? + 1;
+                }
+            },
+            Keyword::EndWhile => {
+                let start = related(self.control.start_of(line))?;
+                self.iterate(line, start)?;
+                self.next_line = start;
+            },
+            Keyword::Repeat => {
+                let count = libm::roundf(condition(&self.parameters)?);
+
+                if count >= 1.0 {
+                    self.repeats.push((line, count as u32));
+                } else {
+                    self.next_line = related(self.control.end_of(line))? + 1;
+                }
+            },
+            Keyword::EndRepeat => {
+                let start = related(self.control.start_of(line))?;
+
+                if let Some((repeat, remaining)) = self.repeats.last_mut() {
+                    if *repeat == start {
+                        *remaining -= 1;
+                        if *remaining > 0 {
+                            self.iterate(line, start)?;
+                            self.next_line = start + 1;
+                        } else {
+                            let _ = self.repeats.pop();
+                            self.exit_loop(start);
+                        }
+                    }
+                }
+            },
+            Keyword::Break => {
+                let start = related(self.control.enclosing(line))?;
+                let end = related(self.control.end_of(start))?;
+                self.repeats
+                    .retain(|&(repeat, _)| repeat < start || repeat > end);
+                self.exit_loop(start);
+                self.next_line = end + 1;
+            },
+            Keyword::Continue => {
+                let start = related(self.control.enclosing(line))?;
+                // jump to whichever statement checks the loop condition
+                self.next_line = match self.control.statement(start) {
+                    Some(s) if s.keyword == Keyword::While => start,
+                    _ => related(self.control.end_of(start))?,
+                };
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Follow a `GOTO`, using the jump table when its target is constant.
+    fn jump(&mut self, line: usize, goto: &Goto) -> Result<(), ExecutionError> {
+        if let Some(condition) = &goto.condition {
+            if !self.decide(line, condition)? {
+                return Ok(());
+            }
+        }
+
+        let target = match self.control.jump_target(line) {
+            Some(target) => target,
+            None => {
+                let number = goto
+                    .target
+                    .evaluate(&self.parameters)
+                    .map_err(|e| ExecutionError::new(line, e))?;
+                let number = libm::roundf(number).max(0.0) as u32;
+
+                self.control
+                    .find_label(number)
+                    .map_err(|e| ExecutionError::new(line, e))?
+            },
+        };
+
+        // jumping backwards is the only way to loop with a GOTO
+        if target <= line {
+            self.iterate(line, target)?;
+        }
+        self.next_line = target;
+
+        Ok(())
+    }
+
+    /// Find the first branch of a conditional whose condition is true.
+    fn branch(&mut self, line: usize) -> Result<(), ExecutionError> {
+        let mut current = line;
+
+        loop {
+            let statement =
+                self.control.statement(current).ok_or_else(|| {
+                    ExecutionError::new(line, ControlErrorKind::Unmatched)
+                })?;
+
+            let taken = match statement.condition().cloned() {
+                Some(condition) => self.decide(current, &condition)?,
+                // else and endif
+                None => true,
+            };
+
+            if taken {
+                self.next_line = current + 1;
+                return Ok(());
+            }
+
+            current = self.control.next_branch(current).ok_or_else(|| {
+                ExecutionError::new(line, ControlErrorKind::Unmatched)
+            })?;
+        }
+    }
+
+    fn call(
+        &mut self,
+        line: usize,
+        statement: &Statement,
+    ) -> Result<(), ExecutionError> {
+        let sub =
+            self.control.subroutine(&statement.label).ok_or_else(|| {
+                ExecutionError::new(line, ControlErrorKind::UndefinedSubroutine)
+            })?;
+
+        match self.budget.max_call_depth {
+            Some(max) if self.frames.len() >= max => {
+                return Err(self.budget_exceeded(
+                    line,
+                    Limit::CallDepth(max),
+                    Some(line),
+                ));
+            },
+            _ => {},
+        }
+        let arguments = statement
+            .arguments
+            .iter()
+            .map(|argument| argument.evaluate(&self.parameters))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ExecutionError::new(line, e))?;
+
+        // #1 to #30 hold the subroutine's arguments
+        let saved = (1..=Frame::ARGUMENTS)
+            .map(|number| {
+                self.parameters.remove(&ParameterId::Numbered(number))
+            })
+            .collect();
+        for (number, value) in (1..=Frame::ARGUMENTS).zip(arguments) {
+            self.parameters.set(&ParameterId::Numbered(number), value);
+        }
+        self.parameters.push_scope();
+
+        self.frames.push(Frame {
+            return_line: line + 1,
+            arguments: saved,
+            repeats: self.repeats.len(),
+        });
+        self.next_line = sub + 1;
+
+        Ok(())
+    }
+
+    fn return_from(
+        &mut self,
+        line: usize,
+        statement: &Statement,
+    ) -> Result<(), ExecutionError> {
+        let value = match statement.arguments.first() {
+            Some(value) => Some(
+                value
+                    .evaluate(&self.parameters)
+                    .map_err(|e| ExecutionError::new(line, e))?,
+            ),
+            None => None,
+        };
+
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            // not inside a call, so there's nowhere to return to
+            None => return Ok(()),
+        };
+
+        self.parameters.pop_scope();
+        for (number, saved) in (1..).zip(frame.arguments) {
+            let id = ParameterId::Numbered(number);
+            match saved {
+                Some(value) => self.parameters.set(&id, value),
+                None => {
+                    let _ = self.parameters.remove(&id);
+                },
+            }
+        }
+        self.repeats.truncate(frame.repeats);
+
+        let returned = ParameterId::named("_value_returned");
+        match value {
+            Some(value) => {
+                self.parameters.set(&ParameterId::named("_value"), value);
+                self.parameters.set(&returned, 1.0);
+            },
+            None => self.parameters.set(&returned, 0.0),
+        }
+
+        self.next_line = frame.return_line;
+        Ok(())
+    }
+
+    fn word_value(
+        &self,
+        letter: char,
+        value: &Expression,
+        text: &str,
+    ) -> Result<f32, ExpressionError> {
+        let number = value.evaluate(&self.parameters)?;
+
+        // literal dimensions may use an implied decimal point
+        match (value, self.dialect.least_input_increment) {
+            (Expression::Number(_), Some(increment))
+                if !text.contains('.')
+                    && self.dialect.is_dimension_letter(letter) =>
+            {
+                Ok(number * increment)
+            },
+            _ => Ok(number),
+        }
+    }
+}
+
+impl<'src> Iterator for Executor<'src> {
+    type Item = Result<ExecutedLine<'src>, ExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> { self.step() }
+}
+
+/// Somewhere [`Executor::run()`] should pause.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Breakpoint {
+    /// Pause before executing a (zero-based) line.
+    Line(usize),
+    /// Pause after a line which assigns to a parameter.
+    ParameterWrite(ParameterId),
+}
+
+/// Why [`Executor::run()`] or [`Executor::run_to_line()`] stopped.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Stop {
+    /// A [`Breakpoint`] was hit.
+    Breakpoint {
+        /// The breakpoint.
+        breakpoint: Breakpoint,
+        /// The line about to be executed (for a [`Breakpoint::Line`]) or
+        /// the line which was just executed (for a
+        /// [`Breakpoint::ParameterWrite`]).
+        line: usize,
+    },
+    /// The line passed to [`Executor::run_to_line()`] is next.
+    ReachedLine(usize),
+    /// The program has finished.
+    Finished,
+}
+
+/// The result of executing a single line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutedLine<'src> {
+    /// The line, with every expression replaced by its value.
+    pub line: Line<'src>,
+    /// Any parameters which were set.
+    pub assignments: Vec<(ParameterId, f32)>,
+    /// The motion caused by this line, if any.
+    pub motion: Option<Motion>,
+    /// The control statement on this line, if any.
+    pub control: Option<Statement>,
+}
+
+/// A subroutine call which hasn't returned yet.
+#[derive(Debug, Clone, PartialEq)]
+struct Frame {
+    /// The line to continue from once the subroutine returns.
+    return_line: usize,
+    /// The caller's values for `#1` to `#30`.
+    arguments: Vec<Option<f32>>,
+    /// The number of active `repeat` loops when the call was made.
+    repeats: usize,
+}
+
+/// A copy of the executor's state, used when stepping backwards.
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    steps: usize,
+    next_line: usize,
+    last_line: Option<usize>,
+    interpreter: Interpreter,
+    parameters: Parameters,
+    last_command: Option<(Mnemonic, f32)>,
+    frames: Vec<Frame>,
+    repeats: Vec<(usize, u32)>,
+    iterations: BTreeMap<usize, usize>,
+    current_loop: Option<usize>,
+}
+
+impl Frame {
+    /// The number of parameters used to pass arguments to a subroutine.
+    const ARGUMENTS: u32 = 30;
+}
+
+/// An error encountered while executing a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionError {
+    /// The (zero-based) line the error occurred on.
+    pub line: usize,
+    /// What went wrong.
+    pub kind: ExecutionErrorKind,
+}
+
+impl ExecutionError {
+    fn new<K: Into<ExecutionErrorKind>>(line: usize, kind: K) -> Self {
+        ExecutionError {
+            line,
+            kind: kind.into(),
+        }
+    }
+}
+
+/// The different kinds of [`ExecutionError`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutionErrorKind {
+    /// An expression couldn't be parsed or evaluated.
+    Expression(ExpressionError),
+    /// A control statement doesn't fit into the program's structure.
+    Control(ControlErrorKind),
+    /// The executor's [`Budget`] was used up.
+    BudgetExceeded(BudgetExceeded),
+}
+
+impl From<ExpressionError> for ExecutionErrorKind {
+    fn from(e: ExpressionError) -> Self { ExecutionErrorKind::Expression(e) }
+}
+
+impl From<ControlErrorKind> for ExecutionErrorKind {
+    fn from(e: ControlErrorKind) -> Self { ExecutionErrorKind::Control(e) }
+}
+
+impl From<BudgetExceeded> for ExecutionErrorKind {
+    fn from(e: BudgetExceeded) -> Self { ExecutionErrorKind::BudgetExceeded(e) }
+}
+
+impl Display for ExecutionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line + 1)?;
+
+        match &self.kind {
+            ExecutionErrorKind::Expression(e) => e.fmt(f),
+            ExecutionErrorKind::Control(e) => e.fmt(f),
+            ExecutionErrorKind::BudgetExceeded(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+/// Limits on how much work an [`Executor`] will do, so programs which
+/// never finish can't hang it.
+///
+/// Each limit is turned off by setting it to `None`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Budget {
+    /// The most lines to execute.
+    pub max_steps: Option<usize>,
+    /// The most times any one loop may go back to the start before it
+    /// finishes.
+    ///
+    /// Loops made with a `GOTO` that jumps backwards never finish, so every
+    /// trip around them is counted.
+    pub max_iterations: Option<usize>,
+    /// The most subroutine calls which may be in progress at once, so a
+    /// subroutine which calls itself forever can't use up all the memory.
+    pub max_call_depth: Option<usize>,
+    /// How long to keep going, measured from the first step.
+    pub timeout: Option<Duration>,
+}
+
+impl Budget {
+    /// How deeply subroutines can be nested, unless configured otherwise.
+    pub const DEFAULT_MAX_CALL_DEPTH: usize = 1_000;
+    /// The most times a loop can run, unless configured otherwise.
+    pub const DEFAULT_MAX_ITERATIONS: usize = 1_000_000;
+
+    /// A [`Budget`] without any limits.
+    pub const fn unlimited() -> Self {
+        Budget {
+            max_steps: None,
+            max_iterations: None,
+            max_call_depth: None,
+            timeout: None,
+        }
+    }
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget {
+            max_iterations: Some(Budget::DEFAULT_MAX_ITERATIONS),
+            max_call_depth: Some(Budget::DEFAULT_MAX_CALL_DEPTH),
+            ..Budget::unlimited()
+        }
+    }
+}
+
+/// Which part of a [`Budget`] was used up.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Limit {
+    /// [`Budget::max_steps`].
+    Steps(usize),
+    /// [`Budget::max_iterations`].
+    Iterations(usize),
+    /// [`Budget::max_call_depth`].
+    CallDepth(usize),
+    /// [`Budget::timeout`].
+    Timeout(Duration),
+}
+
+/// The error returned when an [`Executor`] runs out of [`Budget`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BudgetExceeded {
+    /// The limit which was reached.
+    pub limit: Limit,
+    /// The statement which starts the offending loop (for
+    /// [`Limit::Iterations`]), the call which went too deep (for
+    /// [`Limit::CallDepth`]), or the loop which was running at the time.
+    pub loop_span: Option<Span>,
+}
+
+impl Display for BudgetExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.limit {
+            Limit::Steps(max) => {
+                write!(f, "the program ran for more than {} lines", max)?
+            },
+            Limit::Iterations(max) => {
+                write!(f, "a loop went around more than {} times", max)?
+            },
+            Limit::CallDepth(max) => {
+                write!(f, "subroutines were nested more than {} deep", max)?
+            },
+            Limit::Timeout(timeout) => {
+                write!(f, "the program ran for longer than {:?}", timeout)?
+            },
+        }
+
+        let statement = match self.limit {
+            Limit::CallDepth(_) => "call",
+            _ => "loop",
+        };
+
+        match self.loop_span {
+            Some(span) => {
+                write!(f, " (in the {} on line {})", statement, span.line + 1)
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+/// Something found on a line, with byte offsets relative to the line's
+/// start.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Item {
+    Comment {
+        start: usize,
+        end: usize,
+    },
+    Assignment {
+        parameter: Expression,
+        value: Expression,
+    },
+    Word {
+        letter: char,
+        value: Expression,
+        start: usize,
+        end: usize,
+    },
+    Control(Statement),
+    /// A `GOTO`, and where its target was written.
+    Goto {
+        goto: Goto,
+        start: usize,
+        end: usize,
+    },
+}
+
+/// Break a line into its words, comments, parameter assignments and control
+/// statements.
+pub(crate) fn parse_statement(
+    text: &str,
+) -> Result<Vec<Item>, ExpressionError> {
+    let mut cursor = Cursor::new(text);
+    let mut items = Vec::new();
+
+    loop {
+        cursor.skip_whitespace();
+        let start = cursor.position();
+
+        match cursor.peek() {
+            None => break,
+            Some('(') => {
+                let end = match cursor.rest().find(')') {
+                    Some(close) => start + close + 1,
+                    None => text.len(),
+                };
+                items.push(Item::Comment { start, end });
+                cursor.seek(end);
+            },
+            Some(';') => {
+                items.push(Item::Comment {
+                    start,
+                    end: text.len(),
+                });
+                break;
+            },
+            // tape markers, block delete and checksums don't affect the
+            // program's meaning
+            Some('%') | Some('/') => {
+                let _ = cursor.bump();
+            },
+            Some('*') => break,
+            Some('#') => {
+                let _ = cursor.bump();
+                let parameter = cursor.parameter()?;
+                if !cursor.eat("=") {
+                    cursor.skip_whitespace();
+                    return Err(cursor.unexpected());
+                }
+                let value = cursor.real_value()?;
+                items.push(Item::Assignment { parameter, value });
+            },
+            Some(letter) if letter.is_ascii_alphabetic() => {
+                let _ = cursor.bump();
+
+                if letter.eq_ignore_ascii_case(&'O') {
+                    if let Some(statement) = control::parse(&mut cursor)? {
+                        items.push(Item::Control(statement));
+                        continue;
+                    }
+                    cursor.seek(start + 1);
+                }
+
+                if let Some((goto, target)) =
+                    control::parse_goto(&mut cursor, letter)?
+                {
+                    items.push(Item::Goto {
+                        goto,
+                        start: target.start,
+                        end: target.end,
+                    });
+                    continue;
+                }
+
+                let value = cursor.real_value()?;
+                items.push(Item::Word {
+                    letter,
+                    value,
+                    start,
+                    end: cursor.position(),
+                });
+            },
+            Some(_) => return Err(cursor.unexpected()),
+        }
+    }
+
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execute(src: &str) -> Vec<ExecutedLine<'_>> {
+        Executor::new(src, Dialect::linuxcnc())
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn assignments_take_effect_after_the_line() {
+        let got = execute("#1 = 1\n#1 = 2 G1 X#1");
+
+        assert_eq!(got[1].line.gcodes()[0].value_for('X'), Some(1.0));
+        assert_eq!(got[1].assignments, vec![(ParameterId::Numbered(1), 2.0)]);
+    }
+
+    #[test]
+    fn modal_commands_carry_over() {
+        let got = execute("G1 X1 F100\nX[1 + 1]");
+
+        assert_eq!(got[1].line.gcodes()[0].number, 1.0);
+        assert!(got[1].motion.is_some());
+        assert_eq!(got[1].line.span().line, 1);
+    }
+
+    #[test]
+    fn comments_and_line_numbers_are_kept() {
+        let src = "G0 X0\nN10 G1 X#<_x> (move) ; done";
+        let mut executor = Executor::new(src, Dialect::linuxcnc());
+        executor
+            .parameters_mut()
+            .set(&ParameterId::named("_x"), 3.0);
+
+        let got: Vec<_> = executor.map(Result::unwrap).collect();
+
+        let line = &got[1].line;
+        assert_eq!(line.line_number().unwrap().number(), Some(10.0));
+        let comments: Vec<_> = line
+            .comments()
+            .iter()
+            .map(|comment| comment.value)
+            .collect();
+        assert_eq!(comments, vec!["(move)", "; done"]);
+        assert_eq!(line.comments()[0].span.get_text(src), Some("(move)"));
+    }
+
+    #[test]
+    fn errors_say_which_line_they_are_on() {
+        let mut executor =
+            Executor::new("G0 X0\nG1 X#<undefined>", Dialect::linuxcnc());
+
+        assert!(executor.next().unwrap().is_ok());
+        let err = executor.next().unwrap().unwrap_err();
+
+        assert_eq!(err.line, 1);
+        assert_eq!(
+            err.kind,
+            ExecutionErrorKind::Expression(
+                ExpressionError::UndefinedParameter("undefined".into())
+            )
+        );
+        assert!(executor.next().is_none());
+    }
+
+    #[test]
+    fn implied_decimals_only_apply_to_literals() {
+        let mut executor =
+            Executor::new("#1 = 5\nG1 X100 Y#1", Dialect::fanuc());
+
+        let got: Vec<_> = executor.by_ref().map(Result::unwrap).collect();
+
+        let g1 = &got[1].line.gcodes()[0];
+        assert_eq!(g1.value_for('X'), Some(0.1));
+        assert_eq!(g1.value_for('Y'), Some(5.0));
+    }
+
+    fn x_values(lines: &[ExecutedLine<'_>]) -> Vec<f32> {
+        lines
+            .iter()
+            .flat_map(|executed| executed.line.gcodes())
+            .filter_map(|gcode| gcode.value_for('X'))
+            .collect()
+    }
+
+    #[test]
+    fn subroutines_get_their_own_arguments() {
+        let src =
+            "O<double> sub\nG0 X#1\nO<double> return [#1 * 2]\nO<double> \
+                   endsub\n#1 = 7\nO<double> call [3]\nG0 X#<_value>\nG0 X#1";
+
+        let got = execute(src);
+
+        assert_eq!(x_values(&got), vec![3.0, 6.0, 7.0]);
+    }
+
+    #[test]
+    fn conditionals_take_the_first_true_branch() {
+        let src = "#1 = 2\nO1 if [#1 EQ 1]\nG0 X1\nO1 elseif [#1 EQ 2]\nG0 \
+                   X2\nO1 else\nG0 X3\nO1 endif\nG0 X4";
+
+        let got = execute(src);
+
+        assert_eq!(x_values(&got), vec![2.0, 4.0]);
+    }
+
+    #[test]
+    fn loops() {
+        let src = "#1 = 0\nO1 while [#1 LT 3]\nG0 X#1\n#1 = [#1 + 1]\nO1 \
+                   endwhile\nO2 repeat [2]\nG0 X10\nO2 endrepeat\nO3 do\nO4 if \
+                   [#1 GE 5]\nO3 break\nO4 endif\n#1 = [#1 + 1]\nO3 while [1]\nG0 \
+                   X#1";
+
+        let got = execute(src);
+
+        assert_eq!(x_values(&got), vec![0.0, 1.0, 2.0, 10.0, 10.0, 5.0]);
+    }
+
+    #[test]
+    fn break_on_parameter_writes() {
+        let src =
+            "#1 = 1\n\nO<s> sub\n#<_out> = #1\nO<s> endsub\nO<s> call [2]\nM2";
+        let mut executor = Executor::new(src, Dialect::linuxcnc());
+        let watched = ParameterId::named("_out");
+        executor.add_breakpoint(Breakpoint::ParameterWrite(watched.clone()));
+
+        let got = executor.run().unwrap();
+
+        assert_eq!(
+            got,
+            Stop::Breakpoint {
+                breakpoint: Breakpoint::ParameterWrite(watched),
+                line: 3
+            }
+        );
+        assert_eq!(executor.call_stack().collect::<Vec<_>>(), vec![5]);
+        assert_eq!(executor.last_line(), Some(3));
+        assert_eq!(executor.next_line(), 4);
+        assert_eq!(executor.run(), Ok(Stop::Finished));
+        assert!(executor.is_finished());
+    }
+
+    #[test]
+    fn line_breakpoints_are_hit_every_time() {
+        let src = "O1 repeat [2]\nG0 X1\nO1 endrepeat";
+        let mut executor = Executor::new(src, Dialect::linuxcnc());
+        executor.add_breakpoint(Breakpoint::Line(1));
+        let hit = Stop::Breakpoint {
+            breakpoint: Breakpoint::Line(1),
+            line: 1,
+        };
+
+        assert_eq!(executor.run(), Ok(hit.clone()));
+        assert_eq!(executor.run(), Ok(hit));
+        assert_eq!(executor.run(), Ok(Stop::Finished));
+    }
+
+    #[test]
+    fn breakpoints_on_the_first_line_are_hit() {
+        let src = "G0 X1\nG0 X2";
+        let mut executor = Executor::new(src, Dialect::linuxcnc());
+        executor.add_breakpoint(Breakpoint::Line(0));
+        let hit = Stop::Breakpoint {
+            breakpoint: Breakpoint::Line(0),
+            line: 0,
+        };
+
+        assert_eq!(executor.run(), Ok(hit.clone()));
+        assert_eq!(executor.run(), Ok(Stop::Finished));
+
+        executor.rewind();
+        assert_eq!(executor.run(), Ok(hit));
+    }
+
+    #[test]
+    fn rewinding_replays_from_the_nearest_checkpoint() {
+        let src = "#1 = 0\nO1 while [#1 LT 10]\n#1 = [#1 + 1]\nO1 endwhile";
+        let mut executor =
+            Executor::new(src, Dialect::linuxcnc()).with_checkpoint_interval(4);
+        let counter = |executor: &Executor<'_>| {
+            executor.parameters().get(&ParameterId::Numbered(1))
+        };
+
+        executor.by_ref().for_each(drop);
+        let total = executor.steps();
+        assert_eq!(counter(&executor), Some(10.0));
+
+        executor.seek(7);
+        assert_eq!(executor.steps(), 7);
+        assert_eq!(counter(&executor), Some(2.0));
+
+        executor.rewind();
+        assert_eq!(counter(&executor), None);
+        assert!(!executor.step_back());
+
+        executor.seek(usize::MAX);
+        assert_eq!(executor.steps(), total);
+        assert!(executor.is_finished());
+    }
+
+    #[test]
+    fn editing_parameters_invalidates_later_checkpoints() {
+        let src = "G0 X#1\nG0 X#1\nG0 X#1";
+        let mut executor =
+            Executor::new(src, Dialect::linuxcnc()).with_checkpoint_interval(1);
+        executor.by_ref().for_each(drop);
+
+        executor.seek(1);
+        executor
+            .parameters_mut()
+            .set(&ParameterId::Numbered(1), 5.0);
+        executor.by_ref().for_each(drop);
+        assert_eq!(executor.state().position.x, 5.0);
+
+        executor.seek(2);
+        assert_eq!(executor.state().position.x, 5.0);
+        executor.seek(1);
+        assert_eq!(executor.state().position.x, 0.0);
+    }
+
+    #[test]
+    fn structural_errors_are_reported_when_reached() {
+        let mut executor =
+            Executor::new("G0 X1\nO1 endif", Dialect::linuxcnc());
+
+        assert!(executor.next().unwrap().is_ok());
+        let err = executor.next().unwrap().unwrap_err();
+
+        assert_eq!(err.line, 1);
+        assert_eq!(
+            err.kind,
+            ExecutionErrorKind::Control(ControlErrorKind::Unmatched)
+        );
+    }
+
+    #[test]
+    fn follow_gotos() {
+        let src = "#1 = 0\nN10 #1 = [#1 + 1]\nIF [#1 LT 3] GOTO 10\n#2 = 20\n\
+                   GOTO #2\nG0 X99\nN20 G0 X#1";
+        let mut executor = Executor::new(src, Dialect::fanuc());
+
+        executor.by_ref().for_each(|line| drop(line.unwrap()));
+
+        assert_eq!(executor.state().position.x, 3.0);
+        assert_eq!(executor.steps(), 10);
+    }
+
+    #[test]
+    fn computed_gotos_need_a_unique_label() {
+        let src = "#1 = 5\nGOTO #1\nN5\nN5";
+        let mut executor = Executor::new(src, Dialect::fanuc());
+
+        let err = executor.find_map(Result::err).unwrap();
+
+        assert_eq!(err.line, 1);
+        assert_eq!(
+            err.kind,
+            ExecutionErrorKind::Control(ControlErrorKind::DuplicateLabel(5))
+        );
+    }
+
+    #[test]
+    fn budgets_stop_runaway_programs() {
+        let src = "N1 G0 X1\nGOTO 1";
+        let budget = |budget| {
+            let mut executor = Executor::new(src, Dialect::fanuc())
+                .with_budget(Budget {
+                    timeout: None,
+                    ..budget
+                });
+            let err = executor.find_map(Result::err).unwrap();
+            assert!(executor.is_finished());
+
+            match err.kind {
+                ExecutionErrorKind::BudgetExceeded(e) => (executor.steps(), e),
+                other => panic!("unexpected error: {:?}", other),
+            }
+        };
+
+        let (steps, exceeded) = budget(Budget {
+            max_steps: Some(25),
+            ..Budget::unlimited()
+        });
+        assert_eq!(steps, 25);
+        assert_eq!(exceeded.limit, Limit::Steps(25));
+        assert_eq!(exceeded.loop_span, Some(Span::new(0, 8, 0)));
+
+        let (steps, exceeded) = budget(Budget {
+            max_iterations: Some(3),
+            ..Budget::unlimited()
+        });
+        assert_eq!(steps, 8);
+        assert_eq!(exceeded.limit, Limit::Iterations(3));
+        assert_eq!(
+            exceeded.to_string(),
+            "a loop went around more than 3 times (in the loop on line 1)"
+        );
+    }
+
+    #[test]
+    fn runaway_recursion_is_stopped() {
+        let src = "O100 sub\nO100 call\nO100 endsub\nO100 call";
+        let mut executor = Executor::new(src, Dialect::linuxcnc());
+
+        let err = executor.find_map(Result::err).unwrap();
+
+        assert!(executor.is_finished());
+        match err.kind {
+            ExecutionErrorKind::BudgetExceeded(exceeded) => {
+                let max = Budget::DEFAULT_MAX_CALL_DEPTH;
+                assert_eq!(exceeded.limit, Limit::CallDepth(max));
+                assert_eq!(exceeded.loop_span, Some(Span::new(9, 18, 1)));
+                assert_eq!(
+                    exceeded.to_string(),
+                    "subroutines were nested more than 1000 deep (in the \
+                     call on line 2)"
+                );
+            },
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn the_clock_is_only_read_for_timeouts() {
+        let src = "G0 X1\nG0 X2";
+
+        let mut executor = Executor::new(src, Dialect::linuxcnc())
+            .with_budget(Budget::unlimited());
+        assert_eq!(executor.run(), Ok(Stop::Finished));
+        assert!(executor.started.is_none());
+
+        let mut executor =
+            Executor::new(src, Dialect::linuxcnc()).with_budget(Budget {
+                timeout: Some(Duration::from_secs(60)),
+                ..Budget::unlimited()
+            });
+        assert_eq!(executor.run(), Ok(Stop::Finished));
+        assert!(executor.started.is_some());
+
+        executor.rewind();
+        assert!(executor.started.is_none());
+    }
+
+    #[test]
+    fn loops_which_finish_start_counting_again() {
+        let src = "O1 repeat [2]\nO2 repeat [3]\nG0 X1\nO2 endrepeat\n\
+                   O1 endrepeat";
+        let budget = Budget {
+            max_iterations: Some(2),
+            ..Budget::default()
+        };
+        let executor =
+            Executor::new(src, Dialect::linuxcnc()).with_budget(budget);
+
+        assert!(executor.collect::<Result<Vec<_>, _>>().is_ok());
+    }
+}
@@ -0,0 +1,1324 @@
+//! RS-274/NGC parameters and expressions.
+//!
+//! Parametric programs (e.g. LinuxCNC's) can store values in *parameters*
+//! and compute a word's value from an *expression* instead of writing a
+//! literal number.
+//!
+//! - Numbered parameters are written as `#100`, and can be indirect (`##1` or
+//!   `#[#1 + 2]`)
+//! - Named parameters are written as `#<name>`, with names starting with an
+//!   underscore (`#<_name>`) being global and everything else local to the
+//!   current subroutine
+//! - Expressions are wrapped in square brackets (`[1 + #2 * 3]`)
+//! - Functions take a bracketed argument (`SIN[30]`), with angles in degrees
+//!
+//! ```rust
+//! use gcode::expr::{Expression, ParameterId, Parameters};
+//!
+//! let mut parameters = Parameters::new();
+//! parameters.set(&ParameterId::Numbered(1), 2.0);
+//! parameters.set(&ParameterId::named("_scale"), 10.0);
+//!
+//! let expr = Expression::parse("[#1 * #<_SCALE> + 0.5]").unwrap();
+//!
+//! assert_eq!(expr.evaluate(&parameters), Ok(20.5));
+//! ```
+//!
+//! When a [`Dialect`] has [`expressions`][Dialect::expressions] turned on,
+//! the [`Parser`][crate::Parser] hands each expression it finds to its
+//! [`Callbacks`], and an [`Evaluator`] can be used to work out their values
+//! as the program is parsed.
+
+use crate::{Callbacks, Comment, Mnemonic, Nop, Span, Word};
+use core::fmt::{self, Display, Formatter};
+use std::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+
+#[allow(unused_imports)] // rustdoc links
+use crate::dialect::Dialect;
+
+/// A value which needs to be evaluated.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Expression {
+    /// A literal number.
+    Number(f32),
+    /// A numbered parameter, where the number may itself be an expression
+    /// (e.g. `#3` or `##3`).
+    Parameter(Box<Expression>),
+    /// A named parameter (e.g. `#<_feed>`), with its name normalized by
+    /// [`ParameterId::named()`].
+    NamedParameter(String),
+    /// An operator applied to a single value.
+    Unary(UnaryOperator, Box<Expression>),
+    /// An operator applied to two values.
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    /// A function call (e.g. `SQRT[#1]`).
+    Function(Function, Box<Expression>),
+    /// The two-argument arctangent, `ATAN[y]/[x]`, in degrees.
+    Atan(Box<Expression>, Box<Expression>),
+    /// `EXISTS[#<name>]`, which is `1.0` if the parameter has been set and
+    /// `0.0` otherwise.
+    Exists(Box<Expression>),
+}
+
+/// Operators which take a single value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum UnaryOperator {
+    /// `-x`
+    Negate,
+}
+
+/// Operators which take two values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+#[allow(missing_docs)]
+pub enum BinaryOperator {
+    Power,
+    Multiply,
+    Divide,
+    Modulo,
+    Add,
+    Subtract,
+    Equal,
+    NotEqual,
+    GreaterThan,
+    GreaterOrEqual,
+    LessThan,
+    LessOrEqual,
+    And,
+    Or,
+    ExclusiveOr,
+}
+
+/// Functions which take a single value.
+///
+/// Trigonometric functions work in degrees, as per the RS-274/NGC spec.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Function {
+    /// The absolute value.
+    Abs,
+    /// The inverse cosine, in degrees.
+    Acos,
+    /// The inverse sine, in degrees.
+    Asin,
+    /// The cosine of an angle in degrees.
+    Cos,
+    /// `e` raised to a power.
+    Exp,
+    /// Round towards negative infinity.
+    Fix,
+    /// Round towards positive infinity.
+    Fup,
+    /// The natural logarithm.
+    Ln,
+    /// Round to the nearest integer.
+    Round,
+    /// The sine of an angle in degrees.
+    Sin,
+    /// The square root.
+    Sqrt,
+    /// The tangent of an angle in degrees.
+    Tan,
+}
+
+impl Function {
+    const NAMES: [(&'static str, Function); 12] = [
+        ("ABS", Function::Abs),
+        ("ACOS", Function::Acos),
+        ("ASIN", Function::Asin),
+        ("COS", Function::Cos),
+        ("EXP", Function::Exp),
+        ("FIX", Function::Fix),
+        ("FUP", Function::Fup),
+        ("LN", Function::Ln),
+        ("ROUND", Function::Round),
+        ("SIN", Function::Sin),
+        ("SQRT", Function::Sqrt),
+        ("TAN", Function::Tan),
+    ];
+
+    /// Look up a function by name (ignoring case).
+    pub fn from_name(name: &str) -> Option<Function> {
+        Function::NAMES
+            .iter()
+            .find(|(candidate, _)| candidate.eq_ignore_ascii_case(name))
+            .map(|&(_, function)| function)
+    }
+
+    /// The function's name, as it is written in a program.
+    pub fn name(self) -> &'static str {
+        Function::NAMES
+            .iter()
+            .find(|&&(_, function)| function == self)
+            .map(|&(name, _)| name)
+            .unwrap_or_default()
+    }
+
+    /// Apply the function to a value.
+    pub fn apply(self, value: f32) -> Result<f32, ExpressionError> {
+        let out_of_domain = || ExpressionError::OutOfDomain {
+            function: self,
+            value,
+        };
+
+        Ok(match self {
+            Function::Abs => value.abs(),
+            Function::Acos | Function::Asin
+                if !(-1.0..=1.0).contains(&value) =>
+            {
+                return Err(out_of_domain())
+            },
+            Function::Acos => libm::acosf(value).to_degrees(),
+            Function::Asin => libm::asinf(value).to_degrees(),
+            Function::Cos => libm::cosf(value.to_radians()),
+            Function::Exp => libm::expf(value),
+            Function::Fix => libm::floorf(value),
+            Function::Fup => libm::ceilf(value),
+            Function::Ln if value <= 0.0 => return Err(out_of_domain()),
+            Function::Ln => libm::logf(value),
+            Function::Round => libm::roundf(value),
+            Function::Sin => libm::sinf(value.to_radians()),
+            Function::Sqrt if value < 0.0 => return Err(out_of_domain()),
+            Function::Sqrt => libm::sqrtf(value),
+            Function::Tan => libm::tanf(value.to_radians()),
+        })
+    }
+}
+
+impl BinaryOperator {
+    /// Each operator, spelled the way it is written in a program.
+    const SPELLINGS: [(&'static str, BinaryOperator); 15] = [
+        ("**", BinaryOperator::Power),
+        ("*", BinaryOperator::Multiply),
+        ("/", BinaryOperator::Divide),
+        ("MOD", BinaryOperator::Modulo),
+        ("+", BinaryOperator::Add),
+        ("-", BinaryOperator::Subtract),
+        ("EQ", BinaryOperator::Equal),
+        ("NE", BinaryOperator::NotEqual),
+        ("GT", BinaryOperator::GreaterThan),
+        ("GE", BinaryOperator::GreaterOrEqual),
+        ("LT", BinaryOperator::LessThan),
+        ("LE", BinaryOperator::LessOrEqual),
+        ("AND", BinaryOperator::And),
+        ("OR", BinaryOperator::Or),
+        ("XOR", BinaryOperator::ExclusiveOr),
+    ];
+
+    /// How tightly the operator binds, where higher numbers are evaluated
+    /// first.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOperator::Power => 5,
+            BinaryOperator::Multiply
+            | BinaryOperator::Divide
+            | BinaryOperator::Modulo => 4,
+            BinaryOperator::Add | BinaryOperator::Subtract => 3,
+            BinaryOperator::Equal
+            | BinaryOperator::NotEqual
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterOrEqual
+            | BinaryOperator::LessThan
+            | BinaryOperator::LessOrEqual => 2,
+            BinaryOperator::And
+            | BinaryOperator::Or
+            | BinaryOperator::ExclusiveOr => 1,
+        }
+    }
+
+    fn apply(self, left: f32, right: f32) -> Result<f32, ExpressionError> {
+        let truth = |condition: bool| if condition { 1.0 } else { 0.0 };
+
+        Ok(match self {
+            BinaryOperator::Power => libm::powf(left, right),
+            BinaryOperator::Multiply => left * right,
+            BinaryOperator::Divide if right == 0.0 => {
+                return Err(ExpressionError::DivisionByZero)
+            },
+            BinaryOperator::Divide => left / right,
+            BinaryOperator::Modulo if right == 0.0 => {
+                return Err(ExpressionError::DivisionByZero)
+            },
+            // the result always has the same sign as the divisor
+            BinaryOperator::Modulo => left - right * libm::floorf(left / right),
+            BinaryOperator::Add => left + right,
+            BinaryOperator::Subtract => left - right,
+            BinaryOperator::Equal => truth(left == right),
+            BinaryOperator::NotEqual => truth(left != right),
+            BinaryOperator::GreaterThan => truth(left > right),
+            BinaryOperator::GreaterOrEqual => truth(left >= right),
+            BinaryOperator::LessThan => truth(left < right),
+            BinaryOperator::LessOrEqual => truth(left <= right),
+            BinaryOperator::And => truth(left != 0.0 && right != 0.0),
+            BinaryOperator::Or => truth(left != 0.0 || right != 0.0),
+            BinaryOperator::ExclusiveOr => {
+                truth((left != 0.0) != (right != 0.0))
+            },
+        })
+    }
+}
+
+impl Expression {
+    /// How deeply brackets, parameters (e.g. `##1`) and signs may be nested
+    /// before [`Expression::parse()`] gives up.
+    pub const MAX_NESTING: usize = 100;
+
+    /// Parse a single value (a number, parameter, or bracketed expression).
+    ///
+    /// The entire string must be consumed.
+    pub fn parse(src: &str) -> Result<Expression, ExpressionError> {
+        let mut cursor = Cursor::new(src);
+        let expr = cursor.real_value()?;
+        cursor.skip_whitespace();
+
+        match cursor.peek() {
+            None => Ok(expr),
+            Some(character) => Err(ExpressionError::UnexpectedCharacter {
+                character,
+                position: cursor.position(),
+            }),
+        }
+    }
+
+    /// Calculate the expression's value.
+    ///
+    /// Numbered parameters which haven't been set are `0.0`, while using a
+    /// named parameter before it is set is an error.
+    pub fn evaluate(
+        &self,
+        parameters: &Parameters,
+    ) -> Result<f32, ExpressionError> {
+        match self {
+            Expression::Number(number) => Ok(*number),
+            Expression::Parameter(number) => {
+                let id = ParameterId::numbered(number.evaluate(parameters)?)?;
+                Ok(parameters.get(&id).unwrap_or(0.0))
+            },
+            Expression::NamedParameter(name) => {
+                parameters.named(name).ok_or_else(|| {
+                    ExpressionError::UndefinedParameter(name.clone())
+                })
+            },
+            Expression::Unary(UnaryOperator::Negate, value) => {
+                Ok(-value.evaluate(parameters)?)
+            },
+            Expression::Binary(op, left, right) => op
+                .apply(left.evaluate(parameters)?, right.evaluate(parameters)?),
+            Expression::Function(function, value) => {
+                function.apply(value.evaluate(parameters)?)
+            },
+            Expression::Atan(y, x) => {
+                let (y, x) = (y.evaluate(parameters)?, x.evaluate(parameters)?);
+                Ok(libm::atan2f(y, x).to_degrees())
+            },
+            Expression::Exists(parameter) => {
+                let exists = match parameter.parameter_id(parameters)? {
+                    Some(id) => parameters.get(&id).is_some(),
+                    None => false,
+                };
+                Ok(if exists { 1.0 } else { 0.0 })
+            },
+        }
+    }
+
+    /// Does the expression have the same value no matter what parameters
+    /// are set?
+    pub fn is_constant(&self) -> bool {
+        match self {
+            Expression::Number(_) => true,
+            Expression::Parameter(_)
+            | Expression::NamedParameter(_)
+            | Expression::Exists(_) => false,
+            Expression::Unary(_, value) | Expression::Function(_, value) => {
+                value.is_constant()
+            },
+            Expression::Binary(_, left, right)
+            | Expression::Atan(left, right) => {
+                left.is_constant() && right.is_constant()
+            },
+        }
+    }
+
+    /// The parameters whose values the expression reads.
+    ///
+    /// Indirect parameters are only included when the parameter number is
+    /// constant, and parameters which are only checked using `EXISTS[]` are
+    /// skipped.
+    pub fn parameters(&self) -> Vec<ParameterId> {
+        let mut parameters = Vec::new();
+        self.collect_parameters(&mut parameters);
+        parameters
+    }
+
+    fn collect_parameters(&self, parameters: &mut Vec<ParameterId>) {
+        match self {
+            Expression::Number(_) | Expression::Exists(_) => {},
+            Expression::Parameter(number) => {
+                if number.is_constant() {
+                    if let Ok(Some(id)) = self.parameter_id(&Parameters::new())
+                    {
+                        parameters.push(id);
+                    }
+                } else {
+                    number.collect_parameters(parameters);
+                }
+            },
+            Expression::NamedParameter(name) => {
+                parameters.push(ParameterId::Named(name.clone()))
+            },
+            Expression::Unary(_, value) | Expression::Function(_, value) => {
+                value.collect_parameters(parameters)
+            },
+            Expression::Binary(_, left, right)
+            | Expression::Atan(left, right) => {
+                left.collect_parameters(parameters);
+                right.collect_parameters(parameters);
+            },
+        }
+    }
+
+    /// Find the [`ParameterId`] a parameter expression refers to.
+    pub(crate) fn parameter_id(
+        &self,
+        parameters: &Parameters,
+    ) -> Result<Option<ParameterId>, ExpressionError> {
+        match self {
+            Expression::Parameter(number) => {
+                Ok(Some(ParameterId::numbered(number.evaluate(parameters)?)?))
+            },
+            Expression::NamedParameter(name) => {
+                Ok(Some(ParameterId::Named(name.clone())))
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+/// The name of a parameter.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum ParameterId {
+    /// A numbered parameter (e.g. `#100`).
+    Numbered(u32),
+    /// A named parameter (e.g. `#<_feed>`).
+    Named(String),
+}
+
+impl ParameterId {
+    /// Create a [`ParameterId::Named`], normalizing the name so it can be
+    /// compared with others (names ignore case and whitespace).
+    ///
+    /// ```rust
+    /// # use gcode::expr::ParameterId;
+    /// assert_eq!(ParameterId::named("_Tool Length"), ParameterId::named("_toollength"));
+    /// ```
+    pub fn named(name: &str) -> ParameterId {
+        ParameterId::Named(normalize_name(name))
+    }
+
+    fn numbered(number: f32) -> Result<ParameterId, ExpressionError> {
+        let rounded = libm::roundf(number);
+
+        if rounded >= 0.0 && (number - rounded).abs() < 1e-4 {
+            Ok(ParameterId::Numbered(rounded as u32))
+        } else {
+            Err(ExpressionError::InvalidParameterNumber(number))
+        }
+    }
+
+    /// Is this parameter visible everywhere in the program? Numbered
+    /// parameters and names starting with an underscore are global.
+    pub fn is_global(&self) -> bool {
+        match self {
+            ParameterId::Numbered(_) => true,
+            ParameterId::Named(name) => name.starts_with('_'),
+        }
+    }
+}
+
+impl Display for ParameterId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterId::Numbered(number) => write!(f, "#{}", number),
+            ParameterId::Named(name) => write!(f, "#<{}>", name),
+        }
+    }
+}
+
+pub(crate) fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// A table of parameter values.
+///
+/// Local named parameters belong to a *scope*, which is created when a
+/// subroutine is called ([`Parameters::push_scope()`]) and thrown away when
+/// it returns ([`Parameters::pop_scope()`]).
+///
+/// ```rust
+/// # use gcode::expr::{ParameterId, Parameters};
+/// let mut parameters = Parameters::new();
+/// parameters.set(&ParameterId::named("_global"), 1.0);
+/// parameters.set(&ParameterId::named("local"), 2.0);
+///
+/// parameters.push_scope();
+/// assert_eq!(parameters.named("_global"), Some(1.0));
+/// assert_eq!(parameters.named("local"), None);
+///
+/// parameters.pop_scope();
+/// assert_eq!(parameters.named("local"), Some(2.0));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameters {
+    numbered: BTreeMap<u32, f32>,
+    globals: BTreeMap<String, f32>,
+    /// Local parameters, with the innermost scope last. There is always at
+    /// least one scope (the main program's).
+    scopes: Vec<BTreeMap<String, f32>>,
+}
+
+impl Parameters {
+    /// Create an empty parameter table.
+    pub fn new() -> Self {
+        Parameters {
+            numbered: BTreeMap::new(),
+            globals: BTreeMap::new(),
+            scopes: vec![BTreeMap::new()],
+        }
+    }
+
+    /// Look up a parameter's value, if it has been set.
+    pub fn get(&self, id: &ParameterId) -> Option<f32> {
+        match id {
+            ParameterId::Numbered(number) => self.numbered.get(number).copied(),
+            ParameterId::Named(name) if id.is_global() => {
+                self.globals.get(name).copied()
+            },
+            ParameterId::Named(name) => self
+                .scopes
+                .last()
+                .and_then(|scope| scope.get(name))
+                .copied(),
+        }
+    }
+
+    /// Look up a named parameter.
+    pub fn named(&self, name: &str) -> Option<f32> {
+        self.get(&ParameterId::named(name))
+    }
+
+    /// Set a parameter's value.
+    pub fn set(&mut self, id: &ParameterId, value: f32) {
+        match id {
+            ParameterId::Numbered(number) => {
+                let _ = self.numbered.insert(*number, value);
+            },
+            ParameterId::Named(name) if id.is_global() => {
+                let _ = self.globals.insert(name.clone(), value);
+            },
+            ParameterId::Named(name) => {
+                if let Some(scope) = self.scopes.last_mut() {
+                    let _ = scope.insert(name.clone(), value);
+                }
+            },
+        }
+    }
+
+    /// Forget a parameter's value, returning what it used to be.
+    pub fn remove(&mut self, id: &ParameterId) -> Option<f32> {
+        match id {
+            ParameterId::Numbered(number) => self.numbered.remove(number),
+            ParameterId::Named(name) if id.is_global() => {
+                self.globals.remove(name)
+            },
+            ParameterId::Named(name) => {
+                self.scopes.last_mut().and_then(|scope| scope.remove(name))
+            },
+        }
+    }
+
+    /// Enter a new scope for local parameters (e.g. when calling a
+    /// subroutine).
+    pub fn push_scope(&mut self) { self.scopes.push(BTreeMap::new()); }
+
+    /// Leave the current scope, discarding its local parameters. The main
+    /// program's scope is never removed.
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            let _ = self.scopes.pop();
+        }
+    }
+
+    /// Iterate over every parameter which is currently visible.
+    pub fn iter(&self) -> impl Iterator<Item = (ParameterId, f32)> + '_ {
+        let numbered = self
+            .numbered
+            .iter()
+            .map(|(&number, &value)| (ParameterId::Numbered(number), value));
+        let named = self
+            .globals
+            .iter()
+            .chain(self.scopes.last().into_iter().flatten())
+            .map(|(name, &value)| (ParameterId::Named(name.clone()), value));
+
+        numbered.chain(named)
+    }
+}
+
+impl Default for Parameters {
+    fn default() -> Parameters { Parameters::new() }
+}
+
+/// Errors that may occur while parsing or evaluating an [`Expression`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpressionError {
+    /// A character which wasn't expected at this point.
+    UnexpectedCharacter {
+        /// The character.
+        character: char,
+        /// Its byte offset.
+        position: usize,
+    },
+    /// The text finished in the middle of an expression.
+    UnexpectedEnd,
+    /// A named parameter was used before being given a value.
+    UndefinedParameter(String),
+    /// A parameter number must be a non-negative integer.
+    InvalidParameterNumber(f32),
+    /// A function was given a value it isn't defined for (e.g. `SQRT[-1]`).
+    OutOfDomain {
+        /// The function.
+        function: Function,
+        /// The value it was given.
+        value: f32,
+    },
+    /// An attempt to divide by zero.
+    DivisionByZero,
+    /// Brackets, parameters or signs were nested more than
+    /// [`Expression::MAX_NESTING`] deep.
+    TooDeeplyNested,
+}
+
+impl Display for ExpressionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpressionError::UnexpectedCharacter {
+                character,
+                position,
+            } => write!(
+                f,
+                "unexpected character, '{}', at position {}",
+                character, position
+            ),
+            ExpressionError::UnexpectedEnd => {
+                write!(f, "unexpected end of expression")
+            },
+            ExpressionError::UndefinedParameter(name) => {
+                write!(f, "the parameter #<{}> is not defined", name)
+            },
+            ExpressionError::InvalidParameterNumber(number) => {
+                write!(f, "{} is not a valid parameter number", number)
+            },
+            ExpressionError::OutOfDomain { function, value } => {
+                write!(f, "{} isn't defined for {}", function.name(), value)
+            },
+            ExpressionError::DivisionByZero => write!(f, "division by zero"),
+            ExpressionError::TooDeeplyNested => write!(
+                f,
+                "the expression is nested more than {} deep",
+                Expression::MAX_NESTING
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+/// [`Callbacks`] which evaluate expressions as a program is parsed (see
+/// [`Dialect::expressions`]), keeping track of parameter assignments.
+///
+/// Like RS-274/NGC, an assignment only takes effect once the line it is on
+/// has been read, so every expression on a line sees the same values. Any
+/// other callbacks are passed through to an inner set of [`Callbacks`].
+///
+/// This is enough for straight-line parametric programs. Use an
+/// [`Executor`][crate::executor::Executor] for anything which needs
+/// subroutines, conditionals or loops.
+///
+/// ```rust
+/// use gcode::{
+///     dialect::Dialect,
+///     expr::{Evaluator, ParameterId},
+///     Parser,
+/// };
+///
+/// let src = "#100 = 25.4\nG0 X[#100 * 2] Y[1 + 2 * SIN[30]] Z#<_height>";
+/// let mut evaluator = Evaluator::new();
+/// evaluator
+///     .parameters_mut()
+///     .set(&ParameterId::named("_height"), 5.0);
+///
+/// let lines: Vec<_> =
+///     Parser::<_>::new_with_dialect(src, &mut evaluator, Dialect::linuxcnc())
+///         .collect();
+///
+/// let g0 = &lines[0].gcodes()[0];
+/// assert_eq!(g0.value_for('X'), Some(50.8));
+/// assert_eq!(g0.value_for('Y'), Some(2.0));
+/// assert_eq!(g0.value_for('Z'), Some(5.0));
+/// assert!(evaluator.errors().is_empty());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluator<C = Nop> {
+    parameters: Parameters,
+    /// Assignments which take effect once their line has been read, as
+    /// `(line, parameter, value)`.
+    pending: Vec<(usize, ParameterId, f32)>,
+    errors: Vec<EvaluationError>,
+    inner: C,
+}
+
+impl Evaluator<Nop> {
+    /// Create an [`Evaluator`] with an empty parameter table.
+    pub fn new() -> Self { Evaluator::with_callbacks(Nop) }
+}
+
+impl Default for Evaluator<Nop> {
+    fn default() -> Evaluator<Nop> { Evaluator::new() }
+}
+
+impl<C> Evaluator<C> {
+    /// Create an [`Evaluator`] which passes everything other than
+    /// expressions through to another set of [`Callbacks`].
+    pub fn with_callbacks(inner: C) -> Self {
+        Evaluator {
+            parameters: Parameters::new(),
+            pending: Vec::new(),
+            errors: Vec::new(),
+            inner,
+        }
+    }
+
+    /// Start with a particular set of parameters.
+    pub fn with_parameters(mut self, parameters: Parameters) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    /// The parameter table, not including assignments on the most recent
+    /// line.
+    pub fn parameters(&self) -> &Parameters { &self.parameters }
+
+    /// Get mutable access to the parameter table.
+    pub fn parameters_mut(&mut self) -> &mut Parameters { &mut self.parameters }
+
+    /// Every expression or assignment which couldn't be evaluated.
+    pub fn errors(&self) -> &[EvaluationError] { &self.errors }
+
+    /// The [`Callbacks`] everything else is passed to.
+    pub fn inner(&self) -> &C { &self.inner }
+
+    /// Finish evaluating, returning the final parameter table.
+    pub fn into_parameters(mut self) -> Parameters {
+        self.commit(usize::MAX);
+        self.parameters
+    }
+
+    /// Apply the assignments made before a particular line.
+    fn commit(&mut self, line: usize) {
+        let parameters = &mut self.parameters;
+
+        self.pending.retain(|(assigned_on, id, value)| {
+            if *assigned_on < line {
+                parameters.set(id, *value);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn assign(
+        &mut self,
+        assignment: &str,
+        line: usize,
+    ) -> Result<(), ExpressionError> {
+        let mut cursor = Cursor::new(assignment);
+        cursor.skip_whitespace();
+        if !cursor.eat("#") {
+            return Err(cursor.unexpected());
+        }
+        let parameter = cursor.parameter()?;
+        if !cursor.eat("=") {
+            cursor.skip_whitespace();
+            return Err(cursor.unexpected());
+        }
+        let value = cursor.real_value()?;
+        cursor.skip_whitespace();
+        if cursor.peek().is_some() {
+            return Err(cursor.unexpected());
+        }
+
+        let id = parameter
+            .parameter_id(&self.parameters)?
+            .ok_or(ExpressionError::UnexpectedEnd)?;
+        let value = value.evaluate(&self.parameters)?;
+        self.pending.push((line, id, value));
+
+        Ok(())
+    }
+}
+
+impl<C: Callbacks> Callbacks for Evaluator<C> {
+    fn unknown_content(&mut self, text: &str, span: Span) {
+        self.inner.unknown_content(text, span);
+    }
+
+    fn gcode_buffer_overflowed(
+        &mut self,
+        mnemonic: Mnemonic,
+        major_number: u32,
+        minor_number: u32,
+        arguments: &[Word],
+        span: Span,
+    ) {
+        self.inner.gcode_buffer_overflowed(
+            mnemonic,
+            major_number,
+            minor_number,
+            arguments,
+            span,
+        );
+    }
+
+    fn gcode_argument_buffer_overflowed(
+        &mut self,
+        mnemonic: Mnemonic,
+        major_number: u32,
+        minor_number: u32,
+        argument: Word,
+    ) {
+        self.inner.gcode_argument_buffer_overflowed(
+            mnemonic,
+            major_number,
+            minor_number,
+            argument,
+        );
+    }
+
+    fn comment_buffer_overflow(&mut self, comment: Comment<'_>) {
+        self.inner.comment_buffer_overflow(comment);
+    }
+
+    fn unexpected_line_number(&mut self, line_number: f32, span: Span) {
+        self.inner.unexpected_line_number(line_number, span);
+    }
+
+    fn argument_without_a_command(
+        &mut self,
+        letter: char,
+        value: f32,
+        span: Span,
+    ) {
+        self.inner.argument_without_a_command(letter, value, span);
+    }
+
+    fn number_without_a_letter(&mut self, value: &str, span: Span) {
+        self.inner.number_without_a_letter(value, span);
+    }
+
+    fn letter_without_a_number(&mut self, value: &str, span: Span) {
+        self.inner.letter_without_a_number(value, span);
+    }
+
+    fn partial_word(&mut self, recovered: Word, text: &str, span: Span) {
+        self.inner.partial_word(recovered, text, span);
+    }
+
+    fn line_buffer_overflowed(&mut self, line: usize) {
+        self.inner.line_buffer_overflowed(line);
+    }
+
+    fn checksum_mismatch(&mut self, written: u32, calculated: u8, span: Span) {
+        self.inner.checksum_mismatch(written, calculated, span);
+    }
+
+    fn line_number_out_of_sequence(
+        &mut self,
+        expected: u32,
+        found: u32,
+        span: Span,
+    ) {
+        self.inner
+            .line_number_out_of_sequence(expected, found, span);
+    }
+
+    fn evaluate_expression(
+        &mut self,
+        expression: &str,
+        span: Span,
+    ) -> Option<f32> {
+        self.commit(span.line);
+
+        match Expression::parse(expression)
+            .and_then(|expr| expr.evaluate(&self.parameters))
+        {
+            Ok(value) => Some(value),
+            Err(kind) => {
+                self.errors.push(EvaluationError { span, kind });
+                None
+            },
+        }
+    }
+
+    fn parameter_assignment(&mut self, assignment: &str, span: Span) {
+        self.commit(span.line);
+
+        if let Err(kind) = self.assign(assignment, span.line) {
+            self.errors.push(EvaluationError { span, kind });
+        }
+    }
+}
+
+/// An expression or assignment which an [`Evaluator`] couldn't evaluate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvaluationError {
+    /// Where the expression or assignment is.
+    pub span: Span,
+    /// What went wrong.
+    pub kind: ExpressionError,
+}
+
+impl Display for EvaluationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.span.line + 1, self.kind)
+    }
+}
+
+impl std::error::Error for EvaluationError {}
+
+/// A simple recursive descent parser for expressions, which can also be used
+/// to pick apart a line containing them.
+#[derive(Debug, Clone)]
+pub(crate) struct Cursor<'a> {
+    src: &'a str,
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(src: &'a str) -> Self {
+        Cursor {
+            src,
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    pub(crate) fn position(&self) -> usize { self.position }
+
+    pub(crate) fn seek(&mut self, position: usize) { self.position = position; }
+
+    pub(crate) fn rest(&self) -> &'a str { &self.src[self.position..] }
+
+    pub(crate) fn peek(&self) -> Option<char> { self.rest().chars().next() }
+
+    pub(crate) fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    pub(crate) fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            let _ = self.bump();
+        }
+    }
+
+    /// Consume some text (ignoring case and leading whitespace) if it comes
+    /// next.
+    pub(crate) fn eat(&mut self, text: &str) -> bool {
+        self.skip_whitespace();
+        let rest = self.rest();
+
+        match rest.get(..text.len()) {
+            Some(next) if next.eq_ignore_ascii_case(text) => {
+                self.position += text.len();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub(crate) fn unexpected(&self) -> ExpressionError {
+        match self.peek() {
+            Some(character) => ExpressionError::UnexpectedCharacter {
+                character,
+                position: self.position,
+            },
+            None => ExpressionError::UnexpectedEnd,
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), ExpressionError> {
+        if self.eat(text) {
+            Ok(())
+        } else {
+            self.skip_whitespace();
+            Err(self.unexpected())
+        }
+    }
+
+    /// A number, parameter, or bracketed expression, possibly preceded by a
+    /// sign.
+    pub(crate) fn real_value(&mut self) -> Result<Expression, ExpressionError> {
+        // every kind of nesting comes back through here, so this is where
+        // the recursion gets stopped before it can overflow the stack
+        if self.depth >= Expression::MAX_NESTING {
+            return Err(ExpressionError::TooDeeplyNested);
+        }
+
+        self.depth += 1;
+        let value = self.nested_value();
+        self.depth -= 1;
+
+        value
+    }
+
+    fn nested_value(&mut self) -> Result<Expression, ExpressionError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('[') => self.bracketed(),
+            Some('#') => {
+                let _ = self.bump();
+                self.parameter()
+            },
+            Some('-') => {
+                let _ = self.bump();
+                let value = self.real_value()?;
+                Ok(Expression::Unary(UnaryOperator::Negate, Box::new(value)))
+            },
+            Some('+') => {
+                let _ = self.bump();
+                self.real_value()
+            },
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if c.is_ascii_alphabetic() => self.function(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// A function call, like `SIN[30]`, `ATAN[1]/[2]` or `EXISTS[#<x>]`.
+    fn function(&mut self) -> Result<Expression, ExpressionError> {
+        let start = self.position;
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let name = &rest[..length];
+        self.position += length;
+
+        if name.eq_ignore_ascii_case("ATAN") {
+            let y = self.bracketed()?;
+            self.expect("/")?;
+            let x = self.bracketed()?;
+            Ok(Expression::Atan(Box::new(y), Box::new(x)))
+        } else if name.eq_ignore_ascii_case("EXISTS") {
+            self.expect("[")?;
+            self.expect("#")?;
+            let parameter = self.parameter()?;
+            self.expect("]")?;
+            Ok(Expression::Exists(Box::new(parameter)))
+        } else if let Some(function) = Function::from_name(name) {
+            let value = self.bracketed()?;
+            Ok(Expression::Function(function, Box::new(value)))
+        } else {
+            self.position = start;
+            Err(self.unexpected())
+        }
+    }
+
+    fn bracketed(&mut self) -> Result<Expression, ExpressionError> {
+        self.expect("[")?;
+        let expr = self.expression(0)?;
+        self.expect("]")?;
+        Ok(expr)
+    }
+
+    /// Everything after the `#`.
+    pub(crate) fn parameter(&mut self) -> Result<Expression, ExpressionError> {
+        self.skip_whitespace();
+
+        if self.peek() == Some('<') {
+            let _ = self.bump();
+            let rest = self.rest();
+            let end = rest.find('>').ok_or(ExpressionError::UnexpectedEnd)?;
+            self.position += end + 1;
+
+            Ok(Expression::NamedParameter(normalize_name(&rest[..end])))
+        } else {
+            Ok(Expression::Parameter(Box::new(self.real_value()?)))
+        }
+    }
+
+    fn number(&mut self) -> Result<Expression, ExpressionError> {
+        let start = self.position;
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        self.position += length;
+
+        rest[..length].parse().map(Expression::Number).map_err(|_| {
+            ExpressionError::UnexpectedCharacter {
+                character: '.',
+                position: start,
+            }
+        })
+    }
+
+    /// Parse a binary expression using precedence climbing.
+    fn expression(
+        &mut self,
+        min_precedence: u8,
+    ) -> Result<Expression, ExpressionError> {
+        let mut left = self.real_value()?;
+
+        loop {
+            let checkpoint = self.position;
+            let op = match self.binary_operator() {
+                Some(op) if op.precedence() >= min_precedence => op,
+                _ => {
+                    self.position = checkpoint;
+                    return Ok(left);
+                },
+            };
+
+            let right = self.expression(op.precedence() + 1)?;
+            left = Expression::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn binary_operator(&mut self) -> Option<BinaryOperator> {
+        BinaryOperator::SPELLINGS
+            .iter()
+            .find(|(spelling, _)| self.eat(spelling))
+            .map(|&(_, op)| op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Parser, WordValue};
+
+    fn evaluate(src: &str) -> Result<f32, ExpressionError> {
+        Expression::parse(src)?.evaluate(&Parameters::new())
+    }
+
+    #[test]
+    fn operator_precedence() {
+        let inputs = vec![
+            ("[1 + 2 * 3]", 7.0),
+            ("[[1 + 2] * 3]", 9.0),
+            ("[2 ** 3 * 2]", 16.0),
+            ("[10 - 4 - 3]", 3.0),
+            ("[1 + 1 EQ 2]", 1.0),
+            ("[1 LT 2 AND 3 GT 4]", 0.0),
+            ("[1 or 0]", 1.0),
+            ("[-7 MOD 3]", 2.0),
+            ("-[2]", -2.0),
+            ("1.", 1.0),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(evaluate(src), Ok(should_be), "{}", src);
+        }
+    }
+
+    #[test]
+    fn indirect_parameters() {
+        let mut parameters = Parameters::new();
+        parameters.set(&ParameterId::Numbered(1), 5.0);
+        parameters.set(&ParameterId::Numbered(5), 42.0);
+
+        let got = Expression::parse("##1").unwrap().evaluate(&parameters);
+
+        assert_eq!(got, Ok(42.0));
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let max = Expression::MAX_NESTING;
+        assert_eq!(evaluate(&format!("{}1", "-".repeat(max - 1))), Ok(-1.0));
+        assert_eq!(
+            evaluate(&format!("{}1", "-".repeat(max))),
+            Err(ExpressionError::TooDeeplyNested)
+        );
+
+        let brackets = format!("{}1{}", "[".repeat(20_000), "]".repeat(20_000));
+        let hashes = format!("{}1", "#".repeat(100_000));
+        let signs = format!("{}1", "-".repeat(20_000));
+
+        for src in &[brackets, hashes, signs] {
+            assert_eq!(
+                Expression::parse(src),
+                Err(ExpressionError::TooDeeplyNested)
+            );
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            evaluate("[1 + ]"),
+            Err(ExpressionError::UnexpectedCharacter {
+                character: ']',
+                position: 5,
+            })
+        );
+        assert_eq!(evaluate("[1 + 2"), Err(ExpressionError::UnexpectedEnd));
+        assert_eq!(evaluate("[1 / 0]"), Err(ExpressionError::DivisionByZero));
+        assert_eq!(
+            evaluate("#<missing>"),
+            Err(ExpressionError::UndefinedParameter("missing".into()))
+        );
+        assert_eq!(
+            evaluate("#-1"),
+            Err(ExpressionError::InvalidParameterNumber(-1.0))
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let inputs = vec![
+            ("SIN[30]", 0.5),
+            ("cos[60]", 0.5),
+            ("[2 * TAN[45]]", 2.0),
+            ("ASIN[1]", 90.0),
+            ("ATAN[1]/[-1]", 135.0),
+            ("SQRT[[3 ** 2] + 4 ** 2]", 5.0),
+            ("FIX[-1.5]", -2.0),
+            ("FUP[1.2]", 2.0),
+            ("ROUND[2.5]", 3.0),
+            ("ABS[-3]", 3.0),
+            ("LN[EXP[2]]", 2.0),
+        ];
+
+        for (src, should_be) in inputs {
+            let got = evaluate(src).unwrap();
+            assert!((got - should_be).abs() < 1e-5, "{} = {}", src, got);
+        }
+
+        assert_eq!(
+            evaluate("SQRT[-1]"),
+            Err(ExpressionError::OutOfDomain {
+                function: Function::Sqrt,
+                value: -1.0
+            })
+        );
+        assert!(evaluate("NOPE[1]").is_err());
+    }
+
+    #[test]
+    fn check_whether_parameters_exist() {
+        let mut parameters = Parameters::new();
+        parameters.set(&ParameterId::named("_set"), 0.0);
+        let exists = |src: &str| {
+            Expression::parse(src)
+                .unwrap()
+                .evaluate(&parameters)
+                .unwrap()
+        };
+
+        assert_eq!(exists("EXISTS[#<_set>]"), 1.0);
+        assert_eq!(exists("EXISTS[#<_unset>]"), 0.0);
+        assert_eq!(exists("EXISTS[#5]"), 0.0);
+    }
+
+    #[test]
+    fn local_parameters_are_scoped() {
+        let mut parameters = Parameters::new();
+        parameters.push_scope();
+        parameters.set(&ParameterId::named("depth"), 3.0);
+        parameters.set(&ParameterId::Numbered(7), 1.0);
+        parameters.pop_scope();
+
+        assert_eq!(parameters.named("depth"), None);
+        assert_eq!(parameters.get(&ParameterId::Numbered(7)), Some(1.0));
+    }
+
+    fn parse_with(src: &str, evaluator: &mut Evaluator) -> Vec<Option<f32>> {
+        Parser::<_>::new_with_dialect(src, evaluator, Dialect::linuxcnc())
+            .flat_map(|line| line.into_gcodes())
+            .map(|gcode| gcode.value_for('X'))
+            .collect()
+    }
+
+    #[test]
+    fn assignments_take_effect_on_the_next_line() {
+        let src = "#1 = 1\n#1 = [#1 + 1] G0 X#1\nG0 X#1\n#2 = [1 / 0]";
+        let mut evaluator = Evaluator::new();
+
+        let got = parse_with(src, &mut evaluator);
+
+        assert_eq!(got, vec![Some(1.0), Some(2.0)]);
+        assert_eq!(evaluator.errors().len(), 1);
+        assert_eq!(
+            evaluator.errors()[0].to_string(),
+            "line 4: division by zero"
+        );
+        let parameters = evaluator.into_parameters();
+        assert_eq!(parameters.get(&ParameterId::Numbered(1)), Some(2.0));
+    }
+
+    #[test]
+    fn unevaluated_expressions_are_kept() {
+        let src = "G0 X[1 + 2] G[#1]";
+        let mut unknown = Vec::new();
+        let mut callbacks = UnknownContent(&mut unknown);
+
+        let lines: Vec<_> = Parser::<_>::new_with_dialect(
+            src,
+            &mut callbacks,
+            Dialect::linuxcnc(),
+        )
+        .collect();
+
+        let x = lines[0].gcodes()[0].arguments()[0];
+        assert_eq!(x.value, WordValue::Expression(Span::new(4, 11, 0)));
+        assert_eq!(x.span, Span::new(3, 11, 0));
+        // a command needs a number
+        assert_eq!(lines[0].gcodes().len(), 1);
+        assert_eq!(unknown, vec![String::from("[#1]")]);
+    }
+
+    struct UnknownContent<'a>(&'a mut Vec<String>);
+
+    impl Callbacks for UnknownContent<'_> {
+        fn unknown_content(&mut self, text: &str, _span: Span) {
+            self.0.push(String::from(text));
+        }
+    }
+}
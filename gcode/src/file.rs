@@ -0,0 +1,414 @@
+//! A one-stop shop for loading, checking, editing and saving a program.
+//!
+//! The rest of the crate is made of small pieces which can be plugged
+//! together however you like, but most of the time you just want to read a
+//! file, see how long it'll take, tidy it up and write it back out. A
+//! [`GcodeFile`] does exactly that.
+//!
+//! ```rust
+//! use gcode::{
+//!     pipeline::{Pipeline, RenumberPass},
+//!     profile::MachineProfile,
+//!     writer::WriterConfig,
+//!     GcodeFile,
+//! };
+//!
+//! let src = "N5 G21 G90\nN6 G1 X10 F600\nN7 Y10\nN8 M30";
+//!
+//! let file = GcodeFile::parse(src)?
+//!     .analyze(&MachineProfile::new("bench mill"))
+//!     .transform(Pipeline::new().with_pass(RenumberPass::new(10, 10)));
+//! let written = file.write(&WriterConfig::for_dialect(file.dialect()))?;
+//!
+//! // the writer spells out the modal G1 on line 3
+//! assert_eq!(written, "N10 G21 G90\nN20 G1 X10 F600\nN30 G1 Y10\nN40 M30\n");
+//! assert!(file.diagnostics().is_empty());
+//! assert!(file.analysis().unwrap().total_time() > 1.0);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Everything a [`GcodeFile`] does can also be done by hand with a
+//! [`Parser`], an [`Analyzer`](crate::analysis::Analyzer), a [`Pipeline`]
+//! and [`writer::reformat()`], which is the way to go when you need to
+//! stream a program instead of holding it in memory.
+
+use crate::{
+    analysis::Analysis,
+    detect,
+    dialect::Dialect,
+    pipeline::{Context, Diagnostics, Pipeline},
+    profile::MachineProfile,
+    program::Program,
+    writer::{self, WriterConfig},
+    Callbacks, Mnemonic, Parser, Span, Word,
+};
+use core::fmt::{self, Display, Formatter};
+use std::string::String;
+
+/// A whole program, along with everything learned about it so far.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcodeFile {
+    program: Program,
+    profile: Option<MachineProfile>,
+    analysis: Option<Analysis>,
+    context: Context,
+    diagnostics: Diagnostics,
+}
+
+impl GcodeFile {
+    /// Parse a program, guessing which [`Dialect`] it was written for (see
+    /// [`detect::dialect()`]).
+    ///
+    /// This fails on the first thing the parser doesn't understand. Lines
+    /// containing nothing but a `%` tape marker are fine, as is the free-form
+    /// text after commands like `M117 Printing...` or `M23 part.gco`.
+    pub fn parse(src: &str) -> Result<GcodeFile, SyntaxError> {
+        GcodeFile::parse_with_dialect(
+            src,
+            detect::dialect(src).dialect.dialect(),
+        )
+    }
+
+    /// Parse a program written for a particular [`Dialect`].
+    pub fn parse_with_dialect(
+        src: &str,
+        dialect: Dialect,
+    ) -> Result<GcodeFile, SyntaxError> {
+        match first_syntax_error(src, dialect) {
+            Some(error) => Err(error),
+            None => Ok(GcodeFile::from(Program::parse(src, dialect))),
+        }
+    }
+
+    /// Estimate how long the program will take on a particular machine.
+    ///
+    /// The result is available from [`GcodeFile::analysis()`], and is kept
+    /// up to date by any later [`GcodeFile::transform()`]s.
+    pub fn analyze(mut self, profile: &MachineProfile) -> Self {
+        self.analysis = Some(profile.analyzer().analyze(&self.text()));
+        self.profile = Some(profile.clone());
+        self
+    }
+
+    /// Run the program through a [`Pipeline`] of passes.
+    ///
+    /// Every file has its own [`Context`], so information left by one
+    /// pipeline is available to the next. Any [`Diagnostics`] are added to
+    /// [`GcodeFile::diagnostics()`].
+    pub fn transform(mut self, mut pipeline: Pipeline) -> Self {
+        let mut raised = pipeline.run(&mut self.program, &mut self.context);
+        self.diagnostics.append(&mut raised);
+
+        if let Some(profile) = &self.profile {
+            self.analysis = Some(profile.analyzer().analyze(&self.text()));
+        }
+
+        self
+    }
+
+    /// Write the program back out, formatted using the provided
+    /// [`WriterConfig`].
+    ///
+    /// Use the [`Display`] impl instead to get the text exactly as it is.
+    /// This fails if a word can't be written back out.
+    pub fn write(&self, config: &WriterConfig) -> Result<String, fmt::Error> {
+        let mut out = String::new();
+        writer::reformat(&self.text(), self.dialect(), config, &mut out)?;
+        Ok(out)
+    }
+
+    /// The [`Dialect`] the program is written in.
+    pub fn dialect(&self) -> &Dialect { self.program.dialect() }
+
+    /// The program itself.
+    pub fn program(&self) -> &Program { &self.program }
+
+    /// Get mutable access to the program, for edits a [`Pipeline`] can't
+    /// make.
+    ///
+    /// Unlike [`GcodeFile::transform()`], this won't refresh the
+    /// [`GcodeFile::analysis()`].
+    pub fn program_mut(&mut self) -> &mut Program { &mut self.program }
+
+    /// The machine the program was last analyzed for, if any.
+    pub fn profile(&self) -> Option<&MachineProfile> { self.profile.as_ref() }
+
+    /// The estimated timeline, if [`GcodeFile::analyze()`] has been called.
+    pub fn analysis(&self) -> Option<&Analysis> { self.analysis.as_ref() }
+
+    /// The [`Context`] shared by every [`Pipeline`] run on this file.
+    pub fn context(&self) -> &Context { &self.context }
+
+    /// Get mutable access to the [`Context`] (e.g. to set values a pass
+    /// reads).
+    pub fn context_mut(&mut self) -> &mut Context { &mut self.context }
+
+    /// Everything reported by the passes run so far.
+    pub fn diagnostics(&self) -> &Diagnostics { &self.diagnostics }
+
+    /// Give up the file's bookkeeping and just keep the [`Program`].
+    pub fn into_program(self) -> Program { self.program }
+
+    fn text(&self) -> String { self.program.to_string() }
+}
+
+impl From<Program> for GcodeFile {
+    fn from(program: Program) -> Self {
+        GcodeFile {
+            program,
+            profile: None,
+            analysis: None,
+            context: Context::new(),
+            diagnostics: Diagnostics::new(),
+        }
+    }
+}
+
+impl Display for GcodeFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { self.program.fmt(f) }
+}
+
+/// Something the parser couldn't make sense of.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct SyntaxError {
+    /// What was wrong.
+    pub message: String,
+    /// Where the problem is.
+    pub span: Span,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.span.line + 1, self.message)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+/// Parse `src` and return the first thing the parser didn't understand,
+/// ignoring `%` tape markers and the text given to [`TEXT_COMMANDS`].
+pub(crate) fn first_syntax_error(
+    src: &str,
+    dialect: Dialect,
+) -> Option<SyntaxError> {
+    let mut problems = Problems { src, first: None };
+    let lines = Parser::<_>::new_with_dialect(src, &mut problems, dialect);
+    let _ = lines.count();
+
+    problems.first
+}
+
+/// [`Callbacks`] which remember the first problem.
+#[derive(Debug)]
+struct Problems<'src> {
+    src: &'src str,
+    first: Option<SyntaxError>,
+}
+
+/// Miscellaneous commands which take free-form text (a message or a
+/// filename) instead of arguments.
+const TEXT_COMMANDS: &[u32] = &[23, 28, 32, 117, 118, 928];
+
+impl<'src> Problems<'src> {
+    fn report(&mut self, span: Span, message: String) {
+        if self.first.is_some() {
+            return;
+        }
+
+        let (start, line) = self.line_containing(span);
+        if let Some(text) = text_argument(line) {
+            if span.start >= start + text {
+                return;
+            }
+        }
+
+        self.first = Some(SyntaxError { message, span });
+    }
+
+    /// The line `span` starts on, and where that line starts.
+    fn line_containing(&self, span: Span) -> (usize, &'src str) {
+        let src = self.src;
+        let offset = span.start.min(src.len());
+        let start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let end = src[offset..].find('\n').map_or(src.len(), |i| offset + i);
+
+        (start, &src[start..end])
+    }
+}
+
+/// If `line` is one of the [`TEXT_COMMANDS`], where its text starts.
+fn text_argument(line: &str) -> Option<usize> {
+    fn skip_digits(text: &str) -> &str {
+        text.trim_start_matches(|c: char| c.is_ascii_digit())
+    }
+
+    let rest = line.trim_start();
+    let rest = match rest.strip_prefix(|c| c == 'N' || c == 'n') {
+        Some(number) => skip_digits(number).trim_start(),
+        None => rest,
+    };
+    let digits = rest.strip_prefix(|c| c == 'M' || c == 'm')?;
+    let after = skip_digits(digits);
+    let number: u32 = digits[..digits.len() - after.len()].parse().ok()?;
+
+    if TEXT_COMMANDS.contains(&number) {
+        Some(line.len() - after.len())
+    } else {
+        None
+    }
+}
+
+impl Callbacks for Problems<'_> {
+    fn unknown_content(&mut self, text: &str, span: Span) {
+        if writer::is_tape_marker(text)
+            && writer::is_tape_marker(self.line_containing(span).1)
+        {
+            return;
+        }
+
+        self.report(span, format!("unknown content \"{}\"", text));
+    }
+
+    fn gcode_buffer_overflowed(
+        &mut self,
+        _mnemonic: Mnemonic,
+        _major_number: u32,
+        _minor_number: u32,
+        _arguments: &[Word],
+        span: Span,
+    ) {
+        self.report(span, "too many commands".to_string());
+    }
+
+    fn unexpected_line_number(&mut self, line_number: f32, span: Span) {
+        self.report(span, format!("unexpected line number N{}", line_number));
+    }
+
+    fn argument_without_a_command(
+        &mut self,
+        letter: char,
+        value: f32,
+        span: Span,
+    ) {
+        self.report(
+            span,
+            format!("argument without a command ({}{})", letter, value),
+        );
+    }
+
+    fn number_without_a_letter(&mut self, value: &str, span: Span) {
+        self.report(span, format!("number without a letter ({})", value));
+    }
+
+    fn letter_without_a_number(&mut self, value: &str, span: Span) {
+        self.report(span, format!("letter without a number ({})", value));
+    }
+
+    fn parameter_assignment(&mut self, assignment: &str, span: Span) {
+        self.report(span, format!("parameter assignment ({})", assignment));
+    }
+
+    fn checksum_mismatch(&mut self, written: u32, calculated: u8, span: Span) {
+        self.report(
+            span,
+            format!("checksum {} should be {}", written, calculated),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{Pass, Severity};
+
+    #[test]
+    fn parse_fails_on_the_first_problem() {
+        let err = GcodeFile::parse("%\nG1 X5\nG1 Y$ Z2\nQ\n%").unwrap_err();
+
+        assert_eq!(err.span.line, 2);
+        assert_eq!(err.to_string(), "line 3: unknown content \"$ \"");
+    }
+
+    #[test]
+    fn tape_markers_are_fine_with_crlf() {
+        let file = GcodeFile::parse("%\r\nG1 X1\r\n%\r\n").unwrap();
+
+        assert_eq!(file.program().lines().len(), 3);
+    }
+
+    #[test]
+    fn the_example_programs_parse() {
+        let programs = [
+            include_str!("../tests/data/program_1.gcode"),
+            include_str!("../tests/data/program_2.gcode"),
+            include_str!("../tests/data/program_3.gcode"),
+            include_str!("../tests/data/PI_octcat.gcode"),
+            include_str!("../tests/data/PI_rustlogo.gcode"),
+            include_str!(
+                "../tests/data/Insulpro.Piping.-.115mm.OD.-.40mm.WT.txt"
+            ),
+        ];
+
+        for src in &programs {
+            if let Err(e) = GcodeFile::parse(src) {
+                panic!("{} ({:?})", e, e.span.get_text(src));
+            }
+        }
+    }
+
+    #[test]
+    fn messages_and_filenames_arent_syntax_errors() {
+        let src = "M117 Printing 50% done\nN3 M23 part.gco\nG1 X1 $\nM30";
+
+        let err =
+            GcodeFile::parse_with_dialect(src, Dialect::reprap()).unwrap_err();
+
+        assert_eq!(err.span.line, 2);
+    }
+
+    /// Moves another 10mm, and says so.
+    #[derive(Debug)]
+    struct GoFurther;
+
+    impl Pass for GoFurther {
+        fn name(&self) -> &str { "go-further" }
+
+        fn run(
+            &mut self,
+            program: &mut Program,
+            context: &mut Context,
+        ) -> Diagnostics {
+            program.push_line("G1 X10");
+            let _ = context.set("went-further", "yes");
+
+            let mut diagnostics = Diagnostics::new();
+            diagnostics.report(Severity::Info, "went further");
+            diagnostics
+        }
+    }
+
+    #[test]
+    fn transforming_keeps_the_analysis_up_to_date() {
+        let file = GcodeFile::parse("G21 G91\nG1 X10 F600")
+            .unwrap()
+            .analyze(&MachineProfile::new("test"));
+        let before = file.analysis().unwrap().total_time();
+
+        let file = file.transform(Pipeline::new().with_pass(GoFurther));
+
+        let after = file.analysis().unwrap().total_time();
+        assert!(
+            (after - 2.0 * before).abs() < 1e-3,
+            "{} vs {}",
+            after,
+            before
+        );
+        assert_eq!(file.context().get("went-further"), Some("yes"));
+        assert_eq!(file.diagnostics().len(), 1);
+        assert_eq!(file.to_string(), "G21 G91\nG1 X10 F600\nG1 X10\n");
+    }
+}
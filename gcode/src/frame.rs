@@ -0,0 +1,556 @@
+//! A compact binary format for sending parsed commands to firmware.
+//!
+//! Parsing text is a lot of work for a small microcontroller. Instead, a
+//! host can use this crate to parse a program and send each [`GCode`] as a
+//! [`Frame`], which firmware decodes with a [`FrameDecoder`] without needing
+//! an allocator or a floating point parser.
+//!
+//! Each frame is laid out as:
+//!
+//! | Bytes | Contents                                                 |
+//! | ----- | -------------------------------------------------------- |
+//! | 1     | [`SYNC`], marking the start of a frame                   |
+//! | 1     | the length of the payload                                |
+//! | 1     | a sequence number, so dropped frames can be detected     |
+//! | 1     | the command's letter (e.g. `G`)                          |
+//! | 2     | the command's major number (little endian)               |
+//! | 1     | the command's minor number                               |
+//! | ...   | the arguments                                            |
+//! | 2     | a CRC-16 of everything after the length (little endian)  |
+//!
+//! Each argument is its letter followed by its value as a little endian
+//! `i32` in fixed point (see [`SCALE`]). A letter with its top bit set is a
+//! flag with no value (e.g. the `X` in `G28 X`).
+//!
+//! ```rust
+//! use gcode::{
+//!     frame::{FrameDecoder, FrameEncoder, MAX_FRAME_LENGTH},
+//!     Mnemonic,
+//! };
+//!
+//! let mut encoder = FrameEncoder::new();
+//! let mut decoder = FrameDecoder::new();
+//! let mut buffer = [0; MAX_FRAME_LENGTH];
+//!
+//! for gcode in gcode::parse("G1 X10.5 Y-2 F3000\nG28 Z") {
+//!     let bytes = encoder.encode(&gcode, &mut buffer).unwrap();
+//!
+//!     // ... send the bytes to the firmware, which feeds them to a decoder
+//!     for &byte in bytes {
+//!         if let Some(frame) = decoder.push_byte(byte) {
+//!             let frame = frame.unwrap();
+//!             assert_eq!(frame.mnemonic, Mnemonic::General);
+//!             assert_eq!(frame.major_number, gcode.major_number() as u16);
+//!         }
+//!     }
+//! }
+//!
+//! let last = decoder.last_frame().unwrap();
+//! assert_eq!(last.sequence, 1);
+//! assert!(last.argument('Z').unwrap().is_flag());
+//! ```
+
+use crate::{buffers::Buffer, GCode, Mnemonic, Word};
+use arrayvec::ArrayVec;
+use core::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+};
+
+/// The byte every frame starts with.
+pub const SYNC: u8 = 0xA5;
+
+/// Argument values are sent as a multiple of `1 / SCALE`, giving 4 decimal
+/// places and a range of roughly ±214,748.
+pub const SCALE: f32 = 10_000.0;
+
+/// The most arguments a single [`Frame`] can carry.
+pub const MAX_ARGUMENTS: usize = 16;
+
+/// The number of bytes in a payload before the arguments.
+const HEADER_LENGTH: usize = 5;
+
+/// The number of bytes around the payload (the sync byte, length and CRC).
+const OVERHEAD: usize = 4;
+
+/// The flag bit set on an argument's letter when it has no value.
+const FLAG: u8 = 0x80;
+
+/// The longest a frame can possibly be, in bytes.
+pub const MAX_FRAME_LENGTH: usize =
+    OVERHEAD + HEADER_LENGTH + MAX_ARGUMENTS * 5;
+
+/// Storage for a whole frame (`arrayvec` only supports some array sizes, so
+/// this is a bit bigger than [`MAX_FRAME_LENGTH`]).
+type FrameBuffer = ArrayVec<[u8; 96]>;
+
+/// A single argument in a [`Frame`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Argument {
+    /// The argument's (uppercase) letter.
+    pub letter: char,
+    /// The value in fixed point (see [`SCALE`]), or `None` for a flag.
+    pub fixed: Option<i32>,
+}
+
+impl Argument {
+    /// The argument's value.
+    pub fn value(&self) -> Option<f32> {
+        self.fixed.map(|fixed| fixed as f32 / SCALE)
+    }
+
+    /// Is this a letter without a value?
+    pub fn is_flag(&self) -> bool { self.fixed.is_none() }
+}
+
+/// A command decoded by a [`FrameDecoder`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Frame {
+    /// The frame's sequence number, which wraps around after 255.
+    pub sequence: u8,
+    /// The kind of command.
+    pub mnemonic: Mnemonic,
+    /// The integral part of the command number (the `38` in `G38.2`).
+    pub major_number: u16,
+    /// The fractional part of the command number (the `2` in `G38.2`).
+    pub minor_number: u8,
+    /// The command's arguments, in the order they were written.
+    pub arguments: ArrayVec<[Argument; MAX_ARGUMENTS]>,
+}
+
+impl Frame {
+    /// Find the argument with a particular letter.
+    pub fn argument(&self, letter: char) -> Option<&Argument> {
+        let letter = letter.to_ascii_uppercase();
+        self.arguments.iter().find(|arg| arg.letter == letter)
+    }
+
+    /// Get the value for a particular argument.
+    pub fn value_for(&self, letter: char) -> Option<f32> {
+        self.argument(letter).and_then(Argument::value)
+    }
+}
+
+/// Turns [`GCode`]s into frames, numbering each one.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FrameEncoder {
+    sequence: u8,
+}
+
+impl FrameEncoder {
+    /// Create a new [`FrameEncoder`], starting at sequence number 0.
+    pub fn new() -> Self { FrameEncoder::default() }
+
+    /// The sequence number the next frame will use.
+    pub fn next_sequence(&self) -> u8 { self.sequence }
+
+    /// Encode a [`GCode`] into `buffer`, returning the bytes of the frame.
+    ///
+    /// The sequence number is only used up when encoding succeeds.
+    pub fn encode<'buf, A: Buffer<Word>>(
+        &mut self,
+        gcode: &GCode<A>,
+        buffer: &'buf mut [u8],
+    ) -> Result<&'buf [u8], EncodeError> {
+        let length = encode(gcode, self.sequence, buffer)?;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        Ok(&buffer[..length])
+    }
+}
+
+/// Encode a [`GCode`] into `buffer` with a particular sequence number,
+/// returning the length of the frame.
+pub fn encode<A: Buffer<Word>>(
+    gcode: &GCode<A>,
+    sequence: u8,
+    buffer: &mut [u8],
+) -> Result<usize, EncodeError> {
+    let arguments = gcode.arguments();
+    if arguments.len() > MAX_ARGUMENTS {
+        return Err(EncodeError::TooManyArguments);
+    }
+
+    let mut payload = FrameBuffer::new();
+    let major = u16::try_from(gcode.key().major).map_err(|_| {
+        EncodeError::OutOfRange {
+            letter: mnemonic_letter(gcode.mnemonic()) as char,
+        }
+    })?;
+
+    payload.push(sequence);
+    payload.push(mnemonic_letter(gcode.mnemonic()));
+    payload.extend(major.to_le_bytes().iter().copied());
+    payload.push(gcode.minor_number() as u8);
+
+    for word in arguments {
+        let letter = word.letter.to_ascii_uppercase();
+        if !letter.is_ascii_alphabetic() {
+            return Err(EncodeError::InvalidLetter { letter });
+        }
+
+        if word.is_flag() {
+            payload.push(letter as u8 | FLAG);
+            continue;
+        }
+
+        let value = word.number().ok_or(EncodeError::NotANumber { letter })?;
+        let fixed = libm::roundf(value * SCALE);
+        if !(i32::MIN as f32..=i32::MAX as f32).contains(&fixed) {
+            return Err(EncodeError::OutOfRange { letter });
+        }
+
+        payload.push(letter as u8);
+        payload.extend((fixed as i32).to_le_bytes().iter().copied());
+    }
+
+    let length = payload.len() + OVERHEAD;
+    if buffer.len() < length {
+        return Err(EncodeError::BufferTooSmall { required: length });
+    }
+
+    let crc = crc16(&payload);
+    buffer[0] = SYNC;
+    buffer[1] = payload.len() as u8;
+    buffer[2..length - 2].copy_from_slice(&payload);
+    buffer[length - 2..length].copy_from_slice(&crc.to_le_bytes());
+
+    Ok(length)
+}
+
+/// Reasons a [`GCode`] couldn't be encoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum EncodeError {
+    /// The command has more than [`MAX_ARGUMENTS`] arguments.
+    TooManyArguments,
+    /// An argument's letter isn't an ASCII letter.
+    InvalidLetter {
+        /// The letter.
+        letter: char,
+    },
+    /// An argument is an expression which hasn't been evaluated.
+    NotANumber {
+        /// The argument's letter.
+        letter: char,
+    },
+    /// A value is too big to be sent.
+    OutOfRange {
+        /// The letter of the argument or command.
+        letter: char,
+    },
+    /// The output buffer can't hold the frame.
+    BufferTooSmall {
+        /// How many bytes the frame needs.
+        required: usize,
+    },
+}
+
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::TooManyArguments => write!(
+                f,
+                "a frame can't hold more than {} arguments",
+                MAX_ARGUMENTS
+            ),
+            EncodeError::InvalidLetter { letter } => {
+                write!(f, "\"{}\" can't be sent as an argument", letter)
+            },
+            EncodeError::NotANumber { letter } => {
+                write!(f, "the {} argument isn't a number", letter)
+            },
+            EncodeError::OutOfRange { letter } => {
+                write!(f, "the {} value is too big to send", letter)
+            },
+            EncodeError::BufferTooSmall { required } => {
+                write!(f, "the frame needs a {} byte buffer", required)
+            },
+        }
+    }
+}
+
+with_std! {
+    impl std::error::Error for EncodeError {}
+}
+
+/// Reads frames a byte at a time, e.g. as they arrive over a serial port.
+///
+/// Any bytes before a [`SYNC`] byte are skipped, so the decoder can pick up
+/// again after a corrupted frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameDecoder {
+    buffer: FrameBuffer,
+    last_frame: Option<Frame>,
+}
+
+impl FrameDecoder {
+    /// Create a new [`FrameDecoder`].
+    pub fn new() -> Self {
+        FrameDecoder {
+            buffer: ArrayVec::new(),
+            last_frame: None,
+        }
+    }
+
+    /// The most recent frame which was decoded successfully.
+    pub fn last_frame(&self) -> Option<&Frame> { self.last_frame.as_ref() }
+
+    /// Throw away any partially received frame.
+    pub fn reset(&mut self) { self.buffer.clear(); }
+
+    /// Feed the decoder another byte, returning the result once a whole
+    /// frame has arrived.
+    pub fn push_byte(
+        &mut self,
+        byte: u8,
+    ) -> Option<Result<Frame, DecodeError>> {
+        if self.buffer.is_empty() && byte != SYNC {
+            return None;
+        }
+        self.buffer.push(byte);
+
+        let payload_length = usize::from(*self.buffer.get(1)?);
+        if payload_length < HEADER_LENGTH
+            || payload_length + OVERHEAD > MAX_FRAME_LENGTH
+        {
+            self.buffer.clear();
+            return Some(Err(DecodeError::InvalidLength));
+        }
+        if self.buffer.len() < payload_length + OVERHEAD {
+            return None;
+        }
+
+        let result = decode(&self.buffer).map(|(frame, _)| frame);
+        self.buffer.clear();
+        if let Ok(ref frame) = result {
+            self.last_frame = Some(frame.clone());
+        }
+
+        Some(result)
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> FrameDecoder { FrameDecoder::new() }
+}
+
+/// Decode the frame at the start of `bytes`, returning it and the number of
+/// bytes it used.
+pub fn decode(bytes: &[u8]) -> Result<(Frame, usize), DecodeError> {
+    if bytes.first() != Some(&SYNC) {
+        return Err(DecodeError::MissingSync);
+    }
+    let payload_length =
+        usize::from(*bytes.get(1).ok_or(DecodeError::Incomplete)?);
+    if payload_length < HEADER_LENGTH {
+        return Err(DecodeError::InvalidLength);
+    }
+    let length = payload_length + OVERHEAD;
+    if bytes.len() < length {
+        return Err(DecodeError::Incomplete);
+    }
+
+    let payload = &bytes[2..length - 2];
+    let crc = u16::from_le_bytes([bytes[length - 2], bytes[length - 1]]);
+    if crc16(payload) != crc {
+        return Err(DecodeError::CrcMismatch);
+    }
+
+    let mnemonic = Mnemonic::for_letter(payload[1] as char)
+        .ok_or(DecodeError::Malformed)?;
+    let mut frame = Frame {
+        sequence: payload[0],
+        mnemonic,
+        major_number: u16::from_le_bytes([payload[2], payload[3]]),
+        minor_number: payload[4],
+        arguments: ArrayVec::new(),
+    };
+
+    let mut rest = &payload[HEADER_LENGTH..];
+    while let Some((&letter, tail)) = rest.split_first() {
+        let argument = if letter & FLAG != 0 {
+            rest = tail;
+            Argument {
+                letter: (letter & !FLAG) as char,
+                fixed: None,
+            }
+        } else {
+            if tail.len() < 4 {
+                return Err(DecodeError::Malformed);
+            }
+            rest = &tail[4..];
+            Argument {
+                letter: letter as char,
+                fixed: Some(i32::from_le_bytes([
+                    tail[0], tail[1], tail[2], tail[3],
+                ])),
+            }
+        };
+
+        if !argument.letter.is_ascii_alphabetic() {
+            return Err(DecodeError::Malformed);
+        }
+        frame
+            .arguments
+            .try_push(argument)
+            .map_err(|_| DecodeError::Malformed)?;
+    }
+
+    Ok((frame, length))
+}
+
+/// Reasons a frame couldn't be decoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum DecodeError {
+    /// The bytes didn't start with [`SYNC`].
+    MissingSync,
+    /// The frame hasn't finished arriving yet.
+    Incomplete,
+    /// The length byte is too small or too big for a frame.
+    InvalidLength,
+    /// The frame was corrupted on the way.
+    CrcMismatch,
+    /// The frame had a valid CRC but its contents didn't make sense.
+    Malformed,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::MissingSync => write!(f, "missing sync byte"),
+            DecodeError::Incomplete => write!(f, "incomplete frame"),
+            DecodeError::InvalidLength => write!(f, "invalid frame length"),
+            DecodeError::CrcMismatch => write!(f, "CRC mismatch"),
+            DecodeError::Malformed => write!(f, "malformed frame"),
+        }
+    }
+}
+
+with_std! {
+    impl std::error::Error for DecodeError {}
+}
+
+/// The CRC-16/CCITT-FALSE checksum (polynomial `0x1021`, starting at
+/// `0xFFFF`) used by every frame.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xFFFF, |crc, &byte| {
+        let mut crc = crc ^ (u16::from(byte) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn mnemonic_letter(mnemonic: Mnemonic) -> u8 {
+    match mnemonic {
+        Mnemonic::General => b'G',
+        Mnemonic::Miscellaneous => b'M',
+        Mnemonic::ProgramNumber => b'O',
+        Mnemonic::ToolChange => b'T',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Span, WordValue};
+
+    fn frame_for(src: &str) -> Frame {
+        let gcode = crate::parse(src).next().unwrap();
+        let mut buffer = [0; MAX_FRAME_LENGTH];
+        let length = encode(&gcode, 7, &mut buffer).unwrap();
+
+        let (frame, used) = decode(&buffer[..length]).unwrap();
+        assert_eq!(used, length);
+        frame
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn round_trip_a_command() {
+        let got = frame_for("G38.2 Z-10.25 F100 X");
+
+        assert_eq!(got.sequence, 7);
+        assert_eq!(got.mnemonic, Mnemonic::General);
+        assert_eq!((got.major_number, got.minor_number), (38, 2));
+        assert_eq!(got.value_for('z'), Some(-10.25));
+        assert_eq!(got.argument('F').unwrap().fixed, Some(1_000_000));
+        assert!(got.argument('X').unwrap().is_flag());
+    }
+
+    #[test]
+    fn corrupted_frames_are_rejected_and_skipped() {
+        let gcode = crate::parse("M104 S210").next().unwrap();
+        let mut encoder = FrameEncoder::new();
+        let mut first = [0; MAX_FRAME_LENGTH];
+        let mut second = [0; MAX_FRAME_LENGTH];
+        let length = encoder.encode(&gcode, &mut first).unwrap().len();
+        let second = encoder.encode(&gcode, &mut second).unwrap();
+        // flip a bit in the command number
+        first[5] ^= 0x01;
+        let mut decoder = FrameDecoder::new();
+
+        let mut results = ArrayVec::<[_; 4]>::new();
+        // line noise, then the corrupted frame, then a good frame
+        for &byte in [0x00, 0x42].iter().chain(&first[..length]).chain(second) {
+            results.extend(decoder.push_byte(byte));
+        }
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0], Err(DecodeError::CrcMismatch));
+        let frame = results[1].as_ref().unwrap();
+        assert_eq!(frame.sequence, 1);
+        assert_eq!(frame.value_for('S'), Some(210.0));
+    }
+
+    #[test]
+    fn commands_which_cant_be_sent() {
+        let mut buffer = [0; MAX_FRAME_LENGTH];
+        let too_big = GCode::new(Mnemonic::General, 1.0, Span::default())
+            .with_argument(Word::new('X', 1e6, Span::default()));
+        let expression = GCode::new(Mnemonic::General, 1.0, Span::default())
+            .with_argument(Word {
+                letter: 'X',
+                value: WordValue::Expression(Span::default()),
+                span: Span::default(),
+            });
+
+        assert_eq!(
+            encode(&too_big, 0, &mut buffer),
+            Err(EncodeError::OutOfRange { letter: 'X' })
+        );
+        assert_eq!(
+            encode(&expression, 0, &mut buffer),
+            Err(EncodeError::NotANumber { letter: 'X' })
+        );
+        assert_eq!(
+            encode(&crate::parse("G0 X1").next().unwrap(), 0, &mut buffer[..8]),
+            Err(EncodeError::BufferTooSmall { required: 14 })
+        );
+    }
+}
@@ -1,11 +1,16 @@
 use crate::{
     buffers::{Buffer, CapacityError, DefaultArguments},
+    parser::{parse_single_gcode, ParseError},
     Span, Word,
 };
-use core::fmt::{self, Debug, Display, Formatter};
+use core::{
+    convert::TryFrom,
+    fmt::{self, Debug, Display, Formatter},
+    str::FromStr,
+};
 
 /// The general category for a [`GCode`].
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[cfg_attr(
     feature = "serde-1",
     derive(serde_derive::Serialize, serde_derive::Deserialize)
@@ -55,6 +60,137 @@ impl Display for Mnemonic {
     }
 }
 
+/// Identifies a kind of command (e.g. `G38.2` or `M104`), ignoring its
+/// arguments.
+///
+/// Unlike a [`GCode`]'s `number`, a [`CommandKey`] doesn't contain any
+/// floats, so it can be compared, hashed and sorted, making it a good key
+/// for lookup tables and histograms.
+///
+/// ```rust
+/// use gcode::{CommandKey, GCode, Mnemonic};
+///
+/// let gcode: GCode = "G38.2 Z-10".parse().unwrap();
+///
+/// assert_eq!(gcode.key(), CommandKey::new(Mnemonic::General, 38, 2));
+/// assert_eq!(gcode.key().to_string(), "G38.2");
+/// assert_eq!("g01".parse::<CommandKey>().unwrap(), CommandKey::general(1));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct CommandKey {
+    /// The general category.
+    pub mnemonic: Mnemonic,
+    /// The integral part of the command number (i.e. the `12` in `G12.3`).
+    ///
+    /// This is only negative for tool and program numbers (e.g.
+    /// RepRapFirmware's `T-1`).
+    pub major: i32,
+    /// The digits after the decimal point (i.e. the `3` in `G12.3`, or the
+    /// `25` in `G38.25`).
+    pub minor: u32,
+    /// How many digits [`CommandKey::minor`] is written with, so the `5` in
+    /// `G12.05` can be told apart from the one in `G12.5`.
+    pub minor_digits: u8,
+}
+
+impl CommandKey {
+    /// Create a new [`CommandKey`], assuming the minor number is written
+    /// without any leading zeroes.
+    pub const fn new(mnemonic: Mnemonic, major: i32, minor: u32) -> Self {
+        let mut minor_digits = 0;
+        let mut remaining = minor;
+        while remaining != 0 {
+            minor_digits += 1;
+            remaining /= 10;
+        }
+
+        CommandKey::with_minor_digits(mnemonic, major, minor, minor_digits)
+    }
+
+    /// Create a new [`CommandKey`] whose minor number has leading zeroes
+    /// (e.g. `G12.05`).
+    pub const fn with_minor_digits(
+        mnemonic: Mnemonic,
+        major: i32,
+        minor: u32,
+        minor_digits: u8,
+    ) -> Self {
+        CommandKey {
+            mnemonic,
+            major,
+            minor,
+            minor_digits,
+        }
+    }
+
+    /// The key for a `G` command without a minor number (e.g. `G1`).
+    pub const fn general(major: i32) -> Self {
+        CommandKey::new(Mnemonic::General, major, 0)
+    }
+
+    /// The key for an `M` command without a minor number (e.g. `M104`).
+    pub const fn miscellaneous(major: i32) -> Self {
+        CommandKey::new(Mnemonic::Miscellaneous, major, 0)
+    }
+}
+
+impl CommandKey {
+    /// The command number as it would be written (e.g. `38.25` for
+    /// `G38.25`).
+    pub fn number(&self) -> f32 {
+        let minor =
+            self.minor as f32 / libm::powf(10.0, f32::from(self.minor_digits));
+
+        if self.major < 0 {
+            self.major as f32 - minor
+        } else {
+            self.major as f32 + minor
+        }
+    }
+
+    /// Write the `.05` in `G12.05`, if there is a minor number.
+    pub(crate) fn write_minor<W: fmt::Write>(
+        &self,
+        out: &mut W,
+    ) -> fmt::Result {
+        if self.minor == 0 {
+            return Ok(());
+        }
+
+        write!(out, ".{:01$}", self.minor, usize::from(self.minor_digits))
+    }
+}
+
+impl Display for CommandKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.mnemonic, self.major)?;
+        self.write_minor(f)
+    }
+}
+
+impl FromStr for CommandKey {
+    type Err = ParseError;
+
+    /// Parse a command on its own (e.g. `"G38.2"` or `"m6"`), without any
+    /// arguments.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let gcode: GCode = s.parse()?;
+
+        match gcode.arguments().first() {
+            Some(argument) => Err(ParseError::Unexpected(argument.span)),
+            None => Ok(gcode.key()),
+        }
+    }
+}
+
+impl<A: Buffer<Word>> From<&GCode<A>> for CommandKey {
+    fn from(gcode: &GCode<A>) -> Self { gcode.key() }
+}
+
 /// The in-memory representation of a single command in the G-code language
 /// (e.g. `"G01 X50.0 Y-20.0"`).
 #[derive(Clone)]
@@ -82,9 +218,58 @@ impl GCode {
     }
 }
 
+/// A [`GCode`] which borrows its arguments instead of owning them.
+///
+/// Everything needed to create one can be done in a `const` context, so
+/// firmware can keep fixed sequences of commands in a `static` table without
+/// parsing them at runtime.
+///
+/// ```rust
+/// use gcode::{BorrowedGCode, Mnemonic, Span, Word};
+///
+/// const fn word(letter: char, value: f32) -> Word {
+///     Word::new(letter, value, Span::PLACEHOLDER)
+/// }
+///
+/// static HOMING: [BorrowedGCode<'static>; 3] = [
+///     BorrowedGCode::new_borrowed(Mnemonic::General, 28.0, &[
+///         Word::flag('X', Span::PLACEHOLDER),
+///         Word::flag('Y', Span::PLACEHOLDER),
+///     ]),
+///     BorrowedGCode::new_borrowed(Mnemonic::General, 90.0, &[]),
+///     BorrowedGCode::new_borrowed(Mnemonic::General, 0.0, &[
+///         word('X', 10.0),
+///         word('Y', 10.0),
+///     ]),
+/// ];
+///
+/// assert_eq!(HOMING[0].to_string(), "G28 X Y");
+/// assert_eq!(HOMING[2].value_for('Y'), Some(10.0));
+/// ```
+pub type BorrowedGCode<'a> = GCode<&'a [Word]>;
+
+impl<'a> GCode<&'a [Word]> {
+    /// Create a new [`BorrowedGCode`] with a [`Span::PLACEHOLDER`] span.
+    ///
+    /// Arguments can't be added to the result later on, because the slice
+    /// they're stored in is read-only.
+    pub const fn new_borrowed(
+        mnemonic: Mnemonic,
+        number: f32,
+        arguments: &'a [Word],
+    ) -> Self {
+        GCode {
+            mnemonic,
+            number,
+            arguments,
+            span: Span::PLACEHOLDER,
+        }
+    }
+}
+
 impl<A: Buffer<Word>> GCode<A> {
     /// Create a new [`GCode`] which uses a custom [`Buffer`].
-    pub fn new_with_argument_buffer(
+    pub const fn new_with_argument_buffer(
         mnemonic: Mnemonic,
         number: f32,
         span: Span,
@@ -98,18 +283,46 @@ impl<A: Buffer<Word>> GCode<A> {
         }
     }
 
+    /// The general category this [`GCode`] belongs to.
+    pub fn mnemonic(&self) -> Mnemonic { self.mnemonic }
+
     /// The integral part of a command number (i.e. the `12` in `G12.3`).
+    ///
+    /// Tool and program numbers can be negative (e.g. RepRapFirmware's
+    /// `T-1`), which gives `0` here, so use [`GCode::key()`] when that
+    /// matters.
     pub fn major_number(&self) -> u32 {
-        debug_assert!(self.number >= 0.0);
-
-        libm::floorf(self.number) as u32
+        u32::try_from(self.key().major).unwrap_or(0)
     }
 
-    /// The fractional part of a command number (i.e. the `3` in `G12.3`).
-    pub fn minor_number(&self) -> u32 {
-        let fract = self.number - libm::floorf(self.number);
-        let digit = libm::roundf(fract * 10.0);
-        digit as u32
+    /// The digits after the decimal point in a command number (i.e. the `3`
+    /// in `G12.3`, or the `25` in `G38.25`).
+    pub fn minor_number(&self) -> u32 { self.key().minor }
+
+    /// The [`CommandKey`] identifying what kind of command this is.
+    pub fn key(&self) -> CommandKey {
+        let major = libm::truncf(self.number);
+        // an f32 only has about 7 significant digits, so anything after the
+        // third decimal place is noise
+        let fraction = libm::roundf(libm::fabsf(self.number - major) * 1000.0);
+        let mut minor = fraction.min(999.0) as u32;
+        let mut minor_digits = 3;
+
+        while minor != 0 && minor.is_multiple_of(10) {
+            minor /= 10;
+            minor_digits -= 1;
+        }
+
+        if minor == 0 {
+            minor_digits = 0;
+        }
+
+        CommandKey::with_minor_digits(
+            self.mnemonic,
+            major as i32,
+            minor,
+            minor_digits,
+        )
     }
 
     /// The arguments attached to this [`GCode`].
@@ -117,6 +330,37 @@ impl<A: Buffer<Word>> GCode<A> {
         self.arguments.as_slice()
     }
 
+    /// Where this [`GCode`] lies in the original string.
+    pub fn span(&self) -> Span { self.span }
+
+    /// Is this a line of arguments which relies on the command from an
+    /// earlier line (e.g. the `X12 Y13` in `G1 X10 Y10\nX12 Y13`)?
+    ///
+    /// The parser fills in the most recent command word, but that isn't
+    /// always the command a controller would use (see
+    /// [`Interpreter::inherited_command()`]). A continuation's [`Span`] only
+    /// covers its arguments, because the command word was somewhere else.
+    ///
+    /// ```rust
+    /// use gcode::{GCode, Mnemonic, Span, Word};
+    ///
+    /// let gcodes: Vec<_> = gcode::parse("G1 X10 Y10\nX12 Y13").collect();
+    ///
+    /// assert!(!gcodes[0].is_modal_continuation());
+    /// assert!(gcodes[1].is_modal_continuation());
+    /// assert_eq!(gcodes[1].mnemonic, Mnemonic::General);
+    /// assert_eq!(gcodes[1].major_number(), 1);
+    /// ```
+    ///
+    /// [`Interpreter::inherited_command()`]: crate::interpret::Interpreter::inherited_command
+    pub fn is_modal_continuation(&self) -> bool {
+        !self.span.is_placeholder()
+            && self
+                .arguments()
+                .first()
+                .is_some_and(|first| first.span.start == self.span.start)
+    }
+
     /// Add an argument to the list of arguments attached to this [`GCode`].
     pub fn push_argument(
         &mut self,
@@ -151,19 +395,50 @@ impl<A: Buffer<Word>> GCode<A> {
     ///
     /// assert_eq!(gcode.value_for('Y'), Some(-3.14));
     /// ```
+    ///
+    /// Arguments without a number (e.g. the `X` in `G28 X`) are ignored, use
+    /// [`GCode::has_argument()`] to check for them.
     pub fn value_for(&self, letter: char) -> Option<f32> {
-        let letter = letter.to_ascii_lowercase();
+        self.argument(letter).and_then(|arg| arg.number())
+    }
 
-        for arg in self.arguments() {
-            if arg.letter.to_ascii_lowercase() == letter {
-                return Some(arg.value);
-            }
-        }
+    /// Was an argument with this letter provided, with or without a number?
+    ///
+    /// ```rust
+    /// let gcode: gcode::GCode = "G28 X Y0".parse().unwrap();
+    ///
+    /// assert!(gcode.has_argument('X'));
+    /// assert!(gcode.has_argument('y'));
+    /// assert!(!gcode.has_argument('Z'));
+    /// assert_eq!(gcode.value_for('X'), None);
+    /// ```
+    pub fn has_argument(&self, letter: char) -> bool {
+        self.argument(letter).is_some()
+    }
+
+    fn argument(&self, letter: char) -> Option<&Word> {
+        let letter = letter.to_ascii_lowercase();
 
-        None
+        self.arguments()
+            .iter()
+            .find(|arg| arg.letter.to_ascii_lowercase() == letter)
     }
 }
 
+impl FromStr for GCode {
+    type Err = ParseError;
+
+    /// Parse a single [`GCode`], failing if the text contains anything
+    /// else (apart from comments and a line number).
+    fn from_str(s: &str) -> Result<Self, Self::Err> { parse_single_gcode(s) }
+}
+
+impl<'a> TryFrom<&'a str> for GCode {
+    type Error = ParseError;
+
+    fn try_from(s: &'a str) -> Result<Self, Self::Error> { s.parse() }
+}
+
 impl<A: Buffer<Word>> Extend<Word> for GCode<A> {
     fn extend<I: IntoIterator<Item = Word>>(&mut self, words: I) {
         for word in words {
@@ -198,11 +473,7 @@ impl<A: Buffer<Word>> Debug for GCode<A> {
 
 impl<A: Buffer<Word>> Display for GCode<A> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}{}", self.mnemonic, self.major_number())?;
-
-        if self.minor_number() != 0 {
-            write!(f, ".{}", self.minor_number())?;
-        }
+        write!(f, "{}", self.key())?;
 
         for arg in self.arguments() {
             write!(f, " {}", arg)?;
@@ -235,8 +506,8 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::WordValue;
     use arrayvec::ArrayVec;
-    use std::prelude::v1::*;
 
     type BigBuffer = ArrayVec<[Word; 32]>;
 
@@ -266,6 +537,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn command_keys_are_total() {
+        let deselect: GCode = "T-1".parse().unwrap();
+        let probe: GCode = "G38.25".parse().unwrap();
+
+        assert_eq!(
+            deselect.key(),
+            CommandKey::new(Mnemonic::ToolChange, -1, 0)
+        );
+        assert_eq!(deselect.major_number(), 0);
+        assert_eq!(deselect.to_string(), "T-1");
+        assert_eq!(probe.key(), CommandKey::new(Mnemonic::General, 38, 25));
+        assert_eq!(probe.minor_number(), 25);
+        assert_eq!(probe.key().number(), 38.25);
+        assert_eq!("G38.2".parse::<GCode>().unwrap().minor_number(), 2);
+    }
+
+    #[test]
+    fn leading_zeroes_in_minor_numbers_are_kept() {
+        let padded: GCode = "G12.05 X1".parse().unwrap();
+        let plain: GCode = "G12.5 X1".parse().unwrap();
+
+        assert_ne!(padded.key(), plain.key());
+        assert_eq!(
+            padded.key(),
+            CommandKey::with_minor_digits(Mnemonic::General, 12, 5, 2)
+        );
+        assert_eq!(plain.key(), CommandKey::new(Mnemonic::General, 12, 5));
+        assert_eq!(padded.key().number(), 12.05);
+        assert_eq!(plain.key().number(), 12.5);
+        assert_eq!(padded.to_string(), "G12.05 X1");
+        assert_eq!(plain.to_string(), "G12.5 X1");
+        assert_eq!("G12.05".parse::<CommandKey>().unwrap(), padded.key());
+    }
+
     #[test]
     fn get_argument_values() {
         let mut code = GCode::new_with_argument_buffer(
@@ -276,13 +582,13 @@ mod tests {
         );
         code.push_argument(Word {
             letter: 'X',
-            value: 10.0,
+            value: WordValue::Number(10.0),
             span: Span::default(),
         })
         .unwrap();
         code.push_argument(Word {
             letter: 'y',
-            value: -3.5,
+            value: WordValue::Number(-3.5),
             span: Span::default(),
         })
         .unwrap();
@@ -292,4 +598,56 @@ mod tests {
         assert_eq!(code.value_for('Y'), Some(-3.5));
         assert_eq!(code.value_for('Z'), None);
     }
+
+    #[test]
+    fn parse_a_single_gcode() {
+        let code: GCode = "N10 G01 X5 (move) Y-2".parse().unwrap();
+
+        assert_eq!(code.mnemonic(), Mnemonic::General);
+        assert_eq!(code.major_number(), 1);
+        assert_eq!(code.value_for('X'), Some(5.0));
+        assert_eq!(code.value_for('Y'), Some(-2.0));
+
+        assert_eq!(
+            GCode::try_from("G0 M3"),
+            Err(ParseError::TooMany(Span::new(3, 5, 0)))
+        );
+        assert_eq!(
+            "X5".parse::<GCode>(),
+            Err(ParseError::Unexpected(Span::new(0, 2, 0)))
+        );
+        assert_eq!("\n".parse::<GCode>(), Err(ParseError::Empty));
+    }
+
+    #[test]
+    fn command_keys_ignore_formatting() {
+        let key: CommandKey = "g01".parse().unwrap();
+
+        assert_eq!(key, CommandKey::general(1));
+        assert_eq!(key, "G1".parse().unwrap());
+        assert_eq!(" M104 ".parse(), Ok(CommandKey::miscellaneous(104)));
+        assert!(
+            CommandKey::general(38) < CommandKey::new(Mnemonic::General, 38, 2)
+        );
+        assert!(CommandKey::general(99) < CommandKey::miscellaneous(0));
+        assert_eq!(
+            "G1 X5".parse::<CommandKey>(),
+            Err(ParseError::Unexpected(Span::new(3, 5, 0)))
+        );
+    }
+
+    #[test]
+    fn borrowed_gcodes_compare_with_parsed_ones() {
+        const ARGUMENTS: &[Word] = &[Word::new('X', 5.0, Span::PLACEHOLDER)];
+        const MOVE: BorrowedGCode<'static> =
+            GCode::new_borrowed(Mnemonic::General, 1.0, ARGUMENTS);
+
+        let parsed: GCode = "G1 X5".parse().unwrap();
+        assert_eq!(MOVE, parsed);
+
+        let mut copy = MOVE.clone();
+        let extra = Word::new('Y', 2.0, Span::PLACEHOLDER);
+        assert_eq!(copy.push_argument(extra), Err(CapacityError(extra)));
+        assert_eq!(copy.arguments(), ARGUMENTS);
+    }
 }
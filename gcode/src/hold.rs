@@ -0,0 +1,306 @@
+//! Finding where a program can safely be paused.
+//!
+//! A feed hold stops the machine wherever it happens to be, which isn't
+//! always harmless. Stopping part way through a threading pass loses the
+//! spindle synchronisation and ruins the thread, pausing inside a canned
+//! cycle can leave a drill or tap sitting in the hole, and holding in the
+//! middle of an arc while cutter compensation is active can leave a mark
+//! when the controller resumes.
+//!
+//! A [`HoldMap`] marks the lines where a pause would be harmful so a sender
+//! can defer a requested hold until the next safe boundary.
+//!
+//! ```rust
+//! use gcode::{
+//!     dialect::Dialect,
+//!     hold::{Hazard, HoldMap},
+//! };
+//!
+//! let src = "\
+//! G0 X0 Z2
+//! G33 Z-20 K1.5
+//! G33 X2 Z-22 K1.5
+//! G0 X5
+//! G81 X10 Y10 Z-5 R1
+//! G80";
+//! let holds = HoldMap::new(src, &Dialect::linuxcnc());
+//!
+//! assert!(holds.is_safe(0));
+//! // both threading passes need to finish before the machine can stop
+//! assert_eq!(holds.regions()[0].hazard, Hazard::Threading);
+//! assert_eq!(holds.hold_after(1), 2);
+//! assert_eq!(holds.hold_after(4), 4);
+//!
+//! let safe: Vec<_> = holds.safe_lines().collect();
+//! assert_eq!(safe, vec![0, 3, 5]);
+//! ```
+
+use crate::{dialect::Dialect, CommandKey, GCode, Mnemonic, Nop, Parser, Span};
+use core::fmt::{self, Display, Formatter};
+use std::vec::Vec;
+
+/// Why pausing on a line would be harmful.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Hazard {
+    /// An arc (`G2`/`G3`) while cutter compensation (`G41`/`G42`) is active.
+    CompensatedArc,
+    /// A spindle-synchronised threading move or cycle (`G33` or `G32`,
+    /// depending on the [`Dialect::threading`] style, and `G76`).
+    Threading,
+    /// A drilling, boring or tapping cycle (`G73`, `G74` and `G81` to
+    /// `G89`, plus `G76` when it's a fine boring cycle).
+    CannedCycle,
+}
+
+impl Display for Hazard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Hazard::CompensatedArc => {
+                write!(f, "arc with cutter compensation active")
+            },
+            Hazard::Threading => write!(f, "threading"),
+            Hazard::CannedCycle => write!(f, "canned cycle"),
+        }
+    }
+}
+
+/// A run of lines which shouldn't be interrupted by a feed hold.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct UnsafeRegion {
+    /// Why the region shouldn't be interrupted.
+    pub hazard: Hazard,
+    /// The (zero-based) line the region starts on.
+    pub first_line: usize,
+    /// The (zero-based) line the region finishes on, inclusive.
+    pub last_line: usize,
+    /// The commands which make up the region.
+    pub span: Span,
+}
+
+impl UnsafeRegion {
+    /// Does this region include a particular line?
+    pub fn contains(&self, line: usize) -> bool {
+        self.first_line <= line && line <= self.last_line
+    }
+}
+
+/// The places in a program where a feed hold would be harmful.
+///
+/// Consecutive threading moves are kept in a single region because the
+/// spindle synchronisation is lost as soon as the machine stops, while each
+/// compensated arc and canned cycle gets a region of its own.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct HoldMap {
+    regions: Vec<UnsafeRegion>,
+    line_count: usize,
+}
+
+impl HoldMap {
+    /// Find the unsafe regions in a program.
+    pub fn new(src: &str, dialect: &Dialect) -> Self {
+        let mut regions: Vec<UnsafeRegion> = Vec::new();
+        let mut compensating = false;
+        // was the last line with any commands on it unsafe?
+        let mut previous_unsafe = false;
+
+        for line in Parser::<_>::new_with_dialect(src, Nop, *dialect) {
+            if line.gcodes().is_empty() {
+                continue;
+            }
+
+            let span = line.span();
+            let mut hazard = None;
+
+            for gcode in line.gcodes() {
+                if let Some(on) = cutter_compensation(gcode) {
+                    compensating = on;
+                }
+
+                hazard =
+                    hazard.or_else(|| hazard_of(gcode, compensating, dialect));
+            }
+
+            let hazard = match hazard {
+                Some(hazard) => hazard,
+                None => {
+                    previous_unsafe = false;
+                    continue;
+                },
+            };
+
+            match regions.last_mut() {
+                Some(region)
+                    if previous_unsafe
+                        && hazard == Hazard::Threading
+                        && region.hazard == Hazard::Threading =>
+                {
+                    region.last_line = span.line;
+                    region.span = region.span.merge(span);
+                },
+                _ => regions.push(UnsafeRegion {
+                    hazard,
+                    first_line: span.line,
+                    last_line: span.line,
+                    span,
+                }),
+            }
+            previous_unsafe = true;
+        }
+
+        HoldMap {
+            regions,
+            line_count: src.split('\n').count(),
+        }
+    }
+
+    /// Every [`UnsafeRegion`], in the order they appear.
+    pub fn regions(&self) -> &[UnsafeRegion] { &self.regions }
+
+    /// The number of lines in the program.
+    pub fn line_count(&self) -> usize { self.line_count }
+
+    /// The [`UnsafeRegion`] a line is part of, if any.
+    pub fn region_at(&self, line: usize) -> Option<&UnsafeRegion> {
+        let index = self
+            .regions
+            .partition_point(|region| region.last_line < line);
+
+        self.regions
+            .get(index)
+            .filter(|region| region.contains(line))
+    }
+
+    /// Can the machine be held while this line is running?
+    pub fn is_safe(&self, line: usize) -> bool {
+        self.region_at(line).is_none()
+    }
+
+    /// If a hold is requested while a line is running, the last line which
+    /// should be allowed to finish before the machine stops.
+    ///
+    /// This is the line itself when it's safe to hold straight away.
+    pub fn hold_after(&self, line: usize) -> usize {
+        self.region_at(line).map_or(line, |region| region.last_line)
+    }
+
+    /// The lines where the machine can be held straight away.
+    pub fn safe_lines(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.line_count).filter(move |&line| self.is_safe(line))
+    }
+}
+
+/// Does this command turn cutter compensation on (`Some(true)`) or off
+/// (`Some(false)`)?
+fn cutter_compensation(gcode: &GCode) -> Option<bool> {
+    match (gcode.mnemonic(), gcode.major_number()) {
+        (Mnemonic::General, 41) | (Mnemonic::General, 42) => Some(true),
+        (Mnemonic::General, 40) => Some(false),
+        // the end of the program resets everything
+        (Mnemonic::Miscellaneous, 2) | (Mnemonic::Miscellaneous, 30) => {
+            Some(false)
+        },
+        _ => None,
+    }
+}
+
+fn hazard_of(
+    gcode: &GCode,
+    compensating: bool,
+    dialect: &Dialect,
+) -> Option<Hazard> {
+    const ARCS: [CommandKey; 2] =
+        [CommandKey::general(2), CommandKey::general(3)];
+
+    if gcode.mnemonic() != Mnemonic::General {
+        return None;
+    }
+
+    let threading = dialect.threading.map(|style| style.move_gcode());
+
+    match gcode.major_number() {
+        major if Some(major) == threading => Some(Hazard::Threading),
+        76 if threading.is_some() => Some(Hazard::Threading),
+        73 | 74 | 76 | 81..=89 => Some(Hazard::CannedCycle),
+        _ if compensating && ARCS.contains(&gcode.key()) => {
+            Some(Hazard::CompensatedArc)
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_arcs_are_unsafe_while_compensating() {
+        let src = "\
+G41 D1
+G1 X10 Y0
+G2 X20 Y10 I0 J10
+G1 Y20
+G3 X10 Y30 R10
+G40 G1 X0
+G2 X10 Y10 R10";
+
+        let holds = HoldMap::new(src, &Dialect::linuxcnc());
+
+        let regions: Vec<_> = holds
+            .regions()
+            .iter()
+            .map(|region| (region.hazard, region.first_line))
+            .collect();
+        assert_eq!(
+            regions,
+            vec![(Hazard::CompensatedArc, 2), (Hazard::CompensatedArc, 4)]
+        );
+        assert_eq!(holds.hold_after(3), 3);
+    }
+
+    #[test]
+    fn threading_passes_are_grouped_until_something_else_happens() {
+        let src = "\
+G33 Z-10 K1
+(taper out)
+G33 X1 Z-11 K1
+G0 X5
+G33 Z-10 K1
+G76 P1 Z-10 I-0.5 J0.1 K0.8";
+
+        let holds = HoldMap::new(src, &Dialect::linuxcnc());
+
+        let regions: Vec<_> = holds
+            .regions()
+            .iter()
+            .map(|region| (region.first_line, region.last_line))
+            .collect();
+        assert_eq!(regions, vec![(0, 2), (4, 5)]);
+        // comments inside a region are still part of it
+        assert!(!holds.is_safe(1));
+        assert_eq!(holds.region_at(5).unwrap().span.line, 4);
+    }
+
+    #[test]
+    fn every_hole_in_a_canned_cycle_is_unsafe() {
+        let src = "G81 X0 Y0 Z-5 R1\nX10\nX20\nG80\nG0 Z10";
+
+        let holds = HoldMap::new(src, &Dialect::linuxcnc());
+
+        let lines: Vec<_> =
+            holds.regions().iter().map(|r| r.first_line).collect();
+        assert_eq!(lines, vec![0, 1, 2]);
+        assert_eq!(holds.safe_lines().collect::<Vec<_>>(), vec![3, 4]);
+    }
+}
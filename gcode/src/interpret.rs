@@ -0,0 +1,2636 @@
+//! Giving meaning to parsed g-code.
+//!
+//! Most g-code commands are *modal*, meaning they change the machine's state
+//! (e.g. `G20` switches to inches) and that change sticks around until
+//! something else overrides it. The [`Interpreter`] walks through a program
+//! and keeps track of this [`MachineState`] so consumers don't need to.
+//!
+//! ```rust
+//! use gcode::{
+//!     dialect::Dialect,
+//!     interpret::{Interpreter, ToolSelection, Units},
+//! };
+//!
+//! let src = "G20\nT3 M6\nG01 X1";
+//! let mut interpreter = Interpreter::new(Dialect::linuxcnc());
+//!
+//! for line in gcode::full_parse_with_callbacks(src, gcode::Nop) {
+//!     interpreter.process_line(&line);
+//! }
+//!
+//! let state = interpreter.state();
+//! assert_eq!(state.units, Units::Inches);
+//! assert_eq!(state.tool.active, Some(ToolSelection::tool(3)));
+//! ```
+
+use crate::{
+    buffers::{Buffer, Buffers},
+    dialect::{Dialect, ThreadingStyle},
+    CommandKey, GCode, Line, Mnemonic, Span, Word,
+};
+use arrayvec::ArrayVec;
+use core::fmt::{self, Display, Formatter};
+
+/// The tool (and offset register) selected by a `T` word.
+///
+/// See [`Dialect::tool_selection()`] for how a `T` word is decoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct ToolSelection {
+    /// The tool's index, or `None` if the tool is being deselected (e.g.
+    /// RepRap's `T-1`).
+    pub tool: Option<u32>,
+    /// The offset register to use, if the tool word specified one.
+    pub offset: Option<u32>,
+}
+
+impl ToolSelection {
+    /// Deselect the current tool.
+    pub const DESELECT: ToolSelection = ToolSelection {
+        tool: None,
+        offset: None,
+    };
+
+    /// Select a tool without specifying an offset register.
+    pub const fn tool(index: u32) -> Self {
+        ToolSelection {
+            tool: Some(index),
+            offset: None,
+        }
+    }
+
+    /// Is this deselecting the current tool?
+    pub fn is_deselect(&self) -> bool { self.tool.is_none() }
+}
+
+/// The units used for distances.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Units {
+    /// Millimeters (`G21`).
+    Millimeters,
+    /// Inches (`G20`).
+    Inches,
+}
+
+/// How coordinates are interpreted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Positioning {
+    /// Coordinates are relative to the origin (`G90`).
+    Absolute,
+    /// Coordinates are relative to the current position (`G91`).
+    Relative,
+}
+
+/// Which tools have been selected.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct ToolState {
+    /// A tool which has been selected with a `T` word, but is waiting for a
+    /// tool change (`M6`) before it is used.
+    pub prepared: Option<ToolSelection>,
+    /// The tool currently being used.
+    pub active: Option<ToolSelection>,
+}
+
+/// A location in 3D space, in millimeters.
+///
+/// With the `glam` or `nalgebra` features enabled, a [`Position`] can be
+/// converted to and from those crates' vector and point types using
+/// [`From`].
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Position {
+    #[allow(missing_docs)]
+    pub x: f32,
+    #[allow(missing_docs)]
+    pub y: f32,
+    #[allow(missing_docs)]
+    pub z: f32,
+}
+
+impl Position {
+    /// The origin, `(0, 0, 0)`.
+    pub const ORIGIN: Position = Position::new(0.0, 0.0, 0.0);
+
+    /// Create a new [`Position`].
+    pub const fn new(x: f32, y: f32, z: f32) -> Self { Position { x, y, z } }
+
+    /// The straight-line distance between two [`Position`]s.
+    pub fn distance_to(self, other: Position) -> f32 {
+        let dx = other.x - self.x;
+        let dy = other.y - self.y;
+        let dz = other.z - self.z;
+
+        libm::sqrtf(dx * dx + dy * dy + dz * dz)
+    }
+
+    /// Linearly interpolate between two [`Position`]s, where a `fraction` of
+    /// `0.0` gives `self` and `1.0` gives `other`.
+    pub fn lerp(self, other: Position, fraction: f32) -> Position {
+        Position {
+            x: self.x + (other.x - self.x) * fraction,
+            y: self.y + (other.y - self.y) * fraction,
+            z: self.z + (other.z - self.z) * fraction,
+        }
+    }
+
+    /// Convert to `(first, second, axial)` coordinates for a [`Plane`].
+    fn to_plane(self, plane: Plane) -> (f32, f32, f32) {
+        match plane {
+            Plane::XY => (self.x, self.y, self.z),
+            Plane::ZX => (self.z, self.x, self.y),
+            Plane::YZ => (self.y, self.z, self.x),
+        }
+    }
+
+    fn from_plane(plane: Plane, first: f32, second: f32, axial: f32) -> Self {
+        match plane {
+            Plane::XY => Position::new(first, second, axial),
+            Plane::ZX => Position::new(second, axial, first),
+            Plane::YZ => Position::new(axial, first, second),
+        }
+    }
+}
+
+/// Conversions to and from [`glam`](https://docs.rs/glam)'s vector types,
+/// with the `glam` feature enabled.
+#[cfg(feature = "glam")]
+mod glam_conversions {
+    use super::Position;
+
+    impl From<Position> for glam::Vec3 {
+        fn from(p: Position) -> Self { glam::Vec3::new(p.x, p.y, p.z) }
+    }
+
+    impl From<Position> for glam::DVec3 {
+        fn from(p: Position) -> Self {
+            glam::DVec3::new(f64::from(p.x), f64::from(p.y), f64::from(p.z))
+        }
+    }
+
+    impl From<glam::Vec3> for Position {
+        fn from(v: glam::Vec3) -> Self { Position::new(v.x, v.y, v.z) }
+    }
+
+    impl From<glam::DVec3> for Position {
+        fn from(v: glam::DVec3) -> Self {
+            Position::new(v.x as f32, v.y as f32, v.z as f32)
+        }
+    }
+}
+
+/// Conversions to and from [`nalgebra`](https://docs.rs/nalgebra)'s point
+/// and vector types, with the `nalgebra` feature enabled.
+#[cfg(feature = "nalgebra")]
+mod nalgebra_conversions {
+    use super::Position;
+    use nalgebra::{Point3, Vector3};
+
+    impl From<Position> for Point3<f32> {
+        fn from(p: Position) -> Self { Point3::new(p.x, p.y, p.z) }
+    }
+
+    impl From<Position> for Point3<f64> {
+        fn from(p: Position) -> Self {
+            Point3::new(f64::from(p.x), f64::from(p.y), f64::from(p.z))
+        }
+    }
+
+    impl From<Position> for Vector3<f32> {
+        fn from(p: Position) -> Self { Vector3::new(p.x, p.y, p.z) }
+    }
+
+    impl From<Position> for Vector3<f64> {
+        fn from(p: Position) -> Self {
+            Vector3::new(f64::from(p.x), f64::from(p.y), f64::from(p.z))
+        }
+    }
+
+    impl From<Point3<f32>> for Position {
+        fn from(p: Point3<f32>) -> Self { Position::new(p.x, p.y, p.z) }
+    }
+
+    impl From<Point3<f64>> for Position {
+        fn from(p: Point3<f64>) -> Self {
+            Position::new(p.x as f32, p.y as f32, p.z as f32)
+        }
+    }
+
+    impl From<Vector3<f32>> for Position {
+        fn from(v: Vector3<f32>) -> Self { Position::new(v.x, v.y, v.z) }
+    }
+
+    impl From<Vector3<f64>> for Position {
+        fn from(v: Vector3<f64>) -> Self {
+            Position::new(v.x as f32, v.y as f32, v.z as f32)
+        }
+    }
+}
+
+/// The plane arcs are drawn in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Plane {
+    /// The XY plane (`G17`), using `I` and `J` for the arc's center.
+    XY,
+    /// The ZX plane (`G18`), using `K` and `I` for the arc's center.
+    ZX,
+    /// The YZ plane (`G19`), using `J` and `K` for the arc's center.
+    YZ,
+}
+
+/// The kind of motion used when axis words are given.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum MotionMode {
+    /// Move as fast as possible (`G0`).
+    Rapid,
+    /// Move in a straight line at the feed rate (`G1`).
+    Linear,
+    /// A clockwise arc (`G2`).
+    ClockwiseArc,
+    /// A counter-clockwise arc (`G3`).
+    CounterClockwiseArc,
+    /// A straight line synchronised with the spindle, for cutting threads
+    /// (`G33`, or `G32` on Fanuc lathes).
+    Threading,
+}
+
+/// A circular (or helical) arc.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Arc {
+    /// The arc's center. The coordinate along the plane's normal is
+    /// meaningless.
+    pub center: Position,
+    /// Is the arc drawn clockwise when looking down on the plane?
+    pub clockwise: bool,
+    /// The plane the arc is drawn in.
+    pub plane: Plane,
+}
+
+/// The different kinds of [`Motion`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum MotionKind {
+    /// A rapid move.
+    Rapid,
+    /// A straight line at the feed rate.
+    Linear,
+    /// An arc at the feed rate.
+    Arc(Arc),
+    /// A straight line synchronised with the spindle.
+    Threading(Thread),
+}
+
+/// The details of a [`MotionKind::Threading`] move.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Thread {
+    /// How far the tool moves for each turn of the spindle, in millimeters.
+    pub pitch: f32,
+}
+
+/// Movement from one [`Position`] to another.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Motion {
+    /// How the tool gets from [`Motion::start`] to [`Motion::end`].
+    pub kind: MotionKind,
+    /// Where the motion starts.
+    pub start: Position,
+    /// Where the motion ends.
+    pub end: Position,
+    /// The programmed feed rate in millimeters per minute, if there was one.
+    /// Rapid moves don't use the feed rate, while threading moves go at
+    /// whatever speed the spindle sets (the pitch multiplied by the spindle
+    /// speed, when it's known).
+    pub feed_rate: Option<f32>,
+}
+
+impl Motion {
+    /// Is this a rapid move?
+    pub fn is_rapid(&self) -> bool { self.kind == MotionKind::Rapid }
+
+    /// The distance travelled.
+    pub fn length(&self) -> f32 {
+        match self.kind {
+            MotionKind::Rapid
+            | MotionKind::Linear
+            | MotionKind::Threading(_) => self.start.distance_to(self.end),
+            MotionKind::Arc(ref arc) => {
+                let geometry = ArcGeometry::new(arc, self.start, self.end);
+                let arc_length = geometry.radius() * geometry.sweep;
+
+                libm::sqrtf(
+                    arc_length * arc_length
+                        + geometry.axial_travel * geometry.axial_travel,
+                )
+            },
+        }
+    }
+
+    /// The angle an arc turns through, in radians, or zero for a straight
+    /// move. This is always positive, whichever way the arc goes.
+    pub fn sweep(&self) -> f32 {
+        match self.kind {
+            MotionKind::Rapid
+            | MotionKind::Linear
+            | MotionKind::Threading(_) => 0.0,
+            MotionKind::Arc(ref arc) => {
+                ArcGeometry::new(arc, self.start, self.end).sweep
+            },
+        }
+    }
+
+    /// Find the [`Position`] a particular `fraction` of the way along this
+    /// [`Motion`] (`0.0` is the start and `1.0` is the end).
+    ///
+    /// ```rust
+    /// # use gcode::interpret::{Arc, Motion, MotionKind, Plane, Position};
+    /// // a counter-clockwise quarter circle around the origin
+    /// let motion = Motion {
+    ///     kind: MotionKind::Arc(Arc {
+    ///         center: Position::ORIGIN,
+    ///         clockwise: false,
+    ///         plane: Plane::XY,
+    ///     }),
+    ///     start: Position::new(1.0, 0.0, 0.0),
+    ///     end: Position::new(0.0, 1.0, 0.0),
+    ///     feed_rate: Some(100.0),
+    /// };
+    ///
+    /// let halfway = motion.point_at(0.5);
+    /// let expected = core::f32::consts::FRAC_1_SQRT_2;
+    /// assert!((halfway.x - expected).abs() < 1e-6);
+    /// assert!((halfway.y - expected).abs() < 1e-6);
+    /// ```
+    pub fn point_at(&self, fraction: f32) -> Position {
+        let fraction = fraction.clamp(0.0, 1.0);
+
+        match self.kind {
+            MotionKind::Rapid
+            | MotionKind::Linear
+            | MotionKind::Threading(_) => self.start.lerp(self.end, fraction),
+            MotionKind::Arc(ref arc) => {
+                ArcGeometry::new(arc, self.start, self.end).point_at(fraction)
+            },
+        }
+    }
+}
+
+/// The derived properties of an [`Arc`], in the arc's [`Plane`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ArcGeometry {
+    plane: Plane,
+    center: (f32, f32),
+    start_radius: f32,
+    end_radius: f32,
+    start_angle: f32,
+    /// The (always positive) angle swept through, in radians.
+    sweep: f32,
+    clockwise: bool,
+    start_axial: f32,
+    axial_travel: f32,
+}
+
+impl ArcGeometry {
+    fn new(arc: &Arc, start: Position, end: Position) -> Self {
+        let (ca, cb, _) = arc.center.to_plane(arc.plane);
+        let (sa, sb, s_axial) = start.to_plane(arc.plane);
+        let (ea, eb, e_axial) = end.to_plane(arc.plane);
+
+        let start_angle = libm::atan2f(sb - cb, sa - ca);
+        let end_angle = libm::atan2f(eb - cb, ea - ca);
+
+        let mut sweep = if arc.clockwise {
+            start_angle - end_angle
+        } else {
+            end_angle - start_angle
+        };
+        // note: an arc which ends where it started is a full circle
+        if sweep <= 0.0 {
+            sweep += 2.0 * core::f32::consts::PI;
+        }
+
+        ArcGeometry {
+            plane: arc.plane,
+            center: (ca, cb),
+            start_radius: libm::hypotf(sa - ca, sb - cb),
+            end_radius: libm::hypotf(ea - ca, eb - cb),
+            start_angle,
+            sweep,
+            clockwise: arc.clockwise,
+            start_axial: s_axial,
+            axial_travel: e_axial - s_axial,
+        }
+    }
+
+    fn radius(&self) -> f32 { (self.start_radius + self.end_radius) / 2.0 }
+
+    fn point_at(&self, fraction: f32) -> Position {
+        let swept = self.sweep * fraction;
+        let angle = if self.clockwise {
+            self.start_angle - swept
+        } else {
+            self.start_angle + swept
+        };
+        let radius = self.start_radius
+            + (self.end_radius - self.start_radius) * fraction;
+
+        Position::from_plane(
+            self.plane,
+            self.center.0 + radius * libm::cosf(angle),
+            self.center.1 + radius * libm::sinf(angle),
+            self.start_axial + self.axial_travel * fraction,
+        )
+    }
+}
+
+/// Which way the spindle is turning.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum SpindleDirection {
+    /// The spindle is stopped (`M5`).
+    Stopped,
+    /// Clockwise (`M3`).
+    Clockwise,
+    /// Counter-clockwise (`M4`).
+    CounterClockwise,
+}
+
+/// How the spindle's `S` value is interpreted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum SpindleSpeedMode {
+    /// `S` is the spindle's speed in revolutions per minute (`G97`).
+    Rpm,
+    /// `S` is a constant surface speed (`G96`) in meters per minute, or feet
+    /// per minute when using [`Units::Inches`].
+    ConstantSurfaceSpeed,
+}
+
+/// Everything we know about the spindle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct SpindleState {
+    /// Which way the spindle is turning.
+    pub direction: SpindleDirection,
+    /// How [`SpindleState::speed`] is interpreted.
+    pub mode: SpindleSpeedMode,
+    /// The most recent `S` value, if there was one.
+    pub speed: Option<f32>,
+    /// The maximum spindle speed in RPM, as set by the program itself (e.g.
+    /// `G50 S2000` on a Fanuc lathe or `G96 D2000` in LinuxCNC).
+    pub max_rpm: Option<f32>,
+}
+
+impl SpindleState {
+    /// How fast will the spindle turn when the tool is cutting at a
+    /// particular diameter?
+    ///
+    /// In [`SpindleSpeedMode::ConstantSurfaceSpeed`] the speed increases as
+    /// the diameter gets smaller, up to [`SpindleState::max_rpm`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gcode::interpret::{
+    ///     SpindleDirection, SpindleSpeedMode, SpindleState, Units,
+    /// };
+    ///
+    /// let spindle = SpindleState {
+    ///     direction: SpindleDirection::Clockwise,
+    ///     mode: SpindleSpeedMode::ConstantSurfaceSpeed,
+    ///     speed: Some(200.0),
+    ///     max_rpm: Some(2000.0),
+    /// };
+    ///
+    /// // 200 m/min at 100mm is about 637 RPM
+    /// let rpm = spindle.rpm_at_diameter(Units::Millimeters, 100.0).unwrap();
+    /// assert!((rpm - 636.6).abs() < 0.1);
+    /// // but at 10mm we'd need 6366 RPM, which gets clamped
+    /// let rpm = spindle.rpm_at_diameter(Units::Millimeters, 10.0).unwrap();
+    /// assert_eq!(rpm, 2000.0);
+    /// ```
+    pub fn rpm_at_diameter(&self, units: Units, diameter: f32) -> Option<f32> {
+        let speed = self.speed?;
+
+        match self.mode {
+            SpindleSpeedMode::Rpm => Some(speed),
+            SpindleSpeedMode::ConstantSurfaceSpeed => {
+                // surface speed is per minute, in m (metric) or ft (imperial)
+                let circumference = core::f32::consts::PI * diameter.abs();
+                let per_unit = match units {
+                    Units::Millimeters => 1000.0,
+                    Units::Inches => 12.0,
+                };
+                let rpm = speed * per_unit / circumference;
+
+                match self.max_rpm {
+                    Some(max) if rpm.is_nan() || rpm > max => Some(max),
+                    _ if rpm.is_finite() => Some(rpm),
+                    _ => None,
+                }
+            },
+        }
+    }
+}
+
+impl Default for SpindleState {
+    fn default() -> SpindleState {
+        SpindleState {
+            direction: SpindleDirection::Stopped,
+            mode: SpindleSpeedMode::Rpm,
+            speed: None,
+            max_rpm: None,
+        }
+    }
+}
+
+/// One of the work coordinate systems, `G54` to `G59.3`.
+///
+/// ```rust
+/// # use gcode::interpret::CoordinateSystem;
+/// let g59_2 = CoordinateSystem::from_gcode(59, 2).unwrap();
+///
+/// assert_eq!(g59_2.index(), 8);
+/// assert_eq!(g59_2.gcode(), (59, 2));
+/// assert_eq!(CoordinateSystem::from_gcode(53, 0), None);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct CoordinateSystem(u8);
+
+impl CoordinateSystem {
+    /// The first (and default) coordinate system.
+    pub const G54: CoordinateSystem = CoordinateSystem(1);
+
+    /// Get the coordinate system selected by a `G` code, if there is one.
+    pub const fn from_gcode(major: u32, minor: u32) -> Option<Self> {
+        match (major, minor) {
+            (54..=59, 0) => Some(CoordinateSystem((major - 53) as u8)),
+            (59, 1..=3) => Some(CoordinateSystem(6 + minor as u8)),
+            _ => None,
+        }
+    }
+
+    /// The coordinate system's number, from `1` (`G54`) to `9` (`G59.3`).
+    pub const fn index(self) -> u8 { self.0 }
+
+    /// The major and minor numbers of the `G` code which selects this
+    /// coordinate system.
+    pub const fn gcode(self) -> (u32, u32) {
+        if self.0 <= 6 {
+            (53 + self.0 as u32, 0)
+        } else {
+            (59, self.0 as u32 - 6)
+        }
+    }
+}
+
+impl Default for CoordinateSystem {
+    fn default() -> CoordinateSystem { CoordinateSystem::G54 }
+}
+
+/// How the feed rate is measured.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum FeedMode {
+    /// Distance per minute (`G94`).
+    PerMinute,
+    /// Distance per revolution of the spindle (`G95`).
+    PerRevolution,
+}
+
+/// Where a canned cycle takes the tool once a hole is finished.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum CycleReturn {
+    /// Back to the height the tool was at before the cycle started
+    /// (`G98`).
+    InitialLevel,
+    /// Back to the `R` plane (`G99`).
+    RPlane,
+}
+
+/// The modal state of a machine at a particular point in a program.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct MachineState {
+    /// The units used for distances.
+    pub units: Units,
+    /// How coordinates are interpreted.
+    pub positioning: Positioning,
+    /// Tool selection.
+    pub tool: ToolState,
+    /// The spindle.
+    pub spindle: SpindleState,
+    /// The current position, in millimeters.
+    pub position: Position,
+    /// The kind of motion used when axis words are given.
+    pub motion_mode: MotionMode,
+    /// The plane arcs are drawn in.
+    pub plane: Plane,
+    /// The feed rate in millimeters per minute (or per revolution, see
+    /// [`MachineState::feed_mode`]), if one has been set.
+    pub feed_rate: Option<f32>,
+    /// The active work coordinate system.
+    pub coordinate_system: CoordinateSystem,
+    /// How [`MachineState::feed_rate`] is measured.
+    pub feed_mode: FeedMode,
+    /// Where canned cycles retract to.
+    pub cycle_return: CycleReturn,
+}
+
+impl Default for MachineState {
+    fn default() -> MachineState {
+        MachineState {
+            units: Units::Millimeters,
+            positioning: Positioning::Absolute,
+            tool: ToolState::default(),
+            spindle: SpindleState::default(),
+            position: Position::ORIGIN,
+            motion_mode: MotionMode::Rapid,
+            plane: Plane::XY,
+            feed_rate: None,
+            coordinate_system: CoordinateSystem::G54,
+            feed_mode: FeedMode::PerMinute,
+            cycle_return: CycleReturn::InitialLevel,
+        }
+    }
+}
+
+/// Something which walks through a program, keeping track of the
+/// [`MachineState`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Interpreter {
+    dialect: Dialect,
+    state: MachineState,
+    /// The pitch of the last threading move, for moves which don't repeat
+    /// it.
+    thread_pitch: f32,
+    /// The finishing passes set by the first block of a Fanuc-style `G76`.
+    finishing_passes: u32,
+    /// The heights used by the canned cycle in progress, which later holes
+    /// can leave out.
+    cycle_heights: Option<CycleHeights>,
+}
+
+/// The heights a canned cycle moves between, in millimeters.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct CycleHeights {
+    /// Where the tool was before the first hole.
+    initial: f32,
+    r_plane: f32,
+    bottom: f32,
+}
+
+impl Interpreter {
+    /// Create a new [`Interpreter`] for a particular [`Dialect`], starting
+    /// from the default [`MachineState`].
+    pub fn new(dialect: Dialect) -> Self {
+        Interpreter::with_state(dialect, MachineState::default())
+    }
+
+    /// Create a new [`Interpreter`] which starts from a known
+    /// [`MachineState`].
+    pub fn with_state(dialect: Dialect, state: MachineState) -> Self {
+        Interpreter {
+            dialect,
+            state,
+            thread_pitch: 0.0,
+            finishing_passes: 1,
+            cycle_heights: None,
+        }
+    }
+
+    /// The [`Dialect`] commands are interpreted with.
+    pub fn dialect(&self) -> &Dialect { &self.dialect }
+
+    /// The current [`MachineState`].
+    pub fn state(&self) -> &MachineState { &self.state }
+
+    /// The command a [modal continuation] actually runs as, given the
+    /// current [`MachineState`], or `None` if it isn't a continuation.
+    ///
+    /// This is normally the command for the current
+    /// [`MachineState::motion_mode`], which may not be the command the
+    /// parser filled in (it just uses the last command word, even an `M`
+    /// code). Canned cycles (`G73` to `G89`) are the exception, because
+    /// each new position on its own line drills another hole.
+    ///
+    /// ```rust
+    /// use gcode::{dialect::Dialect, interpret::Interpreter, CommandKey};
+    ///
+    /// let src = "G1 X10 F100\nM3 S1000\nX20";
+    /// let mut interpreter = Interpreter::new(Dialect::generic());
+    /// let mut inherited = Vec::new();
+    ///
+    /// for gcode in gcode::parse(src) {
+    ///     inherited.push(interpreter.inherited_command(&gcode));
+    ///     interpreter.process(&gcode);
+    /// }
+    ///
+    /// // the parser thinks "X20" belongs to the M3, but the machine is
+    /// // still doing G1 moves
+    /// assert_eq!(inherited, vec![None, None, Some(CommandKey::general(1))]);
+    /// ```
+    ///
+    /// [modal continuation]: GCode::is_modal_continuation
+    pub fn inherited_command<A: Buffer<Word>>(
+        &self,
+        gcode: &GCode<A>,
+    ) -> Option<CommandKey> {
+        if !gcode.is_modal_continuation() {
+            return None;
+        }
+
+        let key = gcode.key();
+        if is_canned_cycle(key) {
+            return Some(key);
+        }
+
+        let major = match self.state.motion_mode {
+            MotionMode::Rapid => 0,
+            MotionMode::Linear => 1,
+            MotionMode::ClockwiseArc => 2,
+            MotionMode::CounterClockwiseArc => 3,
+            MotionMode::Threading => self
+                .dialect
+                .threading
+                .map_or(33, ThreadingStyle::move_gcode),
+        };
+
+        Some(CommandKey::general(major as i32))
+    }
+
+    /// Turn a [modal continuation] into a [`GCode`] which spells out the
+    /// command it inherits (see [`Interpreter::inherited_command()`]), for
+    /// controllers which don't support modal commands.
+    ///
+    /// ```rust
+    /// use gcode::{dialect::Dialect, interpret::Interpreter};
+    ///
+    /// let mut interpreter = Interpreter::new(Dialect::generic());
+    /// let mut explicit = Vec::new();
+    ///
+    /// for gcode in gcode::parse("G0 Z5\nM3\nX1 Y2") {
+    ///     let gcode = interpreter.materialize(&gcode).unwrap_or(gcode);
+    ///     interpreter.process(&gcode);
+    ///     explicit.push(gcode.to_string());
+    /// }
+    ///
+    /// assert_eq!(explicit, vec!["G0 Z5", "M3", "G0 X1 Y2"]);
+    /// ```
+    ///
+    /// [modal continuation]: GCode::is_modal_continuation
+    pub fn materialize<A: Buffer<Word> + Clone>(
+        &self,
+        gcode: &GCode<A>,
+    ) -> Option<GCode<A>> {
+        let key = self.inherited_command(gcode)?;
+
+        Some(GCode {
+            mnemonic: key.mnemonic,
+            number: key.number(),
+            ..gcode.clone()
+        })
+    }
+
+    /// Update the [`MachineState`] using every [`GCode`] in a [`Line`],
+    /// returning the [`Motion`] it caused (if any).
+    ///
+    /// Commands are processed in their order of execution rather than the
+    /// order they were written in, so `M6 T2` changes to tool `2` just like
+    /// `T2 M6` does. A line should only contain one motion, if there are more
+    /// then only the last one is returned.
+    pub fn process_line<'input, B: Buffers<'input>>(
+        &mut self,
+        line: &Line<'input, B>,
+    ) -> Option<Motion> {
+        enter_span!(
+            TRACE,
+            "line",
+            start = line.span().start,
+            end = line.span().end,
+            line = line.span().line
+        );
+        let mut motion = None;
+
+        for gcode in execution_order(line) {
+            motion = self.process(gcode).or(motion);
+        }
+
+        motion
+    }
+
+    /// Update the [`MachineState`] using a single [`GCode`], returning the
+    /// [`Motion`] it caused (if any).
+    pub fn process<A: Buffer<Word>>(
+        &mut self,
+        gcode: &GCode<A>,
+    ) -> Option<Motion> {
+        enter_span!(
+            TRACE,
+            "command",
+            key = %gcode.key(),
+            start = gcode.span.start,
+            end = gcode.span.end,
+            line = gcode.span.line
+        );
+
+        let motion = match gcode.mnemonic {
+            Mnemonic::General | Mnemonic::Miscellaneous => self.command(gcode),
+            Mnemonic::ToolChange => {
+                if let Some(selection) = self.dialect.tool_selection(gcode) {
+                    self.select_tool(selection);
+                }
+                None
+            },
+            Mnemonic::ProgramNumber => None,
+        };
+
+        event!(TRACE, ?motion, "processed");
+
+        motion
+    }
+
+    fn command<A: Buffer<Word>>(&mut self, gcode: &GCode<A>) -> Option<Motion> {
+        let major = gcode.major_number();
+        let minor = gcode.minor_number();
+
+        if gcode.mnemonic == Mnemonic::General && !self.f_is_pitch(gcode) {
+            if let Some(feed_rate) = gcode.value_for('F') {
+                self.state.feed_rate = Some(feed_rate * self.scale());
+            }
+        }
+
+        if self.is_fanuc_threading_setup(gcode) {
+            // "G76 P020060" means 2 finishing passes, no chamfer and a 60
+            // degree thread
+            if let Some(p) = gcode.value_for('P') {
+                self.finishing_passes = (p.max(0.0) as u32 / 10_000) % 100;
+            }
+            return None;
+        }
+
+        let state = &mut self.state;
+        let spindle = &mut state.spindle;
+
+        if gcode.mnemonic == Mnemonic::General
+            && minor == 0
+            && Some(major) == self.dialect.spindle_clamp_gcode
+        {
+            if let Some(max_rpm) = gcode.value_for('S') {
+                spindle.max_rpm = Some(max_rpm);
+            }
+            return None;
+        }
+
+        if sets_spindle_speed(gcode.mnemonic, major, minor) {
+            if let Some(speed) = gcode.value_for('S') {
+                spindle.speed = Some(speed);
+            }
+        }
+
+        match (gcode.mnemonic, major, minor) {
+            (Mnemonic::General, 20, 0) => state.units = Units::Inches,
+            (Mnemonic::General, 21, 0) => state.units = Units::Millimeters,
+            (Mnemonic::General, 90, 0) => {
+                state.positioning = Positioning::Absolute
+            },
+            (Mnemonic::General, 91, 0) => {
+                state.positioning = Positioning::Relative
+            },
+            (Mnemonic::General, _, 0)
+                if self.dialect.feed_mode_gcodes.contains(major) =>
+            {
+                state.feed_mode =
+                    if major == self.dialect.feed_mode_gcodes.per_minute {
+                        FeedMode::PerMinute
+                    } else {
+                        FeedMode::PerRevolution
+                    };
+            },
+            (Mnemonic::General, 98, 0) => {
+                state.cycle_return = CycleReturn::InitialLevel
+            },
+            (Mnemonic::General, 99, 0) => {
+                state.cycle_return = CycleReturn::RPlane
+            },
+            (Mnemonic::General, 17, 0) => state.plane = Plane::XY,
+            (Mnemonic::General, 18, 0) => state.plane = Plane::ZX,
+            (Mnemonic::General, 19, 0) => state.plane = Plane::YZ,
+            (Mnemonic::General, 54..=59, _) => {
+                if let Some(system) = CoordinateSystem::from_gcode(major, minor)
+                {
+                    state.coordinate_system = system;
+                }
+            },
+            (Mnemonic::General, 96, 0) => {
+                spindle.mode = SpindleSpeedMode::ConstantSurfaceSpeed;
+                if let Some(max_rpm) = gcode.value_for('D') {
+                    spindle.max_rpm = Some(max_rpm);
+                }
+            },
+            (Mnemonic::General, 97, 0) => spindle.mode = SpindleSpeedMode::Rpm,
+            (Mnemonic::Miscellaneous, 3, 0) => {
+                spindle.direction = SpindleDirection::Clockwise
+            },
+            (Mnemonic::Miscellaneous, 4, 0) => {
+                spindle.direction = SpindleDirection::CounterClockwise
+            },
+            (Mnemonic::Miscellaneous, 5, 0) => {
+                spindle.direction = SpindleDirection::Stopped
+            },
+            (Mnemonic::Miscellaneous, 6, 0) => {
+                if let Some(prepared) = state.tool.prepared.take() {
+                    state.tool.active = Some(prepared);
+                }
+            },
+            _ => {},
+        }
+
+        // note: modal changes on the same command (e.g. "G91 X2") apply to
+        // its motion
+        if gcode.mnemonic == Mnemonic::General {
+            self.motion(gcode, major, minor)
+        } else {
+            None
+        }
+    }
+
+    /// Does this command use `F` for a thread's pitch instead of the feed
+    /// rate (i.e. a Fanuc-style `G32` or `G76`)?
+    fn f_is_pitch<A: Buffer<Word>>(&self, gcode: &GCode<A>) -> bool {
+        self.dialect.threading == Some(ThreadingStyle::Fanuc)
+            && gcode.minor_number() == 0
+            && matches!(gcode.major_number(), 32 | 76)
+    }
+
+    /// Is this the first block of a Fanuc-style `G76`, which only sets up
+    /// the next cycle?
+    fn is_fanuc_threading_setup<A: Buffer<Word>>(
+        &self,
+        gcode: &GCode<A>,
+    ) -> bool {
+        self.dialect.threading == Some(ThreadingStyle::Fanuc)
+            && gcode.key() == CommandKey::general(76)
+            && !gcode.has_argument('X')
+            && !gcode.has_argument('Z')
+    }
+
+    /// The factor used to convert the current units to millimeters.
+    fn scale(&self) -> f32 {
+        match self.state.units {
+            Units::Millimeters => 1.0,
+            Units::Inches => 25.4,
+        }
+    }
+
+    /// Figure out where the axis words in a [`GCode`] would take us, if it
+    /// has any.
+    fn target<A: Buffer<Word>>(&self, gcode: &GCode<A>) -> Option<Position> {
+        let scale = self.scale();
+        let current = self.state.position;
+        let relative = self.state.positioning == Positioning::Relative;
+        let axis = |letter, current: f32| match gcode.value_for(letter) {
+            Some(value) if relative => Some(current + value * scale),
+            Some(value) => Some(value * scale),
+            None => None,
+        };
+
+        let x = axis('X', current.x);
+        let y = axis('Y', current.y);
+        let z = axis('Z', current.z);
+
+        if x.is_none() && y.is_none() && z.is_none() {
+            None
+        } else {
+            Some(Position::new(
+                x.unwrap_or(current.x),
+                y.unwrap_or(current.y),
+                z.unwrap_or(current.z),
+            ))
+        }
+    }
+
+    /// Handle any motion (or change in position) caused by a `G` code.
+    fn motion<A: Buffer<Word>>(
+        &mut self,
+        gcode: &GCode<A>,
+        major: u32,
+        minor: u32,
+    ) -> Option<Motion> {
+        let threading = self.dialect.threading;
+        let mode = match (major, minor) {
+            (0, 0) => Some(MotionMode::Rapid),
+            (1, 0) => Some(MotionMode::Linear),
+            (2, 0) => Some(MotionMode::ClockwiseArc),
+            (3, 0) => Some(MotionMode::CounterClockwiseArc),
+            (_, 0) if threading.is_some_and(|t| t.move_gcode() == major) => {
+                Some(MotionMode::Threading)
+            },
+            _ => None,
+        };
+
+        if let Some(mode) = mode {
+            self.state.motion_mode = mode;
+        }
+        // any other motion (or G80) cancels the canned cycle
+        if mode.is_some() || (major, minor) == (80, 0) {
+            self.cycle_heights = None;
+        }
+
+        if let Some(cycle) = self.tapping_cycle(gcode) {
+            self.state.position = cycle.end();
+            if !cycle.rigid {
+                self.cycle_heights = Some(CycleHeights {
+                    initial: self
+                        .cycle_heights
+                        .map_or(cycle.start.z, |heights| heights.initial),
+                    r_plane: cycle.hole.z,
+                    bottom: cycle.bottom,
+                });
+            }
+            return None;
+        }
+
+        if let Some(style) = threading {
+            if let Some(pitch) = gcode.value_for(style.pitch_letter()) {
+                if mode == Some(MotionMode::Threading) {
+                    self.thread_pitch = pitch * self.scale();
+                }
+            }
+        }
+
+        match (major, minor) {
+            // homing goes to the origin of every axis mentioned, or all of
+            // them if none are
+            (28, 0) => {
+                let mentioned = |letter| gcode.has_argument(letter);
+                let all = !mentioned('X') && !mentioned('Y') && !mentioned('Z');
+                let current = self.state.position;
+                let pick = |letter, value| {
+                    if all || mentioned(letter) {
+                        0.0
+                    } else {
+                        value
+                    }
+                };
+                let home = Position::new(
+                    pick('X', current.x),
+                    pick('Y', current.y),
+                    pick('Z', current.z),
+                );
+
+                Some(self.move_to(MotionKind::Rapid, home))
+            },
+            // setting the position doesn't actually move anything
+            (92, 0) => {
+                if let Some(position) = self.absolute_target(gcode) {
+                    self.state.position = position;
+                }
+                None
+            },
+            // probing moves in a straight line
+            (38, _) => {
+                let target = self.target(gcode)?;
+                Some(self.move_to(MotionKind::Linear, target))
+            },
+            // these use axis words for something other than motion
+            (4, _)
+            | (10, _)
+            | (30, _)
+            | (33, 1)
+            | (52, _)
+            | (53, _)
+            | (73..=89, _) => None,
+            _ => {
+                let target = self.target(gcode)?;
+                let kind = match self.state.motion_mode {
+                    MotionMode::Rapid => MotionKind::Rapid,
+                    MotionMode::Linear => MotionKind::Linear,
+                    MotionMode::ClockwiseArc => {
+                        MotionKind::Arc(self.arc(gcode, target, true))
+                    },
+                    MotionMode::CounterClockwiseArc => {
+                        MotionKind::Arc(self.arc(gcode, target, false))
+                    },
+                    MotionMode::Threading => MotionKind::Threading(Thread {
+                        pitch: self.thread_pitch,
+                    }),
+                };
+
+                Some(self.move_to(kind, target))
+            },
+        }
+    }
+
+    /// Like [`Interpreter::target()`], except relative positioning is
+    /// ignored.
+    fn absolute_target<A: Buffer<Word>>(
+        &self,
+        gcode: &GCode<A>,
+    ) -> Option<Position> {
+        let mut absolute = *self;
+        absolute.state.positioning = Positioning::Absolute;
+        absolute.target(gcode)
+    }
+
+    fn move_to(&mut self, kind: MotionKind, end: Position) -> Motion {
+        let start = self.state.position;
+        self.state.position = end;
+
+        Motion {
+            kind,
+            start,
+            end,
+            feed_rate: match kind {
+                MotionKind::Rapid => None,
+                MotionKind::Threading(thread) => {
+                    self.spindle_rpm().map(|rpm| rpm * thread.pitch)
+                },
+                _ => self.feed_per_minute(),
+            },
+        }
+    }
+
+    /// The feed rate in millimeters per minute, converting from feed per
+    /// revolution if necessary.
+    fn feed_per_minute(&self) -> Option<f32> {
+        let feed_rate = self.state.feed_rate?;
+
+        match self.state.feed_mode {
+            FeedMode::PerMinute => Some(feed_rate),
+            FeedMode::PerRevolution => {
+                self.spindle_rpm().map(|rpm| rpm * feed_rate)
+            },
+        }
+    }
+
+    /// Work out what a tapping cycle (`G84`, `G74`, or LinuxCNC's `G33.1`)
+    /// will do, without changing the [`MachineState`].
+    ///
+    /// Later holes in a canned cycle can leave out the `R` and `Z` words,
+    /// which are remembered until the cycle is cancelled.
+    ///
+    /// ```rust
+    /// use gcode::{
+    ///     dialect::Dialect,
+    ///     interpret::{Interpreter, MotionKind, Thread},
+    /// };
+    ///
+    /// let mut interpreter = Interpreter::new(Dialect::generic());
+    /// let src = "G95 M3 S500\nG0 Z10";
+    /// for line in gcode::full_parse_with_callbacks(src, gcode::Nop) {
+    ///     let _ = interpreter.process_line(&line);
+    /// }
+    ///
+    /// // an M6 tap, using feed per revolution
+    /// let g84 = "G84 X20 Y5 Z-12 R2 F1".parse().unwrap();
+    /// let cycle = interpreter.tapping_cycle(&g84).unwrap();
+    ///
+    /// assert_eq!(cycle.pitch, Some(1.0));
+    /// assert_eq!(cycle.feed_rate, Some(500.0));
+    /// assert!(cycle.check().is_ok());
+    ///
+    /// let tapping: Vec<_> = cycle
+    ///     .motions()
+    ///     .filter(|m| m.kind == MotionKind::Threading(Thread { pitch: 1.0 }))
+    ///     .collect();
+    /// // in to the bottom of the hole, then back out again
+    /// assert_eq!(tapping.len(), 2);
+    /// assert_eq!(tapping[1].end.z, 2.0);
+    /// ```
+    pub fn tapping_cycle<A: Buffer<Word>>(
+        &self,
+        gcode: &GCode<A>,
+    ) -> Option<TappingCycle> {
+        if gcode.mnemonic != Mnemonic::General {
+            return None;
+        }
+
+        let rigid = match (gcode.major_number(), gcode.minor_number()) {
+            (84, 0) | (74, 0) => false,
+            (33, 1)
+                if self.dialect.threading == Some(ThreadingStyle::LinuxCnc) =>
+            {
+                true
+            },
+            _ => return None,
+        };
+        let left_hand = gcode.major_number() == 74;
+
+        let scale = self.scale();
+        let start = self.state.position;
+        let target = self.target(gcode).unwrap_or(start);
+        let relative = self.state.positioning == Positioning::Relative;
+        let value = |letter| gcode.value_for(letter).map(|v| v * scale);
+
+        let (r_plane, bottom, retract) = if rigid {
+            // G33.1 goes straight down from where it is and back again
+            (start.z, target.z, start.z)
+        } else {
+            let previous = self.cycle_heights;
+            let initial = previous.map_or(start.z, |heights| heights.initial);
+            // in incremental mode R is measured from the initial level, and
+            // Z from the R plane
+            let r_plane = match value('R') {
+                Some(r) if relative => initial + r,
+                Some(r) => r,
+                None => previous.map_or(start.z, |heights| heights.r_plane),
+            };
+            let bottom = match gcode.value_for('Z') {
+                Some(z) if relative => r_plane + z * scale,
+                Some(_) => target.z,
+                None => previous?.bottom,
+            };
+            let retract = match self.state.cycle_return {
+                CycleReturn::InitialLevel => initial.max(r_plane),
+                CycleReturn::RPlane => r_plane,
+            };
+
+            (r_plane, bottom, retract)
+        };
+
+        let spindle_rpm = self.spindle_rpm();
+        let feed = value('F').or(self.state.feed_rate);
+        let pitch = if rigid {
+            value('K').or(Some(self.thread_pitch))
+        } else {
+            match self.state.feed_mode {
+                FeedMode::PerRevolution => feed,
+                FeedMode::PerMinute => spindle_rpm
+                    .filter(|&rpm| rpm > 0.0)
+                    .zip(feed)
+                    .map(|(rpm, feed)| feed / rpm),
+            }
+        };
+        let feed_rate = match (rigid, self.state.feed_mode) {
+            (false, FeedMode::PerMinute) => feed,
+            _ => spindle_rpm.zip(pitch).map(|(rpm, pitch)| rpm * pitch),
+        };
+
+        Some(TappingCycle {
+            start,
+            hole: Position::new(target.x, target.y, r_plane),
+            bottom,
+            retract_z: retract,
+            pitch,
+            feed_rate,
+            spindle: self.state.spindle,
+            left_hand,
+            rigid,
+        })
+    }
+
+    /// The spindle's speed in RPM, if it's known.
+    fn spindle_rpm(&self) -> Option<f32> {
+        let spindle = &self.state.spindle;
+
+        match spindle.mode {
+            SpindleSpeedMode::Rpm => spindle.speed,
+            // threads are normally cut at a fixed speed, so this is a guess
+            SpindleSpeedMode::ConstantSurfaceSpeed => spindle.max_rpm,
+        }
+    }
+
+    /// Work out the passes a threading cycle (`G76`) will make, without
+    /// changing the [`MachineState`].
+    ///
+    /// The machine finishes a cycle back where it started, so
+    /// [`Interpreter::process()`] doesn't return any [`Motion`] for it. Use
+    /// [`ThreadingCycle::motions()`] to see what happens in between.
+    ///
+    /// ```rust
+    /// use gcode::{
+    ///     dialect::Dialect,
+    ///     interpret::{Interpreter, Position},
+    /// };
+    ///
+    /// let mut interpreter = Interpreter::new(Dialect::linuxcnc());
+    /// let src = "M3 S300\nG0 X10 Z2";
+    /// for line in gcode::full_parse_with_callbacks(src, gcode::Nop) {
+    ///     let _ = interpreter.process_line(&line);
+    /// }
+    ///
+    /// let g76 = "G76 P1.5 Z-20 I-1 J0.2 K0.8 H1".parse().unwrap();
+    /// let cycle = interpreter.threading_cycle(&g76).unwrap();
+    ///
+    /// assert_eq!(cycle.pitch, 1.5);
+    /// // 0.2, 0.4, 0.6 and 0.8mm deep, then a spring pass
+    /// assert_eq!(cycle.pass_count(), 5);
+    /// assert_eq!(cycle.motions().count(), 20);
+    /// let last = cycle.motions().last().unwrap();
+    /// assert_eq!(last.end, Position::new(10.0, 0.0, 2.0));
+    /// ```
+    pub fn threading_cycle<A: Buffer<Word>>(
+        &self,
+        gcode: &GCode<A>,
+    ) -> Option<ThreadingCycle> {
+        if gcode.key() != CommandKey::general(76) {
+            return None;
+        }
+
+        let scale = self.scale();
+        let start = self.state.position;
+        let end_z = self.target(gcode)?.z;
+        let spindle_rpm = self.spindle_rpm();
+
+        match self.dialect.threading? {
+            ThreadingStyle::LinuxCnc => {
+                let value = |letter| gcode.value_for(letter).map(|v| v * scale);
+
+                Some(ThreadingCycle {
+                    start,
+                    end_z,
+                    crest_x: start.x + value('I')?,
+                    depth: value('K')?.abs(),
+                    first_cut: value('J')?.abs(),
+                    degression: gcode.value_for('R').unwrap_or(1.0),
+                    spring_passes: gcode.value_for('H').map_or(0, |h| h as u32),
+                    pitch: value('P')?,
+                    spindle_rpm,
+                })
+            },
+            ThreadingStyle::Fanuc => {
+                if self.is_fanuc_threading_setup(gcode) {
+                    return None;
+                }
+
+                let increment = self.dialect.least_input_increment;
+                let value = |letter| {
+                    let raw = gcode.value_for(letter)?;
+                    Some(raw * increment.unwrap_or(1.0) * scale)
+                };
+                let root_x = self.target(gcode)?.x;
+                let depth = value('P')?.abs();
+                let inwards = if root_x > start.x { 1.0 } else { -1.0 };
+
+                Some(ThreadingCycle {
+                    start,
+                    end_z,
+                    crest_x: root_x - inwards * depth,
+                    depth,
+                    first_cut: value('Q')?.abs(),
+                    // each pass removes the same amount of material
+                    degression: 2.0,
+                    spring_passes: self.finishing_passes,
+                    pitch: gcode.value_for('F')? * scale,
+                    spindle_rpm,
+                })
+            },
+        }
+    }
+
+    fn arc<A: Buffer<Word>>(
+        &self,
+        gcode: &GCode<A>,
+        end: Position,
+        clockwise: bool,
+    ) -> Arc {
+        let plane = self.state.plane;
+        let scale = self.scale();
+        let start = self.state.position;
+        let offset = |letter| gcode.value_for(letter).unwrap_or(0.0) * scale;
+
+        let center = match gcode.value_for('R') {
+            Some(radius) => {
+                center_from_radius(start, end, radius * scale, clockwise, plane)
+            },
+            None => Position::new(
+                start.x + offset('I'),
+                start.y + offset('J'),
+                start.z + offset('K'),
+            ),
+        };
+
+        Arc {
+            center,
+            clockwise,
+            plane,
+        }
+    }
+
+    fn select_tool(&mut self, selection: ToolSelection) {
+        if self.dialect.immediate_tool_change {
+            self.state.tool.active = Some(selection);
+        } else {
+            self.state.tool.prepared = Some(selection);
+        }
+    }
+}
+
+/// A threading cycle (`G76`), normalised so it looks the same whichever
+/// [`ThreadingStyle`] it was written in.
+///
+/// Each pass rapids in to its depth, cuts along Z in sync with the spindle,
+/// rapids back out to the starting X, then back to the starting Z. Pass `n`
+/// (counting from `1`) cuts `first_cut * n^(1 / degression)` deep, until the
+/// full depth is reached, followed by any spring passes at the full depth.
+/// Compound infeed angles and tapers aren't modelled.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct ThreadingCycle {
+    /// Where the cycle starts and finishes.
+    pub start: Position,
+    /// Where the thread finishes along Z.
+    pub end_z: f32,
+    /// The X coordinate of the thread's crest, where cutting starts.
+    pub crest_x: f32,
+    /// How deep the thread is, measured from the crest.
+    pub depth: f32,
+    /// How deep the first pass cuts.
+    pub first_cut: f32,
+    /// How quickly each pass gets shallower, where `1.0` means they all cut
+    /// the same depth and `2.0` means they all remove the same area.
+    pub degression: f32,
+    /// Extra passes at the full depth.
+    pub spring_passes: u32,
+    /// How far the tool moves for each turn of the spindle.
+    pub pitch: f32,
+    /// The spindle speed, if it's known.
+    pub spindle_rpm: Option<f32>,
+}
+
+impl ThreadingCycle {
+    /// The most cutting passes a cycle will make, in case the first cut is
+    /// absurdly small compared to the thread's depth.
+    pub const MAX_PASSES: u32 = 1000;
+
+    /// How many passes the cycle makes, including spring passes.
+    pub fn pass_count(&self) -> u32 {
+        let mut cutting = 1;
+
+        while cutting < ThreadingCycle::MAX_PASSES
+            && self.cut_depth(cutting) < self.depth
+        {
+            cutting += 1;
+        }
+
+        cutting + self.spring_passes
+    }
+
+    /// How deep a pass (counting from `0`) cuts.
+    pub fn depth_of_pass(&self, pass: u32) -> f32 { self.cut_depth(pass + 1) }
+
+    fn cut_depth(&self, n: u32) -> f32 {
+        if self.first_cut <= 0.0 || self.degression <= 0.0 {
+            return self.depth;
+        }
+
+        let depth =
+            self.first_cut * libm::powf(n as f32, 1.0 / self.degression);
+        // allow for rounding errors, so a 0.8mm thread cut 0.2mm at a time
+        // takes 4 passes
+        if depth >= self.depth - self.depth * 1e-4 {
+            self.depth
+        } else {
+            depth
+        }
+    }
+
+    /// Which way X moves to cut deeper.
+    fn inwards(&self) -> f32 {
+        if self.crest_x > self.start.x {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+
+    /// Every [`Motion`] the cycle makes, in order.
+    pub fn motions(&self) -> ThreadingMotions {
+        ThreadingMotions {
+            cycle: *self,
+            passes: self.pass_count(),
+            index: 0,
+        }
+    }
+}
+
+/// An iterator over the [`Motion`]s in a [`ThreadingCycle`], created by
+/// [`ThreadingCycle::motions()`].
+#[derive(Debug, Clone)]
+pub struct ThreadingMotions {
+    cycle: ThreadingCycle,
+    passes: u32,
+    /// Four motions per pass.
+    index: u32,
+}
+
+impl Iterator for ThreadingMotions {
+    type Item = Motion;
+
+    fn next(&mut self) -> Option<Motion> {
+        let cycle = &self.cycle;
+        let (pass, step) = (self.index / 4, self.index % 4);
+        if pass >= self.passes {
+            return None;
+        }
+        self.index += 1;
+
+        let x = cycle.crest_x + cycle.inwards() * cycle.depth_of_pass(pass);
+        let at = |x, z| Position::new(x, cycle.start.y, z);
+        let (start_x, start_z) = (cycle.start.x, cycle.start.z);
+        let rapid = |start, end| Motion {
+            kind: MotionKind::Rapid,
+            start,
+            end,
+            feed_rate: None,
+        };
+
+        Some(match step {
+            0 => rapid(cycle.start, at(x, start_z)),
+            1 => Motion {
+                kind: MotionKind::Threading(Thread { pitch: cycle.pitch }),
+                start: at(x, start_z),
+                end: at(x, cycle.end_z),
+                feed_rate: cycle.spindle_rpm.map(|rpm| rpm * cycle.pitch),
+            },
+            2 => rapid(at(x, cycle.end_z), at(start_x, cycle.end_z)),
+            _ => rapid(at(start_x, cycle.end_z), cycle.start),
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.passes * 4).saturating_sub(self.index) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+/// A tapping cycle, where the tool feeds into a hole in sync with the
+/// spindle and then reverses back out.
+///
+/// Fanuc-style `G84` (right hand) and `G74` (left hand) cycles rapid to
+/// the hole, then down to the `R` plane, tap to the bottom and back up to
+/// the `R` plane, then retract (see [`CycleReturn`]). LinuxCNC's `G33.1`
+/// taps straight down from where the tool is and back again.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct TappingCycle {
+    /// Where the tool was before the cycle.
+    pub start: Position,
+    /// Where tapping starts, above the hole at the `R` plane.
+    pub hole: Position,
+    /// How deep the hole is tapped.
+    pub bottom: f32,
+    /// Where the tool finishes along Z.
+    pub retract_z: f32,
+    /// How far the tap moves per revolution, in millimeters, if it can be
+    /// worked out.
+    pub pitch: Option<f32>,
+    /// How fast the tap moves, in millimeters per minute, if it can be
+    /// worked out.
+    pub feed_rate: Option<f32>,
+    /// The spindle at the start of the cycle.
+    pub spindle: SpindleState,
+    /// Is this cutting a left-handed thread (`G74`)?
+    pub left_hand: bool,
+    /// Is this LinuxCNC's rigid tapping (`G33.1`)?
+    pub rigid: bool,
+}
+
+impl TappingCycle {
+    /// Where the tool ends up.
+    pub fn end(&self) -> Position {
+        Position::new(self.hole.x, self.hole.y, self.retract_z)
+    }
+
+    /// Check the cycle can actually tap a thread, with the spindle turning
+    /// the right way at a fixed speed and a known feed rate.
+    pub fn check(&self) -> Result<(), TappingError> {
+        let expected = if self.left_hand {
+            SpindleDirection::CounterClockwise
+        } else {
+            SpindleDirection::Clockwise
+        };
+
+        match self.spindle.direction {
+            SpindleDirection::Stopped => {
+                return Err(TappingError::SpindleStopped)
+            },
+            // rigid tapping follows whichever way the spindle turns
+            direction if direction != expected && !self.rigid => {
+                return Err(TappingError::WrongDirection { expected })
+            },
+            _ => {},
+        }
+
+        if self.spindle.mode == SpindleSpeedMode::ConstantSurfaceSpeed {
+            return Err(TappingError::VariableSpindleSpeed);
+        }
+        if self.spindle.speed.is_none_or(|speed| speed <= 0.0) {
+            return Err(TappingError::UnknownSpindleSpeed);
+        }
+        if self.pitch.is_none_or(|pitch| pitch <= 0.0) {
+            return Err(TappingError::UnknownFeedRate);
+        }
+
+        Ok(())
+    }
+
+    /// Like [`TappingCycle::check()`], except the feed rate and spindle
+    /// speed must also match the pitch of the tap being used (to within
+    /// 1%).
+    pub fn check_pitch(&self, tap_pitch: f32) -> Result<(), TappingError> {
+        self.check()?;
+
+        let programmed = self.pitch.unwrap_or_default();
+        if (programmed - tap_pitch).abs() > tap_pitch.abs() * 0.01 {
+            return Err(TappingError::PitchMismatch {
+                programmed,
+                tap: tap_pitch,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Every [`Motion`] the cycle makes, in order.
+    ///
+    /// Tapping moves are a [`MotionKind::Threading`] when the pitch is
+    /// known, otherwise they're [`MotionKind::Linear`].
+    pub fn motions(&self) -> TappingMotions {
+        let mut motions = ArrayVec::new();
+        let hole = self.hole;
+        let bottom = Position::new(hole.x, hole.y, self.bottom);
+        let above = Position::new(hole.x, hole.y, self.start.z);
+        let kind = match self.pitch {
+            Some(pitch) => MotionKind::Threading(Thread { pitch }),
+            None => MotionKind::Linear,
+        };
+        let mut push = |kind, start: Position, end: Position| {
+            if start != end {
+                motions.push(Motion {
+                    kind,
+                    start,
+                    end,
+                    feed_rate: match kind {
+                        MotionKind::Rapid => None,
+                        _ => self.feed_rate,
+                    },
+                });
+            }
+        };
+
+        push(MotionKind::Rapid, self.start, above);
+        push(MotionKind::Rapid, above, hole);
+        push(kind, hole, bottom);
+        push(kind, bottom, hole);
+        push(MotionKind::Rapid, hole, self.end());
+
+        TappingMotions {
+            motions: motions.into_iter(),
+        }
+    }
+}
+
+/// An iterator over the [`Motion`]s in a [`TappingCycle`], created by
+/// [`TappingCycle::motions()`].
+#[derive(Debug, Clone)]
+pub struct TappingMotions {
+    motions: arrayvec::IntoIter<[Motion; 5]>,
+}
+
+impl Iterator for TappingMotions {
+    type Item = Motion;
+
+    fn next(&mut self) -> Option<Motion> { self.motions.next() }
+
+    fn size_hint(&self) -> (usize, Option<usize>) { self.motions.size_hint() }
+}
+
+/// Reasons a [`TappingCycle`] won't cut a usable thread.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+#[allow(variant_size_differences)] // the whole enum is only 12 bytes
+pub enum TappingError {
+    /// The spindle isn't turning.
+    SpindleStopped,
+    /// The spindle is turning the wrong way for this kind of tap.
+    WrongDirection {
+        /// The way the spindle should be turning.
+        expected: SpindleDirection,
+    },
+    /// The spindle is using constant surface speed (`G96`), so the feed
+    /// can't keep up with it.
+    VariableSpindleSpeed,
+    /// The spindle speed was never set.
+    UnknownSpindleSpeed,
+    /// There is no feed rate (or pitch) to tap with.
+    UnknownFeedRate,
+    /// The feed rate and spindle speed don't match the tap's pitch.
+    PitchMismatch {
+        /// The pitch the feed rate and spindle speed give.
+        programmed: f32,
+        /// The pitch of the tap.
+        tap: f32,
+    },
+}
+
+impl Display for TappingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TappingError::SpindleStopped => {
+                write!(f, "the spindle isn't turning")
+            },
+            TappingError::WrongDirection { expected } => write!(
+                f,
+                "the spindle should be turning {}",
+                match expected {
+                    SpindleDirection::CounterClockwise => "counter-clockwise",
+                    _ => "clockwise",
+                }
+            ),
+            TappingError::VariableSpindleSpeed => write!(
+                f,
+                "tapping needs a fixed spindle speed, not constant surface \
+                 speed"
+            ),
+            TappingError::UnknownSpindleSpeed => {
+                write!(f, "the spindle speed was never set")
+            },
+            TappingError::UnknownFeedRate => {
+                write!(f, "there is no feed rate to tap with")
+            },
+            TappingError::PitchMismatch { programmed, tap } => write!(
+                f,
+                "the feed rate gives a pitch of {}mm but the tap is {}mm",
+                programmed, tap
+            ),
+        }
+    }
+}
+
+with_std! {
+    impl std::error::Error for TappingError {}
+}
+
+/// A [`GCode`] with its meaning decoded, so consumers don't need to match on
+/// raw [`Word`]s themselves.
+///
+/// Straight moves, arcs and rapids are all a [`Command::Move`], with the
+/// [`Motion::kind`] saying which.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+#[allow(variant_size_differences)] // can't box without an allocator
+pub enum Command {
+    /// The machine moved (e.g. `G1 X10`, `G2 X5 Y5 R5` or `G28`).
+    Move(Motion),
+    /// Change the motion mode without moving (e.g. a `G1` on its own).
+    SetMotionMode(MotionMode),
+    /// Switch units (`G20`/`G21`).
+    SetUnits(Units),
+    /// Switch between absolute and relative positioning (`G90`/`G91`).
+    SetPositioning(Positioning),
+    /// Select the plane arcs are drawn in (`G17`/`G18`/`G19`).
+    SetPlane(Plane),
+    /// Select a work coordinate system (`G54` to `G59.3`).
+    SetCoordinateSystem(CoordinateSystem),
+    /// Tell the machine it is somewhere else without moving (`G92`).
+    SetPosition(Position),
+    /// Pause for some number of seconds (`G4`).
+    Dwell(f32),
+    /// Select a tool with a `T` word.
+    SelectTool(ToolSelection),
+    /// Swap to the selected tool (`M6`), giving the tool now being used.
+    ChangeTool(Option<ToolSelection>),
+    /// Start or stop the spindle (`M3`/`M4`/`M5`).
+    Spindle(SpindleDirection),
+    /// Cut a thread in several passes (`G76`).
+    ThreadingCycle(ThreadingCycle),
+    /// Tap a hole (`G84`, `G74` or `G33.1`).
+    TappingCycle(TappingCycle),
+    /// Something without a typed representation (yet), which may still have
+    /// updated the [`MachineState`].
+    Other(CommandKey),
+}
+
+impl Interpreter {
+    /// Like [`Interpreter::process()`], except the [`GCode`] is turned into
+    /// a typed [`Command`].
+    ///
+    /// ```rust
+    /// use gcode::{
+    ///     dialect::Dialect,
+    ///     interpret::{Command, Interpreter, Units},
+    /// };
+    ///
+    /// let mut interpreter = Interpreter::new(Dialect::generic());
+    /// let g20 = "G20".parse().unwrap();
+    ///
+    /// let command = interpreter.interpret(&g20);
+    ///
+    /// assert_eq!(command, Command::SetUnits(Units::Inches));
+    /// ```
+    pub fn interpret<A: Buffer<Word>>(&mut self, gcode: &GCode<A>) -> Command {
+        if let Some(cycle) = self.threading_cycle(gcode) {
+            let _ = self.process(gcode);
+            return Command::ThreadingCycle(cycle);
+        }
+        if let Some(cycle) = self.tapping_cycle(gcode) {
+            let _ = self.process(gcode);
+            return Command::TappingCycle(cycle);
+        }
+
+        if let Some(motion) = self.process(gcode) {
+            return Command::Move(motion);
+        }
+
+        let state = &self.state;
+        let key = gcode.key();
+
+        match (gcode.mnemonic, key.major, key.minor) {
+            (Mnemonic::ToolChange, _, _) => self
+                .dialect
+                .tool_selection(gcode)
+                .map_or(Command::Other(key), Command::SelectTool),
+            (Mnemonic::General, 0..=3, 0) => {
+                Command::SetMotionMode(state.motion_mode)
+            },
+            (Mnemonic::General, 4, 0) => self
+                .dialect
+                .dwell_seconds(gcode)
+                .map_or(Command::Other(key), Command::Dwell),
+            (Mnemonic::General, 17..=19, 0) => Command::SetPlane(state.plane),
+            (Mnemonic::General, 20..=21, 0) => Command::SetUnits(state.units),
+            (Mnemonic::General, 90..=91, 0) => {
+                Command::SetPositioning(state.positioning)
+            },
+            (Mnemonic::General, 92, 0) => Command::SetPosition(state.position),
+            (Mnemonic::General, 54..=59, _)
+                if CoordinateSystem::from_gcode(
+                    key.major as u32,
+                    key.minor,
+                )
+                .is_some() =>
+            {
+                Command::SetCoordinateSystem(state.coordinate_system)
+            },
+            (Mnemonic::Miscellaneous, 3..=5, 0) => {
+                Command::Spindle(state.spindle.direction)
+            },
+            (Mnemonic::Miscellaneous, 6, 0) => {
+                Command::ChangeTool(state.tool.active)
+            },
+            _ => Command::Other(key),
+        }
+    }
+}
+
+/// Adds [`Interpret::interpret()`] to anything which yields [`GCode`]s.
+///
+/// ```rust
+/// use gcode::interpret::{Command, Interpret, MotionKind, Units};
+///
+/// let commands: Vec<_> = gcode::parse("G20 G1 X1 F10\nG2 X2 Y1 R1")
+///     .interpret()
+///     .collect();
+///
+/// assert_eq!(commands[0].command, Command::SetUnits(Units::Inches));
+/// match commands[1].command {
+///     Command::Move(motion) => assert_eq!(motion.kind, MotionKind::Linear),
+///     other => panic!("expected a move, found {:?}", other),
+/// }
+/// // the accumulated state comes along with each command
+/// assert_eq!(commands[1].state.feed_rate, Some(254.0));
+/// assert_eq!(commands[2].state.position.x, 50.8);
+/// ```
+pub trait Interpret<A: Buffer<Word>>:
+    Iterator<Item = GCode<A>> + Sized
+{
+    /// Interpret each [`GCode`] using the default [`Dialect`].
+    fn interpret(self) -> Commands<Self> {
+        self.interpret_with(Interpreter::new(Dialect::default()))
+    }
+
+    /// Interpret each [`GCode`] using a particular [`Interpreter`] (e.g.
+    /// one for another [`Dialect`] or starting from a known
+    /// [`MachineState`]).
+    fn interpret_with(self, interpreter: Interpreter) -> Commands<Self> {
+        Commands {
+            gcodes: self,
+            interpreter,
+        }
+    }
+}
+
+impl<A, I> Interpret<A> for I
+where
+    A: Buffer<Word>,
+    I: Iterator<Item = GCode<A>>,
+{
+}
+
+/// A [`Command`], along with where it came from and the [`MachineState`]
+/// after it was executed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Interpreted {
+    /// The decoded command.
+    pub command: Command,
+    /// Where the [`GCode`] was in its source text.
+    pub span: Span,
+    /// The machine's state once the command has been executed.
+    pub state: MachineState,
+    /// The command a [modal continuation] inherited (see
+    /// [`Interpreter::inherited_command()`]).
+    ///
+    /// [modal continuation]: GCode::is_modal_continuation
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub inherited: Option<CommandKey>,
+}
+
+/// An iterator over [`Interpreted`] commands, created by
+/// [`Interpret::interpret()`].
+#[derive(Debug, Clone)]
+pub struct Commands<I> {
+    gcodes: I,
+    interpreter: Interpreter,
+}
+
+impl<I> Commands<I> {
+    /// The [`Interpreter`] being used.
+    pub fn interpreter(&self) -> &Interpreter { &self.interpreter }
+
+    /// Get the [`Interpreter`] back, with the state at the end of the
+    /// commands seen so far.
+    pub fn into_interpreter(self) -> Interpreter { self.interpreter }
+}
+
+impl<A, I> Iterator for Commands<I>
+where
+    A: Buffer<Word>,
+    I: Iterator<Item = GCode<A>>,
+{
+    type Item = Interpreted;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let gcode = self.gcodes.next()?;
+        let inherited = self.interpreter.inherited_command(&gcode);
+        let command = self.interpreter.interpret(&gcode);
+
+        Some(Interpreted {
+            command,
+            span: gcode.span(),
+            state: self.interpreter.state,
+            inherited,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) { self.gcodes.size_hint() }
+}
+
+/// Find an arc's center using the `R` format, where a positive radius means
+/// the arc is less than 180 degrees and negative means more.
+fn center_from_radius(
+    start: Position,
+    end: Position,
+    radius: f32,
+    clockwise: bool,
+    plane: Plane,
+) -> Position {
+    let (sa, sb, axial) = start.to_plane(plane);
+    let (ea, eb, _) = end.to_plane(plane);
+
+    let half_chord = libm::hypotf(ea - sa, eb - sb) / 2.0;
+    let height =
+        libm::sqrtf((radius * radius - half_chord * half_chord).max(0.0));
+    let direction = libm::atan2f(eb - sb, ea - sa);
+    let turn = if (radius > 0.0) == clockwise {
+        -core::f32::consts::FRAC_PI_2
+    } else {
+        core::f32::consts::FRAC_PI_2
+    };
+
+    Position::from_plane(
+        plane,
+        (sa + ea) / 2.0 + height * libm::cosf(direction + turn),
+        (sb + eb) / 2.0 + height * libm::sinf(direction + turn),
+        axial,
+    )
+}
+
+/// Does this command start a canned cycle (e.g. `G81` drilling)?
+fn is_canned_cycle(key: CommandKey) -> bool {
+    key.mnemonic == Mnemonic::General && matches!(key.major, 73..=79 | 81..=89)
+}
+
+/// Commands which may have a spindle speed (`S`) attached.
+///
+/// Printers reuse `S` for all sorts of things (temperatures, fan speeds,
+/// etc.), so we need to be explicit.
+fn sets_spindle_speed(mnemonic: Mnemonic, major: u32, minor: u32) -> bool {
+    match mnemonic {
+        Mnemonic::General => {
+            minor == 0 && (major <= 3 || major == 96 || major == 97)
+        },
+        Mnemonic::Miscellaneous => minor == 0 && (major == 3 || major == 4),
+        _ => false,
+    }
+}
+
+const LAST_EXECUTION_STAGE: u8 = 2;
+
+/// The [`GCode`]s in a [`Line`], in the order they're executed (see
+/// [`Interpreter::process_line()`]).
+pub(crate) fn execution_order<'a, 'input, B: Buffers<'input>>(
+    line: &'a Line<'input, B>,
+) -> impl Iterator<Item = &'a GCode<B::Arguments>> + 'a {
+    (0..=LAST_EXECUTION_STAGE).flat_map(move |stage| {
+        line.gcodes()
+            .iter()
+            .filter(move |gcode| execution_stage(gcode) == stage)
+    })
+}
+
+/// The order commands on the same line are executed in, loosely based on
+/// section 3.8 of the RS-274/NGC spec.
+fn execution_stage<A: Buffer<Word>>(gcode: &GCode<A>) -> u8 {
+    match gcode.mnemonic {
+        Mnemonic::ToolChange => 0,
+        Mnemonic::Miscellaneous
+            if gcode.key() == CommandKey::miscellaneous(6) =>
+        {
+            1
+        },
+        _ => LAST_EXECUTION_STAGE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interpret(src: &str, dialect: Dialect) -> MachineState {
+        let mut interpreter = Interpreter::new(dialect);
+
+        for line in crate::full_parse_with_callbacks(src, crate::Nop) {
+            let _ = interpreter.process_line(&line);
+        }
+
+        *interpreter.state()
+    }
+
+    fn motions(src: &str) -> Vec<Motion> {
+        let mut interpreter = Interpreter::new(Dialect::default());
+
+        crate::full_parse_with_callbacks(src, crate::Nop)
+            .filter_map(|line| interpreter.process_line(&line))
+            .collect()
+    }
+
+    fn assert_close(left: Position, right: Position) {
+        assert!(left.distance_to(right) < 1e-4, "{:?} != {:?}", left, right);
+    }
+
+    #[test]
+    fn gcodes_are_turned_into_typed_commands() {
+        let src = "G91 G18\nG0 X1\nG4 P0.5\nT2 M6 M3 S1000\nG92 X0\nM30";
+
+        let got: Vec<_> = crate::parse(src)
+            .interpret_with(Interpreter::new(Dialect::linuxcnc()))
+            .map(|interpreted| interpreted.command)
+            .collect();
+
+        let rapid = Motion {
+            kind: MotionKind::Rapid,
+            start: Position::ORIGIN,
+            end: Position::new(1.0, 0.0, 0.0),
+            feed_rate: None,
+        };
+        assert_eq!(
+            got,
+            vec![
+                Command::SetPositioning(Positioning::Relative),
+                Command::SetPlane(Plane::ZX),
+                Command::Move(rapid),
+                Command::Dwell(0.5),
+                Command::SelectTool(ToolSelection::tool(2)),
+                Command::ChangeTool(Some(ToolSelection::tool(2))),
+                Command::Spindle(SpindleDirection::Clockwise),
+                Command::SetPosition(Position::ORIGIN),
+                Command::Other(CommandKey::miscellaneous(30)),
+            ]
+        );
+    }
+
+    #[test]
+    fn threading_moves_follow_the_spindle() {
+        let src = "G1 F100\nS200 M3\nG33 Z-10 K1.5\nZ-20";
+        let mut interpreter = Interpreter::new(Dialect::linuxcnc());
+
+        let got: Vec<_> = crate::full_parse_with_callbacks(src, crate::Nop)
+            .filter_map(|line| interpreter.process_line(&line))
+            .collect();
+
+        assert_eq!(got.len(), 2);
+        for motion in &got {
+            assert_eq!(
+                motion.kind,
+                MotionKind::Threading(Thread { pitch: 1.5 })
+            );
+            assert_eq!(motion.feed_rate, Some(300.0));
+        }
+        assert_eq!(interpreter.state().feed_rate, Some(100.0));
+    }
+
+    #[test]
+    fn delta_calibration_isnt_threading() {
+        let src = "G1 F100\nS200 M3\nG33 P3 V1";
+
+        for dialect in &[Dialect::generic(), Dialect::reprap()] {
+            let mut interpreter = Interpreter::new(*dialect);
+
+            let threading = crate::full_parse_with_callbacks(src, crate::Nop)
+                .filter_map(|line| interpreter.process_line(&line))
+                .any(|motion| matches!(motion.kind, MotionKind::Threading(_)));
+
+            assert!(!threading, "{:?}", dialect);
+        }
+    }
+
+    #[test]
+    fn fanuc_threading_cycles_use_two_blocks() {
+        let src = "G97 S500 M3\nG00 X22. Z5.\nG76 P020060 Q50 R0.02\n\
+                   G76 X18.16 Z-30. P920 Q300 F1.5";
+        let mut interpreter = Interpreter::new(Dialect::fanuc_lathe());
+        let mut cycles = Vec::new();
+
+        for line in crate::full_parse_with_callbacks(src, crate::Nop) {
+            for gcode in line.gcodes() {
+                cycles.extend(interpreter.threading_cycle(gcode));
+            }
+            let _ = interpreter.process_line(&line);
+        }
+
+        assert_eq!(cycles.len(), 1);
+        let cycle = cycles[0];
+        assert_eq!(cycle.pitch, 1.5);
+        assert_eq!(cycle.spring_passes, 2);
+        assert!((cycle.crest_x - 19.08).abs() < 1e-4);
+        // 0.3 * sqrt(10) is the first pass to reach 0.92mm deep
+        assert_eq!(cycle.pass_count(), 12);
+        let deepest = cycle.motions().nth(4 * 9 + 1).unwrap();
+        assert!((deepest.start.x - 18.16).abs() < 1e-4);
+        assert_eq!(deepest.end.z, -30.0);
+        // the lead isn't a feed rate
+        assert_eq!(interpreter.state().feed_rate, None);
+        assert_eq!(interpreter.state().position, cycle.start);
+    }
+
+    fn tapping_cycles(src: &str, dialect: Dialect) -> Vec<TappingCycle> {
+        let mut interpreter = Interpreter::new(dialect);
+        let mut cycles = Vec::new();
+
+        for line in crate::full_parse_with_callbacks(src, crate::Nop) {
+            for gcode in execution_order(&line) {
+                cycles.extend(interpreter.tapping_cycle(gcode));
+                let _ = interpreter.process(gcode);
+            }
+        }
+
+        cycles
+    }
+
+    #[test]
+    fn tapping_with_feed_per_revolution() {
+        let src = "G0 X0 Y0 Z10\nM3 S600\nG95 G84 X5 Y5 Z-12 R2 F1.25";
+
+        let got = tapping_cycles(src, Dialect::linuxcnc());
+
+        assert_eq!(got.len(), 1);
+        let cycle = got[0];
+        assert_eq!(cycle.pitch, Some(1.25));
+        assert_eq!(cycle.feed_rate, Some(750.0));
+        assert_eq!(cycle.hole, Position::new(5.0, 5.0, 2.0));
+        assert_eq!(cycle.bottom, -12.0);
+        assert_eq!(cycle.retract_z, 10.0);
+        assert_eq!(cycle.check(), Ok(()));
+        assert_eq!(
+            cycle.check_pitch(1.5),
+            Err(TappingError::PitchMismatch {
+                programmed: 1.25,
+                tap: 1.5
+            })
+        );
+
+        let kinds: Vec<_> = cycle.motions().map(|m| m.kind).collect();
+        let tap = MotionKind::Threading(Thread { pitch: 1.25 });
+        assert_eq!(
+            kinds,
+            vec![
+                MotionKind::Rapid,
+                MotionKind::Rapid,
+                tap,
+                tap,
+                MotionKind::Rapid
+            ]
+        );
+    }
+
+    #[test]
+    fn pitch_is_derived_from_feed_per_minute() {
+        let src = "G0 Z5\nM3 S500\nG94 G99 G84 X0 Z-10 R1 F750\nX10\nG98 G84 X20";
+
+        let got = tapping_cycles(src, Dialect::linuxcnc());
+
+        let retracts: Vec<_> = got.iter().map(|c| c.retract_z).collect();
+        assert_eq!(retracts, vec![1.0, 1.0, 5.0]);
+        // later holes reuse the same R plane and depth
+        for cycle in &got {
+            assert_eq!(cycle.pitch, Some(1.5));
+            assert_eq!(cycle.feed_rate, Some(750.0));
+            assert_eq!(cycle.bottom, -10.0);
+            assert_eq!(cycle.hole.z, 1.0);
+        }
+        assert_eq!(got[2].hole.x, 20.0);
+    }
+
+    #[test]
+    fn left_hand_taps_need_the_spindle_reversed() {
+        let src = "M3 S300\nG74 Z-5 R1 F300\nG80\nM4\nG74 Z-5 R1 F300";
+
+        let got: Vec<_> = tapping_cycles(src, Dialect::linuxcnc())
+            .iter()
+            .map(|cycle| cycle.check())
+            .collect();
+
+        assert_eq!(
+            got,
+            vec![
+                Err(TappingError::WrongDirection {
+                    expected: SpindleDirection::CounterClockwise
+                }),
+                Ok(()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rigid_tapping_goes_straight_down_and_back() {
+        let src = "G0 X3 Y4 Z2\nM3 S1000\nG33.1 Z-8 K0.8";
+
+        let got = tapping_cycles(src, Dialect::linuxcnc());
+
+        assert_eq!(got.len(), 1);
+        let cycle = got[0];
+        assert!(cycle.rigid);
+        assert_eq!(cycle.pitch, Some(0.8));
+        assert_eq!(cycle.feed_rate, Some(800.0));
+        assert_eq!(cycle.end(), Position::new(3.0, 4.0, 2.0));
+        assert_eq!(cycle.motions().count(), 2);
+    }
+
+    #[test]
+    fn fanuc_lathes_use_g99_for_feed_per_revolution() {
+        let got = interpret("G99\nG1 F0.2", Dialect::fanuc_lathe());
+
+        assert_eq!(got.feed_mode, FeedMode::PerRevolution);
+        // G98 and G99 aren't the canned cycle return levels on a lathe
+        assert_eq!(got.cycle_return, CycleReturn::InitialLevel);
+
+        let got = interpret("G95\nG99", Dialect::linuxcnc());
+        assert_eq!(got.feed_mode, FeedMode::PerRevolution);
+        assert_eq!(got.cycle_return, CycleReturn::RPlane);
+    }
+
+    #[test]
+    fn tools_wait_for_a_tool_change() {
+        let got = interpret("T3", Dialect::linuxcnc());
+
+        assert_eq!(got.tool.prepared, Some(ToolSelection::tool(3)));
+        assert_eq!(got.tool.active, None);
+    }
+
+    #[test]
+    fn tool_change_before_the_tool_on_the_same_line() {
+        let got = interpret("T1 M6\nM6 T2", Dialect::linuxcnc());
+
+        assert_eq!(got.tool.prepared, None);
+        assert_eq!(got.tool.active, Some(ToolSelection::tool(2)));
+    }
+
+    #[test]
+    fn printers_change_tools_immediately() {
+        let got = interpret("T1", Dialect::reprap());
+        assert_eq!(got.tool.active, Some(ToolSelection::tool(1)));
+
+        let got = interpret("T1\nT-1", Dialect::reprap());
+        assert_eq!(got.tool.active, Some(ToolSelection::DESELECT));
+        assert!(got.tool.active.unwrap().is_deselect());
+
+        let got = interpret("T1\nT-2\nT1.5", Dialect::reprap());
+        assert_eq!(got.tool.active, Some(ToolSelection::tool(1)));
+    }
+
+    #[test]
+    fn lathe_tools_include_their_offset() {
+        let got = interpret("T0303", Dialect::fanuc_lathe());
+
+        assert_eq!(
+            got.tool.active,
+            Some(ToolSelection {
+                tool: Some(3),
+                offset: Some(3)
+            })
+        );
+    }
+
+    #[test]
+    fn spindle_speed_and_direction() {
+        let got = interpret("M3 S1200", Dialect::generic());
+
+        assert_eq!(got.spindle.direction, SpindleDirection::Clockwise);
+        assert_eq!(got.spindle.mode, SpindleSpeedMode::Rpm);
+        assert_eq!(got.spindle.speed, Some(1200.0));
+
+        let got = interpret("M3 S1200\nM5", Dialect::generic());
+
+        assert_eq!(got.spindle.direction, SpindleDirection::Stopped);
+        assert_eq!(got.spindle.speed, Some(1200.0));
+    }
+
+    #[test]
+    fn printers_dont_confuse_temperatures_with_spindle_speeds() {
+        let got = interpret("M104 S200\nM106 S255", Dialect::reprap());
+
+        assert_eq!(got.spindle.speed, None);
+    }
+
+    #[test]
+    fn fanuc_lathes_clamp_constant_surface_speed_with_g50() {
+        let src = "G50 S1800\nG96 S150 M3";
+        let got = interpret(src, Dialect::fanuc_lathe());
+
+        assert_eq!(got.spindle.max_rpm, Some(1800.0));
+        assert_eq!(got.spindle.mode, SpindleSpeedMode::ConstantSurfaceSpeed);
+        assert_eq!(got.spindle.speed, Some(150.0));
+        assert_eq!(got.spindle.rpm_at_diameter(got.units, 5.0), Some(1800.0));
+        assert_eq!(got.spindle.rpm_at_diameter(got.units, 0.0), Some(1800.0));
+    }
+
+    #[test]
+    fn g50_is_only_a_clamp_on_some_controls() {
+        let got = interpret("G50 S1800", Dialect::linuxcnc());
+
+        assert_eq!(got.spindle.max_rpm, None);
+    }
+
+    #[test]
+    fn linuxcnc_sets_the_clamp_with_g96_d() {
+        let got = interpret("G96 D2500 S100", Dialect::linuxcnc());
+
+        assert_eq!(got.spindle.max_rpm, Some(2500.0));
+        assert_eq!(got.spindle.rpm_at_diameter(got.units, 0.0), Some(2500.0));
+    }
+
+    #[test]
+    fn units_and_positioning() {
+        let got = interpret("G20 G91", Dialect::generic());
+
+        assert_eq!(got.units, Units::Inches);
+        assert_eq!(got.positioning, Positioning::Relative);
+
+        let got = interpret("G20 G91\nG21 G90", Dialect::generic());
+
+        assert_eq!(got, MachineState::default());
+    }
+
+    #[test]
+    fn axis_words_move_the_machine() {
+        let got = motions("G0 X10 Y5\nG1 Z-1 F300\nX20");
+
+        assert_eq!(got.len(), 3);
+        assert!(got[0].is_rapid());
+        assert_eq!(got[0].end, Position::new(10.0, 5.0, 0.0));
+        assert_eq!(got[0].feed_rate, None);
+        assert_eq!(got[1].kind, MotionKind::Linear);
+        assert_eq!(got[1].feed_rate, Some(300.0));
+        assert_eq!(got[2].kind, MotionKind::Linear);
+        assert_eq!(got[2].start, Position::new(10.0, 5.0, -1.0));
+        assert_eq!(got[2].end, Position::new(20.0, 5.0, -1.0));
+    }
+
+    #[test]
+    fn axis_words_on_a_non_motion_command_use_the_current_mode() {
+        let got = motions("G1 X1 F100\nG91 X2");
+
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[1].kind, MotionKind::Linear);
+        assert_eq!(got[1].end, Position::new(3.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn positions_are_always_in_millimeters() {
+        let got = interpret("G20 G1 X1 F10", Dialect::linuxcnc());
+
+        assert_eq!(got.position, Position::new(25.4, 0.0, 0.0));
+        assert_eq!(got.feed_rate, Some(254.0));
+    }
+
+    #[test]
+    fn set_position_and_home_without_moving_other_axes() {
+        let got = motions("G1 X5 Y5 Z5\nG92 X0\nG28 Z0\nG28");
+
+        assert_eq!(got.len(), 3);
+        assert_eq!(got[1].start, Position::new(0.0, 5.0, 5.0));
+        assert_eq!(got[1].end, Position::new(0.0, 5.0, 0.0));
+        assert_eq!(got[2].end, Position::ORIGIN);
+    }
+
+    #[test]
+    fn home_the_axes_given_as_flags() {
+        let got = motions("G1 X5 Y5 Z5\nG28 X Y");
+
+        assert_eq!(got[1].end, Position::new(0.0, 0.0, 5.0));
+    }
+
+    #[test]
+    fn dwells_and_machine_settings_dont_move() {
+        let got = motions("G4 X2\nM92 X80 Y80\nM203 Z5");
+
+        assert!(got.is_empty());
+    }
+
+    #[test]
+    fn arcs_using_a_center_offset() {
+        let got = motions("G1 X1 F100\nG3 X0 Y1 I-1 J0");
+        let arc = got[1];
+
+        assert_eq!(
+            arc.kind,
+            MotionKind::Arc(Arc {
+                center: Position::ORIGIN,
+                clockwise: false,
+                plane: Plane::XY,
+            })
+        );
+        assert!((arc.length() - core::f32::consts::FRAC_PI_2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn arcs_using_a_radius() {
+        let short = motions("G2 X2 Y0 R1.5");
+        let long = motions("G2 X2 Y0 R-1.5");
+
+        // the short way round has its center below the chord
+        match (short[0].kind, long[0].kind) {
+            (MotionKind::Arc(short_arc), MotionKind::Arc(long_arc)) => {
+                assert!(short_arc.center.y < 0.0);
+                assert!(long_arc.center.y > 0.0);
+            },
+            other => panic!("Expected arcs, found {:?}", other),
+        }
+        assert!(short[0].length() < long[0].length());
+        assert!(short[0].point_at(0.5).y > 0.0);
+    }
+
+    #[test]
+    fn full_circles_and_helixes() {
+        let got = motions("G1 X1 F100\nG2 X1 Y0 Z2 I-1 J0");
+        let helix = got[1];
+        let circumference = 2.0 * core::f32::consts::PI;
+        let expected = libm::sqrtf(circumference * circumference + 4.0);
+
+        assert!((helix.length() - expected).abs() < 1e-4);
+        assert_close(helix.point_at(0.25), Position::new(0.0, -1.0, 0.5));
+        assert_close(helix.point_at(1.0), Position::new(1.0, 0.0, 2.0));
+    }
+
+    #[test]
+    fn arcs_in_other_planes() {
+        use core::f32::consts::FRAC_1_SQRT_2;
+        let got = motions("G18 G2 X1 Z1 I0 K1");
+
+        assert_close(
+            got[0].point_at(0.5),
+            Position::new(FRAC_1_SQRT_2, 0.0, 1.0 - FRAC_1_SQRT_2),
+        );
+    }
+
+    #[test]
+    fn continuations_inherit_the_modal_command() {
+        let src = "G0 X1\nY2\nG81 X5 Z-1 R1\nX6\nG80\nG33 Z-10 K1.5\nX1\nT1";
+        let interpreter = Interpreter::new(Dialect::linuxcnc());
+
+        let got: Vec<_> = crate::parse(src)
+            .interpret_with(interpreter)
+            .map(|interpreted| interpreted.inherited)
+            .collect();
+
+        assert_eq!(
+            got,
+            vec![
+                None,
+                Some(CommandKey::general(0)),
+                None,
+                // another hole, not a G0
+                Some(CommandKey::general(81)),
+                None,
+                None,
+                Some(CommandKey::general(33)),
+                None,
+            ]
+        );
+    }
+
+    #[cfg(feature = "glam")]
+    #[test]
+    fn glam_round_trip() {
+        let position = Position::new(1.0, 2.5, -3.0);
+
+        let vector: glam::DVec3 = position.into();
+
+        assert_eq!(vector, glam::DVec3::new(1.0, 2.5, -3.0));
+        assert_eq!(Position::from(vector), position);
+        assert_eq!(Position::from(glam::Vec3::from(position)), position);
+    }
+
+    #[cfg(feature = "nalgebra")]
+    #[test]
+    fn nalgebra_round_trip() {
+        let position = Position::new(1.0, 2.5, -3.0);
+
+        let point: nalgebra::Point3<f64> = position.into();
+        let offset: nalgebra::Vector3<f32> = position.into();
+
+        assert_eq!(point, nalgebra::Point3::new(1.0, 2.5, -3.0));
+        assert_eq!(Position::from(point), position);
+        assert_eq!(Position::from(offset), position);
+    }
+}
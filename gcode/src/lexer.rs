@@ -1,4 +1,7 @@
-use crate::Span;
+use crate::{
+    dialect::{AlternateLeader, Dialect, MAX_ALTERNATE_LEADERS},
+    Span,
+};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub(crate) enum TokenType {
@@ -6,6 +9,13 @@ pub(crate) enum TokenType {
     Number,
     Comment,
     Newline,
+    /// A parameter (`#1`) or bracketed expression (`[1 + 2]`), when the
+    /// [`Dialect`] allows them.
+    Expression,
+    /// A parameter assignment (`#1 = 2`).
+    Assignment,
+    /// A line's checksum (`*27`), when the [`Dialect`] uses them.
+    Checksum,
     Unknown,
 }
 
@@ -37,14 +47,67 @@ pub(crate) struct Lexer<'input> {
     current_position: usize,
     current_line: usize,
     src: &'input str,
+    /// characters which should be lexed as though they were letters
+    leaders: [Option<AlternateLeader>; MAX_ALTERNATE_LEADERS],
+    /// lex parameters and bracketed expressions (see
+    /// [`Dialect::expressions`])
+    expressions: bool,
+    /// lex line checksums (see [`Dialect::checksums`])
+    checksums: bool,
+    /// keep going after a second decimal point (see [`Dialect::lenient`])
+    lenient: bool,
 }
 
 impl<'input> Lexer<'input> {
     pub(crate) fn new(src: &'input str) -> Self {
+        Lexer::starting_at(src, 0, 0)
+    }
+
+    /// Start lexing part-way through some text, where `position` is at the
+    /// start of a token on the zero-based `line`.
+    pub(crate) fn starting_at(
+        src: &'input str,
+        position: usize,
+        line: usize,
+    ) -> Self {
         Lexer {
-            current_position: 0,
-            current_line: 0,
+            current_position: position,
+            current_line: line,
             src,
+            leaders: [None; MAX_ALTERNATE_LEADERS],
+            expressions: false,
+            checksums: false,
+            lenient: false,
+        }
+    }
+
+    /// Also treat the [`Dialect::alternate_leaders`] as letters, lex
+    /// expressions and checksums if the [`Dialect`] uses them, and keep
+    /// malformed numbers together if it's [`Dialect::lenient`].
+    pub(crate) fn with_dialect(mut self, dialect: &Dialect) -> Self {
+        self.leaders = dialect.alternate_leaders;
+        self.expressions = dialect.expressions;
+        self.checksums = dialect.checksums;
+        self.lenient = dialect.lenient;
+        self
+    }
+
+    fn is_leader(&self, c: char) -> bool {
+        self.leaders.iter().flatten().any(|leader| leader.character == c)
+    }
+
+    fn classify(&self, c: char) -> TokenType {
+        match TokenType::from(c) {
+            TokenType::Unknown if self.is_leader(c) => TokenType::Letter,
+            TokenType::Unknown
+                if self.expressions && (c == '#' || c == '[') =>
+            {
+                TokenType::Expression
+            },
+            TokenType::Unknown if self.checksums && c == '*' => {
+                TokenType::Checksum
+            },
+            kind => kind,
         }
     }
 
@@ -132,14 +195,15 @@ impl<'input> Lexer<'input> {
         let c = self.rest().chars().next()?;
         let start = self.current_position;
 
-        if c.is_ascii_alphabetic() {
-            self.current_position += 1;
+        if c.is_ascii_alphabetic() || self.is_leader(c) {
+            let end = start + c.len_utf8();
+            self.current_position = end;
             Some(Token {
                 kind: TokenType::Letter,
-                value: &self.src[start..=start],
+                value: &self.src[start..end],
                 span: Span {
                     start,
-                    end: start + 1,
+                    end,
                     line: self.current_line,
                 },
             })
@@ -152,6 +216,7 @@ impl<'input> Lexer<'input> {
         let start = self.current_position;
         let line = self.current_line;
 
+        let lenient = self.lenient;
         let mut decimal_seen = false;
         let mut letters_seen = 0;
 
@@ -161,7 +226,7 @@ impl<'input> Lexer<'input> {
 
             if (is_sign && letters_seen == 1) || c.is_ascii_digit() {
                 true
-            } else if c == '.' && !decimal_seen {
+            } else if c == '.' && (!decimal_seen || lenient) {
                 decimal_seen = true;
                 true
             } else {
@@ -180,6 +245,62 @@ impl<'input> Lexer<'input> {
         })
     }
 
+    /// Lex a parameter or bracketed expression (possibly with a sign in
+    /// front), or a whole parameter assignment.
+    ///
+    /// An expression which doesn't finish before the end of the line is
+    /// garbage, and so is everything after it on that line.
+    fn tokenize_expression(&mut self) -> Option<Token<'input>> {
+        let start = self.current_position;
+        let rest = self.rest();
+        let line_length = rest.find('\n').unwrap_or(rest.len());
+
+        let (kind, length) = match expression_length(rest) {
+            Some(length) if rest.starts_with('#') => {
+                match assignment_length(rest, length) {
+                    Some(length) => (TokenType::Assignment, length),
+                    None => (TokenType::Expression, length),
+                }
+            },
+            Some(length) => (TokenType::Expression, length),
+            None if rest.starts_with(&['#', '['][..]) => {
+                (TokenType::Unknown, line_length)
+            },
+            None => return None,
+        };
+
+        self.current_position += length;
+
+        Some(Token {
+            kind,
+            value: &rest[..length],
+            span: Span::new(start, start + length, self.current_line),
+        })
+    }
+
+    /// Lex a `*` and the checksum after it, or just the `*` if there are no
+    /// digits (which makes it garbage).
+    fn tokenize_checksum(&mut self) -> Option<Token<'input>> {
+        let start = self.current_position;
+        let rest = self.rest();
+        let digits = rest[1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len() - 1);
+        let kind = if digits > 0 {
+            TokenType::Checksum
+        } else {
+            TokenType::Unknown
+        };
+        let length = 1 + digits;
+        self.current_position += length;
+
+        Some(Token {
+            kind,
+            value: &rest[..length],
+            span: Span::new(start, start + length, self.current_line),
+        })
+    }
+
     fn tokenize_newline(&mut self) -> Option<Token<'input>> {
         let start = self.current_position;
         let line = self.current_line;
@@ -202,10 +323,91 @@ impl<'input> Lexer<'input> {
     }
 
     fn peek(&self) -> Option<TokenType> {
-        self.rest().chars().next().map(TokenType::from)
+        self.rest().chars().next().map(|c| self.classify(c))
     }
 }
 
+/// The length of the parameter (`#1`, `##2`, `#<name>` or `#[1 + 2]`) or
+/// bracketed expression at the start of some text, which may have a sign in
+/// front of it.
+fn expression_length(text: &str) -> Option<usize> {
+    let sign = usize::from(text.starts_with(&['+', '-'][..]));
+    let rest = &text[sign..];
+    // "##2" is the parameter numbered by #2, so any number of #s may come
+    // before the name, number or expression
+    let target = rest.trim_start_matches('#');
+    let hashes = rest.len() - target.len();
+
+    let length = match target.chars().next()? {
+        '[' => bracketed_length(target)?,
+        _ if hashes == 0 => return None,
+        '<' => {
+            target
+                .find(&['>', '\n'][..])
+                .filter(|&end| target[end..].starts_with('>'))?
+                + 1
+        },
+        c if c.is_ascii_digit() => target
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(target.len()),
+        _ => return None,
+    };
+
+    Some(sign + hashes + length)
+}
+
+/// The length of some text starting with a `[`, up to and including its
+/// matching `]`.
+fn bracketed_length(text: &str) -> Option<usize> {
+    let mut depth = 0_usize;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            },
+            '\n' => return None,
+            _ => {},
+        }
+    }
+
+    None
+}
+
+/// If the parameter at the start of some text is followed by `=` and a
+/// value, the length of the whole assignment.
+fn assignment_length(text: &str, parameter: usize) -> Option<usize> {
+    let is_blank = |c: char| c == ' ' || c == '\t';
+    let after = &text[parameter..];
+    let equals = after.trim_start_matches(is_blank).strip_prefix('=')?;
+    let value = equals.trim_start_matches(is_blank);
+    let value_start = text.len() - value.len();
+
+    let value_length = match expression_length(value) {
+        Some(length) => length,
+        None => {
+            let length = value
+                .char_indices()
+                .take_while(|&(i, c)| {
+                    c.is_ascii_digit()
+                        || c == '.'
+                        || (i == 0 && (c == '-' || c == '+'))
+                })
+                .count();
+            if !value[..length].contains(|c: char| c.is_ascii_digit()) {
+                return None;
+            }
+            length
+        },
+    };
+
+    Some(value_start + value_length)
+}
+
 impl<'input> From<&'input str> for Lexer<'input> {
     fn from(other: &'input str) -> Lexer<'input> {
         Lexer::new(other)
@@ -241,13 +443,32 @@ impl<'input> Iterator for Lexer<'input> {
                 TokenType::Letter => {
                     return Some(self.tokenize_letter().expect(MSG))
                 },
+                TokenType::Number | TokenType::Expression
+                    if self.expressions =>
+                {
+                    if let Some(token) = self.tokenize_expression() {
+                        return Some(token);
+                    }
+                    return Some(self.tokenize_number().expect(MSG));
+                },
                 TokenType::Number => {
                     return Some(self.tokenize_number().expect(MSG))
                 },
                 TokenType::Newline => {
                     return Some(self.tokenize_newline().expect(MSG))
                 },
-                TokenType::Unknown => self.current_position += 1,
+                TokenType::Checksum => {
+                    return Some(self.tokenize_checksum().expect(MSG))
+                },
+                TokenType::Expression | TokenType::Assignment => {
+                    unreachable!("only lexed when expressions are enabled")
+                },
+                TokenType::Unknown => {
+                    // garbage may not be ASCII, so step over a whole character
+                    // to avoid slicing in the middle of one
+                    let c = self.rest().chars().next().expect(MSG);
+                    self.current_position += c.len_utf8();
+                },
             }
         }
 
@@ -272,7 +493,7 @@ mod tests {
     fn take_while_works_as_expected() {
         let mut lexer = Lexer::new("12345abcd");
 
-        let got = lexer.chomp(|c| c.is_digit(10));
+        let got = lexer.chomp(|c| c.is_ascii_digit());
 
         assert_eq!(got, Some("12345"));
         assert_eq!(lexer.current_position, 5);
@@ -369,6 +590,18 @@ mod tests {
         assert_eq!(next.value, "x");
     }
 
+    #[test]
+    fn garbage_can_contain_multi_byte_characters() {
+        let mut lexer = Lexer::new("é°ü X1");
+
+        let got = lexer.next().unwrap();
+
+        assert_eq!(got.value, "é°ü ");
+        assert_eq!(got.kind, TokenType::Unknown);
+        assert!(got.span.is_valid_for(lexer.src));
+        assert_eq!(lexer.next().unwrap().value, "X");
+    }
+
     #[test]
     fn tokenize_a_letter() {
         let mut lexer = Lexer::new("asd\nf");
@@ -450,4 +683,75 @@ mod tests {
         assert_eq!(got.value, "2");
         assert_eq!(got.span.line, 1);
     }
+
+    fn kinds(lexer: Lexer<'_>) -> Vec<(&str, TokenType)> {
+        lexer.map(|t| (t.value, t.kind)).collect()
+    }
+
+    #[test]
+    fn alternate_leaders_are_letters() {
+        let generic = Lexer::new(":1234");
+        assert_eq!(
+            kinds(generic),
+            vec![(":", TokenType::Unknown), ("1234", TokenType::Number)]
+        );
+
+        let fanuc = Lexer::new(":1234").with_dialect(&Dialect::fanuc());
+        assert_eq!(
+            kinds(fanuc),
+            vec![(":", TokenType::Letter), ("1234", TokenType::Number)]
+        );
+    }
+
+    #[test]
+    fn expressions_are_single_tokens() {
+        let src = "#1 = [2 * #<_x>]\nX-#1 Y[1 + SIN[30]] Z##2 A[1";
+        let lexer = Lexer::new(src).with_dialect(&Dialect::linuxcnc());
+
+        assert_eq!(
+            kinds(lexer),
+            vec![
+                ("#1 = [2 * #<_x>]", TokenType::Assignment),
+                ("\n", TokenType::Newline),
+                ("X", TokenType::Letter),
+                ("-#1", TokenType::Expression),
+                ("Y", TokenType::Letter),
+                ("[1 + SIN[30]]", TokenType::Expression),
+                ("Z", TokenType::Letter),
+                ("##2", TokenType::Expression),
+                ("A", TokenType::Letter),
+                ("[1", TokenType::Unknown),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_chains_of_parameters_dont_overflow_the_stack() {
+        let src = format!("X{}1 Y2", "#".repeat(100_000));
+        let lexer = Lexer::new(&src).with_dialect(&Dialect::linuxcnc());
+
+        let tokens = kinds(lexer);
+
+        assert_eq!(tokens[1], (&src[1..100_002], TokenType::Expression));
+        assert_eq!(tokens.len(), 4);
+    }
+
+    #[test]
+    fn lenient_dialects_keep_malformed_numbers_together() {
+        let strict = Lexer::new("X1.2.3").with_dialect(&Dialect::generic());
+        assert_eq!(
+            kinds(strict),
+            vec![
+                ("X", TokenType::Letter),
+                ("1.2", TokenType::Number),
+                (".3", TokenType::Number),
+            ]
+        );
+
+        let lenient = Lexer::new("X1.2.3").with_dialect(&Dialect::reprap());
+        assert_eq!(
+            kinds(lenient),
+            vec![("X", TokenType::Letter), ("1.2.3", TokenType::Number)]
+        );
+    }
 }
@@ -96,6 +96,10 @@
 //! assert_eq!(errors.garbage[0], "$$%# ");
 //! ```
 //!
+//! If you just want to load a whole file, see how long it'll take, tidy it up
+//! and save it again, the [`GcodeFile`] type (behind the `std` feature) wraps
+//! all of that up in a few lines. See the [`file`](mod@file) module for an example.
+//!
 //! # Customising Memory Usage
 //!
 //! You'll need to manually create a [`Parser`] if you want control over buffer
@@ -127,6 +131,84 @@
 //! assert_eq!(lines, 1);
 //! ```
 //!
+//! Commands which never change (e.g. a homing sequence) don't need to be
+//! parsed at all. A [`BorrowedGCode`] can be built in a `const` context, so
+//! firmware can keep them in a `static` table.
+//!
+//! # Streaming Input
+//!
+//! When a program arrives a few bytes at a time (e.g. over a serial link),
+//! a [`StreamingParser`] can be fed each chunk as it comes in. Lines are
+//! buffered until their newline arrives, so words and comments can be split
+//! across chunks.
+//!
+//! ```rust
+//! use gcode::{Nop, StreamingParser};
+//!
+//! let mut parser: StreamingParser = StreamingParser::new(Nop);
+//! let mut moves = Vec::new();
+//!
+//! for chunk in ["G01 X1", "0 (a com", "ment)\nY", "5\n"] {
+//!     parser.push_bytes(chunk.as_bytes(), |line| {
+//!         moves.extend(line.gcodes().iter().cloned())
+//!     });
+//! }
+//!
+//! assert_eq!(moves[0].value_for('X'), Some(10.0));
+//! // modal commands carry over from one line to the next
+//! assert_eq!(moves[1].major_number(), 1);
+//! assert_eq!(moves[1].value_for('Y'), Some(5.0));
+//! ```
+//!
+//! Firmware with its own link to the host can skip parsing text entirely,
+//! and receive each command as a binary [`frame`] instead.
+//!
+//! # Interpreting G-Code
+//!
+//! Most commands change the machine's modal state instead of doing something
+//! immediately. The [`interpret::Interpreter`] keeps track of this state
+//! (units, positioning mode, tool selection, etc.) as it walks through a
+//! program, and [`interpret::Interpret`] turns a stream of [`GCode`]s into
+//! typed [`interpret::Command`]s.
+//!
+//! With the `std` feature enabled, the [`analysis`] module builds on this to
+//! estimate a program's timeline (which [`calibration`] can tune against
+//! times measured on a real machine, and the [`planner`] can refine with
+//! the speed the machine really moves at). Hosts can [`replay`] a captured
+//! serial session to see which commands it gets wrong. The timeline can
+//! also be checked for moves which shake the machine near its
+//! [`resonance`]. Intake pipelines can check which [`modal`] conventions
+//! (units, work offsets, etc.) a file uses before accepting it. The
+//! [`executor`] module runs parametric programs which use `#` parameters and
+//! `[...]` expressions, and [`coverage`] checks how much of such a program
+//! has really been exercised. A [`profile::MachineProfile`] describes a particular
+//! machine, and can check whether a program will run on it, while
+//! [`detect`] guesses which dialect an unknown program was written for.
+//! A [`batch`] runs the same checks over a whole folder of programs.
+//! Slicer profile editors can check start and end G-code against a
+//! [`snippet`] policy before it ends up in every print.
+//! The configuration a file was sliced with can be read from (and edited
+//! in) its trailing [`slicer`] settings block.
+//! Applications which leave their own annotations in comments can describe
+//! them with [`events`] schemas and get them back as typed events, and GUIs
+//! can draw a [`preview`] of the toolpath with their existing 2D graphics
+//! stack. Senders can check which lines are unsafe to [`hold`] on before
+//! pausing a job, and [`queue`] whether the link is fast enough to keep the
+//! machine's planner buffer from running dry.
+//!
+//! # Writing G-Code
+//!
+//! The [`writer`] module lets you turn [`GCode`]s and [`Line`]s back into
+//! text, with numbers formatted the way a particular [`dialect::Dialect`]
+//! expects, while the [`transform`] module (behind the `std` feature)
+//! rewrites entire programs. Transforms can be chained together into a
+//! [`pipeline::Pipeline`] of post-processing passes, which can leave typed
+//! [`metadata`] on lines and segments for later passes to read. Programs which
+//! must be written back out without any numeric drift can keep their numbers
+//! as exact [`decimal::Decimal`]s, and tools which only edit a few lines can
+//! use [`RawLines`] to copy everything else (including anything the parser
+//! doesn't understand) verbatim.
+//!
 //! # Spans
 //!
 //! Something that distinguishes this crate from a lot of other g-code parsers
@@ -153,6 +235,22 @@
 //! - **std:** adds `std::error::Error` impls to any errors and switches to
 //!   `Vec` for the default backing buffers
 //! - **serde-1:** allows serializing and deserializing most types with `serde`
+//! - **profile-toml:** loading machine profiles and pipelines from TOML
+//! - **profile-json:** loading machine profiles and pipelines from JSON
+//! - **builtin-profiles:** ready-made profiles for popular machines
+//! - **sidecar:** saving analysis indices to disk, so large files don't need
+//!   to be analyzed again
+//! - **kurbo:** converting toolpaths into `kurbo` paths for rendering
+//!   previews
+//! - **lyon:** converting toolpaths into `lyon` paths for rendering previews
+//! - **glam:** converting positions to and from `glam`'s vector types
+//! - **nalgebra:** converting positions to and from `nalgebra`'s vector types
+//! - **tracing:** emits `tracing` spans and events as lines are parsed,
+//!   commands are interpreted and pipeline passes run, so you can see why a
+//!   file was understood the way it was
+//! - **corpus:** real-world programs from popular slicers and CAM packages,
+//!   for checking your own passes against
+//! - **nightly:** benchmarks, which rely on the unstable `test` crate
 #![deny(
     bare_trait_objects,
     elided_lifetimes_in_paths,
@@ -181,22 +279,71 @@ extern crate pretty_assertions;
 #[macro_use]
 mod macros;
 
+with_std! {
+    pub mod analysis;
+    pub mod batch;
+    pub mod calibration;
+    pub mod control;
+    pub mod coverage;
+    pub mod detect;
+    pub mod events;
+    pub mod executor;
+    pub mod expr;
+    pub mod file;
+    pub mod hold;
+    pub mod line_index;
+    pub mod lint;
+    pub mod metadata;
+    pub mod metrics;
+    pub mod modal;
+    pub mod pipeline;
+    pub mod planner;
+    pub mod preview;
+    pub mod profile;
+    pub mod program;
+    pub mod queue;
+    pub mod replay;
+    pub mod resonance;
+    pub mod seek;
+    pub mod sidecar;
+    pub mod slicer;
+    pub mod snippet;
+    pub mod spill;
+    pub mod transform;
+}
+#[cfg(feature = "corpus")]
+#[cfg_attr(docsrs, doc(cfg(feature = "corpus")))]
+pub mod corpus;
+
 pub mod buffers;
 mod callbacks;
 mod comment;
+pub mod decimal;
+pub mod dialect;
+pub mod frame;
 mod gcode;
+pub mod interpret;
 mod lexer;
 mod line;
 mod parser;
+mod raw;
 mod span;
+mod streaming;
 mod words;
+pub mod writer;
 
 pub use crate::{
     callbacks::{Callbacks, Nop},
     comment::Comment,
-    gcode::{GCode, Mnemonic},
+    gcode::{BorrowedGCode, CommandKey, GCode, Mnemonic},
     line::Line,
-    parser::{full_parse_with_callbacks, parse, Parser},
+    parser::{full_parse_with_callbacks, parse, ParseError, Parser},
+    raw::{RawLine, RawLines},
     span::Span,
-    words::Word,
+    streaming::StreamingParser,
+    words::{Word, WordValue},
 };
+
+with_std! {
+    pub use crate::file::GcodeFile;
+}
@@ -0,0 +1,194 @@
+//! Mapping between line numbers and byte offsets.
+//!
+//! Every [`Span`] records the line it starts on, but tools which memory-map
+//! a huge file often need to go the other way: find where line 1,000,000
+//! starts so they can seek to it, or turn a byte offset from somewhere else
+//! into a line and column for an editor. A [`LineIndex`] records where each
+//! line starts, so these lookups don't need to rescan the text.
+//!
+//! ```rust
+//! use gcode::line_index::LineIndex;
+//!
+//! let src = "G21\nG0 X10 Y20\r\nM30";
+//! let index = LineIndex::new(src);
+//!
+//! assert_eq!(index.line_count(), 3);
+//! assert_eq!(index.line_start(1), Some(4));
+//! assert_eq!(index.line_text(src, 1), Some("G0 X10 Y20"));
+//!
+//! let offset = src.find("Y20").unwrap();
+//! assert_eq!(index.line_and_column(offset), (1, 7));
+//! ```
+//!
+//! An index can also be built a chunk at a time with
+//! [`LineIndex::push_str()`], while the same chunks are being fed to a
+//! parser.
+
+use crate::Span;
+use core::ops::Range;
+use std::vec::Vec;
+
+/// The byte offset each line starts at.
+///
+/// Lines are separated by `\n`, the same as for [`Span::line`], so a `\r`
+/// before the newline is treated as part of the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    /// Index some text.
+    pub fn new(src: &str) -> Self {
+        let mut index = LineIndex::default();
+        index.push_str(src);
+        index
+    }
+
+    /// Index the next chunk of text, as if it had been appended to
+    /// everything indexed so far.
+    ///
+    /// ```rust
+    /// # use gcode::line_index::LineIndex;
+    /// let mut index = LineIndex::default();
+    ///
+    /// index.push_str("G0 X1\nG0");
+    /// index.push_str(" X2\nM30");
+    ///
+    /// assert_eq!(index, LineIndex::new("G0 X1\nG0 X2\nM30"));
+    /// ```
+    pub fn push_str(&mut self, chunk: &str) {
+        let start = self.len;
+        let newlines = chunk
+            .bytes()
+            .enumerate()
+            .filter(|&(_, b)| b == b'\n')
+            .map(|(i, _)| start + i + 1);
+
+        self.line_starts.extend(newlines);
+        self.len += chunk.len();
+    }
+
+    /// The number of lines, including an empty line after a trailing
+    /// newline.
+    pub fn line_count(&self) -> usize { self.line_starts.len() }
+
+    /// The total number of bytes which have been indexed.
+    pub fn len(&self) -> usize { self.len }
+
+    /// Has nothing been indexed?
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// The byte offset a (zero-based) line starts at.
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.line_starts.get(line).copied()
+    }
+
+    /// The bytes covered by a line, not including its trailing newline.
+    pub fn line_range(&self, line: usize) -> Option<Range<usize>> {
+        let start = self.line_start(line)?;
+        let end = match self.line_start(line + 1) {
+            Some(next) => next - 1,
+            None => self.len,
+        };
+
+        Some(start..end)
+    }
+
+    /// A [`Span`] covering an entire line, not including its trailing
+    /// newline.
+    pub fn line_span(&self, line: usize) -> Option<Span> {
+        self.line_range(line)
+            .map(|range| Span::new(range.start, range.end, line))
+    }
+
+    /// Get a line's text, without its line ending (`\n` or `\r\n`).
+    ///
+    /// `src` must be the text the index was built from.
+    pub fn line_text<'src>(
+        &self,
+        src: &'src str,
+        line: usize,
+    ) -> Option<&'src str> {
+        let text = src.get(self.line_range(line)?)?;
+
+        Some(text.strip_suffix('\r').unwrap_or(text))
+    }
+
+    /// The (zero-based) line containing a byte offset.
+    ///
+    /// Offsets past the end of the text are on the last line.
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.line_starts
+            .partition_point(|&start| start <= offset)
+            .saturating_sub(1)
+    }
+
+    /// The (zero-based) line and column of a byte offset, where the column
+    /// is the number of bytes since the start of the line.
+    pub fn line_and_column(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_of(offset);
+
+        (line, offset - self.line_starts[line])
+    }
+
+    /// Where each line starts.
+    pub fn line_starts(&self) -> &[usize] { &self.line_starts }
+}
+
+impl Default for LineIndex {
+    /// An index of the empty string, which still has one (empty) line.
+    fn default() -> Self {
+        LineIndex {
+            line_starts: vec![0],
+            len: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Nop, Parser};
+
+    #[test]
+    fn empty_text_has_one_line() {
+        let index = LineIndex::new("");
+
+        assert_eq!(index.line_count(), 1);
+        assert_eq!(index.line_range(0), Some(0..0));
+        assert_eq!(index.line_range(1), None);
+        assert_eq!(index.line_and_column(0), (0, 0));
+    }
+
+    #[test]
+    fn trailing_newlines_start_an_empty_line() {
+        let src = "G0\n\nG1\n";
+        let index = LineIndex::new(src);
+
+        assert_eq!(index.line_starts(), &[0, 3, 4, 7]);
+        assert_eq!(index.line_text(src, 1), Some(""));
+        assert_eq!(index.line_text(src, 3), Some(""));
+        assert_eq!(index.line_of(100), 3);
+    }
+
+    #[test]
+    fn agrees_with_the_parser() {
+        let src = "G90\r\n(comment)\n\nN10 G1 X1 ; move\nM30";
+        let index = LineIndex::new(src);
+
+        for line in Parser::<_>::new(src, Nop) {
+            let span = line.span();
+
+            assert_eq!(index.line_of(span.start), span.line);
+            let whole_line = index.line_span(span.line).unwrap();
+            assert!(whole_line.start <= span.start);
+            assert!(span.end <= whole_line.end);
+        }
+    }
+}
@@ -0,0 +1,787 @@
+//! Spotting likely mistakes in programs.
+//!
+//! The checks in this module look at a program's structure without running
+//! it, so they can point out problems in branches which are rarely taken.
+//! [`conditions()`] looks at the control flow of parametric programs, while
+//! [`pairing()`] makes sure things like the spindle and heaters are turned
+//! off again before the program ends, [`tapping()`] checks that tapping
+//! cycles have the spindle speed and feed they need, and [`arcs()`] points
+//! out arcs whose centers had to be guessed.
+//!
+//! ```rust
+//! use gcode::lint::{self, WarningKind};
+//!
+//! let src = "\
+//! #<depth> = 2
+//! O1 if [#<dpeth> GT 1]
+//!   G1 Z-#<depth>
+//! O1 endif
+//! ";
+//!
+//! let warnings = lint::conditions(src);
+//!
+//! assert_eq!(warnings.len(), 1);
+//! assert_eq!(warnings[0].line, 1);
+//! assert_eq!(
+//!     warnings[0].kind,
+//!     WarningKind::NeverAssigned(gcode::expr::ParameterId::named("dpeth")),
+//! );
+//! ```
+
+use crate::{
+    control::{ControlErrorKind, ControlFlow, Keyword, Statement},
+    dialect::Dialect,
+    executor::{parse_statement, Item},
+    expr::{ParameterId, Parameters},
+    interpret::{self, Interpreter, Plane, TappingError},
+    CommandKey, GCode, Mnemonic, Nop, Parser, Span,
+};
+use core::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+
+/// Something in a program which is probably a mistake.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Warning {
+    /// The (zero-based) line the problem was found on.
+    pub line: usize,
+    /// What the problem is.
+    pub kind: WarningKind,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line + 1, self.kind)
+    }
+}
+
+/// The different kinds of [`Warning`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum WarningKind {
+    /// A condition reads a parameter which is never given a value.
+    NeverAssigned(ParameterId),
+    /// A condition which is always true or always false.
+    ConstantCondition(bool),
+    /// A block which can never run, spanning up to (but not including) the
+    /// `end` line.
+    Unreachable {
+        /// The (zero-based) line the block finishes on.
+        end: usize,
+    },
+    /// A control statement which doesn't fit into the program's structure.
+    Structure(ControlErrorKind),
+    /// Something (e.g. the spindle, coolant or a heater) was turned on and
+    /// left on when the program ended.
+    LeftOn {
+        /// The command which turned it on.
+        command: CommandKey,
+        /// Where the command is.
+        span: Span,
+    },
+    /// A tapping cycle which won't cut a usable thread.
+    Tapping(TappingError),
+    /// An arc which only gives one of its plane's center offsets, so the
+    /// other was taken to be `0`.
+    PartialArc {
+        /// The offset which was left out (e.g. `J`).
+        missing: char,
+    },
+    /// An arc with neither a radius nor any center offsets.
+    ArcWithoutCenter,
+}
+
+impl Display for WarningKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            WarningKind::NeverAssigned(parameter) => {
+                write!(f, "{} is never assigned a value", parameter)
+            },
+            WarningKind::ConstantCondition(value) => {
+                write!(f, "this condition is always {}", value)
+            },
+            WarningKind::Unreachable { end } => {
+                write!(f, "the code up to line {} can never run", end + 1)
+            },
+            WarningKind::Structure(e) => e.fmt(f),
+            WarningKind::LeftOn { command, .. } => write!(
+                f,
+                "{} is never turned off before the program ends",
+                command
+            ),
+            WarningKind::Tapping(e) => write!(f, "can't tap: {}", e),
+            WarningKind::PartialArc { missing } => write!(
+                f,
+                "the arc has no {} offset, so it was taken to be 0",
+                missing
+            ),
+            WarningKind::ArcWithoutCenter => {
+                write!(f, "the arc has neither a radius nor a center")
+            },
+        }
+    }
+}
+
+/// Check the conditions in a program's `if`, `elseif`, `while` and `repeat`
+/// statements, and look for blocks which can never be reached.
+///
+/// This looks for:
+///
+/// - Conditions reading a parameter which is never assigned (parameters only
+///   checked with `EXISTS[]`, subroutine arguments and the parameters LinuxCNC
+///   predefines are ignored)
+/// - Conditions which don't depend on any parameters, so are always true or
+///   always false (`while` loops which are exited with a `break` are allowed to
+///   loop forever)
+/// - Branches which can't be reached because of a constant condition, and
+///   subroutines which are never called
+/// - Control statements which don't match up
+pub fn conditions(src: &str) -> Vec<Warning> {
+    let control = ControlFlow::new(src);
+    let assigned = assigned_parameters(src);
+    let mut warnings: Vec<Warning> = control
+        .errors()
+        .iter()
+        .map(|error| Warning {
+            line: error.line,
+            kind: WarningKind::Structure(error.kind),
+        })
+        .collect();
+
+    for (line, statement) in control.statements() {
+        if let Some(condition) = statement.condition() {
+            let in_subroutine = subroutine_containing(&control, line).is_some();
+
+            for parameter in condition.parameters() {
+                if !assigned.contains(&parameter)
+                    && !is_predefined(&parameter, in_subroutine)
+                {
+                    warnings.push(Warning {
+                        line,
+                        kind: WarningKind::NeverAssigned(parameter),
+                    });
+                }
+            }
+        }
+
+        check_reachability(&control, line, statement, &mut warnings);
+    }
+
+    let called: BTreeSet<_> = control
+        .statements()
+        .filter(|(_, statement)| statement.keyword == Keyword::Call)
+        .map(|(_, statement)| &statement.label)
+        .collect();
+
+    for (line, statement) in control.statements() {
+        if statement.keyword == Keyword::Sub
+            && !called.contains(&statement.label)
+        {
+            if let Some(end) = control.end_of(line) {
+                warnings.push(Warning {
+                    line,
+                    kind: WarningKind::Unreachable { end },
+                });
+            }
+        }
+    }
+
+    warnings.sort_by_key(|warning| warning.line);
+    warnings
+}
+
+/// Make sure every stateful `M` command is turned off again before the
+/// program ends (`M2` or `M30`, or the end of the text), so the machine
+/// isn't left running.
+///
+/// These are checked:
+///
+/// - The spindle (`M3` and `M4`, turned off by `M5`)
+/// - Coolant (`M7` and `M8`, turned off by `M9`)
+/// - Fans (`M106`, turned off by `M107` or `M106 S0`), for each `P` index
+/// - Hot end heaters (`M104` and `M109`), for each tool
+/// - The bed and chamber heaters (`M140`, `M190`, `M141` and `M191`)
+///
+/// Heaters are turned off by setting their temperature to `0`. Each
+/// warning points at the most recent command which turned something on.
+///
+/// ```rust
+/// use gcode::{
+///     dialect::Dialect,
+///     lint::{self, WarningKind},
+///     CommandKey,
+/// };
+///
+/// let src = "M3 S12000\nM8\nG1 X10 F300\nM9\nM30";
+///
+/// let warnings = lint::pairing(src, &Dialect::generic());
+///
+/// assert_eq!(warnings.len(), 1);
+/// assert_eq!(warnings[0].line, 0);
+/// match &warnings[0].kind {
+///     WarningKind::LeftOn { command, span } => {
+///         assert_eq!(*command, CommandKey::miscellaneous(3));
+///         assert_eq!(&src[span.start..span.end], "M3 S12000");
+///     },
+///     other => panic!("unexpected warning: {:?}", other),
+/// }
+/// ```
+pub fn pairing(src: &str, dialect: &Dialect) -> Vec<Warning> {
+    const PROGRAM_ENDS: [CommandKey; 2] =
+        [CommandKey::miscellaneous(2), CommandKey::miscellaneous(30)];
+
+    let mut warnings = Vec::new();
+    let mut turned_on: BTreeMap<Device, (CommandKey, Span)> = BTreeMap::new();
+    let mut tool = 0;
+
+    let mut report = |turned_on: &mut BTreeMap<Device, (CommandKey, Span)>| {
+        for (_, (command, span)) in core::mem::take(turned_on) {
+            warnings.push(Warning {
+                line: span.line,
+                kind: WarningKind::LeftOn { command, span },
+            });
+        }
+    };
+
+    for line in Parser::<_>::new_with_dialect(src, Nop, *dialect) {
+        let gcodes = line.gcodes();
+        let mut heater_index = false;
+
+        for (i, gcode) in gcodes.iter().enumerate() {
+            if gcode.mnemonic == Mnemonic::ToolChange {
+                // deselecting the tool (RepRapFirmware's "T-1") leaves
+                // M104 and friends pointing at the last one
+                if !core::mem::take(&mut heater_index) {
+                    if let Ok(index) = u32::try_from(gcode.key().major) {
+                        tool = index;
+                    }
+                }
+                continue;
+            }
+            // arguments carried over from an earlier line (e.g. "S2000"
+            // after "M3 S1000") don't repeat the command
+            if gcode.span.is_placeholder() {
+                continue;
+            }
+
+            // "M104 T1 S200" is read as an M104 followed by "T1 S200"
+            let heater = gcodes
+                .get(i + 1)
+                .filter(|next| next.mnemonic == Mnemonic::ToolChange)
+                .filter(|_| is_heater(gcode));
+            heater_index = heater.is_some();
+
+            if let Some((device, on)) = device_change(gcode, heater, tool) {
+                if on {
+                    let _ = turned_on.insert(device, (gcode.key(), gcode.span));
+                } else {
+                    let _ = turned_on.remove(&device);
+                }
+            }
+
+            if PROGRAM_ENDS.contains(&gcode.key()) {
+                report(&mut turned_on);
+            }
+        }
+    }
+
+    report(&mut turned_on);
+
+    warnings.sort_by_key(|warning| warning.line);
+    warnings
+}
+
+/// Something which can be left turned on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Device {
+    Spindle,
+    Coolant,
+    Fan(u32),
+    Heater(u32),
+    Bed,
+    Chamber,
+}
+
+fn is_heater(gcode: &GCode) -> bool {
+    gcode.mnemonic == Mnemonic::Miscellaneous
+        && gcode.minor_number() == 0
+        && matches!(gcode.major_number(), 104 | 109)
+}
+
+/// Does a command turn something on (`true`) or off (`false`)? A heater's
+/// tool index and temperature may be in the `T` word that follows it.
+fn device_change(
+    gcode: &GCode,
+    heater: Option<&GCode>,
+    tool: u32,
+) -> Option<(Device, bool)> {
+    if gcode.mnemonic != Mnemonic::Miscellaneous || gcode.minor_number() != 0 {
+        return None;
+    }
+
+    let value = |letter| {
+        gcode
+            .value_for(letter)
+            .or_else(|| heater.and_then(|t| t.value_for(letter)))
+    };
+    let fan = || Device::Fan(value('P').map_or(0, |index| index as u32));
+    // heaters may be given their target temperature with R instead of S
+    let heating = || {
+        value('S')
+            .or_else(|| value('R'))
+            .map(|temperature| temperature > 0.0)
+    };
+
+    match gcode.major_number() {
+        3 | 4 => Some((Device::Spindle, true)),
+        5 => Some((Device::Spindle, false)),
+        7 | 8 => Some((Device::Coolant, true)),
+        9 => Some((Device::Coolant, false)),
+        106 => Some((fan(), value('S').is_none_or(|speed| speed > 0.0))),
+        107 => Some((fan(), false)),
+        104 | 109 => {
+            let tool = heater.map_or(tool, GCode::major_number);
+            Some((Device::Heater(tool), heating()?))
+        },
+        140 | 190 => Some((Device::Bed, heating()?)),
+        141 | 191 => Some((Device::Chamber, heating()?)),
+        _ => None,
+    }
+}
+
+fn check_reachability(
+    control: &ControlFlow,
+    line: usize,
+    statement: &Statement,
+    warnings: &mut Vec<Warning>,
+) {
+    let value = match statement.condition() {
+        Some(condition) if condition.is_constant() => {
+            match condition.evaluate(&Parameters::new()) {
+                Ok(value) => value,
+                Err(_) => return,
+            }
+        },
+        _ => return,
+    };
+    let mut warn = |line, kind| warnings.push(Warning { line, kind });
+
+    match statement.keyword {
+        Keyword::If | Keyword::ElseIf => {
+            warn(line, WarningKind::ConstantCondition(value != 0.0));
+            let next = match control.next_branch(line) {
+                Some(next) => next,
+                None => return,
+            };
+
+            if value == 0.0 {
+                warn(line, WarningKind::Unreachable { end: next });
+            } else {
+                // every later branch is unreachable
+                let mut branch = next;
+                while let Some(after) = control.next_branch(branch) {
+                    warn(branch, WarningKind::Unreachable { end: after });
+                    branch = after;
+                }
+            }
+        },
+        Keyword::While
+            if control.closes_do_loop(line)
+                && (value == 0.0 || !has_break(control, line)) =>
+        {
+            warn(line, WarningKind::ConstantCondition(value != 0.0));
+        },
+        Keyword::While if control.closes_do_loop(line) => {},
+        Keyword::While => {
+            if value == 0.0 {
+                warn(line, WarningKind::ConstantCondition(false));
+                if let Some(end) = control.end_of(line) {
+                    warn(line, WarningKind::Unreachable { end });
+                }
+            } else if !has_break(control, line) {
+                warn(line, WarningKind::ConstantCondition(true));
+            }
+        },
+        Keyword::Repeat if libm::roundf(value) < 1.0 => {
+            if let Some(end) = control.end_of(line) {
+                warn(line, WarningKind::Unreachable { end });
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Does a loop contain a `break`?
+fn has_break(control: &ControlFlow, line: usize) -> bool {
+    let start = control.start_of(line).unwrap_or(line);
+
+    control.statements().any(|(other, statement)| {
+        statement.keyword == Keyword::Break
+            && control.enclosing(other) == Some(start)
+    })
+}
+
+fn subroutine_containing(control: &ControlFlow, line: usize) -> Option<usize> {
+    control
+        .statements()
+        .filter(|(start, statement)| {
+            statement.keyword == Keyword::Sub && *start < line
+        })
+        .find(|&(start, _)| control.end_of(start).is_some_and(|end| end > line))
+        .map(|(start, _)| start)
+}
+
+/// Every parameter the program assigns to directly.
+fn assigned_parameters(src: &str) -> BTreeSet<ParameterId> {
+    let empty = Parameters::new();
+
+    src.lines()
+        .filter_map(|text| parse_statement(text).ok())
+        .flatten()
+        .filter_map(|item| match item {
+            Item::Assignment { parameter, .. } => {
+                parameter.parameter_id(&empty).ok().flatten()
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parameters which are set by the controller (or a subroutine call) rather
+/// than the program.
+fn is_predefined(parameter: &ParameterId, in_subroutine: bool) -> bool {
+    const NAMES: &[&str] = &[
+        "_vmajor",
+        "_vminor",
+        "_line",
+        "_motion_mode",
+        "_plane",
+        "_ccomp",
+        "_metric",
+        "_imperial",
+        "_absolute",
+        "_incremental",
+        "_inverse_time",
+        "_units_per_minute",
+        "_units_per_rev",
+        "_coord_system",
+        "_tool_offset",
+        "_retract_r_plane",
+        "_retract_old_z",
+        "_spindle_rpm_mode",
+        "_spindle_css_mode",
+        "_ijk_absolute_mode",
+        "_lathe_diameter_mode",
+        "_lathe_radius_mode",
+        "_spindle_on",
+        "_spindle_cw",
+        "_mist",
+        "_flood",
+        "_speed_override",
+        "_feed_override",
+        "_adaptive_feed",
+        "_feed_hold",
+        "_feed",
+        "_rpm",
+        "_current_tool",
+        "_current_pocket",
+        "_selected_tool",
+        "_selected_pocket",
+        "_value",
+        "_value_returned",
+        "_task",
+        "_call_level",
+        "_remap_level",
+    ];
+    const AXES: &str = "xyzabcuvw";
+
+    match parameter {
+        ParameterId::Numbered(number) => {
+            *number >= 5000 || (in_subroutine && *number <= 30)
+        },
+        ParameterId::Named(name) => {
+            let axis = name
+                .strip_prefix("_abs_")
+                .or_else(|| name.strip_prefix('_'))
+                .is_some_and(|axis| axis.len() == 1 && AXES.contains(axis));
+
+            NAMES.contains(&name.as_str())
+                || axis
+                || name.starts_with("_ini[")
+                || name.starts_with("_hal[")
+        },
+    }
+}
+
+/// Check every tapping cycle (`G84`, `G74`, and LinuxCNC's `G33.1`) using
+/// [`TappingCycle::check()`], making sure the spindle is turning the right
+/// way at a fixed speed and there's a feed rate to tap with.
+///
+/// ```rust
+/// use gcode::{
+///     dialect::Dialect,
+///     interpret::TappingError,
+///     lint::{self, WarningKind},
+/// };
+///
+/// let src = "G0 Z5\nG84 X10 Z-10 R2 F500\nM3 S500\nX20\nG96 S100\nG84 X30\nG80";
+///
+/// let warnings = lint::tapping(src, &Dialect::generic());
+///
+/// let got: Vec<_> = warnings.iter().map(|w| (w.line, &w.kind)).collect();
+/// assert_eq!(
+///     got,
+///     vec![
+///         (1, &WarningKind::Tapping(TappingError::SpindleStopped)),
+///         (5, &WarningKind::Tapping(TappingError::VariableSpindleSpeed)),
+///     ]
+/// );
+/// ```
+///
+/// [`TappingCycle::check()`]: crate::interpret::TappingCycle::check
+pub fn tapping(src: &str, dialect: &Dialect) -> Vec<Warning> {
+    let mut interpreter = Interpreter::new(*dialect);
+    let mut warnings = Vec::new();
+
+    for line in Parser::<_>::new_with_dialect(src, Nop, *dialect) {
+        for gcode in interpret::execution_order(&line) {
+            let cycle = interpreter.tapping_cycle(gcode);
+
+            if let Some(Err(e)) = cycle.map(|cycle| cycle.check()) {
+                warnings.push(Warning {
+                    line: line.span().line,
+                    kind: WarningKind::Tapping(e),
+                });
+            }
+
+            let _ = interpreter.process(gcode);
+        }
+    }
+
+    warnings
+}
+
+/// Check every arc (`G2` and `G3`) which gives its center as offsets from
+/// its start point instead of using a radius (`R`).
+///
+/// Most controllers read a missing offset as `0`, so `G2 X10 I5` in the XY
+/// plane is the same as `G2 X10 I5 J0`, and the [`Interpreter`] does too.
+/// That's usually what was meant, but some controllers refuse to run it and
+/// it can also be a sign of a word lost in transmission. An arc without any
+/// offsets would be centered on its own start point.
+///
+/// ```rust
+/// use gcode::{
+///     dialect::Dialect,
+///     lint::{self, WarningKind},
+/// };
+///
+/// let src = "G17 G2 X10 Y0 I5 J0\nG3 X0 I-5\nG18 G2 X10 Z0 K5\nG2 X0";
+///
+/// let warnings = lint::arcs(src, &Dialect::generic());
+///
+/// let got: Vec<_> = warnings.iter().map(|w| (w.line, &w.kind)).collect();
+/// assert_eq!(
+///     got,
+///     vec![
+///         (1, &WarningKind::PartialArc { missing: 'J' }),
+///         (2, &WarningKind::PartialArc { missing: 'I' }),
+///         (3, &WarningKind::ArcWithoutCenter),
+///     ]
+/// );
+/// ```
+pub fn arcs(src: &str, dialect: &Dialect) -> Vec<Warning> {
+    let mut interpreter = Interpreter::new(*dialect);
+    let mut warnings = Vec::new();
+
+    for line in Parser::<_>::new_with_dialect(src, Nop, *dialect) {
+        for gcode in interpret::execution_order(&line) {
+            let key = interpreter
+                .inherited_command(gcode)
+                .unwrap_or_else(|| gcode.key());
+            let is_arc =
+                key == CommandKey::general(2) || key == CommandKey::general(3);
+
+            if is_arc
+                && !gcode.arguments().is_empty()
+                && gcode.value_for('R').is_none()
+            {
+                let (first, second) = center_letters(interpreter.state().plane);
+                let kind = match (
+                    gcode.value_for(first).is_some(),
+                    gcode.value_for(second).is_some(),
+                ) {
+                    (true, true) => None,
+                    (true, false) => {
+                        Some(WarningKind::PartialArc { missing: second })
+                    },
+                    (false, true) => {
+                        Some(WarningKind::PartialArc { missing: first })
+                    },
+                    (false, false) => Some(WarningKind::ArcWithoutCenter),
+                };
+
+                if let Some(kind) = kind {
+                    warnings.push(Warning {
+                        line: line.span().line,
+                        kind,
+                    });
+                }
+            }
+
+            let _ = interpreter.process(gcode);
+        }
+    }
+
+    warnings
+}
+
+/// The letters giving an arc's center in a [`Plane`].
+fn center_letters(plane: Plane) -> (char, char) {
+    match plane {
+        Plane::XY => ('I', 'J'),
+        Plane::ZX => ('I', 'K'),
+        Plane::YZ => ('J', 'K'),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpret::SpindleDirection;
+
+    fn kinds(src: &str) -> Vec<(usize, WarningKind)> {
+        conditions(src)
+            .into_iter()
+            .map(|warning| (warning.line, warning.kind))
+            .collect()
+    }
+
+    #[test]
+    fn constant_conditions_make_branches_unreachable() {
+        let src = "O1 if [1 GT 2]\nG0 X1\nO1 elseif [1]\nG0 X2\nO1 else\nG0 \
+                   X3\nO1 endif";
+
+        assert_eq!(
+            kinds(src),
+            vec![
+                (0, WarningKind::ConstantCondition(false)),
+                (0, WarningKind::Unreachable { end: 2 }),
+                (2, WarningKind::ConstantCondition(true)),
+                (4, WarningKind::Unreachable { end: 6 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn infinite_loops_with_a_break_are_fine() {
+        let src = "O1 while [1]\nO2 if [#<_x> GT 10]\nO1 break\nO2 \
+                   endif\nO1 endwhile\nO3 do\nO3 while [1]";
+
+        assert_eq!(kinds(src), vec![(6, WarningKind::ConstantCondition(true))]);
+    }
+
+    #[test]
+    fn subroutine_arguments_and_exists_checks_are_assigned() {
+        let src = "O<s> sub\nO1 if [#1 GT 0 AND EXISTS[#<x>]]\nO1 endif\nO<s> \
+                   endsub\nO<s> call [2]\nO2 if [#1 GT 0]\nO2 endif";
+
+        assert_eq!(
+            kinds(src),
+            vec![(5, WarningKind::NeverAssigned(ParameterId::Numbered(1)))]
+        );
+    }
+
+    #[test]
+    fn uncalled_subroutines_and_structural_errors() {
+        let src = "O<unused> sub\nO<unused> endsub\nO2 endif";
+
+        assert_eq!(
+            kinds(src),
+            vec![
+                (0, WarningKind::Unreachable { end: 1 }),
+                (2, WarningKind::Structure(ControlErrorKind::Unmatched)),
+            ]
+        );
+    }
+
+    fn left_on(src: &str) -> Vec<(usize, CommandKey)> {
+        pairing(src, &Dialect::reprap())
+            .into_iter()
+            .map(|warning| match warning.kind {
+                WarningKind::LeftOn { command, .. } => (warning.line, command),
+                other => panic!("unexpected warning: {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn printer_heaters_and_fans_are_tracked_separately() {
+        let src = "M140 S60\nM104 S210\nT1\nM109 S215\nM106 S255\nM106 P1\n\
+                   G1 X10 E1\nM104 S0\nM104 T0 S0\nM106 S0\nM84";
+
+        assert_eq!(
+            left_on(src),
+            vec![
+                (0, CommandKey::miscellaneous(140)),
+                (5, CommandKey::miscellaneous(106)),
+            ]
+        );
+    }
+
+    #[test]
+    fn deselecting_the_tool_doesnt_pick_a_heater() {
+        let src = "T1\nM104 S210\nT-1\nM104 S0";
+
+        assert_eq!(left_on(src), vec![]);
+    }
+
+    #[test]
+    fn each_program_end_is_checked() {
+        let src = "M3 S1000\nS2000\nM4\nM2\nM8\nM9\nM30\nM7";
+
+        assert_eq!(
+            left_on(src),
+            vec![
+                (2, CommandKey::miscellaneous(4)),
+                (7, CommandKey::miscellaneous(7)),
+            ]
+        );
+    }
+
+    #[test]
+    fn tapping_needs_the_spindle_turning_the_right_way() {
+        let src = "G84 Z-5 R1 F1\nG80\nM4 S200\nG84 Z-5 R1 F1\nG80\nM3\n\
+                   G84 Z-5 R1 F1";
+
+        let got: Vec<_> = tapping(src, &Dialect::linuxcnc())
+            .into_iter()
+            .map(|w| (w.line, w.kind))
+            .collect();
+
+        assert_eq!(
+            got,
+            vec![
+                (0, WarningKind::Tapping(TappingError::SpindleStopped)),
+                (
+                    3,
+                    WarningKind::Tapping(TappingError::WrongDirection {
+                        expected: SpindleDirection::Clockwise
+                    })
+                ),
+            ]
+        );
+    }
+}
@@ -8,3 +8,25 @@ macro_rules! with_std {
         )*
     }
 }
+
+/// Emit a [`tracing`] event when the "tracing" feature flag is enabled.
+///
+/// The arguments are the level (e.g. `TRACE`) followed by anything
+/// [`tracing::event!()`] accepts.
+macro_rules! event {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($arg)*);
+    };
+}
+
+/// Enter a [`tracing`] span which lasts until the end of the current block,
+/// when the "tracing" feature flag is enabled.
+macro_rules! enter_span {
+    ($level:ident, $name:expr $(, $($fields:tt)*)?) => {
+        #[cfg(feature = "tracing")]
+        let _entered =
+            tracing::span!(tracing::Level::$level, $name $(, $($fields)*)?)
+                .entered();
+    };
+}
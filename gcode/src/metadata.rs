@@ -0,0 +1,330 @@
+//! Attaching typed information to segments and commands.
+//!
+//! Analyses and transforms often discover things which a later pass would
+//! like to know about (e.g. "this move is a bridge" or "this line is part
+//! of the support material"). Rather than every pass agreeing on a shared
+//! set of fields, each one can define its own type and store it in a
+//! [`Metadata`] map, which holds at most one value of any particular type.
+//!
+//! [`Annotations`] keeps a [`Metadata`] map for each segment or line, and
+//! is available from [`Analysis::metadata()`] and
+//! [`Context::metadata()`].
+//!
+//! ```rust
+//! use gcode::{analysis::Analyzer, dialect::Dialect};
+//!
+//! /// Left by a "bridge detector" pass.
+//! #[derive(Debug, Clone, PartialEq)]
+//! struct Bridge;
+//!
+//! /// Left by a later pass which slows bridges down.
+//! #[derive(Debug, Clone, PartialEq)]
+//! struct SpeedFactor(f32);
+//!
+//! let src = "G1 X10 F600\nG1 X20\nG1 X30";
+//! let mut analysis = Analyzer::new(Dialect::reprap()).analyze(src);
+//!
+//! let _ = analysis.metadata_mut().insert(1, Bridge);
+//!
+//! let bridges: Vec<_> =
+//!     analysis.metadata().tagged::<Bridge>().map(|(&i, _)| i).collect();
+//! for index in bridges {
+//!     let _ = analysis.metadata_mut().insert(index, SpeedFactor(0.5));
+//! }
+//!
+//! let segment = analysis.metadata().get(1).unwrap();
+//! assert!(segment.contains::<Bridge>());
+//! assert_eq!(segment.get::<SpeedFactor>(), Some(&SpeedFactor(0.5)));
+//! assert!(analysis.metadata().get(0).is_none());
+//! ```
+//!
+//! [`Analysis::metadata()`]: crate::analysis::Analysis::metadata
+//! [`Context::metadata()`]: crate::pipeline::Context::metadata
+
+use core::{
+    any::{type_name, Any, TypeId},
+    fmt::{self, Debug, Formatter},
+};
+use std::{boxed::Box, collections::BTreeMap};
+
+/// A value which can be stored in [`Metadata`].
+///
+/// This is implemented for every type which is `Clone`, `Debug` and
+/// `PartialEq`.
+pub trait Tag: Any + Debug {
+    /// Make a boxed copy of the value.
+    fn clone_tag(&self) -> Box<dyn Tag>;
+
+    /// Compare against another tag, which may be a different type.
+    fn eq_tag(&self, other: &dyn Tag) -> bool;
+
+    /// The name of the value's type, for debugging.
+    fn type_name(&self) -> &'static str;
+
+    /// Get the value as an [`Any`] so it can be downcast.
+    fn as_any(&self) -> &dyn Any;
+
+    /// Get the value as a mutable [`Any`] so it can be downcast.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Convert a boxed value into a boxed [`Any`] so it can be downcast.
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Any + Clone + Debug + PartialEq> Tag for T {
+    fn clone_tag(&self) -> Box<dyn Tag> { Box::new(self.clone()) }
+
+    fn eq_tag(&self, other: &dyn Tag) -> bool {
+        other.as_any().downcast_ref::<T>() == Some(self)
+    }
+
+    fn type_name(&self) -> &'static str { type_name::<T>() }
+
+    fn as_any(&self) -> &dyn Any { self }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> { self }
+}
+
+/// A map holding at most one value of each type.
+#[derive(Default)]
+pub struct Metadata {
+    values: BTreeMap<TypeId, Box<dyn Tag>>,
+}
+
+impl Metadata {
+    /// Create an empty [`Metadata`] map.
+    pub fn new() -> Self { Metadata::default() }
+
+    /// Store a value, returning the previous value of the same type.
+    pub fn insert<T: Tag>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .map(downcast)
+    }
+
+    /// Get the value of a particular type.
+    pub fn get<T: Tag>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.as_any().downcast_ref())
+    }
+
+    /// Get a mutable reference to the value of a particular type.
+    pub fn get_mut<T: Tag>(&mut self) -> Option<&mut T> {
+        self.values
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.as_any_mut().downcast_mut())
+    }
+
+    /// Get the value of a particular type, inserting one if it isn't
+    /// already there.
+    pub fn get_or_insert_with<T, F>(&mut self, default: F) -> &mut T
+    where
+        T: Tag,
+        F: FnOnce() -> T,
+    {
+        self.values
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(default()))
+            .as_any_mut()
+            .downcast_mut()
+            .expect("values are always stored under their own type")
+    }
+
+    /// Remove the value of a particular type.
+    pub fn remove<T: Tag>(&mut self) -> Option<T> {
+        self.values.remove(&TypeId::of::<T>()).map(downcast)
+    }
+
+    /// Is there a value of a particular type?
+    pub fn contains<T: Tag>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    /// The number of values in the map.
+    pub fn len(&self) -> usize { self.values.len() }
+
+    /// Is the map empty?
+    pub fn is_empty(&self) -> bool { self.values.is_empty() }
+
+    /// Copy every value from another map, replacing any values of the same
+    /// type.
+    pub fn extend_from(&mut self, other: &Metadata) {
+        for (&key, value) in &other.values {
+            let _ = self.values.insert(key, value.clone_tag());
+        }
+    }
+}
+
+fn downcast<T: Tag>(value: Box<dyn Tag>) -> T {
+    *value
+        .into_any()
+        .downcast()
+        .expect("values are always stored under their own type")
+}
+
+impl Clone for Metadata {
+    fn clone(&self) -> Self {
+        Metadata {
+            values: self
+                .values
+                .iter()
+                .map(|(&key, value)| (key, value.clone_tag()))
+                .collect(),
+        }
+    }
+}
+
+impl PartialEq for Metadata {
+    fn eq(&self, other: &Metadata) -> bool {
+        self.values.len() == other.values.len()
+            && self.values.iter().all(|(key, value)| {
+                other
+                    .values
+                    .get(key)
+                    .is_some_and(|theirs| value.eq_tag(theirs.as_ref()))
+            })
+    }
+}
+
+impl Debug for Metadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.values.values().map(|value| (value.type_name(), value)),
+            )
+            .finish()
+    }
+}
+
+/// [`Metadata`] for each item in a collection (e.g. each
+/// [`Segment`][crate::analysis::Segment] in an analysis), identified by a
+/// key like its index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotations<K: Ord> {
+    items: BTreeMap<K, Metadata>,
+}
+
+impl<K: Ord> Annotations<K> {
+    /// Create an empty set of [`Annotations`].
+    pub fn new() -> Self {
+        Annotations {
+            items: BTreeMap::new(),
+        }
+    }
+
+    /// Attach a value to an item, returning the previous value of the same
+    /// type.
+    pub fn insert<T: Tag>(&mut self, key: K, value: T) -> Option<T> {
+        self.entry(key).insert(value)
+    }
+
+    /// The [`Metadata`] attached to an item, if there is any.
+    pub fn get(&self, key: K) -> Option<&Metadata> { self.items.get(&key) }
+
+    /// A mutable reference to the [`Metadata`] for an item.
+    pub fn get_mut(&mut self, key: K) -> Option<&mut Metadata> {
+        self.items.get_mut(&key)
+    }
+
+    /// The [`Metadata`] for an item, creating an empty map if necessary.
+    pub fn entry(&mut self, key: K) -> &mut Metadata {
+        self.items.entry(key).or_default()
+    }
+
+    /// Get a particular type of value attached to an item.
+    pub fn value<T: Tag>(&self, key: K) -> Option<&T> {
+        self.get(key).and_then(Metadata::get)
+    }
+
+    /// Remove everything attached to an item.
+    pub fn remove(&mut self, key: K) -> Option<Metadata> {
+        self.items.remove(&key)
+    }
+
+    /// Every item with a particular type of value attached, in order.
+    pub fn tagged<T: Tag>(&self) -> impl Iterator<Item = (&K, &T)> + '_ {
+        self.items
+            .iter()
+            .filter_map(|(key, metadata)| Some((key, metadata.get::<T>()?)))
+    }
+
+    /// Every item with something attached, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Metadata)> + '_ {
+        self.items.iter()
+    }
+
+    /// Is there anything attached to any item?
+    pub fn is_empty(&self) -> bool {
+        self.items.values().all(Metadata::is_empty)
+    }
+
+    /// Remove everything.
+    pub fn clear(&mut self) { self.items.clear(); }
+}
+
+impl<K: Ord> Default for Annotations<K> {
+    fn default() -> Self { Annotations::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{string::String, vec, vec::Vec};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Bridge;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Label(String);
+
+    #[test]
+    fn one_value_per_type() {
+        let mut metadata = Metadata::new();
+
+        assert_eq!(metadata.insert(Label("first".into())), None);
+        assert_eq!(metadata.insert(5_u32), None);
+        assert_eq!(
+            metadata.insert(Label("second".into())),
+            Some(Label("first".into()))
+        );
+
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata.get::<u32>(), Some(&5));
+        assert_eq!(metadata.get::<u64>(), None);
+        *metadata.get_or_insert_with(|| 0_u32) += 1;
+        assert_eq!(metadata.remove::<u32>(), Some(6));
+        assert!(!metadata.contains::<u32>());
+    }
+
+    #[test]
+    fn maps_compare_by_value() {
+        let mut first = Metadata::new();
+        let _ = first.insert(Bridge);
+        let _ = first.insert(Label("a".into()));
+        let second = first.clone();
+
+        assert_eq!(first, second);
+
+        let _ = first.insert(Label("b".into()));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn find_every_tagged_item() {
+        let mut annotations = Annotations::new();
+        let _ = annotations.insert(3, Bridge);
+        let _ = annotations.insert(1, Label("x".into()));
+        let _ = annotations.insert(1, Bridge);
+
+        let got: Vec<_> = annotations
+            .tagged::<Bridge>()
+            .map(|(&key, _)| key)
+            .collect();
+
+        assert_eq!(got, vec![1, 3]);
+        assert_eq!(annotations.value::<Label>(3), None);
+    }
+}
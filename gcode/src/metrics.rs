@@ -0,0 +1,194 @@
+//! Measuring how much of a file the parser could make sense of.
+//!
+//! The parser is deliberately forgiving and skips over anything it doesn't
+//! understand, which makes it hard to tell a slightly odd file from one
+//! which is mostly garbage. [`ParseMetrics`] keeps track of what was skipped
+//! so tools can triage files quickly.
+//!
+//! ```rust
+//! use gcode::{
+//!     dialect::Dialect,
+//!     metrics::{LineStatus, ParseMetrics},
+//! };
+//!
+//! let src = "G1 X10\nG1 X20 @@\n$$$$\n";
+//! let metrics = ParseMetrics::measure(src, &Dialect::generic());
+//!
+//! assert_eq!(
+//!     metrics.lines(),
+//!     &[LineStatus::Ok, LineStatus::Partial, LineStatus::Failed]
+//! );
+//! assert_eq!(metrics.problem_lines().collect::<Vec<_>>(), &[1, 2]);
+//! assert_eq!(metrics.understood_bytes(), 10);
+//! assert_eq!(metrics.total_bytes(), 16);
+//! ```
+
+use crate::{dialect::Dialect, Callbacks, Parser, Span};
+use std::vec::Vec;
+
+/// How well a single line was understood.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum LineStatus {
+    /// Everything on the line was understood (blank lines are always ok).
+    Ok,
+    /// Some of the line was understood, but the rest was skipped.
+    Partial,
+    /// None of the line was understood.
+    Failed,
+}
+
+/// Statistics about how much of a file the parser understood.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseMetrics {
+    lines: Vec<LineStatus>,
+    total_bytes: usize,
+    understood_bytes: usize,
+}
+
+impl ParseMetrics {
+    /// Parse some text and measure how much of it was understood.
+    pub fn measure(src: &str, dialect: &Dialect) -> Self {
+        let mut skipped = Skipped::default();
+        Parser::<_>::new_with_dialect(src, &mut skipped, *dialect)
+            .for_each(drop);
+
+        let mut lines = Vec::new();
+        let mut total_bytes = 0;
+        let mut understood_bytes = 0;
+
+        for (line_number, text) in src.lines().enumerate() {
+            let total = significant_bytes(text);
+            let garbage: usize = skipped
+                .spans
+                .iter()
+                .filter(|span| span.line == line_number)
+                .filter_map(|span| span.get_text(src))
+                .map(significant_bytes)
+                .sum::<usize>()
+                .min(total);
+
+            lines.push(if garbage == 0 {
+                LineStatus::Ok
+            } else if garbage < total {
+                LineStatus::Partial
+            } else {
+                LineStatus::Failed
+            });
+            total_bytes += total;
+            understood_bytes += total - garbage;
+        }
+
+        ParseMetrics {
+            lines,
+            total_bytes,
+            understood_bytes,
+        }
+    }
+
+    /// The [`LineStatus`] for each line, indexed by (zero-based) line number.
+    pub fn lines(&self) -> &[LineStatus] { &self.lines }
+
+    /// The (zero-based) numbers of every line which wasn't fully understood.
+    pub fn problem_lines(&self) -> impl Iterator<Item = usize> + '_ {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(_, status)| **status != LineStatus::Ok)
+            .map(|(line_number, _)| line_number)
+    }
+
+    /// The number of bytes in the file, ignoring whitespace.
+    pub fn total_bytes(&self) -> usize { self.total_bytes }
+
+    /// The number of (non-whitespace) bytes which were understood as words
+    /// or comments.
+    pub fn understood_bytes(&self) -> usize { self.understood_bytes }
+
+    /// The fraction of the file which was understood, from `0.0` to `1.0`.
+    ///
+    /// An empty file is considered to be completely understood.
+    pub fn fraction_understood(&self) -> f32 {
+        if self.total_bytes == 0 {
+            1.0
+        } else {
+            self.understood_bytes as f32 / self.total_bytes as f32
+        }
+    }
+}
+
+fn significant_bytes(text: &str) -> usize {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(char::len_utf8)
+        .sum()
+}
+
+/// [`Callbacks`] which remember where the parser skipped over something.
+#[derive(Debug, Default)]
+struct Skipped {
+    spans: Vec<Span>,
+}
+
+impl Callbacks for Skipped {
+    fn unknown_content(&mut self, _text: &str, span: Span) {
+        self.spans.push(span);
+    }
+
+    fn unexpected_line_number(&mut self, _line_number: f32, span: Span) {
+        self.spans.push(span);
+    }
+
+    fn argument_without_a_command(
+        &mut self,
+        _letter: char,
+        _value: f32,
+        span: Span,
+    ) {
+        self.spans.push(span);
+    }
+
+    fn number_without_a_letter(&mut self, _value: &str, span: Span) {
+        self.spans.push(span);
+    }
+
+    fn letter_without_a_number(&mut self, _value: &str, span: Span) {
+        self.spans.push(span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measure(src: &str) -> ParseMetrics {
+        ParseMetrics::measure(src, &Dialect::generic())
+    }
+
+    #[test]
+    fn a_clean_file_is_fully_understood() {
+        let got = measure("G90 ; absolute\n\nG1 X10 (move)\n");
+
+        assert_eq!(got.fraction_understood(), 1.0);
+        assert_eq!(got.problem_lines().count(), 0);
+        assert_eq!(got.lines().len(), 3);
+    }
+
+    #[test]
+    fn empty_files_are_fully_understood() {
+        assert_eq!(measure("").fraction_understood(), 1.0);
+    }
+
+    #[test]
+    fn stray_letters_and_numbers_are_garbage() {
+        let got = measure("G1 X1 G ; oops\n12.5\nN10 G0 N20");
+
+        assert_eq!(
+            got.lines(),
+            &[LineStatus::Partial, LineStatus::Failed, LineStatus::Partial]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Summarising which modes a program uses.
+//!
+//! A shop whose machines only run metric, absolute programs in `G54` doesn't
+//! need to know exactly where a file switches to inches, just that it does.
+//! A [`ModalSummary`] lists every value each modal group takes while the
+//! machine is moving, so files mixing conventions can be turned away before
+//! they're queued.
+//!
+//! ```rust
+//! use gcode::{
+//!     dialect::Dialect,
+//!     interpret::{CoordinateSystem, Units},
+//!     modal::ModalSummary,
+//! };
+//!
+//! let src = "G21 G90 G54\nG1 X10 F500\nG20\nG1 X1\nG55 G0 X0";
+//!
+//! let summary = ModalSummary::of(src, &Dialect::generic());
+//!
+//! assert_eq!(summary.units, vec![Units::Millimeters, Units::Inches]);
+//! let g55 = CoordinateSystem::from_gcode(55, 0).unwrap();
+//! assert_eq!(summary.coordinate_systems, vec![CoordinateSystem::G54, g55]);
+//! assert_eq!(summary.mixed(), vec!["units", "coordinate systems"]);
+//! ```
+
+use crate::{
+    dialect::Dialect,
+    interpret::{
+        CoordinateSystem, CycleReturn, FeedMode, Interpreter, MachineState,
+        MotionMode, Plane, Positioning, SpindleSpeedMode, Units,
+    },
+    Nop, Parser,
+};
+use std::vec::Vec;
+
+/// Every value each modal group took while the machine was moving, in the
+/// order they were first used.
+///
+/// Modes which are set but never moved in (e.g. a `G20` in a header which
+/// is immediately followed by `G21`) aren't included, and neither are the
+/// defaults if the program switches away from them before its first move.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct ModalSummary {
+    /// The units distances were given in (`G20` and `G21`).
+    pub units: Vec<Units>,
+    /// How coordinates were interpreted (`G90` and `G91`).
+    pub positioning: Vec<Positioning>,
+    /// The work coordinate systems used (`G54` to `G59.3`).
+    pub coordinate_systems: Vec<CoordinateSystem>,
+    /// The planes used (`G17`, `G18` and `G19`).
+    pub planes: Vec<Plane>,
+    /// How feed rates were measured (e.g. `G94` and `G95`).
+    pub feed_modes: Vec<FeedMode>,
+    /// The kinds of motion used (`G0`, `G1`, `G2`, etc.).
+    pub motion_modes: Vec<MotionMode>,
+    /// How the spindle speed was given (`G96` and `G97`).
+    pub spindle_speed_modes: Vec<SpindleSpeedMode>,
+    /// Where canned cycles retracted to (`G98` and `G99`).
+    pub cycle_returns: Vec<CycleReturn>,
+}
+
+impl ModalSummary {
+    /// Create an empty [`ModalSummary`].
+    pub fn new() -> Self { ModalSummary::default() }
+
+    /// Run a program through an [`Interpreter`], recording the modes in
+    /// effect for every move.
+    pub fn of(src: &str, dialect: &Dialect) -> Self {
+        let mut interpreter = Interpreter::new(*dialect);
+        let mut summary = ModalSummary::new();
+
+        for line in Parser::<_>::new_with_dialect(src, Nop, *dialect) {
+            if interpreter.process_line(&line).is_some() {
+                summary.record(interpreter.state());
+            }
+        }
+
+        summary
+    }
+
+    /// Record the modes in a [`MachineState`], for callers who are already
+    /// interpreting a program themselves.
+    pub fn record(&mut self, state: &MachineState) {
+        add(&mut self.units, state.units);
+        add(&mut self.positioning, state.positioning);
+        add(&mut self.coordinate_systems, state.coordinate_system);
+        add(&mut self.planes, state.plane);
+        add(&mut self.feed_modes, state.feed_mode);
+        add(&mut self.motion_modes, state.motion_mode);
+        add(&mut self.spindle_speed_modes, state.spindle.mode);
+        add(&mut self.cycle_returns, state.cycle_return);
+    }
+
+    /// The names of the modal groups which took more than one value,
+    /// ignoring [`ModalSummary::motion_modes`] because every program mixes
+    /// those.
+    pub fn mixed(&self) -> Vec<&'static str> {
+        let groups = [
+            ("units", self.units.len()),
+            ("positioning", self.positioning.len()),
+            ("coordinate systems", self.coordinate_systems.len()),
+            ("planes", self.planes.len()),
+            ("feed modes", self.feed_modes.len()),
+            ("spindle speed modes", self.spindle_speed_modes.len()),
+            ("cycle returns", self.cycle_returns.len()),
+        ];
+
+        groups
+            .iter()
+            .filter(|&&(_, count)| count > 1)
+            .map(|&(name, _)| name)
+            .collect()
+    }
+}
+
+fn add<T: PartialEq>(values: &mut Vec<T>, value: T) {
+    if !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_are_only_counted_once_the_machine_moves() {
+        let src = "G20 G91\nG21 G90 G18\nG1 X1 F100\nG91 G2 X1 Z1 I1\nG0 \
+                   Z5\nG17 G90";
+
+        let got = ModalSummary::of(src, &Dialect::generic());
+
+        assert_eq!(got.units, vec![Units::Millimeters]);
+        assert_eq!(
+            got.positioning,
+            vec![Positioning::Absolute, Positioning::Relative]
+        );
+        assert_eq!(got.planes, vec![Plane::ZX]);
+        assert_eq!(
+            got.motion_modes,
+            vec![
+                MotionMode::Linear,
+                MotionMode::ClockwiseArc,
+                MotionMode::Rapid
+            ]
+        );
+        assert_eq!(got.mixed(), vec!["positioning"]);
+    }
+
+    #[test]
+    fn programs_without_moves_use_nothing() {
+        let got =
+            ModalSummary::of("G20 G91 G55\nM3 S1000\nM5", &Dialect::grbl());
+
+        assert_eq!(got, ModalSummary::new());
+        assert!(got.mixed().is_empty());
+    }
+}
@@ -1,10 +1,18 @@
 use crate::{
-    buffers::{Buffers, DefaultBuffers},
+    buffers::{Buffer, Buffers, DefaultBuffers},
+    dialect::Dialect,
     lexer::{Lexer, Token, TokenType},
-    words::{Atom, Word, WordsOrComments},
-    Callbacks, Comment, GCode, Line, Mnemonic, Nop,
+    words::{Atom, Word, WordValue, WordsOrComments},
+    writer, Callbacks, CommandKey, Comment, GCode, Line, Mnemonic, Nop, Span,
 };
-use core::{iter::Peekable, marker::PhantomData};
+use core::{
+    fmt::{self, Display, Formatter},
+    iter::Peekable,
+    marker::PhantomData,
+};
+
+#[cfg(feature = "std")]
+use crate::events::{EventSchemas, WithEvents};
 
 /// Parse each [`GCode`] in some text, ignoring any errors that may occur or
 /// [`Comment`]s that are found.
@@ -28,7 +36,7 @@ pub fn full_parse_with_callbacks<'input, C: Callbacks + 'input>(
 ) -> impl Iterator<Item = Line<'input>> + 'input {
     let tokens = Lexer::new(src);
     let atoms = WordsOrComments::new(tokens);
-    Lines::new(atoms, callbacks)
+    Lines::new(src, atoms, callbacks)
 }
 
 /// A parser for parsing g-code programs.
@@ -43,11 +51,97 @@ impl<'input, C, B> Parser<'input, C, B> {
     /// Create a new [`Parser`] from some source text and a set of
     /// [`Callbacks`].
     pub fn new(src: &'input str, callbacks: C) -> Self {
-        let tokens = Lexer::new(src);
-        let atoms = WordsOrComments::new(tokens);
-        let lines = Lines::new(atoms, callbacks);
+        Parser::new_with_dialect(src, callbacks, Dialect::default())
+    }
+
+    /// Create a new [`Parser`] which follows the conventions of a particular
+    /// [`Dialect`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use gcode::{dialect::Dialect, Nop, Parser};
+    ///
+    /// let src = "G01 X100 Y2.5 F300";
+    /// let lines: Vec<_> =
+    ///     Parser::<_>::new_with_dialect(src, Nop, Dialect::fanuc()).collect();
+    ///
+    /// let g01 = &lines[0].gcodes()[0];
+    /// // Fanuc-style controls treat dimensions without a decimal point as
+    /// // multiples of 0.001
+    /// assert_eq!(g01.value_for('X'), Some(0.1));
+    /// assert_eq!(g01.value_for('Y'), Some(2.5));
+    /// assert_eq!(g01.value_for('F'), Some(300.0));
+    /// ```
+    pub fn new_with_dialect(
+        src: &'input str,
+        callbacks: C,
+        dialect: Dialect,
+    ) -> Self {
+        let tokens = Lexer::new(src).with_dialect(&dialect);
+        let atoms = WordsOrComments::with_dialect(tokens, dialect);
+        let mut lines = Lines::new(src, atoms, callbacks);
+        lines.checksums = dialect.checksums;
         Parser { lines }
     }
+
+    /// Pick up parsing part-way through some text, as if everything before
+    /// `position` (on the zero-based `line`) had already been parsed and the
+    /// most recent command was `last_command`.
+    pub(crate) fn resume(
+        src: &'input str,
+        callbacks: C,
+        dialect: Dialect,
+        position: usize,
+        line: usize,
+        last_command: Option<Word>,
+    ) -> Self {
+        let tokens =
+            Lexer::starting_at(src, position, line).with_dialect(&dialect);
+        let atoms = WordsOrComments::with_dialect(tokens, dialect);
+        let mut lines = Lines::new(src, atoms, callbacks);
+        lines.last_gcode_type = last_command;
+        lines.checksums = dialect.checksums;
+        Parser { lines }
+    }
+
+    /// Also parse any comments which match one of a set of [`EventSchema`]s,
+    /// yielding each [`CustomEvent`] straight after the [`Line`] it was
+    /// found on.
+    ///
+    /// [`EventSchema`]: crate::events::EventSchema
+    /// [`CustomEvent`]: crate::events::CustomEvent
+    #[cfg(feature = "std")]
+    pub fn with_events(
+        self,
+        schemas: &EventSchemas,
+    ) -> WithEvents<'_, 'input, C, B>
+    where
+        B: Buffers<'input>,
+    {
+        WithEvents::new(self, schemas)
+    }
+
+    /// The most recent command word, which will be used for any arguments
+    /// that appear without a command (e.g. the `X5` in `G1 X1\nX5`).
+    pub(crate) fn last_command(&self) -> Option<Word> {
+        self.lines.last_gcode_type
+    }
+
+    /// The line number expected on the next line, when checking that they
+    /// go up by one (see [`Dialect::checksums`]).
+    pub(crate) fn next_line_number(&self) -> Option<u32> {
+        self.lines.next_line_number
+    }
+
+    /// Carry on checking line numbers from an earlier [`Parser`].
+    pub(crate) fn with_next_line_number(
+        mut self,
+        next_line_number: Option<u32>,
+    ) -> Self {
+        self.lines.next_line_number = next_line_number;
+        self
+    }
 }
 
 impl<'input, B> From<&'input str> for Parser<'input, Nop, B> {
@@ -66,14 +160,180 @@ impl<'input, C: Callbacks, B: Buffers<'input>> Iterator
     }
 }
 
+/// An error returned when parsing a single [`GCode`] or [`Word`] from a
+/// string.
+///
+/// ```rust
+/// use gcode::{GCode, ParseError, Span, Word};
+/// use std::convert::TryFrom;
+///
+/// let g90: GCode = "G90 (absolute)".parse().unwrap();
+/// assert_eq!(g90.major_number(), 90);
+///
+/// let x = Word::try_from("X-1.5").unwrap();
+/// assert_eq!(x.number(), Some(-1.5));
+///
+/// assert_eq!(
+///     "G90 G91".parse::<GCode>(),
+///     Err(ParseError::TooMany(Span::new(4, 7, 0)))
+/// );
+/// assert_eq!("".parse::<Word>(), Err(ParseError::Empty));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum ParseError {
+    /// There was nothing to parse.
+    Empty,
+    /// More than one item was found, with the span of the first extra one.
+    TooMany(Span),
+    /// Some of the text couldn't be understood.
+    Unexpected(Span),
+    /// There wasn't enough room to store everything.
+    InsufficientCapacity,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "nothing to parse"),
+            ParseError::TooMany(span) => write!(
+                f,
+                "expected a single item, but found another at {}",
+                span.start
+            ),
+            ParseError::Unexpected(span) => {
+                write!(f, "unexpected content at {}", span.start)
+            },
+            ParseError::InsufficientCapacity => {
+                write!(f, "insufficient capacity")
+            },
+        }
+    }
+}
+
+with_std! {
+    impl std::error::Error for ParseError {}
+}
+
+/// Parse text which should contain exactly one [`GCode`].
+pub(crate) fn parse_single_gcode(src: &str) -> Result<GCode, ParseError> {
+    let mut error = FirstError::default();
+    let mut first = None;
+    let mut extra = None;
+
+    for line in Parser::<_>::new(src, &mut error) {
+        for gcode in line.gcodes() {
+            if first.is_none() {
+                first = Some(gcode.clone());
+            } else if extra.is_none() {
+                extra = Some(gcode.span);
+            }
+        }
+    }
+
+    if let Some(e) = error.0 {
+        return Err(e);
+    }
+
+    match (first, extra) {
+        (_, Some(span)) => Err(ParseError::TooMany(span)),
+        (Some(gcode), None) => Ok(gcode),
+        (None, None) => Err(ParseError::Empty),
+    }
+}
+
+/// [`Callbacks`] which remember the first problem encountered.
+#[derive(Debug, Default)]
+struct FirstError(Option<ParseError>);
+
+impl FirstError {
+    fn record(&mut self, error: ParseError) {
+        if self.0.is_none() {
+            self.0 = Some(error);
+        }
+    }
+}
+
+impl Callbacks for FirstError {
+    fn unknown_content(&mut self, _text: &str, span: Span) {
+        self.record(ParseError::Unexpected(span));
+    }
+
+    fn gcode_buffer_overflowed(
+        &mut self,
+        _mnemonic: Mnemonic,
+        _major_number: u32,
+        _minor_number: u32,
+        _arguments: &[Word],
+        _span: Span,
+    ) {
+        self.record(ParseError::InsufficientCapacity);
+    }
+
+    fn gcode_argument_buffer_overflowed(
+        &mut self,
+        _mnemonic: Mnemonic,
+        _major_number: u32,
+        _minor_number: u32,
+        _argument: Word,
+    ) {
+        self.record(ParseError::InsufficientCapacity);
+    }
+
+    fn unexpected_line_number(&mut self, _line_number: f32, span: Span) {
+        self.record(ParseError::Unexpected(span));
+    }
+
+    fn argument_without_a_command(
+        &mut self,
+        _letter: char,
+        _value: f32,
+        span: Span,
+    ) {
+        self.record(ParseError::Unexpected(span));
+    }
+
+    fn number_without_a_letter(&mut self, _value: &str, span: Span) {
+        self.record(ParseError::Unexpected(span));
+    }
+
+    fn letter_without_a_number(&mut self, _value: &str, span: Span) {
+        self.record(ParseError::Unexpected(span));
+    }
+
+    fn checksum_mismatch(
+        &mut self,
+        _written: u32,
+        _calculated: u8,
+        span: Span,
+    ) {
+        self.record(ParseError::Unexpected(span));
+    }
+}
+
+/// The number attached to a command or line number. The words we get from
+/// [`WordsOrComments`] always have one.
+fn number_of(word: Word) -> f32 { word.number().unwrap_or_default() }
+
+fn is_m110<A: Buffer<Word>>(gcode: &GCode<A>) -> bool {
+    gcode.key() == CommandKey::miscellaneous(110)
+}
+
 #[derive(Debug)]
 struct Lines<'input, I, C, B>
 where
     I: Iterator<Item = Atom<'input>>,
 {
+    src: &'input str,
     atoms: Peekable<I>,
     callbacks: C,
     last_gcode_type: Option<Word>,
+    /// verify checksums and line numbers (see [`Dialect::checksums`])
+    checksums: bool,
+    next_line_number: Option<u32>,
     _buffers: PhantomData<B>,
 }
 
@@ -81,11 +341,14 @@ impl<'input, I, C, B> Lines<'input, I, C, B>
 where
     I: Iterator<Item = Atom<'input>>,
 {
-    fn new(atoms: I, callbacks: C) -> Self {
+    fn new(src: &'input str, atoms: I, callbacks: C) -> Self {
         Lines {
+            src,
             atoms: atoms.peekable(),
             callbacks,
             last_gcode_type: None,
+            checksums: false,
+            next_line_number: None,
             _buffers: PhantomData,
         }
     }
@@ -109,7 +372,25 @@ where
         {
             line.set_line_number(word);
         } else {
-            self.callbacks.unexpected_line_number(word.value, word.span);
+            self.callbacks
+                .unexpected_line_number(number_of(word), word.span);
+        }
+    }
+
+    fn handle_word(
+        &mut self,
+        word: Word,
+        line: &mut Line<'input, B>,
+        temp_gcode: &mut Option<GCode<B::Arguments>>,
+    ) {
+        if !word.letter.eq_ignore_ascii_case(&'n') {
+            self.handle_arg(word, line, temp_gcode);
+        } else if self.checksums && temp_gcode.as_ref().is_some_and(is_m110) {
+            // "M110 N100" resets the line number rather than having one
+            self.handle_arg(word, line, temp_gcode);
+        } else {
+            // line numbers are annoying, so handle them separately
+            self.handle_line_number(word, line, temp_gcode.is_some());
         }
     }
 
@@ -137,7 +418,7 @@ where
 
             *temp_gcode = Some(GCode::new_with_argument_buffer(
                 mnemonic,
-                word.value,
+                number_of(word),
                 word.span,
                 B::Arguments::default(),
             ));
@@ -148,7 +429,7 @@ where
         // we've got an argument, try adding it to the gcode we're building
         if let Some(temp) = temp_gcode {
             if let Err(e) = temp.push_argument(word) {
-                self.on_arg_push_error(&temp, e.0);
+                self.on_arg_push_error(temp, e.0);
             }
             return;
         }
@@ -157,10 +438,20 @@ where
         // the command ("G90") and wants to use the one from the last line?
         match self.last_gcode_type {
             Some(ty) => {
+                event!(
+                    TRACE,
+                    letter = %ty.letter,
+                    number = number_of(ty),
+                    start = word.span.start,
+                    line = word.span.line,
+                    "continuing the previous command"
+                );
+                // the command word was on an earlier line, so our span
+                // should only cover the arguments on this one
                 let mut new_gcode = GCode::new_with_argument_buffer(
                     Mnemonic::for_letter(ty.letter).unwrap(),
-                    ty.value,
-                    ty.span,
+                    number_of(ty),
+                    Span::PLACEHOLDER,
                     B::Arguments::default(),
                 );
                 if let Err(e) = new_gcode.push_argument(word) {
@@ -170,25 +461,143 @@ where
             },
             // oh well, you can't say we didn't try...
             None => {
+                event!(
+                    DEBUG,
+                    letter = %word.letter,
+                    start = word.span.start,
+                    line = word.span.line,
+                    "argument without a command"
+                );
                 self.callbacks.argument_without_a_command(
                     word.letter,
-                    word.value,
+                    number_of(word),
                     word.span,
                 );
             },
         }
     }
 
-    fn handle_broken_word(&mut self, token: Token<'_>) {
+    fn handle_broken_word(
+        &mut self,
+        token: Token<'_>,
+        temp_gcode: &mut Option<GCode<B::Arguments>>,
+    ) {
         if token.kind == TokenType::Letter {
-            self.callbacks
-                .letter_without_a_number(token.value, token.span);
+            let letter = token.value.chars().next().unwrap_or_default();
+
+            match temp_gcode {
+                // a lone letter after a command is a flag (e.g. the "X" and
+                // "Y" in "G28 X Y")
+                Some(temp)
+                    if Mnemonic::for_letter(letter).is_none()
+                        && !letter.eq_ignore_ascii_case(&'n') =>
+                {
+                    if let Err(e) =
+                        temp.push_argument(Word::flag(letter, token.span))
+                    {
+                        self.on_arg_push_error(temp, e.0);
+                    }
+                },
+                _ => {
+                    event!(
+                        DEBUG,
+                        text = token.value,
+                        start = token.span.start,
+                        line = token.span.line,
+                        "letter without a number"
+                    );
+                    self.callbacks
+                        .letter_without_a_number(token.value, token.span)
+                },
+            }
         } else {
+            event!(
+                DEBUG,
+                text = token.value,
+                start = token.span.start,
+                line = token.span.line,
+                "number without a letter"
+            );
             self.callbacks
                 .number_without_a_letter(token.value, token.span);
         }
     }
 
+    /// Give the [`Callbacks`] a chance to evaluate a [`Word`]'s expression,
+    /// returning `None` if the [`Word`] is useless without a number.
+    fn evaluate(&mut self, word: Word, expression: Token<'_>) -> Option<Word> {
+        if let Some(value) = self
+            .callbacks
+            .evaluate_expression(expression.value, expression.span)
+        {
+            return Some(Word {
+                value: WordValue::Number(value),
+                ..word
+            });
+        }
+
+        if Mnemonic::for_letter(word.letter).is_some()
+            || word.letter.eq_ignore_ascii_case(&'n')
+        {
+            self.callbacks
+                .unknown_content(expression.value, expression.span);
+            None
+        } else {
+            Some(word)
+        }
+    }
+
+    /// Compare a checksum with the one calculated from the start of its line.
+    fn verify_checksum(&mut self, token: Token<'_>) {
+        let before = &self.src[..token.span.start];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        let calculated = writer::checksum(&before[line_start..]);
+
+        match token.value[1..].parse::<u32>() {
+            Ok(written) if written == u32::from(calculated) => {},
+            Ok(written) => {
+                self.callbacks
+                    .checksum_mismatch(written, calculated, token.span)
+            },
+            Err(_) => {
+                self.callbacks
+                    .checksum_mismatch(u32::MAX, calculated, token.span)
+            },
+        }
+    }
+
+    /// Make sure each line number is one more than the last, keeping track
+    /// of any `M110` which resets them.
+    fn check_line_number(&mut self, line: &Line<'input, B>) {
+        let line_number = line.line_number();
+        let number = |word: Word| word.number().map(|n| n.max(0.0) as u32);
+
+        if let Some(m110) = line.gcodes().iter().find(|g| is_m110(g)) {
+            let reset = m110.value_for('N').map(|n| n.max(0.0) as u32);
+            if let Some(n) = reset.or_else(|| line_number.and_then(number)) {
+                self.next_line_number = n.checked_add(1);
+            }
+            return;
+        }
+
+        let word = match line_number {
+            Some(word) => word,
+            None => return,
+        };
+        let found = match number(word) {
+            Some(found) => found,
+            None => return,
+        };
+
+        if let Some(expected) = self.next_line_number {
+            if found != expected {
+                self.callbacks
+                    .line_number_out_of_sequence(expected, found, word.span);
+            }
+        }
+        self.next_line_number = found.checked_add(1);
+    }
+
     fn on_arg_push_error(&mut self, gcode: &GCode<B::Arguments>, arg: Word) {
         self.callbacks.gcode_argument_buffer_overflowed(
             gcode.mnemonic,
@@ -227,15 +636,21 @@ where
         // constructing
         let mut temp_gcode = None;
 
-        if let None = self.atoms.peek() {
-            // There is nothing left in the file. :sad-face:
-            // This ends the parser's work.
-            return None;
-        }
+        // There is nothing left in the file. :sad-face:
+        // This ends the parser's work.
+        let _ = self.atoms.peek()?;
 
         while let Some(atom) = self.atoms.next() {
             match atom {
                 Atom::Unknown(token) => {
+                    event!(
+                        DEBUG,
+                        text = token.value,
+                        start = token.span.start,
+                        end = token.span.end,
+                        line = token.span.line,
+                        "unknown content"
+                    );
                     self.callbacks.unknown_content(token.value, token.span)
                 },
                 Atom::Comment(comment) => {
@@ -250,18 +665,49 @@ where
                     }
                     // Otherwise, the g-code had an empty line and we can ignore it.
                 },
-                // line numbers are annoying, so handle them separately
-                Atom::Word(word) if word.letter.to_ascii_lowercase() == 'n' => {
-                    self.handle_line_number(
+                Atom::Word(word) => {
+                    self.handle_word(word, &mut line, &mut temp_gcode)
+                },
+                Atom::PartialWord(word, number) => {
+                    event!(
+                        DEBUG,
+                        letter = %word.letter,
+                        text = number.value,
+                        start = number.span.start,
+                        line = number.span.line,
+                        "salvaged a malformed number"
+                    );
+                    self.callbacks.partial_word(
                         word,
-                        &mut line,
-                        temp_gcode.is_some(),
+                        number.value,
+                        number.span,
                     );
+                    self.handle_word(word, &mut line, &mut temp_gcode)
                 },
-                Atom::Word(word) => {
-                    self.handle_arg(word, &mut line, &mut temp_gcode)
+                Atom::Expression(word, expression) => {
+                    match self.evaluate(word, expression) {
+                        Some(word)
+                            if word.letter.eq_ignore_ascii_case(&'n') =>
+                        {
+                            self.handle_line_number(
+                                word,
+                                &mut line,
+                                temp_gcode.is_some(),
+                            );
+                        },
+                        Some(word) => {
+                            self.handle_arg(word, &mut line, &mut temp_gcode)
+                        },
+                        None => {},
+                    }
+                },
+                Atom::Assignment(token) => self
+                    .callbacks
+                    .parameter_assignment(token.value, token.span),
+                Atom::Checksum(token) => self.verify_checksum(token),
+                Atom::BrokenWord(token) => {
+                    self.handle_broken_word(token, &mut temp_gcode)
                 },
-                Atom::BrokenWord(token) => self.handle_broken_word(token),
             }
         }
 
@@ -271,6 +717,20 @@ where
             }
         }
 
+        if self.checksums {
+            self.check_line_number(&line);
+        }
+
+        event!(
+            TRACE,
+            start = line.span().start,
+            end = line.span().end,
+            line = line.span().line,
+            gcodes = line.gcodes().len(),
+            comments = line.comments().len(),
+            "parsed a line"
+        );
+
         Some(line)
     }
 }
@@ -278,9 +738,9 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Span;
+    use crate::{Span, WordValue};
     use arrayvec::ArrayVec;
-    use std::{prelude::v1::*, sync::Mutex};
+    use std::{sync::Mutex, vec::Vec};
 
     #[derive(Debug)]
     struct MockCallbacks<'a> {
@@ -310,7 +770,7 @@ mod tests {
     ) -> Lines<'_, impl Iterator<Item = Atom<'_>>, Nop, BigBuffers> {
         let tokens = Lexer::new(src);
         let atoms = WordsOrComments::new(tokens);
-        Lines::new(atoms, Nop)
+        Lines::new(src, atoms, Nop)
     }
 
     #[test]
@@ -339,7 +799,7 @@ mod tests {
             line.line_number(),
             Some(Word {
                 letter: 'N',
-                value: 42.0,
+                value: WordValue::Number(42.0),
                 span
             })
         );
@@ -386,12 +846,12 @@ mod tests {
             GCode::new(Mnemonic::General, 1.0, Span::new(0, src.len(), 0))
                 .with_argument(Word {
                     letter: 'X',
-                    value: 5.0,
+                    value: WordValue::Number(5.0),
                     span: Span::new(3, 5, 0),
                 })
                 .with_argument(Word {
                     letter: 'Y',
-                    value: -20.0,
+                    value: WordValue::Number(-20.0),
                     span: Span::new(6, 10, 0),
                 });
 
@@ -404,6 +864,43 @@ mod tests {
         assert_eq!(g01, &should_be);
     }
 
+    #[test]
+    fn letters_without_a_number_are_flags() {
+        let got: Vec<_> = parse("G28 X Y0\nM84 X E").collect();
+
+        assert_eq!(got.len(), 2);
+        let g28 = &got[0].gcodes()[0];
+        assert_eq!(
+            g28.arguments(),
+            &[
+                Word::flag('X', Span::new(4, 5, 0)),
+                Word::new('Y', 0.0, Span::new(6, 8, 0)),
+            ]
+        );
+        let m84 = &got[1].gcodes()[0];
+        let letters: Vec<_> = m84
+            .arguments()
+            .iter()
+            .map(|arg| (arg.letter, arg.value))
+            .collect();
+        assert_eq!(letters, vec![('X', WordValue::Flag), ('E', WordValue::Flag)]);
+    }
+
+    #[test]
+    fn leading_zeroes_dont_change_the_command() {
+        let padded: Vec<_> = crate::parse("G04 P500 M06 G01 X1").collect();
+        let normal: Vec<_> = crate::parse("G4 P500 M6 G1 X1").collect();
+
+        assert_eq!(padded.len(), normal.len());
+        for (left, right) in padded.iter().zip(&normal) {
+            assert_eq!(left.mnemonic(), right.mnemonic());
+            assert_eq!(left.major_number(), right.major_number());
+            assert_eq!(left.minor_number(), right.minor_number());
+            assert_eq!(left.value_for('P'), right.value_for('P'));
+            assert_eq!(left.value_for('X'), right.value_for('X'));
+        }
+    }
+
     #[test]
     fn multiple_commands_on_the_same_line() {
         let src = "G01 X5 G90 (comment) G91 M10\nG01";
@@ -463,6 +960,15 @@ mod tests {
         assert_eq!(got, expected);
     }
 
+    #[test]
+    fn implicit_command_span_only_covers_its_own_line() {
+        let src = "G01 X1.0\nX3.0 Y4.0";
+
+        let got: Vec<_> = crate::parse(src).collect();
+
+        assert_eq!(got[1].span, Span::new(9, 18, 1));
+    }
+
     #[test]
     // This test focuses on the G90 and M7 on the same line.
     fn implicit_command_two_commands_on_line() {
@@ -481,4 +987,101 @@ mod tests {
         let got: Vec<_> = crate::parse(src).collect();
         assert_eq!(got, expected);
     }
+
+    #[derive(Debug, Default)]
+    struct Protocol {
+        mismatches: Vec<(u32, u8)>,
+        gaps: Vec<(u32, u32)>,
+    }
+
+    impl Callbacks for Protocol {
+        fn checksum_mismatch(&mut self, written: u32, calc: u8, _: Span) {
+            self.mismatches.push((written, calc));
+        }
+
+        fn line_number_out_of_sequence(
+            &mut self,
+            expected: u32,
+            found: u32,
+            _span: Span,
+        ) {
+            self.gaps.push((expected, found));
+        }
+    }
+
+    #[test]
+    fn checksums_and_line_numbers_are_verified() {
+        let mut src = String::new();
+        writer::write_with_checksum(&mut src, 10, "M110").unwrap();
+        src.push('\n');
+        writer::write_with_checksum(&mut src, 11, "G1 X5").unwrap();
+        src.push_str("\nN13 G1 X6*99\nM110 N100\n");
+        writer::write_with_checksum(&mut src, 101, "G1 X7").unwrap();
+        let dialect = Dialect {
+            checksums: true,
+            ..Dialect::default()
+        };
+        let mut callbacks = Protocol::default();
+
+        let parser: Parser<'_, _> =
+            Parser::new_with_dialect(&src, &mut callbacks, dialect);
+        let gcodes: Vec<GCode> =
+            parser.flat_map(|line| line.into_gcodes()).collect();
+
+        assert_eq!(gcodes.len(), 5);
+        assert!(gcodes.iter().all(|g| g.arguments().len() <= 1));
+        let calculated = writer::checksum("N13 G1 X6");
+        assert_eq!(callbacks.mismatches, vec![(99, calculated)]);
+        assert_eq!(callbacks.gaps, vec![(12, 13)]);
+    }
+
+    #[derive(Debug, Default)]
+    struct Salvaged {
+        partial: Vec<(Word, String)>,
+        stray_numbers: Vec<String>,
+    }
+
+    impl Callbacks for Salvaged {
+        fn partial_word(&mut self, recovered: Word, text: &str, _span: Span) {
+            self.partial.push((recovered, text.to_string()));
+        }
+
+        fn number_without_a_letter(&mut self, value: &str, _span: Span) {
+            self.stray_numbers.push(value.to_string());
+        }
+    }
+
+    #[test]
+    fn lenient_dialects_salvage_malformed_numbers() {
+        let src = "G1 X1.2.3 Y-4..5 N6.7.8";
+
+        let mut strict = Salvaged::default();
+        let parser: Parser<'_, _> =
+            Parser::new_with_dialect(src, &mut strict, Dialect::generic());
+        let gcodes: Vec<GCode> =
+            parser.flat_map(|line| line.into_gcodes()).collect();
+
+        assert_eq!(gcodes[0].value_for('X'), Some(1.2));
+        assert_eq!(gcodes[0].value_for('Y'), Some(-4.0));
+        assert!(strict.partial.is_empty());
+        assert_eq!(strict.stray_numbers, vec![".3", ".5", ".8"]);
+
+        let mut lenient = Salvaged::default();
+        let parser: Parser<'_, _> =
+            Parser::new_with_dialect(src, &mut lenient, Dialect::reprap());
+        let gcodes: Vec<GCode> =
+            parser.flat_map(|line| line.into_gcodes()).collect();
+
+        let x = gcodes[0].arguments()[0];
+        assert_eq!(x.value, WordValue::Number(1.2));
+        assert_eq!(x.span, Span::new(3, 9, 0));
+        assert_eq!(gcodes[0].value_for('Y'), Some(-4.0));
+        assert!(lenient.stray_numbers.is_empty());
+        let texts: Vec<_> = lenient
+            .partial
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["1.2.3", "-4..5", "6.7.8"]);
+    }
 }
@@ -0,0 +1,1628 @@
+//! Composing post-processing passes.
+//!
+//! Each transform or optimisation is wrapped up as a [`Pass`], which edits a
+//! [`Program`] in place and reports anything interesting as
+//! [`Diagnostics`]. A [`Pipeline`] runs a list of passes in order, sharing a
+//! [`Context`] between them so an early pass can leave information for a
+//! later one.
+//!
+//! ```rust
+//! use gcode::{
+//!     dialect::Dialect,
+//!     pipeline::{Context, Pipeline, RemapAxesPass, RenumberPass},
+//!     program::Program,
+//!     transform::AxisMap,
+//! };
+//!
+//! let mut pipeline = Pipeline::new()
+//!     .with_pass(RemapAxesPass::new(AxisMap::new().with('E', 'A')))
+//!     .with_pass(RenumberPass::new(10, 10));
+//! let mut program = Program::parse("N1 G1 X5 E1\nN2 M30", Dialect::reprap());
+//!
+//! let diagnostics = pipeline.run(&mut program, &mut Context::new());
+//! assert!(diagnostics.is_empty());
+//! assert_eq!(program.to_string(), "N10 G1 X5 A1\nN20 M30\n");
+//!
+//! // passes can be switched off by name
+//! pipeline.set_enabled("renumber", false);
+//! let mut program = Program::parse("N1 G1 E1", Dialect::reprap());
+//! let _ = pipeline.run(&mut program, &mut Context::new());
+//! assert_eq!(program.to_string(), "N1 G1 A1\n");
+//! ```
+
+use crate::{
+    metadata::Annotations,
+    profile::DialectName,
+    program::Program,
+    transform::{
+        self, AxisMap, RemapError, RepairConfig, SanitizeConfig, WordOrder,
+    },
+};
+use core::{
+    convert::TryFrom,
+    fmt::{self, Debug, Display, Formatter},
+};
+use std::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+/// A single step in a [`Pipeline`].
+pub trait Pass {
+    /// A short name which identifies the pass (e.g. `"renumber"`).
+    fn name(&self) -> &str;
+
+    /// Apply the pass to a program.
+    fn run(
+        &mut self,
+        program: &mut Program,
+        context: &mut Context,
+    ) -> Diagnostics;
+}
+
+/// Information shared between the passes in a [`Pipeline`].
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Context {
+    values: BTreeMap<String, String>,
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    metadata: Annotations<usize>,
+}
+
+impl Context {
+    /// Create an empty [`Context`].
+    pub fn new() -> Self { Context::default() }
+
+    /// Look up a value left by an earlier pass.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    /// Store a value for later passes, returning the previous value.
+    pub fn set<K, V>(&mut self, key: K, value: V) -> Option<String>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.values.insert(key.into(), value.into())
+    }
+
+    /// Every value in the [`Context`], sorted by key.
+    pub fn values(&self) -> impl Iterator<Item = (&str, &str)> + '_ {
+        self.values.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Typed metadata attached to the program's lines, keyed by the line's
+    /// index in [`Program::lines()`].
+    ///
+    /// The indices aren't updated when a pass adds or removes lines, so a
+    /// pass which does that should keep the metadata in sync itself.
+    pub fn metadata(&self) -> &Annotations<usize> { &self.metadata }
+
+    /// Attach metadata to lines for later passes to read.
+    pub fn metadata_mut(&mut self) -> &mut Annotations<usize> {
+        &mut self.metadata
+    }
+}
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Severity {
+    /// Something the user may like to know.
+    Info,
+    /// Something which is probably a mistake.
+    Warning,
+    /// The pass couldn't do its job.
+    Error,
+}
+
+/// A message from a [`Pass`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Diagnostic {
+    /// The name of the [`Pass`] which raised the diagnostic (filled in by
+    /// the [`Pipeline`]).
+    pub pass: String,
+    /// How serious it is.
+    pub severity: Severity,
+    /// The (zero-based) line it refers to, if any.
+    pub line: Option<usize>,
+    /// A human-readable description.
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        write!(f, "{}", severity)?;
+        if !self.pass.is_empty() {
+            write!(f, " [{}]", self.pass)?;
+        }
+        if let Some(line) = self.line {
+            write!(f, " (line {})", line + 1)?;
+        }
+
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// A list of [`Diagnostic`]s.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    /// Create an empty list of [`Diagnostics`].
+    pub fn new() -> Self { Diagnostics::default() }
+
+    /// Add a [`Diagnostic`].
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.items.push(diagnostic);
+    }
+
+    /// Add a message which isn't attached to a particular line.
+    pub fn report<M: Into<String>>(&mut self, severity: Severity, message: M) {
+        self.push(Diagnostic {
+            pass: String::new(),
+            severity,
+            line: None,
+            message: message.into(),
+        });
+    }
+
+    /// Add a message about a particular (zero-based) line.
+    pub fn report_line<M: Into<String>>(
+        &mut self,
+        severity: Severity,
+        line: usize,
+        message: M,
+    ) {
+        self.push(Diagnostic {
+            pass: String::new(),
+            severity,
+            line: Some(line),
+            message: message.into(),
+        });
+    }
+
+    /// Move every [`Diagnostic`] from `other` into this list.
+    pub fn append(&mut self, other: &mut Diagnostics) {
+        self.items.append(&mut other.items);
+    }
+
+    /// Are there no diagnostics?
+    pub fn is_empty(&self) -> bool { self.items.is_empty() }
+
+    /// How many diagnostics are there?
+    pub fn len(&self) -> usize { self.items.len() }
+
+    /// Did anything fail?
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(|d| d.severity == Severity::Error)
+    }
+
+    /// Iterate over the diagnostics in the order they were raised.
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> + '_ {
+        self.items.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type IntoIter = core::slice::Iter<'a, Diagnostic>;
+    type Item = &'a Diagnostic;
+
+    fn into_iter(self) -> Self::IntoIter { self.items.iter() }
+}
+
+impl IntoIterator for Diagnostics {
+    type IntoIter = vec::IntoIter<Diagnostic>;
+    type Item = Diagnostic;
+
+    fn into_iter(self) -> Self::IntoIter { self.items.into_iter() }
+}
+
+/// An ordered list of [`Pass`]es, each of which can be switched on or off.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+    stop_on_error: bool,
+}
+
+struct Stage {
+    pass: Box<dyn Pass>,
+    enabled: bool,
+}
+
+impl Pipeline {
+    /// Create an empty [`Pipeline`] which keeps going after a pass reports
+    /// an error.
+    pub fn new() -> Self { Pipeline::default() }
+
+    /// Add a pass to the end of the pipeline.
+    pub fn with_pass<P: Pass + 'static>(mut self, pass: P) -> Self {
+        self.push(pass);
+        self
+    }
+
+    /// Should the remaining passes be skipped once one reports a
+    /// [`Severity::Error`]?
+    pub fn stop_on_error(mut self, stop: bool) -> Self {
+        self.stop_on_error = stop;
+        self
+    }
+
+    /// Add a pass to the end of the pipeline.
+    pub fn push<P: Pass + 'static>(&mut self, pass: P) {
+        self.push_boxed(Box::new(pass));
+    }
+
+    /// Add an already boxed pass to the end of the pipeline.
+    pub fn push_boxed(&mut self, pass: Box<dyn Pass>) {
+        self.stages.push(Stage {
+            pass,
+            enabled: true,
+        });
+    }
+
+    /// Insert a pass at a particular position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than [`Pipeline::len()`].
+    pub fn insert<P: Pass + 'static>(&mut self, index: usize, pass: P) {
+        self.stages.insert(
+            index,
+            Stage {
+                pass: Box::new(pass),
+                enabled: true,
+            },
+        );
+    }
+
+    /// Remove the first pass with a particular name.
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn Pass>> {
+        let index = self.position(name)?;
+        Some(self.stages.remove(index).pass)
+    }
+
+    /// Where the first pass with a particular name is in the pipeline.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.stages
+            .iter()
+            .position(|stage| stage.pass.name() == name)
+    }
+
+    /// Switch every pass with a particular name on or off, returning `false`
+    /// if there weren't any.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        let mut found = false;
+
+        for stage in &mut self.stages {
+            if stage.pass.name() == name {
+                stage.enabled = enabled;
+                found = true;
+            }
+        }
+
+        found
+    }
+
+    /// Is the first pass with a particular name switched on?
+    pub fn is_enabled(&self, name: &str) -> Option<bool> {
+        self.position(name).map(|index| self.stages[index].enabled)
+    }
+
+    /// The name of each pass, in the order they are run.
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.stages.iter().map(|stage| stage.pass.name())
+    }
+
+    /// The number of passes.
+    pub fn len(&self) -> usize { self.stages.len() }
+
+    /// Does the pipeline contain no passes?
+    pub fn is_empty(&self) -> bool { self.stages.is_empty() }
+
+    /// Run every enabled pass in order, collecting their [`Diagnostics`].
+    pub fn run(
+        &mut self,
+        program: &mut Program,
+        context: &mut Context,
+    ) -> Diagnostics {
+        let mut diagnostics = Diagnostics::new();
+
+        for stage in self.stages.iter_mut().filter(|stage| stage.enabled) {
+            enter_span!(DEBUG, "pass", name = stage.pass.name());
+
+            let mut raised = stage.pass.run(program, context);
+            for diagnostic in &mut raised.items {
+                if diagnostic.pass.is_empty() {
+                    diagnostic.pass = stage.pass.name().to_string();
+                }
+                event!(
+                    DEBUG,
+                    severity = ?diagnostic.severity,
+                    line = diagnostic.line,
+                    "{}",
+                    diagnostic
+                );
+            }
+
+            let failed = raised.has_errors();
+            diagnostics.append(&mut raised);
+
+            if failed && self.stop_on_error {
+                break;
+            }
+        }
+
+        diagnostics
+    }
+}
+
+impl Debug for Pipeline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut passes = f.debug_list();
+        for stage in &self.stages {
+            let _ = passes.entry(&(stage.pass.name(), stage.enabled));
+        }
+        passes.finish()
+    }
+}
+
+/// Run [`transform::sanitize()`] as a [`Pass`] named `"sanitize"`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SanitizePass {
+    /// What to redact.
+    pub config: SanitizeConfig,
+}
+
+impl SanitizePass {
+    /// Create a new [`SanitizePass`].
+    pub fn new(config: SanitizeConfig) -> Self { SanitizePass { config } }
+}
+
+impl Pass for SanitizePass {
+    fn name(&self) -> &str { "sanitize" }
+
+    fn run(&mut self, program: &mut Program, _: &mut Context) -> Diagnostics {
+        let dialect = *program.dialect();
+        let sanitized =
+            transform::sanitize(&program.to_string(), &dialect, &self.config);
+        *program = Program::parse(&sanitized, dialect);
+
+        Diagnostics::new()
+    }
+}
+
+/// Run [`transform::remap_axes()`] as a [`Pass`] named `"remap-axes"`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RemapAxesPass {
+    /// How the axes are renamed.
+    pub map: AxisMap,
+}
+
+impl RemapAxesPass {
+    /// Create a new [`RemapAxesPass`].
+    pub fn new(map: AxisMap) -> Self { RemapAxesPass { map } }
+}
+
+impl Pass for RemapAxesPass {
+    fn name(&self) -> &str { "remap-axes" }
+
+    fn run(&mut self, program: &mut Program, _: &mut Context) -> Diagnostics {
+        let dialect = *program.dialect();
+        let mut diagnostics = Diagnostics::new();
+
+        match transform::remap_axes(&program.to_string(), &dialect, &self.map) {
+            Ok(remapped) => *program = Program::parse(&remapped, dialect),
+            Err(e) => {
+                let message = e.to_string();
+                match e {
+                    RemapError::Collision { line, .. }
+                    | RemapError::ArcOutsidePlane { line } => {
+                        diagnostics.report_line(Severity::Error, line, message)
+                    },
+                    _ => diagnostics.report(Severity::Error, message),
+                }
+            },
+        }
+
+        diagnostics
+    }
+}
+
+/// Run [`transform::cancel_object()`] as a [`Pass`] named `"cancel-object"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CancelObjectPass {
+    /// The label of the object to remove.
+    pub label: String,
+}
+
+impl CancelObjectPass {
+    /// Create a new [`CancelObjectPass`].
+    pub fn new<S: Into<String>>(label: S) -> Self {
+        CancelObjectPass {
+            label: label.into(),
+        }
+    }
+}
+
+impl Pass for CancelObjectPass {
+    fn name(&self) -> &str { "cancel-object" }
+
+    fn run(&mut self, program: &mut Program, _: &mut Context) -> Diagnostics {
+        let dialect = *program.dialect();
+        let mut diagnostics = Diagnostics::new();
+
+        match transform::cancel_object(
+            &program.to_string(),
+            &dialect,
+            &self.label,
+        ) {
+            Ok(cancelled) => *program = Program::parse(&cancelled, dialect),
+            Err(e) => diagnostics.report(Severity::Error, e.to_string()),
+        }
+
+        diagnostics
+    }
+}
+
+/// Run [`Program::renumber()`] as a [`Pass`] named `"renumber"`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RenumberPass {
+    /// The first line number.
+    pub start: u32,
+    /// How much to increase the line number by each time.
+    pub step: u32,
+}
+
+impl RenumberPass {
+    /// Create a new [`RenumberPass`].
+    pub fn new(start: u32, step: u32) -> Self { RenumberPass { start, step } }
+}
+
+impl Pass for RenumberPass {
+    fn name(&self) -> &str { "renumber" }
+
+    fn run(&mut self, program: &mut Program, _: &mut Context) -> Diagnostics {
+        program.renumber(self.start, self.step);
+        Diagnostics::new()
+    }
+}
+
+/// Run [`transform::repair()`] as a [`Pass`] named `"repair"`, reporting
+/// each [`Fix`][transform::Fix] as an informational [`Diagnostic`].
+///
+/// A [`Program`] doesn't remember whether its source ended with a newline,
+/// so the last line is always checked for truncation unless
+/// [`RepairConfig::truncated_last_line`] is turned off.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct RepairPass {
+    /// Which kinds of damage to fix.
+    pub config: RepairConfig,
+}
+
+impl RepairPass {
+    /// Create a new [`RepairPass`].
+    pub fn new(config: RepairConfig) -> Self { RepairPass { config } }
+}
+
+impl Pass for RepairPass {
+    fn name(&self) -> &str { "repair" }
+
+    fn run(&mut self, program: &mut Program, _: &mut Context) -> Diagnostics {
+        let dialect = *program.dialect();
+        let src = program.lines().join("\n");
+        let repaired = transform::repair(&src, &self.config);
+        let mut diagnostics = Diagnostics::new();
+
+        for fix in &repaired.fixes {
+            diagnostics.report_line(
+                Severity::Info,
+                fix.span.line,
+                fix.kind.to_string(),
+            );
+        }
+        *program = Program::parse(&repaired.program, dialect);
+
+        diagnostics
+    }
+}
+
+/// Run [`transform::reorder_words()`] as a [`Pass`] named
+/// `"reorder-words"`, warning about each line whose commands were left out
+/// of order.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReorderWordsPass {
+    /// The order words are put in.
+    pub order: WordOrder,
+}
+
+impl ReorderWordsPass {
+    /// Create a new [`ReorderWordsPass`].
+    pub fn new(order: WordOrder) -> Self { ReorderWordsPass { order } }
+}
+
+impl Pass for ReorderWordsPass {
+    fn name(&self) -> &str { "reorder-words" }
+
+    fn run(&mut self, program: &mut Program, _: &mut Context) -> Diagnostics {
+        let dialect = *program.dialect();
+        let reordered = transform::reorder_words(
+            &program.to_string(),
+            &dialect,
+            &self.order,
+        );
+        let mut diagnostics = Diagnostics::new();
+
+        for span in &reordered.kept_order {
+            diagnostics.report_line(
+                Severity::Warning,
+                span.line,
+                "commands are out of order, but were left alone",
+            );
+        }
+        *program = Program::parse(&reordered.program, dialect);
+
+        diagnostics
+    }
+}
+
+/// A description of a [`Pipeline`], typically loaded from a config file so
+/// users can customise post-processing without recompiling.
+///
+/// With the `profile-toml` or `profile-json` features enabled, a
+/// description can be loaded with `PipelineConfig::from_toml()` or
+/// `PipelineConfig::from_json()`. Any other key on a pass is one of its
+/// parameters.
+///
+/// ```toml
+/// stop_on_error = true
+///
+/// [[passes]]
+/// name = "remap-axes"
+/// axes = { E = "A" }
+///
+/// [[passes]]
+/// name = "renumber"
+/// enabled = false
+/// start = 100
+/// step = 5
+/// ```
+///
+/// A [`PassRegistry`] turns the description into a [`Pipeline`].
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct PipelineConfig {
+    /// Skip the remaining passes once one reports an error (see
+    /// [`Pipeline::stop_on_error()`]).
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub stop_on_error: bool,
+    /// The passes to run, in order.
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub passes: Vec<PassConfig>,
+}
+
+impl PipelineConfig {
+    /// Load a [`PipelineConfig`] from TOML.
+    #[cfg(feature = "profile-toml")]
+    pub fn from_toml(src: &str) -> Result<Self, PipelineError> {
+        toml::from_str(src).map_err(|e| PipelineError::Syntax(e.to_string()))
+    }
+
+    /// Load a [`PipelineConfig`] from JSON.
+    #[cfg(feature = "profile-json")]
+    pub fn from_json(src: &str) -> Result<Self, PipelineError> {
+        serde_json::from_str(src)
+            .map_err(|e| PipelineError::Syntax(e.to_string()))
+    }
+}
+
+/// The description of a single [`Pass`] in a [`PipelineConfig`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct PassConfig {
+    /// The pass's name (see [`Pass::name()`]).
+    pub name: String,
+    /// Should the pass be run?
+    #[cfg_attr(feature = "serde-1", serde(default = "enabled_by_default"))]
+    pub enabled: bool,
+    /// Settings specific to this pass.
+    #[cfg_attr(feature = "serde-1", serde(flatten))]
+    pub params: BTreeMap<String, Param>,
+}
+
+#[cfg(feature = "serde-1")]
+fn enabled_by_default() -> bool { true }
+
+impl PassConfig {
+    /// Describe an enabled pass with no parameters.
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        PassConfig {
+            name: name.into(),
+            enabled: true,
+            params: BTreeMap::new(),
+        }
+    }
+
+    /// Set a parameter.
+    pub fn with_param<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<Param>,
+    {
+        let _ = self.params.insert(key.into(), value.into());
+        self
+    }
+
+    /// Make sure every parameter is one the pass understands.
+    pub fn expect_only(&self, known: &[&str]) -> Result<(), PipelineError> {
+        match self
+            .params
+            .keys()
+            .find(|key| !known.contains(&key.as_str()))
+        {
+            Some(unknown) => Err(PipelineError::UnknownParameter {
+                pass: self.name.clone(),
+                parameter: unknown.clone(),
+                expected: known.iter().map(|k| k.to_string()).collect(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Get a parameter, complaining if it is missing.
+    pub fn required(&self, key: &str) -> Result<&Param, PipelineError> {
+        self.params
+            .get(key)
+            .ok_or_else(|| PipelineError::MissingParameter {
+                pass: self.name.clone(),
+                parameter: key.to_string(),
+            })
+    }
+
+    /// Get an optional parameter which should be `true` or `false`.
+    pub fn boolean(&self, key: &str) -> Result<Option<bool>, PipelineError> {
+        self.typed(key, "true or false", |param| match *param {
+            Param::Bool(b) => Some(b),
+            _ => None,
+        })
+    }
+
+    /// Get an optional parameter which should be a whole number between `0`
+    /// and `u32::MAX`.
+    pub fn unsigned(&self, key: &str) -> Result<Option<u32>, PipelineError> {
+        self.typed(key, "a non-negative integer", |param| match *param {
+            Param::Integer(n) => u32::try_from(n).ok(),
+            _ => None,
+        })
+    }
+
+    /// Get an optional parameter which should be a string.
+    pub fn string(&self, key: &str) -> Result<Option<&str>, PipelineError> {
+        self.typed(key, "a string", |param| match param {
+            Param::String(s) => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Get an optional parameter which should be a list of strings.
+    pub fn strings(
+        &self,
+        key: &str,
+    ) -> Result<Option<Vec<String>>, PipelineError> {
+        self.typed(key, "a list of strings", |param| match param {
+            Param::List(items) => items
+                .iter()
+                .map(|item| match item {
+                    Param::String(s) => Some(s.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        })
+    }
+
+    /// Get an optional parameter which should be a table.
+    pub fn table(
+        &self,
+        key: &str,
+    ) -> Result<Option<&BTreeMap<String, Param>>, PipelineError> {
+        self.typed(key, "a table", |param| match param {
+            Param::Table(table) => Some(table),
+            _ => None,
+        })
+    }
+
+    /// Create an error saying a parameter was given a bad value.
+    pub fn invalid<R: Into<String>>(
+        &self,
+        key: &str,
+        reason: R,
+    ) -> PipelineError {
+        PipelineError::InvalidParameter {
+            pass: self.name.clone(),
+            parameter: key.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    fn typed<'a, T, F>(
+        &'a self,
+        key: &str,
+        expected: &str,
+        convert: F,
+    ) -> Result<Option<T>, PipelineError>
+    where
+        F: FnOnce(&'a Param) -> Option<T>,
+    {
+        match self.params.get(key) {
+            Some(param) => match convert(param) {
+                Some(value) => Ok(Some(value)),
+                None => {
+                    Err(self.invalid(key, format!("expected {}", expected)))
+                },
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// The value of a parameter in a [`PassConfig`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(untagged)
+)]
+pub enum Param {
+    /// `true` or `false`.
+    Bool(bool),
+    /// A whole number.
+    Integer(i64),
+    /// A number with a fractional part.
+    Float(f64),
+    /// Some text.
+    String(String),
+    /// A list of values.
+    List(Vec<Param>),
+    /// A set of named values.
+    Table(BTreeMap<String, Param>),
+}
+
+impl From<bool> for Param {
+    fn from(other: bool) -> Param { Param::Bool(other) }
+}
+
+impl From<i64> for Param {
+    fn from(other: i64) -> Param { Param::Integer(other) }
+}
+
+impl From<f64> for Param {
+    fn from(other: f64) -> Param { Param::Float(other) }
+}
+
+impl<'a> From<&'a str> for Param {
+    fn from(other: &'a str) -> Param { Param::String(other.to_string()) }
+}
+
+impl From<String> for Param {
+    fn from(other: String) -> Param { Param::String(other) }
+}
+
+impl<P: Into<Param>> From<Vec<P>> for Param {
+    fn from(other: Vec<P>) -> Param {
+        Param::List(other.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Reasons a [`PipelineConfig`] couldn't be loaded or turned into a
+/// [`Pipeline`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipelineError {
+    /// The file couldn't be parsed.
+    Syntax(String),
+    /// No pass with this name has been registered.
+    UnknownPass {
+        /// The name which was used.
+        name: String,
+        /// A registered name which is spelled similarly.
+        suggestion: Option<String>,
+    },
+    /// A pass was given a parameter it doesn't understand.
+    UnknownParameter {
+        /// The pass's name.
+        pass: String,
+        /// The parameter's name.
+        parameter: String,
+        /// The parameters the pass does understand.
+        expected: Vec<String>,
+    },
+    /// A pass wasn't given a parameter it needs.
+    MissingParameter {
+        /// The pass's name.
+        pass: String,
+        /// The parameter's name.
+        parameter: String,
+    },
+    /// A parameter's value doesn't make sense.
+    InvalidParameter {
+        /// The pass's name.
+        pass: String,
+        /// The parameter's name.
+        parameter: String,
+        /// What is wrong with it.
+        reason: String,
+    },
+}
+
+impl Display for PipelineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::Syntax(e) => write!(f, "invalid pipeline: {}", e),
+            PipelineError::UnknownPass { name, suggestion } => {
+                write!(f, "unknown pass \"{}\"", name)?;
+                match suggestion {
+                    Some(s) => write!(f, " (did you mean \"{}\"?)", s),
+                    None => Ok(()),
+                }
+            },
+            PipelineError::UnknownParameter {
+                pass,
+                parameter,
+                expected,
+            } => {
+                write!(
+                    f,
+                    "the \"{}\" pass doesn't take a \"{}\" parameter",
+                    pass, parameter
+                )?;
+                if expected.is_empty() {
+                    write!(f, " (it takes no parameters)")
+                } else {
+                    write!(f, " (expected one of: {})", expected.join(", "))
+                }
+            },
+            PipelineError::MissingParameter { pass, parameter } => write!(
+                f,
+                "the \"{}\" pass needs a \"{}\" parameter",
+                pass, parameter
+            ),
+            PipelineError::InvalidParameter {
+                pass,
+                parameter,
+                reason,
+            } => write!(
+                f,
+                "invalid \"{}\" parameter for the \"{}\" pass: {}",
+                parameter, pass, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+type Factory = Box<dyn Fn(&PassConfig) -> Result<Box<dyn Pass>, PipelineError>>;
+
+/// Something which knows how to create each kind of [`Pass`] from its
+/// [`PassConfig`].
+///
+/// ```rust
+/// use gcode::pipeline::{PassConfig, PassRegistry, PipelineConfig};
+///
+/// let config = PipelineConfig {
+///     stop_on_error: false,
+///     passes: vec![PassConfig::new("renumbre")],
+/// };
+///
+/// let err = PassRegistry::default().build(&config).unwrap_err();
+///
+/// assert_eq!(
+///     err.to_string(),
+///     "unknown pass \"renumbre\" (did you mean \"renumber\"?)"
+/// );
+/// ```
+pub struct PassRegistry {
+    factories: BTreeMap<String, Factory>,
+}
+
+impl PassRegistry {
+    /// Create a registry which doesn't know about any passes.
+    pub fn new() -> Self {
+        PassRegistry {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    /// Create a registry containing every pass in this module
+    /// ([`SanitizePass`], [`RemapAxesPass`], [`CancelObjectPass`],
+    /// [`RenumberPass`], [`RepairPass`] and [`ReorderWordsPass`]).
+    pub fn with_builtin_passes() -> Self {
+        let mut registry = PassRegistry::new();
+        registry.register("sanitize", sanitize_from_config);
+        registry.register("remap-axes", remap_axes_from_config);
+        registry.register("cancel-object", cancel_object_from_config);
+        registry.register("renumber", renumber_from_config);
+        registry.register("repair", repair_from_config);
+        registry.register("reorder-words", reorder_words_from_config);
+
+        registry
+    }
+
+    /// Teach the registry how to create a pass, replacing any existing pass
+    /// with the same name.
+    pub fn register<N, F>(&mut self, name: N, factory: F)
+    where
+        N: Into<String>,
+        F: Fn(&PassConfig) -> Result<Box<dyn Pass>, PipelineError> + 'static,
+    {
+        let _ = self.factories.insert(name.into(), Box::new(factory));
+    }
+
+    /// The names of every registered pass, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.factories.keys().map(String::as_str)
+    }
+
+    /// Create a single [`Pass`].
+    pub fn create(
+        &self,
+        config: &PassConfig,
+    ) -> Result<Box<dyn Pass>, PipelineError> {
+        match self.factories.get(&config.name) {
+            Some(factory) => factory(config),
+            None => Err(PipelineError::UnknownPass {
+                name: config.name.clone(),
+                suggestion: self.closest_name(&config.name),
+            }),
+        }
+    }
+
+    /// Create a [`Pipeline`] from its description.
+    pub fn build(
+        &self,
+        config: &PipelineConfig,
+    ) -> Result<Pipeline, PipelineError> {
+        let mut pipeline = Pipeline::new().stop_on_error(config.stop_on_error);
+
+        for pass in &config.passes {
+            pipeline.stages.push(Stage {
+                pass: self.create(pass)?,
+                enabled: pass.enabled,
+            });
+        }
+
+        Ok(pipeline)
+    }
+
+    fn closest_name(&self, name: &str) -> Option<String> {
+        self.names()
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|&(distance, _)| distance <= 3)
+            .min()
+            .map(|(_, candidate)| candidate.to_string())
+    }
+}
+
+impl Default for PassRegistry {
+    fn default() -> PassRegistry { PassRegistry::with_builtin_passes() }
+}
+
+impl Debug for PassRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+fn sanitize_from_config(
+    config: &PassConfig,
+) -> Result<Box<dyn Pass>, PipelineError> {
+    config.expect_only(&[
+        "redact",
+        "remove",
+        "paths",
+        "timestamps",
+        "network_commands",
+        "replacement",
+    ])?;
+    let defaults = SanitizeConfig::default();
+
+    Ok(Box::new(SanitizePass::new(SanitizeConfig {
+        redact: config.strings("redact")?.unwrap_or_default(),
+        remove: config.strings("remove")?.unwrap_or_default(),
+        paths: config.boolean("paths")?.unwrap_or(defaults.paths),
+        timestamps: config
+            .boolean("timestamps")?
+            .unwrap_or(defaults.timestamps),
+        network_commands: config
+            .boolean("network_commands")?
+            .unwrap_or(defaults.network_commands),
+        replacement: config
+            .string("replacement")?
+            .map_or(defaults.replacement, String::from),
+    })))
+}
+
+fn remap_axes_from_config(
+    config: &PassConfig,
+) -> Result<Box<dyn Pass>, PipelineError> {
+    config.expect_only(&["axes"])?;
+    let axes = match config.required("axes")? {
+        Param::Table(axes) => axes,
+        _ => return Err(config.invalid("axes", "expected a table")),
+    };
+    let mut map = AxisMap::new();
+
+    for (from, to) in axes {
+        let to = match to {
+            Param::String(to) => to,
+            _ => return Err(config.invalid("axes", "axes must be strings")),
+        };
+        let from = single_letter(from)
+            .ok_or_else(|| config.invalid("axes", "expected single letters"))?;
+        let to = single_letter(to)
+            .ok_or_else(|| config.invalid("axes", "expected single letters"))?;
+        map = map.with(from, to);
+    }
+
+    map.validate()
+        .map_err(|e| config.invalid("axes", e.to_string()))?;
+
+    Ok(Box::new(RemapAxesPass::new(map)))
+}
+
+fn cancel_object_from_config(
+    config: &PassConfig,
+) -> Result<Box<dyn Pass>, PipelineError> {
+    config.expect_only(&["label"])?;
+    let label = match config.required("label")? {
+        Param::String(label) => label.clone(),
+        // M486 objects are numbered
+        Param::Integer(number) => number.to_string(),
+        _ => return Err(config.invalid("label", "expected a string")),
+    };
+
+    Ok(Box::new(CancelObjectPass::new(label)))
+}
+
+fn renumber_from_config(
+    config: &PassConfig,
+) -> Result<Box<dyn Pass>, PipelineError> {
+    config.expect_only(&["start", "step"])?;
+    let start = config.unsigned("start")?.unwrap_or(10);
+    let step = config.unsigned("step")?.unwrap_or(10);
+
+    Ok(Box::new(RenumberPass::new(start, step)))
+}
+
+fn repair_from_config(
+    config: &PassConfig,
+) -> Result<Box<dyn Pass>, PipelineError> {
+    config.expect_only(&[
+        "merged_lines",
+        "doubled_decimal_points",
+        "control_characters",
+        "truncated_last_line",
+    ])?;
+    let defaults = RepairConfig::default();
+
+    Ok(Box::new(RepairPass::new(RepairConfig {
+        merged_lines: config
+            .boolean("merged_lines")?
+            .unwrap_or(defaults.merged_lines),
+        doubled_decimal_points: config
+            .boolean("doubled_decimal_points")?
+            .unwrap_or(defaults.doubled_decimal_points),
+        control_characters: config
+            .boolean("control_characters")?
+            .unwrap_or(defaults.control_characters),
+        truncated_last_line: config
+            .boolean("truncated_last_line")?
+            .unwrap_or(defaults.truncated_last_line),
+    })))
+}
+
+fn reorder_words_from_config(
+    config: &PassConfig,
+) -> Result<Box<dyn Pass>, PipelineError> {
+    config.expect_only(&["order", "dialect"])?;
+    let dialect = match config.string("dialect")? {
+        Some(name) => Some(DialectName::from_name(name).ok_or_else(|| {
+            config.invalid("dialect", format!("unknown dialect \"{}\"", name))
+        })?),
+        None => None,
+    };
+
+    let order = match (config.string("order")?, dialect) {
+        (Some(_), Some(_)) => {
+            return Err(config.invalid(
+                "order",
+                "only one of \"order\" and \"dialect\" can be given",
+            ))
+        },
+        (Some(letters), None) => {
+            if !letters.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err(config.invalid("order", "expected only letters"));
+            }
+            WordOrder::new(letters)
+        },
+        (None, Some(dialect)) => WordOrder::for_dialect(dialect),
+        (None, None) => WordOrder::default(),
+    };
+
+    Ok(Box::new(ReorderWordsPass::new(order)))
+}
+
+fn single_letter(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), None) => Some(letter),
+        _ => None,
+    }
+}
+
+/// The Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            let insertion = current[j] + 1;
+            let deletion = previous[j + 1] + 1;
+            current.push(substitution.min(insertion).min(deletion));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+
+    /// Records which passes ran by appending its name to a context value.
+    #[derive(Debug)]
+    struct Trace(&'static str, Option<Severity>);
+
+    impl Pass for Trace {
+        fn name(&self) -> &str { self.0 }
+
+        fn run(&mut self, _: &mut Program, ctx: &mut Context) -> Diagnostics {
+            let trace = format!("{}{}", ctx.get("trace").unwrap_or(""), self.0);
+            let _ = ctx.set("trace", trace);
+
+            let mut diagnostics = Diagnostics::new();
+            if let Some(severity) = self.1 {
+                diagnostics.report_line(severity, 0, "oops");
+            }
+            diagnostics
+        }
+    }
+
+    fn run(pipeline: &mut Pipeline) -> (Context, Diagnostics) {
+        let mut program = Program::new(Dialect::generic());
+        let mut context = Context::new();
+        let diagnostics = pipeline.run(&mut program, &mut context);
+
+        (context, diagnostics)
+    }
+
+    #[test]
+    fn passes_run_in_order_and_share_the_context() {
+        let mut pipeline = Pipeline::new()
+            .with_pass(Trace("a", None))
+            .with_pass(Trace("c", None));
+        pipeline.insert(1, Trace("b", None));
+
+        let (context, _) = run(&mut pipeline);
+
+        assert_eq!(context.get("trace"), Some("abc"));
+        assert_eq!(pipeline.names().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn disabled_passes_are_skipped() {
+        let mut pipeline = Pipeline::new()
+            .with_pass(Trace("a", None))
+            .with_pass(Trace("b", None));
+
+        assert!(pipeline.set_enabled("a", false));
+        assert!(!pipeline.set_enabled("missing", false));
+        let (context, _) = run(&mut pipeline);
+
+        assert_eq!(context.get("trace"), Some("b"));
+        assert_eq!(pipeline.is_enabled("a"), Some(false));
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Travel;
+
+    /// Tags every rapid move.
+    #[derive(Debug)]
+    struct FindTravel;
+
+    impl Pass for FindTravel {
+        fn name(&self) -> &str { "find-travel" }
+
+        fn run(
+            &mut self,
+            program: &mut Program,
+            ctx: &mut Context,
+        ) -> Diagnostics {
+            for (i, line) in program.lines().iter().enumerate() {
+                if line.starts_with("G0") {
+                    let _ = ctx.metadata_mut().insert(i, Travel);
+                }
+            }
+            Diagnostics::new()
+        }
+    }
+
+    /// Comments out anything an earlier pass tagged as [`Travel`].
+    #[derive(Debug)]
+    struct DropTravel;
+
+    impl Pass for DropTravel {
+        fn name(&self) -> &str { "drop-travel" }
+
+        fn run(
+            &mut self,
+            program: &mut Program,
+            ctx: &mut Context,
+        ) -> Diagnostics {
+            let tagged: Vec<_> =
+                ctx.metadata().tagged::<Travel>().map(|(&i, _)| i).collect();
+            for i in tagged {
+                let line = &mut program.lines_mut()[i];
+                *line = format!(";{}", line);
+            }
+            Diagnostics::new()
+        }
+    }
+
+    #[test]
+    fn later_passes_can_read_metadata() {
+        let mut pipeline =
+            Pipeline::new().with_pass(FindTravel).with_pass(DropTravel);
+        let mut program =
+            Program::parse("G0 X1\nG1 X2\nG0 X3", Dialect::generic());
+
+        let _ = pipeline.run(&mut program, &mut Context::new());
+
+        assert_eq!(program.to_string(), ";G0 X1\nG1 X2\n;G0 X3\n");
+    }
+
+    #[test]
+    fn diagnostics_are_tagged_with_their_pass() {
+        let mut pipeline = Pipeline::new()
+            .with_pass(Trace("a", Some(Severity::Warning)))
+            .with_pass(Trace("b", Some(Severity::Error)))
+            .with_pass(Trace("c", None))
+            .stop_on_error(true);
+
+        let (context, diagnostics) = run(&mut pipeline);
+
+        assert_eq!(context.get("trace"), Some("ab"));
+        assert!(diagnostics.has_errors());
+        let passes: Vec<_> =
+            diagnostics.iter().map(|d| d.pass.as_str()).collect();
+        assert_eq!(passes, vec!["a", "b"]);
+        assert_eq!(
+            diagnostics.iter().last().unwrap().to_string(),
+            "error [b] (line 1): oops"
+        );
+    }
+
+    #[test]
+    fn failed_remaps_leave_the_program_alone() {
+        let src = "G1 A1 E1\n";
+        let mut program = Program::parse(src, Dialect::reprap());
+        let mut pass = RemapAxesPass::new(AxisMap::new().with('E', 'A'));
+
+        let diagnostics = pass.run(&mut program, &mut Context::new());
+
+        assert_eq!(program.to_string(), src);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics.iter().next().unwrap().line, Some(0));
+    }
+
+    #[test]
+    fn build_a_pipeline_from_its_description() {
+        let config = PipelineConfig {
+            stop_on_error: true,
+            passes: vec![
+                PassConfig::new("sanitize")
+                    .with_param("redact", vec!["bob"])
+                    .with_param("timestamps", false),
+                PassConfig {
+                    enabled: false,
+                    ..PassConfig::new("renumber").with_param("step", 5)
+                },
+            ],
+        };
+
+        let mut pipeline = PassRegistry::default().build(&config).unwrap();
+
+        assert_eq!(
+            pipeline.names().collect::<Vec<_>>(),
+            vec!["sanitize", "renumber"]
+        );
+        assert_eq!(pipeline.is_enabled("renumber"), Some(false));
+        let mut program =
+            Program::parse("N1 G1 X1 ; bob 12:30", Dialect::generic());
+        let _ = pipeline.run(&mut program, &mut Context::new());
+        assert_eq!(program.to_string(), "N1 G1 X1 ; [redacted] 12:30\n");
+    }
+
+    #[test]
+    fn bad_parameters_are_explained() {
+        let registry = PassRegistry::default();
+        let inputs = [
+            (
+                PassConfig::new("renumber").with_param("stpe", 5),
+                "the \"renumber\" pass doesn't take a \"stpe\" parameter \
+                 (expected one of: start, step)",
+            ),
+            (
+                PassConfig::new("renumber").with_param("step", -5),
+                "invalid \"step\" parameter for the \"renumber\" pass: \
+                 expected a non-negative integer",
+            ),
+            (
+                PassConfig::new("remap-axes"),
+                "the \"remap-axes\" pass needs a \"axes\" parameter",
+            ),
+            (
+                PassConfig::new("remap-axes").with_param(
+                    "axes",
+                    Param::Table(
+                        vec![(String::from("X"), Param::from("F"))]
+                            .into_iter()
+                            .collect(),
+                    ),
+                ),
+                "invalid \"axes\" parameter for the \"remap-axes\" pass: \
+                 \"F\" is not an axis",
+            ),
+        ];
+
+        for (config, should_be) in &inputs {
+            let err = registry.create(config).err().unwrap();
+
+            assert_eq!(err.to_string(), *should_be);
+        }
+    }
+
+    #[test]
+    fn register_a_custom_pass() {
+        let mut registry = PassRegistry::new();
+        registry.register("trace", |config: &PassConfig| {
+            config.expect_only(&[])?;
+            Ok(Box::new(Trace("trace", None)) as Box<dyn Pass>)
+        });
+        let config = PipelineConfig {
+            stop_on_error: false,
+            passes: vec![PassConfig::new("trace")],
+        };
+
+        let mut pipeline = registry.build(&config).unwrap();
+        let (context, _) = run(&mut pipeline);
+
+        assert_eq!(context.get("trace"), Some("trace"));
+        assert!(registry.create(&PassConfig::new("renumber")).is_err());
+    }
+
+    #[test]
+    fn cancel_an_object_by_number() {
+        let config = PassConfig::new("cancel-object").with_param("label", 1);
+        let mut pass = PassRegistry::default().create(&config).unwrap();
+        let src = "M486 S0\nG1 X1\nM486 S1\nG1 X2\nM486 S-1\n";
+        let mut program = Program::parse(src, Dialect::reprap());
+
+        let diagnostics = pass.run(&mut program, &mut Context::new());
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(program.to_string(), "M486 S0\nG1 X1\nG0 X2\n");
+
+        pass = Box::new(CancelObjectPass::new("7"));
+        let diagnostics = pass.run(&mut program, &mut Context::new());
+        assert!(diagnostics.has_errors());
+    }
+
+    #[test]
+    fn repairs_are_reported_against_their_line() {
+        let config =
+            PassConfig::new("repair").with_param("truncated_last_line", false);
+        let mut pass = PassRegistry::default().create(&config).unwrap();
+        let mut program =
+            Program::parse("G28\nG1 X1G1 X2\nG1 X", Dialect::reprap());
+
+        let diagnostics = pass.run(&mut program, &mut Context::new());
+
+        assert_eq!(program.to_string(), "G28\nG1 X1\nG1 X2\nG1 X\n");
+        let got: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(got, vec!["info (line 2): split merged lines"]);
+    }
+
+    #[test]
+    fn reorder_words_using_a_dialects_conventions() {
+        let config =
+            PassConfig::new("reorder-words").with_param("dialect", "reprap");
+        let mut pass = PassRegistry::default().create(&config).unwrap();
+        let src = "G1 F1500 E0.5 X10\nG0 Z5 Y2 X1\nM82 G92 E0";
+        let mut program = Program::parse(src, Dialect::reprap());
+
+        let diagnostics = pass.run(&mut program, &mut Context::new());
+
+        assert_eq!(
+            program.to_string(),
+            "G1 X10 E0.5 F1500\nG0 X1 Y2 Z5\nM82 G92 E0\n"
+        );
+        let got: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            got,
+            vec![
+                "warning (line 3): commands are out of order, but were left \
+                 alone"
+            ]
+        );
+
+        let both = config.with_param("order", "NGXYZ");
+        assert!(PassRegistry::default().create(&both).is_err());
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn passes_and_lines_are_traced() {
+        use std::sync::{Arc, Mutex};
+        use tracing::{
+            field::{Field, Visit},
+            span, Event, Metadata, Subscriber,
+        };
+
+        /// Remembers the name of every span and the message of every event.
+        #[derive(Default, Clone)]
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        struct Message<'a>(&'a mut String);
+
+        impl Visit for Message<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                if field.name() == "message" {
+                    *self.0 = format!("{:?}", value);
+                }
+            }
+        }
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool { true }
+
+            fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+                let name = format!("span {}", span.metadata().name());
+                self.0.lock().unwrap().push(name);
+                span::Id::from_u64(1)
+            }
+
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let mut message = String::new();
+                event.record(&mut Message(&mut message));
+                self.0.lock().unwrap().push(message);
+            }
+
+            fn enter(&self, _: &span::Id) {}
+
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let recorder = Recorder::default();
+        let mut pipeline =
+            Pipeline::new().with_pass(Trace("a", Some(Severity::Warning)));
+
+        tracing::subscriber::with_default(recorder.clone(), || {
+            let _ = run(&mut pipeline);
+            let _ = crate::parse("G1 X1\nY2 $").count();
+        });
+
+        let got = recorder.0.lock().unwrap();
+        assert_eq!(
+            got.as_slice(),
+            &[
+                "span pass",
+                "warning [a] (line 1): oops",
+                "parsed a line",
+                "continuing the previous command",
+                "unknown content",
+                "parsed a line",
+            ]
+        );
+    }
+
+    #[cfg(feature = "profile-toml")]
+    #[test]
+    fn load_a_pipeline_from_toml() {
+        let src = r#"
+            stop_on_error = true
+
+            [[passes]]
+            name = "remap-axes"
+            axes = { E = "A" }
+
+            [[passes]]
+            name = "renumber"
+            enabled = false
+            start = 100
+        "#;
+
+        let got = PipelineConfig::from_toml(src).unwrap();
+
+        assert!(got.stop_on_error);
+        assert_eq!(got.passes.len(), 2);
+        assert!(got.passes[0].enabled);
+        assert_eq!(got.passes[1].unsigned("start").unwrap(), Some(100));
+        assert!(PassRegistry::default().build(&got).is_ok());
+
+        let err = PipelineConfig::from_toml("stop_on_eror = true").unwrap_err();
+        assert!(err.to_string().contains("stop_on_eror"), "{}", err);
+    }
+
+    #[cfg(feature = "profile-json")]
+    #[test]
+    fn load_a_pipeline_from_json() {
+        let src = r#"{"passes": [
+            {"name": "sanitize", "remove": ["ACME"], "paths": false}
+        ]}"#;
+
+        let got = PipelineConfig::from_json(src).unwrap();
+
+        assert!(!got.stop_on_error);
+        assert_eq!(
+            got.passes[0].strings("remove").unwrap(),
+            Some(vec![String::from("ACME")])
+        );
+        assert!(PassRegistry::default().build(&got).is_ok());
+    }
+}
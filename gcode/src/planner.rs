@@ -0,0 +1,437 @@
+//! How fast the machine actually moves.
+//!
+//! The feed rate in a program is only the speed the machine is asked for.
+//! Real machines have to accelerate up to it and slow down again before
+//! sharp corners, so short moves and tight curves often never reach their
+//! commanded speed. A [`Plan`] runs the same lookahead a firmware's motion
+//! planner does (limiting the speed through each corner using the junction
+//! deviation, then making sure every move can speed up and slow down in
+//! time), and records the entry, cruise and exit speeds of every
+//! [`Segment`].
+//!
+//! Previewers can use this to colour paths by the speed the machine will
+//! really move at.
+//!
+//! ```rust
+//! use gcode::{
+//!     analysis::Analyzer,
+//!     dialect::Dialect,
+//!     planner::{Plan, PlannerConfig},
+//! };
+//!
+//! // a long straight line, then a short move around a right angle
+//! let src = "G1 X100 F6000\nY2";
+//! let analyzer = Analyzer::new(Dialect::generic());
+//! let analysis = analyzer.analyze(src);
+//! let config = PlannerConfig {
+//!     acceleration: 1000.0,
+//!     ..Default::default()
+//! };
+//!
+//! let plan = Plan::new(&analysis, analyzer.config(), &config);
+//! let (long, short) = (&plan.segments()[0], &plan.segments()[1]);
+//!
+//! assert_eq!(long.nominal_speed, 100.0);
+//! assert_eq!(long.cruise_speed, 100.0);
+//! // slow down for the corner
+//! assert!(long.exit_speed < 20.0);
+//! assert_eq!(long.exit_speed, short.entry_speed);
+//! // the second move is too short to get up to speed
+//! assert!(short.is_acceleration_limited());
+//! assert!(plan.total_time() > analysis.total_time());
+//! ```
+//!
+//! [`Segment`]: crate::analysis::Segment
+
+use crate::{
+    analysis::{Analysis, EstimatorConfig, SegmentKind},
+    interpret::{Motion, Position},
+    Span,
+};
+use std::vec::Vec;
+
+/// The machine's limits, as used by its motion planner.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct PlannerConfig {
+    /// How quickly the machine speeds up and slows down, in millimeters per
+    /// second squared.
+    pub acceleration: f32,
+    /// How far the path may deviate from a sharp corner, in millimeters,
+    /// which controls how fast the machine can go around it (the same
+    /// setting Grbl and Marlin use).
+    pub junction_deviation: f32,
+}
+
+impl Default for PlannerConfig {
+    fn default() -> PlannerConfig {
+        PlannerConfig {
+            acceleration: 1000.0,
+            junction_deviation: 0.05,
+        }
+    }
+}
+
+/// The speeds the planner chose for a single segment, in millimeters per
+/// second.
+///
+/// Each segment accelerates from its `entry_speed` to its `cruise_speed`,
+/// holds that speed, then decelerates to its `exit_speed`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct PlannedSegment {
+    /// The length of the move, in millimeters (zero for a dwell).
+    pub length: f32,
+    /// The speed the program asked for.
+    pub nominal_speed: f32,
+    /// The speed at the start of the segment.
+    pub entry_speed: f32,
+    /// The fastest the machine moves during the segment.
+    pub cruise_speed: f32,
+    /// The speed at the end of the segment.
+    pub exit_speed: f32,
+    /// How long the segment takes, in seconds.
+    pub duration: f32,
+    /// The acceleration used.
+    pub acceleration: f32,
+    /// Where the segment came from in the source text.
+    pub span: Span,
+}
+
+impl PlannedSegment {
+    /// The distance spent accelerating, in millimeters.
+    pub fn acceleration_distance(&self) -> f32 {
+        ramp_distance(self.entry_speed, self.cruise_speed, self.acceleration)
+    }
+
+    /// The distance spent decelerating, in millimeters.
+    pub fn deceleration_distance(&self) -> f32 {
+        ramp_distance(self.exit_speed, self.cruise_speed, self.acceleration)
+    }
+
+    /// Does the machine never reach the commanded speed?
+    pub fn is_acceleration_limited(&self) -> bool {
+        self.cruise_speed < self.nominal_speed * 0.999
+    }
+
+    /// The speed a certain distance (in millimeters) into the segment.
+    pub fn speed_at(&self, distance: f32) -> f32 {
+        let distance = distance.clamp(0.0, self.length);
+        let decelerate_from = self.length - self.deceleration_distance();
+
+        if distance < self.acceleration_distance() {
+            speed_after(self.entry_speed, self.acceleration, distance)
+        } else if distance > decelerate_from {
+            speed_after(
+                self.exit_speed,
+                self.acceleration,
+                self.length - distance,
+            )
+        } else {
+            self.cruise_speed
+        }
+    }
+}
+
+/// The speed profile for an entire [`Analysis`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Plan {
+    segments: Vec<PlannedSegment>,
+}
+
+impl Plan {
+    /// Plan the speed of every segment in an [`Analysis`].
+    ///
+    /// Feed rates are chosen the same way as the estimator which created
+    /// the analysis, so pass in its [`EstimatorConfig`]. The machine comes to
+    /// a stop for every dwell, and at the start and end of the program.
+    pub fn new(
+        analysis: &Analysis,
+        estimator: &EstimatorConfig,
+        config: &PlannerConfig,
+    ) -> Self {
+        let acceleration = config.acceleration.max(f32::EPSILON);
+        let mut segments: Vec<PlannedSegment> = Vec::new();
+        // the most recent move's direction, if the machine is still moving
+        let mut previous: Option<(usize, Position)> = None;
+        // the fastest each segment may be entered at
+        let mut entry_limits = Vec::new();
+
+        for segment in analysis.segments() {
+            let mut planned = PlannedSegment {
+                length: 0.0,
+                nominal_speed: 0.0,
+                entry_speed: 0.0,
+                cruise_speed: 0.0,
+                exit_speed: 0.0,
+                duration: segment.duration,
+                acceleration,
+                span: segment.span,
+            };
+            let mut entry_limit = 0.0;
+
+            match segment.kind {
+                SegmentKind::Motion(ref motion) if motion.length() > 0.0 => {
+                    planned.length = motion.length();
+                    planned.nominal_speed =
+                        estimator.feed_rate_for(motion) / 60.0;
+
+                    if let Some((index, direction)) = previous {
+                        entry_limit = junction_speed(
+                            direction,
+                            entry_direction(motion),
+                            acceleration,
+                            config.junction_deviation,
+                        )
+                        .min(segments[index].nominal_speed)
+                        .min(planned.nominal_speed);
+                    }
+                    previous = Some((segments.len(), exit_direction(motion)));
+                },
+                // a zero-length move doesn't change anything
+                SegmentKind::Motion(_) => {},
+                SegmentKind::Dwell { .. } => previous = None,
+            }
+
+            segments.push(planned);
+            entry_limits.push(entry_limit);
+        }
+
+        plan_speeds(&mut segments, &entry_limits);
+
+        Plan { segments }
+    }
+
+    /// The planned speeds for each segment, in the same order as
+    /// [`Analysis::segments()`].
+    pub fn segments(&self) -> &[PlannedSegment] { &self.segments }
+
+    /// How long the program takes when acceleration is taken into account,
+    /// in seconds.
+    pub fn total_time(&self) -> f32 {
+        self.segments.iter().map(|segment| segment.duration).sum()
+    }
+}
+
+/// Work out each segment's speeds, given the fastest it may be entered at.
+///
+/// Dwells and zero-length moves are skipped over, but a dwell's entry limit
+/// of zero still forces the machine to stop.
+fn plan_speeds(segments: &mut [PlannedSegment], entry_limits: &[f32]) {
+    let moving: Vec<usize> = (0..segments.len())
+        .filter(|&i| segments[i].length > 0.0)
+        .collect();
+
+    // start from the fastest each move could be entered, then make sure
+    // every move can slow down in time for the next one...
+    for &i in &moving {
+        segments[i].entry_speed = entry_limits[i];
+    }
+    let mut next_entry = 0.0;
+    for &i in moving.iter().rev() {
+        let segment = &mut segments[i];
+        let reachable =
+            speed_after(next_entry, segment.acceleration, segment.length);
+        segment.entry_speed = segment.entry_speed.min(reachable);
+        segment.exit_speed = next_entry;
+        next_entry = segment.entry_speed;
+    }
+
+    // ... and can speed up enough to reach it
+    let mut previous_exit = 0.0;
+    for &i in &moving {
+        let segment = &mut segments[i];
+        segment.entry_speed = segment.entry_speed.min(previous_exit);
+        let reachable = speed_after(
+            segment.entry_speed,
+            segment.acceleration,
+            segment.length,
+        );
+        segment.exit_speed = segment.exit_speed.min(reachable);
+        previous_exit = segment.exit_speed;
+
+        let PlannedSegment {
+            length,
+            nominal_speed,
+            entry_speed,
+            exit_speed,
+            acceleration,
+            ..
+        } = *segment;
+        // the speed where the acceleration and deceleration ramps meet
+        let peak = libm::sqrtf(
+            (2.0 * acceleration * length
+                + entry_speed * entry_speed
+                + exit_speed * exit_speed)
+                / 2.0,
+        );
+        segment.cruise_speed = nominal_speed.min(peak);
+        segment.duration = trapezoid_duration(segment);
+    }
+}
+
+fn trapezoid_duration(segment: &PlannedSegment) -> f32 {
+    let a = segment.acceleration;
+    let cruise = segment.cruise_speed;
+
+    if cruise <= 0.0 {
+        return 0.0;
+    }
+
+    let accelerating = (cruise - segment.entry_speed) / a;
+    let decelerating = (cruise - segment.exit_speed) / a;
+    let cruising = (segment.length
+        - segment.acceleration_distance()
+        - segment.deceleration_distance())
+    .max(0.0)
+        / cruise;
+
+    accelerating + cruising + decelerating
+}
+
+/// The distance needed to change between two speeds.
+fn ramp_distance(from: f32, to: f32, acceleration: f32) -> f32 {
+    ((to * to - from * from) / (2.0 * acceleration)).max(0.0)
+}
+
+/// The speed reached after accelerating over a distance.
+fn speed_after(speed: f32, acceleration: f32, distance: f32) -> f32 {
+    libm::sqrtf(speed * speed + 2.0 * acceleration * distance)
+}
+
+/// The fastest the machine can go from moving in one direction to another,
+/// using the junction deviation model from Grbl.
+fn junction_speed(
+    from: Position,
+    to: Position,
+    acceleration: f32,
+    junction_deviation: f32,
+) -> f32 {
+    let cos_theta = -(from.x * to.x + from.y * to.y + from.z * to.z);
+
+    if cos_theta > 0.999_999 {
+        // a complete reversal
+        return 0.0;
+    }
+    if cos_theta < -0.999_999 {
+        // straight ahead
+        return f32::INFINITY;
+    }
+
+    let sin_half_theta = libm::sqrtf(0.5 * (1.0 - cos_theta));
+
+    libm::sqrtf(
+        acceleration * junction_deviation * sin_half_theta
+            / (1.0 - sin_half_theta),
+    )
+}
+
+/// The direction a motion starts off in (for arcs, the tangent).
+fn entry_direction(motion: &Motion) -> Position {
+    direction(motion.start, motion.point_at(1e-3))
+}
+
+/// The direction a motion finishes in.
+fn exit_direction(motion: &Motion) -> Position {
+    direction(motion.point_at(1.0 - 1e-3), motion.end)
+}
+
+fn direction(from: Position, to: Position) -> Position {
+    let length = from.distance_to(to);
+
+    if length > 0.0 {
+        Position::new(
+            (to.x - from.x) / length,
+            (to.y - from.y) / length,
+            (to.z - from.z) / length,
+        )
+    } else {
+        Position::ORIGIN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analysis::Analyzer, dialect::Dialect};
+
+    fn plan(src: &str) -> Plan {
+        let analyzer = Analyzer::new(Dialect::generic());
+        let config = PlannerConfig {
+            acceleration: 100.0,
+            junction_deviation: 0.05,
+        };
+
+        Plan::new(&analyzer.analyze(src), analyzer.config(), &config)
+    }
+
+    #[test]
+    fn straight_lines_flow_into_each_other() {
+        let got = plan("G1 X100 F600\nX200\nX300");
+        let speeds: Vec<_> = got
+            .segments()
+            .iter()
+            .map(|s| (s.entry_speed, s.cruise_speed, s.exit_speed))
+            .collect();
+
+        assert_eq!(
+            speeds,
+            vec![(0.0, 10.0, 10.0), (10.0, 10.0, 10.0), (10.0, 10.0, 0.0)]
+        );
+        // 0.1s to speed up and slow down, which would otherwise cover 1mm
+        assert!((got.total_time() - 30.1).abs() < 1e-3);
+    }
+
+    #[test]
+    fn reversals_come_to_a_stop() {
+        let got = plan("G1 X100 F600\nX0");
+
+        assert_eq!(got.segments()[0].exit_speed, 0.0);
+        assert_eq!(got.segments()[1].entry_speed, 0.0);
+    }
+
+    #[test]
+    fn short_moves_never_reach_full_speed() {
+        let got = plan("G1 X1 F6000");
+        let segment = &got.segments()[0];
+
+        // half a millimeter each way at 100mm/s²
+        assert!((segment.cruise_speed - 10.0).abs() < 1e-3);
+        assert!(segment.is_acceleration_limited());
+        assert!((segment.speed_at(0.5) - 10.0).abs() < 1e-3);
+        assert_eq!(segment.speed_at(0.0), 0.0);
+        assert!((segment.duration - 0.2).abs() < 1e-4);
+    }
+
+    #[test]
+    fn dwells_stop_the_machine() {
+        let got = plan("G1 X100 F600\nG4 P1\nG1 X200");
+        let segments = got.segments();
+
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].exit_speed, 0.0);
+        assert_eq!(segments[1].duration, 1.0);
+        assert_eq!(segments[2].entry_speed, 0.0);
+    }
+
+    #[test]
+    fn gentle_corners_are_faster_than_sharp_ones() {
+        let sharp = plan("G1 X100 F6000\nY100");
+        let gentle = plan("G1 X100 F6000\nX200 Y10");
+
+        assert!(
+            sharp.segments()[0].exit_speed < gentle.segments()[0].exit_speed
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! The 2D shape of a toolpath, for drawing previews.
+//!
+//! GUI applications usually already have a 2D graphics stack which knows
+//! how to draw paths made of lines and Bézier curves. This module projects
+//! every move in an [`Analysis`] onto the XY plane as a list of
+//! [`PathElement`]s, with arcs turned into cubic curves, so they can be
+//! handed straight to the renderer.
+//!
+//! With the `kurbo` or `lyon` features enabled, the path can be created as
+//! a `kurbo::BezPath` (`to_bez_path()`) or a `lyon_path::Path`
+//! (`to_lyon_path()`) directly.
+//!
+//! ```rust
+//! use gcode::{
+//!     analysis::Analyzer,
+//!     dialect::Dialect,
+//!     preview::{path_elements, PathElement, PreviewOptions},
+//! };
+//!
+//! let src = "G0 X10\nG1 X20\nG3 X30 Y10 I0 J10";
+//! let analysis = Analyzer::new(Dialect::generic()).analyze(src);
+//!
+//! let elements = path_elements(&analysis, &PreviewOptions::default());
+//!
+//! // rapids are left out, so the path starts where cutting begins
+//! assert_eq!(elements[0], PathElement::MoveTo((10.0, 0.0)));
+//! assert_eq!(elements[1], PathElement::LineTo((20.0, 0.0)));
+//! // a quarter circle only needs a single curve
+//! assert!(matches!(elements[2], PathElement::CubicTo(_, _, (x, y)) if x == 30.0 && y == 10.0));
+//! assert_eq!(elements.len(), 3);
+//! ```
+
+use crate::{
+    analysis::{Analysis, SegmentKind},
+    interpret::{Motion, MotionKind, Plane, Position},
+};
+use core::f32::consts::FRAC_PI_2;
+use std::vec::Vec;
+
+/// A point on the XY plane.
+pub type Point = (f32, f32);
+
+/// A single piece of a 2D path.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum PathElement {
+    /// Start a new sub-path at a point.
+    MoveTo(Point),
+    /// A straight line to a point.
+    LineTo(Point),
+    /// A cubic Bézier curve, with two control points, ending at a point.
+    CubicTo(Point, Point, Point),
+}
+
+/// Which moves end up in a preview.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct PreviewOptions {
+    /// Draw rapid moves as well as cutting moves. When this is `false`, each
+    /// rapid starts a new sub-path instead.
+    pub include_rapids: bool,
+}
+
+/// Project the moves in an [`Analysis`] onto the XY plane.
+///
+/// Arcs in the XY plane (including helices) become one cubic curve for
+/// every quarter turn, while arcs in the other planes are seen edge-on, and
+/// become straight lines. Moves which only go up and down are left out.
+pub fn path_elements(
+    analysis: &Analysis,
+    options: &PreviewOptions,
+) -> Vec<PathElement> {
+    let mut elements = Vec::new();
+    // where the pen is, if it's down
+    let mut current: Option<Point> = None;
+
+    for segment in analysis.segments() {
+        let motion = match segment.kind {
+            SegmentKind::Motion(ref motion) => motion,
+            SegmentKind::Dwell { .. } => continue,
+        };
+
+        if motion.is_rapid() && !options.include_rapids {
+            current = None;
+            continue;
+        }
+
+        let start = project(motion.start);
+        if current != Some(start) {
+            elements.push(PathElement::MoveTo(start));
+        }
+
+        let before = elements.len();
+        push_motion(&mut elements, motion);
+
+        if elements.len() == before && current != Some(start) {
+            // a plunge doesn't draw anything, so don't leave a stray move
+            let _ = elements.pop();
+        } else {
+            current = Some(project(motion.end));
+        }
+    }
+
+    elements
+}
+
+fn project(position: Position) -> Point { (position.x, position.y) }
+
+fn push_motion(elements: &mut Vec<PathElement>, motion: &Motion) {
+    let (start, end) = (project(motion.start), project(motion.end));
+
+    match motion.kind {
+        MotionKind::Arc(ref arc) if arc.plane == Plane::XY => {
+            let sweep = motion.sweep();
+            let pieces = libm::ceilf(sweep / FRAC_PI_2 - 1e-4).max(1.0);
+            let angle = sweep / pieces;
+            // the distance to each control point, as a fraction of the
+            // radius, for the usual cubic approximation of a circle
+            let k = 4.0 / 3.0 * libm::tanf(angle / 4.0);
+            let center = project(arc.center);
+            let tangent = |(x, y): Point| {
+                let (dx, dy) = (x - center.0, y - center.1);
+                if arc.clockwise {
+                    (dy * k, -dx * k)
+                } else {
+                    (-dy * k, dx * k)
+                }
+            };
+
+            let pieces = pieces as usize;
+            let mut from = start;
+            for i in 1..=pieces {
+                let to = if i == pieces {
+                    end
+                } else {
+                    project(motion.point_at(i as f32 / pieces as f32))
+                };
+                let (t1, t2) = (tangent(from), tangent(to));
+
+                elements.push(PathElement::CubicTo(
+                    (from.0 + t1.0, from.1 + t1.1),
+                    (to.0 - t2.0, to.1 - t2.1),
+                    to,
+                ));
+                from = to;
+            }
+        },
+        _ if start == end => {},
+        _ => elements.push(PathElement::LineTo(end)),
+    }
+}
+
+/// Project the moves in an [`Analysis`] onto the XY plane as a
+/// [`kurbo::BezPath`].
+///
+/// See [`path_elements()`] for how moves are converted.
+#[cfg(feature = "kurbo")]
+pub fn to_bez_path(
+    analysis: &Analysis,
+    options: &PreviewOptions,
+) -> kurbo::BezPath {
+    let point = |(x, y): Point| kurbo::Point::new(f64::from(x), f64::from(y));
+    let mut path = kurbo::BezPath::new();
+
+    for element in path_elements(analysis, options) {
+        match element {
+            PathElement::MoveTo(to) => path.move_to(point(to)),
+            PathElement::LineTo(to) => path.line_to(point(to)),
+            PathElement::CubicTo(c1, c2, to) => {
+                path.curve_to(point(c1), point(c2), point(to))
+            },
+        }
+    }
+
+    path
+}
+
+/// Project the moves in an [`Analysis`] onto the XY plane as a
+/// [`lyon_path::Path`].
+///
+/// See [`path_elements()`] for how moves are converted. Each sub-path is
+/// left open.
+#[cfg(feature = "lyon")]
+pub fn to_lyon_path(
+    analysis: &Analysis,
+    options: &PreviewOptions,
+) -> lyon_path::Path {
+    let point = |(x, y): Point| lyon_path::math::point(x, y);
+    let mut builder = lyon_path::Path::builder();
+    let mut open = false;
+
+    for element in path_elements(analysis, options) {
+        match element {
+            PathElement::MoveTo(to) => {
+                if open {
+                    builder.end(false);
+                }
+                let _ = builder.begin(point(to));
+                open = true;
+            },
+            PathElement::LineTo(to) => {
+                let _ = builder.line_to(point(to));
+            },
+            PathElement::CubicTo(c1, c2, to) => {
+                let _ =
+                    builder.cubic_bezier_to(point(c1), point(c2), point(to));
+            },
+        }
+    }
+
+    if open {
+        builder.end(false);
+    }
+
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analysis::Analyzer, dialect::Dialect};
+
+    fn elements(src: &str, options: PreviewOptions) -> Vec<PathElement> {
+        let analysis = Analyzer::new(Dialect::generic()).analyze(src);
+
+        path_elements(&analysis, &options)
+    }
+
+    #[test]
+    fn rapids_split_the_path() {
+        let src = "G1 X10 F600\nG0 X20\nG1 Y10";
+
+        let got = elements(src, PreviewOptions::default());
+
+        assert_eq!(
+            got,
+            vec![
+                PathElement::MoveTo((0.0, 0.0)),
+                PathElement::LineTo((10.0, 0.0)),
+                PathElement::MoveTo((20.0, 0.0)),
+                PathElement::LineTo((20.0, 10.0)),
+            ]
+        );
+
+        let with_rapids = elements(
+            src,
+            PreviewOptions {
+                include_rapids: true,
+            },
+        );
+        assert_eq!(with_rapids.len(), 4);
+        assert_eq!(with_rapids[2], PathElement::LineTo((20.0, 0.0)));
+    }
+
+    #[test]
+    fn plunges_are_left_out() {
+        let got = elements("G1 Z-1 F100\nX5\nZ1", PreviewOptions::default());
+
+        assert_eq!(
+            got,
+            vec![
+                PathElement::MoveTo((0.0, 0.0)),
+                PathElement::LineTo((5.0, 0.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn full_circles_use_four_curves() {
+        let got =
+            elements("G1 X10 F600\nG2 X10 Y0 I-10 J0", Default::default());
+
+        let curves: Vec<_> = got
+            .iter()
+            .filter(|e| matches!(e, PathElement::CubicTo(..)))
+            .collect();
+        assert_eq!(curves.len(), 4);
+
+        // the first quarter goes clockwise, down to (0, -10)
+        match curves[0] {
+            PathElement::CubicTo(c1, _, (x, y)) => {
+                assert!(x.abs() < 1e-4 && (y + 10.0).abs() < 1e-4);
+                assert!((c1.0 - 10.0).abs() < 1e-4 && c1.1 < -5.0);
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[cfg(feature = "kurbo")]
+    #[test]
+    fn convert_to_kurbo() {
+        use kurbo::Shape;
+
+        let analysis = Analyzer::new(Dialect::generic())
+            .analyze("G1 X10 F600\nG3 X0 Y10 I-10 J0");
+
+        let path = to_bez_path(&analysis, &PreviewOptions::default());
+
+        // a line and a quarter circle
+        let expected = 10.0 + core::f64::consts::PI * 10.0 / 2.0;
+        assert!((path.perimeter(1e-3) - expected).abs() < 0.05);
+    }
+
+    #[cfg(feature = "lyon")]
+    #[test]
+    fn convert_to_lyon() {
+        let analysis = Analyzer::new(Dialect::generic())
+            .analyze("G1 X10 F600\nG0 X20\nG1 Y10");
+
+        let path = to_lyon_path(&analysis, &PreviewOptions::default());
+
+        let begins = path
+            .iter()
+            .filter(|e| matches!(e, lyon_path::Event::Begin { .. }))
+            .count();
+        assert_eq!(begins, 2);
+    }
+}
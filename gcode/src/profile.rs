@@ -0,0 +1,1022 @@
+//! Describing a particular machine.
+//!
+//! A [`MachineProfile`] bundles together everything the rest of the crate
+//! needs to know about a machine (its [`Dialect`], how far each axis can
+//! travel, which commands it understands, etc.) so applications can ship one
+//! profile per printer or CNC instead of wiring up each component by hand.
+//!
+//! With the `profile-toml` or `profile-json` features enabled, profiles can
+//! be loaded at runtime.
+//!
+//! ```toml
+//! name = "Desktop Router"
+//! dialect = "grbl"
+//! supported_commands = ["G0", "G1", "G2", "G3", "G4", "G20", "G21", "G90", "M3", "M5"]
+//! kinematics = { type = "cartesian" }
+//!
+//! [[axes]]
+//! letter = "X"
+//! min = 0.0
+//! max = 400.0
+//! max_feed_rate = 5000.0
+//!
+//! [[value_ranges]]
+//! letter = "S"
+//! min = 0.0
+//! max = 24000.0
+//!
+//! [buffers]
+//! max_line_length = 80
+//!
+//! [estimator]
+//! rapid_feed_rate = 5000.0
+//! default_feed_rate = 1000.0
+//! ```
+//!
+//! With the `builtin-profiles` feature enabled, profiles for a handful of
+//! popular machines are also available (see `Profile`).
+//!
+//! Once loaded, a profile can check that a program will run on the machine.
+//!
+//! ```rust
+//! use gcode::profile::{Axis, MachineProfile, ViolationKind};
+//!
+//! let mut profile = MachineProfile::new("Small Mill");
+//! profile.axes.push(Axis::new('X', 0.0, 100.0));
+//! profile.supported_commands = Some(vec!["G0".into(), "G1".into()]);
+//!
+//! let violations = profile.validate("G0 X50\nG1 X150 F100\nM3 S1000");
+//!
+//! assert_eq!(violations.len(), 2);
+//! assert_eq!(violations[0].line, 1);
+//! assert_eq!(
+//!     violations[0].kind,
+//!     ViolationKind::OutOfBounds { axis: 'X', value: 150.0 }
+//! );
+//! assert_eq!(
+//!     violations[1].kind,
+//!     ViolationKind::UnsupportedCommand("M3".into())
+//! );
+//! ```
+
+use crate::{
+    analysis::{Analyzer, EstimatorConfig, SegmentKind},
+    buffers::Buffer,
+    dialect::Dialect,
+    interpret::{Motion, MotionKind, Position},
+    CommandKey, GCode, Mnemonic, Nop, Parser, Word,
+};
+use core::fmt::{self, Display, Formatter};
+use std::{string::String, vec::Vec};
+
+/// A description of a machine's capabilities.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct MachineProfile {
+    /// A human-friendly name for the machine.
+    pub name: String,
+    /// The flavour of g-code the machine's controller speaks.
+    pub dialect: DialectName,
+    /// The machine's axes and how far they can travel.
+    pub axes: Vec<Axis>,
+    /// The `G` and `M` commands the machine understands (e.g. `"G1"` or
+    /// `"M104"`), or `None` if it will accept anything.
+    pub supported_commands: Option<Vec<String>>,
+    /// Limits on how much the controller can buffer.
+    pub buffers: BufferLimits,
+    /// How the machine's motors move the tool.
+    pub kinematics: Kinematics,
+    /// Assumptions used when estimating how long a program will take.
+    pub estimator: EstimatorConfig,
+    /// The values the machine accepts for particular letters (e.g. a fan
+    /// speed between `0` and `255`).
+    pub value_ranges: Vec<ValueRange>,
+}
+
+impl MachineProfile {
+    /// Create a profile for a generic machine, with no limits.
+    pub fn new(name: &str) -> Self {
+        MachineProfile {
+            name: String::from(name),
+            dialect: DialectName::Generic,
+            axes: Vec::new(),
+            supported_commands: None,
+            buffers: BufferLimits::default(),
+            kinematics: Kinematics::Cartesian,
+            estimator: EstimatorConfig::default(),
+            value_ranges: Vec::new(),
+        }
+    }
+
+    /// Load a profile from TOML.
+    #[cfg(feature = "profile-toml")]
+    pub fn from_toml(src: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(src)
+    }
+
+    /// Load a profile from JSON.
+    #[cfg(feature = "profile-json")]
+    pub fn from_json(src: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(src)
+    }
+
+    /// The [`Dialect`] used by the machine.
+    pub fn dialect(&self) -> Dialect { self.dialect.dialect() }
+
+    /// An [`Analyzer`] which estimates timings for this machine.
+    pub fn analyzer(&self) -> Analyzer {
+        Analyzer::new(self.dialect()).with_config(self.estimator)
+    }
+
+    /// Look up an axis by its letter (ignoring case).
+    pub fn axis(&self, letter: char) -> Option<&Axis> {
+        self.axes
+            .iter()
+            .find(|axis| axis.letter.eq_ignore_ascii_case(&letter))
+    }
+
+    /// Does the machine understand a particular command?
+    ///
+    /// Tool selections and program numbers are always supported.
+    pub fn supports<A: Buffer<Word>>(&self, gcode: &GCode<A>) -> bool {
+        let supported = match &self.supported_commands {
+            Some(supported) => supported,
+            None => return true,
+        };
+
+        match gcode.mnemonic() {
+            Mnemonic::General | Mnemonic::Miscellaneous => {},
+            Mnemonic::ToolChange | Mnemonic::ProgramNumber => return true,
+        }
+
+        let key = gcode.key();
+        supported
+            .iter()
+            .any(|command| command.trim().parse::<CommandKey>() == Ok(key))
+    }
+
+    /// Check each of a command's arguments against the machine's
+    /// [`MachineProfile::value_ranges`], returning a
+    /// [`ViolationKind::OutOfRange`] for every value it won't accept.
+    ///
+    /// A range for a particular command takes precedence over one for the
+    /// letter as a whole. This is what [`MachineProfile::validate()`] uses,
+    /// but it can also be called on each command as it's parsed.
+    ///
+    /// ```rust
+    /// use gcode::profile::{MachineProfile, ValueRange, ViolationKind};
+    ///
+    /// let mut profile = MachineProfile::new("Printer");
+    /// profile
+    ///     .value_ranges
+    ///     .push(ValueRange::new('S', 0.0, 255.0).for_command("M106"));
+    ///
+    /// let fan = gcode::parse("M106 S300").next().unwrap();
+    /// let got: Vec<_> = profile.out_of_range(&fan).collect();
+    ///
+    /// assert_eq!(
+    ///     got,
+    ///     vec![ViolationKind::OutOfRange {
+    ///         letter: 'S',
+    ///         command: "M106".into(),
+    ///         value: 300.0,
+    ///         min: 0.0,
+    ///         max: 255.0,
+    ///     }]
+    /// );
+    /// assert_eq!(got[0].to_string(), "M106 S300 is outside 0 to 255, try S255");
+    /// ```
+    pub fn out_of_range<'a, A: Buffer<Word>>(
+        &'a self,
+        gcode: &'a GCode<A>,
+    ) -> impl Iterator<Item = ViolationKind> + 'a {
+        let key = gcode.key();
+
+        gcode.arguments().iter().filter_map(move |word| {
+            let value = word.number()?;
+            let letter = word.letter.to_ascii_uppercase();
+            let mut ranges = self
+                .value_ranges
+                .iter()
+                .filter(|range| range.letter.eq_ignore_ascii_case(&letter));
+            let range = ranges
+                .clone()
+                .find(|range| range.command.is_some() && range.applies_to(key))
+                .or_else(|| ranges.find(|range| range.command.is_none()))?;
+
+            if range.contains(value) {
+                return None;
+            }
+
+            Some(ViolationKind::OutOfRange {
+                letter,
+                command: key.to_string(),
+                value,
+                min: range.min,
+                max: range.max,
+            })
+        })
+    }
+
+    /// Check whether a program will run on this machine.
+    ///
+    /// Travel limits are checked against the program's coordinates, so any
+    /// work offsets the machine applies aren't taken into account.
+    pub fn validate(&self, src: &str) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        if let Some(max) = self.buffers.max_line_length {
+            for (line, text) in src.lines().enumerate() {
+                if text.len() > max {
+                    violations.push(Violation {
+                        line,
+                        kind: ViolationKind::LineTooLong { length: text.len() },
+                    });
+                }
+            }
+        }
+
+        for line in Parser::<_>::new_with_dialect(src, Nop, self.dialect()) {
+            for gcode in line.gcodes() {
+                if !self.supports(gcode) {
+                    violations.push(Violation {
+                        line: line.span().line,
+                        kind: ViolationKind::UnsupportedCommand(
+                            gcode.key().to_string(),
+                        ),
+                    });
+                }
+
+                for kind in self.out_of_range(gcode) {
+                    violations.push(Violation {
+                        line: line.span().line,
+                        kind,
+                    });
+                }
+            }
+        }
+
+        for segment in self.analyzer().analyze(src).segments() {
+            if let SegmentKind::Motion(motion) = &segment.kind {
+                self.check_motion(segment.span.line, motion, &mut violations);
+            }
+        }
+
+        violations.sort_by_key(|violation| violation.line);
+        violations
+    }
+
+    fn check_motion(
+        &self,
+        line: usize,
+        motion: &Motion,
+        violations: &mut Vec<Violation>,
+    ) {
+        // arcs can bulge past their end points, so check along the way
+        let samples = match motion.kind {
+            MotionKind::Arc(_) => 16,
+            _ => 1,
+        };
+
+        for axis in &self.axes {
+            let component = match axis.component() {
+                Some(component) => component,
+                None => continue,
+            };
+
+            let outside = (1..=samples)
+                .map(|i| component(motion.point_at(i as f32 / samples as f32)))
+                .find(|&value| value < axis.min || value > axis.max);
+
+            if let Some(value) = outside {
+                violations.push(Violation {
+                    line,
+                    kind: ViolationKind::OutOfBounds {
+                        axis: axis.letter,
+                        value,
+                    },
+                });
+            }
+
+            let (max_feed_rate, feed_rate) =
+                match (axis.max_feed_rate, motion.feed_rate) {
+                    (Some(max), Some(feed)) if !motion.is_rapid() => {
+                        (max, feed)
+                    },
+                    _ => continue,
+                };
+            let length = motion.length();
+            if length <= 0.0 {
+                continue;
+            }
+            let distance =
+                (component(motion.end) - component(motion.start)).abs();
+            let axis_feed_rate = feed_rate * distance / length;
+
+            if axis_feed_rate > max_feed_rate {
+                violations.push(Violation {
+                    line,
+                    kind: ViolationKind::FeedRateTooHigh {
+                        axis: axis.letter,
+                        feed_rate: axis_feed_rate,
+                    },
+                });
+            }
+        }
+    }
+}
+
+impl Default for MachineProfile {
+    fn default() -> MachineProfile { MachineProfile::new("") }
+}
+
+/// Ready-made profiles for popular machines.
+///
+/// These are based on each machine's published specifications, using the
+/// stock firmware's default settings.
+#[cfg(feature = "builtin-profiles")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Profile {
+    /// The Creality Ender 3, running Marlin.
+    Ender3,
+    /// The Original Prusa MK4.
+    PrusaMk4,
+    /// A 350mm Voron 2.4, running Klipper.
+    Voron,
+    /// A standard size Carbide 3D Shapeoko 3, running Grbl.
+    Shapeoko,
+    /// A 1000mm Inventables X-Carve, running Grbl.
+    XCarve,
+    /// A generic Fanuc-controlled vertical mill, with a 30" x 16" x 20"
+    /// work envelope.
+    FanucMill,
+}
+
+#[cfg(feature = "builtin-profiles")]
+impl Profile {
+    /// Every built-in profile.
+    pub const ALL: [Profile; 6] = [
+        Profile::Ender3,
+        Profile::PrusaMk4,
+        Profile::Voron,
+        Profile::Shapeoko,
+        Profile::XCarve,
+        Profile::FanucMill,
+    ];
+
+    const NAMES: &'static [(&'static str, Profile)] = &[
+        ("ender3", Profile::Ender3),
+        ("prusa-mk4", Profile::PrusaMk4),
+        ("voron", Profile::Voron),
+        ("shapeoko", Profile::Shapeoko),
+        ("x-carve", Profile::XCarve),
+        ("fanuc-mill", Profile::FanucMill),
+    ];
+
+    /// Look up a built-in profile by its short name (e.g. `"prusa-mk4"`),
+    /// ignoring case.
+    pub fn from_name(name: &str) -> Option<Profile> {
+        Profile::NAMES
+            .iter()
+            .find(|(candidate, _)| candidate.eq_ignore_ascii_case(name))
+            .map(|&(_, profile)| profile)
+    }
+
+    /// The profile's short name, as accepted by [`Profile::from_name()`].
+    pub fn name(self) -> &'static str {
+        Profile::NAMES
+            .iter()
+            .find(|&&(_, profile)| profile == self)
+            .map(|&(name, _)| name)
+            .unwrap_or_default()
+    }
+
+    /// Get the full [`MachineProfile`].
+    pub fn machine_profile(self) -> MachineProfile {
+        match self {
+            Profile::Ender3 => MachineProfile {
+                dialect: DialectName::RepRap,
+                axes: vec![
+                    Axis::new('X', 0.0, 220.0).with_max_feed_rate(30000.0),
+                    Axis::new('Y', 0.0, 220.0).with_max_feed_rate(30000.0),
+                    Axis::new('Z', 0.0, 250.0).with_max_feed_rate(300.0),
+                ],
+                buffers: BufferLimits {
+                    max_line_length: Some(96),
+                    planner_blocks: Some(16),
+                    receive_buffer: Some(128),
+                },
+                estimator: EstimatorConfig {
+                    rapid_feed_rate: 9000.0,
+                    default_feed_rate: 1500.0,
+                    ..EstimatorConfig::default()
+                },
+                value_ranges: printer_value_ranges(),
+                ..MachineProfile::new("Creality Ender 3")
+            },
+            Profile::PrusaMk4 => MachineProfile {
+                dialect: DialectName::RepRap,
+                axes: vec![
+                    Axis::new('X', 0.0, 250.0).with_max_feed_rate(12000.0),
+                    Axis::new('Y', -4.0, 210.0).with_max_feed_rate(12000.0),
+                    Axis::new('Z', 0.0, 220.0).with_max_feed_rate(720.0),
+                ],
+                buffers: BufferLimits {
+                    max_line_length: Some(96),
+                    planner_blocks: Some(16),
+                    receive_buffer: None,
+                },
+                estimator: EstimatorConfig {
+                    rapid_feed_rate: 10800.0,
+                    default_feed_rate: 1500.0,
+                    ..EstimatorConfig::default()
+                },
+                value_ranges: printer_value_ranges(),
+                ..MachineProfile::new("Original Prusa MK4")
+            },
+            Profile::Voron => MachineProfile {
+                dialect: DialectName::RepRap,
+                axes: vec![
+                    Axis::new('X', 0.0, 350.0).with_max_feed_rate(30000.0),
+                    Axis::new('Y', 0.0, 350.0).with_max_feed_rate(30000.0),
+                    Axis::new('Z', 0.0, 340.0).with_max_feed_rate(900.0),
+                ],
+                kinematics: Kinematics::CoreXY,
+                estimator: EstimatorConfig {
+                    rapid_feed_rate: 18000.0,
+                    default_feed_rate: 1500.0,
+                    ..EstimatorConfig::default()
+                },
+                value_ranges: printer_value_ranges(),
+                ..MachineProfile::new("Voron 2.4 (350mm)")
+            },
+            Profile::Shapeoko => MachineProfile {
+                axes: vec![
+                    Axis::new('X', 0.0, 425.0).with_max_feed_rate(10000.0),
+                    Axis::new('Y', 0.0, 425.0).with_max_feed_rate(10000.0),
+                    Axis::new('Z', -75.0, 0.0).with_max_feed_rate(5000.0),
+                ],
+                estimator: EstimatorConfig {
+                    rapid_feed_rate: 5000.0,
+                    default_feed_rate: 1000.0,
+                    ..EstimatorConfig::default()
+                },
+                ..grbl_machine("Carbide 3D Shapeoko 3")
+            },
+            Profile::XCarve => MachineProfile {
+                axes: vec![
+                    Axis::new('X', 0.0, 750.0).with_max_feed_rate(8000.0),
+                    Axis::new('Y', 0.0, 750.0).with_max_feed_rate(8000.0),
+                    Axis::new('Z', -65.0, 0.0).with_max_feed_rate(500.0),
+                ],
+                estimator: EstimatorConfig {
+                    rapid_feed_rate: 8000.0,
+                    default_feed_rate: 1000.0,
+                    ..EstimatorConfig::default()
+                },
+                ..grbl_machine("Inventables X-Carve (1000mm)")
+            },
+            Profile::FanucMill => MachineProfile {
+                dialect: DialectName::Fanuc,
+                axes: vec![
+                    Axis::new('X', 0.0, 762.0),
+                    Axis::new('Y', 0.0, 406.0),
+                    Axis::new('Z', -508.0, 0.0),
+                ],
+                estimator: EstimatorConfig {
+                    rapid_feed_rate: 25400.0,
+                    default_feed_rate: 500.0,
+                    ..EstimatorConfig::default()
+                },
+                ..MachineProfile::new("Generic Fanuc Mill")
+            },
+        }
+    }
+}
+
+/// Fan speeds are a PWM duty cycle out of 255 in Marlin and Klipper.
+#[cfg(feature = "builtin-profiles")]
+fn printer_value_ranges() -> Vec<ValueRange> {
+    vec![ValueRange::new('S', 0.0, 255.0).for_command("M106")]
+}
+
+#[cfg(feature = "builtin-profiles")]
+fn grbl_machine(name: &str) -> MachineProfile {
+    const SUPPORTED: &[&str] = &[
+        "G0", "G1", "G2", "G3", "G4", "G10", "G17", "G18", "G19", "G20", "G21",
+        "G28", "G28.1", "G30", "G30.1", "G38.2", "G38.3", "G38.4", "G38.5",
+        "G40", "G43.1", "G49", "G53", "G54", "G55", "G56", "G57", "G58", "G59",
+        "G61", "G80", "G90", "G91", "G91.1", "G92", "G92.1", "G93", "G94",
+        "M0", "M1", "M2", "M3", "M4", "M5", "M7", "M8", "M9", "M30",
+    ];
+
+    MachineProfile {
+        dialect: DialectName::Grbl,
+        supported_commands: Some(
+            SUPPORTED
+                .iter()
+                .map(|&command| String::from(command))
+                .collect(),
+        ),
+        buffers: BufferLimits {
+            max_line_length: Some(80),
+            planner_blocks: Some(15),
+            receive_buffer: Some(128),
+        },
+        // Grbl's default maximum spindle speed ($30)
+        value_ranges: vec![ValueRange::new('S', 0.0, 1000.0)],
+        ..MachineProfile::new(name)
+    }
+}
+
+/// The built-in [`Dialect`]s, by name.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum DialectName {
+    /// [`Dialect::generic()`]
+    #[default]
+    Generic,
+    /// [`Dialect::reprap()`]
+    RepRap,
+    /// [`Dialect::grbl()`]
+    Grbl,
+    /// [`Dialect::linuxcnc()`]
+    LinuxCnc,
+    /// [`Dialect::fanuc()`]
+    Fanuc,
+    /// [`Dialect::fanuc_lathe()`]
+    #[cfg_attr(feature = "serde-1", serde(rename = "fanuc_lathe"))]
+    FanucLathe,
+}
+
+impl DialectName {
+    const NAMES: &'static [(&'static str, DialectName)] = &[
+        ("generic", DialectName::Generic),
+        ("reprap", DialectName::RepRap),
+        ("grbl", DialectName::Grbl),
+        ("linuxcnc", DialectName::LinuxCnc),
+        ("fanuc", DialectName::Fanuc),
+        ("fanuc_lathe", DialectName::FanucLathe),
+    ];
+
+    /// Look up a dialect by the name it's given in a profile (ignoring
+    /// case).
+    pub fn from_name(name: &str) -> Option<DialectName> {
+        DialectName::NAMES
+            .iter()
+            .find(|(candidate, _)| candidate.eq_ignore_ascii_case(name))
+            .map(|&(_, dialect)| dialect)
+    }
+
+    /// The name used for this dialect in a profile.
+    pub fn name(self) -> &'static str {
+        DialectName::NAMES
+            .iter()
+            .find(|&&(_, dialect)| dialect == self)
+            .map(|&(name, _)| name)
+            .unwrap_or_default()
+    }
+
+    /// Get the corresponding [`Dialect`].
+    pub fn dialect(self) -> Dialect {
+        match self {
+            DialectName::Generic => Dialect::generic(),
+            DialectName::RepRap => Dialect::reprap(),
+            DialectName::Grbl => Dialect::grbl(),
+            DialectName::LinuxCnc => Dialect::linuxcnc(),
+            DialectName::Fanuc => Dialect::fanuc(),
+            DialectName::FanucLathe => Dialect::fanuc_lathe(),
+        }
+    }
+}
+
+/// One of the machine's axes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Axis {
+    /// The letter used for this axis in a program (e.g. `X`).
+    pub letter: char,
+    /// The smallest coordinate the axis can reach, in millimeters.
+    pub min: f32,
+    /// The largest coordinate the axis can reach, in millimeters.
+    pub max: f32,
+    /// The fastest the axis can move, in millimeters per minute.
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub max_feed_rate: Option<f32>,
+}
+
+impl Axis {
+    /// Create an axis which can travel between two coordinates.
+    pub const fn new(letter: char, min: f32, max: f32) -> Self {
+        Axis {
+            letter,
+            min,
+            max,
+            max_feed_rate: None,
+        }
+    }
+
+    /// Set the axis's maximum feed rate.
+    pub const fn with_max_feed_rate(self, max_feed_rate: f32) -> Self {
+        Axis {
+            max_feed_rate: Some(max_feed_rate),
+            ..self
+        }
+    }
+
+    /// Get this axis's coordinate from a [`Position`], if it is one of the
+    /// linear axes the interpreter keeps track of.
+    fn component(&self) -> Option<fn(Position) -> f32> {
+        match self.letter.to_ascii_uppercase() {
+            'X' => Some(|p| p.x),
+            'Y' => Some(|p| p.y),
+            'Z' => Some(|p| p.z),
+            _ => None,
+        }
+    }
+}
+
+/// The values a letter may take, either everywhere or only as an argument
+/// to a particular command.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct ValueRange {
+    /// The letter being checked (e.g. `S`).
+    pub letter: char,
+    /// Only check the letter when it's used by this command (e.g. `"M106"`),
+    /// or everywhere if it's `None`.
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub command: Option<String>,
+    /// The smallest value allowed.
+    pub min: f32,
+    /// The largest value allowed.
+    pub max: f32,
+}
+
+impl ValueRange {
+    /// Allow a letter to take values between `min` and `max` (inclusive),
+    /// wherever it's used.
+    pub const fn new(letter: char, min: f32, max: f32) -> Self {
+        ValueRange {
+            letter,
+            command: None,
+            min,
+            max,
+        }
+    }
+
+    /// Only check the letter when it's used by a particular command.
+    pub fn for_command(self, command: &str) -> Self {
+        ValueRange {
+            command: Some(String::from(command)),
+            ..self
+        }
+    }
+
+    /// Is the value allowed?
+    pub fn contains(&self, value: f32) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+
+    /// Does this range apply to the arguments of a particular command?
+    pub fn applies_to(&self, key: CommandKey) -> bool {
+        match &self.command {
+            Some(command) => command.trim().parse::<CommandKey>() == Ok(key),
+            None => true,
+        }
+    }
+}
+
+/// Limits on how much the controller can buffer.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct BufferLimits {
+    /// The longest line the controller will accept, in bytes.
+    pub max_line_length: Option<usize>,
+    /// The number of moves the motion planner can look ahead.
+    pub planner_blocks: Option<usize>,
+    /// The size of the controller's receive buffer, in bytes.
+    pub receive_buffer: Option<usize>,
+}
+
+/// How the machine's motors move the tool.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(tag = "type", rename_all = "lowercase")
+)]
+pub enum Kinematics {
+    /// Each motor drives a single axis.
+    #[default]
+    Cartesian,
+    /// Two motors work together to move the X and Y axes.
+    CoreXY,
+    /// Three arms hold the tool above a round bed.
+    Delta {
+        /// The length of each diagonal rod, in millimeters.
+        arm_length: f32,
+        /// The horizontal distance from the center to each tower, in
+        /// millimeters.
+        radius: f32,
+    },
+}
+
+/// Something in a program which won't work on a particular machine.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Violation {
+    /// The (zero-based) line the problem is on.
+    pub line: usize,
+    /// What the problem is.
+    pub kind: ViolationKind,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line + 1, self.kind)
+    }
+}
+
+/// The different kinds of [`Violation`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum ViolationKind {
+    /// A command the machine doesn't understand.
+    UnsupportedCommand(String),
+    /// A move which goes past an axis's travel limits.
+    OutOfBounds {
+        /// The axis.
+        axis: char,
+        /// The first coordinate found outside the limits, in millimeters.
+        value: f32,
+    },
+    /// A move which is faster than an axis can go.
+    FeedRateTooHigh {
+        /// The axis.
+        axis: char,
+        /// How fast the axis would need to move, in millimeters per minute.
+        feed_rate: f32,
+    },
+    /// A line which is too long for the controller's buffer.
+    LineTooLong {
+        /// The line's length, in bytes.
+        length: usize,
+    },
+    /// A value outside the [`ValueRange`] for its letter.
+    OutOfRange {
+        /// The letter.
+        letter: char,
+        /// The command the value was given to (e.g. `"M106"`).
+        command: String,
+        /// The value as it was written.
+        value: f32,
+        /// The smallest value allowed.
+        min: f32,
+        /// The largest value allowed.
+        max: f32,
+    },
+}
+
+impl ViolationKind {
+    /// A value which would fix the problem, if there's an obvious one.
+    ///
+    /// For a [`ViolationKind::OutOfRange`], this is the nearest value which
+    /// is allowed.
+    pub fn suggestion(&self) -> Option<f32> {
+        match *self {
+            ViolationKind::OutOfRange {
+                value, min, max, ..
+            } => Some(value.max(min).min(max)),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ViolationKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ViolationKind::UnsupportedCommand(command) => {
+                write!(f, "the machine doesn't support {}", command)
+            },
+            ViolationKind::OutOfBounds { axis, value } => {
+                write!(f, "the {} axis can't reach {}", axis, value)
+            },
+            ViolationKind::FeedRateTooHigh { axis, feed_rate } => write!(
+                f,
+                "the {} axis can't move at {} mm/min",
+                axis, feed_rate
+            ),
+            ViolationKind::LineTooLong { length } => {
+                write!(f, "the line is too long ({} bytes)", length)
+            },
+            ViolationKind::OutOfRange {
+                letter,
+                command,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "{} {}{} is outside {} to {}, try {}{}",
+                command,
+                letter,
+                value,
+                min,
+                max,
+                letter,
+                self.suggestion().unwrap_or(*value)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arcs_are_checked_along_their_length() {
+        let mut profile = MachineProfile::new("test");
+        profile.axes.push(Axis::new('Y', -4.0, 4.0));
+
+        // a semicircle from (0, 0) to (10, 0), bulging up to y = 5
+        let got = profile.validate("G2 X10 I5 F100\nG1 X20");
+
+        assert_eq!(got.len(), 1, "{:?}", got);
+        assert_eq!(got[0].line, 0);
+        match got[0].kind {
+            ViolationKind::OutOfBounds { axis: 'Y', value } => {
+                assert!(value > 4.0)
+            },
+            ref other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn per_axis_feed_rates() {
+        let mut profile = MachineProfile::new("test");
+        profile
+            .axes
+            .push(Axis::new('Z', -50.0, 50.0).with_max_feed_rate(300.0));
+
+        let got = profile.validate("G1 X100 Z1 F1000\nG1 Z-10");
+
+        assert_eq!(
+            got,
+            vec![Violation {
+                line: 1,
+                kind: ViolationKind::FeedRateTooHigh {
+                    axis: 'Z',
+                    feed_rate: 1000.0
+                }
+            }]
+        );
+    }
+
+    #[test]
+    fn long_lines() {
+        let mut profile = MachineProfile::new("test");
+        profile.buffers.max_line_length = Some(10);
+
+        let got = profile.validate("G1 X1\nG1 X1 Y2 Z3 (comment)\n");
+
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].kind, ViolationKind::LineTooLong { length: 21 });
+    }
+
+    #[test]
+    fn command_ranges_take_precedence() {
+        let mut profile = MachineProfile::new("test");
+        profile.value_ranges = vec![
+            ValueRange::new('S', 0.0, 255.0).for_command("M106"),
+            ValueRange::new('S', 0.0, 300.0),
+        ];
+
+        let got = profile.validate("M106 S300\nM104 S300\nM3 S400\nM106 s-1");
+
+        let got: Vec<_> = got
+            .iter()
+            .map(|v| (v.line, v.kind.suggestion(), v.kind.to_string()))
+            .collect();
+        assert_eq!(
+            got,
+            vec![
+                (
+                    0,
+                    Some(255.0),
+                    String::from("M106 S300 is outside 0 to 255, try S255")
+                ),
+                (
+                    2,
+                    Some(300.0),
+                    String::from("M3 S400 is outside 0 to 300, try S300")
+                ),
+                (
+                    3,
+                    Some(0.0),
+                    String::from("M106 S-1 is outside 0 to 255, try S0")
+                ),
+            ]
+        );
+    }
+
+    #[cfg(feature = "builtin-profiles")]
+    #[test]
+    fn builtin_profiles_have_axes() {
+        for &profile in &Profile::ALL {
+            let machine = profile.machine_profile();
+
+            assert!(!machine.name.is_empty());
+            assert!(machine.axes.iter().all(|axis| axis.min < axis.max));
+            assert!(machine.axis('Z').is_some(), "{:?}", profile);
+            assert_eq!(Profile::from_name(profile.name()), Some(profile));
+        }
+    }
+
+    #[cfg(feature = "builtin-profiles")]
+    #[test]
+    fn grbl_doesnt_support_tool_changes() {
+        let machine = Profile::Shapeoko.machine_profile();
+
+        let got = machine.validate("T1 M6\nG0 X10");
+
+        assert_eq!(
+            got,
+            vec![Violation {
+                line: 0,
+                kind: ViolationKind::UnsupportedCommand("M6".into())
+            }]
+        );
+    }
+
+    #[cfg(feature = "profile-toml")]
+    #[test]
+    fn load_from_toml() {
+        let src = r#"
+            name = "Printer"
+            dialect = "reprap"
+            kinematics = { type = "delta", arm_length = 215.0, radius = 105.0 }
+
+            [[axes]]
+            letter = "Z"
+            min = 0.0
+            max = 300.0
+        "#;
+
+        let got = MachineProfile::from_toml(src).unwrap();
+
+        assert_eq!(got.name, "Printer");
+        assert_eq!(got.dialect(), Dialect::reprap());
+        assert_eq!(
+            got.kinematics,
+            Kinematics::Delta {
+                arm_length: 215.0,
+                radius: 105.0
+            }
+        );
+        assert_eq!(got.axis('z'), Some(&Axis::new('Z', 0.0, 300.0)));
+        assert_eq!(got.estimator, EstimatorConfig::default());
+    }
+
+    #[cfg(feature = "profile-json")]
+    #[test]
+    fn load_from_json() {
+        let src = r#"{"name": "Mill", "dialect": "fanuc_lathe",
+                      "supported_commands": ["G00", "G01"]}"#;
+
+        let got = MachineProfile::from_json(src).unwrap();
+
+        assert_eq!(got.dialect, DialectName::FanucLathe);
+        assert!(got.supports(&crate::parse("G1 X1").next().unwrap()));
+        assert!(!got.supports(&crate::parse("G2 X1").next().unwrap()));
+    }
+}
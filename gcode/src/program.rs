@@ -0,0 +1,616 @@
+//! An owned, editable g-code program.
+
+use crate::{
+    dialect::Dialect,
+    interpret::{
+        Interpreter, MachineState, Plane, Positioning, SpindleDirection, Units,
+    },
+    lexer::{Lexer, TokenType},
+    writer::{self, is_tape_marker, Writer},
+    CommandKey, GCode, Line, Mnemonic, Nop, Parser, Span, Word,
+};
+use core::fmt::{self, Display, Formatter};
+use std::{string::String, vec::Vec};
+
+/// A program which owns its source text.
+///
+/// The program is stored line-by-line so it can be edited without
+/// re-parsing everything, and lines which aren't touched keep their original
+/// formatting.
+///
+/// ```rust
+/// use gcode::{dialect::Dialect, program::Program};
+///
+/// let mut program = Program::parse("G21\nG0 X10", Dialect::generic());
+/// program.push_line("M30");
+///
+/// assert_eq!(program.lines().len(), 3);
+/// assert_eq!(program.to_string(), "G21\nG0 X10\nM30\n");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    dialect: Dialect,
+    lines: Vec<String>,
+}
+
+impl Program {
+    /// Create an empty [`Program`].
+    pub fn new(dialect: Dialect) -> Self {
+        Program {
+            dialect,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Create a [`Program`] from some source text.
+    pub fn parse(src: &str, dialect: Dialect) -> Self {
+        Program {
+            dialect,
+            lines: src.lines().map(String::from).collect(),
+        }
+    }
+
+    /// The [`Dialect`] this program is written in.
+    pub fn dialect(&self) -> &Dialect { &self.dialect }
+
+    /// The text of each line, without trailing newlines.
+    pub fn lines(&self) -> &[String] { &self.lines }
+
+    /// Get mutable access to the program's lines.
+    pub fn lines_mut(&mut self) -> &mut Vec<String> { &mut self.lines }
+
+    /// Add a line to the end of the program.
+    pub fn push_line<S: Into<String>>(&mut self, line: S) {
+        self.lines.push(line.into());
+    }
+
+    /// Run the program through an [`Interpreter`] to find the
+    /// [`MachineState`] it leaves the machine in.
+    pub fn final_state(&self) -> MachineState {
+        let src = self.to_string();
+        let mut interpreter = Interpreter::new(self.dialect);
+
+        for line in Parser::<_>::new_with_dialect(&src, Nop, self.dialect) {
+            let _ = interpreter.process_line(&line);
+        }
+
+        *interpreter.state()
+    }
+
+    /// Renumber every line which has a line number (`N10`), counting up from
+    /// `start` in increments of `step`.
+    ///
+    /// Lines with a checksum (`N10 G1 X5*93`) get a new one to match. If the
+    /// numbers would go past [`u32::MAX`], the rest of the lines are left
+    /// alone.
+    ///
+    /// ```rust
+    /// # use gcode::{dialect::Dialect, program::Program};
+    /// let src = "N5 G0 X1\nG1 X2\nN7 M30";
+    /// let mut program = Program::parse(src, Dialect::generic());
+    ///
+    /// program.renumber(10, 10);
+    ///
+    /// assert_eq!(program.to_string(), "N10 G0 X1\nG1 X2\nN20 M30\n");
+    /// ```
+    pub fn renumber(&mut self, start: u32, step: u32) {
+        let dialect = self.dialect;
+        let mut next = Some(start);
+
+        for text in &mut self.lines {
+            let number = match parse_line(text, dialect)
+                .and_then(|line| line.line_number())
+            {
+                Some(number) => number,
+                None => continue,
+            };
+            let current = match next {
+                Some(current) => current,
+                None => break,
+            };
+
+            let span = number.span;
+            text.replace_range(span.start..span.end, &format!("N{}", current));
+            update_checksum(text, dialect);
+            next = current.checked_add(step);
+        }
+    }
+
+    /// Join two programs together so they can be run as a single job.
+    ///
+    /// This is more involved than just concatenating their text:
+    ///
+    /// - Program end commands (`M2` and `M30`) and anything after them are
+    ///   removed from `first`, as are any `%` tape markers at the start of
+    ///   `second`
+    /// - If `first` leaves the spindle running, it is stopped
+    /// - If `first` leaves the machine in a different units, positioning mode,
+    ///   plane or work coordinate system than the defaults `second` expects,
+    ///   they are reset
+    /// - If `first` turns a heater off at the end and `second` never sets that
+    ///   heater's temperature, it is heated back up to the last temperature
+    ///   `first` used before continuing
+    /// - If either program uses line numbers, the result is renumbered
+    ///
+    /// The result uses `first`'s [`Dialect`].
+    ///
+    /// ```rust
+    /// # use gcode::{dialect::Dialect, program::Program};
+    /// let dialect = Dialect::generic();
+    /// let src = "%\nG20 G55\nM3 S1000\nG1 X1\nM30\n%";
+    /// let first = Program::parse(src, dialect);
+    /// let second = Program::parse("%\nG0 X5\nM30\n%", dialect);
+    ///
+    /// let joined = Program::concat(&first, &second);
+    ///
+    /// assert_eq!(
+    ///     joined.to_string(),
+    ///     "%\nG20 G55\nM3 S1000\nG1 X1\nM5\nG21 G54\nG0 X5\nM30\n%\n",
+    /// );
+    /// ```
+    pub fn concat(first: &Program, second: &Program) -> Program {
+        let dialect = first.dialect;
+        let mut joined = Program::new(dialect);
+
+        for text in &first.lines {
+            match parse_line(text, dialect) {
+                Some(ref line) if is_program_end(line) => {
+                    let remainder = without_program_end(line, text, &dialect);
+                    if !remainder.is_empty() {
+                        joined.push_line(remainder);
+                    }
+                    break;
+                },
+                _ => joined.push_line(text.clone()),
+            }
+        }
+
+        let state = first.final_state();
+        joined.lines.extend(reconcile(
+            &state,
+            &MachineState::default(),
+            &dialect,
+        ));
+        joined.lines.extend(reheat(first, second));
+
+        let body = second
+            .lines
+            .iter()
+            .skip_while(|text| is_tape_marker(text) || text.trim().is_empty());
+        joined.lines.extend(body.cloned());
+
+        let numbers: Vec<Word> = joined
+            .lines
+            .iter()
+            .filter_map(|text| parse_line(text, dialect)?.line_number())
+            .collect();
+        if let Some(first) = numbers.first().and_then(Word::number) {
+            joined.renumber(first as u32, 10);
+        }
+
+        joined
+    }
+
+    /// Remove any files being uploaded to the printer's SD card (the lines
+    /// between `M28 filename` and `M29`) from this program and return them
+    /// as separate [`Upload`]s.
+    ///
+    /// Host logs often interleave an upload with the live job, and the
+    /// uploaded commands shouldn't be treated as if they were executed.
+    ///
+    /// ```rust
+    /// # use gcode::{dialect::Dialect, program::Program};
+    /// let src = "M105\nN1 M28 part.gco*77\nG28\nG1 X10\nM29\nM23 part.gco";
+    /// let mut log = Program::parse(src, Dialect::reprap());
+    ///
+    /// let uploads = log.extract_uploads();
+    ///
+    /// assert_eq!(log.lines(), &["M105", "M23 part.gco"]);
+    /// assert_eq!(uploads.len(), 1);
+    /// assert_eq!(uploads[0].filename, "part.gco");
+    /// assert_eq!(uploads[0].program.lines(), &["G28", "G1 X10"]);
+    /// assert!(uploads[0].terminated);
+    /// ```
+    pub fn extract_uploads(&mut self) -> Vec<Upload> {
+        let mut uploads = Vec::new();
+        let mut live = Vec::new();
+        let mut current: Option<Upload> = None;
+
+        for (index, text) in self.lines.drain(..).enumerate() {
+            match (upload_command(&text), current.take()) {
+                (Some((28, filename)), previous) => {
+                    // a new upload implicitly finishes the previous one
+                    uploads.extend(previous);
+                    current = Some(Upload {
+                        filename: String::from(filename),
+                        program: Program::new(self.dialect),
+                        line: index,
+                        terminated: false,
+                    });
+                },
+                (Some((29, _)), Some(mut upload)) => {
+                    upload.terminated = true;
+                    uploads.push(upload);
+                },
+                (_, Some(mut upload)) => {
+                    upload.program.lines.push(text);
+                    current = Some(upload);
+                },
+                (_, None) => live.push(text),
+            }
+        }
+
+        uploads.extend(current);
+        self.lines = live;
+
+        uploads
+    }
+}
+
+/// A file uploaded to a printer's SD card using `M28`/`M29`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upload {
+    /// The name of the file being written.
+    pub filename: String,
+    /// The file's contents.
+    pub program: Program,
+    /// The (zero-based) index of the `M28` line in the original program.
+    pub line: usize,
+    /// Was the upload finished with an `M29`? An upload without one
+    /// continues until the end of the program.
+    pub terminated: bool,
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// If this line starts (`M28`) or finishes (`M29`) an SD card upload, get
+/// the command number and the rest of the line (the filename).
+///
+/// This works on the raw text because filenames aren't valid g-code, and
+/// copes with the line numbers and checksums a host adds when streaming.
+fn upload_command(text: &str) -> Option<(u32, &str)> {
+    let mut text = text.trim();
+
+    if let Some(star) = text.rfind('*') {
+        if text[star + 1..].chars().all(|c| c.is_ascii_digit()) {
+            text = text[..star].trim_end();
+        }
+    }
+
+    if let Some(rest) = text.strip_prefix(|c| c == 'N' || c == 'n') {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        text = rest[digits..].trim_start();
+    }
+
+    let rest = text.strip_prefix(|c| c == 'M' || c == 'm')?;
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let number = rest[..digits].parse().ok()?;
+    let argument = &rest[digits..];
+
+    if (number == 28 || number == 29)
+        && (argument.is_empty() || argument.starts_with(char::is_whitespace))
+    {
+        Some((number, argument.trim()))
+    } else {
+        None
+    }
+}
+
+fn parse_line(text: &str, dialect: Dialect) -> Option<Line<'_>> {
+    Parser::<_>::new_with_dialect(text, Nop, dialect).next()
+}
+
+/// Recalculate the `*93` checksum at the end of a line, if it has one.
+fn update_checksum(text: &mut String, dialect: Dialect) {
+    let dialect = Dialect {
+        checksums: true,
+        ..dialect
+    };
+    let checksum = Lexer::new(text)
+        .with_dialect(&dialect)
+        .find(|token| token.kind == TokenType::Checksum)
+        .map(|token| token.span);
+
+    if let Some(span) = checksum {
+        let value = writer::checksum(&text[..span.start]);
+        text.replace_range(span.start..span.end, &format!("*{}", value));
+    }
+}
+
+fn is_program_end_command(gcode: &GCode) -> bool {
+    const PROGRAM_ENDS: [CommandKey; 2] =
+        [CommandKey::miscellaneous(2), CommandKey::miscellaneous(30)];
+
+    PROGRAM_ENDS.contains(&gcode.key())
+}
+
+fn is_program_end(line: &Line<'_>) -> bool {
+    line.gcodes().iter().any(is_program_end_command)
+}
+
+/// Rewrite a line without its program end commands, returning an empty
+/// string if there is nothing left worth keeping.
+fn without_program_end(
+    line: &Line<'_>,
+    src: &str,
+    dialect: &Dialect,
+) -> String {
+    let mut line = line.clone();
+    line.gcodes.retain(|gcode| !is_program_end_command(gcode));
+
+    if line.gcodes.is_empty() {
+        return String::new();
+    }
+
+    let mut text = String::new();
+    let _ = Writer::for_dialect(&mut text, dialect)
+        .write_line_with_source(&line, src);
+    text.truncate(text.trim_end_matches(['\r', '\n']).len());
+
+    text
+}
+
+fn write_line(line: &Line<'_>, dialect: &Dialect) -> String {
+    let mut text = String::new();
+    let _ = Writer::for_dialect(&mut text, dialect).write_line(line);
+    text.truncate(text.trim_end_matches(['\r', '\n']).len());
+
+    text
+}
+
+fn command(mnemonic: Mnemonic, number: f32) -> GCode {
+    GCode::new(mnemonic, number, Span::PLACEHOLDER)
+}
+
+/// Generate the lines needed to get from one [`MachineState`] to another.
+fn reconcile(
+    from: &MachineState,
+    to: &MachineState,
+    dialect: &Dialect,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    if from.spindle.direction != SpindleDirection::Stopped
+        && to.spindle.direction == SpindleDirection::Stopped
+    {
+        let mut line: Line<'_> = Line::default();
+        let _ = line.push_gcode(command(Mnemonic::Miscellaneous, 5.0));
+        lines.push(write_line(&line, dialect));
+    }
+
+    let mut modal: Line<'_> = Line::default();
+
+    if from.units != to.units {
+        let number = match to.units {
+            Units::Inches => 20.0,
+            Units::Millimeters => 21.0,
+        };
+        let _ = modal.push_gcode(command(Mnemonic::General, number));
+    }
+    if from.positioning != to.positioning {
+        let number = match to.positioning {
+            Positioning::Absolute => 90.0,
+            Positioning::Relative => 91.0,
+        };
+        let _ = modal.push_gcode(command(Mnemonic::General, number));
+    }
+    if from.plane != to.plane {
+        let number = match to.plane {
+            Plane::XY => 17.0,
+            Plane::ZX => 18.0,
+            Plane::YZ => 19.0,
+        };
+        let _ = modal.push_gcode(command(Mnemonic::General, number));
+    }
+    if from.coordinate_system != to.coordinate_system {
+        let (major, minor) = to.coordinate_system.gcode();
+        let number = major as f32 + minor as f32 / 10.0;
+        let _ = modal.push_gcode(command(Mnemonic::General, number));
+    }
+
+    if !modal.gcodes().is_empty() {
+        lines.push(write_line(&modal, dialect));
+    }
+
+    lines
+}
+
+/// The commands for setting (and waiting for) a heater's temperature.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Heater {
+    set: u32,
+    wait: u32,
+}
+
+/// Heaters in the order they should be warmed up (the bed is slower).
+const HEATERS: [Heater; 2] = [
+    // bed
+    Heater {
+        set: 140,
+        wait: 190,
+    },
+    // hotend
+    Heater {
+        set: 104,
+        wait: 109,
+    },
+];
+
+impl Heater {
+    fn temperature(&self, gcode: &GCode) -> Option<f32> {
+        let major = gcode.major_number();
+
+        if gcode.mnemonic == Mnemonic::Miscellaneous
+            && (major == self.set || major == self.wait)
+        {
+            gcode.value_for('S').or_else(|| gcode.value_for('R'))
+        } else {
+            None
+        }
+    }
+
+    fn temperatures(&self, program: &Program) -> Vec<f32> {
+        let src = program.to_string();
+
+        crate::parse(&src)
+            .filter_map(|gcode| self.temperature(&gcode))
+            .collect()
+    }
+}
+
+/// Heat anything `first` switched off back up if `second` relies on it.
+fn reheat(first: &Program, second: &Program) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for heater in &HEATERS {
+        if !heater.temperatures(second).is_empty() {
+            continue;
+        }
+
+        let temperatures = heater.temperatures(first);
+        let switched_off = temperatures.last() == Some(&0.0);
+        let last_used = temperatures.iter().rev().find(|&&t| t > 0.0);
+
+        if let (true, Some(&temperature)) = (switched_off, last_used) {
+            let mut line: Line<'_> = Line::default();
+            let wait = command(Mnemonic::Miscellaneous, heater.wait as f32)
+                .with_argument(Word::new('S', temperature, Span::PLACEHOLDER));
+            let _ = line.push_gcode(wait);
+            lines.push(write_line(&line, &first.dialect));
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(src: &str) -> Program { Program::parse(src, Dialect::reprap()) }
+
+    #[test]
+    fn program_end_is_removed_from_a_line_with_other_commands() {
+        let first = program("G1 X1 F100\nM5 M30 ; done\n;trailing");
+        let second = program("G1 X2");
+
+        let got = Program::concat(&first, &second);
+
+        assert_eq!(got.to_string(), "G1 X1 F100\nM5 ; done\nG1 X2\n");
+    }
+
+    #[test]
+    fn renumbering_updates_checksums() {
+        let mut src = String::new();
+        writer::write_with_checksum(&mut src, 1, "G1 X1").unwrap();
+        src.push_str("\nG1 X2 ; *12\n");
+        writer::write_with_checksum(&mut src, 2, "G1 X3").unwrap();
+        let mut program = program(&src);
+
+        program.renumber(10, 10);
+
+        let mut expected = [String::new(), String::new()];
+        writer::write_with_checksum(&mut expected[0], 10, "G1 X1").unwrap();
+        writer::write_with_checksum(&mut expected[1], 20, "G1 X3").unwrap();
+        assert_eq!(
+            program.lines(),
+            &[&expected[0], "G1 X2 ; *12", &expected[1]]
+        );
+    }
+
+    #[test]
+    fn renumbering_stops_before_overflowing() {
+        let mut program = program("N1 G0\nN2 G1\nN3 G2");
+
+        program.renumber(u32::MAX - 5, 5);
+
+        assert_eq!(
+            program.lines(),
+            &["N4294967290 G0", "N4294967295 G1", "N3 G2"]
+        );
+    }
+
+    #[test]
+    fn concatenating_programs_with_line_numbers_renumbers_them() {
+        let first = program("N10 G90\nN20 G0 X1\nN30 M30");
+        let second = program("N10 G91\nN20 G0 X1\nN30 M2");
+
+        let got = Program::concat(&first, &second);
+
+        assert_eq!(
+            got.to_string(),
+            "N10 G90\nN20 G0 X1\nN30 G91\nN40 G0 X1\nN50 M2\n"
+        );
+    }
+
+    #[test]
+    fn relative_positioning_is_reset() {
+        let first = program("G91\nG0 X1");
+        let second = program("G0 X1");
+
+        let got = Program::concat(&first, &second);
+
+        assert_eq!(got.lines(), &["G91", "G0 X1", "G90", "G0 X1"]);
+    }
+
+    #[test]
+    fn reheat_when_the_next_plate_doesnt() {
+        let first = program("M190 S60\nM109 S210\nG1 X1\nM104 S0\nM140 S0");
+        let second = program("G1 X2");
+
+        let got = Program::concat(&first, &second);
+
+        assert_eq!(&got.lines()[5..], &["M190 S60", "M109 S210", "G1 X2"]);
+    }
+
+    #[test]
+    fn leave_heaters_alone_when_the_next_plate_sets_them() {
+        let first = program("M109 S210\nG1 X1\nM104 S0");
+        let second = program("M109 S200\nG1 X2");
+
+        let got = Program::concat(&first, &second);
+
+        assert_eq!(got.lines().len(), 5);
+    }
+
+    #[test]
+    fn recognise_upload_commands() {
+        let inputs = vec![
+            ("M28 file.gco", Some((28, "file.gco"))),
+            ("N12 M28 /sd/a b.gco*101", Some((28, "/sd/a b.gco"))),
+            ("m029", Some((29, ""))),
+            ("M290 Z0.1", None),
+            ("M280 P0 S10", None),
+            ("G28", None),
+            ("N5", None),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(upload_command(src), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn unterminated_uploads_run_to_the_end() {
+        let mut log = program("G28\nM28 a.gco\nG1 X1\nM28 b.gco\nG1 X2");
+
+        let uploads = log.extract_uploads();
+
+        assert_eq!(log.lines(), &["G28"]);
+        assert_eq!(uploads.len(), 2);
+        assert_eq!(uploads[0].filename, "a.gco");
+        assert!(!uploads[0].terminated);
+        assert_eq!(uploads[1].program.lines(), &["G1 X2"]);
+        assert_eq!(uploads[1].line, 3);
+    }
+}
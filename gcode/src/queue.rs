@@ -0,0 +1,299 @@
+//! Predicting whether a sender can keep the machine busy.
+//!
+//! Firmware doesn't run commands as they arrive. Moves go into a planner
+//! buffer which holds a handful of them, and the host is only told to send
+//! more once there's room. When lots of short moves arrive over a slow link
+//! the buffer can run dry, and the machine stutters while it waits for the
+//! next command, leaving blobs and zits on printed parts.
+//!
+//! A [`QueueModel`] replays a program's timeline against a model of the
+//! link and the planner buffer, recording how full the buffer is when each
+//! command is sent and how long the machine is left waiting for it. Senders
+//! can use this to decide when to throttle other traffic, or to warn that a
+//! program needs a faster link.
+//!
+//! ```rust
+//! use gcode::{
+//!     analysis::Analyzer,
+//!     dialect::Dialect,
+//!     queue::{QueueConfig, QueueModel},
+//! };
+//! use std::fmt::Write;
+//!
+//! // one long move, then lots of tiny ones
+//! let mut src = String::from("G1 X100 F6000\n");
+//! for i in 0..50 {
+//!     writeln!(src, "G1 X{:.2}", 100.0 + i as f32 * 0.01).unwrap();
+//! }
+//!
+//! let analysis = Analyzer::new(Dialect::generic()).analyze(&src);
+//! let config = QueueConfig {
+//!     depth: 8,
+//!     bytes_per_second: 960.0, // 9600 baud
+//!     latency: 0.0,
+//! };
+//! let model = QueueModel::new(&analysis, &config);
+//!
+//! // the buffer fills up while the long move runs, so the host has to wait
+//! // for room before sending the 9th command...
+//! assert_eq!(model.commands()[7].occupancy, 7);
+//! assert!(model.commands()[8].sent_at > 1.0);
+//! // ... but the tiny moves are quicker to run than to send, so the buffer
+//! // soon drains and the machine waits for each one
+//! let last = model.commands().last().unwrap();
+//! assert_eq!(last.occupancy, 1);
+//! assert!(last.starved_for > 0.0);
+//! assert!(model.stutters().count() > 0);
+//! ```
+
+use crate::{analysis::Analysis, planner::Plan, Span};
+use std::vec::Vec;
+
+/// The link to the machine and the size of its planner buffer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct QueueConfig {
+    /// How many moves the planner buffer can hold (e.g. `BLOCK_BUFFER_SIZE`
+    /// in Marlin, or `BLOCK_BUFFER_SIZE` in Grbl's `config.h`).
+    pub depth: usize,
+    /// How fast commands can be sent, in bytes per second (roughly the baud
+    /// rate divided by 10 for a serial port).
+    pub bytes_per_second: f32,
+    /// A fixed delay for each command, in seconds (e.g. waiting for the
+    /// firmware's `ok` to come back over USB).
+    pub latency: f32,
+}
+
+impl Default for QueueConfig {
+    fn default() -> QueueConfig {
+        QueueConfig {
+            depth: 16,
+            bytes_per_second: 11_520.0,
+            latency: 0.001,
+        }
+    }
+}
+
+/// What happened when a single command (one line of the program) was sent.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct QueuedCommand {
+    /// The line containing the command.
+    pub span: Span,
+    /// When the host started sending the command, in seconds since the
+    /// start of the program.
+    pub sent_at: f32,
+    /// How many moves were already waiting in the planner buffer (including
+    /// the one being executed) when the command was sent.
+    pub occupancy: usize,
+    /// How long the machine sat idle waiting for this command, in seconds.
+    pub starved_for: f32,
+}
+
+impl QueuedCommand {
+    /// Did the machine have to stop and wait for this command?
+    pub fn is_stutter(&self) -> bool { self.starved_for > 0.0 }
+}
+
+/// A model of a program being streamed to a machine.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct QueueModel {
+    commands: Vec<QueuedCommand>,
+    finish_time: f32,
+}
+
+impl QueueModel {
+    /// Stream a program using the durations from an [`Analysis`].
+    pub fn new(analysis: &Analysis, config: &QueueConfig) -> Self {
+        let timeline = analysis
+            .segments()
+            .iter()
+            .map(|segment| (segment.span, segment.duration));
+        QueueModel::from_timeline(timeline, config)
+    }
+
+    /// Stream a program using the durations from a [`Plan`], which take
+    /// acceleration into account.
+    ///
+    /// The plan must have been made from the same `analysis`.
+    pub fn with_plan(
+        analysis: &Analysis,
+        plan: &Plan,
+        config: &QueueConfig,
+    ) -> Self {
+        let timeline = analysis
+            .segments()
+            .iter()
+            .zip(plan.segments())
+            .map(|(segment, planned)| (segment.span, planned.duration));
+        QueueModel::from_timeline(timeline, config)
+    }
+
+    fn from_timeline<I>(timeline: I, config: &QueueConfig) -> Self
+    where
+        I: IntoIterator<Item = (Span, f32)>,
+    {
+        let depth = config.depth.max(1);
+        let mut commands: Vec<QueuedCommand> = Vec::new();
+        // when each move finishes executing
+        let mut finishes: Vec<f32> = Vec::new();
+        // when the host can start sending the next command
+        let mut link_free = 0.0_f32;
+        // when the current command's moves can go into the buffer
+        let mut ready = 0.0_f32;
+
+        for (span, duration) in timeline {
+            // when a move can go into the buffer, given the moves before it
+            let slot = |finishes: &[f32]| {
+                finishes
+                    .len()
+                    .checked_sub(depth)
+                    .map_or(0.0, |index| finishes[index])
+            };
+
+            let same_command = commands
+                .last()
+                .is_some_and(|command| command.span.line == span.line);
+            if !same_command {
+                // bytes on the line, plus its newline
+                let bytes = (span.end - span.start + 1) as f32;
+                let sent_at = link_free.max(slot(&finishes));
+                let finished = finishes.partition_point(|&f| f <= sent_at);
+
+                commands.push(QueuedCommand {
+                    span,
+                    sent_at,
+                    occupancy: finishes.len() - finished,
+                    starved_for: 0.0,
+                });
+                ready = sent_at
+                    + bytes / config.bytes_per_second.max(f32::EPSILON)
+                    + config.latency;
+            }
+
+            // moves generated by the same command (e.g. a canned cycle) wait
+            // for room in the buffer, holding up the link while they do
+            ready = ready.max(slot(&finishes));
+            link_free = ready;
+
+            let start = match finishes.last() {
+                Some(&previous) if ready > previous => {
+                    if let Some(command) = commands.last_mut() {
+                        command.starved_for += ready - previous;
+                    }
+                    ready
+                },
+                Some(&previous) => previous,
+                None => ready,
+            };
+            finishes.push(start + duration);
+        }
+
+        QueueModel {
+            commands,
+            finish_time: finishes.last().copied().unwrap_or(0.0),
+        }
+    }
+
+    /// Every command which makes the machine do something, in the order
+    /// they are sent.
+    ///
+    /// Lines which don't produce a [`Segment`][crate::analysis::Segment]
+    /// (e.g. comments or temperature changes) aren't included, and are
+    /// assumed to take no time to send.
+    pub fn commands(&self) -> &[QueuedCommand] { &self.commands }
+
+    /// Every command the machine had to stop and wait for.
+    pub fn stutters(&self) -> impl Iterator<Item = &QueuedCommand> + '_ {
+        self.commands.iter().filter(|command| command.is_stutter())
+    }
+
+    /// The total time the machine spends waiting for commands, in seconds.
+    pub fn total_starvation(&self) -> f32 {
+        self.commands
+            .iter()
+            .map(|command| command.starved_for)
+            .sum()
+    }
+
+    /// When the machine finishes, in seconds since the first command was
+    /// sent, including any time spent waiting for commands.
+    pub fn total_time(&self) -> f32 { self.finish_time }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analysis::Analyzer, dialect::Dialect, planner::PlannerConfig};
+
+    fn fast_link() -> QueueConfig {
+        QueueConfig {
+            depth: 4,
+            bytes_per_second: 1e6,
+            latency: 0.0,
+        }
+    }
+
+    #[test]
+    fn long_moves_keep_the_buffer_full() {
+        let src = "G1 X10 F600\nX20\nX30\nX40\nX50\nX60\nX70";
+        let analysis = Analyzer::new(Dialect::generic()).analyze(src);
+
+        let got = QueueModel::new(&analysis, &fast_link());
+
+        let occupancy: Vec<_> =
+            got.commands().iter().map(|c| c.occupancy).collect();
+        // once the buffer is full, the next command is sent as soon as a
+        // move finishes and makes room for it
+        assert_eq!(occupancy, vec![0, 1, 2, 3, 3, 3, 3]);
+        assert_eq!(got.stutters().count(), 0);
+        // the fifth move has to wait for the first to finish
+        assert!((got.commands()[4].sent_at - 1.0).abs() < 1e-4);
+        assert!((got.total_time() - analysis.total_time()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn a_slow_link_starves_the_machine() {
+        // each line takes 0.1s to run, but 0.7s to send
+        let src = "G1 F60\nG1 X.1\nG1 X.2\nG1 X.3";
+        let analysis = Analyzer::new(Dialect::generic()).analyze(src);
+        let config = QueueConfig {
+            bytes_per_second: 10.0,
+            ..fast_link()
+        };
+
+        let got = QueueModel::new(&analysis, &config);
+
+        let starved: Vec<_> = got
+            .commands()
+            .iter()
+            .map(|c| (c.span.line, (c.starved_for * 10.0).round()))
+            .collect();
+        assert_eq!(starved, vec![(1, 0.0), (2, 6.0), (3, 6.0)]);
+        assert!((got.total_starvation() - 1.2).abs() < 1e-4);
+    }
+
+    #[test]
+    fn planned_durations_are_used() {
+        let src = "G1 X10 F600\nX20";
+        let analyzer = Analyzer::new(Dialect::generic());
+        let analysis = analyzer.analyze(src);
+        let plan =
+            Plan::new(&analysis, analyzer.config(), &PlannerConfig::default());
+
+        let got = QueueModel::with_plan(&analysis, &plan, &fast_link());
+
+        assert!(got.total_time() > analysis.total_time());
+    }
+}
@@ -0,0 +1,360 @@
+use crate::{
+    buffers::{Buffers, DefaultBuffers},
+    dialect::Dialect,
+    Callbacks, Comment, Line, Mnemonic, Nop, Parser, Span, Word,
+};
+use core::marker::PhantomData;
+
+/// Iterate over each physical line in some text, alongside whatever the
+/// parser made of it.
+///
+/// This is useful for tools which copy most of a file unchanged and only
+/// edit a few lines. Anything the parser couldn't fully understand (e.g. a
+/// vendor-specific command) has no [`RawLine::parsed`] value, so it can be
+/// passed through verbatim instead of being lost.
+///
+/// Any problems are still reported to the [`Callbacks`].
+///
+/// ```rust
+/// use gcode::{CommandKey, Nop, RawLines};
+///
+/// let src = "G90\r\n@PAUSE now\nM0 (wait)\nG1 X5\n";
+/// let m0 = CommandKey::miscellaneous(0);
+///
+/// // comment out any program stops, copying everything else as-is
+/// let mut output = String::new();
+/// for raw in RawLines::<_>::new(src, Nop) {
+///     let is_stop = raw.parsed.as_ref().is_some_and(|line| {
+///         line.gcodes().iter().any(|g| g.key() == m0)
+///     });
+///
+///     if is_stop {
+///         output.push(';');
+///     }
+///     output.push_str(raw.text);
+///     output.push_str(raw.ending);
+/// }
+///
+/// assert_eq!(output, "G90\r\n@PAUSE now\n;M0 (wait)\nG1 X5\n");
+/// ```
+#[derive(Debug)]
+pub struct RawLines<'input, C = Nop, B = DefaultBuffers> {
+    src: &'input str,
+    /// Where the next line starts.
+    position: usize,
+    line: usize,
+    callbacks: C,
+    dialect: Dialect,
+    /// The last command word, for lines which only contain arguments.
+    last_command: Option<Word>,
+    /// The line number expected next, when checking line numbers.
+    next_line_number: Option<u32>,
+    _buffers: PhantomData<B>,
+}
+
+impl<'input, C, B> RawLines<'input, C, B> {
+    /// Create a new [`RawLines`] iterator which uses a set of
+    /// [`Callbacks`].
+    pub fn new(src: &'input str, callbacks: C) -> Self {
+        RawLines::new_with_dialect(src, callbacks, Dialect::default())
+    }
+
+    /// Create a new [`RawLines`] iterator which follows the conventions of a
+    /// particular [`Dialect`].
+    pub fn new_with_dialect(
+        src: &'input str,
+        callbacks: C,
+        dialect: Dialect,
+    ) -> Self {
+        RawLines {
+            src,
+            position: 0,
+            line: 0,
+            callbacks,
+            dialect,
+            last_command: None,
+            next_line_number: None,
+            _buffers: PhantomData,
+        }
+    }
+
+    /// The [`Callbacks`] used to report errors.
+    pub fn callbacks(&self) -> &C { &self.callbacks }
+}
+
+impl<'input, C, B> Iterator for RawLines<'input, C, B>
+where
+    C: Callbacks,
+    B: Buffers<'input>,
+{
+    type Item = RawLine<'input, B>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.src.len() {
+            return None;
+        }
+
+        let start = self.position;
+        let rest = &self.src[start..];
+        let (end, next) = match rest.find('\n') {
+            Some(newline) if rest[..newline].ends_with('\r') => {
+                (start + newline - 1, start + newline + 1)
+            },
+            Some(newline) => (start + newline, start + newline + 1),
+            None => (self.src.len(), self.src.len()),
+        };
+
+        let mut recorder = Recorder {
+            callbacks: &mut self.callbacks,
+            problems: false,
+        };
+        let (parsed, extra) = {
+            let mut parser: Parser<'_, _, B> = Parser::resume(
+                &self.src[..end],
+                &mut recorder,
+                self.dialect,
+                start,
+                self.line,
+                self.last_command,
+            )
+            .with_next_line_number(self.next_line_number);
+
+            let parsed = parser.next();
+            let extra = parser.next().is_some();
+            self.last_command = parser.last_command();
+            self.next_line_number = parser.next_line_number();
+            (parsed, extra)
+        };
+
+        let parsed = if recorder.problems || extra {
+            None
+        } else {
+            Some(parsed.unwrap_or_else(|| Line {
+                span: Span::new(start, end, self.line),
+                ..Default::default()
+            }))
+        };
+
+        let raw = RawLine {
+            text: &self.src[start..end],
+            ending: &self.src[end..next],
+            line: self.line,
+            parsed,
+        };
+        self.position = next;
+        self.line += 1;
+
+        Some(raw)
+    }
+}
+
+/// A single physical line, as yielded by [`RawLines`].
+#[derive(Debug)]
+pub struct RawLine<'input, B: Buffers<'input> = DefaultBuffers> {
+    /// The line's text, without its line ending.
+    pub text: &'input str,
+    /// The line ending (`"\n"` or `"\r\n"`), or an empty string for the
+    /// last line of a file which doesn't end with a newline.
+    pub ending: &'input str,
+    /// The (zero-based) line number.
+    pub line: usize,
+    /// The parsed [`Line`], or `None` if any of the text couldn't be
+    /// represented by one (e.g. it wasn't understood, or there wasn't
+    /// enough room to store it). Blank lines are parsed as an empty [`Line`].
+    pub parsed: Option<Line<'input, B>>,
+}
+
+// implemented by hand because the normal derives are too strict
+impl<'input, B> Clone for RawLine<'input, B>
+where
+    B: Buffers<'input>,
+    Line<'input, B>: Clone,
+{
+    fn clone(&self) -> Self {
+        RawLine {
+            text: self.text,
+            ending: self.ending,
+            line: self.line,
+            parsed: self.parsed.clone(),
+        }
+    }
+}
+
+impl<'input, B> PartialEq for RawLine<'input, B>
+where
+    B: Buffers<'input>,
+    Line<'input, B>: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+            && self.ending == other.ending
+            && self.line == other.line
+            && self.parsed == other.parsed
+    }
+}
+
+impl<'input, B: Buffers<'input>> RawLine<'input, B> {
+    /// Did the parser understand everything on this line?
+    pub fn is_parsed(&self) -> bool { self.parsed.is_some() }
+}
+
+/// Forwards everything to the user's [`Callbacks`], remembering whether
+/// anything on the line was lost.
+struct Recorder<'a, C> {
+    callbacks: &'a mut C,
+    problems: bool,
+}
+
+impl<C: Callbacks> Callbacks for Recorder<'_, C> {
+    fn unknown_content(&mut self, text: &str, span: Span) {
+        self.problems = true;
+        self.callbacks.unknown_content(text, span);
+    }
+
+    fn gcode_buffer_overflowed(
+        &mut self,
+        mnemonic: Mnemonic,
+        major_number: u32,
+        minor_number: u32,
+        arguments: &[Word],
+        span: Span,
+    ) {
+        self.problems = true;
+        self.callbacks.gcode_buffer_overflowed(
+            mnemonic,
+            major_number,
+            minor_number,
+            arguments,
+            span,
+        );
+    }
+
+    fn gcode_argument_buffer_overflowed(
+        &mut self,
+        mnemonic: Mnemonic,
+        major_number: u32,
+        minor_number: u32,
+        argument: Word,
+    ) {
+        self.problems = true;
+        self.callbacks.gcode_argument_buffer_overflowed(
+            mnemonic,
+            major_number,
+            minor_number,
+            argument,
+        );
+    }
+
+    fn comment_buffer_overflow(&mut self, comment: Comment<'_>) {
+        self.problems = true;
+        self.callbacks.comment_buffer_overflow(comment);
+    }
+
+    fn unexpected_line_number(&mut self, line_number: f32, span: Span) {
+        self.problems = true;
+        self.callbacks.unexpected_line_number(line_number, span);
+    }
+
+    fn argument_without_a_command(
+        &mut self,
+        letter: char,
+        value: f32,
+        span: Span,
+    ) {
+        self.problems = true;
+        self.callbacks
+            .argument_without_a_command(letter, value, span);
+    }
+
+    fn number_without_a_letter(&mut self, value: &str, span: Span) {
+        self.problems = true;
+        self.callbacks.number_without_a_letter(value, span);
+    }
+
+    fn letter_without_a_number(&mut self, value: &str, span: Span) {
+        self.problems = true;
+        self.callbacks.letter_without_a_number(value, span);
+    }
+
+    fn partial_word(&mut self, recovered: Word, text: &str, span: Span) {
+        self.problems = true;
+        self.callbacks.partial_word(recovered, text, span);
+    }
+
+    fn line_buffer_overflowed(&mut self, line: usize) {
+        self.problems = true;
+        self.callbacks.line_buffer_overflowed(line);
+    }
+
+    fn evaluate_expression(
+        &mut self,
+        expression: &str,
+        span: Span,
+    ) -> Option<f32> {
+        self.callbacks.evaluate_expression(expression, span)
+    }
+
+    fn parameter_assignment(&mut self, assignment: &str, span: Span) {
+        // assignments aren't part of a Line, so they'd be lost
+        self.problems = true;
+        self.callbacks.parameter_assignment(assignment, span);
+    }
+
+    fn checksum_mismatch(&mut self, written: u32, calculated: u8, span: Span) {
+        self.problems = true;
+        self.callbacks.checksum_mismatch(written, calculated, span);
+    }
+
+    fn line_number_out_of_sequence(
+        &mut self,
+        expected: u32,
+        found: u32,
+        span: Span,
+    ) {
+        self.problems = true;
+        self.callbacks
+            .line_number_out_of_sequence(expected, found, span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommandKey;
+    use arrayvec::ArrayVec;
+
+    fn statuses(src: &str) -> ArrayVec<[(&str, bool); 8]> {
+        RawLines::<_>::new(src, Nop)
+            .map(|raw| (raw.text, raw.is_parsed()))
+            .collect()
+    }
+
+    #[test]
+    fn unknown_lines_are_kept_verbatim() {
+        let got = statuses("G90\n\n(note)\n%weird\nG1 X5");
+
+        assert_eq!(
+            got.as_slice(),
+            &[
+                ("G90", true),
+                ("", true),
+                ("(note)", true),
+                ("%weird", false),
+                ("G1 X5", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn arguments_carry_over_to_the_next_line() {
+        let src = "G1 X1\r\nY2\r\n";
+
+        let got: ArrayVec<[_; 4]> = RawLines::<_>::new(src, Nop).collect();
+
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[1].ending, "\r\n");
+        let line = got[1].parsed.as_ref().unwrap();
+        assert_eq!(line.gcodes()[0].key(), CommandKey::general(1));
+        assert_eq!(line.span(), Span::new(7, 9, 1));
+    }
+}
@@ -0,0 +1,570 @@
+//! Comparing the time estimator against a captured serial session.
+//!
+//! Hosts which log every line they send and receive already have a record
+//! of how long each command really took. [`replay()`] runs the commands
+//! from such a log through an [`Analyzer`] and lines each estimate up with
+//! the measured time, then totals them by command so systematic errors
+//! stand out (e.g. `M109` always taking far longer than predicted because
+//! the estimator can't know how long heating takes).
+//!
+//! Logs are plain text, with one timestamp (in seconds), a direction (`>`
+//! for lines sent to the machine, `<` for lines received from it) and the
+//! line's text on each line.
+//!
+//! ```rust
+//! use gcode::{
+//!     analysis::Analyzer,
+//!     dialect::Dialect,
+//!     replay::{self, Bias, ReplayConfig},
+//!     CommandKey,
+//! };
+//!
+//! let log = "\
+//! 0.00 > G1 X10 F600
+//! 1.05 < ok
+//! 1.05 > M109 S210
+//! 1.10 < T:25.0 /210.0
+//! 31.00 < ok
+//! 31.00 > G1 X20
+//! 32.10 < ok";
+//!
+//! let entries = replay::parse_log(log).unwrap();
+//! let analyzer = Analyzer::new(Dialect::reprap());
+//! let report =
+//!     replay::replay(&analyzer, &entries, &ReplayConfig::default()).unwrap();
+//!
+//! let g1 = report.class(CommandKey::general(1)).unwrap();
+//! assert_eq!(g1.count, 2);
+//! assert_eq!(g1.bias(0.1), Bias::Accurate);
+//!
+//! // the estimator doesn't know how long it takes to heat up
+//! let flagged: Vec<_> = report.flagged().map(|class| class.key).collect();
+//! assert_eq!(flagged, vec![CommandKey::miscellaneous(109)]);
+//! ```
+
+use crate::{analysis::Analyzer, CommandKey, Nop, Parser};
+use core::fmt::{self, Display, Formatter};
+use std::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// Which way a line in a session log went.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Direction {
+    /// From the host to the machine (written as `>`).
+    Sent,
+    /// From the machine to the host (written as `<`).
+    Received,
+}
+
+/// A single line from a session log.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LogEntry<'log> {
+    /// The (zero-based) line in the log.
+    pub line: usize,
+    /// When the line was sent or received, in seconds.
+    ///
+    /// This is an `f64` because sessions can run for many hours, which is
+    /// long enough for an `f32` to lose the millisecond precision short
+    /// moves need.
+    pub time: f64,
+    /// Which way the line went.
+    pub direction: Direction,
+    /// The line's text, without the timestamp or direction.
+    pub text: &'log str,
+}
+
+impl<'log> LogEntry<'log> {
+    /// Is this the machine acknowledging a command?
+    pub fn is_ack(&self) -> bool {
+        self.direction == Direction::Received && self.text.starts_with("ok")
+    }
+}
+
+/// Settings for [`replay()`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct ReplayConfig {
+    /// How many commands the firmware acknowledges before it starts running
+    /// them (i.e. the size of its planner buffer), or `0` if it only replies
+    /// once a command has finished.
+    ///
+    /// With a full buffer, the acknowledgement for one command arrives as
+    /// the command this many places before it finishes.
+    pub buffered: usize,
+    /// How far (as a fraction of the measured time) a command's total
+    /// estimate can be out before [`Replay::flagged()`] reports it.
+    pub tolerance: f32,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> ReplayConfig {
+        ReplayConfig {
+            buffered: 0,
+            tolerance: 0.1,
+        }
+    }
+}
+
+/// Reasons a session log couldn't be replayed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// A line didn't start with a timestamp.
+    BadTimestamp {
+        /// The (zero-based) line in the log.
+        line: usize,
+    },
+    /// A line wasn't marked as sent (`>`) or received (`<`).
+    UnknownDirection {
+        /// The (zero-based) line in the log.
+        line: usize,
+    },
+    /// The machine acknowledged a command which was never sent.
+    UnexpectedAck {
+        /// The (zero-based) line in the log.
+        line: usize,
+    },
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::BadTimestamp { line } => {
+                write!(f, "line {} doesn't start with a timestamp", line + 1)
+            },
+            ReplayError::UnknownDirection { line } => write!(
+                f,
+                "line {} isn't marked as sent (>) or received (<)",
+                line + 1
+            ),
+            ReplayError::UnexpectedAck { line } => write!(
+                f,
+                "line {} acknowledges a command which was never sent",
+                line + 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Read a session log, skipping blank lines.
+pub fn parse_log(log: &str) -> Result<Vec<LogEntry<'_>>, ReplayError> {
+    let mut entries = Vec::new();
+
+    for (line, text) in log.lines().enumerate() {
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        let (time, rest) = split_word(text);
+        let time = time
+            .parse()
+            .map_err(|_| ReplayError::BadTimestamp { line })?;
+        let (direction, rest) = split_word(rest);
+        let direction = match direction {
+            ">" => Direction::Sent,
+            "<" => Direction::Received,
+            _ => return Err(ReplayError::UnknownDirection { line }),
+        };
+
+        entries.push(LogEntry {
+            line,
+            time,
+            direction,
+            text: rest,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// A command from the log, with its estimated and measured durations.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct ReplayedCommand {
+    /// The (zero-based) line in the log the command was sent on.
+    pub line: usize,
+    /// The command's text.
+    pub text: String,
+    /// The first command on the line, if there is one.
+    pub key: Option<CommandKey>,
+    /// When the command was sent, in seconds.
+    pub sent_at: f64,
+    /// How long the estimator expected the command to take, in seconds.
+    pub predicted: f32,
+    /// How long the command took, in seconds, or `None` if the log ended
+    /// before it was acknowledged.
+    pub actual: Option<f32>,
+}
+
+/// How the estimator compares with reality.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Bias {
+    /// The commands took longer than predicted.
+    Under,
+    /// The estimates were within tolerance.
+    Accurate,
+    /// The commands finished sooner than predicted.
+    Over,
+}
+
+/// The totals for every measured command with the same [`CommandKey`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct ClassSummary {
+    /// The command.
+    pub key: CommandKey,
+    /// The number of times it was measured.
+    pub count: usize,
+    /// The total estimated time, in seconds.
+    pub predicted: f32,
+    /// The total measured time, in seconds.
+    pub actual: f32,
+}
+
+impl ClassSummary {
+    /// How far out the estimate was, as a fraction of the measured time.
+    ///
+    /// This is negative when the commands took longer than predicted.
+    pub fn relative_error(&self) -> f32 {
+        if self.actual > 0.0 {
+            (self.predicted - self.actual) / self.actual
+        } else if self.predicted > 0.0 {
+            f32::INFINITY
+        } else {
+            0.0
+        }
+    }
+
+    /// Was the estimate too low, too high, or within `tolerance`?
+    pub fn bias(&self, tolerance: f32) -> Bias {
+        let error = self.relative_error();
+
+        if error < -tolerance {
+            Bias::Under
+        } else if error > tolerance {
+            Bias::Over
+        } else {
+            Bias::Accurate
+        }
+    }
+}
+
+impl Display for ClassSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} commands, predicted {:.2}s, took {:.2}s",
+            self.key, self.count, self.predicted, self.actual
+        )?;
+
+        let error = self.relative_error();
+        if error.is_finite() {
+            let percent = libm::fabsf(error) * 100.0;
+            let word = if error < 0.0 { "under" } else { "over" };
+            write!(f, " ({:.0}% {})", percent, word)
+        } else {
+            write!(f, " (expected to take no time)")
+        }
+    }
+}
+
+/// The result of [`replay()`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Replay {
+    commands: Vec<ReplayedCommand>,
+    classes: Vec<ClassSummary>,
+    tolerance: f32,
+}
+
+impl Replay {
+    /// Every command which was sent, in order.
+    pub fn commands(&self) -> &[ReplayedCommand] { &self.commands }
+
+    /// The totals for each kind of command, ordered by [`CommandKey`].
+    ///
+    /// Commands which were never acknowledged aren't included.
+    pub fn classes(&self) -> &[ClassSummary] { &self.classes }
+
+    /// The totals for a particular kind of command.
+    pub fn class(&self, key: CommandKey) -> Option<&ClassSummary> {
+        self.classes
+            .binary_search_by_key(&key, |class| class.key)
+            .ok()
+            .map(|index| &self.classes[index])
+    }
+
+    /// Every kind of command whose estimate was further out than the
+    /// [`ReplayConfig::tolerance`].
+    pub fn flagged(&self) -> impl Iterator<Item = &ClassSummary> + '_ {
+        let tolerance = self.tolerance;
+        self.classes
+            .iter()
+            .filter(move |class| class.bias(tolerance) != Bias::Accurate)
+    }
+
+    /// The estimated time for every measured command, in seconds.
+    pub fn total_predicted(&self) -> f32 {
+        self.measured().map(|(predicted, _)| predicted).sum()
+    }
+
+    /// The measured time for every measured command, in seconds.
+    pub fn total_actual(&self) -> f32 {
+        self.measured().map(|(_, actual)| actual).sum()
+    }
+
+    fn measured(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        self.commands
+            .iter()
+            .filter_map(|command| Some((command.predicted, command.actual?)))
+    }
+}
+
+impl Display for Replay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "predicted {:.2}s, took {:.2}s",
+            self.total_predicted(),
+            self.total_actual()
+        )?;
+
+        for class in &self.classes {
+            let marker = match class.bias(self.tolerance) {
+                Bias::Accurate => ' ',
+                _ => '!',
+            };
+            writeln!(f, "{} {}", marker, class)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Run the commands sent in a session log through the [`Analyzer`] and
+/// compare each estimate with how long the command really took.
+///
+/// Replies are matched with commands in the order they were sent, and
+/// anything received which doesn't start with `ok` (temperature reports,
+/// `echo:` messages, etc.) is ignored. A command is considered to start
+/// once it has been sent and the command before it has finished, and to
+/// finish when the acknowledgement [`ReplayConfig::buffered`] commands
+/// later arrives.
+pub fn replay(
+    analyzer: &Analyzer,
+    log: &[LogEntry<'_>],
+    config: &ReplayConfig,
+) -> Result<Replay, ReplayError> {
+    let sent: Vec<&LogEntry<'_>> = log
+        .iter()
+        .filter(|entry| entry.direction == Direction::Sent)
+        .collect();
+    let mut acks = Vec::new();
+
+    for entry in log.iter().filter(|entry| entry.is_ack()) {
+        // a reply can't acknowledge a command which hasn't been sent yet
+        let waiting = sent
+            .iter()
+            .take_while(|command| command.time <= entry.time)
+            .count();
+        if acks.len() >= waiting {
+            return Err(ReplayError::UnexpectedAck { line: entry.line });
+        }
+        acks.push(entry.time);
+    }
+
+    let src = sent
+        .iter()
+        .map(|entry| entry.text)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let analysis = analyzer.analyze(&src);
+
+    let mut predicted = vec![0.0; sent.len()];
+    for segment in analysis.segments() {
+        predicted[segment.span.line] += segment.duration;
+    }
+
+    let mut keys = vec![None; sent.len()];
+    for line in Parser::<_>::new_with_dialect(&src, Nop, *analyzer.dialect()) {
+        if let Some(gcode) = line.gcodes().first() {
+            keys[line.span().line] = Some(gcode.key());
+        }
+    }
+
+    let mut commands = Vec::with_capacity(sent.len());
+    let mut previous_end: Option<f64> = None;
+
+    for (i, entry) in sent.iter().enumerate() {
+        let end = acks.get(i + config.buffered).copied();
+        let start = previous_end.map_or(entry.time, |end| end.max(entry.time));
+        previous_end = end;
+
+        commands.push(ReplayedCommand {
+            line: entry.line,
+            text: entry.text.to_string(),
+            key: keys[i],
+            sent_at: entry.time,
+            predicted: predicted[i],
+            actual: end.map(|end| (end - start).max(0.0) as f32),
+        });
+    }
+
+    Ok(Replay {
+        classes: summarise(&commands),
+        commands,
+        tolerance: config.tolerance,
+    })
+}
+
+fn summarise(commands: &[ReplayedCommand]) -> Vec<ClassSummary> {
+    let mut classes: Vec<ClassSummary> = Vec::new();
+
+    for command in commands {
+        let (key, actual) = match (command.key, command.actual) {
+            (Some(key), Some(actual)) => (key, actual),
+            _ => continue,
+        };
+
+        let index = match classes.binary_search_by_key(&key, |class| class.key)
+        {
+            Ok(index) => index,
+            Err(index) => {
+                classes.insert(
+                    index,
+                    ClassSummary {
+                        key,
+                        count: 0,
+                        predicted: 0.0,
+                        actual: 0.0,
+                    },
+                );
+                index
+            },
+        };
+
+        let class = &mut classes[index];
+        class.count += 1;
+        class.predicted += command.predicted;
+        class.actual += actual;
+    }
+
+    classes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+
+    fn analyzer() -> Analyzer { Analyzer::new(Dialect::reprap()) }
+
+    #[test]
+    fn slow_moves_are_under_estimated() {
+        let log = "\
+0 > G1 X10 F600
+2 < ok
+2 > G1 X20
+4 < ok";
+        let entries = parse_log(log).unwrap();
+
+        let got =
+            replay(&analyzer(), &entries, &ReplayConfig::default()).unwrap();
+
+        let g1 = got.class(CommandKey::general(1)).unwrap();
+        assert_eq!(g1.count, 2);
+        assert!((g1.relative_error() + 0.5).abs() < 1e-6);
+        assert_eq!(g1.bias(0.1), Bias::Under);
+        assert_eq!(got.flagged().count(), 1);
+        assert_eq!(
+            g1.to_string(),
+            "G1: 2 commands, predicted 2.00s, took 4.00s (50% under)"
+        );
+    }
+
+    #[test]
+    fn buffered_acks_belong_to_earlier_commands() {
+        // the firmware replies as soon as a move is queued, so once its
+        // two-move buffer is full each reply means the move two places
+        // earlier has finished
+        let log = "\
+0 > G1 X10 F600
+0 < ok
+0 > G1 X20
+0 < ok
+0 > G1 X30
+1 < ok
+1 > G1 X40
+2 < ok
+2 > M400
+4 < ok";
+        let entries = parse_log(log).unwrap();
+        let config = ReplayConfig {
+            buffered: 2,
+            ..Default::default()
+        };
+
+        let got = replay(&analyzer(), &entries, &config).unwrap();
+
+        let actual: Vec<_> = got
+            .commands()
+            .iter()
+            .map(|command| command.actual)
+            .collect();
+        assert_eq!(actual, vec![Some(1.0), Some(1.0), Some(2.0), None, None]);
+        assert_eq!(got.class(CommandKey::miscellaneous(400)), None);
+        assert_eq!(got.flagged().count(), 1);
+        assert!((got.total_predicted() - 3.0).abs() < 1e-6);
+        assert!((got.total_actual() - 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn malformed_logs_are_rejected() {
+        assert_eq!(
+            parse_log("0 > G28\n\nnow > G1 X1").unwrap_err(),
+            ReplayError::BadTimestamp { line: 2 }
+        );
+        assert_eq!(
+            parse_log("0.5 -> G28").unwrap_err(),
+            ReplayError::UnknownDirection { line: 0 }
+        );
+
+        let entries = parse_log("0 < ok\n1 > G28").unwrap();
+        assert_eq!(
+            replay(&analyzer(), &entries, &ReplayConfig::default()),
+            Err(ReplayError::UnexpectedAck { line: 0 })
+        );
+    }
+}
@@ -0,0 +1,502 @@
+//! How often a toolpath changes direction.
+//!
+//! Zig-zag infill, tight perimeters and other back-and-forth moves shake
+//! the machine at a rate set by how quickly the direction reverses. When that
+//! rate lands near one of the machine's resonant frequencies the vibration
+//! shows up as ringing on the part, so people tuning input shaping want to
+//! know which parts of a program will excite it.
+//!
+//! A [`Resonance`] report splits a program's timeline into fixed windows,
+//! counts the reversals along the X and Y axes in each one, and estimates
+//! the dominant frequency from the time between them. Windows whose
+//! frequency is close to a configured resonance are flagged, and
+//! neighbouring flagged windows are merged into [`ResonantRegion`]s.
+//!
+//! ```rust
+//! use gcode::{
+//!     analysis::Analyzer,
+//!     dialect::Dialect,
+//!     resonance::{Resonance, ResonanceConfig},
+//! };
+//! use std::fmt::Write;
+//!
+//! // zig-zag back and forth along X, 10mm at a time, at 100mm/s
+//! let mut src = String::from("G1 F6000\n");
+//! for i in 0..40 {
+//!     let x = if i % 2 == 0 { 10 } else { 0 };
+//!     writeln!(src, "G1 X{} Y{}", x, i as f32 * 0.1).unwrap();
+//! }
+//!
+//! let analysis = Analyzer::new(Dialect::generic()).analyze(&src);
+//! let config = ResonanceConfig {
+//!     resonance_x: Some(5.0),
+//!     ..Default::default()
+//! };
+//! let report = Resonance::new(&analysis, &config);
+//!
+//! // one full back-and-forth every 0.2 seconds
+//! let frequency = report.dominant_frequency('X').unwrap();
+//! assert!((frequency - 5.0).abs() < 0.1);
+//! assert_eq!(report.regions().len(), 1);
+//! assert_eq!(report.regions()[0].axis, 'X');
+//! ```
+
+use crate::{
+    analysis::{Analysis, Segment, SegmentKind},
+    planner::Plan,
+    Span,
+};
+use std::vec::Vec;
+
+/// The axes which are checked for reversals.
+const AXES: [char; 2] = ['X', 'Y'];
+
+/// Settings for a [`Resonance`] report.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct ResonanceConfig {
+    /// The length of each window, in seconds.
+    pub window: f32,
+    /// The machine's resonant frequency along the X axis, in Hz.
+    pub resonance_x: Option<f32>,
+    /// The machine's resonant frequency along the Y axis, in Hz.
+    pub resonance_y: Option<f32>,
+    /// How close (as a fraction of the resonant frequency) a window's
+    /// frequency needs to be before it is flagged.
+    pub tolerance: f32,
+    /// The fewest reversals a window needs before a frequency is estimated
+    /// for it.
+    pub min_reversals: usize,
+    /// Ignore movement along an axis shorter than this, in millimeters, so
+    /// small wobbles in a mostly-straight path aren't counted as reversals.
+    pub min_travel: f32,
+}
+
+impl ResonanceConfig {
+    /// The resonant frequency configured for an axis.
+    pub fn resonance(&self, axis: char) -> Option<f32> {
+        match axis {
+            'X' | 'x' => self.resonance_x,
+            'Y' | 'y' => self.resonance_y,
+            _ => None,
+        }
+    }
+}
+
+impl Default for ResonanceConfig {
+    fn default() -> ResonanceConfig {
+        ResonanceConfig {
+            window: 1.0,
+            resonance_x: None,
+            resonance_y: None,
+            tolerance: 0.1,
+            min_reversals: 4,
+            min_travel: 0.01,
+        }
+    }
+}
+
+/// The direction changes along one axis during a single window.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct FrequencyWindow {
+    /// The axis which was reversing.
+    pub axis: char,
+    /// When the window starts, in seconds since the start of the program.
+    pub start_time: f32,
+    /// When the window ends, in seconds since the start of the program.
+    pub end_time: f32,
+    /// How many times the axis changed direction.
+    pub reversals: usize,
+    /// The dominant back-and-forth frequency, in Hz.
+    pub frequency: f32,
+    /// The resonant frequency this window is likely to excite, if any.
+    pub excites: Option<f32>,
+    /// The lines which changed direction.
+    pub span: Span,
+}
+
+/// A stretch of the program which keeps shaking an axis near its resonant
+/// frequency.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct ResonantRegion {
+    /// The axis being excited.
+    pub axis: char,
+    /// The resonant frequency being excited, in Hz.
+    pub resonance: f32,
+    /// When the region starts, in seconds since the start of the program.
+    pub start_time: f32,
+    /// When the region ends, in seconds since the start of the program.
+    pub end_time: f32,
+    /// The average frequency over the region, in Hz.
+    pub frequency: f32,
+    /// The lines responsible.
+    pub span: Span,
+}
+
+/// The direction-change frequencies found in a program.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Resonance {
+    windows: Vec<FrequencyWindow>,
+    regions: Vec<ResonantRegion>,
+}
+
+impl Resonance {
+    /// Look for direction changes using the times from an [`Analysis`].
+    pub fn new(analysis: &Analysis, config: &ResonanceConfig) -> Self {
+        let timeline = analysis
+            .segments()
+            .iter()
+            .map(|segment| (segment.start_time, segment));
+
+        Resonance::from_timeline(timeline, config)
+    }
+
+    /// Look for direction changes using the times from a [`Plan`], which
+    /// accounts for the machine slowing down at every reversal.
+    ///
+    /// The plan must have been made from the same `analysis`.
+    pub fn with_plan(
+        analysis: &Analysis,
+        plan: &Plan,
+        config: &ResonanceConfig,
+    ) -> Self {
+        let mut time = 0.0;
+        let timeline = analysis.segments().iter().zip(plan.segments()).map(
+            |(segment, planned)| {
+                let start_time = time;
+                time += planned.duration;
+                (start_time, segment)
+            },
+        );
+
+        Resonance::from_timeline(timeline, config)
+    }
+
+    fn from_timeline<'a, I>(timeline: I, config: &ResonanceConfig) -> Self
+    where
+        I: Iterator<Item = (f32, &'a Segment)>,
+    {
+        let reversals = find_reversals(timeline, config.min_travel);
+        let mut windows = Vec::new();
+
+        for (axis, reversals) in AXES.iter().zip(&reversals) {
+            windows.extend(frequency_windows(*axis, reversals, config));
+        }
+
+        windows.sort_by(|a, b| {
+            a.start_time
+                .partial_cmp(&b.start_time)
+                .unwrap_or(core::cmp::Ordering::Equal)
+                .then(a.axis.cmp(&b.axis))
+        });
+        let regions = resonant_regions(&windows, config.window);
+
+        Resonance { windows, regions }
+    }
+
+    /// Every window with enough reversals to estimate a frequency, in
+    /// order.
+    pub fn windows(&self) -> &[FrequencyWindow] { &self.windows }
+
+    /// The windows likely to excite a resonance.
+    pub fn flagged(&self) -> impl Iterator<Item = &FrequencyWindow> + '_ {
+        self.windows.iter().filter(|w| w.excites.is_some())
+    }
+
+    /// Consecutive flagged windows, merged together.
+    pub fn regions(&self) -> &[ResonantRegion] { &self.regions }
+
+    /// The frequency an axis spends the most time reversing at, in Hz.
+    ///
+    /// Each window's frequency is weighted by its number of reversals.
+    pub fn dominant_frequency(&self, axis: char) -> Option<f32> {
+        let axis = axis.to_ascii_uppercase();
+        let (total, weight) = self
+            .windows
+            .iter()
+            .filter(|w| w.axis == axis)
+            .fold((0.0, 0), |(total, weight), w| {
+                (
+                    total + w.frequency * w.reversals as f32,
+                    weight + w.reversals,
+                )
+            });
+
+        if weight == 0 {
+            None
+        } else {
+            Some(total / weight as f32)
+        }
+    }
+}
+
+/// A change of direction along an axis.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Reversal {
+    time: f32,
+    span: Span,
+}
+
+/// Find where each axis in [`AXES`] changes direction.
+fn find_reversals<'a, I>(timeline: I, min_travel: f32) -> [Vec<Reversal>; 2]
+where
+    I: Iterator<Item = (f32, &'a Segment)>,
+{
+    let mut reversals = [Vec::new(), Vec::new()];
+    let mut directions = [None; 2];
+
+    for (start_time, segment) in timeline {
+        let motion = match segment.kind {
+            SegmentKind::Motion(ref motion) => motion,
+            SegmentKind::Dwell { .. } => {
+                // the machine stops, so there's nothing to shake
+                directions = [None; 2];
+                continue;
+            },
+        };
+        let deltas =
+            [motion.end.x - motion.start.x, motion.end.y - motion.start.y];
+
+        for (axis, &delta) in deltas.iter().enumerate() {
+            if delta.abs() < min_travel {
+                continue;
+            }
+
+            let positive = delta > 0.0;
+            if directions[axis].is_some_and(|previous| previous != positive) {
+                reversals[axis].push(Reversal {
+                    time: start_time,
+                    span: segment.span,
+                });
+            }
+            directions[axis] = Some(positive);
+        }
+    }
+
+    reversals
+}
+
+/// Estimate the frequency of an axis' reversals in each window.
+fn frequency_windows(
+    axis: char,
+    reversals: &[Reversal],
+    config: &ResonanceConfig,
+) -> Vec<FrequencyWindow> {
+    let window = config.window.max(f32::EPSILON);
+    let mut windows = Vec::new();
+    let mut remaining = reversals;
+
+    while let Some(first) = remaining.first() {
+        let index = libm::floorf(first.time / window);
+        let start_time = index * window;
+        let end_time = start_time + window;
+        let count = remaining
+            .iter()
+            .take_while(|r| r.time < end_time)
+            .count()
+            .max(1);
+        let (inside, rest) = remaining.split_at(count);
+        remaining = rest;
+
+        if inside.len() < config.min_reversals.max(2) {
+            continue;
+        }
+
+        let mut intervals: Vec<f32> = inside
+            .windows(2)
+            .map(|pair| pair[1].time - pair[0].time)
+            .collect();
+        let interval = median(&mut intervals);
+        if interval <= 0.0 {
+            continue;
+        }
+
+        // a reversal happens twice per cycle
+        let frequency = 1.0 / (2.0 * interval);
+        let excites = config.resonance(axis).filter(|&resonance| {
+            resonance > 0.0
+                && libm::fabsf(frequency - resonance)
+                    <= resonance * config.tolerance
+        });
+        let span = inside[1..]
+            .iter()
+            .fold(inside[0].span, |span, r| span.merge(r.span));
+
+        windows.push(FrequencyWindow {
+            axis,
+            start_time,
+            end_time,
+            reversals: inside.len(),
+            frequency,
+            excites,
+            span,
+        });
+    }
+
+    windows
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values
+        .sort_by(|a, b| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Equal));
+    let middle = values.len() / 2;
+
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+/// Merge flagged windows which follow on from each other.
+fn resonant_regions(
+    windows: &[FrequencyWindow],
+    window_length: f32,
+) -> Vec<ResonantRegion> {
+    let mut regions: Vec<ResonantRegion> = Vec::new();
+    // how many windows went into each region, for averaging
+    let mut counts: Vec<usize> = Vec::new();
+
+    for window in windows {
+        let resonance = match window.excites {
+            Some(resonance) => resonance,
+            None => continue,
+        };
+
+        let existing = regions.iter().rposition(|region| {
+            region.axis == window.axis
+                && region.resonance == resonance
+                && window.start_time - region.end_time < window_length * 0.5
+        });
+
+        match existing {
+            Some(index) => {
+                let region = &mut regions[index];
+                region.end_time = window.end_time;
+                region.frequency += window.frequency;
+                region.span = region.span.merge(window.span);
+                counts[index] += 1;
+            },
+            None => {
+                regions.push(ResonantRegion {
+                    axis: window.axis,
+                    resonance,
+                    start_time: window.start_time,
+                    end_time: window.end_time,
+                    frequency: window.frequency,
+                    span: window.span,
+                });
+                counts.push(1);
+            },
+        }
+    }
+
+    for (region, count) in regions.iter_mut().zip(counts) {
+        region.frequency /= count as f32;
+    }
+
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analysis::Analyzer,
+        dialect::Dialect,
+        planner::{Plan, PlannerConfig},
+    };
+    use std::{fmt::Write, string::String};
+
+    fn zig_zag(passes: usize, length: f32, feed_rate: f32) -> String {
+        let mut src = String::new();
+        writeln!(src, "G1 F{}", feed_rate).unwrap();
+
+        for i in 0..passes {
+            let x = if i % 2 == 0 { length } else { 0.0 };
+            writeln!(src, "G1 X{} Y{}", x, i as f32 * 0.1).unwrap();
+        }
+
+        src
+    }
+
+    #[test]
+    fn straight_lines_have_no_reversals() {
+        let src = "G1 X10 F6000\nY10\nX20\nY20\nX30";
+        let analysis = Analyzer::new(Dialect::generic()).analyze(src);
+
+        let report = Resonance::new(&analysis, &ResonanceConfig::default());
+
+        assert!(report.windows().is_empty());
+        assert_eq!(report.dominant_frequency('X'), None);
+    }
+
+    #[test]
+    fn frequency_away_from_the_resonance_is_not_flagged() {
+        // 2mm passes at 100mm/s reverse every 0.02s, about 25Hz
+        let src = zig_zag(200, 2.0, 6000.0);
+        let analysis = Analyzer::new(Dialect::generic()).analyze(&src);
+        let config = ResonanceConfig {
+            resonance_x: Some(60.0),
+            ..Default::default()
+        };
+
+        let report = Resonance::new(&analysis, &config);
+
+        let frequency = report.dominant_frequency('X').unwrap();
+        assert!((frequency - 25.0).abs() < 1.0, "{}", frequency);
+        assert_eq!(report.flagged().count(), 0);
+        assert!(report.regions().is_empty());
+    }
+
+    #[test]
+    fn planned_times_lower_the_frequency() {
+        let src = zig_zag(200, 2.0, 6000.0);
+        let analyzer = Analyzer::new(Dialect::generic());
+        let analysis = analyzer.analyze(&src);
+        let plan =
+            Plan::new(&analysis, analyzer.config(), &PlannerConfig::default());
+        let config = ResonanceConfig::default();
+
+        let naive = Resonance::new(&analysis, &config);
+        let planned = Resonance::with_plan(&analysis, &plan, &config);
+
+        // stopping at every reversal takes time
+        assert!(
+            planned.dominant_frequency('X').unwrap()
+                < naive.dominant_frequency('X').unwrap()
+        );
+    }
+
+    #[test]
+    fn dwells_break_up_a_zig_zag() {
+        let src = "G1 X1 F600\nX0\nG4 P1\nX1\nG4 P1\nX0";
+        let analysis = Analyzer::new(Dialect::generic()).analyze(src);
+        let config = ResonanceConfig {
+            min_reversals: 1,
+            ..Default::default()
+        };
+
+        let report = Resonance::new(&analysis, &config);
+
+        // only the first pair of moves reverses without stopping, and a
+        // single reversal isn't enough to estimate a frequency from
+        assert!(report.windows().is_empty());
+    }
+}
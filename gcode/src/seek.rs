@@ -0,0 +1,317 @@
+//! Starting to interpret a program part-way through.
+//!
+//! Previewers often want to jump straight to somewhere in the middle of a
+//! huge file (e.g. layer 200), but the [`MachineState`] at that point depends
+//! on everything which came before it. A [`SeekIndex`] is built with a single
+//! pass over the file, recording checkpoints of the modal state every so
+//! often. Afterwards, seeking to any byte offset only needs to replay the
+//! lines since the nearest checkpoint.
+//!
+//! ```rust
+//! use gcode::{dialect::Dialect, interpret::Units, seek::SeekIndex};
+//!
+//! let src = "G20\nG1 X1 F10\nX2\nX3\nG21\nX4\n";
+//! let index = SeekIndex::with_interval(src, Dialect::generic(), 8);
+//!
+//! // start interpreting from the "X3" line
+//! let offset = src.find("X3").unwrap();
+//! let mut seek = index.seek(src, offset);
+//!
+//! assert_eq!(seek.interpreter().state().units, Units::Inches);
+//!
+//! let (line, motion) = seek.next().unwrap();
+//! assert_eq!(line.span().line, 3);
+//! assert_eq!(motion.unwrap().end.x, 3.0 * 25.4);
+//! ```
+
+use crate::{
+    dialect::Dialect,
+    interpret::{Interpreter, MachineState, Motion},
+    Line, Nop, Parser, Word,
+};
+use std::vec::Vec;
+
+/// The default number of bytes between [`Checkpoint`]s.
+pub const DEFAULT_CHECKPOINT_INTERVAL: usize = 16 * 1024;
+
+/// A snapshot of everything needed to resume interpreting a program.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Checkpoint {
+    /// The byte offset of the first line after this checkpoint.
+    pub offset: usize,
+    /// The (zero-based) line number that line is on.
+    pub line: usize,
+    /// The modal state before that line is interpreted.
+    pub state: MachineState,
+    /// The most recent command word, for lines which only contain arguments
+    /// (e.g. `X5` after `G1 X1`).
+    pub last_command: Option<Word>,
+}
+
+/// An index of [`Checkpoint`]s which allows random access into a program.
+///
+/// The index is only valid for the text it was built from.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct SeekIndex {
+    dialect: Dialect,
+    initial_state: MachineState,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl SeekIndex {
+    /// Index a program, recording a [`Checkpoint`] roughly every
+    /// [`DEFAULT_CHECKPOINT_INTERVAL`] bytes.
+    pub fn new(src: &str, dialect: Dialect) -> Self {
+        SeekIndex::with_interval(src, dialect, DEFAULT_CHECKPOINT_INTERVAL)
+    }
+
+    /// Index a program, recording a [`Checkpoint`] roughly every `interval`
+    /// bytes.
+    pub fn with_interval(src: &str, dialect: Dialect, interval: usize) -> Self {
+        SeekIndex::build(src, Interpreter::new(dialect), interval)
+    }
+
+    /// Index a program which starts from a known [`MachineState`].
+    pub fn with_initial_state(
+        src: &str,
+        dialect: Dialect,
+        initial_state: MachineState,
+        interval: usize,
+    ) -> Self {
+        SeekIndex::build(
+            src,
+            Interpreter::with_state(dialect, initial_state),
+            interval,
+        )
+    }
+
+    fn build(src: &str, mut interpreter: Interpreter, interval: usize) -> Self {
+        let interval = interval.max(1);
+        let dialect = *interpreter.dialect();
+        let initial_state = *interpreter.state();
+        let mut checkpoints = Vec::new();
+        let mut next_checkpoint = interval;
+        let mut parser: Parser<'_, Nop> =
+            Parser::new_with_dialect(src, Nop, dialect);
+
+        loop {
+            let last_command = parser.last_command();
+            let line = match parser.next() {
+                Some(line) => line,
+                None => break,
+            };
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let span = line.span();
+            if span.start >= next_checkpoint {
+                checkpoints.push(Checkpoint {
+                    offset: span.start,
+                    line: span.line,
+                    state: *interpreter.state(),
+                    last_command,
+                });
+                next_checkpoint = span.start + interval;
+            }
+
+            let _ = interpreter.process_line(&line);
+        }
+
+        SeekIndex {
+            dialect,
+            initial_state,
+            checkpoints,
+        }
+    }
+
+    /// The [`Dialect`] the program was interpreted with.
+    pub fn dialect(&self) -> &Dialect { &self.dialect }
+
+    /// The [`MachineState`] at the very start of the program.
+    pub fn initial_state(&self) -> &MachineState { &self.initial_state }
+
+    /// Every [`Checkpoint`], in order.
+    pub fn checkpoints(&self) -> &[Checkpoint] { &self.checkpoints }
+
+    /// The last [`Checkpoint`] at or before a byte offset, if there is one.
+    pub fn checkpoint_before(&self, offset: usize) -> Option<&Checkpoint> {
+        let index = self
+            .checkpoints
+            .partition_point(|checkpoint| checkpoint.offset <= offset);
+
+        index.checked_sub(1).map(|i| &self.checkpoints[i])
+    }
+
+    /// Start interpreting `src` from the line containing a byte offset.
+    ///
+    /// The lines between the nearest [`Checkpoint`] and `offset` are replayed
+    /// to reconstruct the [`MachineState`], then the [`Seek`] iterator yields
+    /// each line from there onwards along with the [`Motion`] it caused.
+    ///
+    /// `src` must be the same text the index was built from.
+    pub fn seek<'input>(
+        &self,
+        src: &'input str,
+        offset: usize,
+    ) -> Seek<'input> {
+        let (mut interpreter, mut parser) = match self.checkpoint_before(offset)
+        {
+            Some(checkpoint) => (
+                Interpreter::with_state(self.dialect, checkpoint.state),
+                Parser::resume(
+                    src,
+                    Nop,
+                    self.dialect,
+                    checkpoint.offset,
+                    checkpoint.line,
+                    checkpoint.last_command,
+                ),
+            ),
+            None => (
+                Interpreter::with_state(self.dialect, self.initial_state),
+                Parser::new_with_dialect(src, Nop, self.dialect),
+            ),
+        };
+
+        let mut pending = None;
+
+        for line in &mut parser {
+            if line.is_empty() {
+                continue;
+            }
+            if line.span().end > offset {
+                pending = Some(line);
+                break;
+            }
+
+            let _ = interpreter.process_line(&line);
+        }
+
+        Seek {
+            parser,
+            interpreter,
+            pending,
+        }
+    }
+}
+
+/// An iterator which interprets a program from part-way through, created by
+/// [`SeekIndex::seek()`].
+#[derive(Debug)]
+pub struct Seek<'input> {
+    parser: Parser<'input, Nop>,
+    interpreter: Interpreter,
+    pending: Option<Line<'input>>,
+}
+
+impl<'input> Seek<'input> {
+    /// The [`Interpreter`], which has processed every line yielded so far.
+    pub fn interpreter(&self) -> &Interpreter { &self.interpreter }
+}
+
+impl<'input> Iterator for Seek<'input> {
+    type Item = (Line<'input>, Option<Motion>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.pending.take().or_else(|| self.parser.next())?;
+        let motion = self.interpreter.process_line(&line);
+
+        Some((line, motion))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpret::Position;
+
+    const SRC: &str = "G21 G90\nG1 X1 F100\nX2 Y2\n(comment)\nG91\nX1\nX1\n\n\
+                       G90 G0 Z5\nG1 X0\n";
+
+    fn states_from_scratch(src: &str) -> Vec<(usize, MachineState)> {
+        let mut interpreter = Interpreter::new(Dialect::generic());
+        crate::full_parse_with_callbacks(src, Nop)
+            .map(|line| {
+                let state = *interpreter.state();
+                let _ = interpreter.process_line(&line);
+                (line.span().start, state)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seeking_anywhere_matches_interpreting_from_the_start() {
+        for interval in &[1, 5, 20, 1000] {
+            let index =
+                SeekIndex::with_interval(SRC, Dialect::generic(), *interval);
+
+            for (offset, state) in states_from_scratch(SRC) {
+                let seek = index.seek(SRC, offset);
+
+                assert_eq!(
+                    seek.interpreter().state(),
+                    &state,
+                    "offset {} with an interval of {}",
+                    offset,
+                    interval
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn arguments_without_a_command_use_the_checkpointed_command() {
+        let index = SeekIndex::with_interval(SRC, Dialect::generic(), 1);
+        let offset = SRC.find("X1\nX1").unwrap();
+
+        let checkpoint = index.checkpoint_before(offset).unwrap();
+        assert_eq!(checkpoint.offset, offset);
+        assert_eq!(checkpoint.last_command.unwrap().letter, 'G');
+
+        let motions: Vec<_> = index
+            .seek(SRC, offset)
+            .filter_map(|(_, motion)| motion)
+            .map(|motion| motion.end)
+            .collect();
+
+        assert_eq!(
+            motions,
+            vec![
+                Position::new(3.0, 2.0, 0.0),
+                Position::new(4.0, 2.0, 0.0),
+                Position::new(4.0, 2.0, 5.0),
+                Position::new(0.0, 2.0, 5.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn seeking_into_the_middle_of_a_line_starts_at_that_line() {
+        let index = SeekIndex::new(SRC, Dialect::generic());
+        let offset = SRC.find("Y2").unwrap();
+
+        let (line, _) = index.seek(SRC, offset).next().unwrap();
+
+        assert_eq!(line.span().line, 2);
+    }
+
+    #[test]
+    fn checkpoints_are_spaced_out() {
+        let index = SeekIndex::with_interval(SRC, Dialect::generic(), 20);
+        let offsets: Vec<_> =
+            index.checkpoints().iter().map(|c| c.offset).collect();
+
+        assert_eq!(offsets, vec![25, 46]);
+        assert!(index.checkpoint_before(10).is_none());
+    }
+}
@@ -0,0 +1,329 @@
+//! A cache of everything a viewer needs to open a big file instantly.
+//!
+//! Analysing a multi-gigabyte program takes a while, so viewers which open
+//! the same file repeatedly can save an [`AnalysisIndex`] next to it and
+//! reload that instead. The index records the file's hash, so a stale index
+//! is detected rather than silently used.
+//!
+//! ```rust
+//! use gcode::{analysis::Analyzer, dialect::Dialect, sidecar::AnalysisIndex};
+//!
+//! let src = "G1 Z0.2 F600\nG1 X10\nG1 Z0.4\nG1 X0";
+//! let index = AnalysisIndex::build(src, &Analyzer::new(Dialect::reprap()));
+//!
+//! assert_eq!(index.layers().len(), 2);
+//! assert_eq!(index.time_at_line(2), 1.02);
+//! assert!(index.is_valid_for(src));
+//! assert!(!index.is_valid_for("G1 X5"));
+//! ```
+//!
+//! With the `sidecar` feature enabled, an index can be saved in a compact
+//! binary format with `AnalysisIndex::save()` and reloaded with
+//! `AnalysisIndex::load()`.
+
+use crate::{
+    analysis::{Analyzer, BoundingBox, Event},
+    seek::{SeekIndex, DEFAULT_CHECKPOINT_INTERVAL},
+};
+use std::vec::Vec;
+
+/// The version of the on-disk format, bumped whenever it changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Precomputed information about a program, which can be saved alongside
+/// it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct AnalysisIndex {
+    source_hash: u64,
+    seek: SeekIndex,
+    layers: Vec<Event>,
+    line_times: Vec<f32>,
+    bounding_box: Option<BoundingBox>,
+    total_time: f32,
+}
+
+impl AnalysisIndex {
+    /// Analyse a program and index the results.
+    pub fn build(src: &str, analyzer: &Analyzer) -> Self {
+        let analysis = analyzer.analyze(src);
+        let seek = SeekIndex::with_initial_state(
+            src,
+            *analyzer.dialect(),
+            *analyzer.initial_state(),
+            DEFAULT_CHECKPOINT_INTERVAL,
+        );
+        let line_times = (0..src.lines().count())
+            .map(|line| analysis.time_at_line(line))
+            .collect();
+
+        AnalysisIndex {
+            source_hash: hash(src),
+            seek,
+            layers: analysis.layer_changes(),
+            line_times,
+            bounding_box: analysis.bounding_box(),
+            total_time: analysis.total_time(),
+        }
+    }
+
+    /// Was this index built from `src`?
+    pub fn is_valid_for(&self, src: &str) -> bool {
+        self.source_hash == hash(src)
+    }
+
+    /// A hash of the text the index was built from.
+    pub fn source_hash(&self) -> u64 { self.source_hash }
+
+    /// Checkpoints for jumping into the middle of the program.
+    pub fn seek_index(&self) -> &SeekIndex { &self.seek }
+
+    /// When each layer starts (see
+    /// [`Analysis::layer_changes()`][crate::analysis::Analysis::layer_changes]).
+    pub fn layers(&self) -> &[Event] { &self.layers }
+
+    /// When each line starts executing, in seconds since the start of the
+    /// program.
+    pub fn line_times(&self) -> &[f32] { &self.line_times }
+
+    /// When a particular (zero-based) line starts executing, in seconds since
+    /// the start of the program.
+    pub fn time_at_line(&self, line: usize) -> f32 {
+        self.line_times
+            .get(line)
+            .copied()
+            .unwrap_or(self.total_time)
+    }
+
+    /// The smallest box containing every position the machine visits.
+    pub fn bounding_box(&self) -> Option<BoundingBox> { self.bounding_box }
+
+    /// The estimated time taken to run the entire program, in seconds.
+    pub fn total_time(&self) -> f32 { self.total_time }
+}
+
+/// A 64-bit FNV-1a hash, which (unlike the standard library's hashers) is
+/// guaranteed to stay the same between releases.
+fn hash(src: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    src.bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    })
+}
+
+#[cfg(feature = "sidecar")]
+mod persistence {
+    use super::*;
+    use bincode::Options;
+    use core::fmt::{self, Display, Formatter};
+    use std::{
+        ffi::OsString,
+        fs, io,
+        path::{Path, PathBuf},
+    };
+
+    /// The bytes every index file starts with.
+    const MAGIC: &[u8; 4] = b"GCIX";
+
+    impl AnalysisIndex {
+        /// Encode the index in the compact on-disk format.
+        pub fn to_bytes(&self) -> Vec<u8> {
+            let mut bytes = Vec::new();
+            bytes.extend_from_slice(MAGIC);
+            bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+            bincode::serialize_into(&mut bytes, self)
+                .expect("serializing to a Vec can't fail");
+
+            bytes
+        }
+
+        /// Decode an index, checking it was built from `src`.
+        pub fn from_bytes(bytes: &[u8], src: &str) -> Result<Self, IndexError> {
+            let rest = bytes
+                .strip_prefix(MAGIC.as_ref())
+                .ok_or(IndexError::NotAnIndex)?;
+            if rest.len() < 4 {
+                return Err(IndexError::NotAnIndex);
+            }
+            let (version, payload) = rest.split_at(4);
+            let version = u32::from_le_bytes([
+                version[0], version[1], version[2], version[3],
+            ]);
+
+            if version != FORMAT_VERSION {
+                return Err(IndexError::UnsupportedVersion(version));
+            }
+
+            // a damaged length can't make us allocate more than the file
+            // actually contains
+            let index: AnalysisIndex = bincode::options()
+                .with_fixint_encoding()
+                .allow_trailing_bytes()
+                .with_limit(payload.len() as u64)
+                .deserialize(payload)
+                .map_err(IndexError::Corrupt)?;
+
+            if index.is_valid_for(src) {
+                Ok(index)
+            } else {
+                Err(IndexError::Stale)
+            }
+        }
+
+        /// Save the index next to the file it was built from (see
+        /// [`sidecar_path()`]).
+        pub fn save<P: AsRef<Path>>(&self, gcode_file: P) -> io::Result<()> {
+            fs::write(sidecar_path(gcode_file), self.to_bytes())
+        }
+
+        /// Load the index saved next to a file, checking it is still valid
+        /// for the file's current contents.
+        pub fn load<P: AsRef<Path>>(
+            gcode_file: P,
+            src: &str,
+        ) -> Result<Self, IndexError> {
+            let bytes =
+                fs::read(sidecar_path(gcode_file)).map_err(IndexError::Io)?;
+
+            AnalysisIndex::from_bytes(&bytes, src)
+        }
+    }
+
+    /// Where the index for a file is saved (`part.gcode` is indexed in
+    /// `part.gcode.idx`).
+    pub fn sidecar_path<P: AsRef<Path>>(gcode_file: P) -> PathBuf {
+        let mut path = OsString::from(gcode_file.as_ref());
+        path.push(".idx");
+        PathBuf::from(path)
+    }
+
+    /// Reasons an [`AnalysisIndex`] couldn't be loaded.
+    #[derive(Debug)]
+    pub enum IndexError {
+        /// The index couldn't be read.
+        Io(io::Error),
+        /// The data doesn't start like an index file.
+        NotAnIndex,
+        /// The index was saved in a different version of the format.
+        UnsupportedVersion(u32),
+        /// The index is damaged.
+        Corrupt(bincode::Error),
+        /// The file has changed since the index was built.
+        Stale,
+    }
+
+    impl Display for IndexError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            match self {
+                IndexError::Io(e) => {
+                    write!(f, "unable to read the index: {}", e)
+                },
+                IndexError::NotAnIndex => write!(f, "not an index file"),
+                IndexError::UnsupportedVersion(version) => {
+                    write!(f, "unsupported index format version {}", version)
+                },
+                IndexError::Corrupt(e) => write!(f, "corrupt index: {}", e),
+                IndexError::Stale => {
+                    write!(f, "the file has changed since it was indexed")
+                },
+            }
+        }
+    }
+
+    impl std::error::Error for IndexError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                IndexError::Io(e) => Some(e),
+                IndexError::Corrupt(e) => Some(e),
+                _ => None,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "sidecar")]
+pub use self::persistence::{sidecar_path, IndexError};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+
+    const SRC: &str = "G1 Z0.2 F600\nG1 X10\nG1 Z0.4\nG1 X0 Y-5";
+
+    fn index() -> AnalysisIndex {
+        AnalysisIndex::build(SRC, &Analyzer::new(Dialect::reprap()))
+    }
+
+    #[test]
+    fn index_a_program() {
+        let got = index();
+
+        assert_eq!(got.line_times(), &[0.0, 0.02, 1.02, 1.04]);
+        assert_eq!(got.time_at_line(100), got.total_time());
+        let bounds = got.bounding_box().unwrap();
+        assert_eq!(bounds.min.y, -5.0);
+        assert_eq!(bounds.max.x, 10.0);
+    }
+
+    #[test]
+    fn hashes_are_stable() {
+        assert_eq!(hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[cfg(feature = "sidecar")]
+    #[test]
+    fn round_trip_through_bytes() {
+        let original = index();
+        let bytes = original.to_bytes();
+
+        assert_eq!(&bytes[..4], b"GCIX");
+        assert_eq!(AnalysisIndex::from_bytes(&bytes, SRC).unwrap(), original);
+    }
+
+    #[cfg(feature = "sidecar")]
+    #[test]
+    fn bad_indices_are_rejected() {
+        let mut bytes = index().to_bytes();
+
+        assert!(matches!(
+            AnalysisIndex::from_bytes(&bytes, "G1 X1"),
+            Err(IndexError::Stale)
+        ));
+        assert!(matches!(
+            AnalysisIndex::from_bytes(b"nope", SRC),
+            Err(IndexError::NotAnIndex)
+        ));
+
+        // claim there are far more layers than could fit in the file
+        let seek = bincode::serialized_size(index().seek_index()).unwrap();
+        let layers = 16 + seek as usize;
+        let mut damaged = bytes.clone();
+        damaged[layers..layers + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            AnalysisIndex::from_bytes(&damaged, SRC),
+            Err(IndexError::Corrupt(_))
+        ));
+
+        bytes[4] = 99;
+        assert!(matches!(
+            AnalysisIndex::from_bytes(&bytes, SRC),
+            Err(IndexError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[cfg(feature = "sidecar")]
+    #[test]
+    fn sidecar_files_live_next_to_the_program() {
+        assert_eq!(
+            sidecar_path("parts/bracket.gcode"),
+            std::path::PathBuf::from("parts/bracket.gcode.idx")
+        );
+    }
+}
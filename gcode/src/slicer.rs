@@ -0,0 +1,419 @@
+//! Reading and editing the settings slicers embed in their output.
+//!
+//! PrusaSlicer and the slicers derived from it (SuperSlicer, OrcaSlicer,
+//! Bambu Studio) finish every file with a block of `; key = value` comments
+//! recording the statistics of the print and the full configuration used to
+//! slice it. [`SlicerSettings`] reads that block into typed values, and can
+//! write it back after some of them have been changed.
+//!
+//! ```rust
+//! use gcode::slicer::{SettingValue, SlicerSettings};
+//!
+//! let src = "\
+//! G1 X10 E1
+//! M84
+//!
+//! ; filament used [mm] = 1234.56
+//! ; prusaslicer_config = begin
+//! ; fill_density = 15%
+//! ; layer_height = 0.2
+//! ; printer_model = MK4
+//! ; prusaslicer_config = end
+//! ";
+//!
+//! let mut settings = SlicerSettings::parse(src).unwrap();
+//!
+//! assert_eq!(settings.number("layer_height"), Some(0.2));
+//! assert_eq!(settings.percent("fill_density"), Some(15.0));
+//! assert_eq!(settings.text("printer_model"), Some("MK4"));
+//!
+//! let _ = settings.set("fill_density", SettingValue::Percent(20.0));
+//! let _ = settings.set("ironing", SettingValue::Bool(true));
+//!
+//! assert_eq!(
+//!     settings.write_back(src),
+//!     "\
+//! G1 X10 E1
+//! M84
+//!
+//! ; filament used [mm] = 1234.56
+//! ; prusaslicer_config = begin
+//! ; fill_density = 20%
+//! ; layer_height = 0.2
+//! ; printer_model = MK4
+//! ; ironing = 1
+//! ; prusaslicer_config = end
+//! "
+//! );
+//! ```
+//!
+//! Cura stores its settings differently (as JSON in `;SETTING_3` comments),
+//! so they aren't picked up.
+
+use core::fmt::{self, Display, Formatter};
+use std::{string::String, vec::Vec};
+
+/// A single value from a [`SlicerSettings`] block.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum SettingValue {
+    /// A number (e.g. `0.2`).
+    Number(f64),
+    /// A percentage (e.g. `15%`), without the percent sign.
+    Percent(f64),
+    /// `true` or `false`. Slicers usually write these as `1` and `0`, which
+    /// are read as a [`SettingValue::Number`].
+    Bool(bool),
+    /// Anything else, including lists (`0.4,0.4`) and empty values.
+    Text(String),
+}
+
+impl SettingValue {
+    /// Work out what type of value some text is.
+    pub fn parse(text: &str) -> SettingValue {
+        let text = text.trim();
+
+        if text.eq_ignore_ascii_case("true") {
+            SettingValue::Bool(true)
+        } else if text.eq_ignore_ascii_case("false") {
+            SettingValue::Bool(false)
+        } else if let Some(percent) = text.strip_suffix('%').and_then(number) {
+            SettingValue::Percent(percent)
+        } else if let Some(value) = number(text) {
+            SettingValue::Number(value)
+        } else {
+            SettingValue::Text(String::from(text))
+        }
+    }
+}
+
+/// Parse a plain decimal number, without accepting things like `inf` which
+/// Rust would.
+fn number(text: &str) -> Option<f64> {
+    let plain = !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e'))
+        && text.contains(|c: char| c.is_ascii_digit());
+
+    if plain {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+impl Display for SettingValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingValue::Number(n) => write!(f, "{}", n),
+            SettingValue::Percent(n) => write!(f, "{}%", n),
+            SettingValue::Bool(b) => write!(f, "{}", u8::from(*b)),
+            SettingValue::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl From<f64> for SettingValue {
+    fn from(other: f64) -> SettingValue { SettingValue::Number(other) }
+}
+
+impl From<bool> for SettingValue {
+    fn from(other: bool) -> SettingValue { SettingValue::Bool(other) }
+}
+
+impl From<&str> for SettingValue {
+    fn from(other: &str) -> SettingValue {
+        SettingValue::Text(String::from(other))
+    }
+}
+
+/// The `; key = value` settings block at the end of a sliced file.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct SlicerSettings {
+    first_line: usize,
+    lines: Vec<BlockLine>,
+}
+
+/// A line in the settings block.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+enum BlockLine {
+    Setting {
+        key: String,
+        value: SettingValue,
+        /// The line as it was written, until the value is changed.
+        original: Option<String>,
+    },
+    /// A blank line or a comment which isn't a setting.
+    Other(String),
+}
+
+impl SlicerSettings {
+    /// Find the settings block at the end of a program, if it has one.
+    ///
+    /// The block is the run of comments and blank lines at the very end of
+    /// the file, and must contain at least one `; key = value` comment.
+    pub fn parse(src: &str) -> Option<SlicerSettings> {
+        let lines: Vec<&str> = src.lines().collect();
+        let first_line = lines
+            .iter()
+            .rposition(|line| {
+                let line = line.trim();
+                !line.is_empty() && !line.starts_with(';')
+            })
+            .map_or(0, |last_command| last_command + 1);
+
+        let block: Vec<BlockLine> = lines[first_line..]
+            .iter()
+            .map(|&line| match split_setting(line) {
+                Some((key, value)) => BlockLine::Setting {
+                    key: String::from(key),
+                    value: SettingValue::parse(value),
+                    original: Some(String::from(line)),
+                },
+                None => BlockLine::Other(String::from(line)),
+            })
+            .collect();
+
+        if block
+            .iter()
+            .any(|line| matches!(line, BlockLine::Setting { .. }))
+        {
+            Some(SlicerSettings {
+                first_line,
+                lines: block,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The (zero-based) line the block starts on.
+    pub fn first_line(&self) -> usize { self.first_line }
+
+    /// Every setting, in the order they were written.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &SettingValue)> + '_ {
+        self.lines.iter().filter_map(|line| match line {
+            BlockLine::Setting { key, value, .. } => {
+                Some((key.as_str(), value))
+            },
+            BlockLine::Other(_) => None,
+        })
+    }
+
+    /// The number of settings.
+    pub fn len(&self) -> usize { self.iter().count() }
+
+    /// Are there no settings? (Always `false` for a parsed block.)
+    pub fn is_empty(&self) -> bool { self.iter().next().is_none() }
+
+    /// Look up a setting.
+    pub fn get(&self, key: &str) -> Option<&SettingValue> {
+        self.iter().find(|&(k, _)| k == key).map(|(_, value)| value)
+    }
+
+    /// Get a [`SettingValue::Number`].
+    pub fn number(&self, key: &str) -> Option<f64> {
+        match self.get(key)? {
+            SettingValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Get a [`SettingValue::Percent`], without the percent sign.
+    pub fn percent(&self, key: &str) -> Option<f64> {
+        match self.get(key)? {
+            SettingValue::Percent(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Get a [`SettingValue::Bool`], or a number which is `0` or `1`.
+    pub fn boolean(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            SettingValue::Bool(b) => Some(*b),
+            SettingValue::Number(n) if *n == 0.0 => Some(false),
+            SettingValue::Number(n) if *n == 1.0 => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Get a [`SettingValue::Text`].
+    pub fn text(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            SettingValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Change a setting, returning its previous value.
+    ///
+    /// New settings are added at the end of the block, or just before a
+    /// `; ..._config = end` marker if there is one.
+    pub fn set<K, V>(&mut self, key: K, value: V) -> Option<SettingValue>
+    where
+        K: Into<String>,
+        V: Into<SettingValue>,
+    {
+        let key = key.into();
+        let value = value.into();
+
+        for line in &mut self.lines {
+            if let BlockLine::Setting {
+                key: k,
+                value: v,
+                original,
+            } = line
+            {
+                if *k == key {
+                    *original = None;
+                    return Some(core::mem::replace(v, value));
+                }
+            }
+        }
+
+        let index = self
+            .lines
+            .iter()
+            .position(is_end_marker)
+            .unwrap_or(self.lines.len());
+        self.lines.insert(
+            index,
+            BlockLine::Setting {
+                key,
+                value,
+                original: None,
+            },
+        );
+
+        None
+    }
+
+    /// Replace the settings block at the end of `src` (which should be the
+    /// text this block was parsed from) with the current settings.
+    ///
+    /// Everything before the block, and any lines in the block which
+    /// haven't changed, are copied across untouched.
+    pub fn write_back(&self, src: &str) -> String {
+        let line_ending = if src.contains("\r\n") { "\r\n" } else { "\n" };
+        let start = src
+            .split_inclusive('\n')
+            .take(self.first_line)
+            .map(str::len)
+            .sum();
+
+        let mut out = String::from(&src[..start]);
+
+        for line in &self.lines {
+            match line {
+                BlockLine::Setting {
+                    original: Some(text),
+                    ..
+                }
+                | BlockLine::Other(text) => out.push_str(text),
+                BlockLine::Setting { key, value, .. } => {
+                    out.push_str(&format!("; {} = {}", key, value))
+                },
+            }
+            out.push_str(line_ending);
+        }
+
+        out
+    }
+}
+
+/// Split a `; key = value` comment into its key and value.
+fn split_setting(line: &str) -> Option<(&str, &str)> {
+    let comment = line.trim().strip_prefix(';')?;
+    let (key, value) = comment.split_once('=')?;
+    let key = key.trim();
+
+    let is_key_char = |c: char| {
+        c.is_ascii_alphanumeric()
+            || matches!(
+                c,
+                '_' | '-' | ' ' | '[' | ']' | '(' | ')' | '.' | '/' | '%'
+            )
+    };
+
+    if key.is_empty() || !key.chars().all(is_key_char) {
+        return None;
+    }
+
+    Some((key, value.trim()))
+}
+
+fn is_end_marker(line: &BlockLine) -> bool {
+    match line {
+        BlockLine::Setting { key, value, .. } => {
+            key.ends_with("_config")
+                && *value == SettingValue::Text(String::from("end"))
+        },
+        BlockLine::Other(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_get_the_right_types() {
+        let cases = [
+            ("0.2", SettingValue::Number(0.2)),
+            ("-1", SettingValue::Number(-1.0)),
+            ("15%", SettingValue::Percent(15.0)),
+            ("true", SettingValue::Bool(true)),
+            ("0.4,0.4", SettingValue::Text("0.4,0.4".into())),
+            ("1h 2m 3s", SettingValue::Text("1h 2m 3s".into())),
+            ("inf", SettingValue::Text("inf".into())),
+            ("", SettingValue::Text(String::new())),
+        ];
+
+        for (text, should_be) in cases.iter() {
+            assert_eq!(&SettingValue::parse(text), should_be, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn the_block_only_covers_trailing_comments() {
+        let src = "; generated by PrusaSlicer\n; layer_height = 0.3\nG1 \
+                   X1\n;TYPE:Perimeter\n\n; layer_height = 0.2\n; \
+                   notes = \n";
+
+        let settings = SlicerSettings::parse(src).unwrap();
+
+        assert_eq!(settings.first_line(), 3);
+        assert_eq!(settings.number("layer_height"), Some(0.2));
+        assert_eq!(settings.text("notes"), Some(""));
+        assert_eq!(settings.len(), 2);
+        assert!(SlicerSettings::parse("G1 X1\n; done\n").is_none());
+    }
+
+    #[test]
+    fn writing_back_keeps_untouched_lines_and_line_endings() {
+        let src = "G1 X1\r\n; temperature = 215 \r\n;SETTING_3 {\"a\": \
+                   \"b = c\"}\r\n; bridge_fan_speed = 100";
+        let mut settings = SlicerSettings::parse(src).unwrap();
+
+        assert_eq!(settings.boolean("temperature"), None);
+        assert_eq!(settings.set("bridge_fan_speed", 80.0), Some(100.0.into()));
+
+        assert_eq!(
+            settings.write_back(src),
+            "G1 X1\r\n; temperature = 215 \r\n;SETTING_3 {\"a\": \"b = \
+             c\"}\r\n; bridge_fan_speed = 80\r\n"
+        );
+    }
+}
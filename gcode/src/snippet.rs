@@ -0,0 +1,770 @@
+//! Checking user-provided start and end G-code.
+//!
+//! Slicers let users paste their own G-code into a profile to run before and
+//! after every print, usually with `{placeholders}` for things like the
+//! first layer's temperature. A small mistake there (extruding before the
+//! hotend is hot, a misspelled placeholder, an `M502` which quietly resets
+//! the printer's settings) affects every print made with the profile, so
+//! profile editors want to catch them as soon as the snippet is typed in.
+//!
+//! A [`SnippetPolicy`] describes what a snippet is allowed to do, and
+//! [`SnippetPolicy::verify()`] explains everything which breaks it.
+//!
+//! ```rust
+//! use gcode::{
+//!     dialect::Dialect,
+//!     snippet::{ProblemKind, SnippetPolicy},
+//! };
+//!
+//! let start = "\
+//! G28 ; home
+//! M104 S{first_layer_temperature}
+//! G1 X5 Y5 E10 F600 ; prime line
+//! ";
+//! let mut policy = SnippetPolicy::start_gcode();
+//! policy.known_placeholders.push("first_layer_temperature".to_string());
+//!
+//! let problems = policy.verify(start, &Dialect::reprap());
+//!
+//! assert_eq!(problems.len(), 1);
+//! assert_eq!(problems[0].line, Some(2));
+//! assert_eq!(
+//!     problems[0].kind,
+//!     ProblemKind::ExtrudeBeforeHeating { started: true },
+//! );
+//! assert_eq!(
+//!     problems[0].to_string(),
+//!     "line 3: extrudes without waiting for the hotend to heat up (use M109 \
+//!      instead of M104, or add M109 before extruding)",
+//! );
+//! ```
+
+use crate::{
+    dialect::Dialect, pipeline::Severity, transform::Extruder, Callbacks,
+    CommandKey, GCode, Mnemonic, Parser, Span,
+};
+use core::fmt::{self, Display, Formatter};
+use std::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// Placeholder "names" which are really part of a slicer's template
+/// language.
+const PLACEHOLDER_KEYWORDS: &[&str] = &["if", "elsif", "else", "endif"];
+
+/// What a snippet may contain.
+///
+/// The default policy allows anything, as long as its placeholders are
+/// well-formed.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct SnippetPolicy {
+    /// The only commands a snippet may use (e.g. `"G28"` or `"M104"`). An
+    /// empty list allows every command which isn't forbidden.
+    pub allowed_commands: Vec<String>,
+    /// Commands a snippet must never use.
+    pub forbidden_commands: Vec<String>,
+    /// Sequencing requirements.
+    pub rules: Vec<Rule>,
+    /// The characters a placeholder starts and ends with, or `None` if the
+    /// snippet doesn't use placeholders.
+    pub placeholder_delimiters: Option<(char, char)>,
+    /// The placeholders the slicer knows how to fill in. An empty list
+    /// accepts any name.
+    pub known_placeholders: Vec<String>,
+}
+
+impl SnippetPolicy {
+    /// A policy for start G-code, which must home and heat up before
+    /// moving and extruding, and mustn't change the printer's stored
+    /// settings.
+    pub fn start_gcode() -> Self {
+        SnippetPolicy {
+            forbidden_commands: settings_commands(),
+            rules: vec![Rule::HomeBeforeMove, Rule::HeatBeforeExtrude],
+            ..Default::default()
+        }
+    }
+
+    /// A policy for end G-code, which should turn the heaters off and
+    /// mustn't change the printer's stored settings.
+    pub fn end_gcode() -> Self {
+        SnippetPolicy {
+            forbidden_commands: settings_commands(),
+            rules: vec![
+                Rule::Contains("M104".to_string()),
+                Rule::Contains("M140".to_string()),
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// Check a snippet against this policy, returning every problem found
+    /// in the order they appear.
+    pub fn verify(&self, src: &str, dialect: &Dialect) -> Vec<Problem> {
+        let mut problems = Vec::new();
+
+        let text = match self.placeholder_delimiters {
+            Some(delimiters) => {
+                let (text, placeholders) =
+                    substitute_placeholders(src, delimiters, &mut problems);
+                self.check_placeholder_names(&placeholders, &mut problems);
+                text
+            },
+            None => src.to_string(),
+        };
+
+        let mut unparsed = Unparsed::default();
+        let lines: Vec<_> =
+            Parser::<_>::new_with_dialect(&text, &mut unparsed, *dialect)
+                .collect();
+
+        for span in unparsed.spans {
+            let text = span.get_text(src).unwrap_or_default().trim();
+            problems
+                .push(Problem::at(span, ProblemKind::Unparsed(text.into())));
+        }
+
+        let mut checker = Checker::new(self);
+        for gcode in lines.iter().flat_map(|line| line.gcodes()) {
+            checker.process(gcode, &mut problems);
+        }
+        checker.finish(&mut problems);
+
+        problems.sort_by_key(|p| p.line.unwrap_or(usize::MAX));
+        problems
+    }
+
+    fn check_placeholder_names(
+        &self,
+        placeholders: &[(String, Span)],
+        problems: &mut Vec<Problem>,
+    ) {
+        for (contents, span) in placeholders {
+            let name: String = contents
+                .trim()
+                .chars()
+                .take_while(|&c| c.is_ascii_alphanumeric() || c == '_')
+                .collect();
+
+            let kind = if name.is_empty() {
+                ProblemKind::EmptyPlaceholder
+            } else if self.known_placeholders.is_empty()
+                || PLACEHOLDER_KEYWORDS.contains(&name.as_str())
+                || self.known_placeholders.contains(&name)
+            {
+                continue;
+            } else {
+                ProblemKind::UnknownPlaceholder(name)
+            };
+
+            problems.push(Problem::at(*span, kind));
+        }
+    }
+}
+
+impl Default for SnippetPolicy {
+    fn default() -> SnippetPolicy {
+        SnippetPolicy {
+            allowed_commands: Vec::new(),
+            forbidden_commands: Vec::new(),
+            rules: Vec::new(),
+            placeholder_delimiters: Some(('{', '}')),
+            known_placeholders: Vec::new(),
+        }
+    }
+}
+
+/// Commands which overwrite the settings stored in a printer's EEPROM.
+fn settings_commands() -> Vec<String> {
+    ["M500", "M502"].iter().map(|c| c.to_string()).collect()
+}
+
+/// Something which must happen before something else.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Rule {
+    /// The snippet must wait for the hotend to heat up (`M109`) before
+    /// extruding.
+    HeatBeforeExtrude,
+    /// The snippet must home the machine (`G28`) before moving.
+    HomeBeforeMove,
+    /// The first command may only be used after the second.
+    Requires {
+        /// The command being checked.
+        command: String,
+        /// The command which must come first.
+        prerequisite: String,
+    },
+    /// The snippet must use this command somewhere.
+    Contains(String),
+}
+
+/// Something in a snippet which breaks its [`SnippetPolicy`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Problem {
+    /// The (zero-based) line the problem was found on, if it is about a
+    /// particular line.
+    pub line: Option<usize>,
+    /// Where the problem is in the snippet.
+    pub span: Option<Span>,
+    /// What the problem is.
+    pub kind: ProblemKind,
+}
+
+impl Problem {
+    fn at(span: Span, kind: ProblemKind) -> Self {
+        Problem {
+            line: Some(span.line),
+            span: Some(span),
+            kind,
+        }
+    }
+
+    /// How serious the problem is.
+    pub fn severity(&self) -> Severity { self.kind.severity() }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line + 1, self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+/// The different kinds of [`Problem`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum ProblemKind {
+    /// Text which isn't valid G-code.
+    Unparsed(String),
+    /// A placeholder which is never closed, or a closing delimiter without
+    /// an opening one.
+    UnbalancedPlaceholder,
+    /// A placeholder with nothing in it.
+    EmptyPlaceholder,
+    /// A placeholder the slicer doesn't know about.
+    UnknownPlaceholder(String),
+    /// A command which is on the forbidden list.
+    ForbiddenCommand(String),
+    /// A command which isn't on the allowed list.
+    CommandNotAllowed(String),
+    /// Extruding before waiting for the hotend to heat up.
+    ExtrudeBeforeHeating {
+        /// Was the hotend told to start heating (`M104`) without waiting
+        /// for it?
+        started: bool,
+    },
+    /// Moving before the machine has been homed.
+    MoveBeforeHoming,
+    /// A command used before the command it depends on.
+    MissingPrerequisite {
+        /// The command which was used.
+        command: String,
+        /// The command which should have come first.
+        prerequisite: String,
+    },
+    /// A command the snippet should contain, but doesn't.
+    MissingCommand(String),
+}
+
+impl ProblemKind {
+    /// How serious this kind of problem is.
+    pub fn severity(&self) -> Severity {
+        match self {
+            ProblemKind::MoveBeforeHoming | ProblemKind::MissingCommand(_) => {
+                Severity::Warning
+            },
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl Display for ProblemKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProblemKind::Unparsed(text) => {
+                write!(f, "\"{}\" isn't valid G-code", text)
+            },
+            ProblemKind::UnbalancedPlaceholder => {
+                write!(f, "the placeholder's delimiters don't match up")
+            },
+            ProblemKind::EmptyPlaceholder => {
+                write!(f, "the placeholder is empty")
+            },
+            ProblemKind::UnknownPlaceholder(name) => {
+                write!(f, "\"{}\" isn't a known placeholder", name)
+            },
+            ProblemKind::ForbiddenCommand(command) => {
+                write!(f, "{} isn't allowed in this snippet", command)
+            },
+            ProblemKind::CommandNotAllowed(command) => {
+                write!(f, "{} isn't one of the allowed commands", command)
+            },
+            ProblemKind::ExtrudeBeforeHeating { started: true } => write!(
+                f,
+                "extrudes without waiting for the hotend to heat up (use \
+                 M109 instead of M104, or add M109 before extruding)"
+            ),
+            ProblemKind::ExtrudeBeforeHeating { started: false } => write!(
+                f,
+                "extrudes before the hotend is heated (add M109 before \
+                 extruding)"
+            ),
+            ProblemKind::MoveBeforeHoming => {
+                write!(f, "moves before the machine is homed (add G28 first)")
+            },
+            ProblemKind::MissingPrerequisite {
+                command,
+                prerequisite,
+            } => write!(f, "{} is used before {}", command, prerequisite),
+            ProblemKind::MissingCommand(command) => {
+                write!(f, "the snippet doesn't use {}", command)
+            },
+        }
+    }
+}
+
+/// Replace every placeholder with something the parser will accept,
+/// keeping every byte in the same place so spans still point into the
+/// original snippet.
+///
+/// A placeholder straight after a word's letter (e.g. `S{temperature}`)
+/// becomes a number, anything else becomes whitespace.
+fn substitute_placeholders(
+    src: &str,
+    (open, close): (char, char),
+    problems: &mut Vec<Problem>,
+) -> (String, Vec<(String, Span)>) {
+    let mut text = String::with_capacity(src.len());
+    let mut placeholders = Vec::new();
+    // the start of the current placeholder, and the character to fill it
+    // with
+    let mut current: Option<(usize, char)> = None;
+    let mut line = 0;
+
+    for (i, c) in src.char_indices() {
+        match current {
+            Some((start, fill)) if c == close => {
+                let contents = &src[start + open.len_utf8()..i];
+                let span = Span::new(start, i + c.len_utf8(), line);
+                placeholders.push((contents.to_string(), span));
+                push_filled(&mut text, fill, c);
+                current = None;
+                continue;
+            },
+            Some((start, _)) if c == open || c == '\n' => {
+                let span = Span::new(start, i, line);
+                problems.push(Problem::at(
+                    span,
+                    ProblemKind::UnbalancedPlaceholder,
+                ));
+                // give up on this placeholder, and treat the rest as normal
+                // text
+                current = None;
+                if c == '\n' {
+                    line += 1;
+                    text.push(c);
+                } else {
+                    let fill = fill_after(&text);
+                    current = Some((i, fill));
+                    push_filled(&mut text, fill, c);
+                }
+            },
+            Some((_, fill)) => push_filled(&mut text, fill, c),
+            None if c == open => {
+                let fill = fill_after(&text);
+                current = Some((i, fill));
+                push_filled(&mut text, fill, c);
+            },
+            None if c == close => {
+                let span = Span::new(i, i + c.len_utf8(), line);
+                problems.push(Problem::at(
+                    span,
+                    ProblemKind::UnbalancedPlaceholder,
+                ));
+                text.push(' ');
+                text.extend(core::iter::repeat_n(' ', c.len_utf8() - 1));
+            },
+            None => {
+                if c == '\n' {
+                    line += 1;
+                }
+                text.push(c);
+            },
+        }
+    }
+
+    if let Some((start, _)) = current {
+        let span = Span::new(start, src.len(), line);
+        problems.push(Problem::at(span, ProblemKind::UnbalancedPlaceholder));
+    }
+
+    (text, placeholders)
+}
+
+/// Use a number for placeholders which are a word's value, and whitespace
+/// for everything else.
+fn fill_after(text: &str) -> char {
+    if text.ends_with(|c: char| c.is_ascii_alphabetic()) {
+        '1'
+    } else {
+        ' '
+    }
+}
+
+fn push_filled(text: &mut String, fill: char, original: char) {
+    text.extend(core::iter::repeat_n(fill, original.len_utf8()));
+}
+
+/// [`Callbacks`] which remember where the parser skipped over something.
+#[derive(Debug, Default)]
+struct Unparsed {
+    spans: Vec<Span>,
+}
+
+impl Callbacks for Unparsed {
+    fn unknown_content(&mut self, _text: &str, span: Span) {
+        self.spans.push(span);
+    }
+
+    fn unexpected_line_number(&mut self, _line_number: f32, span: Span) {
+        self.spans.push(span);
+    }
+
+    fn argument_without_a_command(
+        &mut self,
+        _letter: char,
+        _value: f32,
+        span: Span,
+    ) {
+        self.spans.push(span);
+    }
+
+    fn number_without_a_letter(&mut self, _value: &str, span: Span) {
+        self.spans.push(span);
+    }
+
+    fn letter_without_a_number(&mut self, _value: &str, span: Span) {
+        self.spans.push(span);
+    }
+}
+
+/// Walks through a snippet's commands, checking them against a policy.
+struct Checker<'a> {
+    policy: &'a SnippetPolicy,
+    allowed: Vec<CommandKey>,
+    forbidden: Vec<CommandKey>,
+    seen: Vec<CommandKey>,
+    extruder: Extruder,
+    homed: bool,
+    heating: bool,
+    heated: bool,
+    reported_extrusion: bool,
+    reported_motion: bool,
+}
+
+impl<'a> Checker<'a> {
+    fn new(policy: &'a SnippetPolicy) -> Self {
+        Checker {
+            policy,
+            allowed: keys(&policy.allowed_commands),
+            forbidden: keys(&policy.forbidden_commands),
+            seen: Vec::new(),
+            extruder: Extruder::default(),
+            homed: false,
+            heating: false,
+            heated: false,
+            reported_extrusion: false,
+            reported_motion: false,
+        }
+    }
+
+    fn process(&mut self, gcode: &GCode, problems: &mut Vec<Problem>) {
+        let key = gcode.key();
+        let span = gcode.span();
+
+        if self.forbidden.contains(&key) {
+            problems.push(Problem::at(
+                span,
+                ProblemKind::ForbiddenCommand(key.to_string()),
+            ));
+        } else if !self.allowed.is_empty() && !self.allowed.contains(&key) {
+            problems.push(Problem::at(
+                span,
+                ProblemKind::CommandNotAllowed(key.to_string()),
+            ));
+        }
+
+        for rule in &self.policy.rules {
+            if let Some(kind) = self.check(rule, gcode) {
+                problems.push(Problem::at(span, kind));
+            }
+        }
+
+        self.update(gcode);
+        if !self.seen.contains(&key) {
+            self.seen.push(key);
+        }
+    }
+
+    fn check(&mut self, rule: &Rule, gcode: &GCode) -> Option<ProblemKind> {
+        match rule {
+            Rule::HeatBeforeExtrude => {
+                let before = self.extruder.position;
+                let mut after = self.extruder;
+                after.process(gcode);
+
+                if after.position > before
+                    && !self.heated
+                    && !self.reported_extrusion
+                {
+                    self.reported_extrusion = true;
+                    return Some(ProblemKind::ExtrudeBeforeHeating {
+                        started: self.heating,
+                    });
+                }
+            },
+            Rule::HomeBeforeMove => {
+                let moves = is_motion(gcode)
+                    && gcode.arguments().iter().any(|word| {
+                        matches!(
+                            word.letter.to_ascii_uppercase(),
+                            'X' | 'Y' | 'Z'
+                        )
+                    });
+
+                if moves && !self.homed && !self.reported_motion {
+                    self.reported_motion = true;
+                    return Some(ProblemKind::MoveBeforeHoming);
+                }
+            },
+            Rule::Requires {
+                command,
+                prerequisite,
+            } => {
+                if parse_key(command) == Some(gcode.key())
+                    && !self.has_seen(prerequisite)
+                {
+                    return Some(ProblemKind::MissingPrerequisite {
+                        command: normalize(command),
+                        prerequisite: normalize(prerequisite),
+                    });
+                }
+            },
+            Rule::Contains(_) => {},
+        }
+
+        None
+    }
+
+    fn update(&mut self, gcode: &GCode) {
+        self.extruder.process(gcode);
+
+        let target = gcode
+            .value_for('S')
+            .or_else(|| gcode.value_for('R'))
+            .unwrap_or(0.0);
+
+        match (gcode.mnemonic(), gcode.major_number()) {
+            (Mnemonic::General, 28) => self.homed = true,
+            (Mnemonic::Miscellaneous, 104) => self.heating = target > 0.0,
+            (Mnemonic::Miscellaneous, 109) => self.heated = target > 0.0,
+            _ => {},
+        }
+    }
+
+    fn finish(&self, problems: &mut Vec<Problem>) {
+        for rule in &self.policy.rules {
+            if let Rule::Contains(command) = rule {
+                if !self.has_seen(command) {
+                    problems.push(Problem {
+                        line: None,
+                        span: None,
+                        kind: ProblemKind::MissingCommand(normalize(command)),
+                    });
+                }
+            }
+        }
+    }
+
+    fn has_seen(&self, command: &str) -> bool {
+        parse_key(command).is_some_and(|key| self.seen.contains(&key))
+    }
+}
+
+fn is_motion(gcode: &GCode) -> bool {
+    gcode.mnemonic() == Mnemonic::General
+        && matches!(gcode.major_number(), 0..=3)
+        && gcode.minor_number() == 0
+}
+
+fn keys(commands: &[String]) -> Vec<CommandKey> {
+    commands.iter().filter_map(|c| parse_key(c)).collect()
+}
+
+fn parse_key(command: &str) -> Option<CommandKey> {
+    command.trim().parse().ok()
+}
+
+/// Write a command the same way [`CommandKey`]'s `Display` impl does, so
+/// `"g01"` and `"G1"` are reported the same way.
+fn normalize(command: &str) -> String {
+    match parse_key(command) {
+        Some(key) => key.to_string(),
+        None => command.trim().to_ascii_uppercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify(policy: &SnippetPolicy, src: &str) -> Vec<ProblemKind> {
+        policy
+            .verify(src, &Dialect::reprap())
+            .into_iter()
+            .map(|p| p.kind)
+            .collect()
+    }
+
+    #[test]
+    fn a_typical_start_script_is_fine() {
+        let src = "\
+M140 S{first_layer_bed_temperature[0]}
+M104 S{first_layer_temperature[0]}
+G28 ; home all axes
+M190 S{first_layer_bed_temperature[0]}
+M109 S{first_layer_temperature[0]}
+G92 E0
+G1 Z0.3 F3000
+G1 X100 E15 F1500 ; prime line
+";
+        let policy = SnippetPolicy::start_gcode();
+
+        assert_eq!(verify(&policy, src), Vec::new());
+    }
+
+    #[test]
+    fn placeholders_must_be_well_formed() {
+        let mut policy = SnippetPolicy::default();
+        policy.known_placeholders.push("temperature".to_string());
+
+        let got = policy.verify(
+            "M104 S{temperature\nM109 S{}\nM140 S{bed_temp}\n}",
+            &Dialect::reprap(),
+        );
+
+        let lines: Vec<_> = got.iter().map(|p| p.line.unwrap()).collect();
+        assert_eq!(lines, vec![0, 1, 2, 3]);
+        assert_eq!(got[0].kind, ProblemKind::UnbalancedPlaceholder);
+        assert_eq!(got[1].kind, ProblemKind::EmptyPlaceholder);
+        assert_eq!(
+            got[2].kind,
+            ProblemKind::UnknownPlaceholder("bed_temp".to_string())
+        );
+        assert_eq!(got[3].kind, ProblemKind::UnbalancedPlaceholder);
+    }
+
+    #[test]
+    fn template_conditionals_are_not_commands() {
+        let src = "{if is_pla}M106 S255{endif}\nG28";
+        let policy = SnippetPolicy {
+            known_placeholders: vec!["is_pla".to_string()],
+            ..SnippetPolicy::start_gcode()
+        };
+
+        assert_eq!(verify(&policy, src), Vec::new());
+    }
+
+    #[test]
+    fn allowed_and_forbidden_commands() {
+        let policy = SnippetPolicy {
+            allowed_commands: vec!["G28".into(), "g01".into(), "M500".into()],
+            forbidden_commands: vec!["M500".into()],
+            ..Default::default()
+        };
+
+        let got = verify(&policy, "G28\nG1 X10\nM500\nM84");
+
+        assert_eq!(
+            got,
+            vec![
+                ProblemKind::ForbiddenCommand("M500".to_string()),
+                ProblemKind::CommandNotAllowed("M84".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn sequencing_rules() {
+        let policy = SnippetPolicy {
+            rules: vec![
+                Rule::HomeBeforeMove,
+                Rule::HeatBeforeExtrude,
+                Rule::Requires {
+                    command: "G29".into(),
+                    prerequisite: "G28".into(),
+                },
+                Rule::Contains("M84".into()),
+            ],
+            ..Default::default()
+        };
+
+        let got = policy.verify("G1 X10 E5\nG29\nG28", &Dialect::reprap());
+
+        assert_eq!(
+            got.iter().map(|p| (p.line, &p.kind)).collect::<Vec<_>>(),
+            vec![
+                (Some(0), &ProblemKind::MoveBeforeHoming),
+                (
+                    Some(0),
+                    &ProblemKind::ExtrudeBeforeHeating { started: false }
+                ),
+                (
+                    Some(1),
+                    &ProblemKind::MissingPrerequisite {
+                        command: "G29".to_string(),
+                        prerequisite: "G28".to_string(),
+                    }
+                ),
+                (None, &ProblemKind::MissingCommand("M84".to_string())),
+            ]
+        );
+        assert_eq!(got[0].severity(), Severity::Warning);
+        assert_eq!(got[1].severity(), Severity::Error);
+    }
+
+    #[test]
+    fn retracting_is_not_extruding() {
+        let policy = SnippetPolicy::start_gcode();
+
+        let got = verify(&policy, "G28\nM83\nG1 E-2 F1800\nG1 X10 Y10");
+
+        assert_eq!(got, Vec::new());
+    }
+
+    #[test]
+    fn garbage_is_reported() {
+        let got = verify(&SnippetPolicy::default(), "G28\n%%% oops\n");
+
+        assert!(matches!(got[0], ProblemKind::Unparsed(_)), "{:?}", got);
+    }
+}
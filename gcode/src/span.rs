@@ -25,13 +25,74 @@ impl Span {
     /// A placeholder [`Span`] which will be ignored by [`Span::merge()`] and
     /// equality checks.
     pub const PLACEHOLDER: Span =
-        Span::new(usize::max_value(), usize::max_value(), usize::max_value());
+        Span::new(usize::MAX, usize::MAX, usize::MAX);
 
     /// Create a new [`Span`].
+    ///
+    /// This doesn't check that `start` comes before `end`, so prefer
+    /// [`Span::checked_new()`] or [`Span::saturating_new()`] when the
+    /// positions come from outside the parser.
     pub const fn new(start: usize, end: usize, line: usize) -> Self {
         Span { start, end, line }
     }
 
+    /// Create a new [`Span`], returning `None` if it would end before it
+    /// starts.
+    ///
+    /// ```rust
+    /// # use gcode::Span;
+    /// assert!(Span::checked_new(2, 5, 0).is_some());
+    /// assert!(Span::checked_new(5, 2, 0).is_none());
+    /// ```
+    pub const fn checked_new(
+        start: usize,
+        end: usize,
+        line: usize,
+    ) -> Option<Self> {
+        if start <= end {
+            Some(Span::new(start, end, line))
+        } else {
+            None
+        }
+    }
+
+    /// Create a new [`Span`], moving `end` up to `start` if it would
+    /// otherwise end before it starts.
+    ///
+    /// ```rust
+    /// # use gcode::Span;
+    /// assert_eq!(Span::saturating_new(5, 2, 0), Span::new(5, 5, 0));
+    /// ```
+    pub const fn saturating_new(start: usize, end: usize, line: usize) -> Self {
+        if start <= end {
+            Span::new(start, end, line)
+        } else {
+            Span::new(start, start, line)
+        }
+    }
+
+    /// Does this [`Span`] start before it ends?
+    ///
+    /// The [`Span::PLACEHOLDER`] is always valid.
+    pub const fn is_valid(self) -> bool { self.start <= self.end }
+
+    /// Does this [`Span`] point at some text inside `src`, starting and
+    /// ending on character boundaries?
+    ///
+    /// ```rust
+    /// # use gcode::Span;
+    /// let src = "G1 X°";
+    ///
+    /// assert!(Span::new(3, 4, 0).is_valid_for(src));
+    /// // past the end
+    /// assert!(!Span::new(3, 10, 0).is_valid_for(src));
+    /// // in the middle of the "°"
+    /// assert!(!Span::new(3, 5, 0).is_valid_for(src));
+    /// ```
+    pub fn is_valid_for(self, src: &str) -> bool {
+        !self.is_placeholder() && self.get_text(src).is_some()
+    }
+
     /// Get the string this [`Span`] corresponds to.
     ///
     /// Passing in a different string will probably lead to... strange...
@@ -41,18 +102,30 @@ impl Span {
     }
 
     /// Merge two [`Span`]s, making sure [`Span::PLACEHOLDER`] spans go away.
+    ///
+    /// The merged span covers both inputs and is always valid, even if one
+    /// of the inputs wasn't (see [`Span::saturating_new()`]). When the spans
+    /// are on different lines, the merged span uses the earlier line.
     pub fn merge(self, other: Span) -> Span {
         if self.is_placeholder() {
-            other
+            return other.saturated();
         } else if other.is_placeholder() {
-            self
-        } else {
-            Span {
-                start: cmp::min(self.start, other.start),
-                end: cmp::max(self.end, other.end),
-                line: cmp::min(self.line, other.line),
-            }
+            return self.saturated();
         }
+
+        let (first, second) = (self.saturated(), other.saturated());
+        let merged = Span {
+            start: cmp::min(first.start, second.start),
+            end: cmp::max(first.end, second.end),
+            line: cmp::min(first.line, second.line),
+        };
+        debug_assert!(merged.is_valid());
+
+        merged
+    }
+
+    fn saturated(self) -> Span {
+        Span::saturating_new(self.start, self.end, self.line)
     }
 
     /// Is this a [`Span::PLACEHOLDER`]?
@@ -126,4 +199,24 @@ mod tests {
             assert_eq!(input, Span::PLACEHOLDER);
         }
     }
+
+    #[test]
+    fn merging_covers_both_spans() {
+        let got = Span::new(5, 8, 1).merge(Span::new(2, 4, 0));
+
+        assert_eq!(got, Span::new(2, 8, 0));
+    }
+
+    #[test]
+    fn merging_inverted_spans_is_still_valid() {
+        let inverted = Span::new(10, 3, 0);
+
+        let got = inverted.merge(Span::new(12, 14, 0));
+        assert_eq!(got, Span::new(10, 14, 0));
+        assert!(got.is_valid());
+
+        let got = Span::PLACEHOLDER.merge(inverted);
+        assert_eq!(got, Span::new(10, 10, 0));
+        assert!(got.is_valid());
+    }
 }
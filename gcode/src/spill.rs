@@ -0,0 +1,662 @@
+//! Holding huge programs without holding them all in memory.
+//!
+//! A [`Program`] keeps every line in memory, which is a problem for
+//! server-side services chewing through multi-gigabyte files. A
+//! [`SpillingProgram`] parses each line as it is added and has a memory
+//! budget. Once that is used up, the lines collected so far (and the
+//! commands parsed from them) are written to a temporary file in one batch.
+//! Iterating over the program streams the spilled batches back from disk
+//! before moving on to the lines still in memory, so nothing needs to be
+//! parsed a second time.
+//!
+//! ```rust
+//! use gcode::{dialect::Dialect, interpret::Units, spill::SpillingProgram};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let mut program = SpillingProgram::new(Dialect::generic(), 256);
+//!
+//! for line in &["G20", "G1 X1 F100", "X2", "X3", "M30"] {
+//!     program.push_line(*line)?;
+//! }
+//!
+//! assert_eq!(program.len(), 5);
+//! assert!(program.spilled_lines() > 0);
+//! assert!(program.memory_used() <= 256);
+//!
+//! let lines = program.lines()?.collect::<Result<Vec<_>, _>>()?;
+//! assert_eq!(lines, &["G20", "G1 X1 F100", "X2", "X3", "M30"]);
+//!
+//! // the X2 on its own still continues the G1
+//! let parsed = program.parsed_lines()?.nth(2).unwrap()?;
+//! assert_eq!(parsed.gcodes[0].to_string(), "G1 X2");
+//!
+//! let state = program.final_state()?;
+//! assert_eq!(state.units, Units::Inches);
+//! assert_eq!(state.position.x, 3.0 * 25.4);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Program`]: crate::program::Program
+
+use crate::{
+    decimal::{Decimal, MAX_SCALE},
+    dialect::Dialect,
+    interpret::{Interpreter, MachineState},
+    program::Program,
+    GCode, Line, Mnemonic, Nop, Parser, Span, Word, WordValue,
+};
+use core::convert::TryFrom;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    string::String,
+    sync::atomic::{AtomicUsize, Ordering},
+    vec::Vec,
+};
+
+/// A program which moves its lines to a temporary file when they would take
+/// up more than a certain amount of memory.
+///
+/// The temporary file is deleted when the [`SpillingProgram`] is dropped.
+#[derive(Debug)]
+pub struct SpillingProgram {
+    dialect: Dialect,
+    memory_budget: usize,
+    spill_dir: PathBuf,
+    batch: Vec<ParsedLine>,
+    memory_used: usize,
+    spill: Option<Spill>,
+    /// The most recent command word, carried over to the next line.
+    last_command: Option<Word>,
+}
+
+impl SpillingProgram {
+    /// Create an empty [`SpillingProgram`] which keeps at most
+    /// `memory_budget` bytes of text and parsed commands in memory, spilling
+    /// to the system's temporary directory.
+    pub fn new(dialect: Dialect, memory_budget: usize) -> Self {
+        SpillingProgram::with_spill_dir(
+            dialect,
+            memory_budget,
+            std::env::temp_dir(),
+        )
+    }
+
+    /// Create an empty [`SpillingProgram`] which spills to a particular
+    /// directory.
+    pub fn with_spill_dir<P: Into<PathBuf>>(
+        dialect: Dialect,
+        memory_budget: usize,
+        spill_dir: P,
+    ) -> Self {
+        SpillingProgram {
+            dialect,
+            memory_budget,
+            spill_dir: spill_dir.into(),
+            batch: Vec::new(),
+            memory_used: 0,
+            spill: None,
+            last_command: None,
+        }
+    }
+
+    /// Read a program line-by-line, without ever holding more than
+    /// `memory_budget` bytes of it in memory.
+    pub fn from_reader<R: BufRead>(
+        reader: R,
+        dialect: Dialect,
+        memory_budget: usize,
+    ) -> io::Result<Self> {
+        let mut program = SpillingProgram::new(dialect, memory_budget);
+
+        for line in reader.lines() {
+            program.push_line(line?)?;
+        }
+
+        Ok(program)
+    }
+
+    /// The [`Dialect`] this program is written in.
+    pub fn dialect(&self) -> &Dialect { &self.dialect }
+
+    /// Parse a line and add it to the end of the program, spilling to disk
+    /// if the memory budget has been used up.
+    ///
+    /// Lines containing a `'\n'` are rejected with
+    /// [`io::ErrorKind::InvalidInput`], because they would throw off the
+    /// numbering of every line after them.
+    pub fn push_line<S: Into<String>>(&mut self, line: S) -> io::Result<()> {
+        let text = line.into();
+        if text.contains('\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a line can't contain a newline",
+            ));
+        }
+
+        let mut parser: Parser<'_, Nop> = Parser::resume(
+            &text,
+            Nop,
+            self.dialect,
+            0,
+            self.len(),
+            self.last_command,
+        );
+        let gcodes = parser
+            .by_ref()
+            .flat_map(|line| line.gcodes().to_vec())
+            .collect();
+        self.last_command = parser.last_command();
+
+        let line = ParsedLine { text, gcodes };
+        let size = line.memory_used();
+
+        if self.memory_used + size > self.memory_budget {
+            self.spill_batch()?;
+        }
+
+        self.memory_used += size;
+        self.batch.push(line);
+
+        if self.memory_used > self.memory_budget {
+            // a single line was bigger than the entire budget
+            self.spill_batch()?;
+        }
+
+        Ok(())
+    }
+
+    /// The number of lines in the program.
+    pub fn len(&self) -> usize { self.spilled_lines() + self.batch.len() }
+
+    /// Does the program contain no lines?
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// The number of lines which have been written to disk.
+    pub fn spilled_lines(&self) -> usize {
+        self.spill.as_ref().map_or(0, |spill| spill.lines)
+    }
+
+    /// Roughly how many bytes of text and parsed commands are currently
+    /// held in memory.
+    pub fn memory_used(&self) -> usize { self.memory_used }
+
+    /// Iterate over each line and the commands parsed from it.
+    pub fn parsed_lines(&self) -> io::Result<ParsedLines<'_>> {
+        let spilled = match self.spill {
+            Some(ref spill) => Some(BufReader::new(File::open(&spill.path)?)),
+            None => None,
+        };
+
+        Ok(ParsedLines {
+            spilled,
+            in_memory: self.batch.iter(),
+        })
+    }
+
+    /// Iterate over the text of each line, without trailing newlines.
+    pub fn lines(&self) -> io::Result<Lines<'_>> {
+        Ok(Lines {
+            parsed: self.parsed_lines()?,
+        })
+    }
+
+    /// Run the program through an [`Interpreter`] to find the
+    /// [`MachineState`] it leaves the machine in.
+    pub fn final_state(&self) -> io::Result<MachineState> {
+        let mut interpreter = Interpreter::new(self.dialect);
+
+        for parsed in self.parsed_lines()? {
+            let mut line: Line<'_> = Line::default();
+            for gcode in parsed?.gcodes {
+                let _ = line.push_gcode(gcode);
+            }
+
+            let _ = interpreter.process_line(&line);
+        }
+
+        Ok(*interpreter.state())
+    }
+
+    /// Load the entire program into memory.
+    pub fn into_program(self) -> io::Result<Program> {
+        let mut program = Program::new(self.dialect);
+
+        for line in self.lines()? {
+            program.push_line(line?);
+        }
+
+        Ok(program)
+    }
+
+    fn spill_batch(&mut self) -> io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        let spill = match self.spill {
+            Some(ref mut spill) => spill,
+            None => self.spill.insert(Spill::create(&self.spill_dir)?),
+        };
+
+        for line in self.batch.drain(..) {
+            line.write_to(&mut spill.writer)?;
+            spill.lines += 1;
+        }
+        spill.writer.flush()?;
+
+        self.batch.shrink_to_fit();
+        self.memory_used = 0;
+
+        Ok(())
+    }
+}
+
+/// A line in a [`SpillingProgram`], along with the commands parsed from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedLine {
+    /// The line's text, without a trailing newline.
+    pub text: String,
+    /// The commands on this line.
+    ///
+    /// Their spans are byte offsets into [`ParsedLine::text`], while the line
+    /// numbers count from the start of the program.
+    pub gcodes: Vec<GCode>,
+}
+
+impl ParsedLine {
+    /// Roughly how much memory this line takes up.
+    fn memory_used(&self) -> usize {
+        let gcodes: usize = self
+            .gcodes
+            .iter()
+            .map(|gcode| size_of::<GCode>() + size_of_val(gcode.arguments()))
+            .sum();
+
+        self.text.len() + gcodes
+    }
+
+    /// Append this line to a spill file.
+    ///
+    /// Everything is length-prefixed, so the text may contain anything.
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_u64(writer, self.text.len() as u64)?;
+        writer.write_all(self.text.as_bytes())?;
+
+        write_u64(writer, self.gcodes.len() as u64)?;
+        for gcode in &self.gcodes {
+            write_u32(writer, u32::from(mnemonic_letter(gcode.mnemonic())))?;
+            write_u32(writer, gcode.number.to_bits())?;
+            write_span(writer, gcode.span())?;
+
+            write_u64(writer, gcode.arguments().len() as u64)?;
+            for word in gcode.arguments() {
+                write_u32(writer, u32::from(word.letter))?;
+                match word.value {
+                    WordValue::Number(n) => {
+                        writer.write_all(&[0])?;
+                        write_u32(writer, n.to_bits())?;
+                    },
+                    WordValue::Decimal(d) => {
+                        writer.write_all(&[1, d.scale()])?;
+                        writer.write_all(&d.mantissa().to_le_bytes())?;
+                    },
+                    WordValue::Flag => writer.write_all(&[2])?,
+                    WordValue::Expression(span) => {
+                        writer.write_all(&[3])?;
+                        write_span(writer, span)?;
+                    },
+                }
+                write_span(writer, word.span)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the next line back from a spill file, returning `None` at the
+    /// end of the file.
+    fn read_from<R: BufRead>(reader: &mut R) -> io::Result<Option<Self>> {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        // a corrupt length could be anything, so only allocate as much as
+        // is actually there
+        let length = read_length(reader)?;
+        let mut text = Vec::new();
+        let _ = reader.by_ref().take(length as u64).read_to_end(&mut text)?;
+        if text.len() != length {
+            return Err(corrupt());
+        }
+        let text = String::from_utf8(text).map_err(|_| corrupt())?;
+
+        let mut gcodes = Vec::new();
+        for _ in 0..read_length(reader)? {
+            let mnemonic = char::from_u32(read_u32(reader)?)
+                .and_then(Mnemonic::for_letter)
+                .ok_or_else(corrupt)?;
+            let number = f32::from_bits(read_u32(reader)?);
+            let mut gcode = GCode::new(mnemonic, number, read_span(reader)?);
+
+            for _ in 0..read_length(reader)? {
+                let letter =
+                    char::from_u32(read_u32(reader)?).ok_or_else(corrupt)?;
+                let value = match read_u8(reader)? {
+                    0 => WordValue::Number(f32::from_bits(read_u32(reader)?)),
+                    1 => {
+                        let scale = read_u8(reader)?;
+                        if scale > MAX_SCALE {
+                            return Err(corrupt());
+                        }
+                        let mut mantissa = [0; 8];
+                        reader.read_exact(&mut mantissa)?;
+                        let mantissa = i64::from_le_bytes(mantissa);
+                        WordValue::Decimal(Decimal::new(mantissa, scale))
+                    },
+                    2 => WordValue::Flag,
+                    3 => WordValue::Expression(read_span(reader)?),
+                    _ => return Err(corrupt()),
+                };
+                let span = read_span(reader)?;
+
+                let _ = gcode.push_argument(Word {
+                    letter,
+                    value,
+                    span,
+                });
+            }
+
+            gcodes.push(gcode);
+        }
+
+        Ok(Some(ParsedLine { text, gcodes }))
+    }
+}
+
+fn mnemonic_letter(mnemonic: Mnemonic) -> char {
+    match mnemonic {
+        Mnemonic::General => 'G',
+        Mnemonic::Miscellaneous => 'M',
+        Mnemonic::ProgramNumber => 'O',
+        Mnemonic::ToolChange => 'T',
+    }
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "the spill file is corrupt")
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_span<W: Write>(writer: &mut W, span: Span) -> io::Result<()> {
+    write_u64(writer, span.start as u64)?;
+    write_u64(writer, span.end as u64)?;
+    write_u64(writer, span.line as u64)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_length<R: Read>(reader: &mut R) -> io::Result<usize> {
+    usize::try_from(read_u64(reader)?).map_err(|_| corrupt())
+}
+
+fn read_span<R: Read>(reader: &mut R) -> io::Result<Span> {
+    Ok(Span::new(
+        read_length(reader)?,
+        read_length(reader)?,
+        read_length(reader)?,
+    ))
+}
+
+/// The temporary file lines are spilled to.
+#[derive(Debug)]
+struct Spill {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    lines: usize,
+}
+
+impl Spill {
+    fn create(dir: &Path) -> io::Result<Spill> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        loop {
+            let name = format!(
+                "gcode-spill-{}-{}.tmp",
+                process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = dir.join(name);
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    return Ok(Spill {
+                        path,
+                        writer: BufWriter::new(file),
+                        lines: 0,
+                    })
+                },
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) { let _ = fs::remove_file(&self.path); }
+}
+
+/// An iterator over the [`ParsedLine`]s in a [`SpillingProgram`].
+#[derive(Debug)]
+pub struct ParsedLines<'a> {
+    spilled: Option<BufReader<File>>,
+    in_memory: core::slice::Iter<'a, ParsedLine>,
+}
+
+impl<'a> Iterator for ParsedLines<'a> {
+    type Item = io::Result<ParsedLine>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(ref mut spilled) = self.spilled {
+            match ParsedLine::read_from(spilled) {
+                Ok(Some(line)) => return Some(Ok(line)),
+                Ok(None) => self.spilled = None,
+                Err(e) => {
+                    self.spilled = None;
+                    return Some(Err(e));
+                },
+            }
+        }
+
+        self.in_memory.next().cloned().map(Ok)
+    }
+}
+
+/// An iterator over the text of each line in a [`SpillingProgram`].
+#[derive(Debug)]
+pub struct Lines<'a> {
+    parsed: ParsedLines<'a>,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.parsed.next().map(|line| line.map(|line| line.text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(program: &SpillingProgram) -> Vec<String> {
+        program.lines().unwrap().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn everything_fits_in_memory() {
+        let mut program = SpillingProgram::new(Dialect::generic(), 1024);
+        program.push_line("G0 X1").unwrap();
+        program.push_line("G0 X2").unwrap();
+
+        assert_eq!(program.spilled_lines(), 0);
+        assert_eq!(
+            program.memory_used(),
+            2 * (5 + size_of::<GCode>() + size_of::<Word>())
+        );
+        assert_eq!(lines(&program), vec!["G0 X1", "G0 X2"]);
+    }
+
+    #[test]
+    fn parsed_commands_survive_a_trip_to_disk() {
+        let src = ["G1 X1.5 F100", "Y#1 Z", "M30"];
+        let mut dialect = Dialect::linuxcnc();
+        dialect.decimal_precision = Some(4);
+
+        let mut everything = SpillingProgram::new(dialect, usize::MAX);
+        let mut spilled = SpillingProgram::new(dialect, 0);
+        for line in &src {
+            everything.push_line(*line).unwrap();
+            spilled.push_line(*line).unwrap();
+        }
+        assert_eq!(spilled.spilled_lines(), 3);
+
+        let parsed = |program: &SpillingProgram| {
+            program
+                .parsed_lines()
+                .unwrap()
+                .collect::<io::Result<Vec<_>>>()
+                .unwrap()
+        };
+        let got = parsed(&spilled);
+
+        assert_eq!(got, parsed(&everything));
+        let continued = &got[1].gcodes[0];
+        assert_eq!(continued.span().line, 1);
+        match continued.arguments()[0].value {
+            WordValue::Expression(span) => {
+                assert_eq!(span.get_text(&got[1].text), Some("#1"))
+            },
+            other => panic!("unexpected value: {:?}", other),
+        }
+        assert_eq!(continued.arguments()[1].value, WordValue::Flag);
+        assert!(matches!(
+            got[0].gcodes[0].arguments()[0].value,
+            WordValue::Decimal(_)
+        ));
+    }
+
+    #[test]
+    fn corrupt_lines_are_errors() {
+        let read = |bytes: &[u8]| {
+            ParsedLine::read_from(&mut io::Cursor::new(bytes))
+                .unwrap_err()
+                .kind()
+        };
+
+        let mut bad_scale = Vec::new();
+        write_u64(&mut bad_scale, 0).unwrap();
+        write_u64(&mut bad_scale, 1).unwrap();
+        write_u32(&mut bad_scale, u32::from('G')).unwrap();
+        write_u32(&mut bad_scale, 1.0_f32.to_bits()).unwrap();
+        write_span(&mut bad_scale, Span::default()).unwrap();
+        write_u64(&mut bad_scale, 1).unwrap();
+        write_u32(&mut bad_scale, u32::from('X')).unwrap();
+        bad_scale.extend_from_slice(&[1, MAX_SCALE + 1]);
+        bad_scale.extend_from_slice(&15_i64.to_le_bytes());
+        write_span(&mut bad_scale, Span::default()).unwrap();
+        assert_eq!(read(&bad_scale), io::ErrorKind::InvalidData);
+
+        let mut huge_text = Vec::new();
+        write_u64(&mut huge_text, u64::MAX >> 1).unwrap();
+        huge_text.extend_from_slice(b"G1 X1");
+        assert_eq!(read(&huge_text), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn lines_cant_contain_newlines() {
+        let mut program = SpillingProgram::new(Dialect::generic(), 1024);
+
+        let err = program.push_line("G0 X1\nG0 X2").unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(program.is_empty());
+    }
+
+    #[test]
+    fn memory_stays_within_budget() {
+        let src: Vec<_> = (0..1000).map(|i| format!("G1 X{}", i)).collect();
+        let reader = io::Cursor::new(src.join("\n"));
+
+        let program =
+            SpillingProgram::from_reader(reader, Dialect::generic(), 100)
+                .unwrap();
+
+        assert_eq!(program.len(), 1000);
+        assert!(program.spilled_lines() > 900);
+        assert!(program.memory_used() <= 100);
+        assert_eq!(lines(&program), src);
+        assert_eq!(program.final_state().unwrap().position.x, 999.0);
+    }
+
+    #[test]
+    fn lines_bigger_than_the_budget_go_straight_to_disk() {
+        let mut program = SpillingProgram::new(Dialect::generic(), 4);
+        program.push_line("G1 X10 Y20").unwrap();
+
+        assert_eq!(program.spilled_lines(), 1);
+        assert_eq!(program.memory_used(), 0);
+    }
+
+    #[test]
+    fn the_spill_file_is_deleted_on_drop() {
+        let mut program = SpillingProgram::new(Dialect::generic(), 0);
+        program.push_line("G0 X1").unwrap();
+        let path = program.spill.as_ref().unwrap().path.clone();
+        assert!(path.exists());
+
+        drop(program);
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn convert_to_an_ordinary_program() {
+        let mut program = SpillingProgram::new(Dialect::generic(), 8);
+        for line in &["G21", "G0 X1 Y1", "M30"] {
+            program.push_line(*line).unwrap();
+        }
+
+        let got = program.into_program().unwrap();
+
+        assert_eq!(got.to_string(), "G21\nG0 X1 Y1\nM30\n");
+    }
+}
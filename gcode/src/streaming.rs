@@ -0,0 +1,218 @@
+use crate::{
+    buffers::{Buffers, DefaultBuffers},
+    dialect::Dialect,
+    Callbacks, Line, Nop, Parser, Span, Word,
+};
+use arrayvec::{Array, ArrayVec};
+use core::marker::PhantomData;
+
+/// A parser which is fed its input a chunk at a time, for when the whole
+/// program isn't available up front (e.g. it's arriving over a serial port).
+///
+/// Bytes are copied into a fixed-size line buffer (256 bytes by default),
+/// and each [`Line`] is parsed once its newline arrives. Spans are relative
+/// to the start of the line they are on, with [`Span::line`] counting the
+/// lines since the parser was created.
+///
+/// A line which doesn't fit in the buffer is skipped, and reported using
+/// [`Callbacks::line_buffer_overflowed()`].
+///
+/// ```rust
+/// use gcode::{buffers::DefaultBuffers, Nop, StreamingParser};
+///
+/// // room for lines of up to 32 bytes
+/// let mut parser: StreamingParser<Nop, DefaultBuffers, [u8; 32]> =
+///     StreamingParser::new(Nop);
+/// let mut lines = Vec::new();
+///
+/// parser.push_bytes(b"G90\nG0 X", |line| lines.push(line.span().line));
+/// assert_eq!(lines, vec![0]);
+///
+/// parser.push_bytes(b"5\n", |line| lines.push(line.span().line));
+/// assert_eq!(lines, vec![0, 1]);
+/// ```
+#[derive(Debug)]
+pub struct StreamingParser<C = Nop, B = DefaultBuffers, A = [u8; 256]>
+where
+    A: Array<Item = u8>,
+{
+    callbacks: C,
+    dialect: Dialect,
+    line: ArrayVec<A>,
+    /// Is the current line being skipped because it's too long?
+    overflowed: bool,
+    line_number: usize,
+    /// The last command word, for lines which only contain arguments.
+    last_command: Option<Word>,
+    /// The line number expected next, when checking line numbers.
+    next_line_number: Option<u32>,
+    _buffers: PhantomData<B>,
+}
+
+impl<C, B, A> StreamingParser<C, B, A>
+where
+    A: Array<Item = u8>,
+{
+    /// Create a new [`StreamingParser`] which uses a set of [`Callbacks`].
+    pub fn new(callbacks: C) -> Self {
+        StreamingParser::new_with_dialect(callbacks, Dialect::default())
+    }
+
+    /// Create a new [`StreamingParser`] which follows the conventions of a
+    /// particular [`Dialect`].
+    pub fn new_with_dialect(callbacks: C, dialect: Dialect) -> Self {
+        StreamingParser {
+            callbacks,
+            dialect,
+            line: ArrayVec::new(),
+            overflowed: false,
+            line_number: 0,
+            last_command: None,
+            next_line_number: None,
+            _buffers: PhantomData,
+        }
+    }
+
+    /// The [`Callbacks`] used to report errors.
+    pub fn callbacks(&self) -> &C { &self.callbacks }
+
+    /// The number of bytes waiting for the end of their line.
+    pub fn pending(&self) -> usize { self.line.len() }
+}
+
+impl<C, B, A> StreamingParser<C, B, A>
+where
+    C: Callbacks,
+    B: for<'input> Buffers<'input>,
+    A: Array<Item = u8>,
+{
+    /// Feed the parser some more bytes, calling `on_line` for each [`Line`]
+    /// which is completed.
+    pub fn push_bytes<F>(&mut self, bytes: &[u8], mut on_line: F)
+    where
+        F: FnMut(Line<'_, B>),
+    {
+        for &byte in bytes {
+            if byte == b'\n' {
+                self.end_line(&mut on_line);
+            } else if !self.overflowed && self.line.try_push(byte).is_err() {
+                self.overflowed = true;
+                self.callbacks.line_buffer_overflowed(self.line_number);
+            }
+        }
+    }
+
+    /// Parse whatever is left over once the input has finished, for programs
+    /// which don't end with a newline.
+    pub fn finish<F>(&mut self, mut on_line: F)
+    where
+        F: FnMut(Line<'_, B>),
+    {
+        if !self.line.is_empty() || self.overflowed {
+            self.end_line(&mut on_line);
+        }
+    }
+
+    fn end_line<F>(&mut self, on_line: &mut F)
+    where
+        F: FnMut(Line<'_, B>),
+    {
+        if !self.overflowed {
+            let line_number = self.line_number;
+            let text = match core::str::from_utf8(&self.line) {
+                Ok(text) => text,
+                Err(e) => {
+                    // the rest of the line can't be lexed, so only use the
+                    // valid part
+                    let valid = e.valid_up_to();
+                    let span = Span::new(valid, self.line.len(), line_number);
+                    self.callbacks.unknown_content("\u{fffd}", span);
+                    core::str::from_utf8(&self.line[..valid]).unwrap_or("")
+                },
+            };
+
+            let mut parser: Parser<'_, &mut C, B> = Parser::resume(
+                text,
+                &mut self.callbacks,
+                self.dialect,
+                0,
+                line_number,
+                self.last_command,
+            )
+            .with_next_line_number(self.next_line_number);
+            for line in parser.by_ref() {
+                on_line(line);
+            }
+            self.last_command = parser.last_command();
+            self.next_line_number = parser.next_line_number();
+        }
+
+        self.line.clear();
+        self.overflowed = false;
+        self.line_number += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GCode, Mnemonic};
+    use std::vec::Vec;
+
+    #[derive(Debug, Default)]
+    struct Overflows(Vec<usize>);
+
+    impl Callbacks for Overflows {
+        fn line_buffer_overflowed(&mut self, line: usize) { self.0.push(line); }
+    }
+
+    fn parse_chunks<A: Array<Item = u8>>(
+        parser: &mut StreamingParser<Overflows, DefaultBuffers, A>,
+        chunks: &[&[u8]],
+    ) -> Vec<GCode> {
+        let mut gcodes = Vec::new();
+
+        for chunk in chunks {
+            parser.push_bytes(chunk, |line| {
+                gcodes.extend(line.gcodes().iter().cloned())
+            });
+        }
+        parser.finish(|line| gcodes.extend(line.gcodes().iter().cloned()));
+
+        gcodes
+    }
+
+    #[test]
+    fn every_split_gives_the_same_result() {
+        let src = "G90 (absolute)\nG01 X-12.5 Y3 ; move\nN20 M3 S1000\nX7";
+        let mut expected: StreamingParser<Overflows> =
+            StreamingParser::new(Overflows::default());
+        let expected = parse_chunks(&mut expected, &[src.as_bytes()]);
+        assert_eq!(expected.len(), 4);
+
+        for split in 0..src.len() {
+            let (first, second) = src.as_bytes().split_at(split);
+            let mut parser: StreamingParser<Overflows> =
+                StreamingParser::new(Overflows::default());
+
+            let got = parse_chunks(&mut parser, &[first, second]);
+
+            assert_eq!(got, expected, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn long_lines_are_skipped() {
+        let mut parser: StreamingParser<Overflows, DefaultBuffers, [u8; 8]> =
+            StreamingParser::new(Overflows::default());
+
+        let got =
+            parse_chunks(&mut parser, &[b"G0 X1\nG1 X2 Y3 Z4\n", b"M5\n"]);
+
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].major_number(), 0);
+        assert_eq!(got[1].mnemonic(), Mnemonic::Miscellaneous);
+        assert_eq!(got[1].span().line, 2);
+        assert_eq!(parser.callbacks().0, vec![1]);
+    }
+}
@@ -0,0 +1,2757 @@
+//! Rewriting whole programs.
+//!
+//! Unlike the [`writer`][crate::writer], which formats individual commands,
+//! the functions in this module take a program's source text and produce new
+//! programs. Anything which isn't being changed is copied across verbatim so
+//! comments and formatting survive.
+
+use crate::{
+    dialect::Dialect,
+    interpret::{
+        Interpreter, MachineState, Plane, Position, Positioning, ToolSelection,
+        Units,
+    },
+    lexer::{Lexer, TokenType},
+    profile::{DialectName, MachineProfile},
+    words::{Atom, WordsOrComments},
+    writer::{LineEnding, Writer, WriterConfig},
+    CommandKey, GCode, Line, Mnemonic, Nop, Parser, Span, Word,
+};
+use core::fmt::{self, Display, Formatter};
+use std::{format, string::String, vec::Vec};
+
+/// A standalone program which only uses a single tool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolJob {
+    /// The tool used by this job, or `None` if the original program never
+    /// changed tools.
+    pub tool: Option<ToolSelection>,
+    /// The job's source text.
+    pub program: String,
+}
+
+/// Split a program which uses several tools into one program per tool, so it
+/// can be run on a machine without an automatic tool changer.
+///
+/// Every job starts with the original program's preamble (everything before
+/// the first tool change) and finishes with its postamble (everything from
+/// the program end, `M2` or `M30`, onwards). Before each section of the
+/// original program, a line is inserted which restores the units,
+/// positioning mode, plane and work coordinate system that were active at
+/// that point. If the tool was selected by a `T` word on an earlier line
+/// than the `M6` which loads it, that `T` word is repeated after the
+/// restored state so the job still loads the right tool.
+///
+/// Jobs are returned in the order their tools were first used, and if a tool
+/// is used more than once its sections are all put in the same job.
+///
+/// ```rust
+/// use gcode::{dialect::Dialect, interpret::ToolSelection, transform};
+///
+/// let src = "%\nG21 G90\nT1 M6\nG0 X10\nG55\nT2 M6\nG1 X20\nM30\n%\n";
+///
+/// let jobs = transform::split_by_tool(src, &Dialect::generic());
+///
+/// assert_eq!(jobs.len(), 2);
+/// assert_eq!(jobs[0].tool, Some(ToolSelection::tool(1)));
+/// assert_eq!(jobs[0].program, "%\nG21 G90\nT1 M6\nG0 X10\nG55\nM30\n%\n");
+/// assert_eq!(jobs[1].tool, Some(ToolSelection::tool(2)));
+/// assert_eq!(
+///     jobs[1].program,
+///     "%\nG21 G90\nG21 G90 G17 G55\nT2 M6\nG1 X20\nM30\n%\n",
+/// );
+/// ```
+pub fn split_by_tool(src: &str, dialect: &Dialect) -> Vec<ToolJob> {
+    let source_lines: Vec<&str> = src.split_inclusive('\n').collect();
+    let mut interpreter = Interpreter::new(*dialect);
+    let mut tool_changes = Vec::new();
+    let mut program_end = None;
+    let mut last_selection = None;
+
+    for line in Parser::<_>::new_with_dialect(src, Nop, *dialect) {
+        let before = *interpreter.state();
+        let _ = interpreter.process_line(&line);
+
+        if program_end.is_none() && is_program_end(&line) {
+            program_end = Some(line.span().line);
+        }
+
+        let selection = line
+            .gcodes()
+            .iter()
+            .find(|gcode| gcode.mnemonic == Mnemonic::ToolChange)
+            .and_then(|gcode| gcode.span().get_text(src));
+
+        if program_end.is_none() && changes_tool(&line, dialect) {
+            let tool = interpreter.state().tool.active;
+            // a T word on an earlier line stays behind in the previous
+            // section, so remember it for the restore preamble
+            let preparatory = if selection.is_none() {
+                last_selection
+            } else {
+                None
+            };
+            tool_changes.push((line.span().line, before, tool, preparatory));
+        }
+
+        if selection.is_some() {
+            last_selection = selection;
+        }
+    }
+
+    let end = program_end.unwrap_or(source_lines.len());
+    let first_change = match tool_changes.first() {
+        Some(&(line, ..)) => line,
+        None => {
+            return vec![ToolJob {
+                tool: None,
+                program: String::from(src),
+            }]
+        },
+    };
+
+    let preamble = &source_lines[..first_change];
+    let postamble = &source_lines[end..];
+    let mut jobs: Vec<ToolJob> = Vec::new();
+
+    for (i, &(start, state, tool, preparatory)) in
+        tool_changes.iter().enumerate()
+    {
+        let finish = tool_changes.get(i + 1).map_or(end, |next| next.0);
+        let section = &source_lines[start..finish];
+
+        let index = match jobs.iter().position(|job| job.tool == tool) {
+            Some(index) => index,
+            None => {
+                jobs.push(ToolJob {
+                    tool,
+                    program: String::new(),
+                });
+                jobs.len() - 1
+            },
+        };
+        let job = &mut jobs[index];
+
+        if job.program.is_empty() {
+            push_lines(&mut job.program, preamble);
+        }
+        // the preamble already puts the first section in the right state
+        if i > 0 {
+            restore_modal_state(&mut job.program, &state, dialect);
+            if let Some(selection) = preparatory {
+                push_lines(&mut job.program, &[selection]);
+            }
+        }
+        push_lines(&mut job.program, section);
+    }
+
+    for job in &mut jobs {
+        push_lines(&mut job.program, postamble);
+    }
+
+    jobs
+}
+
+/// Settings for [`sanitize()`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct SanitizeConfig {
+    /// Text which is redacted wherever it appears in a comment, ignoring
+    /// case (e.g. usernames or a company name).
+    pub redact: Vec<String>,
+    /// Comments containing any of these (ignoring case) are removed
+    /// entirely.
+    pub remove: Vec<String>,
+    /// Redact absolute file paths in comments.
+    pub paths: bool,
+    /// Redact dates and times of day in comments.
+    pub timestamps: bool,
+    /// Remove lines which configure networking (`M550` to `M554` and `M587`
+    /// to `M589`) and may contain WiFi passwords or addresses.
+    pub network_commands: bool,
+    /// What redacted text is replaced with.
+    pub replacement: String,
+}
+
+impl Default for SanitizeConfig {
+    fn default() -> SanitizeConfig {
+        SanitizeConfig {
+            redact: Vec::new(),
+            remove: Vec::new(),
+            paths: true,
+            timestamps: true,
+            network_commands: true,
+            replacement: String::from("[redacted]"),
+        }
+    }
+}
+
+/// Clean up a program so it can be shared publicly, redacting personal
+/// details from its comments.
+///
+/// Only comments and network configuration commands are touched, so the
+/// sanitized program moves the machine in exactly the same way as the
+/// original. A line which becomes empty because its only comment was
+/// removed is dropped altogether.
+///
+/// Paths are assumed to end at the first whitespace character, so the
+/// remainder of a path containing spaces may be left behind. Use
+/// [`SanitizeConfig::redact`] to be sure a username never appears.
+///
+/// ```rust
+/// use gcode::{
+///     dialect::Dialect,
+///     transform::{self, SanitizeConfig},
+/// };
+///
+/// let src = "; generated 2024-03-01 10:15:00\n\
+///            ; input: /home/alice/parts/bracket.stl\n\
+///            M587 S\"home-wifi\" P\"hunter2\"\n\
+///            G1 X10 F600 ; owner: Alice\n";
+/// let config = SanitizeConfig {
+///     redact: vec![String::from("alice")],
+///     ..Default::default()
+/// };
+///
+/// let got = transform::sanitize(src, &Dialect::reprap(), &config);
+///
+/// assert_eq!(
+///     got,
+///     "; generated [redacted]\n\
+///      ; input: [redacted]\n\
+///      G1 X10 F600 ; owner: [redacted]\n",
+/// );
+/// ```
+pub fn sanitize(
+    src: &str,
+    dialect: &Dialect,
+    config: &SanitizeConfig,
+) -> String {
+    let mut sanitized = String::with_capacity(src.len());
+
+    for text in src.split_inclusive('\n') {
+        let content = text.trim_end_matches(&['\r', '\n'][..]);
+        let line_ending = &text[content.len()..];
+        let mut comments = Vec::new();
+        let mut network_command = false;
+
+        for line in Parser::<_>::new_with_dialect(content, Nop, *dialect) {
+            network_command |= line.gcodes().iter().any(is_network_command);
+            comments.extend(line.comments().iter().map(|c| c.span));
+        }
+
+        if config.network_commands && network_command {
+            continue;
+        }
+
+        let mut new_content = String::with_capacity(content.len());
+        let mut last_end = 0;
+        let mut removed_comment = false;
+
+        for span in comments {
+            new_content.push_str(&content[last_end..span.start]);
+            match sanitize_comment(&content[span.start..span.end], config) {
+                Some(comment) => new_content.push_str(&comment),
+                None => {
+                    new_content.truncate(new_content.trim_end().len());
+                    removed_comment = true;
+                },
+            }
+            last_end = span.end;
+        }
+        new_content.push_str(&content[last_end..]);
+
+        if removed_comment && new_content.trim().is_empty() {
+            continue;
+        }
+
+        sanitized.push_str(&new_content);
+        sanitized.push_str(line_ending);
+    }
+
+    sanitized
+}
+
+fn is_network_command(gcode: &GCode) -> bool {
+    gcode.mnemonic == Mnemonic::Miscellaneous
+        && matches!(gcode.major_number(), 550..=554 | 587..=589)
+}
+
+/// Redact a comment, returning `None` if it should be removed.
+fn sanitize_comment(comment: &str, config: &SanitizeConfig) -> Option<String> {
+    let mentions = |needle: &String| {
+        !needle.is_empty() && find_ignoring_case(comment, needle, 0).is_some()
+    };
+    if config.remove.iter().any(mentions) {
+        return None;
+    }
+
+    let mut comment = String::from(comment);
+
+    for needle in config.redact.iter().filter(|n| !n.is_empty()) {
+        comment = replace_matches(&comment, &config.replacement, |text, i| {
+            find_ignoring_case(text, needle, i)
+                .filter(|&start| start == i)
+                .map(|start| start + needle.len())
+        });
+    }
+
+    if config.paths {
+        comment = replace_matches(&comment, &config.replacement, |text, i| {
+            let starts_token =
+                text[..i].chars().next_back().is_none_or(is_delimiter);
+            let end =
+                text[i..].find(is_delimiter).map_or(text.len(), |n| i + n);
+
+            if starts_token && looks_like_path(&text[i..end]) {
+                Some(end)
+            } else {
+                None
+            }
+        });
+    }
+
+    if config.timestamps {
+        comment = replace_matches(&comment, &config.replacement, |text, i| {
+            let after_digit = text[..i]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_ascii_digit());
+
+            if after_digit {
+                None
+            } else {
+                timestamp_length(&text[i..]).map(|len| i + len)
+            }
+        });
+    }
+
+    Some(comment)
+}
+
+/// Copy `text`, swapping anything `matches` recognises for `replacement`.
+///
+/// The `matches` function is given the text and a byte offset, and returns
+/// where the match ends if one starts at that offset.
+fn replace_matches<F>(text: &str, replacement: &str, matches: F) -> String
+where
+    F: Fn(&str, usize) -> Option<usize>,
+{
+    let mut replaced = String::with_capacity(text.len());
+    let mut skip_until = 0;
+
+    for (i, c) in text.char_indices() {
+        if i < skip_until {
+            continue;
+        }
+
+        match matches(text, i) {
+            Some(end) if end > i => {
+                replaced.push_str(replacement);
+                skip_until = end;
+            },
+            _ => replaced.push(c),
+        }
+    }
+
+    replaced
+}
+
+fn find_ignoring_case(
+    haystack: &str,
+    needle: &str,
+    from: usize,
+) -> Option<usize> {
+    let haystack = haystack.as_bytes();
+    let needle = needle.as_bytes();
+
+    (from..haystack.len()).find(|&i| {
+        haystack
+            .get(i..i + needle.len())
+            .is_some_and(|candidate| candidate.eq_ignore_ascii_case(needle))
+    })
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '"' | '\'' | '=' | ',' | '(' | ')' | ';')
+}
+
+/// Does this look like an absolute path on Unix (`/home/me/x.stl` or
+/// `~/x.stl`) or Windows (`C:\Users\me\x.stl` or `\\server\share`)?
+fn looks_like_path(token: &str) -> bool {
+    let bytes = token.as_bytes();
+
+    match bytes {
+        [b'/', rest @ ..] => rest.len() > 1 && rest[1..].contains(&b'/'),
+        [b'~', b'/', ..] | [b'\\', b'\\', ..] => true,
+        [drive, b':', b'\\' | b'/', ..] => drive.is_ascii_alphabetic(),
+        _ => false,
+    }
+}
+
+/// If `text` starts with a date (`2024-03-01`, `01/03/2024`), a time of day
+/// (`10:15` or `10:15:00.123`) or both, how many bytes long is it?
+fn timestamp_length(text: &str) -> Option<usize> {
+    match date_length(text) {
+        Some(date) => {
+            let time = text[date..]
+                .strip_prefix(&['T', ' '][..])
+                .and_then(time_length)
+                .map_or(0, |time| time + 1);
+
+            Some(date + time)
+        },
+        None => time_length(text),
+    }
+}
+
+fn date_length(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let (first, rest) = digits(bytes);
+    let separator = *rest.first()?;
+    if !matches!(separator, b'-' | b'/' | b'.') {
+        return None;
+    }
+    let (second, rest) = digits(&rest[1..]);
+    if rest.first() != Some(&separator) {
+        return None;
+    }
+    let (third, rest) = digits(&rest[1..]);
+
+    let year_first =
+        first == 4 && (1..=2).contains(&second) && (1..=2).contains(&third);
+    let year_last =
+        (1..=2).contains(&first) && (1..=2).contains(&second) && third == 4;
+
+    if year_first || year_last {
+        Some(bytes.len() - rest.len())
+    } else {
+        None
+    }
+}
+
+fn time_length(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let (hours, rest) = digits(bytes);
+    let minutes = rest.strip_prefix(b":")?;
+    let (minute_digits, mut rest) = digits(minutes);
+
+    if !(1..=2).contains(&hours)
+        || minute_digits != 2
+        || number(&bytes[..hours]) > 23
+        || number(&minutes[..2]) > 59
+    {
+        return None;
+    }
+
+    if let Some(seconds) = rest.strip_prefix(b":") {
+        if digits(seconds).0 == 2 {
+            rest = digits(seconds).1;
+
+            if let Some(fraction) = rest.strip_prefix(b".") {
+                if digits(fraction).0 > 0 {
+                    rest = digits(fraction).1;
+                }
+            }
+        }
+    }
+
+    Some(bytes.len() - rest.len())
+}
+
+/// Split off the leading ASCII digits, returning how many there were.
+fn digits(bytes: &[u8]) -> (usize, &[u8]) {
+    let count = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+    (count, &bytes[count..])
+}
+
+fn number(digits: &[u8]) -> u32 {
+    digits
+        .iter()
+        .fold(0, |n, &digit| n * 10 + u32::from(digit - b'0'))
+}
+
+fn changes_tool<'input>(line: &Line<'input>, dialect: &Dialect) -> bool {
+    line.gcodes().iter().any(|gcode| match gcode.mnemonic {
+        Mnemonic::Miscellaneous => gcode.major_number() == 6,
+        Mnemonic::ToolChange => dialect.immediate_tool_change,
+        _ => false,
+    })
+}
+
+fn is_program_end<'input>(line: &Line<'input>) -> bool {
+    const PROGRAM_ENDS: [CommandKey; 2] =
+        [CommandKey::miscellaneous(2), CommandKey::miscellaneous(30)];
+
+    line.gcodes()
+        .iter()
+        .any(|gcode| PROGRAM_ENDS.contains(&gcode.key()))
+}
+
+fn push_lines(program: &mut String, lines: &[&str]) {
+    for line in lines {
+        if !program.is_empty() && !program.ends_with('\n') {
+            program.push('\n');
+        }
+        program.push_str(line);
+    }
+}
+
+/// A [`Writer`] for lines being spliced into a program, which always ends
+/// them with `"\n"` like [`push_lines()`] does.
+fn line_writer<W: fmt::Write>(out: W, dialect: &Dialect) -> Writer<W> {
+    let config = WriterConfig {
+        line_ending: LineEnding::Lf,
+        ..WriterConfig::for_dialect(dialect)
+    };
+    Writer::new(out, config)
+}
+
+/// Write a line which puts the machine back into a particular modal state.
+fn restore_modal_state(
+    program: &mut String,
+    state: &MachineState,
+    dialect: &Dialect,
+) {
+    let units = match state.units {
+        Units::Inches => 20.0,
+        Units::Millimeters => 21.0,
+    };
+    let positioning = match state.positioning {
+        Positioning::Absolute => 90.0,
+        Positioning::Relative => 91.0,
+    };
+    let plane = match state.plane {
+        Plane::XY => 17.0,
+        Plane::ZX => 18.0,
+        Plane::YZ => 19.0,
+    };
+    let (major, minor) = state.coordinate_system.gcode();
+    let coordinate_system = major as f32 + minor as f32 / 10.0;
+
+    if !program.is_empty() && !program.ends_with('\n') {
+        program.push('\n');
+    }
+
+    let mut line: Line<'_> = Line::default();
+    for &number in &[units, positioning, plane, coordinate_system] {
+        let gcode = GCode::new(Mnemonic::General, number, Span::PLACEHOLDER);
+        let _ = line.push_gcode(gcode);
+    }
+
+    let _ = line_writer(program, dialect).write_line(&line);
+}
+
+/// The letters [`remap_axes()`] is allowed to change.
+const AXIS_LETTERS: &str = "XYZABCUVWE";
+
+/// A renaming of axis letters, used by [`remap_axes()`].
+///
+/// ```rust
+/// use gcode::transform::AxisMap;
+///
+/// let map = AxisMap::new().swap('X', 'Y').with('E', 'A');
+///
+/// assert_eq!(map.get('X'), 'Y');
+/// assert_eq!(map.get('E'), 'A');
+/// assert_eq!(map.get('Z'), 'Z');
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct AxisMap {
+    pairs: Vec<(char, char)>,
+}
+
+impl AxisMap {
+    /// An [`AxisMap`] which leaves every letter alone.
+    pub fn new() -> Self { AxisMap::default() }
+
+    /// Rename the `from` axis to `to`.
+    pub fn with(mut self, from: char, to: char) -> Self {
+        self.pairs
+            .push((from.to_ascii_uppercase(), to.to_ascii_uppercase()));
+        self
+    }
+
+    /// Exchange two axes.
+    pub fn swap(self, first: char, second: char) -> Self {
+        self.with(first, second).with(second, first)
+    }
+
+    /// What an axis letter is renamed to.
+    pub fn get(&self, letter: char) -> char {
+        let letter = letter.to_ascii_uppercase();
+
+        self.pairs
+            .iter()
+            .find(|&&(from, _)| from == letter)
+            .map_or(letter, |&(_, to)| to)
+    }
+
+    /// Does this map rename `letter`?
+    pub fn renames(&self, letter: char) -> bool {
+        let letter = letter.to_ascii_uppercase();
+        self.pairs.iter().any(|&(from, _)| from == letter)
+    }
+
+    fn is_target(&self, letter: char) -> bool {
+        self.pairs.iter().any(|&(_, to)| to == letter)
+    }
+
+    /// The renamed `(from, to)` pairs, in the order they were added.
+    pub fn pairs(&self) -> &[(char, char)] { &self.pairs }
+
+    /// Make sure the map only renames axes, and never sends two axes to the
+    /// same letter.
+    pub fn validate(&self) -> Result<(), RemapError> {
+        for (i, &(from, to)) in self.pairs.iter().enumerate() {
+            for &letter in &[from, to] {
+                if !AXIS_LETTERS.contains(letter) {
+                    return Err(RemapError::NotAnAxis(letter));
+                }
+            }
+
+            let earlier = &self.pairs[..i];
+            if earlier.iter().any(|&(f, _)| f == from) {
+                return Err(RemapError::DuplicateAxis(from));
+            }
+            if earlier.iter().any(|&(_, t)| t == to) {
+                return Err(RemapError::DuplicateTarget(to));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The letter an arc center offset (`I`, `J` or `K`) is renamed to, or
+    /// `None` if its axis is being renamed to something other than `X`, `Y`
+    /// or `Z`.
+    fn arc_center(&self, letter: char) -> Option<char> {
+        let axis = linear_axis_for_center(letter)?;
+        center_for_linear_axis(self.get(axis))
+    }
+
+    /// The plane an arc ends up in, and whether its direction is reversed.
+    fn plane(&self, plane: Plane) -> Option<(Plane, bool)> {
+        let (first, second) = plane_axes(plane);
+        let renamed = (self.get(first), self.get(second));
+
+        [Plane::XY, Plane::ZX, Plane::YZ]
+            .iter()
+            .find_map(|&candidate| {
+                let (a, b) = plane_axes(candidate);
+
+                if renamed == (a, b) {
+                    Some((candidate, false))
+                } else if renamed == (b, a) {
+                    Some((candidate, true))
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+/// Reasons [`remap_axes()`] couldn't rename a program's axes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RemapError {
+    /// Only axis letters (`X`, `Y`, `Z`, `A`, `B`, `C`, `U`, `V`, `W` and
+    /// `E`) can be renamed.
+    NotAnAxis(char),
+    /// The same axis was given two new names.
+    DuplicateAxis(char),
+    /// Two axes would be renamed to the same letter.
+    DuplicateTarget(char),
+    /// An axis would be renamed to a letter the program already uses for
+    /// something else.
+    Collision {
+        /// The letter which would be used twice.
+        letter: char,
+        /// The (zero-based) line the letter is already used on.
+        line: usize,
+    },
+    /// An arc would end up in a plane which can't be expressed with
+    /// `G17`, `G18` or `G19` (e.g. because `X` was renamed to `A`).
+    ArcOutsidePlane {
+        /// The (zero-based) line the arc is on.
+        line: usize,
+    },
+}
+
+impl Display for RemapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RemapError::NotAnAxis(letter) => {
+                write!(f, "\"{}\" is not an axis", letter)
+            },
+            RemapError::DuplicateAxis(letter) => {
+                write!(f, "the {} axis is renamed more than once", letter)
+            },
+            RemapError::DuplicateTarget(letter) => {
+                write!(f, "more than one axis is renamed to {}", letter)
+            },
+            RemapError::Collision { letter, line } => {
+                write!(f, "{} is already used on line {}", letter, line + 1)
+            },
+            RemapError::ArcOutsidePlane { line } => write!(
+                f,
+                "the arc on line {} would leave the XY, ZX and YZ planes",
+                line + 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RemapError {}
+
+/// Rename the axes used by a program, e.g. for a printer which calls its
+/// extruder `A` instead of `E`, or a machine whose gantry is rotated so `X`
+/// and `Y` are swapped.
+///
+/// The offsets (`I`, `J` and `K`) used by arcs and back boring cycles
+/// (`G87`) follow their axes, while other commands' `I`, `J` and `K`
+/// arguments (e.g. a threading cycle's depths) are left alone. Exchanging two
+/// axes mirrors the toolpath, so arcs in a mirrored plane have their
+/// direction flipped (`G2` becomes `G3` and vice versa) and plane selections
+/// (`G17`, `G18` and `G19`) are updated, meaning the machine traces out the
+/// same physical path.
+///
+/// Nothing is changed if the program already uses one of the new letters
+/// for an axis which isn't being renamed.
+///
+/// ```rust
+/// use gcode::{
+///     dialect::Dialect,
+///     transform::{self, AxisMap},
+/// };
+///
+/// let src = "G1 X10 Y5 E0.4 (move)\nG2 X0 Y0 I-5 J0\n";
+/// let map = AxisMap::new().swap('X', 'Y').with('E', 'A');
+///
+/// let got = transform::remap_axes(src, &Dialect::reprap(), &map).unwrap();
+///
+/// assert_eq!(got, "G1 Y10 X5 A0.4 (move)\nG3 Y0 X0 J-5 I0\n");
+/// ```
+pub fn remap_axes(
+    src: &str,
+    dialect: &Dialect,
+    map: &AxisMap,
+) -> Result<String, RemapError> {
+    map.validate()?;
+
+    let mut edits: Vec<(Span, String)> = Vec::new();
+    let mut plane = Plane::XY;
+    // does the active motion command use I, J and K as axis offsets?
+    let mut uses_offsets = false;
+    // the I, J and K words on the current line, which can only be dealt
+    // with once we know which motion command the line uses
+    let mut offsets: Vec<Word> = Vec::new();
+
+    for atom in WordsOrComments::with_dialect(
+        Lexer::new(src).with_dialect(dialect),
+        *dialect,
+    ) {
+        let word = match atom {
+            Atom::Word(word)
+            | Atom::PartialWord(word, _)
+            | Atom::Expression(word, _) => word,
+            // a letter on its own (e.g. the E in "M84 E")
+            Atom::BrokenWord(token) if token.kind == TokenType::Letter => {
+                let letter = token.value.chars().next().unwrap_or_default();
+                Word::flag(letter, token.span)
+            },
+            Atom::Newline(_) => {
+                remap_offsets(
+                    src,
+                    &mut offsets,
+                    uses_offsets,
+                    map,
+                    &mut edits,
+                )?;
+                continue;
+            },
+            _ => continue,
+        };
+        let letter = word.letter.to_ascii_uppercase();
+        let line = word.span.line;
+
+        if letter == 'G' {
+            if let Some(uses) = word.number().and_then(uses_axis_offsets) {
+                uses_offsets = uses;
+            }
+            if let Some(edit) = remap_command(src, &word, map, &mut plane)? {
+                edits.push(edit);
+            }
+        } else if AXIS_LETTERS.contains(letter) {
+            let renamed = map.get(letter);
+
+            // an axis which isn't renamed would be confused with the one
+            // being renamed to its letter
+            if !map.renames(letter) && map.is_target(letter) {
+                return Err(RemapError::Collision { letter, line });
+            }
+            if renamed != letter {
+                edits.push((word.span, rename_letter(src, &word, renamed)));
+            }
+        } else if linear_axis_for_center(letter).is_some() {
+            offsets.push(word);
+        }
+    }
+    remap_offsets(src, &mut offsets, uses_offsets, map, &mut edits)?;
+    edits.sort_by_key(|(span, _)| span.start);
+
+    let mut remapped = String::with_capacity(src.len());
+    let mut cursor = 0;
+
+    for (span, replacement) in edits {
+        remapped.push_str(&src[cursor..span.start]);
+        remapped.push_str(&replacement);
+        cursor = span.end;
+    }
+    remapped.push_str(&src[cursor..]);
+
+    Ok(remapped)
+}
+
+/// Rename the `I`, `J` and `K` words on a line, if its motion command uses
+/// them as offsets along the axes.
+fn remap_offsets(
+    src: &str,
+    offsets: &mut Vec<Word>,
+    uses_offsets: bool,
+    map: &AxisMap,
+    edits: &mut Vec<(Span, String)>,
+) -> Result<(), RemapError> {
+    for word in offsets.drain(..).filter(|_| uses_offsets) {
+        let letter = word.letter.to_ascii_uppercase();
+        let renamed =
+            map.arc_center(letter).ok_or(RemapError::ArcOutsidePlane {
+                line: word.span.line,
+            })?;
+
+        if renamed != letter {
+            edits.push((word.span, rename_letter(src, &word, renamed)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Does a motion command use `I`, `J` and `K` as offsets along the `X`, `Y`
+/// and `Z` axes? Returns `None` for commands which don't change the motion
+/// mode.
+fn uses_axis_offsets(number: f32) -> Option<bool> {
+    match number as u32 {
+        2 | 3 | 87 => Some(true),
+        0 | 1 | 5 | 33 | 38 | 73 | 74 | 76 | 80..=86 | 88 | 89 => Some(false),
+        _ => None,
+    }
+}
+
+/// Update plane selections and arc directions, keeping track of the active
+/// plane.
+fn remap_command(
+    src: &str,
+    word: &Word,
+    map: &AxisMap,
+    plane: &mut Plane,
+) -> Result<Option<(Span, String)>, RemapError> {
+    let line = word.span.line;
+    let number = word.number().unwrap_or_default();
+
+    if number.fract() != 0.0 {
+        return Ok(None);
+    }
+
+    let new_number = match number as u32 {
+        17..=19 => {
+            *plane = match number as u32 {
+                17 => Plane::XY,
+                18 => Plane::ZX,
+                _ => Plane::YZ,
+            };
+
+            match map.plane(*plane) {
+                Some((Plane::XY, _)) => 17,
+                Some((Plane::ZX, _)) => 18,
+                Some((Plane::YZ, _)) => 19,
+                // only a problem if the program contains arcs
+                None => return Ok(None),
+            }
+        },
+        2 | 3 => match map.plane(*plane) {
+            Some((_, true)) => 5 - number as u32,
+            Some((_, false)) => return Ok(None),
+            None => return Err(RemapError::ArcOutsidePlane { line }),
+        },
+        _ => return Ok(None),
+    };
+
+    if new_number == number as u32 {
+        return Ok(None);
+    }
+
+    // keep any leading zeroes (e.g. "G02")
+    let text = &src[word.span.start..word.span.end];
+    let digits_start = text.find(|c: char| c.is_ascii_digit()).unwrap_or(1);
+    let digits = text[digits_start..]
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .count();
+
+    Ok(Some((
+        word.span,
+        format!("{}{:02$}", &text[..digits_start], new_number, digits),
+    )))
+}
+
+fn rename_letter(src: &str, word: &Word, renamed: char) -> String {
+    let text = &src[word.span.start..word.span.end];
+    let renamed = if word.letter.is_ascii_lowercase() {
+        renamed.to_ascii_lowercase()
+    } else {
+        renamed
+    };
+
+    format!("{}{}", renamed, &text[word.letter.len_utf8()..])
+}
+
+fn plane_axes(plane: Plane) -> (char, char) {
+    match plane {
+        Plane::XY => ('X', 'Y'),
+        Plane::ZX => ('Z', 'X'),
+        Plane::YZ => ('Y', 'Z'),
+    }
+}
+
+fn linear_axis_for_center(letter: char) -> Option<char> {
+    match letter {
+        'I' => Some('X'),
+        'J' => Some('Y'),
+        'K' => Some('Z'),
+        _ => None,
+    }
+}
+
+fn center_for_linear_axis(letter: char) -> Option<char> {
+    match letter {
+        'X' => Some('I'),
+        'Y' => Some('J'),
+        'Z' => Some('K'),
+        _ => None,
+    }
+}
+
+/// Why [`cancel_object()`] couldn't remove an object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CancelError {
+    /// None of the program's objects have this label.
+    UnknownObject {
+        /// The label which was asked for.
+        label: String,
+        /// The labels the program does use (see [`object_labels()`]).
+        known: Vec<String>,
+    },
+}
+
+impl Display for CancelError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CancelError::UnknownObject { label, known } if known.is_empty() => {
+                write!(
+                    f,
+                    "no object is labelled \"{}\" because the program doesn't \
+                     label its objects",
+                    label
+                )
+            },
+            CancelError::UnknownObject { label, known } => write!(
+                f,
+                "no object is labelled \"{}\" (expected one of: {})",
+                label,
+                known.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CancelError {}
+
+/// The labels of every object in a program, in the order they first appear.
+///
+/// Objects can be labelled with Marlin and RepRapFirmware's `M486` command
+/// (using the name given by `M486 A` if there is one, otherwise the object's
+/// number) or Klipper's `EXCLUDE_OBJECT_DEFINE` and `EXCLUDE_OBJECT_START`.
+///
+/// ```rust
+/// use gcode::transform;
+///
+/// let src = "M486 S0 A\"bracket\"\nG1 X1\nM486 S1\nG1 X2\nM486 S-1\n";
+///
+/// assert_eq!(transform::object_labels(src), &["bracket", "1"]);
+/// ```
+pub fn object_labels(src: &str) -> Vec<String> {
+    labelled_objects(src)
+        .into_iter()
+        .map(|object| object.name.unwrap_or(object.id))
+        .collect()
+}
+
+/// Remove an object from a program, so it can be printed as if the object
+/// had been cancelled from the printer's menu.
+///
+/// Every motion inside the object is removed, while anything else (e.g.
+/// temperature and fan commands, or layer change comments) is kept. Where
+/// the object ends, a travel move puts the machine wherever the cancelled
+/// moves would have left it, and with absolute extrusion a `G92 E` resets the
+/// extruder so the next object doesn't extrude everything the cancelled one
+/// would have used.
+///
+/// The `label` can be any of the names returned by [`object_labels()`], or
+/// the number given to an `M486` object.
+///
+/// ```rust
+/// use gcode::{dialect::Dialect, transform};
+///
+/// let src = "M486 S0 A\"bracket\"\n\
+///            G1 X10 Y10 E1 F1200\n\
+///            M106 S255\n\
+///            G1 X20 E2\n\
+///            M486 S1 A\"hinge\"\n\
+///            G0 X50\n\
+///            G1 Y50 E3\n\
+///            M486 S-1\n";
+///
+/// let got = transform::cancel_object(src, &Dialect::reprap(), "bracket")
+///     .unwrap();
+///
+/// assert_eq!(
+///     got,
+///     "M106 S255\n\
+///      G0 X20 Y10 F1200\n\
+///      G92 E2\n\
+///      M486 S1 A\"hinge\"\n\
+///      G0 X50\n\
+///      G1 Y50 E3\n\
+///      M486 S-1\n",
+/// );
+/// ```
+pub fn cancel_object(
+    src: &str,
+    dialect: &Dialect,
+    label: &str,
+) -> Result<String, CancelError> {
+    let objects = labelled_objects(src);
+    let cancelled: Vec<&str> = objects
+        .iter()
+        .filter(|object| {
+            object.id == label || object.name.as_deref() == Some(label)
+        })
+        .map(|object| object.id.as_str())
+        .collect();
+
+    if cancelled.is_empty() {
+        return Err(CancelError::UnknownObject {
+            label: String::from(label),
+            known: object_labels(src),
+        });
+    }
+
+    let mut output = String::with_capacity(src.len());
+    let mut interpreter = Interpreter::new(*dialect);
+    let mut extruder = Extruder::default();
+    let mut last_command = None;
+    // where everything was when the cancelled object started
+    let mut cancelling: Option<(MachineState, Extruder)> = None;
+
+    for (number, text) in src.split_inclusive('\n').enumerate() {
+        if let Some(marker) = object_marker(text) {
+            let (starts_cancelled, ends_cancelled, belongs_to_cancelled) =
+                match marker {
+                    ObjectMarker::Define(ref id) => {
+                        (false, false, cancelled.contains(&id.as_str()))
+                    },
+                    ObjectMarker::Start { ref id, .. } => {
+                        let starts = cancelled.contains(&id.as_str());
+                        (starts, true, starts)
+                    },
+                    ObjectMarker::Name(_) => {
+                        (false, false, cancelling.is_some())
+                    },
+                    ObjectMarker::End => (false, true, cancelling.is_some()),
+                };
+
+            if ends_cancelled {
+                if let Some((state, extruder_before)) = cancelling.take() {
+                    skip_cancelled_object(
+                        &mut output,
+                        (&state, &extruder_before),
+                        (interpreter.state(), &extruder),
+                        dialect,
+                    );
+                }
+            }
+            if starts_cancelled {
+                cancelling = Some((*interpreter.state(), extruder));
+            }
+            if !belongs_to_cancelled {
+                push_lines(&mut output, &[text]);
+            }
+            continue;
+        }
+
+        let mut parser: Parser<'_, Nop> =
+            Parser::resume(text, Nop, *dialect, 0, number, last_command);
+        let mut replacement: Option<String> = None;
+
+        for line in &mut parser {
+            let _ = interpreter.process_line(&line);
+            line.gcodes().iter().for_each(|g| extruder.process(g));
+
+            if cancelling.is_none() || !line.gcodes().iter().any(is_motion) {
+                continue;
+            }
+
+            // keep everything except the motion
+            let mut remaining: Line<'_> = Line::default();
+            for gcode in line.gcodes().iter().filter(|g| !is_motion(g)) {
+                let _ = remaining.push_gcode(gcode.clone());
+            }
+
+            let replacement = replacement.get_or_insert_with(String::new);
+            if !remaining.is_empty() {
+                let _ = line_writer(replacement, dialect)
+                    .write_line_with_source(&remaining, text);
+            }
+        }
+        last_command = parser.last_command();
+
+        match replacement {
+            Some(replacement) => push_lines(&mut output, &[&replacement]),
+            None => push_lines(&mut output, &[text]),
+        }
+    }
+
+    if let Some((state, extruder_before)) = cancelling {
+        skip_cancelled_object(
+            &mut output,
+            (&state, &extruder_before),
+            (interpreter.state(), &extruder),
+            dialect,
+        );
+    }
+
+    Ok(output)
+}
+
+/// An object labelled for cancellation.
+#[derive(Debug, Clone, PartialEq)]
+struct LabelledObject {
+    id: String,
+    name: Option<String>,
+}
+
+fn labelled_objects(src: &str) -> Vec<LabelledObject> {
+    let mut objects: Vec<LabelledObject> = Vec::new();
+    let mut current = None;
+
+    for text in src.lines() {
+        let (id, name) = match object_marker(text) {
+            Some(ObjectMarker::Define(id)) => (id, None),
+            Some(ObjectMarker::Start { id, name }) => {
+                current = Some(id.clone());
+                (id, name)
+            },
+            Some(ObjectMarker::Name(name)) => match current {
+                Some(ref id) => (id.clone(), Some(name)),
+                None => continue,
+            },
+            Some(ObjectMarker::End) => {
+                current = None;
+                continue;
+            },
+            None => continue,
+        };
+
+        match objects.iter_mut().find(|object| object.id == id) {
+            Some(object) => {
+                if name.is_some() {
+                    object.name = name;
+                }
+            },
+            None => objects.push(LabelledObject { id, name }),
+        }
+    }
+
+    objects
+}
+
+/// A line which marks where a labelled object starts or stops.
+#[derive(Debug, Clone, PartialEq)]
+enum ObjectMarker {
+    /// Tell the firmware an object exists (`EXCLUDE_OBJECT_DEFINE`).
+    Define(String),
+    /// Start printing an object.
+    Start { id: String, name: Option<String> },
+    /// Name the object which was just started (`M486 A`).
+    Name(String),
+    /// Stop printing the current object.
+    End,
+}
+
+fn object_marker(text: &str) -> Option<ObjectMarker> {
+    let code = text.split(';').next().unwrap_or_default().trim();
+    let command_length = code
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(code.len());
+    let (command, params) = code.split_at(command_length);
+
+    if command.len() > 4 && command[..4].eq_ignore_ascii_case("M486") {
+        // the first parameter isn't separated by a space (e.g. "M486S1")
+        m486_marker(&code[4..])
+    } else if command.eq_ignore_ascii_case("M486") {
+        m486_marker(params)
+    } else if command.eq_ignore_ascii_case("EXCLUDE_OBJECT_DEFINE") {
+        klipper_object_name(params).map(ObjectMarker::Define)
+    } else if command.eq_ignore_ascii_case("EXCLUDE_OBJECT_START") {
+        klipper_object_name(params)
+            .map(|id| ObjectMarker::Start { id, name: None })
+    } else if command.eq_ignore_ascii_case("EXCLUDE_OBJECT_END") {
+        Some(ObjectMarker::End)
+    } else {
+        None
+    }
+}
+
+fn m486_marker(params: &str) -> Option<ObjectMarker> {
+    let mut id = None;
+    let mut name = None;
+    let mut rest = params.trim();
+
+    while let Some(letter) = rest.chars().next() {
+        let letter = letter.to_ascii_uppercase();
+        // a name takes up the rest of the line and may contain spaces
+        let end = if letter == 'A' {
+            rest.len()
+        } else {
+            rest.find(char::is_whitespace).unwrap_or(rest.len())
+        };
+        let value = rest[letter.len_utf8()..end].trim();
+
+        match letter {
+            'S' => id = Some(value.parse::<i64>().ok()?),
+            'A' => name = Some(String::from(value.trim_matches('"'))),
+            // e.g. "M486 T3" (the number of objects) or "M486 P1" (cancel
+            // an object while printing)
+            _ => {},
+        }
+
+        rest = rest[end..].trim_start();
+    }
+
+    match (id, name) {
+        (Some(id), _) if id < 0 => Some(ObjectMarker::End),
+        (Some(id), name) => Some(ObjectMarker::Start {
+            id: id.to_string(),
+            name,
+        }),
+        (None, Some(name)) => Some(ObjectMarker::Name(name)),
+        (None, None) => None,
+    }
+}
+
+fn klipper_object_name(params: &str) -> Option<String> {
+    params.split_whitespace().find_map(|param| {
+        let (key, value) = param.split_at(param.find('=')?);
+
+        if key.eq_ignore_ascii_case("NAME") {
+            Some(String::from(value[1..].trim_matches('"')))
+        } else {
+            None
+        }
+    })
+}
+
+fn is_motion(gcode: &GCode) -> bool {
+    gcode.mnemonic == Mnemonic::General
+        && gcode.major_number() <= 3
+        && gcode.minor_number() == 0
+}
+
+/// The extruder's position, which the [`Interpreter`] doesn't keep track of.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub(crate) struct Extruder {
+    pub(crate) relative: bool,
+    pub(crate) position: f32,
+}
+
+impl Extruder {
+    pub(crate) fn process(&mut self, gcode: &GCode) {
+        if gcode.minor_number() != 0 {
+            return;
+        }
+
+        match (gcode.mnemonic, gcode.major_number()) {
+            (Mnemonic::Miscellaneous, 82) | (Mnemonic::General, 90) => {
+                self.relative = false
+            },
+            (Mnemonic::Miscellaneous, 83) | (Mnemonic::General, 91) => {
+                self.relative = true
+            },
+            (Mnemonic::General, 92) => {
+                if let Some(e) = gcode.value_for('E') {
+                    self.position = e;
+                }
+            },
+            (Mnemonic::General, 0..=3) => {
+                if let Some(e) = gcode.value_for('E') {
+                    if self.relative {
+                        self.position += e;
+                    } else {
+                        self.position = e;
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Write the lines which leave the machine where a cancelled object's moves
+/// would have.
+fn skip_cancelled_object(
+    output: &mut String,
+    (state_before, extruder_before): (&MachineState, &Extruder),
+    (state_after, extruder_after): (&MachineState, &Extruder),
+    dialect: &Dialect,
+) {
+    const TOLERANCE: f32 = 1e-5;
+
+    let scale = match state_after.units {
+        Units::Inches => 1.0 / 25.4,
+        Units::Millimeters => 1.0,
+    };
+    let before = state_before.position;
+    let after = state_after.position;
+    let mut travel = GCode::new(Mnemonic::General, 0.0, Span::PLACEHOLDER);
+
+    for &(letter, start, end) in &[
+        ('X', before.x, after.x),
+        ('Y', before.y, after.y),
+        ('Z', before.z, after.z),
+    ] {
+        if libm::fabsf(end - start) > TOLERANCE {
+            let value = match state_after.positioning {
+                Positioning::Absolute => end,
+                Positioning::Relative => end - start,
+            };
+            let _ = travel.push_argument(Word::new(
+                letter,
+                value * scale,
+                Span::PLACEHOLDER,
+            ));
+        }
+    }
+
+    if let Some(feed_rate) = state_after.feed_rate {
+        if state_before.feed_rate != state_after.feed_rate {
+            let _ = travel.push_argument(Word::new(
+                'F',
+                feed_rate * scale,
+                Span::PLACEHOLDER,
+            ));
+        }
+    }
+
+    let mut lines: Vec<Line<'_>> = Vec::new();
+
+    if !travel.arguments().is_empty() {
+        let mut line = Line::default();
+        let _ = line.push_gcode(travel);
+        lines.push(line);
+    }
+
+    if !extruder_after.relative
+        && libm::fabsf(extruder_after.position - extruder_before.position)
+            > TOLERANCE
+    {
+        let reset = GCode::new(Mnemonic::General, 92.0, Span::PLACEHOLDER)
+            .with_argument(Word::new(
+                'E',
+                extruder_after.position,
+                Span::PLACEHOLDER,
+            ));
+        let mut line = Line::default();
+        let _ = line.push_gcode(reset);
+        lines.push(line);
+    }
+
+    if !lines.is_empty() && !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+
+    let mut writer = line_writer(output, dialect);
+    for line in &lines {
+        let _ = writer.write_line(line);
+    }
+}
+
+/// What [`clamp_rapids()`] does with a rapid move which would leave the
+/// machine's work envelope.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum RapidLimit {
+    /// Move each axis to the nearest point within its travel, which may
+    /// change the direction the tool travels in.
+    Clamp,
+    /// Follow the original direction until the tool reaches the edge of the
+    /// envelope, then continue along the edge to the clamped end point.
+    Split,
+}
+
+/// Settings for [`clamp_rapids()`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct RapidClampConfig {
+    /// How moves which leave the envelope are brought back inside.
+    pub limit: RapidLimit,
+    /// Add a comment after each rapid which was changed, naming the axes
+    /// which were clamped.
+    pub annotate: bool,
+}
+
+impl Default for RapidClampConfig {
+    fn default() -> RapidClampConfig {
+        RapidClampConfig {
+            limit: RapidLimit::Clamp,
+            annotate: true,
+        }
+    }
+}
+
+/// Make sure rapid moves (`G0`) never go past the travel limits of a
+/// [`MachineProfile`]'s `X`, `Y` and `Z` axes, e.g. when running a program
+/// written for a larger machine.
+///
+/// Only the axis words of an offending rapid are rewritten, so the rest of
+/// the program is copied across verbatim. Cutting moves are never touched,
+/// because clamping them would ruin the part; use
+/// [`MachineProfile::validate()`] to find those. Like `validate()`, limits
+/// are compared against the program's coordinates.
+///
+/// With relative positioning (`G91`) every later move starts wherever the
+/// clamped rapid actually left the tool.
+///
+/// ```rust
+/// use gcode::{
+///     profile::{Axis, MachineProfile},
+///     transform::{self, RapidClampConfig, RapidLimit},
+/// };
+///
+/// let mut profile = MachineProfile::new("Desktop Mill");
+/// profile.axes = vec![
+///     Axis::new('X', 0.0, 200.0),
+///     Axis::new('Y', 0.0, 150.0),
+///     Axis::new('Z', -50.0, 0.0),
+/// ];
+/// let src = "G0 X250 Y100 (park)\nG1 X10 F300\n";
+///
+/// let clamped =
+///     transform::clamp_rapids(src, &profile, &RapidClampConfig::default());
+/// assert_eq!(
+///     clamped,
+///     "G0 X200 Y100 (clamped X to travel limits) (park)\nG1 X10 F300\n"
+/// );
+///
+/// // keep heading in the same direction for as long as possible
+/// let config = RapidClampConfig {
+///     limit: RapidLimit::Split,
+///     annotate: false,
+/// };
+/// let split = transform::clamp_rapids(src, &profile, &config);
+/// assert_eq!(split, "G0 X200 Y80\nG0 X200 Y100 (park)\nG1 X10 F300\n");
+/// ```
+pub fn clamp_rapids(
+    src: &str,
+    profile: &MachineProfile,
+    config: &RapidClampConfig,
+) -> String {
+    let dialect = profile.dialect();
+    let mut interpreter = Interpreter::new(dialect);
+    let mut edits: Vec<(Span, String)> = Vec::new();
+
+    for line in Parser::<_>::new_with_dialect(src, Nop, dialect) {
+        let before = *interpreter.state();
+        let motion = interpreter.process_line(&line);
+
+        let (motion, rapid) = match (motion, rapid_in(&line)) {
+            (Some(motion), Some(rapid)) if motion.is_rapid() => (motion, rapid),
+            _ => continue,
+        };
+
+        let end = clamp_to_travel(profile, motion.end);
+        let clamped: Vec<char> = ['X', 'Y', 'Z']
+            .iter()
+            .copied()
+            .filter(|&letter| {
+                libm::fabsf(
+                    component(end, letter) - component(motion.end, letter),
+                ) > CLAMP_TOLERANCE
+            })
+            .collect();
+
+        if clamped.is_empty() {
+            continue;
+        }
+
+        let mut state = *interpreter.state();
+        let mut start = motion.start;
+
+        let same_modes = before.units == state.units
+            && before.positioning == state.positioning;
+        if config.limit == RapidLimit::Split && same_modes {
+            if let Some(exit) = envelope_exit(profile, motion.start, motion.end)
+            {
+                if exit.distance_to(end) > CLAMP_TOLERANCE
+                    && exit.distance_to(start) > CLAMP_TOLERANCE
+                {
+                    edits.push(split_rapid(
+                        src,
+                        rapid,
+                        &dialect,
+                        &state,
+                        (start, exit),
+                    ));
+                    start = exit;
+                }
+            }
+        }
+
+        let after = rewrite_rapid(
+            src,
+            rapid,
+            &dialect,
+            &state,
+            (start, end),
+            &clamped,
+            &mut edits,
+        );
+
+        if config.annotate {
+            let letters: Vec<String> =
+                clamped.iter().map(|letter| letter.to_string()).collect();
+            edits.push((
+                Span::new(after, after, line.span().line),
+                format!(" (clamped {} to travel limits)", letters.join(", ")),
+            ));
+        }
+
+        // the machine really ends up at the clamped position
+        state.position = end;
+        interpreter = Interpreter::with_state(dialect, state);
+    }
+
+    let mut clamped = String::with_capacity(src.len());
+    let mut cursor = 0;
+
+    for (span, replacement) in edits {
+        clamped.push_str(&src[cursor..span.start]);
+        clamped.push_str(&replacement);
+        cursor = span.end;
+    }
+    clamped.push_str(&src[cursor..]);
+
+    clamped
+}
+
+/// Differences smaller than this (in millimeters) are just rounding errors.
+const CLAMP_TOLERANCE: f32 = 1e-4;
+
+/// The `G0` responsible for a line's rapid move, if it came from one.
+fn rapid_in<'a, 'input>(line: &'a Line<'input>) -> Option<&'a GCode> {
+    line.gcodes().iter().rev().find(|gcode| {
+        gcode.key() == CommandKey::general(0)
+            && gcode
+                .arguments()
+                .iter()
+                .any(|arg| is_linear_axis(arg.letter))
+    })
+}
+
+fn is_linear_axis(letter: char) -> bool {
+    matches!(letter.to_ascii_uppercase(), 'X' | 'Y' | 'Z')
+}
+
+fn component(position: Position, letter: char) -> f32 {
+    match letter {
+        'X' => position.x,
+        'Y' => position.y,
+        _ => position.z,
+    }
+}
+
+/// The `(min, max)` travel of each of the `X`, `Y` and `Z` axes.
+fn travel(profile: &MachineProfile, letter: char) -> (f32, f32) {
+    profile
+        .axis(letter)
+        .map_or((f32::NEG_INFINITY, f32::INFINITY), |axis| {
+            (axis.min, axis.max)
+        })
+}
+
+fn clamp_to_travel(profile: &MachineProfile, position: Position) -> Position {
+    let clamp = |letter| {
+        let (min, max) = travel(profile, letter);
+        component(position, letter).max(min).min(max)
+    };
+
+    Position::new(clamp('X'), clamp('Y'), clamp('Z'))
+}
+
+/// Where a straight move from `start` (which must be inside the envelope)
+/// to `end` first leaves the envelope, if it does.
+fn envelope_exit(
+    profile: &MachineProfile,
+    start: Position,
+    end: Position,
+) -> Option<Position> {
+    let mut fraction: f32 = 1.0;
+
+    for &letter in &['X', 'Y', 'Z'] {
+        let (min, max) = travel(profile, letter);
+        let (from, to) = (component(start, letter), component(end, letter));
+
+        if from < min - CLAMP_TOLERANCE || from > max + CLAMP_TOLERANCE {
+            return None;
+        }
+
+        let limit = if to > max {
+            max
+        } else if to < min {
+            min
+        } else {
+            continue;
+        };
+        fraction = fraction.min((limit - from) / (to - from));
+    }
+
+    if fraction >= 1.0 {
+        None
+    } else {
+        Some(start.lerp(end, fraction))
+    }
+}
+
+/// The value to write for an axis word, in the program's units and
+/// positioning mode.
+fn axis_value(
+    state: &MachineState,
+    letter: char,
+    start: Position,
+    end: Position,
+) -> f32 {
+    let value = match state.positioning {
+        Positioning::Absolute => component(end, letter),
+        Positioning::Relative => {
+            component(end, letter) - component(start, letter)
+        },
+    };
+
+    match state.units {
+        Units::Inches => value / 25.4,
+        Units::Millimeters => value,
+    }
+}
+
+/// Insert a rapid to the point where a move leaves the envelope, on its own
+/// line just before the original one.
+fn split_rapid(
+    src: &str,
+    rapid: &GCode,
+    dialect: &Dialect,
+    state: &MachineState,
+    (start, exit): (Position, Position),
+) -> (Span, String) {
+    let first = rapid.arguments()[0].span;
+    let line_start = src[..first.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_ending = match src[line_start..].find('\n') {
+        Some(i) if src[..line_start + i].ends_with('\r') => "\r\n",
+        _ => "\n",
+    };
+
+    let mut gcode = GCode::new(Mnemonic::General, 0.0, Span::PLACEHOLDER);
+    for &letter in &['X', 'Y', 'Z'] {
+        let mentioned = rapid
+            .arguments()
+            .iter()
+            .any(|arg| arg.letter.eq_ignore_ascii_case(&letter));
+        let moves =
+            libm::fabsf(component(exit, letter) - component(start, letter))
+                > CLAMP_TOLERANCE;
+
+        if mentioned || moves {
+            let value = axis_value(state, letter, start, exit);
+            let _ = gcode.push_argument(Word::new(
+                letter,
+                value,
+                Span::PLACEHOLDER,
+            ));
+        }
+    }
+
+    let mut text = String::new();
+    let _ = Writer::for_dialect(&mut text, dialect).write_gcode(&gcode);
+    text.push_str(line_ending);
+
+    (Span::new(line_start, line_start, first.line), text)
+}
+
+/// Point a rapid's axis words at its new end point, adding words for any
+/// axes it didn't mention, and return where its arguments end.
+fn rewrite_rapid(
+    src: &str,
+    rapid: &GCode,
+    dialect: &Dialect,
+    state: &MachineState,
+    (start, end): (Position, Position),
+    clamped: &[char],
+    edits: &mut Vec<(Span, String)>,
+) -> usize {
+    let write_word =
+        |letter: char| {
+            let value =
+                axis_value(state, letter.to_ascii_uppercase(), start, end);
+            let mut text = String::new();
+            let _ = Writer::for_dialect(&mut text, dialect)
+                .write_word(&Word::new(letter, value, Span::PLACEHOLDER));
+            text
+        };
+    let arguments = rapid.arguments();
+    let last = arguments[arguments.len() - 1].span;
+
+    for arg in arguments.iter().filter(|arg| is_linear_axis(arg.letter)) {
+        let new_text = write_word(arg.letter);
+        if src[arg.span.start..arg.span.end] != new_text {
+            edits.push((arg.span, new_text));
+        }
+    }
+
+    // an axis which was already outside its travel needs to be moved back,
+    // even though the rapid didn't mention it
+    for &letter in clamped {
+        let mentioned = arguments
+            .iter()
+            .any(|arg| arg.letter.eq_ignore_ascii_case(&letter));
+
+        if !mentioned {
+            edits.push((
+                Span::new(last.end, last.end, last.line),
+                format!(" {}", write_word(letter)),
+            ));
+        }
+    }
+
+    last.end
+}
+
+/// Settings for [`repair()`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize),
+    serde(default)
+)]
+pub struct RepairConfig {
+    /// Split lines which were run together when their newline went missing
+    /// (e.g. `G1 X10G1 X20`).
+    pub merged_lines: bool,
+    /// Cut a number short at its second decimal point (e.g. `X1..5`).
+    pub doubled_decimal_points: bool,
+    /// Remove nulls and other control characters.
+    pub control_characters: bool,
+    /// Drop a final line which was obviously cut off part way through.
+    pub truncated_last_line: bool,
+}
+
+impl Default for RepairConfig {
+    fn default() -> RepairConfig {
+        RepairConfig {
+            merged_lines: true,
+            doubled_decimal_points: true,
+            control_characters: true,
+            truncated_last_line: true,
+        }
+    }
+}
+
+/// Something [`repair()`] changed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum FixKind {
+    /// A newline was inserted before a command which had been glued onto
+    /// the previous line.
+    SplitMergedLine,
+    /// A number with more than one decimal point was cut short.
+    DoubledDecimalPoint,
+    /// A run of control characters was removed.
+    ControlCharacters,
+    /// The last line was removed because it had been cut short.
+    TruncatedLastLine,
+}
+
+impl Display for FixKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FixKind::SplitMergedLine => write!(f, "split merged lines"),
+            FixKind::DoubledDecimalPoint => {
+                write!(f, "removed a doubled decimal point")
+            },
+            FixKind::ControlCharacters => {
+                write!(f, "removed control characters")
+            },
+            FixKind::TruncatedLastLine => {
+                write!(f, "removed a truncated last line")
+            },
+        }
+    }
+}
+
+/// A single change made by [`repair()`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Fix {
+    /// What was changed.
+    pub kind: FixKind,
+    /// The damaged text, in the original program.
+    pub span: Span,
+}
+
+/// The result of [`repair()`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Repaired {
+    /// The repaired program.
+    pub program: String,
+    /// Every change which was made, in the order they appear.
+    pub fixes: Vec<Fix>,
+}
+
+/// Fix common kinds of damage to a program, like a file which was
+/// corrupted in transit or cut short by a full SD card.
+///
+/// These are only heuristics, so every change is reported as a [`Fix`] to
+/// be checked by a human:
+///
+/// - A `G`, `M` or `N` word stuck directly onto the number of an argument
+///   starts a new line, as long as the line also uses spaces between its words
+///   (so compact programs like `G0X1Y2` are left alone)
+/// - A number with more than one decimal point is cut short at the second one,
+///   the same way a [lenient][crate::dialect::Dialect::lenient] parser reads it
+///   (e.g. `X1.2.3` becomes `X1.2` and `X1..5` becomes `X1`)
+/// - Nulls and other control characters (except tabs and line endings) are
+///   removed
+/// - If the program doesn't end with a newline, and its last line ends part way
+///   through a word, with an unclosed comment or with an incomplete checksum,
+///   that line is removed. A letter on its own is assumed to be a flag (e.g.
+///   the `E` in `M84 X Y E`) unless it is a command letter or is stuck onto the
+///   end of the previous word, and a truncated number can't be detected.
+///
+/// Comments are never changed, except to remove control characters.
+///
+/// ```rust
+/// use gcode::transform::{self, FixKind, RepairConfig};
+///
+/// let src = "G1 X10G1 X20\nG1 Y1..5\u{0}\u{0}\nG1 X3 Y-";
+///
+/// let repaired = transform::repair(src, &RepairConfig::default());
+///
+/// assert_eq!(repaired.program, "G1 X10\nG1 X20\nG1 Y1\n");
+/// let fixes: Vec<_> = repaired
+///     .fixes
+///     .iter()
+///     .map(|fix| (fix.kind, fix.span.line, &src[fix.span.start..fix.span.end]))
+///     .collect();
+/// assert_eq!(
+///     fixes,
+///     vec![
+///         (FixKind::SplitMergedLine, 0, "G"),
+///         (FixKind::DoubledDecimalPoint, 1, "..5"),
+///         (FixKind::ControlCharacters, 1, "\u{0}\u{0}"),
+///         (FixKind::TruncatedLastLine, 2, "G1 X3 Y-"),
+///     ]
+/// );
+/// ```
+pub fn repair(src: &str, config: &RepairConfig) -> Repaired {
+    let mut program = String::with_capacity(src.len());
+    let mut fixes = Vec::new();
+    let mut start = 0;
+
+    for (line_number, text) in src.split_inclusive('\n').enumerate() {
+        let content = text.trim_end_matches(&['\r', '\n'][..]);
+        let line_ending = &text[content.len()..];
+        let mut repaired = String::with_capacity(content.len());
+        let mut line_fixes = Vec::new();
+
+        repair_line(
+            content,
+            start,
+            line_number,
+            line_ending,
+            config,
+            &mut repaired,
+            &mut line_fixes,
+        );
+
+        let last_line = start + text.len() == src.len();
+        if config.truncated_last_line
+            && last_line
+            && line_ending.is_empty()
+            && is_truncated(&repaired)
+        {
+            // everything else on the line goes with it
+            line_fixes.clear();
+            line_fixes.push(Fix {
+                kind: FixKind::TruncatedLastLine,
+                span: Span::new(start, src.len(), line_number),
+            });
+        } else {
+            program.push_str(&repaired);
+            program.push_str(line_ending);
+        }
+
+        fixes.extend(line_fixes);
+        start += text.len();
+    }
+
+    Repaired { program, fixes }
+}
+
+fn repair_line(
+    content: &str,
+    start: usize,
+    line_number: usize,
+    line_ending: &str,
+    config: &RepairConfig,
+    repaired: &mut String,
+    fixes: &mut Vec<Fix>,
+) {
+    let mut in_comment = None;
+    // the letter at the start of the current word
+    let mut word_letter = None;
+    // where the current word's decimal point is
+    let mut point = None;
+    let mut spaced = false;
+    let mut chars = content.char_indices().peekable();
+
+    let mut fix = |kind, from: usize, to: usize| {
+        let span = Span::new(start + from, start + to, line_number);
+        match fixes.last_mut() {
+            // merge runs of control characters into a single fix
+            Some(previous)
+                if kind == FixKind::ControlCharacters
+                    && previous.kind == kind
+                    && previous.span.end == span.start =>
+            {
+                previous.span = previous.span.merge(span);
+            },
+            _ => fixes.push(Fix { kind, span }),
+        }
+    };
+
+    while let Some((i, c)) = chars.next() {
+        let end = i + c.len_utf8();
+
+        if c.is_control() && c != '\t' {
+            if config.control_characters {
+                fix(FixKind::ControlCharacters, i, end);
+                continue;
+            }
+        } else if let Some(close) = in_comment {
+            if c == close {
+                in_comment = None;
+            }
+        } else if c == '(' || c == ';' {
+            in_comment = Some(if c == '(' { ')' } else { '\n' });
+            word_letter = None;
+        } else if c.is_whitespace() {
+            if word_letter.is_some() {
+                spaced = true;
+            }
+            word_letter = None;
+        } else if c == '.' && word_letter.is_some() {
+            match point {
+                Some(first) if config.doubled_decimal_points => {
+                    // everything from the second point onwards is ignored
+                    let mut to = end;
+                    while let Some(&(j, next)) = chars.peek() {
+                        if !next.is_ascii_digit() && next != '.' {
+                            break;
+                        }
+                        to = j + next.len_utf8();
+                        let _ = chars.next();
+                    }
+
+                    // and "1." is just "1"
+                    let from = if repaired.ends_with('.') {
+                        let _ = repaired.pop();
+                        first
+                    } else {
+                        i
+                    };
+
+                    fix(FixKind::DoubledDecimalPoint, from, to);
+                    continue;
+                },
+                Some(_) => {},
+                None => point = Some(i),
+            }
+        } else if c.is_ascii_alphabetic() {
+            let is_command = |letter: char| {
+                matches!(letter.to_ascii_uppercase(), 'G' | 'M' | 'N')
+            };
+            let glued = repaired
+                .chars()
+                .last()
+                .is_some_and(|previous| previous.is_ascii_digit());
+            let followed_by_number =
+                chars.peek().is_some_and(|&(_, next)| next.is_ascii_digit());
+
+            if config.merged_lines
+                && spaced
+                && glued
+                && followed_by_number
+                && is_command(c)
+                && word_letter.is_some_and(|letter| !is_command(letter))
+            {
+                fix(FixKind::SplitMergedLine, i, end);
+                // use the same line ending as the rest of the file
+                repaired.push_str(if line_ending.is_empty() {
+                    "\n"
+                } else {
+                    line_ending
+                });
+                spaced = false;
+            }
+
+            word_letter = Some(c);
+            point = None;
+        }
+
+        repaired.push(c);
+    }
+}
+
+/// Was the text at the end of a file obviously cut off part way through?
+fn is_truncated(line: &str) -> bool {
+    let mut in_comment = false;
+
+    for c in line.chars() {
+        match c {
+            ';' if !in_comment => return false,
+            '(' => in_comment = true,
+            ')' => in_comment = false,
+            _ => {},
+        }
+    }
+
+    if in_comment {
+        return true;
+    }
+
+    let mut rest = line.trim_end().chars().rev();
+
+    match rest.next() {
+        // a letter on its own is a flag (e.g. the E in "M84 X Y E"), unless
+        // it's a command or stuck onto the end of the previous word
+        Some(c) if c.is_ascii_alphabetic() => {
+            matches!(c.to_ascii_uppercase(), 'G' | 'M' | 'N' | 'O')
+                || rest
+                    .next()
+                    .is_some_and(|previous| !previous.is_whitespace())
+        },
+        Some(c) => matches!(c, '-' | '+' | '.' | '*'),
+        None => false,
+    }
+}
+
+/// The order [`reorder_words()`] puts the words on a line in, written as a
+/// string of letters (e.g. `"NGXYZIJKFSTM"`).
+///
+/// Letters which aren't mentioned go after the ones which are, in the order
+/// they were written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct WordOrder {
+    letters: String,
+}
+
+impl WordOrder {
+    /// Like [`WordOrder::STANDARD`], with the extruder after the other axes
+    /// (e.g. `G1 X10 Y20 E0.5 F1500`).
+    pub const REPRAP: &'static str = "NGXYZABCUVWEIJKFSTM";
+    /// The conventional order: line number, `G` codes, axes, arc centers,
+    /// feed, spindle speed, tool and `M` codes.
+    pub const STANDARD: &'static str = "NGXYZABCUVWIJKFSTM";
+
+    /// Create a [`WordOrder`] from a string of letters.
+    pub fn new<S: Into<String>>(letters: S) -> Self {
+        WordOrder {
+            letters: letters.into().to_ascii_uppercase(),
+        }
+    }
+
+    /// The order conventionally used by a particular dialect.
+    pub fn for_dialect(dialect: DialectName) -> Self {
+        match dialect {
+            DialectName::RepRap => WordOrder::new(WordOrder::REPRAP),
+            _ => WordOrder::default(),
+        }
+    }
+
+    /// The letters, in order.
+    pub fn letters(&self) -> &str { &self.letters }
+
+    /// Where a letter goes, relative to the others.
+    fn rank(&self, letter: char) -> usize {
+        let letter = letter.to_ascii_uppercase();
+
+        self.letters
+            .chars()
+            .position(|c| c == letter)
+            .unwrap_or(self.letters.len())
+    }
+}
+
+impl Default for WordOrder {
+    fn default() -> WordOrder { WordOrder::new(WordOrder::STANDARD) }
+}
+
+/// A word which [`reorder_words()`] moved.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct WordMove {
+    /// The word's letter.
+    pub letter: char,
+    /// Where the word was in the original program.
+    pub from: Span,
+    /// Where the word is in the reordered program.
+    pub to: Span,
+}
+
+/// The result of [`reorder_words()`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Reordered {
+    /// The reordered program.
+    pub program: String,
+    /// Every word which moved, in the order they appear in the new program.
+    pub moves: Vec<WordMove>,
+    /// Lines with several commands which are out of order, but were left
+    /// that way (see [`reorder_words()`]).
+    pub kept_order: Vec<Span>,
+}
+
+/// Put the words on each line into a conventional [`WordOrder`], for
+/// controllers (or people) which expect e.g. `G1 X10 Y20 F300` rather than
+/// `G1 F300 Y20 X10`.
+///
+/// Arguments are only ever moved around within the command they belong to,
+/// so `M3 S1000` never turns into `S1000 M3`, and commands are never
+/// reordered because that could change the order they run in (e.g. `G91 G0
+/// X10` and `G0 G91 X10`). Lines whose commands are out of order are left
+/// that way and listed in [`Reordered::kept_order`].
+///
+/// Words are moved as-is, so spacing, comments and the way numbers are
+/// written are all kept, and every [`WordMove`] says where a word came from.
+///
+/// ```rust
+/// use gcode::{
+///     dialect::Dialect,
+///     transform::{self, WordOrder},
+/// };
+///
+/// let src = "G1 F300 Y20 X10 (cut)\nM6 T2\nG0 Z5 M3 S1000\n";
+///
+/// let order = WordOrder::default();
+/// let got = transform::reorder_words(src, &Dialect::generic(), &order);
+///
+/// assert_eq!(
+///     got.program,
+///     "G1 X10 Y20 F300 (cut)\nM6 T2\nG0 Z5 M3 S1000\n"
+/// );
+/// let moved: Vec<_> = got
+///     .moves
+///     .iter()
+///     .map(|m| (m.letter, &src[m.from.start..m.from.end]))
+///     .collect();
+/// assert_eq!(moved, vec![('X', "X10"), ('F', "F300")]);
+/// // the tool change should come before M6
+/// assert_eq!(got.kept_order.len(), 1);
+/// assert_eq!(got.kept_order[0].line, 1);
+/// ```
+pub fn reorder_words(
+    src: &str,
+    dialect: &Dialect,
+    order: &WordOrder,
+) -> Reordered {
+    // (where the word goes, the word being put there)
+    let mut edits: Vec<(Span, Word)> = Vec::new();
+    let mut kept_order = Vec::new();
+
+    for line in Parser::<_>::new_with_dialect(src, Nop, *dialect) {
+        let ranks: Vec<usize> = line
+            .gcodes()
+            .iter()
+            .map(|gcode| order.rank(command_letter(gcode.mnemonic())))
+            .collect();
+        if ranks.windows(2).any(|pair| pair[0] > pair[1]) {
+            kept_order.push(line.span());
+        }
+
+        for gcode in line.gcodes() {
+            let mut words: Vec<Word> = gcode.arguments().to_vec();
+            if words.iter().any(|word| word.span.is_placeholder()) {
+                continue;
+            }
+
+            words.sort_by_key(|word| word.span.start);
+            let slots: Vec<Span> = words.iter().map(|word| word.span).collect();
+            words.sort_by_key(|word| order.rank(word.letter));
+
+            edits.extend(
+                slots
+                    .into_iter()
+                    .zip(words)
+                    .filter(|(slot, word)| *slot != word.span),
+            );
+        }
+    }
+
+    edits.sort_by_key(|(slot, _)| slot.start);
+
+    let mut program = String::with_capacity(src.len());
+    let mut moves = Vec::with_capacity(edits.len());
+    let mut cursor = 0;
+
+    for (slot, word) in edits {
+        program.push_str(&src[cursor..slot.start]);
+        let start = program.len();
+        program.push_str(&src[word.span.start..word.span.end]);
+        moves.push(WordMove {
+            letter: word.letter,
+            from: word.span,
+            to: Span::new(start, program.len(), slot.line),
+        });
+        cursor = slot.end;
+    }
+    program.push_str(&src[cursor..]);
+
+    Reordered {
+        program,
+        moves,
+        kept_order,
+    }
+}
+
+fn command_letter(mnemonic: Mnemonic) -> char {
+    match mnemonic {
+        Mnemonic::General => 'G',
+        Mnemonic::Miscellaneous => 'M',
+        Mnemonic::ProgramNumber => 'O',
+        Mnemonic::ToolChange => 'T',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn programs_without_tool_changes_are_untouched() {
+        let src = "G0 X1\nG1 X2 F100";
+
+        let got = split_by_tool(src, &Dialect::generic());
+
+        assert_eq!(
+            got,
+            vec![ToolJob {
+                tool: None,
+                program: String::from(src)
+            }]
+        );
+    }
+
+    #[test]
+    fn repeated_tools_share_a_job() {
+        let src = "T1 M6\nG0 X1\nT2 M6\nG0 X2\nT1 M6\nG20 G0 X3\nM30";
+
+        let got = split_by_tool(src, &Dialect::generic());
+
+        assert_eq!(got.len(), 2);
+        assert_eq!(
+            got[0].program,
+            "T1 M6\nG0 X1\nG21 G90 G17 G54\nT1 M6\nG20 G0 X3\nM30"
+        );
+        assert_eq!(got[1].program, "G21 G90 G17 G54\nT2 M6\nG0 X2\nM30");
+    }
+
+    #[test]
+    fn printers_change_tools_immediately() {
+        let src = "G28\nT0\nG1 X10 F100\nT1\nG1 X20\n";
+
+        let got = split_by_tool(src, &Dialect::reprap());
+
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].program, "G28\nT0\nG1 X10 F100\n");
+        assert_eq!(got[1].program, "G28\nG21 G90 G17 G54\nT1\nG1 X20\n");
+    }
+
+    #[test]
+    fn preparatory_tool_selections_are_repeated() {
+        let src = "T1\nM6\nG0 X1\nT2\nG0 X2\nM6\nG0 X3\nM30";
+
+        let got = split_by_tool(src, &Dialect::fanuc());
+
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].tool, Some(ToolSelection::tool(1)));
+        assert_eq!(got[0].program, "T1\nM6\nG0 X1\nT2\nG0 X2\nM30");
+        assert_eq!(got[1].tool, Some(ToolSelection::tool(2)));
+        assert_eq!(got[1].program, "T1\nG21 G90 G17 G54\nT2\nM6\nG0 X3\nM30");
+    }
+
+    #[test]
+    fn restore_extended_coordinate_systems() {
+        let src = "T1 M6\nG59.2 G20\nT2 M6\nM2";
+
+        let got = split_by_tool(src, &Dialect::fanuc());
+
+        assert_eq!(got[1].program, "G20 G90 G17 G59.2\nT2 M6\nM2");
+    }
+
+    fn sanitize_with_defaults(src: &str) -> String {
+        sanitize(src, &Dialect::reprap(), &SanitizeConfig::default())
+    }
+
+    #[test]
+    fn functional_content_is_untouched() {
+        let src = "G21 G90\r\nG1 X10.5 Y-3 F1200\r\nM104 S200\r\n";
+
+        assert_eq!(sanitize_with_defaults(src), src);
+    }
+
+    #[test]
+    fn redact_paths_in_comments() {
+        let src = "(C:\\Users\\bob\\part.nc)\n\
+                   ; file=/Users/bob/Desktop/part.stl\n\
+                   ; config ~/slicer.ini, \\\\nas\\jobs\\part.nc\n\
+                   ; ratio 3/4 and /layer\n";
+
+        let got = sanitize_with_defaults(src);
+
+        assert_eq!(
+            got,
+            "([redacted])\n\
+             ; file=[redacted]\n\
+             ; config [redacted], [redacted]\n\
+             ; ratio 3/4 and /layer\n"
+        );
+    }
+
+    #[test]
+    fn redact_timestamps_in_comments() {
+        let src = "; 2024-03-01T10:15:00.25 and 01/03/2024\n\
+                   ; started at 9:05, layer 1.25, TIME:1234 25:99\n";
+
+        let got = sanitize_with_defaults(src);
+
+        assert_eq!(
+            got,
+            "; [redacted] and [redacted]\n\
+             ; started at [redacted], layer 1.25, TIME:1234 25:99\n"
+        );
+    }
+
+    #[test]
+    fn remove_network_configuration() {
+        let src = "M550 P\"Bob's printer\"\nM552 S1 P192.168.1.10\n\
+                   M587 S\"wifi\" P\"pass(word\"\nG28\nM555 P2\n";
+
+        assert_eq!(sanitize_with_defaults(src), "G28\nM555 P2\n");
+    }
+
+    #[test]
+    fn remove_matching_comments() {
+        let src = "; Licensed to ACME Corp\nG1 X1 (acme corp) ; keep me\n";
+        let config = SanitizeConfig {
+            remove: vec![String::from("ACME")],
+            ..Default::default()
+        };
+
+        let got = sanitize(src, &Dialect::reprap(), &config);
+
+        assert_eq!(got, "G1 X1 ; keep me\n");
+    }
+
+    #[test]
+    fn redacted_text_ignores_case() {
+        let src = "; BOB's part for bob (by Bob)";
+        let config = SanitizeConfig {
+            redact: vec![String::from("bob")],
+            replacement: String::from("***"),
+            ..Default::default()
+        };
+
+        let got = sanitize(src, &Dialect::reprap(), &config);
+
+        assert_eq!(got, "; ***'s part for *** (by ***)");
+    }
+
+    #[test]
+    fn invalid_axis_maps_are_rejected() {
+        let inputs = [
+            (AxisMap::new().with('X', 'F'), RemapError::NotAnAxis('F')),
+            (AxisMap::new().with('I', 'J'), RemapError::NotAnAxis('I')),
+            (
+                AxisMap::new().with('X', 'A').with('X', 'B'),
+                RemapError::DuplicateAxis('X'),
+            ),
+            (
+                AxisMap::new().with('X', 'A').with('Y', 'A'),
+                RemapError::DuplicateTarget('A'),
+            ),
+        ];
+
+        for (map, should_be) in &inputs {
+            assert_eq!(map.validate(), Err(*should_be));
+        }
+    }
+
+    #[test]
+    fn renamed_axes_cant_collide_with_existing_ones() {
+        let src = "G1 E1\nG1 A5 E2\n";
+        let map = AxisMap::new().with('E', 'A');
+
+        let got = remap_axes(src, &Dialect::reprap(), &map);
+
+        assert_eq!(
+            got,
+            Err(RemapError::Collision {
+                letter: 'A',
+                line: 1
+            })
+        );
+    }
+
+    #[test]
+    fn arcs_follow_their_plane() {
+        let src = "G18\ng02 x1 z1 i1 k0\nG17 G3 X1 Y1 R1\n";
+        let map = AxisMap::new().swap('X', 'Z');
+
+        let got = remap_axes(src, &Dialect::generic(), &map).unwrap();
+
+        assert_eq!(got, "G18\ng03 z1 x1 k1 i0\nG19 G2 Z1 Y1 R1\n");
+    }
+
+    #[test]
+    fn only_arcs_and_back_boring_have_axis_offsets() {
+        let src = "G2 X1 Y1 I1 J0\nX2 Y0 I0 J-1\nG76 P1 Z-1 I0.1 J0.2 K1\n\
+                   G87 X5 I1 J2 K-3 R1 Z-5\nG80\n";
+        let map = AxisMap::new().swap('X', 'Y');
+
+        let got = remap_axes(src, &Dialect::generic(), &map).unwrap();
+
+        assert_eq!(
+            got,
+            "G3 Y1 X1 J1 I0\nY2 X0 J0 I-1\nG76 P1 Z-1 I0.1 J0.2 K1\n\
+             G87 Y5 J1 I2 K-3 R1 Z-5\nG80\n"
+        );
+    }
+
+    #[test]
+    fn flags_and_expressions_are_renamed() {
+        let src = "G1 X1 E#1\nM84 E\nG92 E\n";
+        let map = AxisMap::new().with('E', 'A');
+
+        let got = remap_axes(src, &Dialect::linuxcnc(), &map).unwrap();
+
+        assert_eq!(got, "G1 X1 A#1\nM84 A\nG92 A\n");
+    }
+
+    #[test]
+    fn arcs_cant_leave_the_standard_planes() {
+        let src = "G1 X1\nG2 X0 I-1 J0\n";
+        let map = AxisMap::new().with('X', 'A');
+
+        let got = remap_axes(src, &Dialect::generic(), &map);
+
+        assert_eq!(got, Err(RemapError::ArcOutsidePlane { line: 1 }));
+    }
+
+    #[test]
+    fn cancel_a_klipper_object() {
+        let src = "EXCLUDE_OBJECT_DEFINE NAME=cube CENTER=10,10\n\
+                   EXCLUDE_OBJECT_DEFINE NAME=cone CENTER=50,50\n\
+                   M83\n\
+                   EXCLUDE_OBJECT_START NAME=cube\n\
+                   G1 X10 Y10 F3000\n\
+                   G1 X20 E0.5 F1200 M106 S128 ; perimeter\n\
+                   ;LAYER_CHANGE\n\
+                   G1 Z0.4\n\
+                   EXCLUDE_OBJECT_END NAME=cube\n\
+                   EXCLUDE_OBJECT_START NAME=cone\n\
+                   G1 X50 Y50 E0.2\n\
+                   EXCLUDE_OBJECT_END NAME=cone\n";
+
+        let got = cancel_object(src, &Dialect::reprap(), "cube").unwrap();
+
+        assert_eq!(
+            got,
+            "EXCLUDE_OBJECT_DEFINE NAME=cone CENTER=50,50\n\
+             M83\n\
+             M106 S128\n\
+             ;LAYER_CHANGE\n\
+             G0 X20 Y10 Z0.4 F1200\n\
+             EXCLUDE_OBJECT_START NAME=cone\n\
+             G1 X50 Y50 E0.2\n\
+             EXCLUDE_OBJECT_END NAME=cone\n"
+        );
+    }
+
+    #[test]
+    fn the_last_object_may_not_be_closed() {
+        let src = "G21\nM486S0\nG1 X1 E1\nM486S1\nG1 X5 E2\n";
+
+        let got = cancel_object(src, &Dialect::reprap(), "1").unwrap();
+
+        assert_eq!(got, "G21\nM486S0\nG1 X1 E1\nG0 X5\nG92 E2\n");
+    }
+
+    #[test]
+    fn non_ascii_object_markers_dont_panic() {
+        let src = "M486 é\nM486 S0 Aété\nM486 S-1";
+
+        assert_eq!(object_labels(src), &["été"]);
+    }
+
+    #[test]
+    fn cancelling_an_unknown_object() {
+        let src = "M486 S0\nM486 A\"left bracket\"\nG1 X1\nM486 S-1";
+
+        assert_eq!(object_labels(src), &["left bracket"]);
+        let err = cancel_object(src, &Dialect::reprap(), "right bracket")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no object is labelled \"right bracket\" (expected one of: left \
+             bracket)"
+        );
+        assert!(cancel_object("G1 X1", &Dialect::reprap(), "0").is_err());
+    }
+
+    fn small_machine() -> MachineProfile {
+        let mut profile = MachineProfile::new("small");
+        profile.axes = vec![
+            crate::profile::Axis::new('X', 0.0, 100.0),
+            crate::profile::Axis::new('Y', 0.0, 100.0),
+        ];
+        profile
+    }
+
+    #[test]
+    fn relative_rapids_are_clamped_where_the_tool_really_is() {
+        let src = "G91\nG0 X150\nG0 X-20 Y10\nG1 X-200 F100\n";
+        let config = RapidClampConfig {
+            annotate: false,
+            ..Default::default()
+        };
+
+        let got = clamp_rapids(src, &small_machine(), &config);
+
+        // the cut is left alone, even though it leaves the envelope
+        assert_eq!(got, "G91\nG0 X100\nG0 X-20 Y10\nG1 X-200 F100\n");
+    }
+
+    #[test]
+    fn split_rapids_keep_their_direction() {
+        let src = "G20\r\nG0 X1 Y1\r\nG0 X5 Y3 (corner)\r\n";
+        let config = RapidClampConfig {
+            limit: RapidLimit::Split,
+            annotate: true,
+        };
+
+        let got = clamp_rapids(src, &small_machine(), &config);
+
+        // 100mm is 3.937 inches, and the original path leaves the envelope
+        // at X3.937 Y2.4685
+        assert_eq!(
+            got,
+            "G20\r\nG0 X1 Y1\r\nG0 X3.937 Y2.4685\r\nG0 X3.937 Y3 (clamped X \
+             to travel limits) (corner)\r\n"
+        );
+    }
+
+    #[test]
+    fn axes_which_start_outside_are_brought_back() {
+        let src = "G92 X120\nG0 Y10\n";
+
+        let got =
+            clamp_rapids(src, &small_machine(), &RapidClampConfig::default());
+
+        assert_eq!(got, "G92 X120\nG0 Y10 X100 (clamped X to travel limits)\n");
+    }
+
+    fn repair_with_defaults(src: &str) -> Repaired {
+        repair(src, &RepairConfig::default())
+    }
+
+    #[test]
+    fn undamaged_programs_are_left_alone() {
+        let src = "G0X1Y2G1X3\nG90G21 (X1..5 G1 X10G1)\nG1 X5 ; Y1..2\nM84";
+
+        let got = repair_with_defaults(src);
+
+        assert_eq!(got.program, src);
+        assert!(got.fixes.is_empty());
+    }
+
+    #[test]
+    fn merged_lines_keep_the_file_line_endings() {
+        let src = "N10 G1 X10N20 G1 X20 Y5M5\r\nG0 Z5\r\n";
+
+        let got = repair_with_defaults(src);
+
+        assert_eq!(
+            got.program,
+            "N10 G1 X10\r\nN20 G1 X20 Y5\r\nM5\r\nG0 Z5\r\n"
+        );
+        let starts: Vec<_> =
+            got.fixes.iter().map(|fix| fix.span.start).collect();
+        assert_eq!(starts, vec![10, 23]);
+    }
+
+    #[test]
+    fn files_padded_with_nulls() {
+        let src = "G1 X1\n\u{0}\u{0}\u{0}";
+
+        let got = repair_with_defaults(src);
+
+        // the nulls are removed, but the empty line is otherwise fine
+        assert_eq!(got.program, "G1 X1\n");
+        assert_eq!(got.fixes.len(), 1);
+        assert_eq!(got.fixes[0].span, Span::new(6, 9, 1));
+    }
+
+    #[test]
+    fn fixes_can_be_turned_off() {
+        let src = "G1 X1..5 Y2G1\u{7} X5\nG1 (cut";
+        let config = RepairConfig {
+            merged_lines: false,
+            doubled_decimal_points: false,
+            control_characters: false,
+            truncated_last_line: false,
+        };
+
+        let got = repair(src, &config);
+
+        assert_eq!(got.program, src);
+        assert!(got.fixes.is_empty());
+        assert_eq!(repair_with_defaults(src).program, "G1 X1 Y2\nG1 X5\n");
+    }
+
+    #[test]
+    fn numbers_are_cut_short_at_their_second_point() {
+        let src = "G1 X1.2.3 Y4..5. Z.6.\n";
+
+        let got = repair_with_defaults(src);
+
+        assert_eq!(got.program, "G1 X1.2 Y4 Z.6\n");
+        let removed: Vec<_> = got
+            .fixes
+            .iter()
+            .map(|fix| &src[fix.span.start..fix.span.end])
+            .collect();
+        assert_eq!(removed, vec![".3", "..5.", "."]);
+    }
+
+    #[test]
+    fn trailing_flags_arent_truncation() {
+        for src in &["G1 X1\nM84 X Y E", "G28 X Y", "G1 X3 Y"] {
+            let got = repair_with_defaults(src);
+
+            assert_eq!(got.program, *src);
+            assert!(got.fixes.is_empty());
+        }
+
+        for src in &["G1 X1\nG", "G1 X1\nG1 X3Y", "G1 X1\nG1 X2 M"] {
+            let got = repair_with_defaults(src);
+
+            assert_eq!(got.program, "G1 X1\n");
+            assert_eq!(got.fixes[0].kind, FixKind::TruncatedLastLine);
+        }
+    }
+
+    #[test]
+    fn arguments_stay_with_their_own_command() {
+        let src = "G0 Y1 X2 M3 S500 (spin) F9\nZ3 (plunge) Y4\nN10G1F5X1\n";
+
+        let got =
+            reorder_words(src, &Dialect::generic(), &WordOrder::default());
+
+        assert_eq!(
+            got.program,
+            "G0 X2 Y1 M3 F9 (spin) S500\nY4 (plunge) Z3\nN10G1X1F5\n"
+        );
+        assert!(got.kept_order.is_empty());
+        for m in &got.moves {
+            assert_eq!(
+                &got.program[m.to.start..m.to.end],
+                &src[m.from.start..m.from.end]
+            );
+            assert_eq!(m.from.line, m.to.line);
+        }
+    }
+
+    #[test]
+    fn reordering_twice_changes_nothing() {
+        let src = "G1 F300 Y2 X1\nG2 J1 I2 Y3 X4\n";
+        let order = WordOrder::for_dialect(DialectName::Grbl);
+        let once = reorder_words(src, &Dialect::grbl(), &order);
+
+        let twice = reorder_words(&once.program, &Dialect::grbl(), &order);
+
+        assert_eq!(once.program, "G1 X1 Y2 F300\nG2 X4 Y3 I2 J1\n");
+        assert_eq!(twice.program, once.program);
+        assert!(twice.moves.is_empty());
+    }
+}
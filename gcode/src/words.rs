@@ -1,11 +1,17 @@
 use crate::{
+    decimal::{self, Decimal},
+    dialect::Dialect,
     lexer::{Lexer, Token, TokenType},
-    Comment, Span,
+    Comment, ParseError, Span,
+};
+use core::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
 };
-use core::fmt::{self, Display, Formatter};
 
-/// A [`char`]-[`f32`] pair, used for things like arguments (`X3.14`), command
-/// numbers (`G90`) and line numbers (`N10`).
+/// A [`char`]-[`WordValue`] pair, used for things like arguments (`X3.14`),
+/// command numbers (`G90`) and line numbers (`N10`).
 #[derive(Debug, Copy, Clone, PartialEq)]
 #[cfg_attr(
     feature = "serde-1",
@@ -16,20 +22,36 @@ pub struct Word {
     /// The letter part of this [`Word`].
     pub letter: char,
     /// The value part.
-    pub value: f32,
+    pub value: WordValue,
     /// Where the [`Word`] lies in the original string.
     pub span: Span,
 }
 
 impl Word {
     /// Create a new [`Word`].
-    pub fn new(letter: char, value: f32, span: Span) -> Self {
+    pub const fn new(letter: char, value: f32, span: Span) -> Self {
+        Word {
+            letter,
+            value: WordValue::Number(value),
+            span,
+        }
+    }
+
+    /// Create a [`Word`] which is just a letter on its own, like the `X` and
+    /// `Y` in `G28 X Y`.
+    pub const fn flag(letter: char, span: Span) -> Self {
         Word {
             letter,
-            value,
+            value: WordValue::Flag,
             span,
         }
     }
+
+    /// The number attached to this [`Word`], if there is one.
+    pub fn number(&self) -> Option<f32> { self.value.number() }
+
+    /// Is this [`Word`] a letter without a number?
+    pub fn is_flag(&self) -> bool { self.value == WordValue::Flag }
 }
 
 impl Display for Word {
@@ -38,6 +60,66 @@ impl Display for Word {
     }
 }
 
+/// The value part of a [`Word`].
+///
+/// Some commands accept a letter on its own as an argument, for example
+/// `G28 X Y` (home the X and Y axes) or `M84 X E` (disable the X and E
+/// motors). These are represented as a [`WordValue::Flag`] so they can be
+/// told apart from an explicit `X0`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+#[allow(variant_size_differences)] // can't box without an allocator
+pub enum WordValue {
+    /// A normal number.
+    Number(f32),
+    /// A number which is stored exactly as it was written (see
+    /// [`Dialect::decimal_precision`]).
+    Decimal(Decimal),
+    /// The letter was used without a number.
+    Flag,
+    /// A parameter or bracketed expression (see [`Dialect::expressions`])
+    /// which wasn't evaluated while parsing, with the location of its text.
+    ///
+    /// The `expr` module (which needs the `std` feature) can evaluate it
+    /// later on.
+    Expression(Span),
+}
+
+impl WordValue {
+    /// Get the number, if there is one.
+    pub fn number(self) -> Option<f32> {
+        match self {
+            WordValue::Number(n) => Some(n),
+            WordValue::Decimal(d) => Some(d.to_f32()),
+            WordValue::Flag | WordValue::Expression(_) => None,
+        }
+    }
+}
+
+impl From<f32> for WordValue {
+    fn from(other: f32) -> WordValue { WordValue::Number(other) }
+}
+
+impl From<Decimal> for WordValue {
+    fn from(other: Decimal) -> WordValue { WordValue::Decimal(other) }
+}
+
+impl Display for WordValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            WordValue::Number(n) => write!(f, "{}", n),
+            WordValue::Decimal(d) => write!(f, "{}", d),
+            WordValue::Flag => Ok(()),
+            WordValue::Expression(span) => {
+                write!(f, "[expression at {}..{}]", span.start, span.end)
+            },
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub(crate) enum Atom<'input> {
     Word(Word),
@@ -47,6 +129,16 @@ pub(crate) enum Atom<'input> {
     BrokenWord(Token<'input>),
     /// Garbage from the tokenizer (see [`TokenType::Unknown`]).
     Unknown(Token<'input>),
+    /// A [`Word`] whose value is a [`WordValue::Expression`], and the
+    /// expression itself.
+    Expression(Word, Token<'input>),
+    /// A parameter assignment (`#1 = 2`).
+    Assignment(Token<'input>),
+    /// A line's checksum (`*27`).
+    Checksum(Token<'input>),
+    /// A [`Word`] salvaged from a malformed number (see
+    /// [`Dialect::lenient`]), and the number as it was written.
+    PartialWord(Word, Token<'input>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -55,6 +147,9 @@ pub(crate) struct WordsOrComments<'input, I> {
     /// keep track of the last letter so we can deal with a trailing letter
     /// that has no number
     last_letter: Option<Token<'input>>,
+    /// a token we need to revisit after reporting a dangling letter
+    pending: Option<Token<'input>>,
+    dialect: Dialect,
 }
 
 impl<'input, I> WordsOrComments<'input, I>
@@ -62,9 +157,44 @@ where
     I: Iterator<Item = Token<'input>>,
 {
     pub(crate) fn new(tokens: I) -> Self {
+        WordsOrComments::with_dialect(tokens, Dialect::default())
+    }
+
+    pub(crate) fn with_dialect(tokens: I, dialect: Dialect) -> Self {
         WordsOrComments {
             tokens,
             last_letter: None,
+            pending: None,
+            dialect,
+        }
+    }
+
+    /// Figure out what a [`Word`]'s number actually means, taking things like
+    /// [`Dialect::least_input_increment`] into account.
+    ///
+    /// Returns `None` if the number is just a sign or a decimal point.
+    fn value_of(&self, letter: char, number: &str) -> Option<WordValue> {
+        let increment = match self.dialect.least_input_increment {
+            Some(increment)
+                if !number.contains('.')
+                    && self.dialect.is_dimension_letter(letter) =>
+            {
+                Some(increment)
+            },
+            _ => None,
+        };
+
+        if let Some(precision) = self.dialect.decimal_precision {
+            if let Some(value) = exact_value(number, precision, increment) {
+                return Some(WordValue::Decimal(value));
+            }
+        }
+
+        let value: f32 = number.parse().ok()?;
+
+        match increment {
+            Some(increment) => Some(WordValue::Number(value * increment)),
+            None => Some(WordValue::Number(value)),
         }
     }
 }
@@ -76,31 +206,84 @@ where
     type Item = Atom<'input>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(token) = self.tokens.next() {
+        while let Some(token) =
+            self.pending.take().or_else(|| self.tokens.next())
+        {
             let Token { kind, value, span } = token;
+            debug_assert!(span.is_valid(), "malformed token span {:?}", span);
 
             match kind {
+                // a letter can't be paired with a number on the other side of
+                // a comment or newline
+                TokenType::Unknown
+                | TokenType::Newline
+                | TokenType::Comment
+                | TokenType::Assignment
+                | TokenType::Checksum
+                    if self.last_letter.is_some() =>
+                {
+                    self.pending = Some(token);
+                    return self.last_letter.take().map(Atom::BrokenWord);
+                },
                 TokenType::Unknown => return Some(Atom::Unknown(token)),
                 TokenType::Newline => return Some(Atom::Newline(token)),
+                TokenType::Assignment => return Some(Atom::Assignment(token)),
+                TokenType::Checksum => return Some(Atom::Checksum(token)),
                 TokenType::Comment => {
                     return Some(Atom::Comment(Comment { value, span }))
                 },
                 TokenType::Letter if self.last_letter.is_none() => {
                     self.last_letter = Some(token);
                 },
+                // two letters in a row, so the first one can't have a number
+                TokenType::Letter => {
+                    return self.last_letter.replace(token).map(Atom::BrokenWord);
+                },
                 TokenType::Number if self.last_letter.is_some() => {
                     let letter_token = self.last_letter.take().unwrap();
                     let span = letter_token.span.merge(span);
 
-                    debug_assert_eq!(letter_token.value.len(), 1);
-                    let letter = letter_token.value.chars().next().unwrap();
-                    let value = value.parse().expect("");
+                    debug_assert_eq!(letter_token.value.chars().count(), 1);
+                    let c = letter_token.value.chars().next().unwrap();
+                    let letter = self.dialect.leader_letter(c).unwrap_or(c);
+                    let number = well_formed_prefix(value);
+                    let word = match self.value_of(letter, number) {
+                        Some(value) => Word {
+                            letter,
+                            value,
+                            span,
+                        },
+                        None => {
+                            self.pending = Some(Token {
+                                kind: TokenType::Unknown,
+                                ..token
+                            });
+                            return Some(Atom::BrokenWord(letter_token));
+                        },
+                    };
 
-                    return Some(Atom::Word(Word {
+                    if number.len() < value.len() {
+                        return Some(Atom::PartialWord(word, token));
+                    }
+                    return Some(Atom::Word(word));
+                },
+                TokenType::Expression if self.last_letter.is_some() => {
+                    let letter_token = self.last_letter.take().unwrap();
+                    let c = letter_token.value.chars().next().unwrap();
+                    let letter = self.dialect.leader_letter(c).unwrap_or(c);
+                    let word = Word {
                         letter,
-                        value,
-                        span,
-                    }));
+                        value: WordValue::Expression(span),
+                        span: letter_token.span.merge(span),
+                    };
+
+                    return Some(Atom::Expression(word, token));
+                },
+                TokenType::Expression => {
+                    return Some(Atom::Unknown(Token {
+                        kind: TokenType::Unknown,
+                        ..token
+                    }))
                 },
                 _ => return Some(Atom::BrokenWord(token)),
             }
@@ -110,6 +293,78 @@ where
     }
 }
 
+/// The part of a number before its second decimal point, if it has one
+/// (which only happens when the [`Dialect`] is lenient).
+fn well_formed_prefix(number: &str) -> &str {
+    match number.match_indices('.').nth(1) {
+        Some((second, _)) => &number[..second],
+        None => number,
+    }
+}
+
+/// Parse a number as a [`Decimal`], falling back to an `f32` if it has too
+/// many digits or uses an increment which isn't a power of ten.
+fn exact_value(
+    number: &str,
+    precision: u8,
+    increment: Option<f32>,
+) -> Option<Decimal> {
+    match increment {
+        Some(increment) => {
+            let scale = decimal::scale_of_increment(increment)?;
+            let steps = Decimal::parse(number, 0)?;
+            Some(Decimal::new(steps.mantissa(), scale))
+                .filter(|_| scale <= precision)
+        },
+        None => Decimal::parse(number, precision),
+    }
+}
+
+impl FromStr for Word {
+    type Err = ParseError;
+
+    /// Parse a single [`Word`], failing if the text contains anything else
+    /// (apart from comments).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut word = None;
+
+        for atom in WordsOrComments::new(Lexer::new(s)) {
+            match atom {
+                Atom::Word(w) if word.is_none() => word = Some(w),
+                Atom::Word(w) => return Err(ParseError::TooMany(w.span)),
+                Atom::Comment(_) | Atom::Newline(_) => {},
+                Atom::BrokenWord(token) if token.kind == TokenType::Letter => {
+                    let letter = token.value.chars().next().unwrap_or_default();
+                    let flag = Word::flag(letter, token.span);
+
+                    match word {
+                        None => word = Some(flag),
+                        Some(_) => return Err(ParseError::TooMany(token.span)),
+                    }
+                },
+                Atom::BrokenWord(token)
+                | Atom::Unknown(token)
+                | Atom::Assignment(token)
+                | Atom::Checksum(token)
+                | Atom::PartialWord(_, token) => {
+                    return Err(ParseError::Unexpected(token.span))
+                },
+                Atom::Expression(w, _) => {
+                    return Err(ParseError::Unexpected(w.span))
+                },
+            }
+        }
+
+        word.ok_or(ParseError::Empty)
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Word {
+    type Error = ParseError;
+
+    fn try_from(s: &'a str) -> Result<Self, Self::Error> { s.parse() }
+}
+
 impl<'input> From<&'input str> for WordsOrComments<'input, Lexer<'input>> {
     fn from(other: &'input str) -> WordsOrComments<'input, Lexer<'input>> {
         WordsOrComments::new(Lexer::new(other))
@@ -187,7 +442,7 @@ mod tests {
 
         let expected = Atom::Word(Word {
             letter: 'G',
-            value: 90.0,
+            value: WordValue::Number(90.0),
             span: Span {
                 start: 0,
                 end: text.len(),
@@ -196,4 +451,95 @@ mod tests {
         });
         assert_eq!(got, expected);
     }
+
+    #[test]
+    fn dimensions_without_a_decimal_point_use_the_implied_increment() {
+        let text = "X100 Y100. Z-25 F100";
+        let words: Vec<_> =
+            WordsOrComments::with_dialect(Lexer::new(text), Dialect::fanuc())
+                .map(|atom| match atom {
+                    Atom::Word(word) => (word.letter, word.number().unwrap()),
+                    other => panic!("Unexpected atom: {:?}", other),
+                })
+                .collect();
+
+        assert_eq!(
+            words,
+            vec![('X', 0.1), ('Y', 100.0), ('Z', -0.025), ('F', 100.0)]
+        );
+    }
+
+    #[test]
+    fn a_letter_followed_by_another_letter_is_broken() {
+        let atoms: Vec<_> = WordsOrComments::new(Lexer::new("X Y0"))
+            .map(|atom| match atom {
+                Atom::BrokenWord(token) => (token.value, None),
+                Atom::Word(word) => ("word", Some(word)),
+                other => panic!("Unexpected atom: {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(
+            atoms,
+            vec![
+                ("X", None),
+                ("word", Some(Word::new('Y', 0.0, Span::new(2, 4, 0)))),
+            ]
+        );
+    }
+
+    #[test]
+    fn letters_arent_paired_with_numbers_on_the_next_line() {
+        let text = "X\n12.5";
+        let atoms: Vec<_> = WordsOrComments::new(Lexer::new(text))
+            .map(|atom| match atom {
+                Atom::BrokenWord(token) => Some(token.value),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(atoms, vec![Some("X"), None, Some("12.5")]);
+    }
+
+    #[test]
+    fn a_sign_on_its_own_isnt_a_number() {
+        let text = "X- Y.";
+        let atoms: Vec<_> = WordsOrComments::new(Lexer::new(text))
+            .map(|atom| match atom {
+                Atom::BrokenWord(token) => (token.value, "broken"),
+                Atom::Unknown(token) => (token.value, "unknown"),
+                _ => unreachable!(),
+            })
+            .collect();
+
+        assert_eq!(
+            atoms,
+            vec![
+                ("X", "broken"),
+                ("-", "unknown"),
+                ("Y", "broken"),
+                (".", "unknown"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_a_single_word() {
+        let word: Word = " (feed) F1500".parse().unwrap();
+
+        assert_eq!(word.letter, 'F');
+        assert_eq!(word.number(), Some(1500.0));
+        assert_eq!(word.span, Span::new(8, 13, 0));
+
+        assert_eq!(
+            "X1 Y2".parse::<Word>(),
+            Err(ParseError::TooMany(Span::new(3, 5, 0)))
+        );
+        assert_eq!(Word::try_from("X"), Ok(Word::flag('X', Span::new(0, 1, 0))));
+        assert_eq!(
+            Word::try_from("5"),
+            Err(ParseError::Unexpected(Span::new(0, 1, 0)))
+        );
+        assert_eq!(Word::try_from("(nothing)"), Err(ParseError::Empty));
+    }
 }
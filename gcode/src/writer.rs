@@ -0,0 +1,1637 @@
+//! Turning parsed g-code back into text.
+//!
+//! The [`Writer`] works with anything implementing [`core::fmt::Write`], so it
+//! can be used without an allocator (e.g. writing directly to a serial port)
+//! or from inside a [`Display`] impl.
+//!
+//! ```rust
+//! use gcode::{dialect::Dialect, writer::Writer};
+//!
+//! let src = "G01 X1.5 Y-0.25 Z+10";
+//! let mut writer = Writer::for_dialect(String::new(), &Dialect::fanuc());
+//!
+//! for gcode in gcode::parse(src) {
+//!     writer.write_gcode(&gcode).unwrap();
+//! }
+//!
+//! assert_eq!(writer.into_inner(), "G01 X1500 Y-0250 Z10000");
+//! ```
+
+use crate::{
+    buffers::{Buffer, Buffers},
+    decimal::Decimal,
+    dialect::{self, Dialect, ToolEncoding},
+    lexer::Lexer,
+    words::{Atom, WordsOrComments},
+    Comment, GCode, Line, Mnemonic, Nop, Parser, Word, WordValue,
+};
+use core::fmt::{self, Display, Formatter, Write};
+
+/// The largest number of decimal places the [`Writer`] will emit.
+const MAX_DECIMALS: usize = 9;
+const POWERS_OF_TEN: [u64; MAX_DECIMALS + 1] = [
+    1,
+    10,
+    100,
+    1_000,
+    10_000,
+    100_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+    1_000_000_000,
+];
+
+/// How the digits of a number are laid out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum NumberStyle {
+    /// Use as few characters as possible, dropping trailing zeroes and the
+    /// decimal point when they aren't needed (e.g. `X10`, `Y0.25`).
+    Trim {
+        /// The maximum number of digits after the decimal point.
+        max_decimals: u8,
+    },
+    /// Always write exactly `decimals` digits after the decimal point (e.g.
+    /// `X10.000`).
+    FixedDecimals {
+        /// The number of digits after the decimal point.
+        decimals: u8,
+    },
+    /// Write the number without a decimal point, as a multiple of the
+    /// smallest increment (e.g. `X0100` meaning `0.100` when `decimals` is
+    /// `3`).
+    ///
+    /// This is the format expected by older Fanuc-style controls. Only
+    /// dimensions (see [`Dialect::is_dimension_letter()`]) are written this
+    /// way, because controls read feeds, speeds and other parameters
+    /// literally.
+    ImpliedDecimal {
+        /// The number of implied digits after the decimal point.
+        decimals: u8,
+        /// Pad the number with leading zeroes until it is at least this
+        /// many digits long.
+        min_width: u8,
+    },
+}
+
+impl NumberStyle {
+    fn decimals(self) -> usize {
+        let decimals = match self {
+            NumberStyle::Trim { max_decimals } => max_decimals,
+            NumberStyle::FixedDecimals { decimals } => decimals,
+            NumberStyle::ImpliedDecimal { decimals, .. } => decimals,
+        };
+
+        core::cmp::min(usize::from(decimals), MAX_DECIMALS)
+    }
+}
+
+/// Everything needed to decide how a number is written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct NumberFormat {
+    /// How the digits are laid out.
+    pub style: NumberStyle,
+    /// Prefix positive numbers with a `+`.
+    pub plus_sign: bool,
+    /// Write numbers between `-1` and `1` with a leading zero (`0.5` instead
+    /// of `.5`).
+    pub leading_zero: bool,
+    /// Always include a decimal point, even for whole numbers (`X1.` instead
+    /// of `X1`).
+    ///
+    /// This is important when the reader may be using
+    /// [`Dialect::least_input_increment`], and is ignored by
+    /// [`NumberStyle::ImpliedDecimal`].
+    pub always_decimal_point: bool,
+}
+
+impl Default for NumberFormat {
+    fn default() -> NumberFormat { Dialect::generic().number_format }
+}
+
+/// Write a number to some [`Write`]r using the desired [`NumberFormat`].
+///
+/// # Examples
+///
+/// ```rust
+/// use gcode::writer::{self, NumberFormat, NumberStyle};
+///
+/// let format = NumberFormat {
+///     style: NumberStyle::Trim { max_decimals: 3 },
+///     plus_sign: false,
+///     leading_zero: false,
+///     always_decimal_point: false,
+/// };
+/// let mut buffer = String::new();
+///
+/// writer::write_number(&mut buffer, -0.50049, &format).unwrap();
+///
+/// assert_eq!(buffer, "-.5");
+/// ```
+pub fn write_number<W: Write>(
+    out: &mut W,
+    value: f32,
+    format: &NumberFormat,
+) -> fmt::Result {
+    if !value.is_finite() {
+        return write!(out, "{}", value);
+    }
+
+    let decimals = format.style.decimals();
+    let scale = POWERS_OF_TEN[decimals];
+    let scaled =
+        libm::round(libm::fabs(f64::from(value)) * scale as f64) as u64;
+
+    // note: rounding may have turned a tiny number into zero, and we never
+    // want to write "-0" or "+0"
+    if scaled != 0 {
+        if value < 0.0 {
+            out.write_char('-')?;
+        } else if format.plus_sign {
+            out.write_char('+')?;
+        }
+    }
+
+    match format.style {
+        NumberStyle::ImpliedDecimal { min_width, .. } => {
+            write!(out, "{:01$}", scaled, usize::from(min_width))
+        },
+        NumberStyle::FixedDecimals { .. } => {
+            write_decimal(out, scaled / scale, scaled % scale, decimals, format)
+        },
+        NumberStyle::Trim { .. } => {
+            let mut fraction = scaled % scale;
+            let mut digits = decimals;
+
+            while digits > 0 && fraction.is_multiple_of(10) {
+                fraction /= 10;
+                digits -= 1;
+            }
+
+            write_decimal(out, scaled / scale, fraction, digits, format)
+        },
+    }
+}
+
+/// Write a [`Decimal`] digit-for-digit, ignoring any
+/// [`NumberStyle::Trim`] limit on the number of decimal places.
+///
+/// [`NumberStyle::FixedDecimals`] pads the number with zeroes when it has
+/// fewer decimal places than requested. Only [`NumberStyle::ImpliedDecimal`]
+/// may need to round the number, because the position of its decimal point
+/// is fixed.
+///
+/// ```rust
+/// use gcode::{
+///     decimal::Decimal,
+///     writer::{self, NumberFormat, NumberStyle},
+/// };
+///
+/// let value: Decimal = "-0.1234567".parse().unwrap();
+/// let mut buffer = String::new();
+///
+/// writer::write_exact_number(&mut buffer, value, &NumberFormat::default())
+///     .unwrap();
+///
+/// assert_eq!(buffer, "-0.1234567");
+/// ```
+pub fn write_exact_number<W: Write>(
+    out: &mut W,
+    value: Decimal,
+    format: &NumberFormat,
+) -> fmt::Result {
+    let rescaled = match format.style {
+        NumberStyle::Trim { .. } => Some(value),
+        NumberStyle::FixedDecimals { decimals } => {
+            value.rescale(core::cmp::max(decimals, value.scale()))
+        },
+        NumberStyle::ImpliedDecimal { decimals, .. } => value.rescale(decimals),
+    };
+    let value = match rescaled {
+        Some(value) => value,
+        // too big to rescale exactly, so do the best we can
+        None => return write_number(out, value.to_f32(), format),
+    };
+
+    if !value.is_zero() {
+        if value.is_negative() {
+            out.write_char('-')?;
+        } else if format.plus_sign {
+            out.write_char('+')?;
+        }
+    }
+
+    match format.style {
+        NumberStyle::ImpliedDecimal { min_width, .. } => write!(
+            out,
+            "{:01$}",
+            value.mantissa().unsigned_abs(),
+            usize::from(min_width)
+        ),
+        _ => {
+            let (integral, fraction) = value.parts();
+            let digits = usize::from(value.scale());
+            write_decimal(out, integral, fraction, digits, format)
+        },
+    }
+}
+
+fn write_decimal<W: Write>(
+    out: &mut W,
+    integral: u64,
+    fraction: u64,
+    digits: usize,
+    format: &NumberFormat,
+) -> fmt::Result {
+    if integral != 0 || format.leading_zero || digits == 0 {
+        write!(out, "{}", integral)?;
+    }
+
+    if digits > 0 {
+        write!(out, ".{:01$}", fraction, digits)?;
+    } else if format.always_decimal_point {
+        out.write_char('.')?;
+    }
+
+    Ok(())
+}
+
+/// Copy a program to the output verbatim, except every dimension which relied
+/// on [`Dialect::least_input_increment`] is rewritten with an explicit decimal
+/// point.
+///
+/// This is useful when moving programs written for an older control onto a
+/// modern machine.
+///
+/// # Examples
+///
+/// ```rust
+/// use gcode::{dialect::Dialect, writer::{self, NumberFormat}};
+///
+/// let src = "G01 X100 Y2.5 F300 (cut)\nG00 Z-25\n";
+/// let format = NumberFormat {
+///     always_decimal_point: true,
+///     ..Default::default()
+/// };
+/// let mut rewritten = String::new();
+///
+/// writer::add_decimal_points(src, &Dialect::fanuc(), &format, &mut rewritten)
+///     .unwrap();
+///
+/// assert_eq!(rewritten, "G01 X0.1 Y2.5 F300 (cut)\nG00 Z-0.025\n");
+/// ```
+pub fn add_decimal_points<W: Write>(
+    src: &str,
+    dialect: &Dialect,
+    format: &NumberFormat,
+    out: &mut W,
+) -> fmt::Result {
+    let mut cursor = 0;
+
+    if dialect.least_input_increment.is_some() {
+        let atoms = WordsOrComments::with_dialect(
+            Lexer::new(src).with_dialect(dialect),
+            *dialect,
+        );
+
+        for atom in atoms {
+            let word = match atom {
+                Atom::Word(word) => word,
+                _ => continue,
+            };
+            let implied = word
+                .span
+                .get_text(src)
+                .map(|text| !text.contains('.'))
+                .unwrap_or(false);
+
+            match word.number() {
+                Some(value)
+                    if implied && dialect.is_dimension_letter(word.letter) =>
+                {
+                    out.write_str(&src[cursor..word.span.start])?;
+                    out.write_char(word.letter)?;
+                    write_number(out, value, format)?;
+                    cursor = word.span.end;
+                },
+                _ => {},
+            }
+        }
+    }
+
+    out.write_str(&src[cursor..])
+}
+
+/// Settings used by the [`Writer`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WriterConfig {
+    /// How argument values are formatted.
+    pub number_format: NumberFormat,
+    /// The minimum number of digits used for a `G` or `M` command's number
+    /// (see [`Dialect::min_command_digits`]).
+    pub min_command_digits: u8,
+    /// The minimum number of digits used for a `T` word's number (e.g. `4`
+    /// for a lathe's `T0101`).
+    pub min_tool_digits: u8,
+    /// Whether words are separated by spaces.
+    pub spacing: Spacing,
+    /// Whether [`Writer::write_line()`] notes which line of the original
+    /// program each line came from.
+    pub provenance: Provenance,
+    /// Whether [`Writer::write_line()`] writes `N` line numbers.
+    pub line_numbers: LineNumbering,
+    /// Leave out the command word of a [modal continuation] (e.g. write
+    /// `X12 Y13` rather than `G1 X12 Y13`), the way it was originally
+    /// written.
+    ///
+    /// This is off by default, so every command is spelled out for
+    /// controllers which need it. Use
+    /// [`Interpreter::materialize()`][crate::interpret::Interpreter::materialize]
+    /// first to make sure the right command is used.
+    ///
+    /// [modal continuation]: GCode::is_modal_continuation
+    pub implicit_continuations: bool,
+    /// The characters written at the end of each line (see
+    /// [`Dialect::line_ending`]).
+    pub line_ending: LineEnding,
+    /// Whether the last line ends with a [`LineEnding`].
+    ///
+    /// Some serial stacks treat an empty line as a command of its own, so
+    /// turning this off means each line ending is only written once
+    /// something else is written after it.
+    pub final_newline: bool,
+}
+
+/// How words on a line are separated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Spacing {
+    /// Put a space between each word (e.g. `G1 X10 Y20 F1500`).
+    Spaced,
+    /// Leave out all optional whitespace (e.g. `G1X10Y20F1500`), which
+    /// minimizes the number of bytes sent over a serial connection.
+    Dense,
+}
+
+/// How a line's origin is recorded when it is written.
+///
+/// Each line gets a comment like `; src:12`, giving the (one-based) number of
+/// the line in the original program it was parsed from, so anything in a
+/// post-processed file can be traced back to where it came from. Lines which
+/// already have a provenance comment (e.g. because they were written out by
+/// an earlier step) keep it, and lines which weren't parsed from anything
+/// (their [`Span`] is a [`Span::PLACEHOLDER`]) are left alone.
+///
+/// Use [`provenance()`] to read the comment back.
+///
+/// ```rust
+/// use gcode::{
+///     dialect::Dialect,
+///     writer::{self, Provenance, WriterConfig},
+/// };
+///
+/// let dialect = Dialect::reprap();
+/// let config = WriterConfig {
+///     provenance: Provenance::Semicolon,
+///     ..WriterConfig::for_dialect(&dialect)
+/// };
+///
+/// let src = "G90\n\nG1 X10 ; move";
+/// let mut annotated = String::new();
+/// writer::reformat(src, &dialect, &config, &mut annotated).unwrap();
+///
+/// assert_eq!(annotated, "G90 ; src:1\n\nG1 X10 ; move ; src:3\n");
+///
+/// // writing the annotated text again doesn't add more comments
+/// let mut again = String::new();
+/// writer::reformat(&annotated, &dialect, &config, &mut again).unwrap();
+/// assert_eq!(again, annotated);
+/// ```
+///
+/// [`Span`]: crate::Span
+/// [`Span::PLACEHOLDER`]: crate::Span::PLACEHOLDER
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Provenance {
+    /// Don't record where lines came from.
+    Off,
+    /// Use a `;` comment (e.g. `; src:12`).
+    Semicolon,
+    /// Use a parenthesized comment (e.g. `(src:12)`), for controllers which
+    /// don't understand `;`.
+    Parentheses,
+}
+
+/// How [`Writer::write_line()`] deals with `N` line numbers.
+///
+/// ```rust
+/// use gcode::{
+///     dialect::Dialect,
+///     writer::{self, LineNumbering, WriterConfig},
+/// };
+///
+/// let dialect = Dialect::generic();
+/// let config = WriterConfig {
+///     line_numbers: LineNumbering::Renumber { start: 10, step: 10 },
+///     ..WriterConfig::for_dialect(&dialect)
+/// };
+///
+/// let src = "N5 G90\n(comment)\nN7 G0 X1\nG1 Y2";
+/// let mut renumbered = String::new();
+/// writer::reformat(src, &dialect, &config, &mut renumbered).unwrap();
+///
+/// assert_eq!(renumbered, "N10 G90\n(comment)\nN20 G0 X1\nN30 G1 Y2\n");
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum LineNumbering {
+    /// Write the line numbers a [`Line`] already has.
+    Keep,
+    /// Leave out all line numbers.
+    Strip,
+    /// Give every line with commands on it a new line number, replacing any
+    /// it already had.
+    Renumber {
+        /// The first line's number.
+        start: u32,
+        /// How much the number goes up by on each line.
+        step: u32,
+    },
+}
+
+/// The characters which end a line.
+///
+/// ```rust
+/// use gcode::{
+///     dialect::Dialect,
+///     writer::{self, LineEnding, WriterConfig},
+/// };
+///
+/// let dialect = Dialect::generic();
+/// let config = WriterConfig {
+///     line_ending: LineEnding::Cr,
+///     final_newline: false,
+///     ..WriterConfig::for_dialect(&dialect)
+/// };
+///
+/// let src = "G90\nG0 X1\n";
+/// let mut written = String::new();
+/// writer::reformat(src, &dialect, &config, &mut written).unwrap();
+///
+/// assert_eq!(written, "G90\rG0 X1");
+/// ```
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde-1",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum LineEnding {
+    /// A line feed (`"\n"`), as used by most firmware and anything running
+    /// on Unix.
+    #[default]
+    Lf,
+    /// A carriage return followed by a line feed (`"\r\n"`), as used on
+    /// Windows and by a lot of DNC software.
+    CrLf,
+    /// A lone carriage return (`"\r"`), as expected by some older
+    /// controllers and tape punches.
+    Cr,
+}
+
+impl LineEnding {
+    /// The characters which end a line.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+impl Display for LineEnding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The marker at the start of a provenance comment's line number.
+const PROVENANCE_MARKER: &str = "src:";
+
+/// Find the (zero-based) line in the original program a line came from,
+/// using the comment added by [`Provenance`].
+///
+/// ```rust
+/// use gcode::{writer, Nop, Parser};
+///
+/// let src = "G1 X10 ; src:42\nG1 Y5 (src:7)\nG1 Z1";
+/// let lines: Vec<_> = Parser::<_>::new(src, Nop).collect();
+///
+/// assert_eq!(writer::provenance(&lines[0]), Some(41));
+/// assert_eq!(writer::provenance(&lines[1]), Some(6));
+/// assert_eq!(writer::provenance(&lines[2]), None);
+/// ```
+pub fn provenance<'input, B: Buffers<'input>>(
+    line: &Line<'input, B>,
+) -> Option<usize> {
+    line.comments()
+        .iter()
+        .find_map(|comment| provenance_in_comment(comment.value))
+}
+
+fn provenance_in_comment(comment: &str) -> Option<usize> {
+    let start = comment.rfind(PROVENANCE_MARKER)? + PROVENANCE_MARKER.len();
+    let rest = &comment[start..];
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let (number, trailing) = rest.split_at(digits);
+
+    if !trailing.trim_end().trim_end_matches(')').trim().is_empty() {
+        return None;
+    }
+
+    number.parse::<usize>().ok()?.checked_sub(1)
+}
+
+impl WriterConfig {
+    /// Get the [`WriterConfig`] preferred by a particular [`Dialect`].
+    pub fn for_dialect(dialect: &Dialect) -> Self {
+        WriterConfig {
+            number_format: dialect.number_format,
+            min_command_digits: dialect.min_command_digits,
+            min_tool_digits: match dialect.tool_encoding {
+                ToolEncoding::Index => 1,
+                ToolEncoding::ToolAndOffset { offset_digits } => {
+                    offset_digits.saturating_mul(2)
+                },
+            },
+            spacing: Spacing::Spaced,
+            provenance: Provenance::Off,
+            line_numbers: LineNumbering::Keep,
+            implicit_continuations: false,
+            line_ending: dialect.line_ending,
+            final_newline: true,
+        }
+    }
+}
+
+impl Default for WriterConfig {
+    fn default() -> WriterConfig {
+        WriterConfig::for_dialect(&Dialect::default())
+    }
+}
+
+/// Something which writes g-code to a [`Write`]r.
+#[derive(Debug)]
+pub struct Writer<W> {
+    out: W,
+    config: WriterConfig,
+    /// The number given to the next line when renumbering.
+    next_line_number: u32,
+    /// A line has ended, but its line ending is waiting for something to be
+    /// written after it (see [`WriterConfig::final_newline`]).
+    pending_line_ending: bool,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a new [`Writer`].
+    pub fn new(out: W, config: WriterConfig) -> Self {
+        let next_line_number = match config.line_numbers {
+            LineNumbering::Renumber { start, .. } => start,
+            _ => 0,
+        };
+
+        Writer {
+            out,
+            config,
+            next_line_number,
+            pending_line_ending: false,
+        }
+    }
+
+    /// Create a new [`Writer`] which follows the conventions for a particular
+    /// [`Dialect`].
+    pub fn for_dialect(out: W, dialect: &Dialect) -> Self {
+        Writer::new(out, WriterConfig::for_dialect(dialect))
+    }
+
+    /// The [`WriterConfig`] being used.
+    pub fn config(&self) -> &WriterConfig { &self.config }
+
+    /// Get a reference to the underlying [`Write`]r.
+    pub fn get_ref(&self) -> &W { &self.out }
+
+    /// Consume the [`Writer`], returning the underlying [`Write`]r.
+    pub fn into_inner(self) -> W { self.out }
+
+    /// Write a single [`Word`] (e.g. `X-1.5`), or just its letter if it is a
+    /// [`WordValue::Flag`].
+    ///
+    /// A [`WordValue::Expression`] can't be written, because only the
+    /// location of its text is known. Either evaluate it first or use
+    /// [`Writer::write_line_with_source()`].
+    pub fn write_word(&mut self, word: &Word) -> fmt::Result {
+        self.write_word_from(word, None)
+    }
+
+    fn write_word_from(
+        &mut self,
+        word: &Word,
+        src: Option<&str>,
+    ) -> fmt::Result {
+        self.start_line()?;
+        self.out.write_char(word.letter)?;
+
+        let format = self.number_format_for(word.letter);
+
+        match word.value {
+            WordValue::Number(value) => {
+                write_number(&mut self.out, value, &format)
+            },
+            WordValue::Decimal(value) => {
+                write_exact_number(&mut self.out, value, &format)
+            },
+            WordValue::Flag => Ok(()),
+            // the expression's text isn't stored in the word
+            WordValue::Expression(span) => {
+                match src.and_then(|src| span.get_text(src)) {
+                    Some(text) => self.out.write_str(text),
+                    None => Err(fmt::Error),
+                }
+            },
+        }
+    }
+
+    /// Write a [`GCode`] and its arguments, without a trailing newline.
+    ///
+    /// The command word is left out of a modal continuation when
+    /// [`WriterConfig::implicit_continuations`] is set.
+    pub fn write_gcode<A: Buffer<Word>>(
+        &mut self,
+        gcode: &GCode<A>,
+    ) -> fmt::Result {
+        self.write_gcode_from(gcode, None)
+    }
+
+    fn write_gcode_from<A: Buffer<Word>>(
+        &mut self,
+        gcode: &GCode<A>,
+        src: Option<&str>,
+    ) -> fmt::Result {
+        self.start_line()?;
+
+        if self.config.implicit_continuations && gcode.is_modal_continuation() {
+            for (i, arg) in gcode.arguments().iter().enumerate() {
+                if i > 0 {
+                    self.write_separator()?;
+                }
+                self.write_word_from(arg, src)?;
+            }
+
+            return Ok(());
+        }
+
+        match gcode.mnemonic {
+            Mnemonic::General | Mnemonic::Miscellaneous => {
+                let key = gcode.key();
+                write!(
+                    self.out,
+                    "{}{:02$}",
+                    gcode.mnemonic,
+                    gcode.major_number(),
+                    usize::from(self.config.min_command_digits),
+                )?;
+                key.write_minor(&mut self.out)?;
+            },
+            // program and tool numbers are identifiers, so they can be
+            // negative (e.g. "T-1") but never have a fractional part
+            Mnemonic::ProgramNumber => {
+                write!(self.out, "O{}", libm::roundf(gcode.number) as i64)?
+            },
+            Mnemonic::ToolChange => write!(
+                self.out,
+                "T{:01$}",
+                libm::roundf(gcode.number) as i64,
+                usize::from(self.config.min_tool_digits),
+            )?,
+        }
+
+        for arg in gcode.arguments() {
+            self.write_separator()?;
+            self.write_word_from(arg, src)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a [`Comment`] exactly as it appeared in the original text.
+    pub fn write_comment(&mut self, comment: &Comment<'_>) -> fmt::Result {
+        self.start_line()?;
+        self.out.write_str(comment.value)
+    }
+
+    /// Write a [`Line`], followed by a [`LineEnding`].
+    ///
+    /// Any [`Comment`]s are written after the [`GCode`]s.
+    pub fn write_line<'input, B: Buffers<'input>>(
+        &mut self,
+        line: &Line<'input, B>,
+    ) -> fmt::Result {
+        self.write_line_from(line, None)
+    }
+
+    /// Write a [`Line`] which was parsed from `src`, followed by a
+    /// [`LineEnding`].
+    ///
+    /// Unlike [`Writer::write_line()`], any [`WordValue::Expression`]s are
+    /// copied across exactly as they were written.
+    ///
+    /// ```rust
+    /// use gcode::{dialect::Dialect, writer::Writer, Nop, Parser};
+    ///
+    /// let src = "G1 X#1 Y[#2 * 2]";
+    /// let dialect = Dialect::linuxcnc();
+    /// let line = Parser::<Nop>::new_with_dialect(src, Nop, dialect)
+    ///     .next()
+    ///     .unwrap();
+    ///
+    /// let mut writer = Writer::for_dialect(String::new(), &dialect);
+    /// assert!(writer.write_line(&line).is_err());
+    ///
+    /// let mut writer = Writer::for_dialect(String::new(), &dialect);
+    /// writer.write_line_with_source(&line, src).unwrap();
+    /// assert_eq!(writer.into_inner(), "G1 X#1 Y[#2 * 2]\n");
+    /// ```
+    pub fn write_line_with_source<'input, B: Buffers<'input>>(
+        &mut self,
+        line: &Line<'input, B>,
+        src: &str,
+    ) -> fmt::Result {
+        self.write_line_from(line, Some(src))
+    }
+
+    fn write_line_from<'input, B: Buffers<'input>>(
+        &mut self,
+        line: &Line<'input, B>,
+        src: Option<&str>,
+    ) -> fmt::Result {
+        self.start_line()?;
+        let mut first = true;
+
+        match self.config.line_numbers {
+            LineNumbering::Keep => {
+                if let Some(n) = line.line_number() {
+                    let number = n.number().unwrap_or_default();
+                    write!(self.out, "{}{}", n.letter, number as i64)?;
+                    first = false;
+                }
+            },
+            LineNumbering::Strip => {},
+            LineNumbering::Renumber { step, .. } => {
+                if !line.gcodes().is_empty() {
+                    write!(self.out, "N{}", self.next_line_number)?;
+                    self.next_line_number =
+                        self.next_line_number.saturating_add(step);
+                    first = false;
+                }
+            },
+        }
+
+        for gcode in line.gcodes() {
+            if !first {
+                self.write_separator()?;
+            }
+            self.write_gcode_from(gcode, src)?;
+            first = false;
+        }
+
+        for comment in line.comments() {
+            if !first {
+                self.write_separator()?;
+            }
+            self.write_comment(comment)?;
+            first = false;
+        }
+
+        if self.needs_provenance(line) {
+            if !first {
+                self.out.write_char(' ')?;
+            }
+            self.write_provenance(line.span().line)?;
+        }
+
+        self.end_line()
+    }
+
+    /// Finish the current line.
+    pub fn end_line(&mut self) -> fmt::Result {
+        self.start_line()?;
+
+        if self.config.final_newline {
+            self.out.write_str(self.config.line_ending.as_str())
+        } else {
+            self.pending_line_ending = true;
+            Ok(())
+        }
+    }
+
+    /// Write the previous line's ending, if it is still waiting to be
+    /// written.
+    fn start_line(&mut self) -> fmt::Result {
+        if self.pending_line_ending {
+            self.pending_line_ending = false;
+            self.out.write_str(self.config.line_ending.as_str())?;
+        }
+
+        Ok(())
+    }
+
+    /// The [`NumberFormat`] used for a particular letter.
+    ///
+    /// Implied decimal points only apply to dimensions, so anything else
+    /// (e.g. the `S` in `G50 S2000` or the `F` in `F0.2`) is written with an
+    /// explicit decimal point when it needs one.
+    fn number_format_for(&self, letter: char) -> NumberFormat {
+        let format = self.config.number_format;
+
+        match format.style {
+            NumberStyle::ImpliedDecimal { .. }
+                if !dialect::is_dimension_letter(letter) =>
+            {
+                NumberFormat {
+                    style: NumberFormat::default().style,
+                    ..format
+                }
+            },
+            _ => format,
+        }
+    }
+
+    fn needs_provenance<'input, B: Buffers<'input>>(
+        &self,
+        line: &Line<'input, B>,
+    ) -> bool {
+        self.config.provenance != Provenance::Off
+            && !line.is_empty()
+            && !line.span().is_placeholder()
+            && provenance(line).is_none()
+    }
+
+    fn write_provenance(&mut self, line: usize) -> fmt::Result {
+        let line = line + 1;
+
+        match self.config.provenance {
+            Provenance::Off => Ok(()),
+            Provenance::Semicolon => {
+                write!(self.out, "; {}{}", PROVENANCE_MARKER, line)
+            },
+            Provenance::Parentheses => {
+                write!(self.out, "({}{})", PROVENANCE_MARKER, line)
+            },
+        }
+    }
+
+    /// Write a command whose argument is free-form text (e.g. `M117 Hello`
+    /// or `M23 part.gco`), followed by a [`LineEnding`].
+    ///
+    /// Text which comes from a user could otherwise smuggle extra commands
+    /// onto another line or corrupt a checksum, so nothing is written unless
+    /// it passes [`validate_text()`]. Use [`escape_text()`] first to replace
+    /// anything which would be rejected.
+    ///
+    /// ```rust
+    /// use gcode::{
+    ///     dialect::Dialect,
+    ///     writer::{TextError, Writer},
+    ///     GCode,
+    /// };
+    ///
+    /// let m117: GCode = "M117".parse().unwrap();
+    /// let mut writer = Writer::for_dialect(String::new(), &Dialect::reprap());
+    ///
+    /// writer.write_text_command(&m117, "50% done").unwrap();
+    /// let got = writer.write_text_command(&m117, "hi\nM104 S300");
+    ///
+    /// assert_eq!(got, Err(TextError::LineBreak { position: 2 }));
+    /// assert_eq!(writer.into_inner(), "M117 50% done\n");
+    /// ```
+    pub fn write_text_command<A: Buffer<Word>>(
+        &mut self,
+        gcode: &GCode<A>,
+        text: &str,
+    ) -> Result<(), TextError> {
+        validate_text(text)?;
+
+        self.write_gcode(gcode)?;
+        // the text always needs a space, otherwise a leading digit would be
+        // read as part of the command's number
+        self.out.write_char(' ')?;
+        self.out.write_str(text)?;
+        self.end_line()?;
+
+        Ok(())
+    }
+
+    /// Write a line the parser didn't find any commands on, keeping it if it
+    /// is a tape marker and leaving it blank otherwise.
+    fn write_unparsed(&mut self, text: &str) -> fmt::Result {
+        if is_tape_marker(text) {
+            self.start_line()?;
+            self.out.write_char('%')?;
+        }
+
+        self.end_line()
+    }
+
+    fn write_separator(&mut self) -> fmt::Result {
+        match self.config.spacing {
+            Spacing::Spaced => self.out.write_char(' '),
+            Spacing::Dense => Ok(()),
+        }
+    }
+}
+
+/// Parse a program and write it back out using a particular
+/// [`WriterConfig`], normalizing its formatting.
+///
+/// Blank lines and `%` tape markers are kept, but anything else the parser
+/// couldn't make sense of is dropped.
+///
+/// ```rust
+/// use gcode::{
+///     dialect::Dialect,
+///     writer::{self, Spacing, WriterConfig},
+/// };
+///
+/// let dialect = Dialect::reprap();
+/// let dense = WriterConfig {
+///     spacing: Spacing::Dense,
+///     ..WriterConfig::for_dialect(&dialect)
+/// };
+///
+/// let src = "G1 X10 Y20 F1500\n\nM104 S200 ; heat";
+/// let mut compact = String::new();
+/// writer::reformat(src, &dialect, &dense, &mut compact).unwrap();
+/// assert_eq!(compact, "G1X10Y20F1500\n\nM104S200; heat\n");
+///
+/// let spaced = WriterConfig::for_dialect(&dialect);
+/// let mut expanded = String::new();
+/// writer::reformat(&compact, &dialect, &spaced, &mut expanded).unwrap();
+/// assert_eq!(expanded, "G1 X10 Y20 F1500\n\nM104 S200 ; heat\n");
+/// ```
+pub fn reformat<W: Write>(
+    src: &str,
+    dialect: &Dialect,
+    config: &WriterConfig,
+    out: &mut W,
+) -> fmt::Result {
+    let mut writer = Writer::new(out, *config);
+    let mut texts = src.lines();
+    let mut next_line = 0;
+
+    for line in Parser::<Nop>::new_with_dialect(src, Nop, *dialect) {
+        // a trailing line with nothing the parser understood (e.g. a lone
+        // "%") comes back empty, without a real position
+        if line.span().is_placeholder() {
+            continue;
+        }
+
+        let line_number = line.span().line;
+
+        while next_line < line_number {
+            writer.write_unparsed(texts.next().unwrap_or_default())?;
+            next_line += 1;
+        }
+
+        writer.write_line_with_source(&line, src)?;
+        let _ = texts.next();
+        next_line = line_number + 1;
+    }
+
+    // anything after the last command is only worth keeping if it leads up
+    // to a tape marker
+    let remaining = texts
+        .clone()
+        .enumerate()
+        .filter(|(_, text)| is_tape_marker(text))
+        .last()
+        .map_or(0, |(i, _)| i + 1);
+
+    for text in texts.take(remaining) {
+        writer.write_unparsed(text)?;
+    }
+
+    Ok(())
+}
+
+/// Is this line a `%`, marking the start or end of a program on tape?
+pub(crate) fn is_tape_marker(text: &str) -> bool { text.trim() == "%" }
+
+/// Reasons a piece of text can't safely be written as part of a line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextError {
+    /// A newline or carriage return, which would end the line early.
+    LineBreak {
+        /// The character's byte offset.
+        position: usize,
+    },
+    /// Some other control character (e.g. a tab or `NUL`), which a
+    /// controller may treat specially.
+    ControlCharacter {
+        /// The character's byte offset.
+        position: usize,
+        /// The offending character.
+        character: char,
+    },
+    /// A `;` or `(`, which would turn the rest of the text into a comment.
+    Comment {
+        /// The character's byte offset.
+        position: usize,
+    },
+    /// A `*`, which would be mistaken for the start of a checksum.
+    Checksum {
+        /// The character's byte offset.
+        position: usize,
+    },
+    /// The underlying [`Write`]r failed.
+    Write,
+}
+
+impl From<fmt::Error> for TextError {
+    fn from(_: fmt::Error) -> TextError { TextError::Write }
+}
+
+impl Display for TextError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TextError::LineBreak { position } => {
+                write!(f, "line break at {}", position)
+            },
+            TextError::ControlCharacter {
+                position,
+                character,
+            } => write!(f, "control character {:?} at {}", character, position),
+            TextError::Comment { position } => {
+                write!(f, "comment delimiter at {}", position)
+            },
+            TextError::Checksum { position } => {
+                write!(f, "checksum delimiter at {}", position)
+            },
+            TextError::Write => write!(f, "unable to write the text"),
+        }
+    }
+}
+
+with_std! {
+    impl std::error::Error for TextError {}
+}
+
+/// Check that some text (e.g. a message for `M117` or a filename for `M23`)
+/// can be written on a line without changing how the rest of it is read.
+///
+/// ```rust
+/// use gcode::writer::{self, TextError};
+///
+/// assert!(writer::validate_text("Printing bracket.gcode").is_ok());
+/// assert_eq!(
+///     writer::validate_text("50% ; done"),
+///     Err(TextError::Comment { position: 4 })
+/// );
+/// ```
+pub fn validate_text(text: &str) -> Result<(), TextError> {
+    match text.char_indices().find(|&(_, c)| !is_safe_in_text(c)) {
+        Some((position, c)) => Err(match c {
+            '\n' | '\r' => TextError::LineBreak { position },
+            ';' | '(' => TextError::Comment { position },
+            '*' => TextError::Checksum { position },
+            character => TextError::ControlCharacter {
+                position,
+                character,
+            },
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Copy some text to the output, swapping every character which
+/// [`validate_text()`] would reject for `replacement`.
+///
+/// `replacement` must itself be safe, so a space or `_` is normally used.
+///
+/// ```rust
+/// use gcode::writer;
+///
+/// let mut message = String::new();
+/// writer::escape_text(&mut message, "Done!\nM104 S0 ; off", ' ').unwrap();
+///
+/// assert_eq!(message, "Done! M104 S0   off");
+/// assert!(writer::validate_text(&message).is_ok());
+/// ```
+pub fn escape_text<W: Write>(
+    out: &mut W,
+    text: &str,
+    replacement: char,
+) -> fmt::Result {
+    debug_assert!(
+        is_safe_in_text(replacement),
+        "{:?} can't be used as a replacement",
+        replacement
+    );
+
+    for c in text.chars() {
+        if is_safe_in_text(c) {
+            out.write_char(c)?;
+        } else {
+            out.write_char(replacement)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_safe_in_text(c: char) -> bool {
+    !c.is_control() && !matches!(c, ';' | '(' | '*')
+}
+
+/// The RepRap-style checksum of a line, which is every byte before the `*`
+/// XOR-ed together.
+///
+/// ```rust
+/// assert_eq!(gcode::writer::checksum("N42 G1 X10 "), 71);
+/// ```
+pub fn checksum(text: &str) -> u8 {
+    text.bytes().fold(0, |checksum, byte| checksum ^ byte)
+}
+
+/// Write a line the way a host sends it to a RepRap-style printer, with a
+/// line number in front and a [`checksum()`] at the end (see
+/// [`Dialect::checksums`]).
+///
+/// The `text` shouldn't contain a newline or comment, and no newline is
+/// written.
+///
+/// ```rust
+/// use gcode::writer;
+///
+/// let mut line = String::new();
+/// writer::write_with_checksum(&mut line, 42, "G1 X10").unwrap();
+///
+/// assert_eq!(line, "N42 G1 X10*103");
+/// ```
+pub fn write_with_checksum<W: Write>(
+    out: &mut W,
+    line_number: u32,
+    text: &str,
+) -> fmt::Result {
+    let mut checksummed = Checksummed { out, checksum: 0 };
+    write!(checksummed, "N{} {}", line_number, text)?;
+    let checksum = checksummed.checksum;
+
+    write!(out, "*{}", checksum)
+}
+
+/// A [`Write`]r which keeps track of the [`checksum()`] of everything
+/// written so far.
+struct Checksummed<'a, W> {
+    out: &'a mut W,
+    checksum: u8,
+}
+
+impl<W: Write> Write for Checksummed<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.checksum ^= checksum(s);
+        self.out.write_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Mnemonic, Span};
+    use std::string::String;
+
+    fn format(value: f32, format: NumberFormat) -> String {
+        let mut buffer = String::new();
+        write_number(&mut buffer, value, &format).unwrap();
+        buffer
+    }
+
+    const TRIM: NumberFormat = NumberFormat {
+        style: NumberStyle::Trim { max_decimals: 3 },
+        plus_sign: false,
+        leading_zero: true,
+        always_decimal_point: false,
+    };
+
+    #[test]
+    fn trimmed_numbers() {
+        let inputs = vec![
+            (0.0, "0"),
+            (10.0, "10"),
+            (-2.5, "-2.5"),
+            (0.1, "0.1"),
+            (1.23456, "1.235"),
+            (-0.0001, "0"),
+            (99.9999, "100"),
+        ];
+
+        for (value, should_be) in inputs {
+            assert_eq!(format(value, TRIM), should_be);
+        }
+    }
+
+    #[test]
+    fn optional_plus_signs_and_leading_zeroes() {
+        let fmt = NumberFormat {
+            plus_sign: true,
+            leading_zero: false,
+            ..TRIM
+        };
+
+        assert_eq!(format(0.5, fmt), "+.5");
+        assert_eq!(format(-0.5, fmt), "-.5");
+        assert_eq!(format(12.5, fmt), "+12.5");
+        assert_eq!(format(0.0, fmt), "0");
+    }
+
+    #[test]
+    fn whole_numbers_can_keep_their_decimal_point() {
+        let fmt = NumberFormat {
+            always_decimal_point: true,
+            ..TRIM
+        };
+
+        assert_eq!(format(10.0, fmt), "10.");
+        assert_eq!(format(-0.5, fmt), "-0.5");
+    }
+
+    #[test]
+    fn fixed_decimals() {
+        let fmt = NumberFormat {
+            style: NumberStyle::FixedDecimals { decimals: 3 },
+            ..TRIM
+        };
+
+        assert_eq!(format(10.0, fmt), "10.000");
+        assert_eq!(format(-0.25, fmt), "-0.250");
+        assert_eq!(format(0.0, fmt), "0.000");
+    }
+
+    #[test]
+    fn fanuc_style_implied_decimals() {
+        let fmt = Dialect::fanuc().number_format;
+
+        assert_eq!(format(0.1, fmt), "0100");
+        assert_eq!(format(-0.1, fmt), "-0100");
+        assert_eq!(format(12.5, fmt), "12500");
+        assert_eq!(format(0.0, fmt), "0000");
+    }
+
+    #[test]
+    fn implied_decimals_round_trip_with_the_default_config() {
+        let src = "G50 S2000\nG76 P1227 Q100 R.05\nG01 X1.5 Z-2. F0.2\nX42.\n";
+        let dialect = Dialect::fanuc_lathe();
+        let config = WriterConfig::for_dialect(&dialect);
+
+        let mut once = String::new();
+        reformat(src, &dialect, &config, &mut once).unwrap();
+        let mut twice = String::new();
+        reformat(&once, &dialect, &config, &mut twice).unwrap();
+
+        assert_eq!(
+            once,
+            "G50 S2000\r\nG76 P1227 Q100 R0050\r\nG01 X1500 Z-2000 \
+             F0.2\r\nG01 X42000\r\n"
+        );
+        assert_eq!(twice, once);
+    }
+
+    #[test]
+    fn write_a_full_line() {
+        let src = "N10 G01 X1.5 Y-2 (move)";
+        let line = crate::full_parse_with_callbacks(src, Nop).next().unwrap();
+        let mut writer = Writer::new(String::new(), WriterConfig::default());
+
+        writer.write_line(&line).unwrap();
+
+        assert_eq!(writer.into_inner(), "N10 G1 X1.5 Y-2 (move)\n");
+    }
+
+    #[test]
+    fn flags_are_written_without_a_number() {
+        let src = "G28 X Y\nM84 X E";
+        let mut writer = Writer::new(String::new(), WriterConfig::default());
+
+        for line in crate::full_parse_with_callbacks(src, Nop) {
+            writer.write_line(&line).unwrap();
+        }
+
+        assert_eq!(writer.into_inner(), "G28 X Y\nM84 X E\n");
+    }
+
+    #[test]
+    fn minor_numbers_are_kept() {
+        let gcode = GCode::new(Mnemonic::General, 38.2, Span::PLACEHOLDER)
+            .with_argument(Word::new('Z', -10.0, Span::PLACEHOLDER));
+        let mut writer = Writer::new(String::new(), WriterConfig::default());
+
+        writer.write_gcode(&gcode).unwrap();
+
+        assert_eq!(writer.into_inner(), "G38.2 Z-10");
+    }
+
+    #[test]
+    fn provenance_in_parentheses() {
+        let config = WriterConfig {
+            provenance: Provenance::Parentheses,
+            ..Default::default()
+        };
+        let mut writer = Writer::new(String::new(), config);
+
+        for line in crate::full_parse_with_callbacks("G90\nG1 X1", Nop) {
+            writer.write_line(&line).unwrap();
+        }
+        // lines which weren't parsed from anything don't get a comment
+        let mut generated: Line<'_> = Line::default();
+        generated
+            .push_gcode(GCode::new(Mnemonic::General, 4.0, Span::PLACEHOLDER))
+            .unwrap();
+        writer.write_line(&generated).unwrap();
+
+        assert_eq!(writer.into_inner(), "G90 (src:1)\nG1 X1 (src:2)\nG4\n");
+    }
+
+    #[test]
+    fn command_numbers_can_be_zero_padded() {
+        let src = "G4 P500\nM06 T1\nG01 X1\nG38.2 Z-1\nG90";
+        let config = WriterConfig {
+            min_command_digits: 2,
+            ..Default::default()
+        };
+        let mut writer = Writer::new(String::new(), config);
+
+        for line in crate::full_parse_with_callbacks(src, Nop) {
+            writer.write_line(&line).unwrap();
+        }
+
+        assert_eq!(
+            writer.into_inner(),
+            "G04 P500\nM06 T1\nG01 X1\nG38.2 Z-1\nG90\n"
+        );
+    }
+
+    #[test]
+    fn tool_numbers() {
+        let inputs = vec![
+            (Dialect::generic(), "T3 M6", "T3 M6\n"),
+            (Dialect::reprap(), "T-1", "T-1\n"),
+            (Dialect::fanuc_lathe(), "T0101", "T0101\r\n"),
+        ];
+
+        for (dialect, src, should_be) in inputs {
+            let mut writer = Writer::for_dialect(String::new(), &dialect);
+
+            for line in crate::full_parse_with_callbacks(src, Nop) {
+                writer.write_line(&line).unwrap();
+            }
+
+            assert_eq!(writer.into_inner(), should_be);
+        }
+    }
+
+    #[test]
+    fn leading_zeroes_are_normalized_by_default() {
+        let src = "G04 P500\nM06 T1\nG01 X1\n";
+        let mut writer = Writer::new(String::new(), WriterConfig::default());
+
+        for line in crate::full_parse_with_callbacks(src, Nop) {
+            writer.write_line(&line).unwrap();
+        }
+
+        assert_eq!(writer.into_inner(), "G4 P500\nM6 T1\nG1 X1\n");
+    }
+
+    #[test]
+    fn dense_output_round_trips() {
+        let src = "N10G1X10Y-20.5F1500\nM104S200\nT1M6(tool)\n";
+        let dialect = Dialect::generic();
+        let dense = WriterConfig {
+            spacing: Spacing::Dense,
+            ..Default::default()
+        };
+
+        let mut spaced = String::new();
+        reformat(src, &dialect, &WriterConfig::default(), &mut spaced).unwrap();
+        assert_eq!(
+            spaced,
+            "N10 G1 X10 Y-20.5 F1500\nM104 S200\nT1 M6 (tool)\n"
+        );
+
+        let mut compact = String::new();
+        reformat(&spaced, &dialect, &dense, &mut compact).unwrap();
+        assert_eq!(compact, src);
+    }
+
+    #[test]
+    fn leading_zeroes_in_command_numbers_are_kept() {
+        let src = "G12.05 X1\nG12.5 X1";
+        let dialect = Dialect::linuxcnc();
+
+        let mut got = String::new();
+        reformat(src, &dialect, &WriterConfig::default(), &mut got).unwrap();
+
+        assert_eq!(got, "G12.05 X1\nG12.5 X1\n");
+    }
+
+    #[test]
+    fn expressions_are_copied_verbatim() {
+        let src = "G1 X#1 Y[#2 * 2]\nG0 Z[#3/2]";
+        let dialect = Dialect::linuxcnc();
+
+        let mut got = String::new();
+        reformat(
+            src,
+            &dialect,
+            &WriterConfig::for_dialect(&dialect),
+            &mut got,
+        )
+        .unwrap();
+
+        assert_eq!(got, "G1 X#1 Y[#2 * 2]\nG0 Z[#3/2]\n");
+    }
+
+    #[test]
+    fn reformatting_keeps_tape_markers() {
+        let src = "%\r\nG1 X1.\r\n\r\n%\r\n\r\n";
+        let dialect = Dialect::fanuc();
+
+        let mut got = String::new();
+        reformat(src, &dialect, &WriterConfig::default(), &mut got).unwrap();
+
+        assert_eq!(got, "%\nG1 X1\n\n%\n");
+    }
+
+    #[test]
+    fn modal_continuations_can_stay_implicit() {
+        let src = "G1 X1 F100\nX2 Y3 ; next\nG0 Z5\n";
+        let dialect = Dialect::generic();
+        let implicit = WriterConfig {
+            implicit_continuations: true,
+            ..WriterConfig::for_dialect(&dialect)
+        };
+
+        let mut explicit = String::new();
+        reformat(src, &dialect, &WriterConfig::default(), &mut explicit)
+            .unwrap();
+        let mut kept = String::new();
+        reformat(src, &dialect, &implicit, &mut kept).unwrap();
+
+        assert_eq!(explicit, "G1 X1 F100\nG1 X2 Y3 ; next\nG0 Z5\n");
+        assert_eq!(kept, src);
+    }
+
+    #[test]
+    fn every_line_ending_with_and_without_a_final_newline() {
+        let src = "G90\n\nG0 X1\n";
+        let dialect = Dialect::generic();
+        let inputs = [
+            (LineEnding::Lf, true, "G90\n\nG0 X1\n"),
+            (LineEnding::Lf, false, "G90\n\nG0 X1"),
+            (LineEnding::CrLf, true, "G90\r\n\r\nG0 X1\r\n"),
+            (LineEnding::CrLf, false, "G90\r\n\r\nG0 X1"),
+            (LineEnding::Cr, true, "G90\r\rG0 X1\r"),
+            (LineEnding::Cr, false, "G90\r\rG0 X1"),
+        ];
+
+        for &(line_ending, final_newline, should_be) in &inputs {
+            let config = WriterConfig {
+                line_ending,
+                final_newline,
+                ..WriterConfig::for_dialect(&dialect)
+            };
+            let mut got = String::new();
+            reformat(src, &dialect, &config, &mut got).unwrap();
+
+            assert_eq!(got, should_be, "{:?} {}", line_ending, final_newline);
+        }
+    }
+
+    #[test]
+    fn held_back_line_endings_are_written_before_anything_else() {
+        let m117: GCode = "M117".parse().unwrap();
+        let g28: GCode = "G28".parse().unwrap();
+        let config = WriterConfig {
+            line_ending: LineEnding::CrLf,
+            final_newline: false,
+            ..Default::default()
+        };
+        let mut writer = Writer::new(String::new(), config);
+
+        writer.write_text_command(&m117, "homing").unwrap();
+        assert_eq!(writer.get_ref(), "M117 homing");
+        writer.write_gcode(&g28).unwrap();
+        writer.end_line().unwrap();
+
+        assert_eq!(writer.into_inner(), "M117 homing\r\nG28");
+    }
+
+    #[test]
+    fn dialects_pick_their_own_line_endings() {
+        let inputs = [
+            (Dialect::generic(), LineEnding::Lf),
+            (Dialect::reprap(), LineEnding::Lf),
+            (Dialect::grbl(), LineEnding::Lf),
+            (Dialect::linuxcnc(), LineEnding::Lf),
+            (Dialect::fanuc(), LineEnding::CrLf),
+            (Dialect::fanuc_lathe(), LineEnding::CrLf),
+        ];
+
+        for (dialect, should_be) in &inputs {
+            let config = WriterConfig::for_dialect(dialect);
+
+            assert_eq!(config.line_ending, *should_be);
+            assert!(config.final_newline);
+        }
+    }
+
+    #[test]
+    fn text_is_always_separated_from_its_command() {
+        let m23 = GCode::new(Mnemonic::Miscellaneous, 23.0, Span::PLACEHOLDER);
+        let dense = WriterConfig {
+            spacing: Spacing::Dense,
+            ..Default::default()
+        };
+        let mut writer = Writer::new(String::new(), dense);
+
+        writer.write_text_command(&m23, "3dbenchy.gco").unwrap();
+
+        assert_eq!(writer.into_inner(), "M23 3dbenchy.gco\n");
+    }
+
+    #[test]
+    fn unsafe_text_is_rejected() {
+        let inputs = [
+            ("a\rb", TextError::LineBreak { position: 1 }),
+            (
+                "tab\there",
+                TextError::ControlCharacter {
+                    position: 3,
+                    character: '\t',
+                },
+            ),
+            ("é(x)", TextError::Comment { position: 2 }),
+            ("N1 G1*42", TextError::Checksum { position: 5 }),
+        ];
+
+        for &(text, should_be) in &inputs {
+            assert_eq!(validate_text(text), Err(should_be), "{:?}", text);
+
+            let mut escaped = String::new();
+            escape_text(&mut escaped, text, '_').unwrap();
+            assert_eq!(escaped.chars().count(), text.chars().count());
+            assert!(validate_text(&escaped).is_ok());
+        }
+    }
+
+    #[test]
+    fn exact_numbers_survive_a_round_trip() {
+        let src = "G01 X100 Y2.5 Z-0.0625 F300.\n";
+        let mut dialect = Dialect::fanuc();
+        dialect.decimal_precision = Some(6);
+        let fixed = WriterConfig {
+            number_format: NumberFormat {
+                style: NumberStyle::FixedDecimals { decimals: 3 },
+                ..Default::default()
+            },
+            ..WriterConfig::for_dialect(&dialect)
+        };
+        let inputs = [
+            (
+                WriterConfig::for_dialect(&dialect),
+                "G01 X0100 Y2500 Z-0063 F300\r\n",
+            ),
+            (fixed, "G01 X0.100 Y2.500 Z-0.0625 F300.000\r\n"),
+        ];
+
+        for (config, should_be) in &inputs {
+            let mut got = String::new();
+            reformat(src, &dialect, config, &mut got).unwrap();
+
+            assert_eq!(got, *should_be);
+        }
+    }
+}
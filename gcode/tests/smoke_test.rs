@@ -25,6 +25,21 @@ smoke_test!(pi_octcat, "PI_octcat.gcode");
 smoke_test!(pi_rustlogo, "PI_rustlogo.gcode");
 smoke_test!(insulpro_piping, "Insulpro.Piping.-.115mm.OD.-.40mm.WT.txt");
 
+#[test]
+#[cfg(feature = "std")]
+fn written_programs_parse_the_same() {
+    let programs = [
+        include_str!("data/program_1.gcode"),
+        include_str!("data/program_2.gcode"),
+        include_str!("data/program_3.gcode"),
+        include_str!("data/Insulpro.Piping.-.115mm.OD.-.40mm.WT.txt"),
+    ];
+
+    for src in &programs {
+        assert_round_trips(&sanitise_input(src));
+    }
+}
+
 #[test]
 fn expected_program_2_output() {
     let src = include_str!("data/program_2.gcode");
@@ -38,6 +53,64 @@ fn expected_program_2_output() {
     assert_eq!(got.iter().filter(|l| l.comments().is_empty()).count(), 11);
 }
 
+/// Writing a program back out and parsing it again should give the same
+/// commands, give or take the precision numbers are written with.
+#[cfg(feature = "std")]
+fn assert_round_trips(src: &str) {
+    use gcode::{
+        dialect::Dialect,
+        writer::{self, LineNumbering, NumberStyle, Spacing, WriterConfig},
+        GCode,
+    };
+
+    let dialect = Dialect::generic();
+    let mut config = WriterConfig::for_dialect(&dialect);
+    config.number_format.style = NumberStyle::Trim { max_decimals: 6 };
+    let original: Vec<GCode> = gcode::parse(src).collect();
+
+    let styles = [
+        (Spacing::Spaced, LineNumbering::Keep),
+        (Spacing::Dense, LineNumbering::Strip),
+    ];
+
+    for &(spacing, line_numbers) in &styles {
+        let config = WriterConfig {
+            spacing,
+            line_numbers,
+            ..config
+        };
+        let mut written = String::new();
+        writer::reformat(src, &dialect, &config, &mut written).unwrap();
+
+        let got: Vec<GCode> =
+            gcode::full_parse_with_callbacks(&written, PanicOnError)
+                .flat_map(|line| line.gcodes().to_vec())
+                .collect();
+
+        assert_eq!(got.len(), original.len(), "{:?}", config);
+        for (got, expected) in got.iter().zip(&original) {
+            assert_same_command(got, expected);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn assert_same_command(got: &gcode::GCode, expected: &gcode::GCode) {
+    let close = |a: f32, b: f32| (a - b).abs() <= 1e-5 * a.abs().max(1.0);
+
+    assert_eq!(got.key(), expected.key(), "{} != {}", got, expected);
+    assert_eq!(got.arguments().len(), expected.arguments().len());
+    for (a, b) in got.arguments().iter().zip(expected.arguments()) {
+        assert_eq!(a.letter, b.letter, "{} != {}", got, expected);
+        match (a.number(), b.number()) {
+            (Some(x), Some(y)) => {
+                assert!(close(x, y), "{} != {}", got, expected)
+            },
+            (x, y) => assert_eq!(x, y, "{} != {}", got, expected),
+        }
+    }
+}
+
 struct PanicOnError;
 
 impl gcode::Callbacks for PanicOnError {